@@ -0,0 +1,141 @@
+//! Download of the waveform displayed by a benchtop scope, opened as a trace instead of exporting
+//! a file from the scope.
+//!
+//! The scope is given as `scope://HOST[:PORT][/CHANNEL]`. It is reached with VXI-11, or with the
+//! raw SCPI socket of LXI instruments when the port is given. The vendor is identified with
+//! `*IDN?`: LeCroy scopes send the waveform as a TRC file with `WAVEFORM? ALL`, and the others
+//! are read with the Tektronix commands, like the live captures of [`crate::scpi`].
+
+use crate::{
+    loaders::decode_trc,
+    scpi::{Instrument, Protocol, SCPI_PORT, ScopeAddress, connect, read_curve, select_curve},
+};
+use std::io;
+
+/// Scope commands used to read the waveform.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Vendor {
+    LeCroy,
+    Tektronix,
+}
+
+impl Vendor {
+    /// Identifies the vendor from the answer to `*IDN?`. Scopes of other vendors often
+    /// understand the Tektronix commands.
+    fn identify(idn: &str) -> Self {
+        let idn = idn.to_ascii_uppercase();
+        if idn.contains("LECROY") {
+            Self::LeCroy
+        } else {
+            Self::Tektronix
+        }
+    }
+
+    /// Channel read when the URI does not give one.
+    fn default_channel(self) -> &'static str {
+        match self {
+            Self::LeCroy => "C1",
+            Self::Tektronix => "CH1",
+        }
+    }
+}
+
+/// A waveform downloaded from a scope.
+pub struct Waveform {
+    /// Identification of the scope and channel, for display.
+    pub label: String,
+    pub samples: Vec<f32>,
+    /// Sampling rate in MS/s, if the scope reports it.
+    pub sampling_rate: Option<f32>,
+}
+
+/// Parses `scope://HOST[:PORT][/CHANNEL]`. Returns `None` if `uri` is not a scope URI.
+fn parse_uri(uri: &str) -> Option<(ScopeAddress, Option<String>)> {
+    let rest = uri.strip_prefix("scope://")?;
+    let (host, channel) = match rest.split_once('/') {
+        Some((host, channel)) if !channel.is_empty() => (host, Some(channel.to_string())),
+        Some((host, _)) => (host, None),
+        None => (rest, None),
+    };
+    let (protocol, host, port) = match host.rsplit_once(':') {
+        Some((host, port)) => (Protocol::Socket, host, port.parse().ok()?),
+        None => (Protocol::Vxi11, host, SCPI_PORT),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let address = ScopeAddress {
+        protocol,
+        host: host.to_string(),
+        port,
+        channel: String::new(),
+    };
+    Some((address, channel))
+}
+
+/// Reads the waveform of `channel` of a LeCroy scope, and returns its first segment with the
+/// sampling rate.
+fn read_lecroy(instrument: &mut dyn Instrument, channel: &str) -> io::Result<(Vec<f32>, f64)> {
+    instrument.write("COMM_HEADER OFF")?;
+    instrument.write("COMM_FORMAT DEF9,WORD,BIN")?;
+    let response = instrument.query(&format!("{}:WAVEFORM? ALL", channel))?;
+    let (segments, sampling_rate) = decode_trc(&response, channel).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid waveform: {}", e),
+        )
+    })?;
+    let samples = segments.into_iter().next().unwrap_or_default();
+    Ok((samples, sampling_rate))
+}
+
+/// Downloads the waveform currently displayed by the scope at `uri`
+/// (`scope://HOST[:PORT][/CHANNEL]`).
+pub fn fetch(uri: &str) -> io::Result<Waveform> {
+    let (address, channel) = parse_uri(uri).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid scope URI {}, expected scope://HOST[:PORT][/CHANNEL]",
+                uri
+            ),
+        )
+    })?;
+    let mut instrument = connect(&address)?;
+    let idn = instrument.query("*IDN?")?;
+    let idn = String::from_utf8_lossy(&idn).trim().to_string();
+    let vendor = Vendor::identify(&idn);
+    let channel = channel.unwrap_or_else(|| vendor.default_channel().to_string());
+    let (samples, sampling_rate) = match vendor {
+        Vendor::LeCroy => {
+            let (samples, rate) = read_lecroy(instrument.as_mut(), &channel)?;
+            (
+                samples,
+                Some((rate / 1e6) as f32).filter(|&rate| rate > 0.0),
+            )
+        }
+        Vendor::Tektronix => {
+            select_curve(instrument.as_mut(), &channel)?;
+            read_curve(instrument.as_mut())?
+        }
+    };
+    if samples.len() < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} returned {} sample(s)", channel, samples.len()),
+        ));
+    }
+    tracing::info!(
+        target: "loader",
+        "{}: {} ({:?}), {} pts",
+        uri,
+        idn,
+        vendor,
+        samples.len()
+    );
+    Ok(Waveform {
+        label: format!("{} [{}]", address.host, channel),
+        samples,
+        sampling_rate,
+    })
+}
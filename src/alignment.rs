@@ -0,0 +1,305 @@
+//! Alignment of repeated captures.
+//!
+//! Captures of the same operation are rarely triggered at exactly the same time. The lag between
+//! two traces is estimated on a window of samples, and applied as an X offset to one of the
+//! viewers. Phase correlation also estimates the fraction of sample between nominally identical
+//! captures, which is compensated by interpolating the trace.
+
+use crate::{processing::Progress, trace::Trace};
+use rustfft::{FftPlanner, num_complex::Complex};
+use std::{f64::consts::PI, ops::Range, sync::Arc};
+
+/// Number of lags evaluated between two progress reports.
+const PROGRESS_PERIOD: usize = 64;
+
+/// Number of golden-section iterations refining the phase correlation peak, enough for a
+/// precision far below 10⁻⁶ sample.
+const REFINE_ITERATIONS: usize = 40;
+
+/// Alignment methods.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlignmentMethod {
+    /// Search of the integer lag maximizing the normalized cross-correlation.
+    CrossCorrelation,
+    /// Fractional lag estimated from the phase of the cross-power spectrum.
+    PhaseCorrelation,
+}
+
+impl AlignmentMethod {
+    pub fn name(&self) -> &str {
+        match self {
+            AlignmentMethod::CrossCorrelation => "Cross-correlation",
+            AlignmentMethod::PhaseCorrelation => "Phase correlation (sub-sample)",
+        }
+    }
+}
+
+/// Result of an alignment.
+#[derive(Clone, Copy, Debug)]
+pub struct Alignment {
+    /// Number of samples the trace is delayed compared to the reference: the samples of the
+    /// reference window match `trace[window.start + lag..window.end + lag]`. The lag is an integer
+    /// for cross-correlation, and fractional for phase correlation.
+    pub lag: f64,
+    /// Similarity of the aligned samples, between -1 and 1: the normalized correlation for
+    /// cross-correlation, and the height of the correlation peak for phase correlation.
+    pub correlation: f32,
+}
+
+/// Finds the lag in `-max_lag..=max_lag` maximizing the normalized cross-correlation between the
+/// `window` samples of `reference` and the shifted samples of `trace`.
+///
+/// Lags for which the shifted window falls outside `trace` are not evaluated. Returns
+/// `Some(None)` if no lag can be evaluated, and `None` if the processing is cancelled.
+pub fn cross_correlation(
+    reference: &[f32],
+    trace: &[f32],
+    window: Range<usize>,
+    max_lag: usize,
+    progress: &Progress,
+) -> Option<Option<Alignment>> {
+    let window = window.start.min(reference.len())..window.end.min(reference.len());
+    let n = window.len();
+    if n < 2 {
+        return Some(None);
+    }
+
+    // Centered reference, so the correlation with a shifted segment is a simple dot product.
+    let r = &reference[window.clone()];
+    let r_mean = r.iter().map(|&x| x as f64).sum::<f64>() / n as f64;
+    let r: Vec<f64> = r.iter().map(|&x| x as f64 - r_mean).collect();
+    let r_norm = r.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if r_norm == 0.0 {
+        return Some(None);
+    }
+
+    // Range of valid lags, so the shifted window stays in the trace.
+    let min_lag = -(max_lag.min(window.start) as isize);
+    let max_lag = (max_lag as isize).min(trace.len() as isize - window.end as isize);
+    if max_lag < min_lag {
+        return Some(None);
+    }
+
+    let lags = (max_lag - min_lag + 1) as usize;
+    let mut best: Option<Alignment> = None;
+    for (i, lag) in (min_lag..=max_lag).enumerate() {
+        if i % PROGRESS_PERIOD == 0 {
+            if progress.is_cancelled() {
+                return None;
+            }
+            progress.set(i as f32 / lags as f32);
+        }
+        let start = (window.start as isize + lag) as usize;
+        let segment = &trace[start..start + n];
+        let (mut dot, mut sum, mut sum2) = (0.0, 0.0, 0.0);
+        for (&a, &b) in r.iter().zip(segment) {
+            let b = b as f64;
+            dot += a * b;
+            sum += b;
+            sum2 += b * b;
+        }
+        // The reference is centered, so the mean of the segment does not change the dot product.
+        let variance = sum2 - sum * sum / n as f64;
+        if variance <= 0.0 {
+            continue;
+        }
+        let correlation = (dot / (r_norm * variance.sqrt())) as f32;
+        if best.is_none_or(|b| correlation > b.correlation) {
+            best = Some(Alignment {
+                lag: lag as f64,
+                correlation,
+            });
+        }
+    }
+    progress.set(1.0);
+    Some(best)
+}
+
+/// Aligns each of `traces` to the `window` samples of `reference` with `method`, searching lags
+/// up to `max_lag` for cross-correlation. The progress is split evenly between the traces.
+/// Returns `None` if the processing is cancelled.
+pub fn align_all(
+    reference: &[f32],
+    traces: &[Arc<Trace>],
+    window: Range<usize>,
+    method: AlignmentMethod,
+    max_lag: usize,
+    progress: &Progress,
+) -> Option<Vec<Option<Alignment>>> {
+    let n = traces.len() as f32;
+    let mut alignments = Vec::with_capacity(traces.len());
+    for (i, trace) in traces.iter().enumerate() {
+        progress.set_scope(i as f32 / n, 1.0 / n);
+        alignments.push(match method {
+            AlignmentMethod::CrossCorrelation => {
+                cross_correlation(reference, trace, window.clone(), max_lag, progress)?
+            }
+            AlignmentMethod::PhaseCorrelation => {
+                phase_correlation(reference, trace, window.clone(), progress)?
+            }
+        });
+    }
+    Some(alignments)
+}
+
+/// Estimates the fractional lag between the `window` samples of `reference` and the same samples
+/// of `trace` by phase correlation.
+///
+/// A first estimate is calculated on the same samples of both traces. As the estimate is less
+/// accurate when the segments only partially overlap, it is refined on the `trace` segment
+/// shifted by the integer part of the first lag, if it stays in the trace. The lag must be
+/// smaller than the window length. Returns `Some(None)` if the window is too short or flat, and
+/// `None` if the processing is cancelled.
+pub fn phase_correlation(
+    reference: &[f32],
+    trace: &[f32],
+    window: Range<usize>,
+    progress: &Progress,
+) -> Option<Option<Alignment>> {
+    let len = reference.len().min(trace.len());
+    let window = window.start.min(len)..window.end.min(len);
+    let n = window.len();
+    let r = &reference[window.clone()];
+    let Some(coarse) = phase_lag(r, &trace[window.clone()], progress, |x| {
+        progress.set(x / 2.0)
+    })?
+    else {
+        return Some(None);
+    };
+    let whole = coarse.lag.round() as isize;
+    let start = window.start as isize + whole;
+    if whole == 0 || start < 0 || start as usize + n > trace.len() {
+        progress.set(1.0);
+        return Some(Some(coarse));
+    }
+    let start = start as usize;
+    let fine = phase_lag(r, &trace[start..start + n], progress, |x| {
+        progress.set(0.5 + x / 2.0)
+    })?;
+    Some(Some(fine.map_or(coarse, |fine| Alignment {
+        lag: whole as f64 + fine.lag,
+        correlation: fine.correlation,
+    })))
+}
+
+/// Phase correlation of two segments of the same length, with the lag of `b` relative to `a`.
+///
+/// Both segments are centered, weighted by a Hann window and zero-padded, and the normalized
+/// cross-power spectrum is transformed back to find the integer correlation peak. The peak is then
+/// refined by evaluating the band-limited correlation between samples, giving a sub-sample lag.
+/// The completed fraction is reported to `set_progress`.
+fn phase_lag(
+    a: &[f32],
+    b: &[f32],
+    progress: &Progress,
+    set_progress: impl Fn(f32),
+) -> Option<Option<Alignment>> {
+    let n = a.len();
+    if n < 2 {
+        return Some(None);
+    }
+
+    let size = (2 * n).next_power_of_two();
+    let mut planner = FftPlanner::<f64>::new();
+    let fft = planner.plan_fft_forward(size);
+    let spectrum = |samples: &[f32]| {
+        let mean = samples.iter().map(|&x| x as f64).sum::<f64>() / n as f64;
+        let mut buffer: Vec<Complex<f64>> = samples
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                let hann = 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / n as f64).cos();
+                Complex::new((x as f64 - mean) * hann, 0.0)
+            })
+            .collect();
+        buffer.resize(size, Complex::default());
+        fft.process(&mut buffer);
+        buffer
+    };
+    let a = spectrum(a);
+    if progress.is_cancelled() {
+        return None;
+    }
+    set_progress(0.25);
+    let b = spectrum(b);
+    if progress.is_cancelled() {
+        return None;
+    }
+    set_progress(0.5);
+    // Normalized cross-power spectrum. Bins without energy in one of the traces carry no phase
+    // information and are ignored.
+    let mut cross: Vec<Complex<f64>> = a.iter().zip(&b).map(|(a, b)| a.conj() * b).collect();
+    let max_norm = cross.iter().map(|c| c.norm()).fold(0.0, f64::max);
+    if max_norm == 0.0 {
+        return Some(None);
+    }
+    let mut bins = 0;
+    for c in cross.iter_mut() {
+        let norm = c.norm();
+        if norm > max_norm * 1e-9 {
+            *c /= norm;
+            bins += 1;
+        } else {
+            *c = Complex::default();
+        }
+    }
+
+    let mut correlation = cross.clone();
+    planner.plan_fft_inverse(size).process(&mut correlation);
+    if progress.is_cancelled() {
+        return None;
+    }
+    set_progress(0.75);
+    // Lags in -(n - 1)..n, the zero padding preventing circular wrapping.
+    let peak = (-(n as isize - 1)..n as isize)
+        .max_by(|&x, &y| {
+            let at = |lag: isize| correlation[lag.rem_euclid(size as isize) as usize].re;
+            at(x).total_cmp(&at(y))
+        })
+        .unwrap();
+
+    // Band-limited correlation at a fractional lag, evaluated from the spectrum.
+    let at = |lag: f64| {
+        let step = 2.0 * PI * lag / size as f64;
+        cross
+            .iter()
+            .enumerate()
+            .map(|(k, c)| {
+                // Negative frequencies are stored in the second half of the spectrum.
+                let k = if k > size / 2 {
+                    k as f64 - size as f64
+                } else {
+                    k as f64
+                };
+                c.re * (step * k).cos() - c.im * (step * k).sin()
+            })
+            .sum::<f64>()
+    };
+    let (mut lo, mut hi) = (peak as f64 - 1.0, peak as f64 + 1.0);
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let mut x1 = hi - ratio * (hi - lo);
+    let mut x2 = lo + ratio * (hi - lo);
+    let (mut f1, mut f2) = (at(x1), at(x2));
+    for _ in 0..REFINE_ITERATIONS {
+        if progress.is_cancelled() {
+            return None;
+        }
+        if f1 < f2 {
+            lo = x1;
+            (x1, f1) = (x2, f2);
+            x2 = lo + ratio * (hi - lo);
+            f2 = at(x2);
+        } else {
+            hi = x2;
+            (x2, f2) = (x1, f1);
+            x1 = hi - ratio * (hi - lo);
+            f1 = at(x1);
+        }
+    }
+    let lag = (lo + hi) / 2.0;
+    set_progress(1.0);
+    Some(Some(Alignment {
+        lag,
+        correlation: (at(lag) / bins as f64) as f32,
+    }))
+}
@@ -0,0 +1,372 @@
+//! Modal dialog for aligning traces to a reference trace.
+
+use crate::{
+    alignment::{Alignment, AlignmentMethod, align_all},
+    processing::ProcessingJob,
+    trace::Trace,
+};
+use egui::{Button, Color32, ComboBox, DragValue, Id, Modal, ProgressBar, ScrollArea};
+use std::{ops::Range, sync::Arc, time::Duration};
+
+/// Background search of the best lag of each aligned trace. The lag of a trace is `None` if no
+/// lag could be evaluated.
+type AlignmentJob = ProcessingJob<Vec<Option<Alignment>>>;
+
+/// Result of the alignment dialog update.
+pub enum AlignmentDialogResult {
+    /// The user is still configuring the alignment, or the lags are being searched.
+    Pending,
+    /// Each `(target, lag)` trace must be offset by `lag` samples relative to the reference. The
+    /// lags are fractional for phase correlation.
+    Apply {
+        reference: usize,
+        lags: Vec<(usize, f64)>,
+    },
+    /// Dialog was cancelled.
+    Cancelled,
+}
+
+/// A trace which can be aligned.
+pub struct AlignmentSource {
+    pub label: String,
+    pub trace: Arc<Trace>,
+    /// Range selection of the viewer, if any.
+    pub selection: Option<Range<usize>>,
+}
+
+/// Traces aligned by the dialog.
+enum Targets {
+    /// A single trace, aligned to a reference chosen in the dialog.
+    One(usize),
+    /// All the traces except the reference, aligned to a pattern selected in the reference.
+    AllOthers,
+}
+
+/// Dialog for aligning the trace of a viewer to another one, or all the traces to a pattern of
+/// a reference trace, by cross-correlation or phase correlation.
+pub struct AlignmentDialog {
+    id: Id,
+    targets: Targets,
+    /// Index of the reference trace.
+    reference: usize,
+    method: AlignmentMethod,
+    /// Maximum lag searched by cross-correlation, in samples.
+    max_lag: usize,
+    /// Traces whose best correlation is lower are left in place when aligning all the traces.
+    min_correlation: f32,
+    /// Running search, with the indices of the aligned traces.
+    job: Option<(AlignmentJob, Vec<usize>)>,
+    /// Last found alignments, with the indices of the aligned traces.
+    alignments: Option<Vec<(usize, Option<Alignment>)>>,
+    error: Option<String>,
+}
+
+impl AlignmentDialog {
+    /// Default maximum lag, in samples.
+    const DEFAULT_MAX_LAG: usize = 1000;
+
+    /// Default minimum correlation for aligning all the traces.
+    const DEFAULT_MIN_CORRELATION: f32 = 0.5;
+
+    fn with_targets(id: Id, targets: Targets, reference: usize) -> Self {
+        Self {
+            id,
+            targets,
+            reference,
+            method: AlignmentMethod::CrossCorrelation,
+            max_lag: Self::DEFAULT_MAX_LAG,
+            min_correlation: Self::DEFAULT_MIN_CORRELATION,
+            job: None,
+            alignments: None,
+            error: None,
+        }
+    }
+
+    /// Creates the dialog for aligning the trace at index `target`.
+    pub fn new(id: Id, target: usize) -> Self {
+        let reference = if target == 0 { 1 } else { 0 };
+        Self::with_targets(id, Targets::One(target), reference)
+    }
+
+    /// Creates the dialog for aligning all the traces to the pattern selected in the trace at
+    /// index `reference`.
+    pub fn all(id: Id, reference: usize) -> Self {
+        Self::with_targets(id, Targets::AllOthers, reference)
+    }
+
+    /// Indices of the aligned traces, among `count` traces.
+    fn target_indices(&self, count: usize) -> Vec<usize> {
+        match self.targets {
+            Targets::One(target) => vec![target],
+            Targets::AllOthers => (0..count).filter(|&i| i != self.reference).collect(),
+        }
+    }
+
+    /// Shows the dialog and returns the user decision.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        sources: &[AlignmentSource],
+    ) -> AlignmentDialogResult {
+        if let Some((job, targets)) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            match result {
+                Ok(result) => {
+                    if let Targets::One(_) = self.targets
+                        && result.iter().all(Option::is_none)
+                    {
+                        self.error =
+                            Some("No lag could be evaluated in the trace bounds".to_string());
+                    }
+                    self.alignments = Some(targets.iter().copied().zip(result).collect());
+                }
+                Err(e) => self.error = Some(e),
+            }
+            self.job = None;
+        }
+
+        let mut result = AlignmentDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            match self.targets {
+                Targets::One(_) => ui.heading("Align to reference"),
+                Targets::AllOthers => ui.heading("Align all traces to pattern"),
+            };
+            ui.add_space(5.0);
+            egui::Grid::new("alignment_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let previous = self.reference;
+                    match self.targets {
+                        Targets::One(target) => {
+                            ui.label("Trace:");
+                            ui.label(&sources[target].label);
+                            ui.end_row();
+                            ui.label("Reference:");
+                            ComboBox::from_id_salt("alignment_reference")
+                                .selected_text(&sources[self.reference].label)
+                                .show_ui(ui, |ui| {
+                                    for (i, source) in sources.iter().enumerate() {
+                                        if i != target {
+                                            ui.selectable_value(
+                                                &mut self.reference,
+                                                i,
+                                                &source.label,
+                                            );
+                                        }
+                                    }
+                                });
+                        }
+                        Targets::AllOthers => {
+                            ui.label("Reference:");
+                            ui.label(&sources[self.reference].label);
+                            ui.end_row();
+                            ui.label("Traces:");
+                            ui.label(format!("{} other traces", sources.len() - 1));
+                        }
+                    }
+                    ui.end_row();
+                    ui.label("Method:");
+                    let previous_method = self.method;
+                    ComboBox::from_id_salt("alignment_method")
+                        .selected_text(self.method.name())
+                        .show_ui(ui, |ui| {
+                            for method in [
+                                AlignmentMethod::CrossCorrelation,
+                                AlignmentMethod::PhaseCorrelation,
+                            ] {
+                                ui.selectable_value(&mut self.method, method, method.name());
+                            }
+                        });
+                    ui.end_row();
+                    let mut lag_changed = false;
+                    if self.method == AlignmentMethod::CrossCorrelation {
+                        ui.label("Maximum lag:");
+                        lag_changed = ui
+                            .add(
+                                DragValue::new(&mut self.max_lag)
+                                    .range(1..=usize::MAX)
+                                    .suffix(" samples"),
+                            )
+                            .changed();
+                        ui.end_row();
+                    }
+                    if let Targets::AllOthers = self.targets {
+                        ui.label("Minimum correlation:");
+                        ui.add(
+                            DragValue::new(&mut self.min_correlation)
+                                .range(-1.0..=1.0)
+                                .speed(0.01),
+                        )
+                        .on_hover_text("Traces matching the pattern less are left in place");
+                        ui.end_row();
+                    }
+                    if lag_changed || self.reference != previous || self.method != previous_method {
+                        self.alignments = None;
+                        self.error = None;
+                    }
+                });
+            let window = self.window(sources);
+            match (&window, &self.targets) {
+                (Some(window), Targets::One(_)) => ui.label(format!(
+                    "Correlation window: samples {} to {}",
+                    window.start, window.end
+                )),
+                (Some(window), Targets::AllOthers) => ui.label(format!(
+                    "Pattern: samples {} to {}",
+                    window.start, window.end
+                )),
+                (None, Targets::One(_)) => {
+                    ui.label("Select the correlation window with the Range tool.")
+                }
+                (None, Targets::AllOthers) => {
+                    ui.label("Select the pattern with the Range tool in the reference view.")
+                }
+            };
+            if self.method == AlignmentMethod::PhaseCorrelation {
+                ui.label(
+                    "The lag must be small compared to the window. The fraction of sample is \
+                    applied by interpolating the trace.",
+                );
+            }
+            let lag_text = |alignment: &Alignment| match self.method {
+                AlignmentMethod::CrossCorrelation => format!("{}", alignment.lag),
+                AlignmentMethod::PhaseCorrelation => format!("{:.3}", alignment.lag),
+            };
+            match (&self.alignments, &self.targets) {
+                (Some(alignments), Targets::One(_)) => {
+                    if let Some((_, Some(alignment))) = alignments.first() {
+                        ui.label(format!(
+                            "Lag: {} samples, correlation {:.3}",
+                            lag_text(alignment),
+                            alignment.correlation
+                        ));
+                    }
+                }
+                (Some(alignments), Targets::AllOthers) => {
+                    ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        egui::Grid::new("alignment_results")
+                            .num_columns(3)
+                            .striped(true)
+                            .show(ui, |ui| {
+                                ui.strong("Trace");
+                                ui.strong("Lag");
+                                ui.strong("Correlation");
+                                ui.end_row();
+                                for (i, alignment) in alignments {
+                                    ui.label(&sources[*i].label);
+                                    match alignment {
+                                        Some(a) if a.correlation >= self.min_correlation => {
+                                            ui.label(lag_text(a));
+                                            ui.label(format!("{:.3}", a.correlation));
+                                        }
+                                        Some(a) => {
+                                            ui.weak("kept");
+                                            ui.weak(format!("{:.3}", a.correlation));
+                                        }
+                                        None => {
+                                            ui.weak("kept");
+                                            ui.weak("no lag found");
+                                        }
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                }
+                (None, _) => {}
+            }
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if let Some((job, _)) = &self.job {
+                    ui.add(
+                        ProgressBar::new(job.progress())
+                            .desired_width(200.0)
+                            .show_percentage()
+                            .animate(true),
+                    );
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                    if ui.button("Cancel").clicked() {
+                        // Dropping the job cancels it.
+                        self.job = None;
+                    }
+                    return;
+                }
+                if let Some(lags) = self.lags() {
+                    if ui
+                        .add_enabled(!lags.is_empty(), Button::new("Apply"))
+                        .clicked()
+                    {
+                        result = AlignmentDialogResult::Apply {
+                            reference: self.reference,
+                            lags,
+                        };
+                    }
+                } else if ui
+                    .add_enabled(window.is_some(), Button::new("Find lag"))
+                    .clicked()
+                    && let Some(window) = window
+                {
+                    let targets = self.target_indices(sources.len());
+                    let reference = sources[self.reference].trace.clone();
+                    let traces: Vec<_> =
+                        targets.iter().map(|&i| sources[i].trace.clone()).collect();
+                    let max_lag = self.max_lag;
+                    let method = self.method;
+                    self.error = None;
+                    let job = ProcessingJob::spawn(ctx, move |progress| {
+                        align_all(&reference, &traces, window, method, max_lag, progress)
+                    });
+                    self.job = Some((job, targets));
+                }
+                if ui.button("Cancel").clicked() {
+                    result = AlignmentDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() && self.job.is_none() {
+            AlignmentDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+
+    /// Returns the lags to apply from the last found alignments, or `None` if the lags have not
+    /// been searched yet. When aligning all the traces, the traces correlating less than the
+    /// minimum are left out.
+    fn lags(&self) -> Option<Vec<(usize, f64)>> {
+        let alignments = self.alignments.as_ref()?;
+        let min_correlation = match self.targets {
+            Targets::One(_) => f32::NEG_INFINITY,
+            Targets::AllOthers => self.min_correlation,
+        };
+        let lags: Vec<_> = alignments
+            .iter()
+            .filter_map(|(i, alignment)| {
+                alignment
+                    .filter(|a| a.correlation >= min_correlation)
+                    .map(|a| (*i, a.lag))
+            })
+            .collect();
+        // A failed single alignment can be searched again with other settings.
+        if lags.is_empty() && matches!(self.targets, Targets::One(_)) {
+            None
+        } else {
+            Some(lags)
+        }
+    }
+
+    /// Returns the correlation window: the Range selection of the reference, or of the aligned
+    /// trace if the reference has none. When aligning all the traces, the pattern must be
+    /// selected in the reference.
+    fn window(&self, sources: &[AlignmentSource]) -> Option<Range<usize>> {
+        let reference = sources[self.reference].selection.clone();
+        match self.targets {
+            Targets::One(target) => reference.or_else(|| sources[target].selection.clone()),
+            Targets::AllOthers => reference,
+        }
+    }
+}
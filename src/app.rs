@@ -0,0 +1,840 @@
+//! Main application window: file selection, connection to a live source or watching a
+//! directory, then viewing.
+
+use crate::{
+    acquisition::fetch,
+    automation::Command,
+    digital::LogicChannel,
+    error_dialog::ErrorDialog,
+    events::{load_event_list, load_trigger},
+    input::{Args, FileManager, FileManagerResult, FileSettings},
+    live::{Live, LiveStream},
+    loaders::{
+        FrameFile, STDIN_PATH, TraceFormat, TrsFile, file_sampling_rate, file_trigger,
+        guess_format, load_file_mapped, open_capture, open_frames,
+    },
+    multi_viewer::MultiViewer,
+    overlay::load_overlay,
+    remote::{RemotePeer, RemoteServer, reject},
+    settings::Settings,
+    snr::{load_labels, snr},
+    tiling::ColorScale,
+    trace::Trace,
+    transforms::mean_std,
+    watch::FolderWatch,
+};
+use eframe::egui;
+use egui::Color32;
+use std::{path::Path, sync::Arc, time::Duration};
+
+/// Application state: selecting files, viewing traces, or closing.
+enum AppState {
+    /// File selection state with the file manager.
+    Selection(Box<FileManager>),
+    /// Waiting for the first trace of a live source, with the arguments for creating the viewer.
+    Connecting(Box<Live>, Box<Args>),
+    /// Waiting for a remote client to load a file, with the arguments for creating the viewer.
+    Remote(Box<Args>),
+    /// Waiting for the first new file of a watched directory, with the arguments for creating
+    /// the viewer.
+    Watching(Box<FolderWatch>, Box<Args>),
+    /// Viewing state with the multi-viewer, and the arguments the files opened from its menu bar
+    /// are loaded with.
+    Viewing(Box<MultiViewer>, Box<Args>),
+    /// Application is closing.
+    Closing,
+}
+
+/// Main application wrapper that handles file selection and viewing states.
+pub struct TurboPlotApp {
+    state: AppState,
+    /// Automation script commands, executed once the traces are loaded.
+    commands: Vec<Command>,
+    /// Server of the remote control requests, given to the viewer once created.
+    remote: Option<RemoteServer>,
+    /// Failures reported before the viewer is created, such as files which could not be loaded.
+    errors: ErrorDialog,
+    /// Settings remembered between launches.
+    settings: Settings,
+    /// Color scale of the previous launch, replacing the display options of the arguments when
+    /// set.
+    color_scale: Option<ColorScale>,
+    /// File manager opened from the menu bar of the viewer, for adding traces.
+    file_manager: Option<Box<FileManager>>,
+}
+
+impl TurboPlotApp {
+    /// Creates the application, loading the files given in `args` or showing the file manager.
+    /// `commands` are executed once the traces are loaded. The settings of the previous launches
+    /// are read from `storage`.
+    pub fn new(
+        ctx: &egui::Context,
+        args: Args,
+        commands: Vec<Command>,
+        storage: Option<&dyn eframe::Storage>,
+    ) -> Self {
+        let settings = Settings::load(storage);
+        let color_scale = settings.color_scale.filter(|_| !args.display.given);
+        let remote = args.remote.as_ref().and_then(|address| {
+            match RemoteServer::start(ctx, address, args.remote_any_host) {
+                Ok(server) => {
+                    tracing::info!("Remote control listening on {}", address);
+                    Some(server)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to listen on {}: {}", address, e);
+                    None
+                }
+            }
+        });
+        let mut app = Self {
+            state: AppState::Closing,
+            commands,
+            remote,
+            errors: ErrorDialog::new(egui::Id::new("app_error_dialog")),
+            settings,
+            color_scale,
+            file_manager: None,
+        };
+        if args.stream && args.paths != [STDIN_PATH] {
+            tracing::warn!(
+                "--stream only applies to the standard input, the files are loaded entirely."
+            );
+        }
+        app.state = if let Some(source) = &args.live {
+            let interval = Duration::from_secs_f32(args.live_interval);
+            let stream = LiveStream::connect(ctx, source, interval);
+            let live = Live::new(stream, args.live_history);
+            AppState::Connecting(Box::new(live), Box::new(args))
+        } else if args.stream && args.paths == [STDIN_PATH] {
+            let trace = &args.trace;
+            let stream = LiveStream::stdin(
+                ctx,
+                trace.format,
+                trace.dtype,
+                trace.skip_lines,
+                trace.column,
+            );
+            let live = Live::strip_chart(stream, args.strip_length);
+            AppState::Connecting(Box::new(live), Box::new(args))
+        } else if let Some(uri) = &args.listen {
+            let live = Live::strip_chart(LiveStream::listen(ctx, uri), args.strip_length);
+            AppState::Connecting(Box::new(live), Box::new(args))
+        } else if let Some(dir) = &args.watch {
+            match FolderWatch::start(ctx, dir, &args.trace, args.watch_replace) {
+                Ok(watch) => {
+                    tracing::info!(target: "loader", "Watching {} for new files", dir);
+                    AppState::Watching(Box::new(watch), Box::new(args))
+                }
+                Err(e) => {
+                    let message = format!("Failed to watch {}: {}", dir, e);
+                    tracing::error!(target: "loader", "{}", message);
+                    app.errors.push(message, None);
+                    AppState::Selection(Box::new(FileManager::new(args, &app.settings)))
+                }
+            }
+        } else if args.paths.is_empty() && args.connect.is_empty() && app.remote.is_some() {
+            AppState::Remote(Box::new(args))
+        } else if args.paths.is_empty() && args.connect.is_empty() {
+            // No files provided, show file manager
+            AppState::Selection(Box::new(FileManager::new(args, &app.settings)))
+        } else {
+            // Files were provided via command line, load and go to viewing
+            match app.load_and_create_viewer(ctx, &args) {
+                Some(viewer) => AppState::Viewing(Box::new(viewer), Box::new(args)),
+                None => {
+                    // Failed to load, show file manager
+                    AppState::Selection(Box::new(FileManager::new(args, &app.settings)))
+                }
+            }
+        };
+        app
+    }
+
+    /// Loads traces from args and creates a MultiViewer if successful. The automation commands
+    /// are given to the viewer. The loading failures are reported by the viewer, or by the
+    /// application if no trace could be loaded.
+    fn load_and_create_viewer(&mut self, ctx: &egui::Context, args: &Args) -> Option<MultiViewer> {
+        let LoadedTraces {
+            labels,
+            traces,
+            sampling_rates,
+            sources,
+            overlays,
+            errors,
+        } = Self::load_traces(args);
+        if traces.is_empty() {
+            for error in errors {
+                self.errors.push(error, None);
+            }
+            return None;
+        }
+        self.settings.add_sampling_rate(args.trace.sampling_rate);
+        // The files picked in the browser are only kept in memory.
+        #[cfg(not(target_arch = "wasm32"))]
+        self.settings.add_recent_files(&args.paths, &args.trace);
+
+        tracing::info!(
+            target: "renderer",
+            "Using {} GPU threads and {} CPU threads.",
+            args.gpu,
+            args.cpu_threads()
+        );
+
+        let mut viewer =
+            new_multi_viewer(ctx, args, self.color_scale, labels, traces, sampling_rates);
+        viewer.set_pending_files(args.pending_paths.len());
+        set_sources(&mut viewer, 0, sources);
+        viewer.restore_bookmarks(&self.settings);
+        for error in errors {
+            viewer.report_failure(error);
+        }
+        for (label, samples) in overlays {
+            viewer.add_overlay(&label, Arc::new(samples));
+        }
+        let fail = |viewer: &mut MultiViewer, message: String| {
+            tracing::warn!(target: "loader", "{}", message);
+            viewer.report_failure(message);
+        };
+        for path in &args.overlay {
+            match load_overlay(path) {
+                Ok(samples) => viewer.add_overlay(path, Arc::new(samples)),
+                Err(e) => fail(&mut viewer, format!("Failed to load overlay {}", e)),
+            }
+        }
+        let mut events = Vec::new();
+        if let Some(path) = &args.events {
+            match load_event_list(path) {
+                Ok(list) => events.extend(list),
+                Err(e) => fail(&mut viewer, format!("Failed to load events {}", e)),
+            }
+        }
+        if let Some(path) = &args.trigger {
+            match load_trigger(path) {
+                Ok(edges) => events.extend(edges),
+                Err(e) => fail(&mut viewer, format!("Failed to load trigger channel {}", e)),
+            }
+        }
+        if !events.is_empty() {
+            events.sort_by(|a, b| a.sample.total_cmp(&b.sample));
+            viewer.set_events(events);
+        }
+        viewer.run_commands(std::mem::take(&mut self.commands));
+        if let Some(server) = self.remote.take() {
+            viewer.set_remote(server);
+        }
+        Some(viewer)
+    }
+
+    /// Loads traces from the given args. Labels may differ from the input paths when a single
+    /// file produces multiple traces we call frames (e.g. multi-frame WFM or 2D numpy files).
+    fn load_traces(args: &Args) -> LoadedTraces {
+        let mut labels = Vec::new();
+        let mut traces = Vec::new();
+        let mut sampling_rates = Vec::new();
+        let mut sources = Vec::new();
+        let mut overlays = Vec::new();
+        let mut errors = Vec::new();
+        let mut fail = |message: String| {
+            tracing::warn!(target: "loader", "{}", message);
+            errors.push(message);
+        };
+        for (index, path) in args.paths.iter().enumerate() {
+            let trace = args.trace.file(index);
+            let Some(format) = trace.format.or_else(|| guess_format(path)) else {
+                fail(format!("Unrecognized file format: {}", path));
+                continue;
+            };
+            let sampling_rate = file_sampling_rate(path, format).unwrap_or(trace.sampling_rate);
+            let trigger = file_trigger(path, format);
+
+            let trs_file = if format == TraceFormat::Trs {
+                match TrsFile::open(path) {
+                    Ok(file) => Some(Arc::new(file)),
+                    Err(e) => {
+                        tracing::warn!(target: "loader", "Failed to read TRS header of {}: {}", path, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            // The digital channels of sigrok sessions and VCD files are displayed under their
+            // first analog channel, or under an empty trace for the captures without analog
+            // channels.
+            if let Some(capture) = open_capture(path, format) {
+                let capture = match capture {
+                    Ok(capture) => capture,
+                    Err(e) => {
+                        fail(format!("Failed to load {}: {}", path, e));
+                        continue;
+                    }
+                };
+                let sampling_rate = capture
+                    .sampling_rate
+                    .map(|rate| (rate / 1e6) as f32)
+                    .filter(|&rate| rate > 0.0)
+                    .unwrap_or(sampling_rate);
+                let mut channels = capture.analog;
+                if channels.is_empty() {
+                    channels.push((String::new(), vec![0.0; capture.logic_samples]));
+                }
+                let frames = channels.len();
+                let mut logic = Some(capture.logic);
+                for (i, (name, samples)) in channels.into_iter().enumerate() {
+                    labels.push(if name.is_empty() {
+                        path.clone()
+                    } else {
+                        format!("{} [{}]", path, name)
+                    });
+                    traces.push(Arc::new(samples.into()));
+                    sampling_rates.push(sampling_rate);
+                    sources.push(Some(FrameSource {
+                        path: path.clone(),
+                        index: i,
+                        frames,
+                        trs: None,
+                        browse: None,
+                        logic: logic.take().unwrap_or_default(),
+                        trigger: None,
+                        reload: None,
+                    }));
+                }
+                continue;
+            }
+
+            // Browsed files are displayed in a single viewer, their frames being read on demand.
+            if args.browse && format != TraceFormat::Csv {
+                match open_frames(path, format, trace.npy_layout) {
+                    Ok(file) if file.frames() > 1 => {
+                        if args.mean || args.std || args.snr_labels.is_some() {
+                            tracing::warn!(
+                                "Statistics are not calculated for {}, as its frames are browsed.",
+                                path
+                            );
+                        }
+                        let index = match args.trace.frame_selection() {
+                            Ok(selection) => selection.and_then(|sel| sel.first()).unwrap_or(0),
+                            Err(e) => {
+                                fail(format!("Failed to load {}: {}", path, e));
+                                continue;
+                            }
+                        };
+                        match file.read_frame(index) {
+                            Ok(frame) => {
+                                labels.push(format!("{} [frame {}]", path, index));
+                                traces.push(Arc::new(frame.into()));
+                                sampling_rates.push(sampling_rate);
+                                sources.push(Some(FrameSource {
+                                    path: path.clone(),
+                                    index,
+                                    frames: file.frames(),
+                                    trs: trs_file,
+                                    browse: Some(file),
+                                    logic: Vec::new(),
+                                    trigger,
+                                    reload: None,
+                                }));
+                            }
+                            Err(e) => {
+                                fail(format!("Failed to read frame {} of {}: {}", index, path, e))
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(
+                        target: "loader",
+                        "Cannot browse {}, loading all its frames: {}",
+                        path,
+                        e
+                    ),
+                }
+            }
+
+            // All loaders return one or more traces per file
+            let frames = match load_file_mapped(
+                path,
+                Some(format),
+                trace.npy_layout,
+                trace.skip_lines,
+                trace.column,
+                trace.dataset.as_deref(),
+                trace.dtype,
+                // The watched files may be truncated while they are displayed.
+                !args.watch_files,
+            ) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    fail(format!("Failed to load {}: {}", path, e));
+                    continue;
+                }
+            };
+
+            let n = frames.len();
+            let selection = match trace.frame_selection() {
+                Ok(selection) => selection,
+                Err(e) => {
+                    fail(format!("Failed to load {}: {}", path, e));
+                    continue;
+                }
+            };
+            let selected: Vec<_> = frames
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| selection.as_ref().is_none_or(|sel| sel.contains(*i)))
+                .collect();
+
+            // Statistics come before the traces, so they are kept if there are too many traces
+            // to display.
+            if n > 1 && (args.mean || args.std) && !selected.is_empty() {
+                let frames: Vec<&[f32]> = selected.iter().map(|(_, f)| &f[..]).collect();
+                let (mean, std) = mean_std(&frames);
+                if args.mean {
+                    labels.push(format!("{} [mean of {}]", path, frames.len()));
+                    traces.push(Arc::new(mean.into()));
+                    sampling_rates.push(sampling_rate);
+                    sources.push(None);
+                }
+                if args.std {
+                    labels.push(format!("{} [std of {}]", path, frames.len()));
+                    traces.push(Arc::new(std.into()));
+                    sampling_rates.push(sampling_rate);
+                    sources.push(None);
+                }
+            }
+
+            if n > 1
+                && let Some(source) = &args.snr_labels
+                && !selected.is_empty()
+            {
+                match load_labels(source, n, trs_file.as_deref()) {
+                    Ok(frame_labels) => {
+                        let frames: Vec<&[f32]> = selected.iter().map(|(_, f)| &f[..]).collect();
+                        let frame_labels: Vec<i64> =
+                            selected.iter().map(|(i, _)| frame_labels[*i]).collect();
+                        let name = |p: &str| {
+                            Path::new(p)
+                                .file_name()
+                                .map_or(p.to_string(), |s| s.to_string_lossy().to_string())
+                        };
+                        // Overlay labels are reduced to file names, so the label has no path
+                        // separator.
+                        overlays.push((
+                            format!("SNR of {} ({})", name(path), name(source)),
+                            snr(&frames, &frame_labels),
+                        ));
+                    }
+                    Err(e) => fail(format!("Failed to load the SNR labels of {}: {}", path, e)),
+                }
+            }
+
+            for (i, frame) in selected {
+                if n > 1 {
+                    labels.push(format!("{} [frame {}]", path, i));
+                } else {
+                    labels.push(path.clone());
+                }
+                traces.push(Arc::new(frame));
+                sampling_rates.push(sampling_rate);
+                sources.push(Some(FrameSource {
+                    path: path.clone(),
+                    index: i,
+                    frames: n,
+                    trs: trs_file.clone(),
+                    browse: None,
+                    logic: Vec::new(),
+                    trigger,
+                    reload: Some(FileSettings::new(&trace)),
+                }));
+            }
+        }
+
+        for uri in &args.connect {
+            match fetch(uri) {
+                Ok(waveform) => {
+                    labels.push(waveform.label);
+                    traces.push(Arc::new(waveform.samples.into()));
+                    sampling_rates.push(waveform.sampling_rate.unwrap_or(args.trace.sampling_rate));
+                    sources.push(None);
+                }
+                Err(e) => fail(format!("Failed to download {}: {}", uri, e)),
+            }
+        }
+
+        // Safety net: even with --frames, clamp the total number of viewers we
+        // are about to spawn to something sane so the UI stays responsive.
+        if traces.len() > MultiViewer::MAX_VIEWERS {
+            tracing::warn!(
+                "{} traces selected, keeping only the first {} to keep the UI responsive. \
+                 Use --browse to navigate between the frames in a single view instead.",
+                traces.len(),
+                MultiViewer::MAX_VIEWERS
+            );
+            labels.truncate(MultiViewer::MAX_VIEWERS);
+            traces.truncate(MultiViewer::MAX_VIEWERS);
+            sampling_rates.truncate(MultiViewer::MAX_VIEWERS);
+            sources.truncate(MultiViewer::MAX_VIEWERS);
+        }
+
+        LoadedTraces {
+            labels,
+            traces,
+            sampling_rates,
+            sources,
+            overlays,
+            errors,
+        }
+    }
+}
+
+/// Creates the viewers of `traces`, with the rendering threads, processing steps and display
+/// settings given in `args`.
+fn new_multi_viewer(
+    ctx: &egui::Context,
+    args: &Args,
+    color_scale: Option<ColorScale>,
+    labels: Vec<String>,
+    traces: Vec<Arc<Trace>>,
+    sampling_rates: Vec<f32>,
+) -> MultiViewer {
+    let steps = args.trace.processing_steps();
+    let mut viewer = MultiViewer::new(
+        ctx,
+        labels,
+        traces,
+        sampling_rates,
+        steps.clone().unwrap_or_default(),
+        args.gpu,
+        args.cpu_threads(),
+    );
+    if let Err(e) = steps {
+        viewer.report_failure(e);
+    }
+    viewer.set_color_scale(color_scale.unwrap_or_else(|| args.display.color_scale()));
+    viewer.set_keys(args.keys);
+    viewer.set_format(args.format);
+    viewer.set_tile_cache(args.tile_cache);
+    viewer.set_tiling_policy(args.tiling_policy());
+    viewer.set_file_watch(ctx, args.watch_files);
+    if let Some(address) = &args.sync_peer {
+        viewer.set_peer(RemotePeer::connect(address));
+    }
+    viewer
+}
+
+/// Loads the files given in `args`, picked from the menu bar of the viewer, and displays them
+/// below the current traces. The loading failures are reported by the viewer.
+fn open_files(ctx: &egui::Context, viewer: &mut MultiViewer, args: &Args, settings: &mut Settings) {
+    let LoadedTraces {
+        mut labels,
+        mut traces,
+        mut sampling_rates,
+        mut sources,
+        overlays,
+        errors,
+    } = TurboPlotApp::load_traces(args);
+    let first = viewer.viewer_count();
+    let remaining = MultiViewer::MAX_VIEWERS.saturating_sub(first);
+    if traces.len() > remaining {
+        tracing::warn!(
+            "{} traces opened, keeping only {} to keep the UI responsive.",
+            traces.len(),
+            remaining
+        );
+        labels.truncate(remaining);
+        traces.truncate(remaining);
+        sampling_rates.truncate(remaining);
+        sources.truncate(remaining);
+    }
+    if !traces.is_empty() {
+        settings.add_sampling_rate(args.trace.sampling_rate);
+        #[cfg(not(target_arch = "wasm32"))]
+        settings.add_recent_files(&args.paths, &args.trace);
+        if let Some(dir) = Path::new(&args.paths[0]).parent()
+            && !dir.as_os_str().is_empty()
+        {
+            settings.last_directory = Some(dir.to_path_buf());
+        }
+    }
+    let steps = args.trace.processing_steps();
+    viewer.add_traces(
+        ctx,
+        labels,
+        traces,
+        sampling_rates,
+        steps.clone().unwrap_or_default(),
+    );
+    if let Err(e) = steps {
+        viewer.report_failure(e);
+    }
+    set_sources(viewer, first, sources);
+    viewer.restore_bookmarks(settings);
+    for error in errors {
+        viewer.report_failure(error);
+    }
+    for (label, samples) in overlays {
+        viewer.add_overlay(&label, Arc::new(samples));
+    }
+}
+
+/// Gives the viewers from number `first` the `sources` of their traces, for browsing the frames
+/// of the files and inspecting their data.
+fn set_sources(viewer: &mut MultiViewer, first: usize, sources: Vec<Option<FrameSource>>) {
+    for (i, source) in sources.into_iter().enumerate() {
+        let Some(source) = source else {
+            continue;
+        };
+        let i = first + i;
+        viewer.set_source(
+            i,
+            source.path.clone(),
+            (source.frames > 1).then_some(source.index),
+        );
+        if let Some(file) = source.browse {
+            viewer.set_frame_browser(i, source.path, file, source.index);
+        } else if source.frames > 1 {
+            viewer.set_frame_group(i, source.path);
+        }
+        if let Some(file) = source.trs {
+            viewer.set_trace_data(i, file, source.index);
+        }
+        if !source.logic.is_empty() {
+            viewer.set_logic_channels(i, source.logic);
+        }
+        if source.trigger.is_some() {
+            viewer.set_trigger(i, source.trigger);
+        }
+        if let Some(settings) = source.reload {
+            viewer.set_reload(i, settings);
+        }
+    }
+}
+
+/// File and frame index a trace has been loaded from.
+struct FrameSource {
+    path: String,
+    index: usize,
+    /// Number of frames in the file.
+    frames: usize,
+    /// The file, when the data bytes of its frames can be inspected.
+    trs: Option<Arc<TrsFile>>,
+    /// The file, when its frames are browsed in a single viewer.
+    browse: Option<Arc<dyn FrameFile>>,
+    /// Digital channels captured with the trace.
+    logic: Vec<LogicChannel>,
+    /// Trigger sample recorded in the header of the file.
+    trigger: Option<f64>,
+    /// Load settings of the file, when the trace can be loaded again from it.
+    reload: Option<FileSettings>,
+}
+
+/// Traces loaded from the files given in the arguments.
+struct LoadedTraces {
+    labels: Vec<String>,
+    traces: Vec<Arc<Trace>>,
+    /// Sampling rate of each trace in MS/s, which can differ between the files.
+    sampling_rates: Vec<f32>,
+    /// Source of each trace, `None` for the statistics calculated at loading and the waveforms
+    /// downloaded from scopes.
+    sources: Vec<Option<FrameSource>>,
+    /// Arrays calculated at loading and painted over the traces, with their labels.
+    overlays: Vec<(String, Vec<f32>)>,
+    /// Failures of the files which could not be loaded.
+    errors: Vec<String>,
+}
+
+impl eframe::App for TurboPlotApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        if let AppState::Viewing(viewer, _) = &self.state {
+            self.settings.color_scale = Some(viewer.color_scale());
+            self.settings.add_sampling_rate(viewer.sampling_rate());
+            viewer.save_bookmarks(&mut self.settings);
+        }
+        self.settings.save(storage);
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        match &mut self.state {
+            AppState::Selection(file_manager) => match file_manager.update(ctx) {
+                FileManagerResult::Selected(args) => {
+                    if let Some(dir) = Path::new(&args.paths[0]).parent()
+                        && !dir.as_os_str().is_empty()
+                    {
+                        self.settings.last_directory = Some(dir.to_path_buf());
+                    }
+                    // Load traces and transition to viewing state, or select other files
+                    self.state = match self.load_and_create_viewer(ctx, &args) {
+                        Some(viewer) => AppState::Viewing(Box::new(viewer), args),
+                        None => {
+                            AppState::Selection(Box::new(FileManager::new(*args, &self.settings)))
+                        }
+                    };
+                }
+                FileManagerResult::Cancelled => {
+                    // Transition to closing state
+                    self.state = AppState::Closing;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                FileManagerResult::Pending => {}
+            },
+            AppState::Connecting(live, args) => {
+                if let Some(update) = live.update() {
+                    let mut viewer = new_multi_viewer(
+                        ctx,
+                        args,
+                        self.color_scale,
+                        vec![format!("live: {}", live.source())],
+                        vec![Arc::new(update.into_trace())],
+                        vec![live.sampling_rate().unwrap_or(args.trace.sampling_rate)],
+                    );
+                    let AppState::Connecting(live, args) =
+                        std::mem::replace(&mut self.state, AppState::Closing)
+                    else {
+                        unreachable!()
+                    };
+                    viewer.set_live(0, *live);
+                    if let Some(server) = self.remote.take() {
+                        viewer.set_remote(server);
+                    }
+                    self.state = AppState::Viewing(Box::new(viewer), args);
+                    return;
+                }
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.centered_and_justified(|ui| match live.error() {
+                        Some(error) => ui.colored_label(Color32::RED, error),
+                        None => ui.label(format!("Waiting for traces from {}…", live.source())),
+                    });
+                });
+            }
+            AppState::Watching(watch, args) => {
+                while let Some(file) = watch.try_receive() {
+                    let mut traces = match file.traces {
+                        Ok(traces) => traces,
+                        Err(e) => {
+                            let message = format!("Failed to load {}: {}", file.path, e);
+                            tracing::warn!(target: "loader", "{}", message);
+                            self.errors.push(message, None);
+                            continue;
+                        }
+                    };
+                    if watch.replace {
+                        traces.truncate(1);
+                    }
+                    let mut viewer = new_multi_viewer(
+                        ctx,
+                        args,
+                        self.color_scale,
+                        traces.iter().map(|t| t.label.clone()).collect(),
+                        traces
+                            .iter_mut()
+                            .map(|t| Arc::new(std::mem::take(&mut t.samples).into()))
+                            .collect(),
+                        vec![file.sampling_rate; traces.len()],
+                    );
+                    for (i, trace) in traces.iter().enumerate() {
+                        viewer.set_source(i, file.path.clone(), trace.frame);
+                    }
+                    let AppState::Watching(watch, args) =
+                        std::mem::replace(&mut self.state, AppState::Closing)
+                    else {
+                        unreachable!()
+                    };
+                    viewer.set_watch(*watch);
+                    viewer.run_commands(std::mem::take(&mut self.commands));
+                    if let Some(server) = self.remote.take() {
+                        viewer.set_remote(server);
+                    }
+                    self.state = AppState::Viewing(Box::new(viewer), args);
+                    return;
+                }
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.centered_and_justified(|ui| {
+                        ui.label(format!("Waiting for new files in {}…", watch.dir()))
+                    });
+                });
+            }
+            AppState::Remote(args) => {
+                let server = self.remote.as_ref().unwrap();
+                let mut viewer: Option<MultiViewer> = None;
+                for commands in server.receive() {
+                    match (&mut viewer, commands.first()) {
+                        (Some(viewer), _) => viewer.run_commands(commands),
+                        (
+                            None,
+                            Some(Command::Load {
+                                label,
+                                trace,
+                                sampling_rate,
+                            }),
+                        ) => {
+                            let mut new_viewer = new_multi_viewer(
+                                ctx,
+                                args,
+                                self.color_scale,
+                                vec![label.clone()],
+                                vec![Arc::new(trace.clone().into())],
+                                vec![sampling_rate.unwrap_or(args.trace.sampling_rate)],
+                            );
+                            new_viewer.run_commands(commands[1..].to_vec());
+                            viewer = Some(new_viewer);
+                        }
+                        (None, _) => reject(commands, "no file loaded yet, call load_file first"),
+                    }
+                }
+                if let Some(mut viewer) = viewer {
+                    viewer.set_remote(self.remote.take().unwrap());
+                    self.state = AppState::Viewing(Box::new(viewer), args.clone());
+                    return;
+                }
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.centered_and_justified(|ui| {
+                        ui.label(format!(
+                            "Waiting for a file loaded remotely on {}…",
+                            server.address()
+                        ))
+                    });
+                });
+            }
+            AppState::Viewing(viewer, args) => {
+                egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| viewer.menu_bar(ui));
+                if viewer.take_pending_request()
+                    && let Some(next) = args.take_pending()
+                {
+                    open_files(ctx, viewer, &next, &mut self.settings);
+                    viewer.set_pending_files(args.pending_paths.len());
+                }
+                if viewer.take_open_request() && self.file_manager.is_none() {
+                    let remaining = MultiViewer::MAX_VIEWERS.saturating_sub(viewer.viewer_count());
+                    let file_manager =
+                        FileManager::adding(args.as_ref().clone(), &self.settings, remaining);
+                    self.file_manager = Some(Box::new(file_manager));
+                }
+                if let Some(file_manager) = &mut self.file_manager {
+                    match file_manager.update(ctx) {
+                        FileManagerResult::Selected(args) => {
+                            self.file_manager = None;
+                            open_files(ctx, viewer, &args, &mut self.settings);
+                        }
+                        FileManagerResult::Cancelled => self.file_manager = None,
+                        FileManagerResult::Pending => {}
+                    }
+                }
+                // In the browser, the file manager takes the whole window.
+                if cfg!(not(target_arch = "wasm32")) || self.file_manager.is_none() {
+                    egui::CentralPanel::default()
+                        .frame(egui::Frame::default().outer_margin(0.0))
+                        .show(ctx, |ui| {
+                            viewer.update(ctx, ui);
+                        });
+                }
+            }
+            AppState::Closing => {
+                // Do nothing, app is closing
+            }
+        }
+        // No trace is displayed yet, so none can be removed.
+        self.errors.show(ctx, |_| false);
+    }
+}
@@ -0,0 +1,305 @@
+//! Automation of the viewer with Rhai scripts, for reproducible figure generation.
+//!
+//! The script given with `--script` is evaluated before the traces are loaded. It can add files
+//! to load, and queues commands which are executed in order by the
+//! [`crate::multi_viewer::MultiViewer`] once the viewers are ready. Each command waits for the
+//! processing started by the previous ones to finish. For instance:
+//!
+//! ```rhai
+//! load("capture.npy");
+//! sampling_rate(500);
+//! lowpass(20000);
+//! smooth(10);
+//! show(100, 250);
+//! autoscale_y();
+//! export_image("figure.png");
+//! quit();
+//! ```
+//!
+//! Times are in µs from the first sample, frequencies in kHz and sampling rates in MS/s, like
+//! the command-line arguments.
+
+use crate::{
+    filtering::{Filter, FilterSpec},
+    processing::{EnvelopeMethod, ProcessingStep},
+};
+use rhai::{Dynamic, Engine, EvalAltResult};
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc, sync::mpsc::Sender};
+
+/// Command queued by an automation script.
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Selects the viewer targeted by the next commands, by index.
+    Select(usize),
+    /// Appends a processing step to the selected viewer.
+    Process(ProcessingStep),
+    /// Removes all the processing steps of the selected viewer.
+    ResetProcessing,
+    /// Displays the given time interval, in µs.
+    Show { start: f64, end: f64 },
+    /// Displays the given time interval, in µs, and synchronizes the other viewers according to
+    /// the Sync options. Sent by another instance following this one with `--sync-peer`.
+    SyncCamera { start: f64, end: f64 },
+    /// Fits the whole trace in the viewer.
+    Autoscale,
+    /// Fits the visible samples vertically.
+    AutoscaleY,
+    /// Sets the Range selection, in µs.
+    SelectRange { start: f64, end: f64 },
+    /// Clears the Range selection.
+    ClearRange,
+    /// Saves the Range selection, or the visible samples if there is no selection, as a PNG image
+    /// looking like the display.
+    ExportImage(PathBuf),
+    /// Saves the Range selection, or the visible samples if there is no selection, as a Numpy
+    /// file.
+    ExportNpy(PathBuf),
+    /// Adds a viewer displaying a trace loaded by a remote client, and selects it. The sampling
+    /// rate of the first viewer is used if not given.
+    Load {
+        label: String,
+        trace: Vec<f32>,
+        sampling_rate: Option<f32>,
+    },
+    /// Marks an event at the given time in µs, in all the viewers.
+    AddMarker { time: f64, label: String },
+    /// Sends to a remote client the index of the selected viewer, or the first error of the
+    /// commands executed since the previous reply.
+    Reply(Sender<Result<usize, String>>),
+    /// Closes the application.
+    Quit,
+}
+
+/// Result of the evaluation of an automation script.
+#[derive(Default)]
+pub struct Automation {
+    /// Files to load, in addition to the command-line ones.
+    pub paths: Vec<String>,
+    /// Sampling rate in MS/s, if set by the script.
+    pub sampling_rate: Option<f32>,
+    /// Commands to execute once the traces are loaded.
+    pub commands: Vec<Command>,
+}
+
+impl Automation {
+    /// Evaluates the script at `path`.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let automation = Rc::new(RefCell::new(Automation::default()));
+        let engine = engine(&automation);
+        let script = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        engine
+            .run(&script)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        drop(engine);
+        Ok(automation.take())
+    }
+}
+
+/// Converts a script number, integer or float, to `f64`.
+fn number(value: Dynamic) -> Result<f64, Box<EvalAltResult>> {
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|x| x as f64))
+        .map_err(|t| format!("expected a number, got {}", t).into())
+}
+
+/// Converts a script number to a count, which must be positive.
+fn count(value: Dynamic) -> Result<usize, Box<EvalAltResult>> {
+    let x = value
+        .as_int()
+        .map_err(|t| format!("expected an integer, got {}", t))?;
+    usize::try_from(x)
+        .ok()
+        .filter(|&x| x > 0)
+        .ok_or_else(|| format!("expected a positive integer, got {}", x).into())
+}
+
+/// Creates the scripting engine, with functions recording their effects into `automation`.
+fn engine(automation: &Rc<RefCell<Automation>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let a = automation.clone();
+    engine.register_fn("load", move |path: &str| {
+        a.borrow_mut().paths.push(path.to_string())
+    });
+    let a = automation.clone();
+    engine.register_fn(
+        "sampling_rate",
+        move |rate: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            a.borrow_mut().sampling_rate = Some(number(rate)? as f32);
+            Ok(())
+        },
+    );
+
+    let push = {
+        let a = automation.clone();
+        move |command: Command| a.borrow_mut().commands.push(command)
+    };
+
+    let p = push.clone();
+    engine.register_fn(
+        "select",
+        move |index: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let index = index
+                .as_int()
+                .ok()
+                .and_then(|i| usize::try_from(i).ok())
+                .ok_or("expected a viewer index")?;
+            p(Command::Select(index));
+            Ok(())
+        },
+    );
+
+    for (name, filter) in [
+        ("lowpass", Filter::LowPass),
+        ("highpass", Filter::HighPass),
+        ("bandpass", Filter::BandPass),
+        ("notch", Filter::Notch),
+        ("comb", Filter::Comb),
+    ] {
+        let process = {
+            let p = push.clone();
+            let a = automation.clone();
+            move |freq: Dynamic, q: f64| -> Result<(), Box<EvalAltResult>> {
+                let spec = FilterSpec {
+                    filter,
+                    cutoff_freq: number(freq)? as f32,
+                    q: q as f32,
+                    notches: FilterSpec::DEFAULT_NOTCHES,
+                };
+                if !(spec.cutoff_freq.is_finite() && spec.cutoff_freq > 0.0) {
+                    return Err("filter frequency must be positive".into());
+                }
+                if !(spec.q.is_finite() && spec.q > 0.0) {
+                    return Err("filter Q must be positive".into());
+                }
+                // The frequency is checked against the sampling rate of the trace when the
+                // command is executed, but the error is clearer when the script sets the rate.
+                if let Some(rate) = a.borrow().sampling_rate
+                    && spec.coefficients(rate).is_err()
+                {
+                    return Err(format!(
+                        "filter frequency must be below the Nyquist frequency ({} MHz)",
+                        rate / 2.0
+                    )
+                    .into());
+                }
+                p(Command::Process(ProcessingStep::Filter(spec)));
+                Ok(())
+            }
+        };
+        let f = process.clone();
+        engine.register_fn(name, move |freq: Dynamic| {
+            f(freq, FilterSpec::DEFAULT_Q as f64)
+        });
+        engine.register_fn(
+            name,
+            move |freq: Dynamic, q: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                process(freq, number(q)?)
+            },
+        );
+    }
+
+    let p = push.clone();
+    engine.register_fn(
+        "smooth",
+        move |window: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            p(Command::Process(ProcessingStep::MovingAverage {
+                window: count(window)?,
+            }));
+            Ok(())
+        },
+    );
+    for (name, variance) in [("sliding_std", false), ("sliding_variance", true)] {
+        let p = push.clone();
+        engine.register_fn(
+            name,
+            move |window: Dynamic| -> Result<(), Box<EvalAltResult>> {
+                let window = count(window)?;
+                p(Command::Process(if variance {
+                    ProcessingStep::SlidingVariance { window }
+                } else {
+                    ProcessingStep::SlidingStdDev { window }
+                }));
+                Ok(())
+            },
+        );
+    }
+    let p = push.clone();
+    engine.register_fn(
+        "decimate",
+        move |factor: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let factor = number(factor)? as f32;
+            if factor <= 1.0 {
+                return Err("decimation factor must be greater than 1".into());
+            }
+            p(Command::Process(ProcessingStep::Decimate { factor }));
+            Ok(())
+        },
+    );
+    let p = push.clone();
+    engine.register_fn(
+        "upsample",
+        move |factor: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            p(Command::Process(ProcessingStep::Upsample {
+                factor: count(factor)?,
+            }));
+            Ok(())
+        },
+    );
+    for (name, step) in [
+        ("abs", ProcessingStep::Abs),
+        ("square", ProcessingStep::Square),
+        ("remove_dc", ProcessingStep::RemoveDc),
+        (
+            "envelope",
+            ProcessingStep::Envelope(EnvelopeMethod::Hilbert),
+        ),
+    ] {
+        let p = push.clone();
+        engine.register_fn(name, move || p(Command::Process(step)));
+    }
+
+    for (name, command) in [
+        ("reset_processing", Command::ResetProcessing),
+        ("autoscale", Command::Autoscale),
+        ("autoscale_y", Command::AutoscaleY),
+        ("clear_range", Command::ClearRange),
+        ("quit", Command::Quit),
+    ] {
+        let p = push.clone();
+        engine.register_fn(name, move || p(command.clone()));
+    }
+
+    let p = push.clone();
+    engine.register_fn(
+        "show",
+        move |start: Dynamic, end: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            p(Command::Show {
+                start: number(start)?,
+                end: number(end)?,
+            });
+            Ok(())
+        },
+    );
+    let p = push.clone();
+    engine.register_fn(
+        "select_range",
+        move |start: Dynamic, end: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            p(Command::SelectRange {
+                start: number(start)?,
+                end: number(end)?,
+            });
+            Ok(())
+        },
+    );
+    let p = push.clone();
+    engine.register_fn("export_image", move |path: &str| {
+        p(Command::ExportImage(PathBuf::from(path)))
+    });
+    engine.register_fn("export_npy", move |path: &str| {
+        push(Command::ExportNpy(PathBuf::from(path)))
+    });
+
+    engine
+}
@@ -0,0 +1,402 @@
+//! Commands running in the terminal without opening the viewer: rendering a trace to an image,
+//! converting trace files, printing their metadata and benchmarking the renderers.
+//!
+//! Each command returns the exit code of the process, non-zero on failure.
+
+use crate::{
+    export::{render_image, write_csv, write_csv_columns, write_npy, write_npy_rows, write_png},
+    input::{BenchArgs, ConvertArgs, InfoArgs, RenderArgs, TraceArgs},
+    loaders::{
+        BinFile, IsfFile, SampleType, TraceFormat, TrcFile, TrsFile, WavFile, WfmFile,
+        guess_format, load_file, mat_summary, open_capture, open_reader, parquet_summary,
+        unrecognized_format,
+    },
+    processing::{ProcessingStep, Progress, apply_steps, check_steps},
+    render::fit_settings,
+    renderer::{CpuRenderer, GpuRenderer, RENDERER_MAX_TRACE_SIZE, RenderJob, Renderer},
+    tiling::ColorScale,
+    tiling_policy::TILE_WIDTH,
+};
+use npyz::NpyFile;
+use std::{
+    io::{self, BufRead},
+    panic,
+    sync::{
+        Barrier,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Loads the frames of the file at `path` selected by `--frames`, without processing them.
+fn selected_frames(args: &TraceArgs, path: &str) -> Result<Vec<Vec<f32>>, String> {
+    let frames = load_file(
+        path,
+        args.format,
+        args.npy_layout,
+        args.skip_lines,
+        args.column,
+        args.dataset.as_deref(),
+        args.dtype,
+    )?;
+    let selection = args.frame_selection()?;
+    let frames: Vec<_> = frames
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selection.as_ref().is_none_or(|sel| sel.contains(*i)))
+        .map(|(_, frame)| frame)
+        .collect();
+    if frames.is_empty() {
+        return Err("no trace selected in the file".into());
+    }
+    Ok(frames)
+}
+
+/// Returns the processing steps given on the command line and by the preset, checked against the
+/// sampling rate so invalid filters are reported before the files are loaded.
+fn processing_steps(args: &TraceArgs) -> Result<Vec<ProcessingStep>, String> {
+    let steps = args.processing_steps()?;
+    check_steps(&steps, args.sampling_rate)?;
+    Ok(steps)
+}
+
+/// Applies the processing `steps` to `frame`, sampled at the rate given on the command line.
+fn process(args: &TraceArgs, steps: &[ProcessingStep], frame: &[f32]) -> Result<Vec<f32>, String> {
+    let processed = apply_steps(frame, steps, args.sampling_rate, &Progress::default())?;
+    Ok(processed.expect("the processing is never cancelled"))
+}
+
+/// Loads the first trace of the file at `path` selected by `--frames`, and applies the processing
+/// steps given on the command line.
+pub fn load_trace(args: &TraceArgs, path: &str) -> Result<Vec<f32>, String> {
+    let steps = processing_steps(args)?;
+    let frames = selected_frames(args, path)?;
+    process(args, &steps, &frames[0])
+}
+
+/// Prints the error of a command, and returns the exit code of the process.
+fn exit_code(result: Result<(), String>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// Renders the samples of the trace between `--start` and `--end` to a PNG image.
+pub fn render(args: &RenderArgs) -> i32 {
+    exit_code(run_render(args))
+}
+
+fn run_render(args: &RenderArgs) -> Result<(), String> {
+    let trace = load_trace(&args.trace, &args.path)?;
+    let range = args.range.range(trace.len())?;
+    let settings = fit_settings(
+        &trace[range.clone()],
+        args.width,
+        args.height,
+        args.display.color_scale(),
+    );
+    let image = render_image(&trace, range, settings).ok_or("the trace is too short")?;
+    write_png(&args.out, &image).map_err(|e| format!("{}: {}", args.out.display(), e))?;
+    println!("{} -> {}", args.path, args.out.display());
+    Ok(())
+}
+
+/// Writes the selected traces of a file, after processing, to a Numpy or CSV file.
+pub fn convert(args: &ConvertArgs) -> i32 {
+    exit_code(run_convert(args))
+}
+
+fn run_convert(args: &ConvertArgs) -> Result<(), String> {
+    let format = guess_format(&args.out.to_string_lossy());
+    if !matches!(format, Some(TraceFormat::Numpy | TraceFormat::Csv)) {
+        return Err("the output file must have a .npy or .csv extension".into());
+    }
+    let steps = processing_steps(&args.trace)?;
+    let frames: Vec<Vec<f32>> = selected_frames(&args.trace, &args.path)?
+        .iter()
+        .map(|frame| process(&args.trace, &steps, frame))
+        .collect::<Result<_, _>>()?;
+    let out = args.out.as_path();
+    let len = frames.iter().map(Vec::len).max().unwrap_or(0);
+    let range = args.range.range(len)?;
+    let result = match (format, frames.as_slice()) {
+        (Some(TraceFormat::Numpy), [trace]) => write_npy(out, trace, range),
+        (Some(TraceFormat::Numpy), _) => write_npy_rows(out, &frames, range),
+        (_, [trace]) => write_csv(out, trace, range),
+        _ => write_csv_columns(out, &frames, range),
+    };
+    result.map_err(|e| format!("{}: {}", out.display(), e))?;
+    println!(
+        "{} -> {} ({} trace(s))",
+        args.path,
+        out.display(),
+        frames.len()
+    );
+    Ok(())
+}
+
+/// Prints the metadata of each file.
+pub fn info(args: &InfoArgs) -> i32 {
+    let mut code = 0;
+    for path in &args.paths {
+        if let Err(e) = print_info(path, args.format, args.dtype) {
+            eprintln!("{}: {}", path, e);
+            code = 1;
+        }
+    }
+    code
+}
+
+fn print_info(path: &str, format: Option<TraceFormat>, dtype: SampleType) -> Result<(), String> {
+    let format = format
+        .or_else(|| guess_format(path))
+        .ok_or_else(|| unrecognized_format(path))?;
+    match format {
+        TraceFormat::Trs => {
+            let file = TrsFile::open(path).map_err(|e| e.to_string())?;
+            let header = &file.header;
+            println!(
+                "{}: TRS, {} traces, {} pts/trace, {} data bytes/trace",
+                path, header.traces, header.samples, header.data_len
+            );
+            if let Some(x_scale) = header.x_scale {
+                println!("  sampling rate: {:.3} MS/s", 1e-6 / x_scale);
+            }
+            if !header.global_title.is_empty() {
+                println!("  title: {}", header.global_title);
+            }
+            if !header.description.is_empty() {
+                println!("  description: {}", header.description);
+            }
+            for parameter in &header.parameters {
+                println!(
+                    "  data: {} ({} bytes at offset {})",
+                    parameter.name, parameter.len, parameter.offset
+                );
+            }
+        }
+        TraceFormat::TekWfm => {
+            let file = WfmFile::open(path).map_err(|e| e.to_string())?;
+            println!("{}", file.summary());
+        }
+        TraceFormat::LecroyTrc => {
+            let file = TrcFile::open(path).map_err(|e| e.to_string())?;
+            println!("{}", file.summary());
+        }
+        TraceFormat::Wav => {
+            let file = WavFile::open(path).map_err(|e| e.to_string())?;
+            println!("{}", file.summary());
+        }
+        TraceFormat::KeysightBin => {
+            let file = BinFile::open(path).map_err(|e| e.to_string())?;
+            println!("{}", file.summary());
+        }
+        TraceFormat::Isf => {
+            let file = IsfFile::open(path).map_err(|e| e.to_string())?;
+            println!("{}", file.summary());
+        }
+        TraceFormat::Sigrok | TraceFormat::Vcd => {
+            let capture = open_capture(path, format)
+                .unwrap()
+                .map_err(|e| e.to_string())?;
+            println!("{}", capture.summary(path));
+            for channel in &capture.logic {
+                println!("  logic: {}", channel.name);
+            }
+            for (name, _) in &capture.analog {
+                println!("  analog: {}", name);
+            }
+        }
+        TraceFormat::Mat => println!("{}", mat_summary(path).map_err(|e| e.to_string())?),
+        TraceFormat::Parquet => println!("{}", parquet_summary(path).map_err(|e| e.to_string())?),
+        TraceFormat::Numpy => {
+            let reader = open_reader(path).map_err(|e| e.to_string())?;
+            let npy = NpyFile::new(reader).map_err(|e| e.to_string())?;
+            println!(
+                "{}: NumPy {}, shape {:?}, {:?} order",
+                path,
+                npy.dtype().descr(),
+                npy.shape(),
+                npy.order()
+            );
+        }
+        TraceFormat::Csv => {
+            let reader = open_reader(path).map_err(|e| e.to_string())?;
+            let (mut count, mut columns) = (0, 0);
+            for line in reader.lines() {
+                let line = line.map_err(|e| e.to_string())?;
+                if count == 0 {
+                    columns = line.split(',').count();
+                }
+                count += 1;
+            }
+            println!("{}: CSV, {} lines, {} columns", path, count, columns);
+        }
+        TraceFormat::Raw => {
+            let mut reader = open_reader(path).map_err(|e| e.to_string())?;
+            let len = io::copy(&mut reader, &mut io::sink()).map_err(|e| e.to_string())?;
+            println!(
+                "{}: raw {}, {} pts",
+                path,
+                dtype.name(),
+                len / dtype.size() as u64
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Renders the tiles of a view of a whole trace with the GPU and CPU renderers, and prints the
+/// throughput of each renderer.
+pub fn bench(args: &BenchArgs) -> i32 {
+    exit_code(run_bench(args))
+}
+
+fn run_bench(args: &BenchArgs) -> Result<(), String> {
+    let trace = match &args.path {
+        Some(path) => load_trace(&args.trace, path)?,
+        None => generate_trace(args.samples),
+    };
+    if trace.len() < 2 || args.width < TILE_WIDTH || args.height == 0 {
+        return Err("the trace or the view is too small".into());
+    }
+    let settings = fit_settings(&trace, args.width, args.height, ColorScale::default());
+    let tile_samples = (TILE_WIDTH as f32 * settings.samples_per_column).ceil() as usize;
+    if tile_samples >= RENDERER_MAX_TRACE_SIZE {
+        return Err("the view is too narrow for the renderers, increase --width".into());
+    }
+    let bench = TileBench {
+        trace: &trace,
+        tiles: args.width.div_ceil(TILE_WIDTH) as usize,
+        samples_per_column: settings.samples_per_column,
+        height: args.height,
+        offset: settings.offset,
+        scale_y: settings.scale_y,
+    };
+    println!(
+        "{} samples, {} tiles of {}×{} pixels",
+        trace.len(),
+        bench.tiles,
+        TILE_WIDTH,
+        args.height
+    );
+    if args.gpu > 0 {
+        if GpuRenderer::is_available() {
+            bench.report("GPU", args.gpu, GpuRenderer::new);
+        } else {
+            println!("GPU renderer unavailable: no adapter supports compute shaders");
+        }
+    }
+    let cpu = args.cpu_threads();
+    if cpu > 0 {
+        bench.report("CPU", cpu, CpuRenderer::new);
+    }
+    Ok(())
+}
+
+/// Generates a noisy sine wave of `samples` samples, with a fixed seed.
+fn generate_trace(samples: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..samples)
+        .map(|i| {
+            // xorshift64 pseudo-random generator.
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let noise = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+            (i as f32 * 1e-3).sin() + noise * 0.2
+        })
+        .collect()
+}
+
+/// Rendering of the tiles of a view of a whole trace, split like in the viewer.
+struct TileBench<'a> {
+    trace: &'a [f32],
+    tiles: usize,
+    samples_per_column: f32,
+    height: u32,
+    offset: f32,
+    scale_y: f32,
+}
+
+impl TileBench<'_> {
+    /// Renders all the tiles with `threads` renderers created by `new`, and prints the time
+    /// spent.
+    fn report<R: Renderer>(&self, name: &str, threads: usize, new: fn() -> R) {
+        match self.run(threads, new) {
+            Some(elapsed) => {
+                let seconds = elapsed.as_secs_f64();
+                println!(
+                    "{} renderer, {} threads: {:.3} s, {:.1} MS/s, {:.1} tiles/s",
+                    name,
+                    threads,
+                    seconds,
+                    self.trace.len() as f64 / seconds / 1e6,
+                    self.tiles as f64 / seconds
+                );
+            }
+            None => println!("{} renderer unavailable", name),
+        }
+    }
+
+    /// Renders all the tiles with `threads` renderers, each rendering the next remaining tile.
+    /// The renderers are created before starting the measure. Returns `None` if a renderer could
+    /// not be created.
+    fn run<R: Renderer>(&self, threads: usize, new: fn() -> R) -> Option<Duration> {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let barrier = Barrier::new(threads + 1);
+        let start = thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    let renderer = panic::catch_unwind(new);
+                    if renderer.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    barrier.wait();
+                    let Ok(renderer) = renderer else {
+                        return;
+                    };
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= self.tiles || failed.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        self.render_tile(&renderer, index);
+                    }
+                });
+            }
+            barrier.wait();
+            Instant::now()
+        });
+        (!failed.load(Ordering::Relaxed)).then(|| start.elapsed())
+    }
+
+    /// Renders the tile at `index`, like [`crate::tiling::TilingRenderer`] does.
+    fn render_tile(&self, renderer: &impl Renderer, index: usize) {
+        let tile_samples = TILE_WIDTH as f32 * self.samples_per_column;
+        let start = (index as f32 * tile_samples).floor() as usize;
+        let end = ((index + 1) as f32 * tile_samples).floor() as usize;
+        if start >= self.trace.len() {
+            return;
+        }
+        let chunk = &self.trace[start..(end + 1).min(self.trace.len())];
+        if chunk.len() < 2 {
+            return;
+        }
+        renderer.render(RenderJob {
+            chunk_samples: tile_samples as u32,
+            trace: chunk.into(),
+            origin: None,
+            w: TILE_WIDTH,
+            h: self.height,
+            offset: self.offset,
+            scale_y: self.scale_y,
+        });
+    }
+}
@@ -1,5 +1,9 @@
 use crate::util::{Fixed, FixedVec2};
 use egui::Rect;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of bookmark slots of each viewer.
+pub const BOOKMARK_SLOTS: usize = 9;
 
 #[derive(Clone, Copy, PartialEq)]
 pub struct Camera {
@@ -36,3 +40,58 @@ impl Default for Camera {
         Self::new()
     }
 }
+
+/// Camera saved in a bookmark slot, in a form which can be saved in the settings.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    #[serde(with = "fixed_bits")]
+    pub scale: [Fixed; 2],
+    #[serde(with = "fixed_bits")]
+    pub shift: [Fixed; 2],
+}
+
+impl From<Camera> for Bookmark {
+    fn from(camera: Camera) -> Self {
+        Self {
+            scale: [camera.scale.x, camera.scale.y],
+            shift: [camera.shift.x, camera.shift.y],
+        }
+    }
+}
+
+impl From<Bookmark> for Camera {
+    fn from(bookmark: Bookmark) -> Self {
+        Self {
+            scale: FixedVec2 {
+                x: bookmark.scale[0],
+                y: bookmark.scale[1],
+            },
+            shift: FixedVec2 {
+                x: bookmark.shift[0],
+                y: bookmark.shift[1],
+            },
+        }
+    }
+}
+
+/// Saves fixed-point numbers as the decimal strings of their bits, so the far positions of long
+/// traces are restored exactly, which floats cannot hold.
+mod fixed_bits {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(values: &[Fixed; 2], serializer: S) -> Result<S::Ok, S::Error> {
+        values
+            .map(|x| x.to_bits().to_string())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[Fixed; 2], D::Error> {
+        let [a, b] = <[String; 2]>::deserialize(deserializer)?;
+        let parse = |bits: String| {
+            bits.parse()
+                .map(Fixed::from_bits)
+                .map_err(|_| serde::de::Error::custom(format!("invalid bits '{}'", bits)))
+        };
+        Ok([parse(a)?, parse(b)?])
+    }
+}
@@ -0,0 +1,120 @@
+//! Clock cycle grid of a trace.
+//!
+//! Power traces of a synchronous device are made of one pattern per clock cycle. Once the clock
+//! period has been measured, the camera can be stepped by whole cycles and the cycle boundaries
+//! painted over the trace, so the instructions can be inspected one by one.
+
+use crate::{camera::Camera, format::NumberFormat, util::Fixed};
+use egui::{Color32, DragValue, Painter, Rect, Stroke, Ui};
+
+/// Minimum distance between two painted cycle boundaries, in points. Boundaries are hidden when
+/// the cycles are shorter on the screen.
+const MIN_BOUNDARY_SPACING: f32 = 4.0;
+
+/// Clock cycle grid, in samples of the displayed trace.
+pub struct ClockGrid {
+    /// Clock period, in samples.
+    pub period: f64,
+    /// Position of a cycle boundary, in samples.
+    pub origin: f64,
+    /// Number of cycles the camera moves with the `[` and `]` keys.
+    pub step: u32,
+    /// When true, the cycle boundaries are painted over the trace.
+    pub show_boundaries: bool,
+}
+
+impl ClockGrid {
+    pub fn new(period: f64, origin: f64) -> Self {
+        Self {
+            period,
+            origin,
+            step: 1,
+            show_boundaries: true,
+        }
+    }
+
+    /// Scales the grid when the sampling rate of the trace is multiplied by `k`.
+    pub fn rescale(&mut self, k: f64) {
+        self.period *= k;
+        self.origin *= k;
+    }
+
+    /// Camera shift moving the view by `direction` steps of [`ClockGrid::step`] cycles.
+    pub fn step_shift(&self, direction: i32) -> Fixed {
+        Fixed::from_num(direction as f64 * self.step as f64 * self.period)
+    }
+
+    /// Shows the grid settings. Returns false if the user removes the grid.
+    pub fn ui(&mut self, ui: &mut Ui, sampling_rate: f32, format: &NumberFormat) -> bool {
+        let mut keep = true;
+        egui::Grid::new("clock_grid").num_columns(2).show(ui, |ui| {
+            ui.label("Period:");
+            ui.horizontal(|ui| {
+                ui.add(
+                    DragValue::new(&mut self.period)
+                        .range(1.0..=f64::MAX)
+                        .speed(0.01)
+                        .suffix(" samples"),
+                );
+                ui.label(format!(
+                    "({})",
+                    format.quantity(sampling_rate as f64 * 1e6 / self.period, "Hz")
+                ));
+            });
+            ui.end_row();
+            ui.label("Phase:");
+            let mut phase = self.origin.rem_euclid(self.period);
+            if ui
+                .add(
+                    DragValue::new(&mut phase)
+                        .range(0.0..=self.period)
+                        .speed(0.1)
+                        .suffix(" samples"),
+                )
+                .on_hover_text("Position of the cycle boundaries")
+                .changed()
+            {
+                self.origin = phase;
+            }
+            ui.end_row();
+            ui.label("Step:");
+            ui.add(
+                DragValue::new(&mut self.step)
+                    .range(1..=u32::MAX)
+                    .suffix(" cycles"),
+            )
+            .on_hover_text("Cycles moved with the [ and ] keys");
+            ui.end_row();
+        });
+        ui.checkbox(&mut self.show_boundaries, "Show cycle boundaries");
+        if ui.button("Remove").clicked() {
+            keep = false;
+        }
+        keep
+    }
+
+    /// Paints the cycle boundaries as faint vertical lines over the viewport, if they are not too
+    /// close to each other.
+    pub fn paint(&self, camera: &Camera, ppp: f32, painter: &Painter, viewport: &Rect) {
+        if !self.show_boundaries
+            || self.period / camera.scale.x.to_num::<f64>() < (MIN_BOUNDARY_SPACING * ppp) as f64
+        {
+            return;
+        }
+        let painter = painter.with_clip_rect(*viewport);
+        let stroke = Stroke::new(1.0, Color32::from_white_alpha(40));
+        let to_cycle = |x: f32| {
+            (camera.screen_to_world_x(viewport, ppp, x).to_num::<f64>() - self.origin) / self.period
+        };
+        let first = to_cycle(0.0).ceil() as i64;
+        let last = to_cycle(viewport.width()).floor() as i64;
+        for cycle in first..=last {
+            let x = camera.world_to_screen_x(
+                viewport,
+                ppp,
+                Fixed::from_num(self.origin + cycle as f64 * self.period),
+            );
+            painter.vline(x, viewport.y_range(), stroke);
+        }
+    }
+}
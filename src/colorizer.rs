@@ -0,0 +1,320 @@
+//! Colorization of the rendered tiles by the GPU painting the interface.
+//!
+//! When the interface is painted with wgpu, the densities of the tiles are uploaded to textures
+//! colored by a render pass applying the color scale, and registered with the egui renderer,
+//! instead of being colored by the CPU in [`density_image`](crate::tiling::density_image).
+
+use crate::tiling::{ColorScale, Gradient, Tile};
+use eframe::{
+    egui_wgpu::{self, RenderState},
+    wgpu::{self, util::DeviceExt},
+};
+use egui::{Color32, Id, TextureHandle, TextureId, epaint::mutex::RwLock};
+use std::sync::Arc;
+
+/// Colors the tiles on the device of the egui renderer.
+pub struct TileColorizer {
+    render_state: RenderState,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    /// Multiplied by the densities, the inverse of the number of samples of each pixel column.
+    density_scale: f32,
+    power: f32,
+    opacity: f32,
+    /// 0 for [`Gradient::SingleColor`], 1 for [`Gradient::BiColor`], 2 for [`Gradient::Rainbow`]
+    /// and 3 for [`Gradient::Tint`].
+    gradient: u32,
+    /// Intensity of the lowest densities of the single color gradient.
+    min: f32,
+    _padding: [f32; 3],
+    /// Colors of the gradient and of the pixels without density, as sRGB components from 0 to
+    /// 255.
+    start: [f32; 4],
+    end: [f32; 4],
+    background: [f32; 4],
+}
+
+impl TileColorizer {
+    /// Makes a colorizer available to the viewers painted in `ctx`, if it is painted with wgpu.
+    pub fn install(ctx: &egui::Context, render_state: Option<&RenderState>) {
+        if let Some(render_state) = render_state {
+            let colorizer = Arc::new(Self::new(render_state));
+            ctx.data_mut(|d| d.insert_temp(Self::memory_id(), colorizer));
+        }
+    }
+
+    /// Returns the colorizer installed in `ctx`, if any.
+    pub fn get(ctx: &egui::Context) -> Option<Arc<Self>> {
+        ctx.data(|d| d.get_temp(Self::memory_id()))
+    }
+
+    /// Identifier of the colorizer in egui memory. Shared between all the viewers.
+    fn memory_id() -> Id {
+        Id::new("tile_colorizer")
+    }
+
+    fn new(render_state: &RenderState) -> Self {
+        let device = &render_state.device;
+        let shader = device.create_shader_module(wgpu::include_wgsl!("colorizer.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("colorizer_bind_group_layout"),
+            entries: &[
+                // Densities
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Color scale
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("colorizer"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                // egui textures hold sRGB components, which are written as such.
+                targets: &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        Self {
+            render_state: render_state.clone(),
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    /// Returns the texture of `tile` colored with `color_scale`, like
+    /// [`Tile::generate_image`]. Returns `None` if the tile is larger than the textures of the
+    /// device.
+    pub fn texture(&self, tile: &Tile, color_scale: ColorScale) -> Option<TileTexture> {
+        let RenderState {
+            device,
+            queue,
+            renderer,
+            ..
+        } = &self.render_state;
+        let (w, h) = (tile.properties.size.width(), tile.properties.size.height());
+        let max = device.limits().max_texture_dimension_2d;
+        if w == 0 || h == 0 || w > max || h > max {
+            return None;
+        }
+
+        // The densities of each column are a row of the densities texture.
+        let densities = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("tile_densities"),
+                size: wgpu::Extent3d {
+                    width: h,
+                    height: w,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Uint,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&tile.data),
+        );
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("colorizer_params"),
+            contents: bytemuck::cast_slice(&[Params::new(
+                color_scale,
+                tile.properties.scale.x.to_num::<f32>(),
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("colorizer_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &densities.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params.as_entire_binding(),
+                },
+            ],
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("tile"),
+            size: wgpu::Extent3d {
+                width: w,
+                height: h,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let id = renderer
+            .write()
+            .register_native_texture(device, &view, wgpu::FilterMode::Nearest);
+        let texture = NativeTexture {
+            id,
+            renderer: renderer.clone(),
+            view,
+            bind_group,
+            params,
+            samples_per_column: tile.properties.scale.x.to_num::<f32>(),
+        };
+        let mut commands =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.color(&mut commands, &texture);
+        queue.submit([commands.finish()]);
+        Some(TileTexture::Native(texture))
+    }
+
+    /// Colors again `textures` with `color_scale`, from the densities they have been created
+    /// from, in a single submission.
+    pub fn recolor<'a>(
+        &self,
+        textures: impl IntoIterator<Item = &'a NativeTexture>,
+        color_scale: ColorScale,
+    ) {
+        let RenderState { device, queue, .. } = &self.render_state;
+        let mut commands =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for texture in textures {
+            queue.write_buffer(
+                &texture.params,
+                0,
+                bytemuck::cast_slice(&[Params::new(color_scale, texture.samples_per_column)]),
+            );
+            self.color(&mut commands, texture);
+        }
+        queue.submit([commands.finish()]);
+    }
+
+    /// Records the pass coloring `texture` from its densities, with the color scale of its
+    /// parameters.
+    fn color(&self, commands: &mut wgpu::CommandEncoder, texture: &NativeTexture) {
+        let mut pass = commands.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("colorizer"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &texture.view,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &texture.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+impl Params {
+    fn new(color_scale: ColorScale, samples_per_column: f32) -> Self {
+        let components = |color: Color32| color.to_array().map(|c| c as f32);
+        let (gradient, min, start, end) = match color_scale.gradient {
+            Gradient::SingleColor { min, end } => (0, min, Color32::BLACK, end),
+            Gradient::BiColor { start, end } => (1, 0.0, start, end),
+            Gradient::Rainbow => (2, 0.0, Color32::BLACK, Color32::BLACK),
+            Gradient::Tint { color } => (3, 0.0, Color32::BLACK, color),
+        };
+        Self {
+            density_scale: 1.0 / samples_per_column,
+            power: color_scale.power,
+            opacity: color_scale.opacity,
+            gradient,
+            min,
+            _padding: [0.0; 3],
+            start: components(start),
+            end: components(end),
+            background: components(color_scale.gradient.background()),
+        }
+    }
+}
+
+/// Texture of a rendered tile, colored by the CPU or by a [`TileColorizer`].
+pub enum TileTexture {
+    Image(TextureHandle),
+    Native(NativeTexture),
+}
+
+impl TileTexture {
+    pub fn id(&self) -> TextureId {
+        match self {
+            TileTexture::Image(handle) => handle.id(),
+            TileTexture::Native(texture) => texture.id,
+        }
+    }
+}
+
+/// Texture registered with the egui renderer, freed when dropped. The densities it is colored
+/// from are kept on the GPU, so it can be colored again when the color scale changes.
+pub struct NativeTexture {
+    id: TextureId,
+    renderer: Arc<RwLock<egui_wgpu::Renderer>>,
+    /// View of the colored texture.
+    view: wgpu::TextureView,
+    /// Binds the densities and the parameters.
+    bind_group: wgpu::BindGroup,
+    /// Parameters of the color scale.
+    params: wgpu::Buffer,
+    samples_per_column: f32,
+}
+
+impl Drop for NativeTexture {
+    fn drop(&mut self) {
+        self.renderer.write().free_texture(&self.id);
+    }
+}
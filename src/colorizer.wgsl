@@ -0,0 +1,104 @@
+// Colors the densities of a tile like `density_image`, for a texture painted by egui. The colors
+// are sRGB components from 0 to 255, like in `Color32`, and written as such in the texture.
+
+struct Params {
+    // Multiplied by the densities, the inverse of the number of samples of each pixel column.
+    density_scale: f32,
+    power: f32,
+    opacity: f32,
+    // 0 for a single color, 1 for a bi-color gradient, 2 for the rainbow and 3 for a tint.
+    gradient: u32,
+    // Intensity of the lowest densities of the single color gradient.
+    min: f32,
+    // First color of the bi-color gradient.
+    start: vec4<f32>,
+    // Color of the highest densities of the single color and bi-color gradients, and of the tint.
+    end: vec4<f32>,
+    // Color of the pixels without density.
+    background: vec4<f32>,
+};
+
+// Densities of the tile, column by column: the density of the pixel (x, y) is at (y, x).
+@group(0) @binding(0)
+var densities: texture_2d<u32>;
+
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// Rounds like `Color32`, saturating to a byte.
+fn round_u8(value: f32) -> f32 {
+    return clamp(floor(value + 0.5), 0.0, 255.0);
+}
+
+// Linear interpolation between two colors, in gamma space like `Color32::lerp_to_gamma`.
+fn lerp_to_gamma(a: vec4<f32>, b: vec4<f32>, t: f32) -> vec4<f32> {
+    let color = (1.0 - t) * a + t * b;
+    return vec4<f32>(round_u8(color.r), round_u8(color.g), round_u8(color.b), round_u8(color.a));
+}
+
+// Gamma encoding of a linear component, like `ecolor::gamma_u8_from_linear_f32`.
+fn gamma_from_linear(l: f32) -> f32 {
+    if (l <= 0.0) {
+        return 0.0;
+    } else if (l <= 0.0031308) {
+        return round_u8(3294.6 * l);
+    } else if (l <= 1.0) {
+        return round_u8(269.025 * pow(l, 1.0 / 2.4) - 14.025);
+    }
+    return 255.0;
+}
+
+// Fully saturated and bright color of hue `h`, like `Hsva::new(h, 1.0, 1.0, 1.0)`.
+fn rainbow(h: f32) -> vec4<f32> {
+    let f = h * 6.0 - floor(h * 6.0);
+    var rgb: vec3<f32>;
+    switch (i32(floor(h * 6.0)) % 6) {
+        case 0: { rgb = vec3<f32>(1.0, f, 0.0); }
+        case 1: { rgb = vec3<f32>(1.0 - f, 1.0, 0.0); }
+        case 2: { rgb = vec3<f32>(0.0, 1.0, f); }
+        case 3: { rgb = vec3<f32>(0.0, 1.0 - f, 1.0); }
+        case 4: { rgb = vec3<f32>(f, 0.0, 1.0); }
+        default: { rgb = vec3<f32>(1.0, 0.0, 1.0 - f); }
+    }
+    return vec4<f32>(gamma_from_linear(rgb.r), gamma_from_linear(rgb.g), gamma_from_linear(rgb.b), 255.0);
+}
+
+// Color of the intensity x, from 0 to 1, like `Gradient::apply`.
+fn gradient(x: f32) -> vec4<f32> {
+    switch (params.gradient) {
+        case 0u: {
+            let t = x * (1.0 - params.min) + params.min;
+            return lerp_to_gamma(vec4<f32>(0.0, 0.0, 0.0, 255.0), params.end, t);
+        }
+        case 1u: {
+            return lerp_to_gamma(params.start, params.end, x);
+        }
+        case 2u: {
+            return rainbow(mix(4.0 / 6.0, 0.0, x));
+        }
+        default: {
+            let color = params.end * x;
+            return vec4<f32>(round_u8(color.r), round_u8(color.g), round_u8(color.b), round_u8(color.a));
+        }
+    }
+}
+
+// Triangle covering the whole texture.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let density = textureLoad(densities, vec2<u32>(u32(position.y), u32(position.x)), 0).r;
+    var color = params.background;
+    if (density != 0u) {
+        let a = pow(f32(density) * params.density_scale, params.power) * params.opacity;
+        if (a > 0.0) {
+            color = gradient(clamp(a, 0.0, 1.0));
+        }
+    }
+    return color / 255.0;
+}
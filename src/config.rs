@@ -0,0 +1,263 @@
+//! User configuration file, stored as `turboplot/config.toml` in the user configuration
+//! directory (`~/.config` on Linux), or given with `--config`.
+//!
+//! The `defaults` section replaces the default values of command-line options, which still take
+//! precedence when they are given. The `keys` section sets the keyboard shortcuts of the viewer,
+//! with the key names of egui (`ArrowLeft`, `A`, `[`...). For instance:
+//!
+//! ```toml
+//! [defaults]
+//! sampling-rate = 500.0
+//! gpu = 2
+//! cpu = 4
+//! tile-cache = 256
+//! tiling = "fixed"
+//! tile-width = 128
+//! gradient = "single-color"
+//! power = 0.8
+//! opacity = 20.0
+//!
+//! [keys]
+//! pan-left = "A"
+//! pan-right = "D"
+//! ```
+//!
+//! The `format` section sets how the numbers are displayed, as described in
+//! [`crate::format`].
+//!
+//! The file also holds the processing presets: named processing chains which can be applied from
+//! the command line with `--preset` or from the "Processing" menu. For instance:
+//!
+//! ```toml
+//! [[presets.em_envelope]]
+//! step = "filter"
+//! filter = "band-pass"
+//! cutoff-freq = 20000.0
+//! q = 2.0
+//! notches = 8
+//!
+//! [[presets.em_envelope]]
+//! step = "envelope"
+//! method = "hilbert"
+//! ```
+
+use crate::{
+    format::NumberFormat, processing::ProcessingStep, tiling::GradientKind,
+    tiling_policy::TilingMode,
+};
+use egui::Key;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::OnceLock};
+use toml_edit::{DocumentMut, Table};
+
+/// Path of the configuration file given with `--config`, replacing the default one.
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Content of the configuration file. Missing entries take their default value.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Default values of the command-line options.
+    pub defaults: Defaults,
+    /// Keyboard shortcuts of the viewer.
+    pub keys: KeyBindings,
+    /// Formatting of the numbers displayed by the viewer.
+    pub format: NumberFormat,
+    /// Processing presets, by name.
+    pub presets: BTreeMap<String, Vec<ProcessingStep>>,
+}
+
+/// Default values of command-line options. Unset entries keep the built-in defaults.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Defaults {
+    /// Trace sampling rate in MS/s.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling_rate: Option<f32>,
+    /// Number of GPU rendering threads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<usize>,
+    /// Number of CPU rendering threads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<usize>,
+    /// Maximum number of rendered tiles kept in cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_cache: Option<usize>,
+    /// How the width of the rendered tiles is chosen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiling: Option<TilingMode>,
+    /// Width of the rendered tiles, the minimum width in adaptive tiling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_width: Option<u32>,
+    /// Maximum width of the rendered tiles in adaptive tiling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tile_width: Option<u32>,
+    /// Color gradient of the density rendering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<GradientKind>,
+    /// Power applied to the density.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<f32>,
+    /// Opacity of the density.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f32>,
+}
+
+/// Keyboard shortcuts of the viewer.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KeyBindings {
+    /// Moves the view to the left while pressed.
+    #[serde(with = "key_name")]
+    pub pan_left: Key,
+    /// Moves the view to the right while pressed.
+    #[serde(with = "key_name")]
+    pub pan_right: Key,
+    /// Steps the view one clock cycle backward.
+    #[serde(with = "key_name")]
+    pub previous_cycle: Key,
+    /// Steps the view one clock cycle forward.
+    #[serde(with = "key_name")]
+    pub next_cycle: Key,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            pan_left: Key::ArrowLeft,
+            pan_right: Key::ArrowRight,
+            previous_cycle: Key::OpenBracket,
+            next_cycle: Key::CloseBracket,
+        }
+    }
+}
+
+/// Keys are stored by name in the configuration file.
+mod key_name {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(key.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Key::from_name(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown key '{}'", name)))
+    }
+}
+
+impl Config {
+    /// Replaces the path of the configuration file, for `--config`. Must be called before any
+    /// other use of the configuration.
+    pub fn set_path(path: PathBuf) {
+        PATH.set(path).ok();
+    }
+
+    /// Path of the configuration file, if the configuration directory of the user is known.
+    pub fn path() -> Option<PathBuf> {
+        PATH.get()
+            .cloned()
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("turboplot").join("config.toml")))
+    }
+
+    /// Reads the configuration file. Returns the default configuration if the file does not
+    /// exist yet, unless it has been given with `--config`.
+    pub fn load() -> Result<Self, String> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        if !path.exists() {
+            if PATH.get().is_some() {
+                return Err(format!("{}: file not found", path.display()));
+            }
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Saves `steps` as the preset `name` in the configuration file, replacing any preset with
+    /// the same name.
+    pub fn save_preset(&mut self, name: &str, steps: &[ProcessingStep]) -> Result<(), String> {
+        #[derive(Serialize)]
+        struct Preset<'a> {
+            steps: &'a [ProcessingStep],
+        }
+        let text = toml::to_string(&Preset { steps }).map_err(|e| e.to_string())?;
+        let mut preset: DocumentMut = text.parse().map_err(|e| format!("{}", e))?;
+        let mut item = preset.remove("steps").unwrap_or_default();
+        Self::edit_presets(|presets, first| {
+            // The tables are written after the preceding preset, separated by a blank line.
+            if let Some(tables) = item.as_array_of_tables_mut() {
+                for (i, table) in tables.iter_mut().enumerate() {
+                    table.set_position(None);
+                    if i > 0 || !first {
+                        table.decor_mut().set_prefix("\n");
+                    }
+                }
+            }
+            presets.insert(name, item);
+        })?;
+        self.presets.insert(name.to_string(), steps.to_vec());
+        Ok(())
+    }
+
+    /// Removes the preset `name` from the configuration file.
+    pub fn delete_preset(&mut self, name: &str) -> Result<(), String> {
+        Self::edit_presets(|presets, _| {
+            presets.remove(name);
+        })?;
+        self.presets.remove(name);
+        Ok(())
+    }
+
+    /// Applies `edit` to the `presets` table of the configuration file, creating the file and its
+    /// directory if needed. The rest of the file is kept as written, with its comments and
+    /// formatting. `edit` is also told if the file is empty.
+    fn edit_presets(edit: impl FnOnce(&mut Table, bool)) -> Result<(), String> {
+        let path = Self::path().ok_or("Configuration directory not found")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let text = if path.exists() {
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            String::new()
+        };
+        let mut document: DocumentMut = text
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let first = document.is_empty();
+        let presets = document
+            .entry("presets")
+            .or_insert_with(|| {
+                let mut presets = Table::new();
+                presets.set_implicit(true);
+                presets.into()
+            })
+            .as_table_mut()
+            .ok_or_else(|| format!("{}: presets must be a table", path.display()))?;
+        edit(presets, first);
+        if presets.is_empty() {
+            document.remove("presets");
+        }
+        fs::write(&path, document.to_string()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Returns the steps of the preset `name`.
+    pub fn preset(&self, name: &str) -> Result<&[ProcessingStep], String> {
+        self.presets.get(name).map(Vec::as_slice).ok_or_else(|| {
+            let names: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+            if names.is_empty() {
+                format!("Unknown preset '{}': no preset saved", name)
+            } else {
+                format!(
+                    "Unknown preset '{}', available presets: {}",
+                    name,
+                    names.join(", ")
+                )
+            }
+        })
+    }
+}
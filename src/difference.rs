@@ -0,0 +1,132 @@
+//! Live difference between the traces of two viewers.
+//!
+//! The difference is displayed by its own viewer, and calculated again in background each time
+//! one of the source traces or their offsets change, so the divergence of two captures can be
+//! inspected while processing them.
+
+use crate::{processing::ProcessingJob, util::Fixed};
+use egui::{Button, ComboBox, Id, Modal};
+
+/// Calculates `a[i] - b[i + lag]` on the samples available in both traces.
+///
+/// Returns the difference and the index of `a` of its first sample.
+pub fn difference(a: &[f32], b: &[f32], lag: isize) -> (Vec<f32>, usize) {
+    let start = (-lag).max(0) as usize;
+    let end = (b.len() as isize - lag).clamp(0, a.len() as isize) as usize;
+    if end <= start {
+        return (Vec::new(), start);
+    }
+    let b_start = (start as isize + lag) as usize;
+    let trace = a[start..end]
+        .iter()
+        .zip(&b[b_start..])
+        .map(|(&x, &y)| x - y)
+        .collect();
+    (trace, start)
+}
+
+/// State of the sources a difference has been calculated from. The difference is calculated again
+/// when it changes.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SourcesState {
+    /// Generations of the two source traces.
+    pub generations: (u32, u32),
+    /// Horizontal offsets of the two source viewers.
+    pub offsets: (Fixed, Fixed),
+}
+
+/// A difference viewer and its sources, as viewer indices.
+pub struct DifferenceLink {
+    pub a: usize,
+    pub b: usize,
+    /// Viewer displaying the difference, created when the first calculation finishes.
+    pub target: Option<usize>,
+    /// State of the sources for the last started calculation.
+    pub state: Option<SourcesState>,
+    /// Running calculation: the difference, and the index of `a` of its first sample.
+    pub job: Option<ProcessingJob<(Vec<f32>, usize)>>,
+}
+
+impl DifferenceLink {
+    pub fn new(a: usize, b: usize) -> Self {
+        Self {
+            a,
+            b,
+            target: None,
+            state: None,
+            job: None,
+        }
+    }
+}
+
+/// Result of the difference dialog update.
+pub enum DifferenceDialogResult {
+    /// The user is still choosing the trace to subtract.
+    Pending,
+    /// The difference between the two viewers must be created.
+    Create { a: usize, b: usize },
+    /// Dialog was cancelled.
+    Cancelled,
+}
+
+/// Dialog for choosing the trace subtracted from the trace of a viewer.
+pub struct DifferenceDialog {
+    id: Id,
+    /// Index of the viewer the dialog has been opened from.
+    a: usize,
+    /// Index of the subtracted trace.
+    b: Option<usize>,
+}
+
+impl DifferenceDialog {
+    pub fn new(id: Id, a: usize) -> Self {
+        Self { id, a, b: None }
+    }
+
+    /// Shows the dialog and returns the user decision. `labels` are the labels of all the
+    /// viewers.
+    pub fn show(&mut self, ctx: &egui::Context, labels: &[String]) -> DifferenceDialogResult {
+        let mut result = DifferenceDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("Difference with another trace");
+            ui.add_space(5.0);
+            egui::Grid::new("difference_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Trace:");
+                    ui.label(&labels[self.a]);
+                    ui.end_row();
+                    ui.label("Minus:");
+                    ComboBox::from_id_salt("difference_b")
+                        .selected_text(self.b.map(|b| labels[b].as_str()).unwrap_or("None"))
+                        .show_ui(ui, |ui| {
+                            for (i, label) in labels.iter().enumerate() {
+                                if i != self.a {
+                                    ui.selectable_value(&mut self.b, Some(i), label);
+                                }
+                            }
+                        });
+                    ui.end_row();
+                });
+            ui.label("The difference is updated when a trace or its offset changes.");
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(self.b.is_some(), Button::new("Create"))
+                    .clicked()
+                    && let Some(b) = self.b
+                {
+                    result = DifferenceDialogResult::Create { a: self.a, b };
+                }
+                if ui.button("Cancel").clicked() {
+                    result = DifferenceDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() {
+            DifferenceDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Extraction of digital signals from analog traces.
+//!
+//! Trigger, UART or clock lines are often captured on analog channels. A threshold with
+//! hysteresis converts such a trace into a logic level, displayed as a compact strip under the
+//! analog waveform. The digital channels of logic analyzer captures and simulation dumps are
+//! displayed the same way, one strip per channel, the buses showing their value at each change.
+
+use crate::{camera::Camera, processing::ProcessingJob, trace::Trace, util::Fixed};
+use egui::{Align2, Color32, FontId, Painter, Rect, Stroke, pos2};
+use std::sync::Arc;
+
+/// Height of the logic strip, in points.
+const STRIP_HEIGHT: f32 = 14.0;
+
+/// Margin between the logic strip and the bottom of the viewport, in points.
+const STRIP_MARGIN: f32 = 4.0;
+
+/// Vertical distance between two stacked logic strips, in points.
+const STRIP_SPACING: f32 = STRIP_HEIGHT + 6.0;
+
+/// Thresholds of a digital conversion. The level goes high when a sample exceeds `high`, and low
+/// when a sample falls below `low`; samples in between keep the previous level, so noise around
+/// a single threshold does not produce spurious edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    pub low: f32,
+    pub high: f32,
+}
+
+/// A logic level over time, stored as its transitions.
+pub struct DigitalLane {
+    /// Level of the first sample.
+    initial: bool,
+    /// Indices of the samples where the level toggles, in increasing order.
+    edges: Vec<usize>,
+}
+
+impl DigitalLane {
+    /// Creates a lane from the level of its first sample and the indices of the samples where the
+    /// level toggles, in increasing order.
+    pub fn from_edges(initial: bool, edges: Vec<usize>) -> Self {
+        Self { initial, edges }
+    }
+
+    /// Converts `trace` to a logic level with the given thresholds.
+    pub fn new(trace: &[f32], thresholds: Thresholds) -> Self {
+        let initial = trace
+            .first()
+            .is_some_and(|&x| x > thresholds.low.midpoint(thresholds.high));
+        let mut level = initial;
+        let mut edges = Vec::new();
+        for (i, &x) in trace.iter().enumerate() {
+            if (!level && x > thresholds.high) || (level && x < thresholds.low) {
+                level = !level;
+                edges.push(i);
+            }
+        }
+        Self { initial, edges }
+    }
+
+    /// Level of the first sample.
+    pub fn initial(&self) -> bool {
+        self.initial
+    }
+
+    /// Indices of the samples where the level toggles, in increasing order.
+    pub fn edges(&self) -> &[usize] {
+        &self.edges
+    }
+
+    /// Returns the level of the sample at index `x`, which may be fractional.
+    fn level_at(&self, x: f64) -> bool {
+        let toggles = self.edges.partition_point(|&e| (e as f64) <= x);
+        self.initial ^ (toggles % 2 == 1)
+    }
+
+    /// Paints the lane as a strip at the bottom of the viewport, `row` strips above the lowest
+    /// one, with an optional `label` on its left.
+    ///
+    /// When several edges fall in the same pixel column, a single vertical line is drawn for the
+    /// column, so the number of drawn points does not exceed the viewport width.
+    pub fn paint(
+        &self,
+        camera: &Camera,
+        ppp: f32,
+        painter: &Painter,
+        viewport: &Rect,
+        row: usize,
+        label: Option<&str>,
+    ) {
+        paint_strip(painter, viewport, row, label, |top, bottom| {
+            let y = |level: bool| if level { top } else { bottom };
+            let x0 = camera.screen_to_world_x(viewport, ppp, 0.0).to_num::<f64>();
+            let x1 = camera
+                .screen_to_world_x(viewport, ppp, viewport.width())
+                .to_num::<f64>();
+            let mut level = self.level_at(x0);
+            let mut points = vec![pos2(viewport.min.x, y(level))];
+            let mut i = self.edges.partition_point(|&e| (e as f64) <= x0);
+            while i < self.edges.len() && (self.edges[i] as f64) < x1 {
+                let x = camera.world_to_screen_x(viewport, ppp, Fixed::from_num(self.edges[i]));
+                // Edges in the same pixel column are merged.
+                let column_end =
+                    camera.screen_to_world_x(viewport, ppp, ((x * ppp).floor() + 1.0) / ppp);
+                let j = self
+                    .edges
+                    .partition_point(|&e| Fixed::from_num(e) < column_end)
+                    .max(i + 1);
+                let toggles = j - i;
+                points.push(pos2(x, y(level)));
+                if toggles > 1 {
+                    points.push(pos2(x, y(!level)));
+                    points.push(pos2(x, y(level)));
+                }
+                if toggles % 2 == 1 {
+                    level = !level;
+                }
+                points.push(pos2(x, y(level)));
+                i = j;
+            }
+            points.push(pos2(viewport.max.x, y(level)));
+            painter.line(points, Stroke::new(1.0, Color32::LIGHT_GREEN));
+        });
+    }
+
+    /// Returns the level of the `samples` first samples, as zeros and ones.
+    fn samples(&self, samples: usize) -> Vec<f32> {
+        let mut level = self.initial;
+        let mut edges = self.edges.iter().peekable();
+        (0..samples)
+            .map(|i| {
+                while edges.next_if(|&&edge| edge <= i).is_some() {
+                    level = !level;
+                }
+                level as u8 as f32
+            })
+            .collect()
+    }
+}
+
+/// Paints the background of the strip `row` strips above the lowest one, its content with `draw`
+/// given the vertical bounds of the strip, and an optional `label` on its left.
+fn paint_strip(
+    painter: &Painter,
+    viewport: &Rect,
+    row: usize,
+    label: Option<&str>,
+    draw: impl FnOnce(f32, f32),
+) {
+    let bottom = viewport.max.y - STRIP_MARGIN - row as f32 * STRIP_SPACING;
+    let top = bottom - STRIP_HEIGHT;
+    painter.rect_filled(
+        Rect::from_min_max(
+            pos2(viewport.min.x, top - 2.0),
+            pos2(viewport.max.x, bottom + 2.0),
+        ),
+        0.0,
+        Color32::from_black_alpha(160),
+    );
+    draw(top, bottom);
+    if let Some(label) = label {
+        painter.text(
+            pos2(viewport.min.x + 4.0, top.midpoint(bottom)),
+            Align2::LEFT_CENTER,
+            label,
+            FontId::monospace(10.0),
+            Color32::WHITE,
+        );
+    }
+}
+
+/// Values of a multi-bit signal over time, such as a bus of a simulation dump.
+pub struct BusLane {
+    /// Indices of the samples where the value changes, starting with the first sample, with the
+    /// value written in hexadecimal from there. Unknown and floating digits are written `x` and
+    /// `z`.
+    changes: Vec<(usize, String)>,
+}
+
+impl BusLane {
+    /// Creates a lane from the value changes, in increasing order of sample index. The first
+    /// change shall be at index 0.
+    pub fn new(changes: Vec<(usize, String)>) -> Self {
+        Self { changes }
+    }
+
+    /// Paints the lane as a strip at the bottom of the viewport, `row` strips above the lowest
+    /// one, with an optional `label` on its left. Each value is drawn as a box holding the value
+    /// when it is wide enough, the boxes being joined by crossing lines at the changes.
+    ///
+    /// As for the logic levels, the changes in a same pixel column are merged in a vertical line.
+    pub fn paint(
+        &self,
+        camera: &Camera,
+        ppp: f32,
+        painter: &Painter,
+        viewport: &Rect,
+        row: usize,
+        label: Option<&str>,
+    ) {
+        paint_strip(painter, viewport, row, label, |top, bottom| {
+            let stroke = Stroke::new(1.0, Color32::LIGHT_GREEN);
+            let middle = top.midpoint(bottom);
+            let x_of = |i: usize| {
+                camera.world_to_screen_x(viewport, ppp, Fixed::from_num(self.changes[i].0))
+            };
+            let value_box = |x0: f32, x1: f32, value: &str| {
+                let slope = ((x1 - x0) / 2.0).min(3.0);
+                painter.line(
+                    vec![
+                        pos2(x0, middle),
+                        pos2(x0 + slope, top),
+                        pos2(x1 - slope, top),
+                        pos2(x1, middle),
+                        pos2(x1 - slope, bottom),
+                        pos2(x0 + slope, bottom),
+                        pos2(x0, middle),
+                    ],
+                    stroke,
+                );
+                // The value is centered in the visible part of the box.
+                let left = x0.max(viewport.min.x) + slope;
+                let right = x1.min(viewport.max.x) - slope;
+                if right - left > 8.0 {
+                    let galley = painter.layout_no_wrap(
+                        value.to_string(),
+                        FontId::monospace(10.0),
+                        Color32::WHITE,
+                    );
+                    if galley.size().x <= right - left {
+                        let pos = pos2(
+                            left.midpoint(right) - galley.size().x / 2.0,
+                            middle - galley.size().y / 2.0,
+                        );
+                        painter.galley(pos, galley, Color32::WHITE);
+                    }
+                }
+            };
+
+            let x0 = camera.screen_to_world_x(viewport, ppp, 0.0).to_num::<f64>();
+            let x1 = camera
+                .screen_to_world_x(viewport, ppp, viewport.width())
+                .to_num::<f64>();
+            // Index of the value at the left of the viewport.
+            let mut i = self
+                .changes
+                .partition_point(|&(e, _)| (e as f64) <= x0)
+                .saturating_sub(1);
+            let mut start = x_of(i).max(viewport.min.x - 4.0);
+            while i + 1 < self.changes.len() && (self.changes[i + 1].0 as f64) < x1 {
+                let x = x_of(i + 1);
+                let column_end =
+                    camera.screen_to_world_x(viewport, ppp, ((x * ppp).floor() + 1.0) / ppp);
+                let j = self
+                    .changes
+                    .partition_point(|&(e, _)| Fixed::from_num(e) < column_end)
+                    .max(i + 2);
+                value_box(start, x, &self.changes[i].1);
+                if j - i > 2 {
+                    painter.line_segment([pos2(x, top), pos2(x, bottom)], stroke);
+                }
+                start = x;
+                i = j - 1;
+            }
+            if let Some((_, value)) = self.changes.get(i) {
+                value_box(start, viewport.max.x + 4.0, value);
+            }
+        });
+    }
+
+    /// Returns the value of the `samples` first samples. Values with unknown digits give zero.
+    fn samples(&self, samples: usize) -> Vec<f32> {
+        let mut result = vec![0.0; samples];
+        for (k, (start, value)) in self.changes.iter().enumerate() {
+            let end = self
+                .changes
+                .get(k + 1)
+                .map_or(samples, |&(e, _)| e)
+                .min(samples);
+            let value = u128::from_str_radix(value, 16).map_or(0.0, |v| v as f32);
+            result[(*start).min(end)..end].fill(value);
+        }
+        result
+    }
+}
+
+/// Signal of a digital channel: a single logic level, or the value of a bus.
+pub enum LogicLane {
+    Bit(DigitalLane),
+    Bus(BusLane),
+}
+
+impl LogicLane {
+    /// Paints the lane as a strip at the bottom of the viewport, `row` strips above the lowest
+    /// one, with an optional `label` on its left.
+    pub fn paint(
+        &self,
+        camera: &Camera,
+        ppp: f32,
+        painter: &Painter,
+        viewport: &Rect,
+        row: usize,
+        label: Option<&str>,
+    ) {
+        match self {
+            Self::Bit(lane) => lane.paint(camera, ppp, painter, viewport, row, label),
+            Self::Bus(lane) => lane.paint(camera, ppp, painter, viewport, row, label),
+        }
+    }
+
+    /// Returns the value of the `samples` first samples: zero or one for logic levels, and the
+    /// value of buses.
+    pub fn samples(&self, samples: usize) -> Vec<f32> {
+        match self {
+            Self::Bit(lane) => lane.samples(samples),
+            Self::Bus(lane) => lane.samples(samples),
+        }
+    }
+}
+
+/// A named digital channel loaded from a logic analyzer capture or a simulation dump.
+pub struct LogicChannel {
+    pub name: String,
+    pub lane: LogicLane,
+}
+
+/// Digital channels loaded from a file, with the analog channels captured alongside them.
+pub struct LogicCapture {
+    /// Name of the file format, for the summaries.
+    pub format: &'static str,
+    /// Sampling rate in S/s, if recorded.
+    pub sampling_rate: Option<f64>,
+    /// Analog channels, with their names.
+    pub analog: Vec<(String, Vec<f32>)>,
+    /// Digital channels.
+    pub logic: Vec<LogicChannel>,
+    /// Number of samples of the digital channels.
+    pub logic_samples: usize,
+}
+
+impl LogicCapture {
+    /// Describes the sampling rate and the channels of the capture in one line.
+    pub fn summary(&self, path: &str) -> String {
+        format!(
+            "{}: {}, {:.3} MS/s, {} analog channel(s), {} logic channel(s) of {} pts",
+            path,
+            self.format,
+            self.sampling_rate.unwrap_or(f64::NAN) / 1e6,
+            self.analog.len(),
+            self.logic.len(),
+            self.logic_samples
+        )
+    }
+
+    /// Returns the analog channels as traces. Captures without analog channels return their
+    /// digital channels instead, as traces of zeros and ones or of the bus values.
+    pub fn into_traces(self) -> Vec<Vec<f32>> {
+        if !self.analog.is_empty() {
+            return self
+                .analog
+                .into_iter()
+                .map(|(_, samples)| samples)
+                .collect();
+        }
+        self.logic
+            .iter()
+            .map(|channel| channel.lane.samples(self.logic_samples))
+            .collect()
+    }
+}
+
+/// Digital conversion of the trace of a viewer, calculated again when the trace changes.
+pub struct DigitalChannel {
+    pub thresholds: Thresholds,
+    /// Converted lane, with the generation of the trace it has been calculated from.
+    lane: Option<(DigitalLane, u32)>,
+    /// Running conversion, with the generation of its input trace.
+    job: Option<(ProcessingJob<DigitalLane>, u32)>,
+}
+
+impl DigitalChannel {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            lane: None,
+            job: None,
+        }
+    }
+
+    /// Starts the conversion of `trace` in background if the lane has not been calculated for
+    /// this `generation` of the trace yet, and collects the result of the running conversion.
+    pub fn update(&mut self, ctx: &egui::Context, trace: &Arc<Trace>, generation: u32) {
+        if let Some((job, job_generation)) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            match result {
+                Ok(lane) => self.lane = Some((lane, *job_generation)),
+                Err(e) => tracing::warn!("Digital conversion failed: {}", e),
+            }
+            self.job = None;
+        }
+        let up_to_date = |g: Option<u32>| g == Some(generation);
+        if !up_to_date(self.lane.as_ref().map(|(_, g)| *g))
+            && !up_to_date(self.job.as_ref().map(|(_, g)| *g))
+        {
+            let trace = trace.clone();
+            let thresholds = self.thresholds;
+            let job =
+                ProcessingJob::spawn(ctx, move |_| Some(DigitalLane::new(&trace, thresholds)));
+            self.job = Some((job, generation));
+        }
+    }
+
+    /// Returns the last calculated lane, which may be outdated while the conversion of a new trace
+    /// is running.
+    pub fn lane(&self) -> Option<&DigitalLane> {
+        self.lane.as_ref().map(|(lane, _)| lane)
+    }
+}
@@ -0,0 +1,436 @@
+//! Elastic alignment by dynamic time warping.
+//!
+//! Targets with clock jitter or random delays stretch and compress the time axis of the captures,
+//! so a single lag cannot align two traces. Dynamic time warping (DTW) matches each sample of a
+//! reference window with one or more samples of the other trace, minimizing the total distance
+//! between the matched samples. The warp path shows where delays are inserted, and the trace
+//! warped onto the reference can be opened in a new view.
+
+use crate::{
+    alignment_dialog::AlignmentSource,
+    processing::{ProcessingJob, Progress},
+    transforms::{Normalization, normalize},
+};
+use egui::{
+    Button, Color32, ComboBox, DragValue, Id, Modal, ProgressBar, Rect, Sense, Stroke, Ui, pos2,
+    vec2,
+};
+use std::{ops::Range, time::Duration};
+
+/// Maximum number of cells of the banded cost matrix, bounding the memory used by the warp
+/// path search to one byte per cell.
+const MAX_CELLS: usize = 100_000_000;
+
+/// Number of rows calculated between two progress reports.
+const PROGRESS_PERIOD: usize = 256;
+
+/// Maximum number of points drawn per curve in the dialog plots.
+const PLOT_POINTS: usize = 1000;
+
+/// Predecessor of a cell of the warp path.
+const DIAGONAL: u8 = 0;
+const UP: u8 = 1;
+const LEFT: u8 = 2;
+
+/// Result of a dynamic time warping.
+pub struct Warp {
+    /// Matched samples from the first to the last samples of the window, as pairs of reference
+    /// and trace indices relative to the window start.
+    pub path: Vec<(u32, u32)>,
+    /// Trace warped onto the reference: for each reference sample, the mean of the matched trace
+    /// samples.
+    pub warped: Vec<f32>,
+    /// Mean squared distance between the matched samples, after z-score normalization.
+    pub distance: f32,
+}
+
+/// Warps the `window` samples of `trace` onto the same samples of `reference`.
+///
+/// Both segments are normalized to zero mean and unit variance, so gain and offset differences do
+/// not affect the path. Matched samples are at most `band` samples apart (Sakoe-Chiba band), which
+/// bounds the computation to `window.len() × (2 × band + 1)` cells. Returns an error if the
+/// window is too short or too long for the band, and `None` if the processing is cancelled.
+pub fn dtw(
+    reference: &[f32],
+    trace: &[f32],
+    window: Range<usize>,
+    band: usize,
+    progress: &Progress,
+) -> Option<Result<Warp, String>> {
+    let len = reference.len().min(trace.len());
+    let window = window.start.min(len)..window.end.min(len);
+    let n = window.len();
+    if n < 2 {
+        return Some(Err("The window must contain at least 2 samples".to_string()));
+    }
+    let band = band.min(n - 1);
+    let width = 2 * band + 1;
+    if n.saturating_mul(width) > MAX_CELLS {
+        return Some(Err(format!(
+            "The window is too long for this band: at most {} samples with a band of {}",
+            MAX_CELLS / width,
+            band
+        )));
+    }
+    let a = normalize(&reference[window.clone()], Normalization::ZScore, None);
+    let b = normalize(&trace[window.clone()], Normalization::ZScore, None);
+
+    // Cumulated costs of the previous and current rows, and the predecessor of each cell. Cell
+    // (i, j) is stored at column j + band - i of row i.
+    let mut previous = vec![f64::INFINITY; width];
+    let mut current = vec![f64::INFINITY; width];
+    let mut directions = vec![0u8; n * width];
+    for i in 0..n {
+        if i % PROGRESS_PERIOD == 0 {
+            if progress.is_cancelled() {
+                return None;
+            }
+            progress.set(i as f32 / n as f32);
+        }
+        current.fill(f64::INFINITY);
+        let j_min = i.saturating_sub(band);
+        let j_max = (i + band).min(n - 1);
+        for (j, &bj) in b.iter().enumerate().take(j_max + 1).skip(j_min) {
+            let k = j + band - i;
+            let d = (a[i] - bj) as f64;
+            let cost = d * d;
+            if i == 0 && j == 0 {
+                current[k] = cost;
+                continue;
+            }
+            let diagonal = if i > 0 && j > 0 {
+                previous[k]
+            } else {
+                f64::INFINITY
+            };
+            let up = if i > 0 && k + 1 < width {
+                previous[k + 1]
+            } else {
+                f64::INFINITY
+            };
+            let left = if j > 0 && k > 0 {
+                current[k - 1]
+            } else {
+                f64::INFINITY
+            };
+            let (best, direction) = if diagonal <= up && diagonal <= left {
+                (diagonal, DIAGONAL)
+            } else if up <= left {
+                (up, UP)
+            } else {
+                (left, LEFT)
+            };
+            current[k] = cost + best;
+            directions[i * width + k] = direction;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    let total = previous[band];
+
+    let (mut i, mut j) = (n - 1, n - 1);
+    let mut path = vec![(i as u32, j as u32)];
+    while i > 0 || j > 0 {
+        match directions[i * width + j + band - i] {
+            DIAGONAL => {
+                i -= 1;
+                j -= 1;
+            }
+            UP => i -= 1,
+            _ => j -= 1,
+        }
+        path.push((i as u32, j as u32));
+    }
+    path.reverse();
+
+    let segment = &trace[window];
+    let mut sums = vec![(0.0f64, 0u32); n];
+    for &(i, j) in &path {
+        let sum = &mut sums[i as usize];
+        sum.0 += segment[j as usize] as f64;
+        sum.1 += 1;
+    }
+    progress.set(1.0);
+    Some(Ok(Warp {
+        distance: (total / path.len() as f64) as f32,
+        warped: sums
+            .iter()
+            .map(|&(sum, count)| (sum / count as f64) as f32)
+            .collect(),
+        path,
+    }))
+}
+
+/// A warp with the curves displayed by the dialog.
+struct DisplayedWarp {
+    warp: Warp,
+    /// Index of the reference sample of the first warped sample.
+    start: usize,
+    /// Normalized reference, original trace and warped trace.
+    curves: [Vec<f32>; 3],
+}
+
+/// Result of the DTW dialog update.
+pub enum DtwDialogResult {
+    Pending,
+    /// The user asked for displaying the `warped` trace in a new view. Its first sample matches
+    /// the sample `start` of the reference.
+    Create {
+        target: usize,
+        reference: usize,
+        start: usize,
+        warped: Vec<f32>,
+    },
+    /// Dialog was closed.
+    Cancelled,
+}
+
+/// Dialog warping the trace of a viewer onto a reference trace, displaying the warp path and the
+/// warped trace over the reference.
+pub struct DtwDialog {
+    id: Id,
+    /// Index of the warped trace.
+    target: usize,
+    /// Index of the reference trace.
+    reference: usize,
+    /// Maximum distance between matched samples.
+    band: usize,
+    job: Option<ProcessingJob<Result<DisplayedWarp, String>>>,
+    warp: Option<DisplayedWarp>,
+    error: Option<String>,
+}
+
+impl DtwDialog {
+    /// Default band, in samples.
+    const DEFAULT_BAND: usize = 100;
+
+    /// Creates the dialog for warping the trace at index `target`.
+    pub fn new(id: Id, target: usize) -> Self {
+        Self {
+            id,
+            target,
+            reference: if target == 0 { 1 } else { 0 },
+            band: Self::DEFAULT_BAND,
+            job: None,
+            warp: None,
+            error: None,
+        }
+    }
+
+    /// Shows the dialog and returns the user decision.
+    pub fn show(&mut self, ctx: &egui::Context, sources: &[AlignmentSource]) -> DtwDialogResult {
+        if let Some(result) = self.job.as_ref().and_then(|job| job.try_take()) {
+            let result = result.and_then(|result| result);
+            self.job = None;
+            match result {
+                Ok(warp) => self.warp = Some(warp),
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        let mut result = DtwDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("Elastic alignment (DTW)");
+            ui.add_space(5.0);
+            egui::Grid::new("dtw_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Trace:");
+                ui.label(&sources[self.target].label);
+                ui.end_row();
+                ui.label("Reference:");
+                let previous = (self.reference, self.band);
+                ComboBox::from_id_salt("dtw_reference")
+                    .selected_text(&sources[self.reference].label)
+                    .show_ui(ui, |ui| {
+                        for (i, source) in sources.iter().enumerate() {
+                            if i != self.target {
+                                ui.selectable_value(&mut self.reference, i, &source.label);
+                            }
+                        }
+                    });
+                ui.end_row();
+                ui.label("Band:");
+                ui.add(
+                    DragValue::new(&mut self.band)
+                        .range(1..=usize::MAX)
+                        .suffix(" samples"),
+                )
+                .on_hover_text("Maximum distance between matched samples");
+                ui.end_row();
+                if (self.reference, self.band) != previous {
+                    self.warp = None;
+                    self.error = None;
+                }
+            });
+            let window = sources[self.reference]
+                .selection
+                .clone()
+                .or_else(|| sources[self.target].selection.clone());
+            match &window {
+                Some(window) => ui.label(format!(
+                    "Window: samples {} to {}",
+                    window.start, window.end
+                )),
+                None => ui.label("Select the window to warp with the Range tool."),
+            };
+
+            if let Some(warp) = &self.warp {
+                ui.label(format!(
+                    "Mean distance {:.4} (normalized samples)",
+                    warp.warp.distance
+                ));
+                ui.label("Warp path: lag of the matched trace samples");
+                paint_path(ui, &warp.warp.path, self.band);
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::LIGHT_GRAY, "Reference");
+                    ui.colored_label(Color32::from_rgb(255, 140, 0), "Warped");
+                    ui.colored_label(Color32::from_rgb(80, 120, 200), "Original");
+                });
+                paint_curves(ui, &warp.curves);
+            }
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if let Some(job) = &self.job {
+                    ui.add(
+                        ProgressBar::new(job.progress())
+                            .desired_width(200.0)
+                            .show_percentage()
+                            .animate(true),
+                    );
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                    if ui.button("Cancel").clicked() {
+                        // Dropping the job cancels it.
+                        self.job = None;
+                    }
+                    return;
+                }
+                if ui
+                    .add_enabled(window.is_some(), Button::new("Warp"))
+                    .clicked()
+                    && let Some(window) = window
+                {
+                    let reference = sources[self.reference].trace.clone();
+                    let trace = sources[self.target].trace.clone();
+                    let band = self.band;
+                    self.warp = None;
+                    self.error = None;
+                    self.job = Some(ProcessingJob::spawn(ctx, move |progress| {
+                        let warp = match dtw(&reference, &trace, window.clone(), band, progress)? {
+                            Ok(warp) => warp,
+                            Err(e) => return Some(Err(e)),
+                        };
+                        let start = window.start.min(reference.len());
+                        let end = start + warp.warped.len();
+                        let z = |x: &[f32]| normalize(x, Normalization::ZScore, None);
+                        let curves = [
+                            z(&reference[start..end]),
+                            z(&trace[start..end]),
+                            z(&warp.warped),
+                        ];
+                        Some(Ok(DisplayedWarp {
+                            warp,
+                            start,
+                            curves,
+                        }))
+                    }));
+                }
+                if let Some(warp) = &self.warp
+                    && ui
+                        .button("Open warped trace")
+                        .on_hover_text("Display the warped trace in a new view")
+                        .clicked()
+                {
+                    result = DtwDialogResult::Create {
+                        target: self.target,
+                        reference: self.reference,
+                        start: warp.start,
+                        warped: warp.warp.warped.clone(),
+                    };
+                }
+                if ui.button("Close").clicked() {
+                    result = DtwDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() && self.job.is_none() {
+            DtwDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+}
+
+/// Allocates a plot area of the dialog.
+fn plot_rect(ui: &mut Ui) -> Rect {
+    let (rect, _) = ui.allocate_exact_size(vec2(480.0, 140.0), Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 2.0, Color32::from_black_alpha(120));
+    rect
+}
+
+/// Plots the lag `j - i` of the warp path along the reference, between `-band` and `band`.
+fn paint_path(ui: &mut Ui, path: &[(u32, u32)], band: usize) {
+    let rect = plot_rect(ui);
+    let painter = ui.painter_at(rect);
+    let n = path.last().map_or(1, |&(i, _)| i.max(1)) as f32;
+    let band = band.max(1) as f32;
+    let to_screen = |i: f32, lag: f32| {
+        pos2(
+            rect.left() + i / n * rect.width(),
+            rect.center().y - lag / band * rect.height() / 2.0,
+        )
+    };
+    painter.hline(
+        rect.x_range(),
+        rect.center().y,
+        Stroke::new(1.0, Color32::DARK_GRAY),
+    );
+    let stride = path.len().div_ceil(PLOT_POINTS * 2).max(1);
+    let points = path
+        .iter()
+        .step_by(stride)
+        .map(|&(i, j)| to_screen(i as f32, j as f32 - i as f32))
+        .collect();
+    painter.line(points, Stroke::new(1.0, Color32::LIGHT_GREEN));
+}
+
+/// Plots the normalized reference, original trace and warped trace over each other.
+fn paint_curves(ui: &mut Ui, curves: &[Vec<f32>; 3]) {
+    let rect = plot_rect(ui);
+    let painter = ui.painter_at(rect);
+    let (min, max) = curves
+        .iter()
+        .flatten()
+        .filter(|x| x.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &x| {
+            (min.min(x), max.max(x))
+        });
+    if min >= max {
+        return;
+    }
+    let colors = [
+        Color32::LIGHT_GRAY,
+        Color32::from_rgb(80, 120, 200),
+        Color32::from_rgb(255, 140, 0),
+    ];
+    // The original trace is painted first, under the reference and the warped trace.
+    for k in [1, 0, 2] {
+        let curve = &curves[k];
+        let n = curve.len().max(2) as f32 - 1.0;
+        let stride = curve.len().div_ceil(PLOT_POINTS).max(1);
+        let points = curve
+            .iter()
+            .enumerate()
+            .step_by(stride)
+            .map(|(i, &x)| {
+                pos2(
+                    rect.left() + i as f32 / n * rect.width(),
+                    rect.bottom() - (x - min) / (max - min) * rect.height(),
+                )
+            })
+            .collect();
+        painter.line(points, Stroke::new(1.0, colors[k]));
+    }
+}
@@ -0,0 +1,86 @@
+//! Modal dialog reporting the failures happening once the window is opened, such as files which
+//! cannot be loaded or traces which cannot be rendered, instead of aborting the application.
+
+use egui::{Id, Modal};
+use std::collections::VecDeque;
+
+/// Result of the error dialog update.
+pub enum ErrorDialogResult {
+    /// No failure to acknowledge, or the user has not answered yet.
+    Pending,
+    /// The trace with the given identifier must be removed.
+    Remove(u32),
+}
+
+/// Dialog showing the failures one at a time, until the user acknowledges them.
+pub struct ErrorDialog {
+    id: Id,
+    /// Failures not acknowledged yet, oldest first, with the identifier of the trace they are
+    /// related to.
+    failures: VecDeque<(String, Option<u32>)>,
+}
+
+impl ErrorDialog {
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            failures: VecDeque::new(),
+        }
+    }
+
+    /// Queues a failure, related to the trace `trace` if given.
+    pub fn push(&mut self, message: String, trace: Option<u32>) {
+        self.failures.push_back((message, trace));
+    }
+
+    /// Shows the oldest failure, if any, and returns the user decision. Removing the trace of the
+    /// failure is proposed if `removable` returns true for it.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        removable: impl Fn(u32) -> bool,
+    ) -> ErrorDialogResult {
+        let Some((message, trace)) = self.failures.front() else {
+            return ErrorDialogResult::Pending;
+        };
+        let trace = trace.filter(|&id| removable(id));
+        let mut acknowledged = false;
+        let mut remove = false;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.set_max_width(500.0);
+            ui.heading("Error");
+            ui.add_space(5.0);
+            ui.label(message);
+            if self.failures.len() > 1 {
+                ui.weak(format!("{} more error(s)", self.failures.len() - 1));
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if ui.button("OK").clicked() {
+                    acknowledged = true;
+                }
+                if trace.is_some()
+                    && ui
+                        .button("Remove trace")
+                        .on_hover_text("Close the view of the trace and free its samples.")
+                        .clicked()
+                {
+                    remove = true;
+                }
+            });
+        });
+        match trace {
+            Some(id) if remove => {
+                // The other failures of the trace are not relevant anymore.
+                self.failures.retain(|(_, t)| *t != Some(id));
+                ErrorDialogResult::Remove(id)
+            }
+            _ => {
+                if acknowledged || response.should_close() {
+                    self.failures.pop_front();
+                }
+                ErrorDialogResult::Pending
+            }
+        }
+    }
+}
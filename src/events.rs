@@ -0,0 +1,174 @@
+//! Event markers, painted as labeled vertical lines across the viewers.
+//!
+//! Operation boundaries are often given by a trigger channel captured with the measurement, or
+//! logged as a list of timestamps by the acquisition script. Both can be loaded as events, so the
+//! boundaries remain visible while inspecting the measurement channel.
+
+use crate::{
+    camera::Camera,
+    digital::{DigitalLane, Thresholds},
+    overlay::load_overlay,
+    util::Fixed,
+};
+use egui::{Align2, Color32, FontId, Painter, Rect, Stroke, pos2};
+use std::fs;
+
+/// Minimum distance between two labeled markers, in points. Labels of closer markers are hidden.
+const LABEL_SPACING: f32 = 40.0;
+
+/// An event of the common time base of the traces.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Index of the sample where the event occurs, in the loaded traces.
+    pub sample: f64,
+    pub label: String,
+}
+
+/// Loads a list of events from a text file, with one event per line: the sample index, followed
+/// by an optional label separated by a comma, a semicolon or spaces. Empty lines and lines
+/// starting with `#` are ignored. The events are returned in increasing order.
+pub fn load_event_list(path: &str) -> Result<Vec<Event>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut events = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (sample, label) = line
+            .split_once([',', ';', ' ', '\t'])
+            .map_or((line, ""), |(sample, label)| (sample, label.trim()));
+        let sample: f64 = sample
+            .trim()
+            .parse()
+            .map_err(|_| format!("{}:{}: invalid sample index '{}'", path, i + 1, sample))?;
+        events.push(Event {
+            sample,
+            label: label.to_string(),
+        });
+    }
+    events.sort_by(|a, b| a.sample.total_cmp(&b.sample));
+    Ok(events)
+}
+
+/// Loads a trigger channel in one of the trace formats and returns its edges as events, in
+/// increasing order.
+///
+/// The channel is converted to a logic level with thresholds at 40 % and 60 % of its amplitude.
+/// Rising and falling edges are labeled with the number of the pulse they belong to.
+pub fn load_trigger(path: &str) -> Result<Vec<Event>, String> {
+    let trace = load_overlay(path)?;
+    let (min, max) = trace
+        .iter()
+        .filter(|x| x.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &x| {
+            (min.min(x), max.max(x))
+        });
+    if min >= max {
+        return Err(format!("{}: the trigger channel is flat", path));
+    }
+    let thresholds = Thresholds {
+        low: min + 0.4 * (max - min),
+        high: min + 0.6 * (max - min),
+    };
+    let lane = DigitalLane::new(&trace, thresholds);
+    let mut level = lane.initial();
+    let mut pulse = 0;
+    let events = lane
+        .edges()
+        .iter()
+        .map(|&edge| {
+            level = !level;
+            if level {
+                pulse += 1;
+            }
+            Event {
+                sample: edge as f64,
+                label: format!("#{} {}", pulse, if level { "↑" } else { "↓" }),
+            }
+        })
+        .collect();
+    Ok(events)
+}
+
+/// Paints `events` as vertical lines over the viewport, with their labels at the bottom when
+/// there is room for them.
+///
+/// Event samples are given in the original trace samples: they are multiplied by `rate_ratio`,
+/// the sampling rate of the displayed trace divided by the sampling rate of the original trace,
+/// and shifted by the `x_offset` of the viewer, so the markers stay at the same time in every
+/// viewer.
+pub fn paint_events(
+    events: &[Event],
+    rate_ratio: f64,
+    x_offset: Fixed,
+    camera: &Camera,
+    ppp: f32,
+    painter: &Painter,
+    viewport: &Rect,
+) {
+    let color = Color32::from_rgb(255, 220, 80);
+    let painter = painter.with_clip_rect(*viewport);
+    let offset = x_offset.to_num::<f64>();
+    // Events are sorted, so only the visible ones are iterated.
+    let to_sample =
+        |x: f32| (camera.screen_to_world_x(viewport, ppp, x).to_num::<f64>() - offset) / rate_ratio;
+    let (first, last) = (to_sample(0.0), to_sample(viewport.width()));
+    let start = events.partition_point(|e| e.sample < first);
+    let end = events.partition_point(|e| e.sample <= last);
+    let mut last_label = f32::NEG_INFINITY;
+    for event in &events[start..end.max(start)] {
+        let x = camera.world_to_screen_x(
+            viewport,
+            ppp,
+            Fixed::from_num(event.sample * rate_ratio + offset),
+        );
+        painter.vline(
+            x,
+            viewport.y_range(),
+            Stroke::new(1.0, color.gamma_multiply(0.6)),
+        );
+        if !event.label.is_empty() && x - last_label >= LABEL_SPACING {
+            painter.text(
+                // Above the digital lane.
+                pos2(x + 3.0, viewport.bottom() - 24.0),
+                Align2::LEFT_BOTTOM,
+                &event.label,
+                FontId::proportional(11.0),
+                color,
+            );
+            last_label = x;
+        }
+    }
+}
+
+/// Paints the trigger position recorded by the oscilloscope as a vertical line labeled `T` at the
+/// top of the viewport. `sample` is given in the original trace samples, as for [`paint_events`].
+pub fn paint_trigger(
+    sample: f64,
+    rate_ratio: f64,
+    x_offset: Fixed,
+    camera: &Camera,
+    ppp: f32,
+    painter: &Painter,
+    viewport: &Rect,
+) {
+    let color = Color32::from_rgb(255, 140, 0);
+    let painter = painter.with_clip_rect(*viewport);
+    let x = camera.world_to_screen_x(
+        viewport,
+        ppp,
+        Fixed::from_num(sample * rate_ratio + x_offset.to_num::<f64>()),
+    );
+    if !viewport.x_range().contains(x) {
+        return;
+    }
+    painter.vline(x, viewport.y_range(), Stroke::new(1.0, color));
+    painter.text(
+        pos2(x + 3.0, viewport.top() + 2.0),
+        Align2::LEFT_TOP,
+        "T",
+        FontId::proportional(12.0),
+        color,
+    );
+}
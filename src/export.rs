@@ -0,0 +1,305 @@
+//! Extraction of trace selections, as text, rendered images or files.
+
+use crate::{
+    renderer::{CpuRenderer, RenderJob, Renderer},
+    tiling::{ColorScale, TileSize, density_image},
+};
+use egui::{Button, Color32, ColorImage, DragValue, Id, Modal};
+use egui_file_dialog::{DialogState, FileDialog};
+use image::RgbaImage;
+use npyz::WriterBuilder;
+use std::{
+    fmt::Write,
+    fs::{self, File},
+    io::{self, BufWriter},
+    ops::Range,
+    path::Path,
+};
+
+/// Maximum width in pixels of an image rendered from a selection.
+/// Prevents huge allocations when a long selection is exported at a high zoom level.
+const MAX_IMAGE_WIDTH: u32 = 8192;
+
+/// Formats the samples in `range` as CSV text, with one `index,value` line per sample.
+pub fn samples_to_csv(trace: &[f32], range: Range<usize>) -> String {
+    let mut text = String::from("index,value\n");
+    for (i, value) in trace[range.clone()].iter().enumerate() {
+        writeln!(text, "{},{}", range.start + i, value).unwrap();
+    }
+    text
+}
+
+/// Rendering settings used when a selection is converted to an image.
+#[derive(Clone, Copy)]
+pub struct ImageSettings {
+    /// Horizontal resolution, usually the current camera X scale so the image looks like what is
+    /// displayed on screen.
+    pub samples_per_column: f32,
+    /// Image height in pixels.
+    pub height: u32,
+    /// Vertical camera offset.
+    pub offset: f32,
+    /// Vertical camera scale.
+    pub scale_y: f32,
+    pub color_scale: ColorScale,
+}
+
+/// Renders the samples in `range` with the density algorithm of the CPU renderer, and colorizes
+/// the result.
+///
+/// Returns `None` if the range has less than two samples.
+pub fn render_image(
+    trace: &[f32],
+    range: Range<usize>,
+    settings: ImageSettings,
+) -> Option<ColorImage> {
+    let end = range.end.min(trace.len());
+    if end <= range.start + 1 || settings.height == 0 {
+        return None;
+    }
+    let samples = (end - range.start) as u32;
+    let width =
+        ((samples as f32 / settings.samples_per_column).ceil() as u32).clamp(1, MAX_IMAGE_WIDTH);
+    // Include the first sample after the range so the last segment is drawn.
+    let chunk = &trace[range.start..(end + 1).min(trace.len())];
+    let data = CpuRenderer::new().render(RenderJob {
+        chunk_samples: samples,
+        trace: chunk.into(),
+        origin: None,
+        w: width,
+        h: settings.height,
+        offset: settings.offset,
+        scale_y: settings.scale_y,
+    });
+    Some(density_image(
+        &data,
+        TileSize::new(width, settings.height),
+        samples as f32 / width as f32,
+        settings.color_scale,
+    ))
+}
+
+/// Writes the samples in `range` to a float32 Numpy file.
+pub fn write_npy(path: &Path, trace: &[f32], range: Range<usize>) -> io::Result<()> {
+    npyz::to_file_1d(path, trace[range].iter().copied())
+}
+
+/// Writes the samples in `range` to a CSV file, using the same layout as [`samples_to_csv`].
+pub fn write_csv(path: &Path, trace: &[f32], range: Range<usize>) -> io::Result<()> {
+    fs::write(path, samples_to_csv(trace, range))
+}
+
+/// Writes the samples in `range` of traces of the same length to a float32 Numpy file, as a 2D
+/// array with one trace per row.
+pub fn write_npy_rows(path: &Path, traces: &[Vec<f32>], range: Range<usize>) -> io::Result<()> {
+    let len = traces.first().map_or(0, Vec::len);
+    if traces.iter().any(|trace| trace.len() != len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the traces must have the same length",
+        ));
+    }
+    let range = range.start.min(len)..range.end.min(len);
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&[traces.len() as u64, range.len() as u64])
+        .writer(file)
+        .begin_nd()?;
+    writer.extend(
+        traces
+            .iter()
+            .flat_map(|trace| &trace[range.clone()])
+            .copied(),
+    )?;
+    writer.finish()
+}
+
+/// Writes the samples in `range` of traces to a CSV file, with an index column followed by one
+/// column per trace. Shorter traces leave their column empty at the end.
+pub fn write_csv_columns(path: &Path, traces: &[Vec<f32>], range: Range<usize>) -> io::Result<()> {
+    let len = traces.iter().map(Vec::len).max().unwrap_or(0);
+    let mut text = String::from("index");
+    for i in 0..traces.len() {
+        write!(text, ",trace{}", i).unwrap();
+    }
+    text.push('\n');
+    for i in range.start..range.end.min(len) {
+        write!(text, "{}", i).unwrap();
+        for trace in traces {
+            text.push(',');
+            if let Some(value) = trace.get(i) {
+                write!(text, "{}", value).unwrap();
+            }
+        }
+        text.push('\n');
+    }
+    fs::write(path, text)
+}
+
+/// Writes an image to a PNG file.
+pub fn write_png(path: &Path, image: &ColorImage) -> io::Result<()> {
+    let [w, h] = image.size;
+    let buffer = RgbaImage::from_raw(w as u32, h as u32, image.as_raw().to_vec())
+        .expect("Image buffer size mismatch");
+    buffer.save(path).map_err(io::Error::other)
+}
+
+/// Encodes an image as PNG.
+pub fn encode_png(image: &ColorImage) -> io::Result<Vec<u8>> {
+    let [w, h] = image.size;
+    let buffer = RgbaImage::from_raw(w as u32, h as u32, image.as_raw().to_vec())
+        .expect("Image buffer size mismatch");
+    let mut bytes = Vec::new();
+    buffer
+        .write_to(&mut io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(bytes)
+}
+
+/// File formats available for exporting a selection.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Numpy,
+    Csv,
+    Png,
+}
+
+impl ExportFormat {
+    pub fn name(&self) -> &str {
+        match self {
+            ExportFormat::Numpy => "NPY",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Png => "PNG",
+        }
+    }
+
+    pub fn extension(&self) -> &str {
+        match self {
+            ExportFormat::Numpy => "npy",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Png => "png",
+        }
+    }
+}
+
+/// Modal dialog for exporting a range of samples to a file.
+pub struct ExportDialog {
+    id: Id,
+    format: ExportFormat,
+    /// First exported sample.
+    start: usize,
+    /// Exported samples end (excluded).
+    end: usize,
+    /// Name of the trace, used to build the default file name.
+    stem: String,
+    /// Captured from the viewer when the dialog is opened.
+    image_settings: ImageSettings,
+    file_dialog: FileDialog,
+    /// Error message of the last export attempt.
+    error: Option<String>,
+}
+
+impl ExportDialog {
+    /// Creates the dialog with bounds pre-filled from `range`.
+    pub fn new(id: Id, label: &str, range: Range<usize>, image_settings: ImageSettings) -> Self {
+        let stem = Path::new(label)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "trace".to_string());
+        Self {
+            id,
+            format: ExportFormat::Numpy,
+            start: range.start,
+            end: range.end,
+            stem,
+            image_settings,
+            file_dialog: FileDialog::new(),
+            error: None,
+        }
+    }
+
+    /// Shows the dialog. Returns `false` once the dialog is closed, either because the selection
+    /// has been exported or because it has been cancelled.
+    pub fn show(&mut self, ctx: &egui::Context, trace: &[f32]) -> bool {
+        self.file_dialog.update(ctx);
+        if let Some(path) = self.file_dialog.take_picked() {
+            match self.export(&path, trace) {
+                Ok(()) => {
+                    tracing::info!(
+                        "Exported samples {}..{} to {}",
+                        self.start,
+                        self.end,
+                        path.display()
+                    );
+                    return false;
+                }
+                Err(e) => self.error = Some(format!("Export failed: {}", e)),
+            }
+        }
+        if *self.file_dialog.state() == DialogState::Open {
+            // The file dialog would be hidden behind the modal.
+            return true;
+        }
+
+        let mut open = true;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("Export selection");
+            ui.add_space(5.0);
+            egui::Grid::new("export_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Format:");
+                    egui::ComboBox::from_id_salt("export_format")
+                        .selected_text(self.format.name())
+                        .show_ui(ui, |ui| {
+                            for x in [ExportFormat::Numpy, ExportFormat::Csv, ExportFormat::Png] {
+                                ui.selectable_value(&mut self.format, x, x.name());
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("First sample:");
+                    ui.add(DragValue::new(&mut self.start).range(0..=trace.len()));
+                    ui.end_row();
+                    ui.label("End sample (excluded):");
+                    ui.add(DragValue::new(&mut self.end).range(0..=trace.len()));
+                    ui.end_row();
+                });
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                let valid = self.end.min(trace.len()) > self.start + 1;
+                if ui.add_enabled(valid, Button::new("Save…")).clicked() {
+                    let config = self.file_dialog.config_mut();
+                    config.default_file_name = format!(
+                        "{}_{}-{}.{}",
+                        self.stem,
+                        self.start,
+                        self.end,
+                        self.format.extension()
+                    );
+                    self.file_dialog.save_file();
+                }
+                if ui.button("Cancel").clicked() {
+                    open = false;
+                }
+            });
+        });
+        open && !response.should_close()
+    }
+
+    fn export(&self, path: &Path, trace: &[f32]) -> io::Result<()> {
+        let range = self.start..self.end.min(trace.len());
+        match self.format {
+            ExportFormat::Numpy => write_npy(path, trace, range),
+            ExportFormat::Csv => write_csv(path, trace, range),
+            ExportFormat::Png => {
+                let image = render_image(trace, range, self.image_settings)
+                    .ok_or_else(|| io::Error::other("selection is too small"))?;
+                write_png(path, &image)
+            }
+        }
+    }
+}
@@ -0,0 +1,770 @@
+//! Expression engine for deriving new traces from the loaded ones.
+//!
+//! Traces are referenced by their index, `t0` being the first trace, and can be combined with
+//! arithmetic operators and functions, for instance `abs(t0) - 0.5 * t1` or
+//! `lowpass(t0, 2MHz)`. Operations between a trace and a number apply to all the samples, and
+//! operations between traces of different lengths are truncated to the shortest one.
+
+use crate::{
+    filtering::{Filter, FilterSpec},
+    processing::Progress,
+    trace::Trace,
+    transforms::{envelope_hilbert, moving_average},
+};
+use std::{fmt, sync::Arc};
+
+/// Functions available in expressions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Log,
+    Min,
+    Max,
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    Smooth,
+    Envelope,
+}
+
+impl Function {
+    const ALL: [Function; 12] = [
+        Function::Abs,
+        Function::Sqrt,
+        Function::Exp,
+        Function::Log,
+        Function::Min,
+        Function::Max,
+        Function::LowPass,
+        Function::HighPass,
+        Function::BandPass,
+        Function::Notch,
+        Function::Smooth,
+        Function::Envelope,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Function::Abs => "abs",
+            Function::Sqrt => "sqrt",
+            Function::Exp => "exp",
+            Function::Log => "log",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::LowPass => "lowpass",
+            Function::HighPass => "highpass",
+            Function::BandPass => "bandpass",
+            Function::Notch => "notch",
+            Function::Smooth => "smooth",
+            Function::Envelope => "envelope",
+        }
+    }
+
+    /// Usage of the function, displayed in the user interface.
+    pub fn signature(&self) -> &str {
+        match self {
+            Function::Abs => "abs(x)",
+            Function::Sqrt => "sqrt(x)",
+            Function::Exp => "exp(x)",
+            Function::Log => "log(x): natural logarithm",
+            Function::Min => "min(x, y)",
+            Function::Max => "max(x, y)",
+            Function::LowPass => "lowpass(trace, frequency)",
+            Function::HighPass => "highpass(trace, frequency)",
+            Function::BandPass => "bandpass(trace, frequency)",
+            Function::Notch => "notch(trace, frequency)",
+            Function::Smooth => "smooth(trace, samples): moving average",
+            Function::Envelope => "envelope(trace): Hilbert envelope",
+        }
+    }
+
+    /// Returns all the functions.
+    pub fn all() -> &'static [Function] {
+        &Self::ALL
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Function::Abs | Function::Sqrt | Function::Exp | Function::Log | Function::Envelope => {
+                1
+            }
+            _ => 2,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+/// Binary operators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+impl Operator {
+    fn apply(&self, a: f32, b: f32) -> f32 {
+        match self {
+            Operator::Add => a + b,
+            Operator::Sub => a - b,
+            Operator::Mul => a * b,
+            Operator::Div => a / b,
+            Operator::Pow => a.powf(b),
+        }
+    }
+}
+
+/// Parsed expression tree.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// Reference to a loaded trace, by index.
+    Trace(usize),
+    Neg(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+/// Error raised when parsing or evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionError {
+    pub message: String,
+    /// Position of the error in the expression text, in bytes, when relevant.
+    pub position: Option<usize>,
+}
+
+impl ExpressionError {
+    fn at(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position: Some(position),
+        }
+    }
+
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            position: None,
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} (at position {})", self.message, position + 1),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+/// Splits the expression text into tokens with their positions.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = number_end(text, i);
+            let value = text[i..end]
+                .parse()
+                .map_err(|_| ExpressionError::at(i, "Invalid number"))?;
+            tokens.push((i, Token::Number(value)));
+            while chars.next_if(|&(j, _)| j < end).is_some() {}
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = i;
+            while let Some(&(j, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    end = j + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push((i, Token::Ident(text[i..end].to_string())));
+        } else {
+            let token = match c {
+                '+' | '-' | '*' | '/' | '^' => Token::Op(c),
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                ',' => Token::Comma,
+                _ => return Err(ExpressionError::at(i, format!("Unexpected '{}'", c))),
+            };
+            tokens.push((i, token));
+            chars.next();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns the end position of the number starting at `start`: digits with an optional decimal
+/// point and an optional exponent, such as `1.5e-3`.
+fn number_end(text: &str, start: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut end = start;
+    while end < bytes.len() && (bytes[end].is_ascii_digit() || bytes[end] == b'.') {
+        end += 1;
+    }
+    if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+        let mut exponent = end + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'-' || bytes[exponent] == b'+') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            end = exponent;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+    }
+    end
+}
+
+/// Maximum nesting of parentheses, function calls, negations and powers, so deeply nested
+/// expressions are reported instead of overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Recursive descent parser.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    /// Length of the expression text, used as the position of errors at the end.
+    len: usize,
+    /// Current nesting, counted by [`Parser::unary`] which all the recursions go through.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.len, |(i, _)| *i)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|(_, t)| t.clone());
+        self.index += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), ExpressionError> {
+        let position = self.position();
+        if self.next() == Some(expected) {
+            Ok(())
+        } else {
+            Err(ExpressionError::at(position, format!("Expected {}", what)))
+        }
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, ExpressionError> {
+        let mut left = self.term()?;
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek() {
+            let op = if *c == '+' {
+                Operator::Add
+            } else {
+                Operator::Sub
+            };
+            self.next();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+        Ok(left)
+    }
+
+    /// term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, ExpressionError> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(c @ ('*' | '/'))) = self.peek() {
+            let op = if *c == '*' {
+                Operator::Mul
+            } else {
+                Operator::Div
+            };
+            self.next();
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    /// unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, ExpressionError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ExpressionError::at(
+                self.position(),
+                "Expression nested too deeply",
+            ));
+        }
+        self.depth += 1;
+        let expr = if let Some(Token::Op('-')) = self.peek() {
+            self.next();
+            self.unary().map(|e| Expr::Neg(Box::new(e)))
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        expr
+    }
+
+    /// power := primary ('^' unary)?
+    fn power(&mut self) -> Result<Expr, ExpressionError> {
+        let base = self.primary()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.next();
+            return Ok(Expr::Binary(
+                Operator::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    /// primary := number [unit] | trace | function '(' args ')' | '(' expr ')'
+    fn primary(&mut self) -> Result<Expr, ExpressionError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Number(value)) => {
+                // Optional frequency unit, for instance "2MHz".
+                if let Some(Token::Ident(unit)) = self.peek()
+                    && let Some(k) = frequency_unit(unit)
+                {
+                    self.next();
+                    return Ok(Expr::Number(value * k));
+                }
+                Ok(Expr::Number(value))
+            }
+            Some(Token::Ident(name)) => {
+                if let Some(index) = name.strip_prefix('t').and_then(|n| n.parse().ok()) {
+                    return Ok(Expr::Trace(index));
+                }
+                let function = Function::from_name(&name).ok_or_else(|| {
+                    ExpressionError::at(position, format!("Unknown function or trace '{}'", name))
+                })?;
+                self.expect(Token::LeftParen, "'('")?;
+                let mut args = vec![self.expr()?];
+                while let Some(Token::Comma) = self.peek() {
+                    self.next();
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::RightParen, "')'")?;
+                if args.len() != function.arity() {
+                    return Err(ExpressionError::at(
+                        position,
+                        format!(
+                            "{} expects {} argument(s)",
+                            function.name(),
+                            function.arity()
+                        ),
+                    ));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::LeftParen) => {
+                let expr = self.expr()?;
+                self.expect(Token::RightParen, "')'")?;
+                Ok(expr)
+            }
+            _ => Err(ExpressionError::at(position, "Expected a value")),
+        }
+    }
+}
+
+/// Returns the multiplier of a frequency unit to convert it to Hz.
+fn frequency_unit(unit: &str) -> Option<f64> {
+    match unit {
+        "Hz" => Some(1.0),
+        "kHz" => Some(1e3),
+        "MHz" => Some(1e6),
+        "GHz" => Some(1e9),
+        _ => None,
+    }
+}
+
+/// Intermediate value during evaluation.
+enum Value {
+    Scalar(f64),
+    Trace(Arc<Trace>),
+}
+
+impl Expr {
+    /// Parses an expression.
+    pub fn parse(text: &str) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            index: 0,
+            len: text.len(),
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.index < parser.tokens.len() {
+            return Err(ExpressionError::at(parser.position(), "Unexpected token"));
+        }
+        Ok(expr)
+    }
+
+    /// Returns the index of the first trace referenced by the expression, if any.
+    pub fn first_trace(&self) -> Option<usize> {
+        match self {
+            Expr::Number(_) => None,
+            Expr::Trace(i) => Some(*i),
+            Expr::Neg(e) => e.first_trace(),
+            Expr::Binary(_, a, b) => a.first_trace().or_else(|| b.first_trace()),
+            Expr::Call(_, args) => args.iter().find_map(|e| e.first_trace()),
+        }
+    }
+
+    /// Number of nodes of the expression tree, used to report the evaluation progress.
+    fn size(&self) -> usize {
+        1 + match self {
+            Expr::Number(_) | Expr::Trace(_) => 0,
+            Expr::Neg(e) => e.size(),
+            Expr::Binary(_, a, b) => a.size() + b.size(),
+            Expr::Call(_, args) => args.iter().map(|e| e.size()).sum(),
+        }
+    }
+
+    /// Evaluates the expression on all the samples of the given `traces`, sampled at
+    /// `sampling_rate` MS/s. The expression must reference at least one trace, and its result must
+    /// be finite, so `log(0)` or a division by zero is reported as an error.
+    ///
+    /// Returns `Ok(None)` if the evaluation has been cancelled with `progress`.
+    pub fn evaluate(
+        &self,
+        traces: &[Arc<Trace>],
+        sampling_rate: f32,
+        progress: &Progress,
+    ) -> Result<Option<Vec<f32>>, ExpressionError> {
+        let mut evaluator = Evaluator {
+            traces,
+            sampling_rate,
+            progress,
+            done: 0,
+            total: self.size(),
+        };
+        match evaluator.eval(self)? {
+            Some(Value::Trace(trace)) => {
+                if trace.is_empty() {
+                    return Err(ExpressionError::new(
+                        "The result of the expression is empty",
+                    ));
+                }
+                if let Some(i) = trace.iter().position(|x| !x.is_finite()) {
+                    return Err(ExpressionError::new(format!(
+                        "The result of the expression is {} at sample {}",
+                        trace[i], i
+                    )));
+                }
+                Ok(Some(
+                    Arc::try_unwrap(trace).map_or_else(|t| t.to_vec(), Trace::into_vec),
+                ))
+            }
+            Some(Value::Scalar(_)) => Err(ExpressionError::new(
+                "The expression must reference at least one trace",
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    traces: &'a [Arc<Trace>],
+    sampling_rate: f32,
+    progress: &'a Progress,
+    /// Number of evaluated nodes.
+    done: usize,
+    total: usize,
+}
+
+impl Evaluator<'_> {
+    /// Evaluates a node. Returns `None` if cancelled.
+    fn eval(&mut self, expr: &Expr) -> Result<Option<Value>, ExpressionError> {
+        if self.progress.is_cancelled() {
+            return Ok(None);
+        }
+        let value = match expr {
+            Expr::Number(x) => Value::Scalar(*x),
+            Expr::Trace(i) => Value::Trace(
+                self.traces
+                    .get(*i)
+                    .ok_or_else(|| {
+                        ExpressionError::new(format!(
+                            "Trace t{} does not exist ({} traces loaded)",
+                            i,
+                            self.traces.len()
+                        ))
+                    })?
+                    .clone(),
+            ),
+            Expr::Neg(e) => {
+                let Some(v) = self.eval(e)? else {
+                    return Ok(None);
+                };
+                map(v, |x| -x)
+            }
+            Expr::Binary(op, a, b) => {
+                let (Some(a), Some(b)) = (self.eval(a)?, self.eval(b)?) else {
+                    return Ok(None);
+                };
+                combine(a, b, |x, y| op.apply(x, y))
+            }
+            Expr::Call(function, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    let Some(v) = self.eval(arg)? else {
+                        return Ok(None);
+                    };
+                    values.push(v);
+                }
+                self.call(*function, values)?
+            }
+        };
+        self.done += 1;
+        self.progress.set(self.done as f32 / self.total as f32);
+        Ok(Some(value))
+    }
+
+    fn call(&self, function: Function, mut args: Vec<Value>) -> Result<Value, ExpressionError> {
+        let filter = match function {
+            Function::Abs => return Ok(map(args.remove(0), f32::abs)),
+            Function::Sqrt => return Ok(map(args.remove(0), f32::sqrt)),
+            Function::Exp => return Ok(map(args.remove(0), f32::exp)),
+            Function::Log => return Ok(map(args.remove(0), f32::ln)),
+            Function::Min => {
+                let b = args.remove(1);
+                return Ok(combine(args.remove(0), b, f32::min));
+            }
+            Function::Max => {
+                let b = args.remove(1);
+                return Ok(combine(args.remove(0), b, f32::max));
+            }
+            Function::Envelope => {
+                let trace = self.trace_argument(function, &args[0])?;
+                return Ok(Value::Trace(Arc::new(envelope_hilbert(trace).into())));
+            }
+            Function::Smooth => {
+                let trace = self.trace_argument(function, &args[0])?;
+                let window = self.scalar_argument(function, &args[1])?;
+                return Ok(Value::Trace(Arc::new(
+                    moving_average(trace, window.round().max(1.0) as usize).into(),
+                )));
+            }
+            Function::LowPass => Filter::LowPass,
+            Function::HighPass => Filter::HighPass,
+            Function::BandPass => Filter::BandPass,
+            Function::Notch => Filter::Notch,
+        };
+        let trace = self.trace_argument(function, &args[0])?;
+        let frequency = self.scalar_argument(function, &args[1])?;
+        let spec = FilterSpec {
+            filter,
+            cutoff_freq: (frequency / 1e3) as f32,
+            q: FilterSpec::DEFAULT_Q,
+            notches: FilterSpec::DEFAULT_NOTCHES,
+        };
+        let filtered = spec.apply(trace, self.sampling_rate).map_err(|_| {
+            ExpressionError::new(format!(
+                "{}: frequency must be positive and below the Nyquist frequency ({} MHz)",
+                function.name(),
+                self.sampling_rate / 2.0
+            ))
+        })?;
+        Ok(Value::Trace(Arc::new(filtered.into())))
+    }
+
+    fn trace_argument<'v>(
+        &self,
+        function: Function,
+        value: &'v Value,
+    ) -> Result<&'v [f32], ExpressionError> {
+        match value {
+            Value::Trace(trace) => Ok(trace),
+            Value::Scalar(_) => Err(ExpressionError::new(format!(
+                "{}: first argument must be a trace",
+                function.name()
+            ))),
+        }
+    }
+
+    fn scalar_argument(&self, function: Function, value: &Value) -> Result<f64, ExpressionError> {
+        match value {
+            Value::Scalar(x) => Ok(*x),
+            Value::Trace(_) => Err(ExpressionError::new(format!(
+                "{}: second argument must be a number",
+                function.name()
+            ))),
+        }
+    }
+}
+
+/// Applies `f` to a scalar or to all the samples of a trace.
+fn map(value: Value, f: impl Fn(f32) -> f32) -> Value {
+    match value {
+        Value::Scalar(x) => Value::Scalar(f(x as f32) as f64),
+        Value::Trace(trace) => Value::Trace(Arc::new(trace.iter().map(|&x| f(x)).collect())),
+    }
+}
+
+/// Combines two values sample by sample. Scalars are broadcast to all samples, and traces are
+/// truncated to the shortest one.
+fn combine(a: Value, b: Value, f: impl Fn(f32, f32) -> f32) -> Value {
+    match (a, b) {
+        (Value::Scalar(a), Value::Scalar(b)) => Value::Scalar(f(a as f32, b as f32) as f64),
+        (Value::Trace(a), Value::Scalar(b)) => {
+            let b = b as f32;
+            Value::Trace(Arc::new(a.iter().map(|&x| f(x, b)).collect()))
+        }
+        (Value::Scalar(a), Value::Trace(b)) => {
+            let a = a as f32;
+            Value::Trace(Arc::new(b.iter().map(|&y| f(a, y)).collect()))
+        }
+        (Value::Trace(a), Value::Trace(b)) => Value::Trace(Arc::new(
+            a.iter().zip(b.iter()).map(|(&x, &y)| f(x, y)).collect(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(value: f64) -> Box<Expr> {
+        Box::new(Expr::Number(value))
+    }
+
+    #[test]
+    fn tokenize_numbers_identifiers_and_symbols() {
+        let tokens = tokenize("1.5e-3*t12 +(x_1, 2.)").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (0, Token::Number(1.5e-3)),
+                (6, Token::Op('*')),
+                (7, Token::Ident("t12".to_string())),
+                (11, Token::Op('+')),
+                (12, Token::LeftParen),
+                (13, Token::Ident("x_1".to_string())),
+                (16, Token::Comma),
+                (18, Token::Number(2.0)),
+                (20, Token::RightParen),
+            ]
+        );
+        // An exponent without digits is not part of the number.
+        assert_eq!(
+            tokenize("2e").unwrap(),
+            [(0, Token::Number(2.0)), (1, Token::Ident("e".to_string()))]
+        );
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(
+            tokenize("t0 % 2"),
+            Err(ExpressionError::at(3, "Unexpected '%'"))
+        );
+        assert_eq!(
+            tokenize("1..2"),
+            Err(ExpressionError::at(0, "Invalid number"))
+        );
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        use Operator::*;
+        assert_eq!(
+            Expr::parse("1 + 2 * 3").unwrap(),
+            Expr::Binary(
+                Add,
+                number(1.0),
+                Box::new(Expr::Binary(Mul, number(2.0), number(3.0)))
+            )
+        );
+        assert_eq!(
+            Expr::parse("1 - 2 - 3").unwrap(),
+            Expr::Binary(
+                Sub,
+                Box::new(Expr::Binary(Sub, number(1.0), number(2.0))),
+                number(3.0)
+            )
+        );
+        // Powers are right-associative and bind tighter than negation.
+        assert_eq!(
+            Expr::parse("2 ^ 3 ^ 2").unwrap(),
+            Expr::Binary(
+                Pow,
+                number(2.0),
+                Box::new(Expr::Binary(Pow, number(3.0), number(2.0)))
+            )
+        );
+        assert_eq!(
+            Expr::parse("-2 ^ 2").unwrap(),
+            Expr::Neg(Box::new(Expr::Binary(Pow, number(2.0), number(2.0))))
+        );
+        assert_eq!(
+            Expr::parse("(1 + 2) * t0").unwrap(),
+            Expr::Binary(
+                Mul,
+                Box::new(Expr::Binary(Add, number(1.0), number(2.0))),
+                Box::new(Expr::Trace(0))
+            )
+        );
+        assert_eq!(
+            Expr::parse("lowpass(t1, 2MHz)").unwrap(),
+            Expr::Call(Function::LowPass, vec![Expr::Trace(1), Expr::Number(2e6)])
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        let error = |text| Expr::parse(text).unwrap_err();
+        assert_eq!(
+            error("foo(t0)"),
+            ExpressionError::at(0, "Unknown function or trace 'foo'")
+        );
+        assert_eq!(error("(t0 + 1"), ExpressionError::at(7, "Expected ')'"));
+        assert_eq!(error("t0 * "), ExpressionError::at(5, "Expected a value"));
+        assert_eq!(error("t0 t1"), ExpressionError::at(3, "Unexpected token"));
+        assert_eq!(error("abs t0"), ExpressionError::at(4, "Expected '('"));
+        assert_eq!(
+            error("2 * min(t0)"),
+            ExpressionError::at(4, "min expects 2 argument(s)")
+        );
+        assert_eq!(error("1 + ?").to_string(), "Unexpected '?' (at position 5)");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| format!("{}t0{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expr::parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(
+            Expr::parse(&nested(MAX_DEPTH)),
+            Err(ExpressionError::at(
+                MAX_DEPTH,
+                "Expression nested too deeply"
+            ))
+        );
+        assert!(Expr::parse(&"-".repeat(100_000)).is_err());
+        assert!(Expr::parse(&"abs(".repeat(100_000)).is_err());
+        assert!(Expr::parse(&"2^".repeat(100_000)).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Modal dialog for creating a trace from an expression.
+
+use crate::{
+    expression::{Expr, ExpressionError, Function},
+    processing::{NewTrace, ProcessingJob},
+    trace::Trace,
+};
+use egui::{Button, Color32, Id, Modal, ProgressBar, TextEdit};
+use std::{sync::Arc, time::Duration};
+
+/// Background evaluation of an expression.
+type EvaluationJob = ProcessingJob<Result<Vec<f32>, ExpressionError>>;
+
+/// Result of the expression dialog update.
+pub enum ExpressionDialogResult {
+    /// The user is still editing the expression, or the evaluation is running.
+    Pending,
+    /// The expression has been evaluated. The expression is used as the trace label.
+    Created(NewTrace),
+    /// Dialog was cancelled.
+    Cancelled,
+}
+
+/// A trace which can be referenced in expressions.
+pub struct ExpressionSource {
+    pub label: String,
+    pub trace: Arc<Trace>,
+    /// Sampling rate in MS/s.
+    pub sampling_rate: f32,
+}
+
+/// Dialog for deriving a new trace from the loaded ones with an expression.
+pub struct ExpressionDialog {
+    id: Id,
+    text: String,
+    error: Option<ExpressionError>,
+    /// Running evaluation, with the sampling rate of the resulting trace.
+    job: Option<(EvaluationJob, f32)>,
+}
+
+impl ExpressionDialog {
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            text: String::new(),
+            error: None,
+            job: None,
+        }
+    }
+
+    /// Shows the dialog and returns the user decision. `sources` are the traces which can be
+    /// referenced, `t0` being the first one.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        sources: &[ExpressionSource],
+    ) -> ExpressionDialogResult {
+        if let Some((job, sampling_rate)) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            let result = result
+                .map_err(ExpressionError::new)
+                .and_then(|result| result);
+            let sampling_rate = *sampling_rate;
+            self.job = None;
+            match result {
+                Ok(trace) => {
+                    return ExpressionDialogResult::Created(NewTrace {
+                        label: self.text.trim().to_string(),
+                        trace,
+                        sampling_rate,
+                    });
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        let mut result = ExpressionDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("New trace from expression");
+            ui.add_space(5.0);
+            ui.label("Traces:");
+            for (i, source) in sources.iter().enumerate() {
+                ui.monospace(format!("t{}: {}", i, source.label));
+            }
+            ui.add_space(5.0);
+            let response = ui.add(
+                TextEdit::singleline(&mut self.text)
+                    .hint_text("abs(t0) - 0.5 * t1")
+                    .desired_width(400.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            if response.changed() {
+                self.error = None;
+            }
+            ui.collapsing("Functions", |ui| {
+                for function in Function::all() {
+                    ui.monospace(function.signature());
+                }
+                ui.label("Frequencies accept Hz, kHz, MHz and GHz units, e.g. 2MHz.");
+            });
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error.to_string());
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if let Some((job, _)) = &self.job {
+                    ui.add(
+                        ProgressBar::new(job.progress())
+                            .desired_width(200.0)
+                            .show_percentage()
+                            .animate(true),
+                    );
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                    if ui.button("Cancel").clicked() {
+                        // Dropping the job cancels it.
+                        self.job = None;
+                    }
+                    return;
+                }
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui
+                    .add_enabled(!self.text.trim().is_empty(), Button::new("Create"))
+                    .clicked()
+                    || submitted
+                {
+                    self.evaluate(ctx, sources);
+                }
+                if ui.button("Cancel").clicked() {
+                    result = ExpressionDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() && self.job.is_none() {
+            ExpressionDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+
+    /// Parses the expression and starts its evaluation in background.
+    fn evaluate(&mut self, ctx: &egui::Context, sources: &[ExpressionSource]) {
+        let expr = match Expr::parse(&self.text) {
+            Ok(expr) => expr,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+        // The result has the sampling rate of the first referenced trace.
+        let Some(sampling_rate) = expr
+            .first_trace()
+            .and_then(|i| sources.get(i))
+            .map(|s| s.sampling_rate)
+        else {
+            self.error = Some(ExpressionError::new(
+                "The expression must reference an existing trace",
+            ));
+            return;
+        };
+        let traces: Vec<_> = sources.iter().map(|s| s.trace.clone()).collect();
+        let job = ProcessingJob::spawn(ctx, move |progress| {
+            expr.evaluate(&traces, sampling_rate, progress).transpose()
+        });
+        self.job = Some((job, sampling_rate));
+    }
+}
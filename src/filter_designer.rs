@@ -0,0 +1,273 @@
+//! Modal dialog for configuring the filter applied to a trace at runtime.
+
+use crate::{
+    filtering::{Filter, FilterSpec},
+    format::NumberFormat,
+};
+use egui::{
+    Align2, Button, Color32, ComboBox, DragValue, FontId, Id, Modal, Sense, Shape, Stroke, Ui,
+    pos2, vec2,
+};
+use std::f64::consts::PI;
+
+/// Number of frequencies evaluated to plot the filter response.
+const RESPONSE_POINTS: usize = 256;
+/// Frequency range of the response plot, in decades below the Nyquist frequency.
+const RESPONSE_DECADES: i32 = 4;
+/// Gain range of the response plot, in dB.
+const RESPONSE_DB_RANGE: (f64, f64) = (-60.0, 10.0);
+
+/// Result of the filter designer update.
+pub enum FilterDesignerResult {
+    /// The user is still editing the filter.
+    Pending,
+    /// The filter must be applied to the trace.
+    Apply(FilterSpec),
+    /// The filter currently applied must be removed, restoring the original trace.
+    Remove,
+    /// Dialog was cancelled, nothing changes.
+    Cancelled,
+}
+
+/// Filter configuration dialog of a viewer.
+pub struct FilterDesigner {
+    id: Id,
+    /// Filter being edited.
+    spec: FilterSpec,
+    /// True if a filter is currently applied to the trace, in which case it can be removed.
+    applied: bool,
+    /// Also plot the phase in the frequency response.
+    show_phase: bool,
+    /// Preview the filter on the visible samples of the trace.
+    preview: bool,
+    /// Formatting of the frequencies of the response plot.
+    format: NumberFormat,
+}
+
+impl FilterDesigner {
+    /// Creates a designer editing `current`, the filter currently applied to the trace, or a
+    /// default low-pass filter if there is none.
+    pub fn new(id: Id, current: Option<FilterSpec>, format: NumberFormat) -> Self {
+        Self {
+            id,
+            spec: current.unwrap_or(FilterSpec {
+                filter: Filter::LowPass,
+                cutoff_freq: 1000.0,
+                q: FilterSpec::DEFAULT_Q,
+                notches: FilterSpec::DEFAULT_NOTCHES,
+            }),
+            applied: current.is_some(),
+            show_phase: false,
+            preview: false,
+            format,
+        }
+    }
+
+    /// Returns the filter to preview on the visible samples, if the preview is enabled and the
+    /// filter is valid for `sampling_rate`.
+    pub fn preview(&self, sampling_rate: f32) -> Option<FilterSpec> {
+        (self.preview && self.spec.coefficients(sampling_rate).is_ok()).then_some(self.spec)
+    }
+
+    /// Shows the dialog and returns the user decision.
+    ///
+    /// `sampling_rate` is the trace sampling rate in MS/s, used to validate the filter.
+    pub fn update(&mut self, ctx: &egui::Context, sampling_rate: f32) -> FilterDesignerResult {
+        let mut result = FilterDesignerResult::Pending;
+        let mut modal = Modal::new(self.id);
+        if self.preview {
+            // Don't darken the previewed trace.
+            modal = modal.backdrop_color(Color32::TRANSPARENT);
+        }
+        let response = modal.show(ctx, |ui| {
+            ui.heading("Filter");
+            ui.add_space(5.0);
+            egui::Grid::new("filter_designer_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Type:");
+                    ComboBox::from_id_salt("filter_designer_type")
+                        .selected_text(self.spec.filter.name())
+                        .show_ui(ui, |ui| {
+                            for x in [
+                                Filter::LowPass,
+                                Filter::HighPass,
+                                Filter::BandPass,
+                                Filter::Notch,
+                                Filter::Comb,
+                            ] {
+                                ui.selectable_value(&mut self.spec.filter, x, x.name());
+                            }
+                        });
+                    ui.end_row();
+                    ui.label(format!("{}:", self.spec.filter.frequency_label()));
+                    ui.add(
+                        DragValue::new(&mut self.spec.cutoff_freq)
+                            .suffix(" kHz")
+                            .range(0.001..=1000e6)
+                            .speed(10.0),
+                    );
+                    ui.end_row();
+                    ui.label("Q:");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            DragValue::new(&mut self.spec.q)
+                                .range(0.01..=1000.0)
+                                .speed(0.05),
+                        );
+                        if self.spec.filter.has_center_frequency() {
+                            ui.label(format!(
+                                "Bandwidth {} kHz",
+                                self.spec.cutoff_freq / self.spec.q
+                            ));
+                        }
+                        if ui.small_button("Butterworth").clicked() {
+                            self.spec.q = FilterSpec::DEFAULT_Q;
+                        }
+                    });
+                    ui.end_row();
+                    if self.spec.filter == Filter::Comb {
+                        ui.label("Notches:");
+                        ui.add(DragValue::new(&mut self.spec.notches).range(1..=1000));
+                        ui.end_row();
+                    }
+                });
+
+            ui.add_space(5.0);
+            self.paint_response(ui, sampling_rate);
+            ui.checkbox(&mut self.show_phase, "Show phase");
+            ui.checkbox(&mut self.preview, "Preview on visible samples");
+
+            let valid = self.spec.coefficients(sampling_rate).is_ok();
+            if !valid {
+                ui.colored_label(
+                    Color32::RED,
+                    format!(
+                        "Cutoff frequency must be below {} kHz (Nyquist frequency).",
+                        sampling_rate * 500.0
+                    ),
+                );
+            }
+
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if ui.add_enabled(valid, Button::new("Apply")).clicked() {
+                    result = FilterDesignerResult::Apply(self.spec);
+                }
+                if ui
+                    .add_enabled(self.applied, Button::new("Remove filter"))
+                    .clicked()
+                {
+                    result = FilterDesignerResult::Remove;
+                }
+                if ui.button("Cancel").clicked() {
+                    result = FilterDesignerResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() {
+            FilterDesignerResult::Cancelled
+        } else {
+            result
+        }
+    }
+
+    /// Plots the magnitude (and optionally the phase) of the filter frequency response, from
+    /// [`RESPONSE_DECADES`] decades below the Nyquist frequency up to it.
+    fn paint_response(&self, ui: &mut Ui, sampling_rate: f32) {
+        let (response, painter) = ui.allocate_painter(vec2(400.0, 180.0), Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2.0, Color32::BLACK);
+
+        let font_id = FontId::proportional(10.0);
+        let grid_stroke = Stroke::new(1.0, Color32::from_gray(60));
+        let nyquist = sampling_rate as f64 * 0.5e6;
+        let f_min = nyquist / 10f64.powi(RESPONSE_DECADES);
+        let freq_to_x = |f: f64| {
+            rect.left() + rect.width() * ((f / f_min).log10() / RESPONSE_DECADES as f64) as f32
+        };
+        let (db_min, db_max) = RESPONSE_DB_RANGE;
+        let db_to_y = |db: f64| {
+            rect.bottom()
+                - rect.height() * ((db - db_min) / (db_max - db_min)).clamp(0.0, 1.0) as f32
+        };
+
+        // Frequency grid, one line per decade.
+        for decade in 0..=RESPONSE_DECADES {
+            let f = f_min * 10f64.powi(decade);
+            let x = freq_to_x(f);
+            painter.line_segment([pos2(x, rect.top()), pos2(x, rect.bottom())], grid_stroke);
+            let align = if decade == RESPONSE_DECADES {
+                Align2::RIGHT_BOTTOM
+            } else {
+                Align2::LEFT_BOTTOM
+            };
+            painter.text(
+                pos2(x, rect.bottom()),
+                align,
+                self.format.quantity(f, "Hz"),
+                font_id.clone(),
+                Color32::GRAY,
+            );
+        }
+        // Gain grid, one line every 20 dB.
+        for db in (db_min as i32..=0).step_by(20) {
+            let y = db_to_y(db as f64);
+            painter.line_segment([pos2(rect.left(), y), pos2(rect.right(), y)], grid_stroke);
+            painter.text(
+                pos2(rect.left() + 2.0, y),
+                Align2::LEFT_TOP,
+                format!("{} dB", db),
+                font_id.clone(),
+                Color32::GRAY,
+            );
+        }
+
+        // Cutoff (or center) frequency markers, including the harmonics of a comb filter.
+        let markers = if self.spec.filter == Filter::Comb {
+            self.spec.notches
+        } else {
+            1
+        };
+        for k in 1..=markers {
+            let f = self.spec.cutoff_freq as f64 * 1e3 * k as f64;
+            if !(f_min..=nyquist).contains(&f) {
+                continue;
+            }
+            let x = freq_to_x(f);
+            painter.add(Shape::dashed_line(
+                &[pos2(x, rect.top()), pos2(x, rect.bottom())],
+                Stroke::new(1.0, Color32::YELLOW.gamma_multiply(0.5)),
+                4.0,
+                4.0,
+            ));
+        }
+
+        let responses: Vec<_> = (0..RESPONSE_POINTS)
+            .filter_map(|i| {
+                let k = i as f64 / (RESPONSE_POINTS - 1) as f64;
+                let f = f_min * 10f64.powf(k * RESPONSE_DECADES as f64);
+                let (magnitude, phase) = self.spec.response(sampling_rate, f)?;
+                Some((freq_to_x(f), magnitude, phase))
+            })
+            .collect();
+
+        if self.show_phase {
+            let points = responses
+                .iter()
+                .map(|&(x, _, phase)| {
+                    pos2(
+                        x,
+                        rect.center().y - rect.height() * (phase / (2.0 * PI)) as f32,
+                    )
+                })
+                .collect();
+            painter.line(points, Stroke::new(1.0, Color32::ORANGE));
+        }
+        let points = responses
+            .iter()
+            .map(|&(x, magnitude, _)| pos2(x, db_to_y(20.0 * magnitude.max(1e-12).log10())))
+            .collect();
+        painter.line(points, Stroke::new(1.5, Color32::LIGHT_BLUE));
+    }
+}
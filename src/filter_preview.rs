@@ -0,0 +1,170 @@
+//! Live preview of a filter on the visible part of a trace.
+//!
+//! Filtering a huge trace takes time, so while the filter designer is opened only the samples
+//! currently displayed are filtered, and the result is painted over the trace. This allows tuning
+//! the filter parameters interactively.
+//!
+//! The preview is calculated in background while the parameters are dragged or the view is moved.
+//! Calculations are throttled, and only the last requested parameters are calculated once the
+//! running calculation finishes; meanwhile the last calculated preview remains displayed.
+
+use crate::{
+    camera::Camera,
+    filtering::FilterSpec,
+    processing::{ProcessingJob, Progress},
+    util::Fixed,
+};
+use egui::{Color32, Painter, Rect, Stroke, pos2};
+use std::{ops::Range, time::Duration};
+use web_time::Instant;
+
+/// Maximum number of samples filtered for the preview. Above this, the user must zoom in.
+pub const PREVIEW_MAX_SAMPLES: usize = 4_000_000;
+
+/// Number of samples filtered before the visible ones, and discarded, so the transient response of
+/// the filter is not previewed.
+pub const PREVIEW_MARGIN: usize = 4096;
+
+/// Minimum delay between the starts of two preview calculations.
+const PREVIEW_THROTTLE: Duration = Duration::from_millis(40);
+
+/// Parameters of a computed preview. The preview is calculated again only when one of them
+/// changes.
+#[derive(Clone, PartialEq)]
+pub struct PreviewKey {
+    pub spec: FilterSpec,
+    /// Visible samples range, in displayed trace indices.
+    pub visible: Range<usize>,
+    /// Generation of the displayed trace.
+    pub generation: u32,
+    /// Index of the edited filter step, if any.
+    pub edited_step: Option<usize>,
+}
+
+/// Filtered samples of the preview, positioned on the displayed trace.
+pub struct PreviewSamples {
+    pub samples: Vec<f32>,
+    /// Displayed trace index of the first sample.
+    pub start: f64,
+    /// Number of displayed trace samples between two preview samples. Differs from 1 when the
+    /// filter input has another sampling rate than the displayed trace.
+    pub step: f64,
+}
+
+/// Preview calculation, run in background.
+pub type PreviewCalculation =
+    Box<dyn FnOnce(&Progress) -> Result<Option<PreviewSamples>, String> + Send>;
+
+/// Cached filter preview of a viewer.
+#[derive(Default)]
+pub struct FilterPreview {
+    /// Parameters of the cached preview, or of the last preview which could not be calculated.
+    key: Option<PreviewKey>,
+    samples: Option<PreviewSamples>,
+    /// Running calculation, with its parameters.
+    job: Option<(ProcessingJob<PreviewSamples>, PreviewKey)>,
+    /// Start time of the last calculation, for throttling.
+    last_start: Option<Instant>,
+}
+
+impl FilterPreview {
+    /// Collects the running calculation if it has finished, and starts the calculation of the
+    /// preview for `key` if it differs from the cached one and no calculation is running.
+    ///
+    /// `prepare` returns the calculation to run in background, or `None` if there are too many
+    /// samples to process.
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        key: PreviewKey,
+        prepare: impl FnOnce() -> Option<PreviewCalculation>,
+    ) {
+        if let Some((job, job_key)) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            self.samples = result
+                .inspect_err(|e| tracing::warn!("Filter preview failed: {}", e))
+                .ok();
+            self.key = Some(job_key.clone());
+            self.job = None;
+        }
+        if self.key.as_ref() == Some(&key) || self.job.is_some() {
+            return;
+        }
+        if let Some(elapsed) = self.last_start.map(|t| t.elapsed())
+            && elapsed < PREVIEW_THROTTLE
+        {
+            ctx.request_repaint_after(PREVIEW_THROTTLE - elapsed);
+            return;
+        }
+        match prepare() {
+            Some(compute) => {
+                self.job = Some((ProcessingJob::try_spawn(ctx, compute), key));
+                self.last_start = Some(Instant::now());
+            }
+            None => {
+                self.samples = None;
+                self.key = Some(key);
+            }
+        }
+    }
+
+    /// True if the preview has been calculated or is being calculated. It is not when there are
+    /// too many visible samples.
+    pub fn is_available(&self) -> bool {
+        self.samples.is_some() || self.job.is_some()
+    }
+
+    /// Discards the cached preview, and cancels its calculation.
+    pub fn clear(&mut self) {
+        self.key = None;
+        self.samples = None;
+        self.job = None;
+    }
+
+    /// Paints the preview as a line over the trace. When there are many samples per pixel column,
+    /// the minimum and maximum of each column are joined, so the drawn line does not have more
+    /// points than the viewport width.
+    pub fn paint(&self, camera: &Camera, ppp: f32, painter: &Painter, viewport: &Rect) {
+        let Some(preview) = &self.samples else {
+            return;
+        };
+        let to_y = |v: f32| {
+            viewport.center().y
+                - (v + camera.shift.y.to_num::<f32>()) * camera.scale.y.to_num::<f32>() / ppp
+        };
+        let to_x = |i: usize| {
+            camera.world_to_screen_x(
+                viewport,
+                ppp,
+                Fixed::from_num(preview.start + i as f64 * preview.step),
+            )
+        };
+        let mut points = Vec::new();
+        let mut column: Option<(i32, f32, f32)> = None;
+        for (i, &v) in preview.samples.iter().enumerate() {
+            let x = to_x(i);
+            let px = (x * ppp).floor() as i32;
+            match &mut column {
+                Some((c, min, max)) if *c == px => {
+                    *min = min.min(v);
+                    *max = max.max(v);
+                }
+                _ => {
+                    if let Some((c, min, max)) = column {
+                        let cx = c as f32 / ppp;
+                        points.push(pos2(cx, to_y(min)));
+                        points.push(pos2(cx, to_y(max)));
+                    }
+                    column = Some((px, v, v));
+                }
+            }
+        }
+        if let Some((c, min, max)) = column {
+            let cx = c as f32 / ppp;
+            points.push(pos2(cx, to_y(min)));
+            points.push(pos2(cx, to_y(max)));
+        }
+        painter.line(points, Stroke::new(1.0, Color32::YELLOW));
+    }
+}
@@ -1,7 +1,7 @@
-use biquad::{Biquad, Coefficients, DirectForm1, Hertz, Q_BUTTERWORTH_F32, Type};
-use serde::Serialize;
+use biquad::{Biquad, Coefficients, DirectForm1, Errors, Q_BUTTERWORTH_F32, ToHertz, Type};
+use serde::{Deserialize, Serialize};
 
-#[derive(clap::ValueEnum, Copy, Clone, Debug, Serialize, PartialEq, Eq)]
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 /// Digital filters supported by TurboPlot.
 pub enum Filter {
@@ -13,34 +13,193 @@ pub enum Filter {
     BandPass,
     /// Notch filter
     Notch,
+    /// Comb filter: notches at a fundamental frequency and its harmonics
+    Comb,
+}
+
+impl Filter {
+    pub fn name(&self) -> &str {
+        match self {
+            Filter::LowPass => "Low-pass",
+            Filter::HighPass => "High-pass",
+            Filter::BandPass => "Band-pass",
+            Filter::Notch => "Notch",
+            Filter::Comb => "Comb",
+        }
+    }
+
+    /// True if the filter frequency is a center frequency (band-pass and notch filters) rather
+    /// than a cutoff frequency. The bandwidth of these filters is the center frequency divided by
+    /// the quality factor.
+    pub fn has_center_frequency(&self) -> bool {
+        matches!(self, Filter::BandPass | Filter::Notch | Filter::Comb)
+    }
+
+    /// Label of the filter frequency parameter in the user interface.
+    pub fn frequency_label(&self) -> &str {
+        match self {
+            Filter::Comb => "Fundamental frequency",
+            _ if self.has_center_frequency() => "Center frequency",
+            _ => "Cutoff frequency",
+        }
+    }
 }
 
 /// Converts CLI filters into biquad ones.
+/// Comb filters are made of several notch filter stages.
 impl From<Filter> for Type<f32> {
     fn from(value: Filter) -> Self {
         match value {
             Filter::LowPass => Type::LowPass,
             Filter::HighPass => Type::HighPass,
             Filter::BandPass => Type::BandPass,
-            Filter::Notch => Type::Notch,
+            Filter::Notch | Filter::Comb => Type::Notch,
         }
     }
 }
 
-/// Define an interface to apply filter on traces.
-pub trait Filtering {
-    fn apply_filter(&mut self, filter: Filter, fs: Hertz<f32>, f0: Hertz<f32>);
+/// Number of samples filtered between two progress reports.
+const FILTER_CHUNK_SIZE: usize = 1 << 20;
+
+/// A filter and its parameters, as configured in the filter designer.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FilterSpec {
+    pub filter: Filter,
+    /// Cutoff frequency in kHz, or center frequency for band-pass and notch filters.
+    pub cutoff_freq: f32,
+    /// Quality factor.
+    pub q: f32,
+    /// Maximum number of notches of a comb filter, including the fundamental. Harmonics above the
+    /// Nyquist frequency are ignored. Unused by the other filters.
+    pub notches: usize,
 }
 
-/// Extends Vec<f32> to support digital filters.
-impl Filtering for Vec<f32> {
-    fn apply_filter(&mut self, filter: Filter, fs: Hertz<f32>, f0: Hertz<f32>) {
-        let coeffs =
-            Coefficients::<f32>::from_params(filter.into(), fs, f0, Q_BUTTERWORTH_F32).unwrap();
-        let mut biquad = DirectForm1::<f32>::new(coeffs);
+impl FilterSpec {
+    /// Default quality factor, giving a Butterworth response for low-pass and high-pass filters.
+    pub const DEFAULT_Q: f32 = Q_BUTTERWORTH_F32;
+
+    /// Default number of notches of a comb filter.
+    pub const DEFAULT_NOTCHES: usize = 10;
+
+    /// Returns the frequency in kHz and the quality factor of each biquad stage of the filter, for
+    /// a trace sampled at `sampling_rate` MS/s.
+    ///
+    /// Other filters have a single stage. The notches of a comb filter all have the same
+    /// bandwidth, the fundamental frequency divided by Q.
+    fn stages(&self, sampling_rate: f32) -> Vec<(f32, f32)> {
+        match self.filter {
+            Filter::Comb => {
+                let nyquist = sampling_rate * 500.0;
+                (1..=self.notches.max(1))
+                    .map(|k| (self.cutoff_freq * k as f32, self.q * k as f32))
+                    .take_while(|&(f, _)| f < nyquist)
+                    .collect()
+            }
+            _ => vec![(self.cutoff_freq, self.q)],
+        }
+    }
+
+    /// Calculates the coefficients of each biquad stage of the filter, for a trace sampled at
+    /// `sampling_rate` MS/s.
+    /// Fails if the cutoff frequency is above the Nyquist frequency, or if Q is not positive.
+    pub fn coefficients(&self, sampling_rate: f32) -> Result<Vec<Coefficients<f32>>, Errors> {
+        let stages = self.stages(sampling_rate);
+        // biquad only rejects frequencies above twice the sampling rate.
+        let nyquist = sampling_rate * 500.0;
+        if stages.is_empty() || !stages.iter().all(|&(f0, _)| f0 < nyquist) {
+            return Err(Errors::OutsideNyquist);
+        }
+        stages
+            .into_iter()
+            .map(|(f0, q)| {
+                Coefficients::<f32>::from_params(
+                    self.filter.into(),
+                    sampling_rate.mhz(),
+                    f0.khz(),
+                    q,
+                )
+            })
+            .collect()
+    }
+
+    /// Explains why the filter cannot be applied to a trace sampled at `sampling_rate` MS/s, given
+    /// the error returned by [`FilterSpec::coefficients`].
+    pub fn error_message(&self, error: Errors, sampling_rate: f32) -> String {
+        match error {
+            Errors::OutsideNyquist => format!(
+                "{} filter: frequency {} kHz must be positive and below the Nyquist frequency ({} kHz)",
+                self.filter.name(),
+                self.cutoff_freq,
+                sampling_rate * 500.0
+            ),
+            Errors::NegativeQ => format!("{} filter: Q must be positive", self.filter.name()),
+            Errors::NegativeFrequency => {
+                format!("{} filter: frequency must be positive", self.filter.name())
+            }
+        }
+    }
+
+    /// Calculates the frequency response of the filter at frequency `freq` in Hz, for a trace
+    /// sampled at `sampling_rate` MS/s.
+    ///
+    /// Returns the magnitude (linear gain) and the phase (in radians), or `None` if the filter is
+    /// invalid for this sampling rate.
+    pub fn response(&self, sampling_rate: f32, freq: f64) -> Option<(f64, f64)> {
+        let w = 2.0 * std::f64::consts::PI * freq / (sampling_rate as f64 * 1e6);
+        let (c1, s1) = (w.cos(), -w.sin());
+        let (c2, s2) = ((2.0 * w).cos(), -(2.0 * w).sin());
+        let mut magnitude = 1.0;
+        let mut phase = 0.0;
+        // The response of cascaded stages is the product of the stages responses.
+        for c in self.coefficients(sampling_rate).ok()? {
+            // Evaluate H(z) = (b0 + b1.z^-1 + b2.z^-2) / (1 + a1.z^-1 + a2.z^-2) on the unit
+            // circle.
+            let (b0, b1, b2) = (c.b0 as f64, c.b1 as f64, c.b2 as f64);
+            let (a1, a2) = (c.a1 as f64, c.a2 as f64);
+            let num = (b0 + b1 * c1 + b2 * c2, b1 * s1 + b2 * s2);
+            let den = (1.0 + a1 * c1 + a2 * c2, a1 * s1 + a2 * s2);
+            magnitude *= num.0.hypot(num.1) / den.0.hypot(den.1);
+            phase += num.1.atan2(num.0) - den.1.atan2(den.0);
+        }
+        // Wrap phase in [-pi, pi]
+        let phase = (phase + std::f64::consts::PI).rem_euclid(2.0 * std::f64::consts::PI)
+            - std::f64::consts::PI;
+        Some((magnitude, phase))
+    }
+
+    /// Returns a filtered copy of `trace`, sampled at `sampling_rate` MS/s.
+    ///
+    /// Fails as [`FilterSpec::coefficients`] if the filter is invalid for this sampling rate.
+    pub fn apply(&self, trace: &[f32], sampling_rate: f32) -> Result<Vec<f32>, Errors> {
+        self.apply_with_progress(trace, sampling_rate, |_| true)
+            .map(|result| result.expect("the filtering is never aborted"))
+    }
 
-        for x in self.iter_mut() {
-            *x = biquad.run(*x);
+    /// Same as [`FilterSpec::apply`], calling `progress` regularly with the completed fraction.
+    /// The filtering is aborted and `None` is returned if `progress` returns false.
+    pub fn apply_with_progress(
+        &self,
+        trace: &[f32],
+        sampling_rate: f32,
+        mut progress: impl FnMut(f32) -> bool,
+    ) -> Result<Option<Vec<f32>>, Errors> {
+        let coefficients = self.coefficients(sampling_rate)?;
+        let total = (coefficients.len() * trace.len()).max(1);
+        let mut done = 0;
+        let mut result = trace.to_vec();
+        for coeffs in coefficients {
+            let mut biquad = DirectForm1::<f32>::new(coeffs);
+            for chunk in result.chunks_mut(FILTER_CHUNK_SIZE) {
+                for x in chunk.iter_mut() {
+                    *x = biquad.run(*x);
+                }
+                done += chunk.len();
+                if !progress(done as f32 / total as f32) {
+                    return Ok(None);
+                }
+            }
         }
+        Ok(Some(result))
     }
 }
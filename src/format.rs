@@ -0,0 +1,173 @@
+//! Formatting of the numbers displayed by the viewer.
+//!
+//! The readouts of the toolbar, the tools and the dialogs share a single [`NumberFormat`], read
+//! from the `format` section of the configuration file, so they all use the same notation,
+//! precision and separators. For instance:
+//!
+//! ```toml
+//! [format]
+//! precision = 2
+//! notation = "scientific"
+//! durations = "time"
+//! decimal-separator = "comma"
+//! group-separator = "space"
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+/// SI prefixes from 10^-12 to 10^12, by power of 1000.
+const PREFIXES: [&str; 9] = ["p", "n", "µ", "m", "", "k", "M", "G", "T"];
+
+/// Index of the empty prefix in [`PREFIXES`].
+const UNIT_PREFIX: i32 = 4;
+
+/// Notation of the numbers which are not integers.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Notation {
+    /// SI prefix before the unit: `12.500 µs`.
+    Engineering,
+    /// Power of ten: `1.250e-5 s`.
+    Scientific,
+}
+
+/// How durations measured on a trace are displayed.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Durations {
+    /// Number of samples.
+    Samples,
+    /// Time, given by the sampling rate.
+    Time,
+    /// Time, followed by the number of samples.
+    Both,
+}
+
+/// Separator between the integer and the fractional parts of numbers.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DecimalSeparator {
+    Point,
+    Comma,
+}
+
+/// Separator of the groups of three digits of integers.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupSeparator {
+    /// Digits are not grouped.
+    None,
+    Comma,
+    Point,
+    Space,
+    Apostrophe,
+}
+
+impl GroupSeparator {
+    fn char(&self) -> Option<char> {
+        match self {
+            Self::None => None,
+            Self::Comma => Some(','),
+            Self::Point => Some('.'),
+            Self::Space => Some(' '),
+            Self::Apostrophe => Some('\''),
+        }
+    }
+}
+
+/// Formatting of the numbers displayed by the viewer.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NumberFormat {
+    /// Number of digits after the decimal separator.
+    pub precision: u8,
+    pub notation: Notation,
+    pub durations: Durations,
+    pub decimal_separator: DecimalSeparator,
+    pub group_separator: GroupSeparator,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            precision: 3,
+            notation: Notation::Engineering,
+            durations: Durations::Both,
+            decimal_separator: DecimalSeparator::Point,
+            group_separator: GroupSeparator::None,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Formats the quantity `x` of `unit`: `1.234 µs` in engineering notation, `1.234e-6 s` in
+    /// scientific notation.
+    pub fn quantity(&self, x: f64, unit: &str) -> String {
+        let precision = self.precision as usize;
+        let number = match self.notation {
+            Notation::Engineering => {
+                let prefix = if x == 0.0 || !x.is_finite() {
+                    UNIT_PREFIX
+                } else {
+                    (x.abs().log10() / 3.0)
+                        .floor()
+                        .clamp(-UNIT_PREFIX as f64, UNIT_PREFIX as f64) as i32
+                        + UNIT_PREFIX
+                };
+                let scaled = x / 1000f64.powi(prefix - UNIT_PREFIX);
+                format!("{:.*} {}", precision, scaled, PREFIXES[prefix as usize])
+            }
+            Notation::Scientific => format!("{:.*e} ", precision, x),
+        };
+        self.localize(number) + unit
+    }
+
+    /// Formats the number `n` of `unit`. Counts below 1000 are written in full, larger counts as
+    /// quantities: `12.346 kS`.
+    pub fn count(&self, n: usize, unit: &str) -> String {
+        if n < 1000 {
+            format!("{} {}", n, unit)
+        } else {
+            self.quantity(n as f64, unit)
+        }
+    }
+
+    /// Formats the integer `n` with all its digits, grouped by three if a group separator is set.
+    pub fn integer(&self, n: i64) -> String {
+        let digits = n.unsigned_abs().to_string();
+        let mut text = String::new();
+        if n < 0 {
+            text.push('-');
+        }
+        for (i, digit) in digits.chars().enumerate() {
+            if let Some(separator) = self.group_separator.char()
+                && i > 0
+                && (digits.len() - i).is_multiple_of(3)
+            {
+                text.push(separator);
+            }
+            text.push(digit);
+        }
+        text
+    }
+
+    /// Formats a duration of `samples` samples of a trace sampled at `sampling_rate` MS/s, on two
+    /// lines when both the time and the number of samples are displayed.
+    pub fn duration(&self, samples: f64, sampling_rate: f32) -> String {
+        let time = || self.quantity(samples / (sampling_rate as f64 * 1e6), "s");
+        let samples = || format!("{} samples", self.integer(samples.ceil() as i64));
+        match self.durations {
+            Durations::Samples => samples(),
+            Durations::Time => time(),
+            Durations::Both => format!("{}\n{}", time(), samples()),
+        }
+    }
+
+    /// Replaces the decimal point of `number` by the decimal separator.
+    fn localize(&self, number: String) -> String {
+        match self.decimal_separator {
+            DecimalSeparator::Point => number,
+            DecimalSeparator::Comma => number.replace('.', ","),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Navigation between the frames of segmented acquisitions.
+//!
+//! FastFrame WFM files, TRS trace sets and row-wise 2D Numpy arrays can hold thousands of frames,
+//! too many to be displayed in stacked viewers. Opened with `--browse`, such a file is displayed
+//! in a single viewer whose trace is replaced in place by the frame picked in its toolbar, keeping
+//! the camera and the color settings. Frames are read from the file on demand.
+
+use crate::loaders::FrameFile;
+use egui::{Color32, Slider, Ui};
+use std::sync::Arc;
+
+/// Toolbar controls selecting the displayed frame of a multi-frame file.
+pub struct FrameBrowser {
+    path: String,
+    file: Arc<dyn FrameFile>,
+    /// Index of the displayed frame.
+    index: usize,
+    /// Error of the last read.
+    error: Option<String>,
+}
+
+impl FrameBrowser {
+    /// Creates the browser of `file` loaded from `path`, displaying frame `index`.
+    pub fn new(path: String, file: Arc<dyn FrameFile>, index: usize) -> Self {
+        Self {
+            path,
+            file,
+            index,
+            error: None,
+        }
+    }
+
+    /// Index of the displayed frame.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Changes the index of the displayed frame, when the trace has been replaced by another
+    /// frame of the file.
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+        self.error = None;
+    }
+
+    /// Label of the displayed frame.
+    pub fn label(&self) -> String {
+        format!("{} [frame {}]", self.path, self.index)
+    }
+
+    /// Reads the frame at `index` and returns its samples.
+    fn read(&mut self, index: usize) -> Option<Vec<f32>> {
+        match self.file.read_frame(index) {
+            Ok(samples) => {
+                self.index = index;
+                self.error = None;
+                Some(samples)
+            }
+            Err(e) => {
+                self.error = Some(format!("Failed to read frame {}: {}", index, e));
+                None
+            }
+        }
+    }
+
+    /// Shows the navigation controls. Returns the samples of the frame the user navigated to, if
+    /// any.
+    pub fn ui(&mut self, ui: &mut Ui) -> Option<Vec<f32>> {
+        let count = self.file.frames();
+        let mut index = self.index;
+        if ui
+            .add_enabled(index > 0, egui::Button::new("◀"))
+            .on_hover_text("Previous frame")
+            .clicked()
+        {
+            index -= 1;
+        }
+        ui.add(Slider::new(&mut index, 0..=count.saturating_sub(1)).text(format!("/ {}", count)))
+            .on_hover_text("Displayed frame of the file");
+        if ui
+            .add_enabled(index + 1 < count, egui::Button::new("▶"))
+            .on_hover_text("Next frame")
+            .clicked()
+        {
+            index += 1;
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, "⚠").on_hover_text(error);
+        }
+        if index != self.index {
+            self.read(index)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Modal dialog comparing the averages of two groups of traces.
+//!
+//! Before launching a full attack, a difference of means between two groups of traces (for
+//! instance fixed and random inputs, or two values of a key bit) shows quickly whether and where
+//! the traces depend on the grouping.
+
+use crate::{
+    expression_dialog::ExpressionSource,
+    processing::{NewTrace, ProcessingJob},
+    trace::Trace,
+    transforms::mean_std,
+};
+use egui::{Button, Color32, Id, Modal, ProgressBar, ScrollArea};
+use std::{sync::Arc, time::Duration};
+
+/// Group of a trace.
+#[derive(Clone, Copy, PartialEq)]
+enum Group {
+    None,
+    A,
+    B,
+}
+
+/// Result of the group averages dialog update.
+pub enum GroupAverageDialogResult {
+    /// The user is still assigning the groups, or the averages are being calculated.
+    Pending,
+    /// The averages of both groups and their difference have been calculated.
+    Created(Vec<NewTrace>),
+    /// Dialog was cancelled.
+    Cancelled,
+}
+
+/// Dialog assigning the loaded traces to two groups, and calculating the average of each group
+/// and the difference of the averages.
+pub struct GroupAverageDialog {
+    id: Id,
+    /// Group of each trace.
+    groups: Vec<Group>,
+    /// Running calculation of the averages and their difference.
+    job: Option<ProcessingJob<Vec<NewTrace>>>,
+    error: Option<String>,
+}
+
+impl GroupAverageDialog {
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            groups: Vec::new(),
+            job: None,
+            error: None,
+        }
+    }
+
+    /// Shows the dialog and returns the user decision.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        sources: &[ExpressionSource],
+    ) -> GroupAverageDialogResult {
+        if let Some(result) = self.job.as_ref().and_then(|job| job.try_take()) {
+            self.job = None;
+            match result {
+                Ok(traces) => return GroupAverageDialogResult::Created(traces),
+                Err(e) => self.error = Some(e),
+            }
+        }
+        self.groups.resize(sources.len(), Group::None);
+
+        let mut result = GroupAverageDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("Group averages");
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Alternate")
+                    .on_hover_text("Assign the traces to A and B alternately")
+                    .clicked()
+                {
+                    for (i, group) in self.groups.iter_mut().enumerate() {
+                        *group = if i % 2 == 0 { Group::A } else { Group::B };
+                    }
+                }
+                if ui.button("Clear").clicked() {
+                    self.groups.fill(Group::None);
+                }
+            });
+            ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("group_average_grid")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (group, source) in self.groups.iter_mut().zip(sources) {
+                            ui.horizontal(|ui| {
+                                ui.selectable_value(group, Group::None, "–");
+                                ui.selectable_value(group, Group::A, "A");
+                                ui.selectable_value(group, Group::B, "B");
+                            });
+                            ui.label(&source.label);
+                            ui.end_row();
+                        }
+                    });
+            });
+            let count = |g: Group| self.groups.iter().filter(|&&x| x == g).count();
+            let (a, b) = (count(Group::A), count(Group::B));
+            ui.label(format!("Group A: {} traces, group B: {} traces", a, b));
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if let Some(job) = &self.job {
+                    ui.add(
+                        ProgressBar::new(job.progress())
+                            .desired_width(200.0)
+                            .show_percentage()
+                            .animate(true),
+                    );
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                    if ui.button("Cancel").clicked() {
+                        // Dropping the job cancels it.
+                        self.job = None;
+                    }
+                    return;
+                }
+                if ui
+                    .add_enabled(a > 0 && b > 0, Button::new("Compare"))
+                    .on_disabled_hover_text("Each group needs at least one trace")
+                    .clicked()
+                {
+                    self.error = None;
+                    self.start(ctx, sources);
+                }
+                if ui.button("Cancel").clicked() {
+                    result = GroupAverageDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() && self.job.is_none() {
+            GroupAverageDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+
+    /// Starts the calculation of the group averages and their difference. The traces of both
+    /// groups must have the same sampling rate.
+    fn start(&mut self, ctx: &egui::Context, sources: &[ExpressionSource]) {
+        let members = |g: Group| -> Vec<&ExpressionSource> {
+            self.groups
+                .iter()
+                .zip(sources)
+                .filter(|(x, _)| **x == g)
+                .map(|(_, s)| s)
+                .collect()
+        };
+        let (a, b) = (members(Group::A), members(Group::B));
+        let sampling_rate = a[0].sampling_rate;
+        if a.iter().chain(&b).any(|s| s.sampling_rate != sampling_rate) {
+            self.error = Some("The traces of the groups have different sampling rates".to_string());
+            return;
+        }
+        let traces = |group: Vec<&ExpressionSource>| -> Vec<Arc<Trace>> {
+            group.iter().map(|s| s.trace.clone()).collect()
+        };
+        let (a, b) = (traces(a), traces(b));
+        self.job = Some(ProcessingJob::spawn(ctx, move |progress| {
+            let average = |traces: &[Arc<Trace>]| {
+                let traces: Vec<&[f32]> = traces.iter().map(|t| &t[..]).collect();
+                mean_std(&traces).0
+            };
+            let mean_a = average(&a);
+            progress.set(0.5);
+            if progress.is_cancelled() {
+                return None;
+            }
+            let mean_b = average(&b);
+            let difference = mean_a.iter().zip(&mean_b).map(|(x, y)| x - y).collect();
+            Some(vec![
+                NewTrace {
+                    label: format!("[group A mean of {}]", a.len()),
+                    trace: mean_a,
+                    sampling_rate,
+                },
+                NewTrace {
+                    label: format!("[group B mean of {}]", b.len()),
+                    trace: mean_b,
+                    sampling_rate,
+                },
+                NewTrace {
+                    label: "[group A mean - group B mean]".to_string(),
+                    trace: difference,
+                    sampling_rate,
+                },
+            ])
+        }));
+    }
+}
@@ -1,46 +1,218 @@
 //! Command-line arguments and configuration.
 
-use crate::filtering::Filter;
-use crate::loaders::{NpyLayout, TraceFormat};
-use clap::Parser;
-use std::collections::HashSet;
+#[cfg(not(target_arch = "wasm32"))]
+use super::paths::{expand_paths, split_file_options};
+use crate::loaders::{NpyLayout, SampleType, TraceFormat};
+use crate::{
+    config::{Config, Defaults, KeyBindings},
+    filtering::{Filter, FilterSpec},
+    format::NumberFormat,
+    logging,
+    processing::ProcessingStep,
+    tiling::{ColorScale, GradientKind},
+    tiling_policy::{MAX_TILE_WIDTH, TILE_WIDTH, TilingMode, TilingPolicy},
+};
+use clap::{
+    ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    error::ErrorKind, parser::ValueSource,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::{Range, RangeFrom, RangeInclusive};
+use std::path::PathBuf;
 use std::thread::available_parallelism;
 
 /// TurboPlot is a blazingly fast waveform renderer made for visualizing huge traces.
 ///
-/// Arguments for loading and displaying traces.
-/// These can be provided via CLI or modified through the file manager UI.
-#[derive(Parser, Clone)]
+/// Without command, the traces are opened in the viewer as with `turboplot view`.
+#[derive(Parser)]
 #[command(about, version)]
+pub struct Cli {
+    /// Configuration file, read instead of `turboplot/config.toml` in the user configuration
+    /// directory. Its `defaults` section replaces the default values of the options which are not
+    /// given on the command line.
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Print more diagnostic messages, such as the rendering time of each tile. Repeat for even
+    /// more messages. Ignored when `RUST_LOG` is set.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Print fewer diagnostic messages: only warnings, then only errors, then nothing. Ignored
+    /// when `RUST_LOG` is set.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub quiet: u8,
+
+    #[command(subcommand)]
+    pub command: Option<Mode>,
+
+    #[command(flatten)]
+    pub view: Args,
+}
+
+/// Commands of TurboPlot. Only `view` opens the viewer, the others run in the terminal.
+#[derive(Subcommand, Clone)]
+pub enum Mode {
+    /// Open the traces in the viewer. This is the default command.
+    View(Box<Args>),
+    /// Render the first selected trace of a file as a density image, without opening the viewer.
+    Render(RenderArgs),
+    /// Convert the selected traces of a file, after processing, to a Numpy or CSV file.
+    Convert(ConvertArgs),
+    /// Print the metadata of trace files: format, number of traces and samples, sampling rate...
+    /// Only the headers are read when the format allows it.
+    Info(InfoArgs),
+    /// Measure the rendering throughput of the GPU and CPU renderers on a trace.
+    Bench(BenchArgs),
+    /// Render a small density image of each file, to triage many captures at once. The loading
+    /// and processing options (format, frame selection, filters...) apply to each file.
+    Thumbnails(ThumbnailArgs),
+    /// Serve the density images of the first selected trace of a file over HTTP, as tiles at
+    /// several zoom levels, for web frontends and notebooks browsing a trace remotely.
+    Serve(ServeArgs),
+}
+
+/// Arguments for loading and displaying traces in the viewer.
+/// These can be provided via CLI or modified through the file manager UI.
+#[derive(clap::Args, Clone)]
 pub struct Args {
-    /// Data file paths.
+    /// Data file paths. `-` reads the samples piped to the standard input, as CSV values unless
+    /// their format is recognized or given with `--format`. The trace files of directories are
+    /// opened, and the glob patterns with `*` and `?` are expanded, as in `"captures/*.npy"`.
+    /// Load options appended to a path replace the shared ones for its files, as in
+    /// `capture.csv:column=2:skip=1`. The options are format, column, skip, frames, dataset,
+    /// dtype, layout and rate.
     #[arg(required = false, num_args = 0..)]
     pub paths: Vec<String>,
 
-    /// Trace sampling rate in MS/s. Default to 125MS/s
-    #[arg(long, short, default_value_t = 125.0f32)]
-    pub sampling_rate: f32,
+    /// Number of files opened at startup, when more files are given, for instance with a
+    /// directory. The others are opened N at a time with "Open next files" in the File menu, so
+    /// folders with hundreds of traces open at once.
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    pub load_first: usize,
 
-    /// Specify a digital filter.
-    #[arg(long, requires("cutoff_freq"), value_enum)]
-    pub filter: Option<Filter>,
+    /// Files given beyond the `--load-first` ones, not opened yet.
+    #[arg(skip)]
+    pub pending_paths: Vec<String>,
 
-    /// Cutoff frequency in kHz if a filter has been specified.
-    #[arg(long, requires("filter"), default_value_t = 1000.0f32)]
-    pub cutoff_freq: f32,
+    /// Load settings of the pending files, when their paths have load options.
+    #[arg(skip)]
+    pub pending_files: Vec<FileSettings>,
 
-    /// Trace file format. If not specified, TurboPlot will guess from file extension.
-    #[arg(long, short)]
-    pub format: Option<TraceFormat>,
+    #[command(flatten)]
+    pub trace: TraceArgs,
 
-    /// When loading a CSV file, how many lines must be skipped before reading the values.
-    #[arg(long, default_value_t = 0)]
-    pub skip_lines: usize,
+    #[command(flatten)]
+    pub display: DisplayArgs,
 
-    /// When loading a CSV file, this is the index of the column storing the trace values. Index
-    /// starts at zero.
-    #[arg(long, default_value_t = 0)]
-    pub column: usize,
+    /// Keyboard shortcuts of the viewer, read from the configuration file.
+    #[arg(skip)]
+    pub keys: KeyBindings,
+
+    /// Formatting of the displayed numbers, read from the configuration file.
+    #[arg(skip)]
+    pub format: NumberFormat,
+
+    /// Paint the 1D array in FILE over each view, with its own vertical scale and spread over the
+    /// whole trace: for instance a CPA correlation or a TVLA t-statistic per sample. Can be
+    /// repeated.
+    #[arg(long, value_name = "FILE")]
+    pub overlay: Vec<String>,
+
+    /// Paint the signal-to-noise ratio of the frames of multi-trace files over each view, with
+    /// the frames grouped by the labels of SOURCE: a 1D array with one label per frame, or
+    /// `data:N` to use the byte at offset N of the data of each TRS trace.
+    #[arg(long, value_name = "SOURCE")]
+    pub snr_labels: Option<String>,
+
+    /// Mark the events listed in FILE as vertical lines across the views. The file has one event
+    /// per line: a sample index, optionally followed by a label.
+    #[arg(long, value_name = "FILE")]
+    pub events: Option<String>,
+
+    /// Mark the edges of the trigger channel in FILE as vertical lines across the views. The
+    /// channel is loaded like a trace, and must have the sampling rate of the traces.
+    #[arg(long, value_name = "FILE")]
+    pub trigger: Option<String>,
+
+    /// Download the waveform displayed by a LeCroy or Tektronix scope and open it as a trace, like
+    /// a file. URI is `scope://HOST[/CHANNEL]` to connect with VXI-11, or
+    /// `scope://HOST:PORT[/CHANNEL]` for the raw SCPI socket of LXI instruments (port 5025 on
+    /// most scopes), e.g. `scope://192.168.1.10/C2`. The channel is C1 on LeCroy scopes and CH1
+    /// on the others by default. Can be repeated to download several channels.
+    #[arg(long, value_name = "URI", conflicts_with_all = ["live", "watch"])]
+    pub connect: Vec<String>,
+
+    /// Display traces streamed by an acquisition loop as they arrive, instead of loading files.
+    /// SOURCE is `HOST:PORT` to connect to a TCP server, or the path of a serial device or a
+    /// named pipe. Each trace is sent as a little-endian u32 number of samples followed by the
+    /// samples as little-endian f32.
+    ///
+    /// Waveforms can also be captured from a scope with `scpi://HOST[:PORT][/CHANNEL]` (raw SCPI
+    /// socket of LXI instruments, port 5025 by default) or `vxi11://HOST[/CHANNEL]`, e.g.
+    /// `scpi://192.168.1.10/CH2`. The channel is CH1 by default.
+    #[arg(long, value_name = "SOURCE", conflicts_with = "paths")]
+    pub live: Option<String>,
+
+    /// Listen on URI, `tcp://HOST:PORT` or `udp://HOST:PORT`, for a stream of samples sent by an
+    /// acquisition rig, and append them to a growing trace displayed as a strip chart. The
+    /// samples are sent as little-endian f32, without header. TCP connections are accepted one
+    /// after the other, and UDP datagrams must hold whole samples.
+    #[arg(long, value_name = "URI", conflicts_with_all = ["paths", "live", "watch", "connect"])]
+    pub listen: Option<String>,
+
+    /// Display the samples piped to the standard input (`-`) as they arrive, as a growing strip
+    /// chart, instead of loading them once the input is closed. Only raw and CSV samples can be
+    /// streamed.
+    #[arg(long, requires = "paths", conflicts_with_all = ["live", "listen", "watch", "connect"])]
+    pub stream: bool,
+
+    /// Number of last received traces displayed one after the other by `--live`.
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "live")]
+    pub live_history: usize,
+
+    /// Number of last received samples kept by the strip charts of `--listen` and `--stream`. The
+    /// oldest samples are dropped by blocks of 65536 samples.
+    #[arg(long, value_name = "SAMPLES", default_value_t = 1 << 26)]
+    pub strip_length: usize,
+
+    /// Minimum time in seconds between two waveform captures from a scope with `--live`.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5, requires = "live")]
+    pub live_interval: f32,
+
+    /// Open the trace files created in DIR as they appear, for instance by an acquisition rig. The
+    /// files present when TurboPlot starts are ignored.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["paths", "live"])]
+    pub watch: Option<String>,
+
+    /// Replace the trace of the last view with each new file of `--watch`, keeping its camera and
+    /// processing, instead of adding a view per file.
+    #[arg(long, requires = "watch")]
+    pub watch_replace: bool,
+
+    /// Load the opened files again when they change on disk, keeping the camera and processing of
+    /// their views, for iterating over captures written to the same files. Each view can also be
+    /// reloaded from its toolbar.
+    #[arg(long, conflicts_with = "watch")]
+    pub watch_files: bool,
+
+    /// Accept JSON-RPC requests driving the viewer, from acquisition scripts or test benches.
+    /// ADDRESS is `127.0.0.1:PORT` for a localhost TCP port, or the path of a unix socket. Without
+    /// files, TurboPlot waits for a file to be loaded remotely.
+    #[arg(long, value_name = "ADDRESS")]
+    pub remote: Option<String>,
+
+    /// Allow `--remote` to listen on a TCP address which is not a loopback one. The requests are
+    /// not authenticated and can read and write any file the user can access: only use it on a
+    /// trusted network.
+    #[arg(long, requires = "remote")]
+    pub remote_any_host: bool,
+
+    /// Send the camera changes to another TurboPlot instance started with `--remote ADDRESS`, so
+    /// it follows the navigation done in this one. Both instances follow each other when each
+    /// one is given the `--remote` address of the other.
+    #[arg(long, value_name = "ADDRESS")]
+    pub sync_peer: Option<String>,
 
     /// Number of GPU rendering threads to spawn.
     #[arg(long, short, default_value_t = 1)]
@@ -51,13 +223,80 @@ pub struct Args {
     #[arg(long, short)]
     pub cpu: Option<usize>,
 
+    /// Maximum number of rendered tiles kept in memory after leaving the view, so zooming back
+    /// does not render them again. Zero disables the cache.
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    pub tile_cache: usize,
+
+    /// How the width of the rendered tiles is chosen. `fixed` renders tiles of `--tile-width`
+    /// pixel columns. `adaptive` renders wider tiles when zoomed out, up to `--max-tile-width`
+    /// columns, and tiles of `--tile-width` columns around the position zoomed on, which are
+    /// rendered first. Wider tiles need fewer GPU calls, but more GPU memory per call.
+    #[arg(long, value_enum, default_value_t = TilingMode::Adaptive)]
+    pub tiling: TilingMode,
+
+    /// Width of the rendered tiles in pixel columns, the minimum width in `adaptive` tiling.
+    #[arg(long, value_name = "COLUMNS", default_value_t = TILE_WIDTH,
+        value_parser = clap::value_parser!(u32).range(TILE_WIDTHS))]
+    pub tile_width: u32,
+
+    /// Maximum width of the rendered tiles in pixel columns, in `adaptive` tiling.
+    #[arg(long, value_name = "COLUMNS", default_value_t = MAX_TILE_WIDTH,
+        value_parser = clap::value_parser!(u32).range(TILE_WIDTHS))]
+    pub max_tile_width: u32,
+
+    /// For files containing multiple traces, open a single view per file with a frame browser,
+    /// reading the frames on demand, instead of one view per frame. The first selected frame is
+    /// displayed first. Supported by WFM, TRS and row-wise 2D Numpy files.
+    #[arg(long)]
+    pub browse: bool,
+
+    /// For files containing multiple traces, add a view showing the sample-wise mean of the
+    /// selected traces. The mean is calculated on all the selected traces, even if only some of
+    /// them can be displayed.
+    #[arg(long)]
+    pub mean: bool,
+
+    /// For files containing multiple traces, add a view showing the sample-wise standard deviation
+    /// of the selected traces.
+    #[arg(long)]
+    pub std: bool,
+
+    /// Rhai script automating the viewer: loading files, processing traces, moving the camera and
+    /// exporting images. Useful for reproducible figure generation.
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<String>,
+}
+
+/// Options for loading and processing traces, shared by the commands.
+#[derive(clap::Args, Clone)]
+pub struct TraceArgs {
+    /// Trace sampling rate in MS/s. Default to 125MS/s. WAV, Tektronix WFM and ISF, Keysight BIN
+    /// and sigrok files are displayed at the rate they record, VCD files at the rate of their time
+    /// steps, and the MATLAB files exported by PicoScope at the rate of their `Tinterval` array.
+    #[arg(long, short, default_value_t = 125.0f32, value_parser = parse_rate)]
+    pub sampling_rate: f32,
+
+    /// Trace file format. If not specified, TurboPlot will guess from file extension and contents.
+    #[arg(long, short)]
+    pub format: Option<TraceFormat>,
+
+    /// When loading a CSV file, how many lines must be skipped before reading the values.
+    #[arg(long, default_value_t = 0)]
+    pub skip_lines: usize,
+
+    /// When loading a CSV, Parquet or Feather file, this is the index of the column storing the
+    /// trace values. Index starts at zero.
+    #[arg(long, default_value_t = 0)]
+    pub column: usize,
+
     /// For files that contain multiple traces, select which traces to load.
     /// Format: comma-separated indices or ranges, e.g. "1-3,6,7-8,12".
     /// If not specified, all frames are loaded.
-    #[arg(long)]
+    #[arg(long, value_parser = parse_frames)]
     pub frames: Option<String>,
 
-    /// For 2D Numpy arrays, how to interpret the shape.
+    /// For 2D Numpy arrays and MATLAB matrices, how to interpret the shape.
     ///
     /// - `auto` (default): arrays with few columns and many rows are treated
     ///   as column-wise (`--column` picks the signal column, e.g. oscilloscope
@@ -66,46 +305,846 @@ pub struct Args {
     /// - `rows`: shape `(n_traces, pts)`, one trace per row.
     #[arg(long, value_enum, default_value_t = NpyLayout::Auto)]
     pub npy_layout: NpyLayout,
+
+    /// When loading a MATLAB file, name of the array to load. By default, all the numeric arrays
+    /// holding more than one value are loaded. When loading a Parquet or Feather file, name of the
+    /// column to load instead of the one at index `--column`.
+    #[arg(long)]
+    pub dataset: Option<String>,
+
+    /// When loading a raw file (`--format raw`), type of its little-endian samples.
+    #[arg(long, value_enum, default_value_t = SampleType::F32)]
+    pub dtype: SampleType,
+
+    /// Specify a digital filter.
+    #[arg(long, requires("cutoff_freq"), value_enum)]
+    pub filter: Option<Filter>,
+
+    /// Cutoff frequency in kHz if a filter has been specified. For band-pass and notch filters,
+    /// this is the center frequency.
+    #[arg(
+        long,
+        alias("center-freq"),
+        requires("filter"),
+        default_value_t = 1000.0f32
+    )]
+    pub cutoff_freq: f32,
+
+    /// Quality factor of the filter. For band-pass and notch filters, the bandwidth is the center
+    /// frequency divided by Q: a high Q gives a narrow notch. Default to 1/√2 (Butterworth).
+    #[arg(long, requires("filter"), default_value_t = FilterSpec::DEFAULT_Q)]
+    pub q: f32,
+
+    /// Maximum number of notches of a comb filter, at the fundamental frequency and its
+    /// harmonics. Each notch has the same bandwidth, the fundamental frequency divided by Q.
+    #[arg(long, requires("filter"), default_value_t = FilterSpec::DEFAULT_NOTCHES)]
+    pub notches: usize,
+
+    /// Replace samples by their absolute value after loading, after the filter if any. Combined
+    /// with `--smooth`, turns bipolar EM traces into power-like envelopes.
+    #[arg(long)]
+    pub abs: bool,
+
+    /// Smooth traces with a centered moving average over the given number of samples, after the
+    /// filter if any.
+    #[arg(long, value_name = "N")]
+    pub smooth: Option<usize>,
+
+    /// Decimate traces by the given factor after loading, with an anti-aliasing low-pass filter.
+    /// The factor can be fractional.
+    #[arg(long, value_name = "FACTOR", conflicts_with = "resample_rate",
+        value_parser = parse_decimation_factor)]
+    pub decimate: Option<f32>,
+
+    /// Resample traces after loading to the given sampling rate in MS/s, which must be lower than
+    /// the sampling rate. An anti-aliasing low-pass filter is applied.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub resample_rate: Option<f32>,
+
+    /// Upsample traces after loading by the given integer factor, using band-limited
+    /// interpolation. Useful to compare traces captured at different rates on a common time base.
+    #[arg(long, value_name = "FACTOR", conflicts_with_all = ["decimate", "resample_rate"])]
+    pub upsample: Option<usize>,
+
+    /// Apply the processing steps of a preset saved in the configuration file, after the steps
+    /// of the other processing options. Presets are saved from the "Processing" menu.
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Steps of the preset given with `--preset`, read from the configuration file.
+    #[arg(skip)]
+    pub preset_steps: Vec<ProcessingStep>,
+
+    /// Load settings of the files picked in the file manager, replacing the options above for
+    /// the file at the same index of the paths. Empty when all the files share the options.
+    #[arg(skip)]
+    pub files: Vec<FileSettings>,
+}
+
+/// Load settings of a single file, which can differ between the files picked in the file
+/// manager.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileSettings {
+    pub sampling_rate: f32,
+    pub format: Option<TraceFormat>,
+    pub npy_layout: NpyLayout,
+    pub skip_lines: usize,
+    pub column: usize,
+    pub frames: Option<String>,
+    #[serde(default)]
+    pub dataset: Option<String>,
+    #[serde(default)]
+    pub dtype: SampleType,
+}
+
+impl FileSettings {
+    /// Returns the load settings of `args`.
+    pub fn new(args: &TraceArgs) -> Self {
+        Self {
+            sampling_rate: args.sampling_rate,
+            format: args.format,
+            npy_layout: args.npy_layout,
+            skip_lines: args.skip_lines,
+            column: args.column,
+            frames: args.frames.clone(),
+            dataset: args.dataset.clone(),
+            dtype: args.dtype,
+        }
+    }
+
+    /// Names of the load options which can be appended to a path on the command line, as in
+    /// `capture.csv:column=2:skip=1`.
+    pub const OPTIONS: [&str; 12] = [
+        "format",
+        "column",
+        "skip",
+        "skip-lines",
+        "frames",
+        "dataset",
+        "dtype",
+        "layout",
+        "npy-layout",
+        "rate",
+        "sampling-rate",
+        "sr",
+    ];
+
+    /// Sets the load option `key`, one of [`FileSettings::OPTIONS`], to `value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value '{}' for the '{}' option", value, key);
+        match key {
+            "format" => {
+                self.format = Some(TraceFormat::from_str(value, true).map_err(|_| invalid())?)
+            }
+            "column" => self.column = value.parse().map_err(|_| invalid())?,
+            "skip" | "skip-lines" => self.skip_lines = value.parse().map_err(|_| invalid())?,
+            "frames" => {
+                self.frames =
+                    Some(parse_frames(value).map_err(|e| format!("{}: {}", invalid(), e))?)
+            }
+            "dataset" => self.dataset = Some(value.to_string()),
+            "dtype" => self.dtype = SampleType::from_str(value, true).map_err(|_| invalid())?,
+            "layout" | "npy-layout" => {
+                self.npy_layout = NpyLayout::from_str(value, true).map_err(|_| invalid())?
+            }
+            "rate" | "sampling-rate" | "sr" => {
+                self.sampling_rate = value
+                    .parse()
+                    .ok()
+                    .filter(|rate: &f32| *rate > 0.0)
+                    .ok_or_else(invalid)?
+            }
+            _ => return Err(format!("unknown load option '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Replaces the load settings of `args` by these ones.
+    pub fn apply(&self, args: &mut TraceArgs) {
+        args.sampling_rate = self.sampling_rate;
+        args.format = self.format;
+        args.npy_layout = self.npy_layout;
+        args.skip_lines = self.skip_lines;
+        args.column = self.column;
+        args.frames = self.frames.clone();
+        args.dataset = self.dataset.clone();
+        args.dtype = self.dtype;
+    }
+}
+
+/// Color scale options of the density rendering.
+#[derive(clap::Args, Clone)]
+pub struct DisplayArgs {
+    /// Color gradient of the density rendering.
+    #[arg(long, value_enum, default_value_t = GradientKind::Rainbow)]
+    pub gradient: GradientKind,
+
+    /// Power applied to the density before coloring. Lower values reveal rare paths.
+    #[arg(long, default_value_t = 1.0)]
+    pub power: f32,
+
+    /// Opacity of the density rendering.
+    #[arg(long, default_value_t = 10.0)]
+    pub opacity: f32,
+
+    /// Set when a display option is given on the command line or in the configuration file, so
+    /// the last used color scale is not restored.
+    #[arg(skip)]
+    pub given: bool,
+}
+
+/// Range of samples of the traces used by a command, after processing.
+#[derive(clap::Args, Clone)]
+pub struct RangeArgs {
+    /// Index of the first sample, after processing. Default to the start of the trace.
+    #[arg(long, value_name = "INDEX")]
+    pub start: Option<usize>,
+
+    /// Index of the sample after the last one, after processing. Default to the end of the trace.
+    #[arg(long, value_name = "INDEX")]
+    pub end: Option<usize>,
+}
+
+/// Arguments of the render command.
+#[derive(clap::Args, Clone)]
+pub struct RenderArgs {
+    /// File to render.
+    pub path: String,
+
+    /// Path of the PNG image to write.
+    #[arg(long, short)]
+    pub out: PathBuf,
+
+    /// Image width in pixels.
+    #[arg(long, default_value_t = 2048)]
+    pub width: u32,
+
+    /// Image height in pixels.
+    #[arg(long, default_value_t = 512)]
+    pub height: u32,
+
+    #[command(flatten)]
+    pub range: RangeArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+/// Arguments of the convert command.
+#[derive(clap::Args, Clone)]
+pub struct ConvertArgs {
+    /// File to convert.
+    pub path: String,
+
+    /// Path of the file to write, with a `.npy` or `.csv` extension. A single trace is written as
+    /// a 1D Numpy array or an `index,value` CSV file, multiple traces as a 2D Numpy array with one
+    /// trace per row or a CSV file with one column per trace. Traces shorter than `--end` are
+    /// cropped to their length.
+    #[arg(long, short)]
+    pub out: PathBuf,
+
+    #[command(flatten)]
+    pub range: RangeArgs,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+}
+
+/// Arguments of the info command.
+#[derive(clap::Args, Clone)]
+pub struct InfoArgs {
+    /// Files to inspect.
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+
+    /// Trace file format. If not specified, TurboPlot will guess from file extension and contents.
+    #[arg(long, short)]
+    pub format: Option<TraceFormat>,
+
+    /// Type of the little-endian samples of raw files (`--format raw`).
+    #[arg(long, value_enum, default_value_t = SampleType::F32)]
+    pub dtype: SampleType,
+}
+
+/// Arguments of the bench command.
+#[derive(clap::Args, Clone)]
+pub struct BenchArgs {
+    /// File whose first selected trace is rendered. If not given, a random trace is generated.
+    pub path: Option<String>,
+
+    /// Number of samples of the generated trace.
+    #[arg(long, default_value_t = 50_000_000, conflicts_with = "path")]
+    pub samples: usize,
+
+    /// Width in pixels of the view of the whole trace. Its tiles are rendered like in the viewer.
+    #[arg(long, default_value_t = 4096)]
+    pub width: u32,
+
+    /// Height in pixels of the view.
+    #[arg(long, default_value_t = 512)]
+    pub height: u32,
+
+    /// Number of GPU rendering threads. Zero skips the GPU renderer.
+    #[arg(long, short, default_value_t = 1)]
+    pub gpu: usize,
+
+    /// Number of CPU rendering threads. If not specified, as many threads as the CPU can run
+    /// simultaneously. Zero skips the CPU renderer.
+    #[arg(long, short)]
+    pub cpu: Option<usize>,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+}
+
+/// Arguments of the thumbnails batch mode.
+#[derive(clap::Args, Clone)]
+pub struct ThumbnailArgs {
+    /// Files or directories to render. Wildcards `*` and `?` in quoted paths are expanded, e.g.
+    /// "captures/*.npy".
+    #[arg(required = true)]
+    pub patterns: Vec<String>,
+
+    /// Directory where the images are written, as `<file path>.png` with the path relative to
+    /// the deepest directory containing all the files. Created if needed.
+    #[arg(long, short)]
+    pub out: PathBuf,
+
+    /// Image width in pixels.
+    #[arg(long, default_value_t = 512)]
+    pub width: u32,
+
+    /// Image height in pixels.
+    #[arg(long, default_value_t = 128)]
+    pub height: u32,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+/// Arguments of the serve command.
+#[derive(clap::Args, Clone)]
+pub struct ServeArgs {
+    /// File to serve.
+    pub path: String,
+
+    /// Address of the HTTP server, `HOST:PORT`. Use `0.0.0.0:PORT` to accept connections from
+    /// other machines.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub address: String,
+
+    /// Tile width in pixels.
+    #[arg(long, default_value_t = 256)]
+    pub tile_width: u32,
+
+    /// Tile height in pixels.
+    #[arg(long, default_value_t = 256)]
+    pub tile_height: u32,
+
+    /// Maximum number of encoded tiles kept in memory, so the tiles requested again are not
+    /// rendered again. Zero disables the cache.
+    #[arg(long, value_name = "N", default_value_t = 1024)]
+    pub tile_cache: usize,
+
+    #[command(flatten)]
+    pub trace: TraceArgs,
+
+    #[command(flatten)]
+    pub display: DisplayArgs,
+}
+
+/// Accepted widths of the rendered tiles, in pixel columns.
+const TILE_WIDTHS: RangeFrom<i64> = 1..;
+
+/// Options accepted both before and after a command.
+const GLOBAL_OPTIONS: [&str; 3] = ["config", "verbose", "quiet"];
+
+impl Cli {
+    /// Parses the command line and reads the configuration file, given with `--config` or from
+    /// its default location. Returns the command to run, `view` if none is given.
+    ///
+    /// The defaults of the configuration file replace the default values of the options which
+    /// are not given on the command line, and the preset given with `--preset` is read. The
+    /// directories and glob patterns given as files are expanded.
+    pub fn parse_with_config() -> Result<Mode, String> {
+        let matches = Self::command().get_matches();
+        let cli = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Some(path) = &cli.config {
+            Config::set_path(path.clone());
+        }
+        logging::init(cli.verbose, cli.quiet);
+        // The options of the viewer are accepted before a command, but they would be ignored.
+        if let Some((name, _)) = matches.subcommand()
+            && let Some(id) = matches.ids().map(|id| id.as_str()).find(|id| {
+                !GLOBAL_OPTIONS.contains(id)
+                    && matches.value_source(id) == Some(ValueSource::CommandLine)
+            })
+        {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the option '--{}' must be given after the '{}' command",
+                        id.replace('_', "-"),
+                        name
+                    ),
+                )
+                .exit();
+        }
+        let config = Config::load()?;
+        let mut mode = match cli.command {
+            Some(mode) => mode,
+            None => Mode::View(Box::new(cli.view)),
+        };
+        let matches = matches.subcommand().map_or(&matches, |(_, m)| m);
+        mode.apply_config(&config, matches)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = mode.read_paths() {
+            Self::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+        Ok(mode)
+    }
+}
+
+impl Mode {
+    /// Reads the load options appended to the paths of the command, and expands the directories
+    /// and glob patterns of the commands opening several files.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_paths(&mut self) -> Result<(), String> {
+        match self {
+            Mode::View(args) => {
+                args.read_paths()?;
+                args.defer_paths();
+            }
+            Mode::Render(args) => args.path = args.trace.read_file_options(&args.path)?,
+            Mode::Convert(args) => args.path = args.trace.read_file_options(&args.path)?,
+            Mode::Info(args) => args.paths = expand_paths(std::mem::take(&mut args.paths)),
+            Mode::Thumbnails(args) => {
+                args.patterns = expand_paths(std::mem::take(&mut args.patterns))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Applies the configuration to the arguments of the command. `matches` are the arguments
+    /// of the command, used to tell which options have been given on the command line.
+    fn apply_config(&mut self, config: &Config, matches: &ArgMatches) -> Result<(), String> {
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let defaults = &config.defaults;
+        check_defaults(defaults)?;
+        let trace = match self {
+            Mode::View(args) => {
+                args.keys = config.keys;
+                args.format = config.format;
+                args.display.apply_defaults(defaults, given);
+                set_default(&mut args.gpu, defaults.gpu, given("gpu"));
+                args.cpu = args.cpu.or(defaults.cpu);
+                set_default(
+                    &mut args.tile_cache,
+                    defaults.tile_cache,
+                    given("tile_cache"),
+                );
+                set_default(&mut args.tiling, defaults.tiling, given("tiling"));
+                set_default(
+                    &mut args.tile_width,
+                    defaults.tile_width,
+                    given("tile_width"),
+                );
+                set_default(
+                    &mut args.max_tile_width,
+                    defaults.max_tile_width,
+                    given("max_tile_width"),
+                );
+                &mut args.trace
+            }
+            Mode::Render(args) => {
+                args.display.apply_defaults(defaults, given);
+                &mut args.trace
+            }
+            Mode::Convert(args) => &mut args.trace,
+            Mode::Info(_) => return Ok(()),
+            Mode::Bench(args) => {
+                set_default(&mut args.gpu, defaults.gpu, given("gpu"));
+                args.cpu = args.cpu.or(defaults.cpu);
+                &mut args.trace
+            }
+            Mode::Thumbnails(args) => {
+                args.display.apply_defaults(defaults, given);
+                &mut args.trace
+            }
+            Mode::Serve(args) => {
+                args.display.apply_defaults(defaults, given);
+                &mut args.trace
+            }
+        };
+        set_default(
+            &mut trace.sampling_rate,
+            defaults.sampling_rate,
+            given("sampling_rate"),
+        );
+        if let Some(name) = &trace.preset {
+            trace.preset_steps = config.preset(name)?.to_vec();
+            for step in &trace.preset_steps {
+                step.check_parameters()
+                    .map_err(|e| format!("Preset '{}': {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the configured defaults as the options they replace are checked on the command line.
+fn check_defaults(defaults: &Defaults) -> Result<(), String> {
+    let invalid = |key: &str, e: &str| {
+        let path = Config::path().map(|path| path.display().to_string());
+        format!(
+            "{}: defaults.{} {}",
+            path.as_deref().unwrap_or("configuration"),
+            key,
+            e
+        )
+    };
+    if let Some(rate) = defaults.sampling_rate {
+        check_rate(rate).map_err(|e| invalid("sampling-rate", &e))?;
+    }
+    for (key, width) in [
+        ("tile-width", defaults.tile_width),
+        ("max-tile-width", defaults.max_tile_width),
+    ] {
+        if let Some(width) = width
+            && !TILE_WIDTHS.contains(&(width as i64))
+        {
+            return Err(invalid(
+                key,
+                &format!("must be at least {}", TILE_WIDTHS.start),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `value` by the configured `default`, if any, unless the option has been `given` on
+/// the command line.
+fn set_default<T>(value: &mut T, default: Option<T>, given: bool) {
+    if let Some(default) = default.filter(|_| !given) {
+        *value = default;
+    }
+}
+
+/// Returns the number of CPU threads to use, resolving the default if not specified.
+fn cpu_threads(cpu: Option<usize>) -> usize {
+    cpu.unwrap_or_else(|| {
+        available_parallelism()
+            .map(|x| x.get())
+            .unwrap_or_else(|_| {
+                tracing::warn!("Failed to query available parallelism.");
+                1
+            })
+    })
 }
 
 impl Args {
     /// Returns the number of CPU threads to use, resolving the default if not specified.
     pub fn cpu_threads(&self) -> usize {
-        self.cpu.unwrap_or_else(|| {
-            available_parallelism()
-                .map(|x| x.get())
-                .unwrap_or_else(|_| {
-                    println!("Warning: failed to query available parallelism.");
-                    1
-                })
+        cpu_threads(self.cpu)
+    }
+
+    /// Returns the tiling policy of the viewers.
+    pub fn tiling_policy(&self) -> TilingPolicy {
+        TilingPolicy {
+            mode: self.tiling,
+            width: self.tile_width,
+            max_width: self.max_tile_width,
+        }
+    }
+
+    /// Reads the load options appended to the paths, then expands their directories and glob
+    /// patterns. The files of a path share its load options.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_paths(&mut self) -> Result<(), String> {
+        let mut paths = Vec::new();
+        let mut files = Vec::new();
+        let mut options = false;
+        for path in std::mem::take(&mut self.paths) {
+            let mut trace = self.trace.clone();
+            let file = trace.read_file_options(&path)?;
+            options |= file != path;
+            let settings = FileSettings::new(&trace);
+            for path in expand_paths(vec![file]) {
+                paths.push(path);
+                files.push(settings.clone());
+            }
+        }
+        self.paths = paths;
+        if options {
+            self.trace.files = files;
+        }
+        Ok(())
+    }
+
+    /// Keeps the `--load-first` first paths to open at startup, the others being opened later
+    /// with [`Args::take_pending`].
+    #[cfg(not(target_arch = "wasm32"))]
+    fn defer_paths(&mut self) {
+        let count = self.load_first.max(1);
+        if self.paths.len() > count {
+            self.pending_paths = self.paths.split_off(count);
+            if self.trace.files.len() > count {
+                self.pending_files = self.trace.files.split_off(count);
+            }
+            tracing::info!(
+                target: "loader",
+                "Opening the first {} files, {} more can be opened from the File menu.",
+                count,
+                self.pending_paths.len()
+            );
+        }
+    }
+
+    /// Returns the arguments opening the next `--load-first` pending files, or `None` if all the
+    /// files have been opened.
+    pub fn take_pending(&mut self) -> Option<Args> {
+        if self.pending_paths.is_empty() {
+            return None;
+        }
+        let count = self.load_first.max(1).min(self.pending_paths.len());
+        let mut args = self.clone();
+        args.paths = self.pending_paths.drain(..count).collect();
+        args.pending_paths.clear();
+        let count = count.min(self.pending_files.len());
+        args.trace.files = self.pending_files.drain(..count).collect();
+        args.pending_files.clear();
+        // The scopes are only read with the first files.
+        args.connect.clear();
+        Some(args)
+    }
+}
+
+impl BenchArgs {
+    /// Returns the number of CPU threads to use, resolving the default if not specified.
+    pub fn cpu_threads(&self) -> usize {
+        cpu_threads(self.cpu)
+    }
+}
+
+impl RangeArgs {
+    /// Returns the selected range of a trace of `len` samples. The end is clamped to the trace
+    /// length.
+    pub fn range(&self, len: usize) -> Result<Range<usize>, String> {
+        let start = self.start.unwrap_or(0);
+        let end = self.end.unwrap_or(len).min(len);
+        if start >= end {
+            return Err(format!(
+                "empty sample range {}..{}, the trace has {} samples",
+                start, end, len
+            ));
+        }
+        Ok(start..end)
+    }
+}
+
+impl DisplayArgs {
+    /// Returns the color scale of the density rendering.
+    pub fn color_scale(&self) -> ColorScale {
+        ColorScale {
+            power: self.power,
+            opacity: self.opacity,
+            gradient: self.gradient.gradient(),
+        }
+    }
+
+    /// Replaces the options which have not been `given` by the configured defaults.
+    fn apply_defaults(&mut self, defaults: &Defaults, given: impl Fn(&str) -> bool) {
+        set_default(&mut self.gradient, defaults.gradient, given("gradient"));
+        set_default(&mut self.power, defaults.power, given("power"));
+        set_default(&mut self.opacity, defaults.opacity, given("opacity"));
+        self.given = ["gradient", "power", "opacity"].into_iter().any(given)
+            || defaults.gradient.is_some()
+            || defaults.power.is_some()
+            || defaults.opacity.is_some();
+    }
+}
+
+impl TraceArgs {
+    /// Returns the filter to apply to the traces after loading, if any.
+    pub fn filter_spec(&self) -> Option<FilterSpec> {
+        self.filter.map(|filter| FilterSpec {
+            filter,
+            cutoff_freq: self.cutoff_freq,
+            q: self.q,
+            notches: self.notches,
         })
     }
 
-    /// Parses the `--frames` argument into a set of frame indices.
-    /// Returns `None` if `--frames` was not specified (meaning all frames).
-    pub fn frame_selection(&self) -> Option<HashSet<usize>> {
-        let spec = self.frames.as_ref()?;
-        let mut set = HashSet::new();
-        for part in spec.split(',') {
-            let part = part.trim();
-            if let Some((start, end)) = part.split_once('-') {
-                let start: usize = start
-                    .trim()
-                    .parse()
-                    .unwrap_or_else(|_| panic!("Invalid frame range start: '{}'", start.trim()));
-                let end: usize = end
-                    .trim()
-                    .parse()
-                    .unwrap_or_else(|_| panic!("Invalid frame range end: '{}'", end.trim()));
-                assert!(start <= end, "Invalid frame range: {}-{}", start, end);
-                set.extend(start..=end);
-            } else {
-                let idx: usize = part
-                    .parse()
-                    .unwrap_or_else(|_| panic!("Invalid frame index: '{}'", part));
-                set.insert(idx);
+    /// Returns the processing steps to apply to the traces after loading, in order: filter,
+    /// absolute value, smoothing, decimation, upsampling and preset steps. Fails if
+    /// `--resample-rate` is higher than the sampling rate.
+    pub fn processing_steps(&self) -> Result<Vec<ProcessingStep>, String> {
+        let mut steps = Vec::new();
+        if let Some(spec) = self.filter_spec() {
+            steps.push(ProcessingStep::Filter(spec));
+        }
+        if self.abs {
+            steps.push(ProcessingStep::Abs);
+        }
+        if let Some(window) = self.smooth {
+            steps.push(ProcessingStep::MovingAverage { window });
+        }
+        if let Some(factor) = self.decimation_factor()? {
+            steps.push(ProcessingStep::Decimate { factor });
+        }
+        if let Some(factor) = self.upsample {
+            steps.push(ProcessingStep::Upsample { factor });
+        }
+        steps.extend_from_slice(&self.preset_steps);
+        Ok(steps)
+    }
+
+    /// Returns the decimation factor to apply at load time, resolved from `--decimate` or
+    /// `--resample-rate`. Fails if `--resample-rate` is higher than the sampling rate.
+    pub fn decimation_factor(&self) -> Result<Option<f32>, String> {
+        let Some(rate) = self.resample_rate else {
+            return Ok(self.decimate);
+        };
+        if rate > self.sampling_rate {
+            return Err(format!(
+                "the resampling rate ({} MS/s) must not be higher than the sampling rate ({} MS/s)",
+                rate, self.sampling_rate
+            ));
+        }
+        Ok(Some(self.sampling_rate / rate))
+    }
+
+    /// Removes the load options appended to `path`, as in `capture.csv:column=2:skip=1`, and
+    /// applies them. Returns the path of the file.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_file_options(&mut self, path: &str) -> Result<String, String> {
+        let (file, options) = split_file_options(path);
+        if !options.is_empty() {
+            let mut settings = FileSettings::new(self);
+            for (key, value) in options {
+                settings
+                    .set(key, value)
+                    .map_err(|e| format!("{}: {}", path, e))?;
             }
+            settings.apply(self);
+        }
+        Ok(file.to_string())
+    }
+
+    /// Returns the options for loading the file at `index` of the paths, with its own load
+    /// settings if it has some.
+    pub fn file(&self, index: usize) -> TraceArgs {
+        let mut args = self.clone();
+        if let Some(settings) = self.files.get(index) {
+            settings.apply(&mut args);
+        }
+        args.files.clear();
+        args
+    }
+
+    /// Parses the `--frames` argument. Returns `None` if `--frames` was not specified (meaning
+    /// all frames).
+    pub fn frame_selection(&self) -> Result<Option<FrameSelection>, String> {
+        self.frames
+            .as_deref()
+            .map(FrameSelection::parse)
+            .transpose()
+    }
+}
+
+/// Frames selected with `--frames`, as ranges of indices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameSelection(Vec<RangeInclusive<usize>>);
+
+impl FrameSelection {
+    /// Parses comma-separated indices or ranges, such as `1-3,6,7-8,12`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let index = |text: &str| {
+            text.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid frame index '{}'", text.trim()))
+        };
+        let ranges = spec
+            .split(',')
+            .map(|part| match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (index(start)?, index(end)?);
+                    if start > end {
+                        return Err(format!("invalid frame range {}-{}", start, end));
+                    }
+                    Ok(start..=end)
+                }
+                None => index(part).map(|i| i..=i),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self(ranges))
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.0.iter().any(|range| range.contains(&index))
+    }
+
+    /// Returns the first selected frame.
+    pub fn first(&self) -> Option<usize> {
+        self.0.iter().map(|range| *range.start()).min()
+    }
+}
+
+/// Checks a frame selection given with `--frames` or as a load option, see
+/// [`FrameSelection::parse`].
+fn parse_frames(value: &str) -> Result<String, String> {
+    FrameSelection::parse(value)?;
+    Ok(value.to_string())
+}
+
+/// Parses a sampling rate in MS/s, which must be positive.
+fn parse_rate(value: &str) -> Result<f32, String> {
+    check_rate(
+        value
+            .parse()
+            .map_err(|_| "must be a positive number".to_string())?,
+    )
+}
+
+/// Checks that a sampling rate in MS/s is positive.
+fn check_rate(rate: f32) -> Result<f32, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err("must be a positive number".to_string())
+    }
+}
+
+/// Parses a decimation factor, which must be at least 1.
+fn parse_decimation_factor(value: &str) -> Result<f32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|factor: &f32| factor.is_finite() && *factor >= 1.0)
+        .ok_or_else(|| "must be a number greater than or equal to 1".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_selection() {
+        let selection = FrameSelection::parse("1-3, 6,8-8").unwrap();
+        let selected: Vec<usize> = (0..10).filter(|&i| selection.contains(i)).collect();
+        assert_eq!(selected, [1, 2, 3, 6, 8]);
+        assert_eq!(selection.first(), Some(1));
+        assert!(FrameSelection::parse("0-18446744073709551615").is_ok());
+        for invalid in ["", "abc", "1,", "3-1", "-2", "1-", "1.5"] {
+            assert!(FrameSelection::parse(invalid).is_err(), "{}", invalid);
         }
-        Some(set)
     }
 }
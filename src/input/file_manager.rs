@@ -1,17 +1,33 @@
-//! File manager GUI for selecting trace files.
+//! File manager GUI for selecting trace files. In the browser, the files are picked with the
+//! file picker of the browser, and kept in memory.
 
 use super::Args;
+#[cfg(not(target_arch = "wasm32"))]
+use super::preview::{Preview, PreviewSource};
+#[cfg(not(target_arch = "wasm32"))]
+use super::{FileSettings, TraceArgs};
 use crate::filtering::Filter;
-use crate::loaders::{NpyLayout, TraceFormat};
+use crate::loaders::{NpyLayout, SampleType, TraceFormat};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::settings::RecentFile;
+use crate::settings::Settings;
 use egui::{ComboBox, DragValue, TextEdit};
+#[cfg(not(target_arch = "wasm32"))]
 use egui_file_dialog::FileDialog;
+#[cfg(target_arch = "wasm32")]
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 /// Result of the file manager update.
 pub enum FileManagerResult {
     /// No files selected yet, continue showing the dialog.
     Pending,
     /// Files were selected successfully, args contains the paths and settings.
-    Selected(Args),
+    Selected(Box<Args>),
     /// Dialog was cancelled, close the app.
     Cancelled,
 }
@@ -19,27 +35,89 @@ pub enum FileManagerResult {
 /// File manager GUI for selecting trace files.
 pub struct FileManager {
     /// The file dialog.
+    #[cfg(not(target_arch = "wasm32"))]
     file_dialog: FileDialog,
+    /// Names of the files picked in the browser, once read.
+    #[cfg(target_arch = "wasm32")]
+    picked: Arc<Mutex<Option<Vec<String>>>>,
     /// Arguments (editable by the user).
     args: Args,
     /// Text buffer for the frames input field.
     frames_text: String,
+    /// Recently used sampling rates, offered in the settings.
+    recent_rates: Vec<f32>,
+    /// Recently opened files, which can be opened again with their load settings.
+    #[cfg(not(target_arch = "wasm32"))]
+    recent_files: Vec<RecentFile>,
+    /// Preview of the file highlighted in the dialog.
+    #[cfg(not(target_arch = "wasm32"))]
+    preview: Option<Preview>,
+    /// Load settings of the picked files which do not use the shared ones.
+    #[cfg(not(target_arch = "wasm32"))]
+    file_settings: HashMap<PathBuf, FileSettings>,
+    /// Number of traces which can still be displayed, when the picked files are added to the
+    /// displayed ones.
+    #[cfg(target_arch = "wasm32")]
+    remaining: Option<usize>,
 }
 
 impl FileManager {
-    /// Creates a new file manager with the given initial arguments.
-    pub fn new(args: Args) -> Self {
-        let mut file_dialog = FileDialog::new();
+    /// Creates a new file manager with the given initial arguments, opened in the directory of
+    /// the last opened files.
+    pub fn new(mut args: Args, settings: &Settings) -> Self {
+        // Scopes given with `--connect` are only read at startup, not with the opened files, and
+        // the load settings of the files given on the command line do not apply to the picked
+        // ones.
+        args.connect.clear();
+        args.trace.files.clear();
+        args.pending_paths.clear();
+        args.pending_files.clear();
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut file_dialog = match &settings.last_directory {
+            Some(dir) => FileDialog::new().initial_directory(dir.clone()),
+            None => FileDialog::new(),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
         file_dialog.pick_multiple();
-        let frames_text = args.frames.clone().unwrap_or_default();
+        let frames_text = args.trace.frames.clone().unwrap_or_default();
         Self {
+            #[cfg(not(target_arch = "wasm32"))]
             file_dialog,
+            #[cfg(target_arch = "wasm32")]
+            picked: Arc::default(),
             args,
             frames_text,
+            recent_rates: settings.sampling_rates.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            recent_files: settings.recent_files.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            preview: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            file_settings: HashMap::new(),
+            #[cfg(target_arch = "wasm32")]
+            remaining: None,
         }
     }
 
+    /// Creates a file manager adding the picked files to the displayed traces, of which
+    /// `remaining` more can be displayed.
+    pub fn adding(args: Args, settings: &Settings, remaining: usize) -> Self {
+        let mut manager = Self::new(args, settings);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            manager.file_dialog.config_mut().title =
+                Some(format!("Add traces ({} more can be displayed)", remaining));
+            manager.file_dialog.labels_mut().open_button = "🗀  Add".to_string();
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            manager.remaining = Some(remaining);
+        }
+        manager
+    }
+
     /// Updates the file manager UI and returns the result.
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn update(&mut self, ctx: &egui::Context) -> FileManagerResult {
         let state = self.file_dialog.state().clone();
 
@@ -51,229 +129,592 @@ impl FileManager {
             _ => {}
         }
 
-        // Update the dialog with a custom right panel for configuration
+        // Update the dialog with a custom right panel for the preview of the highlighted file, the
+        // recent files and the configuration
+        let mut recent = None;
         self.file_dialog
-            .update_with_right_panel_ui(ctx, &mut |ui, _dialog| {
+            .update_with_right_panel_ui(ctx, &mut |ui, dialog| {
+                let highlighted = dialog
+                    .selected_entry()
+                    .filter(|entry| entry.is_file())
+                    .map(|entry| entry.to_path_buf());
+                preview_ui(&mut self.preview, highlighted, &self.args, ui);
+                let picked: Vec<PathBuf> = dialog
+                    .selected_entries()
+                    .filter(|entry| entry.is_file())
+                    .map(|entry| entry.to_path_buf())
+                    .collect();
+                let mut shared = self.args.trace.clone();
+                shared.frames = frames_spec(&self.frames_text);
+                file_settings_ui(&mut self.file_settings, &picked, &shared, ui);
+                recent = recent_files_ui(&self.recent_files, ui);
+                settings_ui(
+                    &mut self.args,
+                    &mut self.frames_text,
+                    &self.recent_rates,
+                    ui,
+                );
+            });
+
+        if let Some(file) = recent {
+            let mut args = self.args.clone();
+            file.settings.apply(&mut args.trace);
+            args.paths = vec![file.path];
+            return FileManagerResult::Selected(Box::new(args));
+        }
+
+        if let Some(paths) = self.file_dialog.take_picked_multiple() {
+            let paths_str: Vec<String> = paths
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+
+            if !paths_str.is_empty() {
+                return self.selected(paths_str);
+            }
+        }
+
+        FileManagerResult::Pending
+    }
+
+    /// Updates the file manager UI and returns the result. The files are picked with the file
+    /// picker of the browser, which cannot be cancelled.
+    #[cfg(target_arch = "wasm32")]
+    pub fn update(&mut self, ctx: &egui::Context) -> FileManagerResult {
+        if let Some(paths) = self.picked.lock().unwrap().take()
+            && !paths.is_empty()
+        {
+            return self.selected(paths);
+        }
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
                 ui.add_space(10.0);
-                ui.heading("Load Settings");
-                ui.add_space(5.0);
+                let label = match self.remaining {
+                    Some(remaining) => format!("Add files… ({} more can be displayed)", remaining),
+                    None => "Open files…".to_string(),
+                };
+                if ui.button(label).clicked() {
+                    self.pick_files(ctx);
+                }
+            });
+            ui.separator();
+            settings_ui(
+                &mut self.args,
+                &mut self.frames_text,
+                &self.recent_rates,
+                ui,
+            );
+        });
+        FileManagerResult::Pending
+    }
+
+    /// Opens the file picker of the browser, and reads the picked files in background.
+    #[cfg(target_arch = "wasm32")]
+    fn pick_files(&self, ctx: &egui::Context) {
+        let picked = self.picked.clone();
+        let ctx = ctx.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let files = rfd::AsyncFileDialog::new()
+                .add_filter(
+                    "Traces",
+                    &[
+                        "npy", "csv", "wfm", "trs", "trc", "wav", "mat", "bin", "sr", "vcd",
+                        "parquet", "feather", "arrow", "isf", "gz", "zst",
+                    ],
+                )
+                .pick_files()
+                .await
+                .unwrap_or_default();
+            let mut names = Vec::new();
+            for file in files {
+                let name = file.file_name();
+                crate::loaders::add_file(name.clone(), file.read().await);
+                names.push(name);
+            }
+            *picked.lock().unwrap() = Some(names);
+            ctx.request_repaint();
+        });
+    }
+
+    /// Returns the arguments for loading `paths` with the current settings.
+    fn selected(&self, paths: Vec<String>) -> FileManagerResult {
+        let mut args = self.args.clone();
+        args.paths = paths;
+        args.trace.frames = frames_spec(&self.frames_text);
+        // The files with their own settings are loaded with them, the others with the shared ones.
+        #[cfg(not(target_arch = "wasm32"))]
+        if args
+            .paths
+            .iter()
+            .any(|path| self.file_settings.contains_key(Path::new(path)))
+        {
+            let shared = FileSettings::new(&args.trace);
+            args.trace.files = args
+                .paths
+                .iter()
+                .map(|path| {
+                    self.file_settings
+                        .get(Path::new(path))
+                        .cloned()
+                        .unwrap_or_else(|| shared.clone())
+                })
+                .collect();
+        }
+        FileManagerResult::Selected(Box::new(args))
+    }
+}
+
+/// Returns the frame selection typed in `text`, `None` if empty to load all the frames.
+fn frames_spec(text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+/// Shows the preview of the `highlighted` file, rendered with the load settings of `args`. The
+/// preview is rendered again when another file is highlighted or the settings change.
+#[cfg(not(target_arch = "wasm32"))]
+fn preview_ui(
+    preview: &mut Option<Preview>,
+    highlighted: Option<std::path::PathBuf>,
+    args: &Args,
+    ui: &mut egui::Ui,
+) {
+    let Some(path) = highlighted else {
+        *preview = None;
+        return;
+    };
+    let source = PreviewSource {
+        path,
+        format: args.trace.format,
+        npy_layout: args.trace.npy_layout,
+        skip_lines: args.trace.skip_lines,
+        column: args.trace.column,
+        dataset: args.trace.dataset.clone(),
+        dtype: args.trace.dtype,
+    };
+    if preview.as_ref().is_none_or(|p| *p.source() != source) {
+        *preview = Some(Preview::start(ui.ctx(), source, args.display.color_scale()));
+    }
+    ui.add_space(10.0);
+    ui.heading("Preview");
+    ui.add_space(5.0);
+    preview.as_mut().unwrap().ui(ui);
+    ui.add_space(10.0);
+    ui.separator();
+}
 
-                // Sampling rate
-                ui.horizontal(|ui| {
-                    ui.label("Sampling Rate:");
+/// Shows a table of the load settings of each `picked` file when several files are picked, so
+/// they can differ between the files. The settings of a file are the `shared` ones until they are
+/// edited.
+#[cfg(not(target_arch = "wasm32"))]
+fn file_settings_ui(
+    file_settings: &mut HashMap<PathBuf, FileSettings>,
+    picked: &[PathBuf],
+    shared: &TraceArgs,
+    ui: &mut egui::Ui,
+) {
+    file_settings.retain(|path, _| picked.contains(path));
+    if picked.len() < 2 {
+        return;
+    }
+    ui.add_space(10.0);
+    ui.heading("Picked Files");
+    ui.add_space(5.0);
+    egui::ScrollArea::horizontal().show(ui, |ui| {
+        egui::Grid::new("file_settings_grid")
+            .striped(true)
+            .show(ui, |ui| {
+                for header in ["File", "Format", "MS/s", "Column", "Skip", "Traces", ""] {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for path in picked {
+                    let custom = file_settings.contains_key(path);
+                    let mut settings = file_settings
+                        .get(path)
+                        .cloned()
+                        .unwrap_or_else(|| FileSettings::new(shared));
+                    let name = path
+                        .file_name()
+                        .map_or(path.to_string_lossy(), |name| name.to_string_lossy());
+                    ui.label(name).on_hover_text(path.to_string_lossy());
+                    format_combo(path, &mut settings.format, ui);
                     ui.add(
-                        DragValue::new(&mut self.args.sampling_rate)
-                            .suffix(" MS/s")
+                        DragValue::new(&mut settings.sampling_rate)
                             .range(1.0..=1000e9)
                             .speed(25.0),
                     );
-                });
-
-                // For CPU, we need a mutable value to edit
-                let mut cpu_value = self.args.cpu.unwrap_or(self.args.cpu_threads());
-                ui.horizontal(|ui| {
-                    ui.label("CPU Threads:");
+                    ui.add(DragValue::new(&mut settings.column).range(0..=1000));
+                    ui.add(DragValue::new(&mut settings.skip_lines).range(0..=10000));
+                    let mut frames = settings.frames.clone().unwrap_or_default();
                     if ui
-                        .add(DragValue::new(&mut cpu_value).range(1..=self.args.cpu_threads()))
+                        .add(
+                            TextEdit::singleline(&mut frames)
+                                .hint_text("all")
+                                .desired_width(60.0),
+                        )
                         .changed()
                     {
-                        self.args.cpu = Some(cpu_value);
+                        settings.frames = frames_spec(&frames);
                     }
-                })
-                .response
-                .on_hover_text(format!(
-                    "Number of CPU rendering threads (max: {})",
-                    self.args.cpu_threads()
-                ));
+                    if custom
+                        && ui
+                            .small_button("↺")
+                            .on_hover_text("Use the shared load settings")
+                            .clicked()
+                    {
+                        file_settings.remove(path);
+                    } else {
+                        if !custom {
+                            ui.label("");
+                        }
+                        if settings != FileSettings::new(shared) || custom {
+                            file_settings.insert(path.clone(), settings);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+    ui.add_space(10.0);
+    ui.separator();
+}
+
+/// Shows the recently opened files. Returns the clicked one, if any. Files which do not exist
+/// anymore are disabled.
+#[cfg(not(target_arch = "wasm32"))]
+fn recent_files_ui(files: &[RecentFile], ui: &mut egui::Ui) -> Option<RecentFile> {
+    if files.is_empty() {
+        return None;
+    }
+    ui.add_space(10.0);
+    ui.heading("Recent Files");
+    ui.add_space(5.0);
+    let mut clicked = None;
+    for file in files {
+        let path = std::path::Path::new(&file.path);
+        let name = path
+            .file_name()
+            .map_or(file.path.clone(), |name| name.to_string_lossy().to_string());
+        let mut hover = format!(
+            "{}\nSampling rate: {} MS/s",
+            file.path, file.settings.sampling_rate
+        );
+        if let Some(frames) = &file.settings.frames {
+            hover += &format!("\nTraces: {}", frames);
+        }
+        if ui
+            .add_enabled(path.exists(), egui::Button::new(name).truncate())
+            .on_hover_text(hover)
+            .on_disabled_hover_text(format!("{} not found", file.path))
+            .clicked()
+        {
+            clicked = Some(file.clone());
+        }
+    }
+    ui.add_space(10.0);
+    ui.separator();
+    clicked
+}
 
-                ui.add_space(5.0);
+/// Shows the load settings, editing `args` and the text of the frames field. The `recent`
+/// sampling rates can be picked from a menu.
+fn settings_ui(args: &mut Args, frames_text: &mut String, recent: &[f32], ui: &mut egui::Ui) {
+    ui.add_space(10.0);
+    ui.heading("Load Settings");
+    ui.add_space(5.0);
 
-                ui.horizontal(|ui| {
-                    ui.label("GPU Threads:");
-                    ui.add(DragValue::new(&mut self.args.gpu).range(0..=16));
-                })
-                .response
-                .on_hover_text("Number of GPU rendering threads (0 to disable GPU rendering)");
+    // Sampling rate
+    ui.horizontal(|ui| {
+        ui.label("Sampling Rate:");
+        ui.add(
+            DragValue::new(&mut args.trace.sampling_rate)
+                .suffix(" MS/s")
+                .range(1.0..=1000e9)
+                .speed(25.0),
+        );
+        if !recent.is_empty() {
+            ui.menu_button("Recent", |ui| {
+                for &rate in recent {
+                    if ui.button(format!("{} MS/s", rate)).clicked() {
+                        args.trace.sampling_rate = rate;
+                    }
+                }
+            })
+            .response
+            .on_hover_text("Sampling rates used recently");
+        }
+    });
 
-                ui.add_space(15.0);
-                ui.separator();
-                ui.add_space(10.0);
+    // For CPU, we need a mutable value to edit
+    let mut cpu_value = args.cpu.unwrap_or(args.cpu_threads());
+    ui.horizontal(|ui| {
+        ui.label("CPU Threads:");
+        if ui
+            .add(DragValue::new(&mut cpu_value).range(1..=args.cpu_threads()))
+            .changed()
+        {
+            args.cpu = Some(cpu_value);
+        }
+    })
+    .response
+    .on_hover_text(format!(
+        "Number of CPU rendering threads (max: {})",
+        args.cpu_threads()
+    ));
 
-                // Filter section
-                ui.heading("Filter");
-                ui.add_space(5.0);
-
-                // Filter type selection
-                let filter_label = match self.args.filter {
-                    None => "None",
-                    Some(Filter::LowPass) => "Low-pass",
-                    Some(Filter::HighPass) => "High-pass",
-                    Some(Filter::BandPass) => "Band-pass",
-                    Some(Filter::Notch) => "Notch",
-                };
+    ui.add_space(5.0);
 
-                ComboBox::from_id_salt("filter_combo")
-                    .selected_text(filter_label)
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut self.args.filter, None, "None");
-                        ui.selectable_value(
-                            &mut self.args.filter,
-                            Some(Filter::LowPass),
-                            "Low-pass",
-                        );
-                        ui.selectable_value(
-                            &mut self.args.filter,
-                            Some(Filter::HighPass),
-                            "High-pass",
-                        );
-                        ui.selectable_value(
-                            &mut self.args.filter,
-                            Some(Filter::BandPass),
-                            "Band-pass",
-                        );
-                        ui.selectable_value(&mut self.args.filter, Some(Filter::Notch), "Notch");
-                    });
-
-                // Cutoff frequency (only show if filter is enabled)
-                if self.args.filter.is_some() {
-                    ui.add_space(5.0);
-                    ui.label("Cutoff Frequency:");
-                    ui.add(
-                        DragValue::new(&mut self.args.cutoff_freq)
-                            .suffix(" kHz")
-                            .range(0.001..=1000e6)
-                            .speed(10.0),
-                    );
-                }
+    ui.horizontal(|ui| {
+        ui.label("GPU Threads:");
+        ui.add(DragValue::new(&mut args.gpu).range(0..=16));
+    })
+    .response
+    .on_hover_text("Number of GPU rendering threads (0 to disable GPU rendering)");
 
-                ui.add_space(15.0);
-                ui.separator();
-                ui.add_space(10.0);
+    ui.add_space(15.0);
+    ui.separator();
+    ui.add_space(10.0);
 
-                // Format section
-                ui.heading("File Format");
-                ui.add_space(5.0);
+    // Filter section
+    ui.heading("Filter");
+    ui.add_space(5.0);
 
-                let format_label = match self.args.format {
-                    None => "Auto",
-                    Some(TraceFormat::Csv) => "CSV",
-                    Some(TraceFormat::Numpy) => "NPY",
-                    Some(TraceFormat::TekWfm) => "Tek WFM",
-                };
+    // Filter type selection
+    let filter_label = args.trace.filter.as_ref().map_or("None", Filter::name);
 
-                ComboBox::from_id_salt("format_combo")
-                    .selected_text(format_label)
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut self.args.format, None, "Auto");
-                        ui.selectable_value(&mut self.args.format, Some(TraceFormat::Csv), "CSV");
-                        ui.selectable_value(&mut self.args.format, Some(TraceFormat::Numpy), "NPY");
-                        ui.selectable_value(
-                            &mut self.args.format,
-                            Some(TraceFormat::TekWfm),
-                            "Tek WFM",
-                        );
-                    });
-
-                // CSV-specific options.
-                if matches!(self.args.format, None | Some(TraceFormat::Csv)) {
-                    ui.add_space(10.0);
-                    ui.label("CSV Options:");
-                    ui.add_space(5.0);
-
-                    ui.horizontal(|ui| {
-                        ui.label("Column:");
-                        ui.add(DragValue::new(&mut self.args.column).range(0..=1000));
-                    })
-                    .response
-                    .on_hover_text("Index of the column containing trace values, starting from 0.");
-
-                    ui.add_space(5.0);
-                    ui.horizontal(|ui| {
-                        ui.label("Skip lines:");
-                        ui.add(DragValue::new(&mut self.args.skip_lines).range(0..=10000));
-                    })
-                    .response
-                    .on_hover_text("Number of header lines to skip before reading values.");
-                }
+    ComboBox::from_id_salt("filter_combo")
+        .selected_text(filter_label)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut args.trace.filter, None, "None");
+            ui.selectable_value(&mut args.trace.filter, Some(Filter::LowPass), "Low-pass");
+            ui.selectable_value(&mut args.trace.filter, Some(Filter::HighPass), "High-pass");
+            ui.selectable_value(&mut args.trace.filter, Some(Filter::BandPass), "Band-pass");
+            ui.selectable_value(&mut args.trace.filter, Some(Filter::Notch), "Notch");
+            ui.selectable_value(&mut args.trace.filter, Some(Filter::Comb), "Comb");
+        });
 
-                // NPY-specific options: how to interpret 2D arrays.
-                if matches!(self.args.format, None | Some(TraceFormat::Numpy)) {
-                    ui.add_space(10.0);
-                    ui.horizontal(|ui| {
-                        ui.label("NPY 2D layout:");
-                        let layout_label = match self.args.npy_layout {
-                            NpyLayout::Auto => "Auto",
-                            NpyLayout::Columns => "Columns",
-                            NpyLayout::Rows => "Rows",
-                        };
-                        ComboBox::from_id_salt("npy_layout_combo")
-                            .selected_text(layout_label)
-                            .show_ui(ui, |ui| {
-                                ui.selectable_value(
-                                    &mut self.args.npy_layout,
-                                    NpyLayout::Auto,
-                                    "Auto",
-                                );
-                                ui.selectable_value(
-                                    &mut self.args.npy_layout,
-                                    NpyLayout::Columns,
-                                    "Columns (pts, n_traces)",
-                                );
-                                ui.selectable_value(
-                                    &mut self.args.npy_layout,
-                                    NpyLayout::Rows,
-                                    "Rows (n_traces, pts)",
-                                );
-                            });
-                    })
-                    .response
-                    .on_hover_text(
-                        "How to interpret 2D Numpy arrays:\n\
-                         - Auto: shape (pts, few cols) is column-wise, otherwise row-wise.\n\
-                         - Columns: one trace per column (array is transposed).\n\
-                         - Rows: one trace per row.\n\
-                         In both 2D cases, use 'Traces indices' below to pick a subset.",
-                    );
-                }
+    // Cutoff frequency and Q (only show if filter is enabled)
+    if let Some(filter) = args.trace.filter {
+        ui.add_space(5.0);
+        ui.label(format!("{}:", filter.frequency_label()));
+        ui.add(
+            DragValue::new(&mut args.trace.cutoff_freq)
+                .suffix(" kHz")
+                .range(0.001..=1000e6)
+                .speed(10.0),
+        );
+        ui.add_space(5.0);
+        ui.label("Q:");
+        ui.add(
+            DragValue::new(&mut args.trace.q)
+                .range(0.01..=1000.0)
+                .speed(0.05),
+        );
+        if filter == Filter::Comb {
+            ui.add_space(5.0);
+            ui.label("Notches:");
+            ui.add(DragValue::new(&mut args.trace.notches).range(1..=1000));
+        }
+    }
 
-                // Trace selection: applies to any format/layout. For files that
-                // produce a single trace (e.g. 1D NumPy, CSV, column-wise 2D
-                // NumPy) only index 0 is meaningful; for multi-trace files
-                // (row-wise 2D NumPy, FastFrame WFM) any subset can be picked.
-                ui.add_space(5.0);
-                ui.horizontal(|ui| {
-                    ui.label("Traces indices:");
-                    ui.add(
-                        TextEdit::singleline(&mut self.frames_text)
-                            .hint_text("all or 0-3,6,7-8,12")
-                            .desired_width(120.0),
+    ui.add_space(15.0);
+    ui.separator();
+    ui.add_space(10.0);
+
+    // Format section
+    ui.heading("File Format");
+    ui.add_space(5.0);
+
+    format_combo("format_combo", &mut args.trace.format, ui);
+
+    // CSV-specific options.
+    if matches!(args.trace.format, None | Some(TraceFormat::Csv)) {
+        ui.add_space(10.0);
+        ui.label("CSV Options:");
+        ui.add_space(5.0);
+
+        ui.horizontal(|ui| {
+            ui.label("Column:");
+            ui.add(DragValue::new(&mut args.trace.column).range(0..=1000));
+        })
+        .response
+        .on_hover_text("Index of the column containing trace values, starting from 0.");
+
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            ui.label("Skip lines:");
+            ui.add(DragValue::new(&mut args.trace.skip_lines).range(0..=10000));
+        })
+        .response
+        .on_hover_text("Number of header lines to skip before reading values.");
+    }
+
+    // NPY-specific options: how to interpret 2D arrays.
+    if matches!(args.trace.format, None | Some(TraceFormat::Numpy)) {
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            ui.label("NPY 2D layout:");
+            let layout_label = match args.trace.npy_layout {
+                NpyLayout::Auto => "Auto",
+                NpyLayout::Columns => "Columns",
+                NpyLayout::Rows => "Rows",
+            };
+            ComboBox::from_id_salt("npy_layout_combo")
+                .selected_text(layout_label)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut args.trace.npy_layout, NpyLayout::Auto, "Auto");
+                    ui.selectable_value(
+                        &mut args.trace.npy_layout,
+                        NpyLayout::Columns,
+                        "Columns (pts, n_traces)",
                     );
-                })
-                .response
-                .on_hover_text(
-                    "Comma-separated indices or ranges, e.g. \"0-3,6,7-8,12\". Leave empty to load all traces.",
-                );
-            });
+                    ui.selectable_value(
+                        &mut args.trace.npy_layout,
+                        NpyLayout::Rows,
+                        "Rows (n_traces, pts)",
+                    );
+                });
+        })
+        .response
+        .on_hover_text(
+            "How to interpret 2D Numpy arrays:\n\
+             - Auto: shape (pts, few cols) is column-wise, otherwise row-wise.\n\
+             - Columns: one trace per column (array is transposed).\n\
+             - Rows: one trace per row.\n\
+             In both 2D cases, use 'Traces indices' below to pick a subset.",
+        );
+    }
 
-        if let Some(paths) = self.file_dialog.take_picked_multiple() {
-            let paths_str: Vec<String> = paths
-                .iter()
-                .map(|p| p.to_string_lossy().to_string())
-                .collect();
+    // MAT-specific options: which variable to load.
+    if matches!(args.trace.format, None | Some(TraceFormat::Mat)) {
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            ui.label("MAT variable:");
+            let mut name = args.trace.dataset.clone().unwrap_or_default();
+            if ui.text_edit_singleline(&mut name).changed() {
+                args.trace.dataset = (!name.is_empty()).then_some(name);
+            }
+        })
+        .response
+        .on_hover_text("Name of the array to load from MATLAB files. All the arrays if empty.");
+    }
 
-            if !paths_str.is_empty() {
-                let mut args = self.args.clone();
-                args.paths = paths_str;
-                let trimmed = self.frames_text.trim();
-                args.frames = if trimmed.is_empty() {
-                    None
-                } else {
-                    Some(trimmed.to_string())
-                };
-                return FileManagerResult::Selected(args);
+    // Parquet and Feather options: which column to load.
+    if args.trace.format == Some(TraceFormat::Parquet) {
+        ui.add_space(10.0);
+        ui.label("Parquet Options:");
+        ui.add_space(5.0);
+
+        ui.horizontal(|ui| {
+            ui.label("Column:");
+            ui.add(DragValue::new(&mut args.trace.column).range(0..=1000));
+        })
+        .response
+        .on_hover_text("Index of the column to load, starting from 0.");
+
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            ui.label("Column name:");
+            let mut name = args.trace.dataset.clone().unwrap_or_default();
+            if ui.text_edit_singleline(&mut name).changed() {
+                args.trace.dataset = (!name.is_empty()).then_some(name);
             }
-        }
+        })
+        .response
+        .on_hover_text("Name of the column to load, instead of its index if not empty.");
+    }
 
-        FileManagerResult::Pending
+    // Raw options: type of the samples.
+    if args.trace.format == Some(TraceFormat::Raw) {
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            ui.label("Sample type:");
+            ComboBox::from_id_salt("dtype_combo")
+                .selected_text(args.trace.dtype.name())
+                .show_ui(ui, |ui| {
+                    for dtype in SampleType::ALL {
+                        ui.selectable_value(&mut args.trace.dtype, dtype, dtype.name());
+                    }
+                });
+        })
+        .response
+        .on_hover_text("Type of the little-endian samples of the raw files, without header.");
     }
+
+    // Trace selection: applies to any format/layout. For files that
+    // produce a single trace (e.g. 1D NumPy, CSV, column-wise 2D
+    // NumPy) only index 0 is meaningful; for multi-trace files
+    // (row-wise 2D NumPy, FastFrame WFM) any subset can be picked.
+    ui.add_space(5.0);
+    ui.horizontal(|ui| {
+        ui.label("Traces indices:");
+        ui.add(
+            TextEdit::singleline(frames_text)
+                .hint_text("all or 0-3,6,7-8,12")
+                .desired_width(120.0),
+        );
+    })
+    .response
+    .on_hover_text(
+        "Comma-separated indices or ranges, e.g. \"0-3,6,7-8,12\". Leave empty to load all traces.",
+    );
+
+    ui.add_space(5.0);
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut args.mean, "Mean trace");
+        ui.checkbox(&mut args.std, "Std-dev trace");
+    })
+    .response
+    .on_hover_text(
+        "For files containing multiple traces, add views showing the mean and standard deviation of the selected traces.",
+    );
+
+    ui.add_space(5.0);
+    ui.checkbox(&mut args.browse, "Browse frames")
+        .on_hover_text(
+            "For files containing multiple traces, open a single view with a frame browser, reading the frames on demand.",
+        );
+}
+
+/// Shows a combo box selecting the trace `format`, `None` guessing it from the file extension.
+fn format_combo(
+    id_salt: impl std::hash::Hash,
+    format: &mut Option<TraceFormat>,
+    ui: &mut egui::Ui,
+) {
+    let label = match format {
+        None => "Auto",
+        Some(TraceFormat::Csv) => "CSV",
+        Some(TraceFormat::Numpy) => "NPY",
+        Some(TraceFormat::TekWfm) => "Tek WFM",
+        Some(TraceFormat::Trs) => "TRS",
+        Some(TraceFormat::LecroyTrc) => "LeCroy TRC",
+        Some(TraceFormat::Wav) => "WAV",
+        Some(TraceFormat::Mat) => "MAT",
+        Some(TraceFormat::KeysightBin) => "Keysight BIN",
+        Some(TraceFormat::Sigrok) => "sigrok",
+        Some(TraceFormat::Vcd) => "VCD",
+        Some(TraceFormat::Parquet) => "Parquet/Feather",
+        Some(TraceFormat::Isf) => "Tek ISF",
+        Some(TraceFormat::Raw) => "Raw",
+    };
+    ComboBox::from_id_salt(id_salt)
+        .selected_text(label)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(format, None, "Auto");
+            ui.selectable_value(format, Some(TraceFormat::Csv), "CSV");
+            ui.selectable_value(format, Some(TraceFormat::Numpy), "NPY");
+            ui.selectable_value(format, Some(TraceFormat::TekWfm), "Tek WFM");
+            ui.selectable_value(format, Some(TraceFormat::Trs), "TRS");
+            ui.selectable_value(format, Some(TraceFormat::LecroyTrc), "LeCroy TRC");
+            ui.selectable_value(format, Some(TraceFormat::Wav), "WAV");
+            ui.selectable_value(format, Some(TraceFormat::Mat), "MAT");
+            ui.selectable_value(format, Some(TraceFormat::KeysightBin), "Keysight BIN");
+            ui.selectable_value(format, Some(TraceFormat::Sigrok), "sigrok");
+            ui.selectable_value(format, Some(TraceFormat::Vcd), "VCD");
+            ui.selectable_value(format, Some(TraceFormat::Parquet), "Parquet/Feather");
+            ui.selectable_value(format, Some(TraceFormat::Isf), "Tek ISF");
+            ui.selectable_value(format, Some(TraceFormat::Raw), "Raw");
+        });
 }
@@ -2,6 +2,13 @@
 
 mod args;
 mod file_manager;
+#[cfg(not(target_arch = "wasm32"))]
+mod paths;
+#[cfg(not(target_arch = "wasm32"))]
+mod preview;
 
-pub use args::Args;
+pub use args::{
+    Args, BenchArgs, Cli, ConvertArgs, DisplayArgs, FileSettings, InfoArgs, Mode, RenderArgs,
+    ServeArgs, ThumbnailArgs, TraceArgs,
+};
 pub use file_manager::{FileManager, FileManagerResult};
@@ -0,0 +1,160 @@
+//! Expansion of the directories and glob patterns given as trace files on the command line.
+//!
+//! Shells expand the patterns before starting TurboPlot, but not the quoted ones, nor the Windows
+//! command prompt. Patterns may use `*` for any sequence of characters and `?` for a single
+//! character, in any component of the path.
+
+use super::args::FileSettings;
+use crate::loaders::{STDIN_PATH, guess_format};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Replaces the directories of `paths` by the trace files they contain, and the glob patterns by
+/// the files matching them, sorted by name. Patterns matching no file are kept, so their loading
+/// failure is reported.
+pub fn expand_paths(paths: Vec<String>) -> Vec<String> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if path == STDIN_PATH {
+            expanded.push(path);
+        } else if Path::new(&path).is_dir() {
+            let files = trace_files(Path::new(&path));
+            if files.is_empty() {
+                tracing::warn!(target: "loader", "No trace file in {}", path);
+            }
+            expanded.extend(files);
+        } else if has_wildcards(&path) && !Path::new(&path).exists() {
+            let files = glob(&path);
+            if files.is_empty() {
+                tracing::warn!(target: "loader", "No file matches {}", path);
+                expanded.push(path);
+            }
+            expanded.extend(files);
+        } else {
+            expanded.push(path);
+        }
+    }
+    expanded
+}
+
+/// Splits the load options appended to `path`, as in `capture.csv:column=2:skip=1`, from the path
+/// of the file. Existing files are kept whole, even if their name looks like options.
+pub fn split_file_options(path: &str) -> (&str, Vec<(&str, &str)>) {
+    let mut file = path;
+    let mut options = Vec::new();
+    if Path::new(path).exists() {
+        return (file, options);
+    }
+    while let Some((rest, option)) = file.rsplit_once(':') {
+        let Some((key, value)) = option.split_once('=') else {
+            break;
+        };
+        if rest.is_empty() || !FileSettings::OPTIONS.contains(&key) {
+            break;
+        }
+        options.push((key, value));
+        file = rest;
+    }
+    options.reverse();
+    (file, options)
+}
+
+/// Returns the files of `dir` whose format is recognized, sorted by name. Hidden files and
+/// subdirectories are ignored.
+fn trace_files(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .filter(|path| guess_format(path).is_some())
+        .collect();
+    files.sort();
+    files
+}
+
+fn has_wildcards(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Returns the files matching `pattern`, sorted by name.
+fn glob(pattern: &str) -> Vec<String> {
+    let mut matches = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy();
+        if !has_wildcards(&name) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+        matches = matches
+            .iter()
+            .flat_map(|dir| {
+                let entries = if dir.as_os_str().is_empty() {
+                    fs::read_dir(".")
+                } else {
+                    fs::read_dir(dir)
+                };
+                entries
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.file_name())
+                    .filter(|entry| {
+                        let entry = entry.to_string_lossy();
+                        // Hidden files only match the patterns starting with a dot.
+                        (name.starts_with('.') || !entry.starts_with('.'))
+                            && wildcard_match(&name, &entry)
+                    })
+                    .map(|entry| dir.join(entry))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    let mut files: Vec<String> = matches
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Returns true if `name` matches `pattern`, where `*` matches any sequence of characters and `?`
+/// any single character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern, and of the name character it matches up to.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // The last `*` matches one more character.
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,158 @@
+//! Preview of the file highlighted in the file dialog, so the right capture can be recognized
+//! before loading it entirely.
+//!
+//! The first frame of the file is read in background, decimated and rendered at a low resolution
+//! with the CPU renderer. Multi-frame files only have their first frame read.
+
+use crate::{
+    loaders::{
+        NpyLayout, SampleType, TraceFormat, guess_format, load_file, open_frames,
+        unrecognized_format,
+    },
+    render::render_trace,
+    tiling::ColorScale,
+};
+use egui::{ColorImage, TextureHandle, TextureOptions};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Size of the preview image, in pixels.
+const PREVIEW_WIDTH: u32 = 256;
+const PREVIEW_HEIGHT: u32 = 96;
+
+/// Maximum number of samples rendered: longer traces are decimated by keeping evenly spaced
+/// samples, which is enough to recognize the capture.
+const MAX_PREVIEW_SAMPLES: usize = 1 << 20;
+
+/// File previewed, with the load settings it is read with.
+#[derive(Clone, PartialEq)]
+pub struct PreviewSource {
+    pub path: PathBuf,
+    pub format: Option<TraceFormat>,
+    pub npy_layout: NpyLayout,
+    pub skip_lines: usize,
+    pub column: usize,
+    pub dataset: Option<String>,
+    pub dtype: SampleType,
+}
+
+/// Rendered preview and the description of the file.
+struct Rendered {
+    image: ColorImage,
+    /// Number of samples of the first frame and number of frames.
+    samples: usize,
+    frames: usize,
+}
+
+/// Preview of a file, rendered in background.
+pub struct Preview {
+    source: PreviewSource,
+    /// Rendered preview once ready, or the reason why the file cannot be previewed.
+    result: Arc<Mutex<Option<Result<Rendered, String>>>>,
+    texture: Option<TextureHandle>,
+}
+
+impl Preview {
+    /// Starts rendering the preview of `source` with `color_scale`. The user interface is woken
+    /// up when it is ready.
+    pub fn start(ctx: &egui::Context, source: PreviewSource, color_scale: ColorScale) -> Self {
+        let result = Arc::new(Mutex::new(None));
+        let thread_result = Arc::clone(&result);
+        let thread_source = source.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let rendered = render(&thread_source, color_scale);
+            *thread_result.lock().unwrap() = Some(rendered);
+            ctx.request_repaint();
+        });
+        Self {
+            source,
+            result,
+            texture: None,
+        }
+    }
+
+    pub fn source(&self) -> &PreviewSource {
+        &self.source
+    }
+
+    /// Shows the preview, a spinner while it is rendered, or the failure.
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let result = self.result.lock().unwrap();
+        match &*result {
+            None => {
+                ui.add_sized(
+                    [PREVIEW_WIDTH as f32, PREVIEW_HEIGHT as f32],
+                    egui::Spinner::new(),
+                );
+            }
+            Some(Ok(rendered)) => {
+                let texture = self.texture.get_or_insert_with(|| {
+                    ui.ctx().load_texture(
+                        "file_preview",
+                        rendered.image.clone(),
+                        TextureOptions::LINEAR,
+                    )
+                });
+                ui.add(egui::Image::new((
+                    texture.id(),
+                    egui::vec2(PREVIEW_WIDTH as f32, PREVIEW_HEIGHT as f32),
+                )));
+                if rendered.frames > 1 {
+                    ui.label(format!(
+                        "{} samples, frame 0 of {}",
+                        rendered.samples, rendered.frames
+                    ));
+                } else {
+                    ui.label(format!("{} samples", rendered.samples));
+                }
+            }
+            Some(Err(e)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("No preview: {}", e));
+            }
+        }
+    }
+}
+
+/// Reads the first frame of `source` and renders it.
+fn render(source: &PreviewSource, color_scale: ColorScale) -> Result<Rendered, String> {
+    let path = source.path.to_string_lossy();
+    let format = source
+        .format
+        .or_else(|| guess_format(&path))
+        .ok_or_else(|| unrecognized_format(&path))?;
+    // Read only the first frame of the multi-frame files, or the whole file otherwise.
+    let (trace, frames) = match open_frames(&path, format, source.npy_layout) {
+        Ok(file) if file.frames() > 1 => (
+            file.read_frame(0).map_err(|e| e.to_string())?,
+            file.frames(),
+        ),
+        _ => {
+            let frames = load_file(
+                &path,
+                Some(format),
+                source.npy_layout,
+                source.skip_lines,
+                source.column,
+                source.dataset.as_deref(),
+                source.dtype,
+            )?;
+            let count = frames.len();
+            let trace = frames.into_iter().next().ok_or("the file holds no trace")?;
+            (trace, count)
+        }
+    };
+    let samples = trace.len();
+    let step = samples.div_ceil(MAX_PREVIEW_SAMPLES).max(1);
+    let decimated: Vec<f32> = trace.into_iter().step_by(step).collect();
+    let image = render_trace(&decimated, PREVIEW_WIDTH, PREVIEW_HEIGHT, color_scale)
+        .ok_or("the trace is too short")?;
+    Ok(Rendered {
+        image,
+        samples,
+        frames,
+    })
+}
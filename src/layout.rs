@@ -0,0 +1,43 @@
+/// Arrangement of the views in the main window. The views are laid out in rows of the same
+/// number of columns, from left to right and top to bottom.
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// One view per row, stacked from top to bottom.
+    #[default]
+    Stacked,
+    /// Two views per row.
+    Grid,
+    /// All the views side by side, in a single row.
+    SideBySide,
+    /// All the views overlaid in a single view, each trace with its own color, sharing the same
+    /// camera.
+    Overlay,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [
+        Layout::Stacked,
+        Layout::Grid,
+        Layout::SideBySide,
+        Layout::Overlay,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Stacked => "Stacked (1×N)",
+            Layout::Grid => "Grid (2×N)",
+            Layout::SideBySide => "Side by side",
+            Layout::Overlay => "Overlay all",
+        }
+    }
+
+    /// Returns the number of columns for laying out `count` views. The overlaid views are in a
+    /// single cell.
+    pub fn columns(self, count: usize) -> usize {
+        match self {
+            Layout::Stacked | Layout::Overlay => 1,
+            Layout::Grid => 2,
+            Layout::SideBySide => count.max(1),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! TurboPlot, a blazingly fast waveform renderer made for visualizing huge traces.
+//!
+//! The `turboplot` binary is a thin frontend over this library. Other tools can reuse its trace
+//! loaders, its processing steps and its density renderers: [`render`] renders traces to images
+//! without opening the viewer, [`GpuRenderer`] and [`CpuRenderer`] calculate the density of trace
+//! chunks and [`Tiling`] caches the rendered tiles of the viewer.
+
+mod acquisition;
+mod alignment;
+mod alignment_dialog;
+pub mod app;
+pub mod automation;
+pub mod batch;
+mod camera;
+mod clock;
+pub mod colorizer;
+pub mod config;
+mod difference;
+mod digital;
+mod dtw;
+mod error_dialog;
+mod events;
+pub mod export;
+mod expression;
+mod expression_dialog;
+mod filter_designer;
+mod filter_preview;
+pub mod filtering;
+pub mod format;
+mod frame_browser;
+mod group_average;
+pub mod input;
+mod layout;
+mod live;
+pub mod loaders;
+mod logging;
+mod multi_viewer;
+mod overlay;
+mod playback;
+pub mod processing;
+mod processing_menu;
+pub mod pyramid;
+mod rate_estimation;
+mod regions;
+mod remote;
+pub mod render;
+pub mod renderer;
+mod scpi;
+mod script;
+mod settings;
+mod sha256;
+mod snr;
+mod sync_features;
+pub mod thumbnails;
+pub mod tile_server;
+pub mod tiling;
+pub mod tiling_policy;
+pub mod trace;
+mod trace_data;
+mod transforms;
+mod util;
+mod viewer;
+mod watch;
+mod wavelet;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+pub use renderer::{CpuRenderer, GpuRenderer, Renderer};
+pub use tiling::Tiling;
@@ -0,0 +1,435 @@
+//! Live display of traces streamed by an acquisition loop.
+//!
+//! The capture script (for instance a ChipWhisperer loop) sends each trace over a TCP socket, a
+//! serial line or a named pipe, as a little-endian `u32` number of samples followed by the
+//! samples as little-endian `f32`. With Python:
+//!
+//! ```python
+//! sock.sendall(struct.pack("<I", len(trace)) + trace.astype("<f4").tobytes())
+//! ```
+//!
+//! Traces can also be captured from a benchtop scope, see [`crate::scpi`]. The last received
+//! traces are displayed one after the other in a rolling viewer.
+//!
+//! Streaming rigs can instead connect to TurboPlot listening on a TCP or UDP port, and send a
+//! continuous stream of little-endian `f32` samples, without header. The samples are appended to
+//! a single growing trace, displayed as a strip chart. Only the last samples of the trace are
+//! kept, up to `--strip-length`.
+
+use crate::{
+    loaders::{SampleType, TraceFormat, decode_raw, parse_csv_value},
+    scpi::{ScopeAddress, acquire},
+    trace::{Trace, TraceStream},
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Maximum number of samples of a streamed trace. Larger counts are considered as a corrupted
+/// stream.
+const MAX_TRACE_SAMPLES: usize = 1 << 28;
+
+/// Size of the blocks of samples read from a listening socket.
+const LISTEN_BUFFER_SIZE: usize = 1 << 16;
+
+/// Traces received in background from a live source.
+pub struct LiveStream {
+    /// Description of the source, for display.
+    source: String,
+    receiver: Receiver<Vec<f32>>,
+    /// Sampling rate in MS/s reported by the source, if any.
+    sampling_rate: Arc<Mutex<Option<f32>>>,
+    /// Set by the receiving thread when the stream ends.
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl LiveStream {
+    /// Starts receiving traces from `source`: a scope address (see [`ScopeAddress::parse`]),
+    /// polled every `interval`, `HOST:PORT` for a TCP connection, or the path of a serial device
+    /// or a named pipe. Received traces wake up the user interface.
+    pub fn connect(ctx: &egui::Context, source: &str, interval: Duration) -> Self {
+        let thread_source = source.to_string();
+        Self::spawn(ctx, source, move |deliver| {
+            match ScopeAddress::parse(&thread_source) {
+                Some(address) => acquire(&address, interval, deliver),
+                None => open(&thread_source).and_then(|reader| receive(reader, deliver)),
+            }
+        })
+    }
+
+    /// Starts listening on `uri`, `tcp://HOST:PORT` or `udp://HOST:PORT`, for streams of samples.
+    /// The blocks of samples are received as traces, to be appended to each other.
+    pub fn listen(ctx: &egui::Context, uri: &str) -> Self {
+        let thread_uri = uri.to_string();
+        Self::spawn(ctx, uri, move |deliver| {
+            if let Some(address) = thread_uri.strip_prefix("tcp://") {
+                listen_tcp(address, deliver)
+            } else if let Some(address) = thread_uri.strip_prefix("udp://") {
+                listen_udp(address, deliver)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "expected tcp://HOST:PORT or udp://HOST:PORT",
+                ))
+            }
+        })
+    }
+
+    /// Starts reading the samples piped to the standard input as they arrive: raw samples of type
+    /// `dtype` with the `raw` format, or the values of `column` of the CSV lines after the
+    /// `skip_lines` first ones otherwise. The samples are received as traces, to be appended to
+    /// each other.
+    pub fn stdin(
+        ctx: &egui::Context,
+        format: Option<TraceFormat>,
+        dtype: SampleType,
+        skip_lines: usize,
+        column: usize,
+    ) -> Self {
+        Self::spawn(ctx, "stdin", move |deliver| {
+            match format {
+                Some(TraceFormat::Raw) => receive_samples(&mut io::stdin(), dtype, deliver),
+                None | Some(TraceFormat::Csv) => {
+                    receive_csv(io::stdin(), skip_lines, column, deliver)
+                }
+                Some(_) => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only raw and CSV samples can be streamed",
+                )),
+            }
+            .map(|_| ())
+        })
+    }
+
+    /// Runs `run` in a background thread, with a function delivering the received traces and the
+    /// sampling rate of the source, if known. The function returns false when the viewer has
+    /// been closed.
+    fn spawn(
+        ctx: &egui::Context,
+        source: &str,
+        run: impl FnOnce(&mut dyn FnMut(Vec<f32>, Option<f32>) -> bool) -> io::Result<()>
+        + Send
+        + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let sampling_rate = Arc::new(Mutex::new(None));
+        let error = Arc::new(Mutex::new(None));
+        let ctx = ctx.clone();
+        let thread_rate = sampling_rate.clone();
+        let thread_error = error.clone();
+        thread::spawn(move || {
+            let mut deliver = |trace: Vec<f32>, rate: Option<f32>| {
+                if rate.is_some() {
+                    *thread_rate.lock().unwrap() = rate;
+                }
+                // Fails when the viewer has been closed.
+                let delivered = sender.send(trace).is_ok();
+                ctx.request_repaint();
+                delivered
+            };
+            let message = match run(&mut deliver) {
+                Ok(()) => "Stream closed".to_string(),
+                Err(e) => format!("Stream error: {}", e),
+            };
+            *thread_error.lock().unwrap() = Some(message);
+            ctx.request_repaint();
+        });
+        Self {
+            source: source.to_string(),
+            receiver,
+            sampling_rate,
+            error,
+        }
+    }
+
+    /// Returns the traces received since the last call.
+    pub fn receive(&self) -> Vec<Vec<f32>> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Returns why the stream has ended, if it has.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// Returns the sampling rate in MS/s of the last trace, if the source reports it.
+    pub fn sampling_rate(&self) -> Option<f32> {
+        *self.sampling_rate.lock().unwrap()
+    }
+}
+
+/// Opens the live source, as a TCP connection if it is a `HOST:PORT` address, as a file
+/// otherwise.
+fn open(source: &str) -> io::Result<Box<dyn Read + Send>> {
+    let is_address = source.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty() && !host.contains('/') && port.parse::<u16>().is_ok()
+    });
+    if is_address {
+        Ok(Box::new(TcpStream::connect(source)?))
+    } else {
+        Ok(Box::new(File::open(source)?))
+    }
+}
+
+/// Receives the traces from `reader` until the end of the stream, and gives them to `deliver`
+/// until it returns false. Returns `Ok` when the stream ends between two traces.
+fn receive(
+    reader: Box<dyn Read + Send>,
+    mut deliver: impl FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut header = [0; 4];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(header) as usize;
+        if len > MAX_TRACE_SAMPLES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid trace length {}", len),
+            ));
+        }
+        let mut bytes = vec![0; len * 4];
+        reader.read_exact(&mut bytes)?;
+        let (trace, _) = decode_raw(&bytes, SampleType::F32);
+        if !deliver(trace, None) {
+            return Ok(());
+        }
+    }
+}
+
+/// Gives the samples of type `dtype` streamed by `reader` to `deliver`, as they are read. Samples
+/// split between two reads are kept for the next one. Returns false if `deliver` has returned
+/// false, true at the end of the stream.
+fn receive_samples(
+    reader: &mut dyn Read,
+    dtype: SampleType,
+    deliver: &mut dyn FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<bool> {
+    let mut buffer = vec![0; LISTEN_BUFFER_SIZE];
+    let mut len = 0;
+    loop {
+        let read = match reader.read(&mut buffer[len..]) {
+            Ok(0) => return Ok(true),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        len += read;
+        let (samples, consumed) = decode_raw(&buffer[..len], dtype);
+        buffer.copy_within(consumed..len, 0);
+        len -= consumed;
+        if !samples.is_empty() && !deliver(samples, None) {
+            return Ok(false);
+        }
+    }
+}
+
+/// Gives the values of `column` of the CSV lines streamed by `reader` after the `skip` first ones
+/// to `deliver`, as they are read. Returns false if `deliver` has returned false, true at the end
+/// of the stream.
+fn receive_csv(
+    reader: impl Read,
+    skip: usize,
+    column: usize,
+    deliver: &mut dyn FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<bool> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut number = 0;
+    let mut samples = Vec::new();
+    loop {
+        line.clear();
+        let end = reader.read_line(&mut line)? == 0;
+        if !end {
+            number += 1;
+            if number > skip {
+                let line = line.trim_end_matches(['\r', '\n']);
+                samples.push(parse_csv_value(line, number, column)?);
+            }
+        }
+        // The values are delivered once all the received lines have been read, instead of one
+        // at a time.
+        if (end || reader.buffer().is_empty())
+            && !samples.is_empty()
+            && !deliver(std::mem::take(&mut samples), None)
+        {
+            return Ok(false);
+        }
+        if end {
+            return Ok(true);
+        }
+    }
+}
+
+/// Accepts TCP connections on `address`, one at a time, and gives the samples they stream to
+/// `deliver` until it returns false.
+fn listen_tcp(
+    address: &str,
+    deliver: &mut dyn FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    tracing::info!(target: "loader", "Listening on tcp://{}", listener.local_addr()?);
+    loop {
+        let (mut stream, peer) = listener.accept()?;
+        tracing::info!(target: "loader", "Receiving samples from {}", peer);
+        match receive_samples(&mut stream, SampleType::F32, deliver) {
+            Ok(true) => tracing::info!(target: "loader", "Connection from {} closed", peer),
+            Ok(false) => return Ok(()),
+            Err(e) => tracing::warn!(target: "loader", "Connection from {} lost: {}", peer, e),
+        }
+    }
+}
+
+/// Receives the datagrams sent to `address`, each holding whole samples, and gives their samples
+/// to `deliver` until it returns false.
+fn listen_udp(
+    address: &str,
+    deliver: &mut dyn FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(address)?;
+    tracing::info!(target: "loader", "Listening on udp://{}", socket.local_addr()?);
+    let mut buffer = vec![0; LISTEN_BUFFER_SIZE];
+    loop {
+        let (len, _) = socket.recv_from(&mut buffer)?;
+        let (samples, _) = decode_raw(&buffer[..len], SampleType::F32);
+        if !samples.is_empty() && !deliver(samples, None) {
+            return Ok(());
+        }
+    }
+}
+
+/// Samples received by a live source since the last update.
+pub enum LiveUpdate {
+    /// Trace replacing the displayed one.
+    Trace(Vec<f32>),
+    /// Trace of a strip chart, made of the displayed one and the received samples.
+    Samples {
+        trace: Trace,
+        /// Index in the trace of the first received sample.
+        start: usize,
+        /// Number of samples dropped from the start of the displayed trace.
+        dropped: usize,
+    },
+}
+
+impl LiveUpdate {
+    /// Returns the trace to display as a new one.
+    pub fn into_trace(self) -> Trace {
+        match self {
+            LiveUpdate::Trace(samples) => samples.into(),
+            LiveUpdate::Samples { trace, .. } => trace,
+        }
+    }
+}
+
+/// Live source of a viewer, with the last received traces.
+pub struct Live {
+    stream: LiveStream,
+    /// Last received traces, oldest first. Unused by strip charts.
+    history: VecDeque<Vec<f32>>,
+    /// Maximum number of traces in `history`.
+    capacity: usize,
+    /// Samples of a strip chart, appending the received samples to the trace. `None` for the
+    /// other sources.
+    strip: Option<TraceStream>,
+    /// Number of traces, or samples for a strip chart, received since the connection.
+    received: usize,
+    /// When true, the camera follows the end of the trace as new traces arrive.
+    pub follow: bool,
+}
+
+impl Live {
+    pub fn new(stream: LiveStream, capacity: usize) -> Self {
+        Self {
+            stream,
+            history: VecDeque::new(),
+            capacity: capacity.max(1),
+            strip: None,
+            received: 0,
+            follow: true,
+        }
+    }
+
+    /// Creates a strip chart, appending the samples received from `stream` to the trace, and
+    /// keeping at least its last `max_len` samples.
+    pub fn strip_chart(stream: LiveStream, max_len: usize) -> Self {
+        Self {
+            strip: Some(TraceStream::new(max_len.max(2))),
+            ..Self::new(stream, 1)
+        }
+    }
+
+    /// Appends `trace` to the history, dropping the oldest traces beyond the capacity.
+    fn push(&mut self, trace: Vec<f32>, capacity: usize) {
+        self.history.push_back(trace);
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+        self.received += 1;
+    }
+
+    /// Receives the new traces. Returns the concatenation of the traces of the history if some
+    /// have been received, and if it has enough samples to be rendered. Strip charts return the
+    /// received samples instead, once at least two samples have been received.
+    pub fn update(&mut self) -> Option<LiveUpdate> {
+        let traces = self.stream.receive();
+        if let Some(strip) = &mut self.strip {
+            let (start, received) = (strip.len(), self.received);
+            let mut dropped = 0;
+            for samples in &traces {
+                dropped += strip.append(samples);
+                self.received += samples.len();
+            }
+            // Samples are kept in the stream until the trace can be rendered.
+            if self.received == received || self.received < 2 {
+                return None;
+            }
+            return Some(LiveUpdate::Samples {
+                trace: strip.trace(),
+                start: start.saturating_sub(dropped),
+                dropped,
+            });
+        }
+        if traces.is_empty() {
+            return None;
+        }
+        for trace in traces {
+            self.push(trace, self.capacity);
+        }
+        let trace: Vec<f32> = self.history.iter().flatten().copied().collect();
+        (trace.len() >= 2).then_some(LiveUpdate::Trace(trace))
+    }
+
+    /// Describes the number of received traces or samples, for display.
+    pub fn status(&self) -> String {
+        match self.strip {
+            None => format!("Live: {} traces", self.received),
+            Some(_) => format!("Live: {} samples", self.received),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.stream.source
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.stream.error()
+    }
+
+    pub fn sampling_rate(&self) -> Option<f32> {
+        self.stream.sampling_rate()
+    }
+}
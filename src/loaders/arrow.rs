@@ -0,0 +1,390 @@
+//! Feather file parser (`.feather`, `.arrow`), reading the numeric columns of Feather version 2
+//! files, which are Arrow IPC files.
+//!
+//! Reference: Apache Arrow columnar format specification, "IPC File Format", and the `File.fbs`,
+//! `Message.fbs` and `Schema.fbs` definitions of its FlatBuffers metadata. Only the record batches
+//! with uncompressed buffers are read.
+
+use super::parquet::ColumnInfo;
+use std::io;
+
+/// Magic bytes at the start and at the end of the files.
+const MAGIC: &[u8; 6] = b"ARROW1";
+
+/// Type tags of the fields.
+const TYPE_NULL: u8 = 1;
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_BINARY: u8 = 4;
+const TYPE_UTF8: u8 = 5;
+const TYPE_BOOL: u8 = 6;
+const TYPE_LIST: u8 = 12;
+const TYPE_STRUCT: u8 = 13;
+const TYPE_FIXED_SIZE_LIST: u8 = 16;
+const TYPE_LARGE_BINARY: u8 = 19;
+const TYPE_LARGE_UTF8: u8 = 20;
+const TYPE_LARGE_LIST: u8 = 21;
+
+/// Header type of the record batch messages.
+const RECORD_BATCH: u8 = 3;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn unsupported(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.into())
+}
+
+/// A table of a FlatBuffers buffer.
+#[derive(Clone, Copy)]
+struct Table<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn u32_at(data: &[u8], pos: usize) -> io::Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid("truncated metadata"))
+}
+
+fn i64_at(data: &[u8], pos: usize) -> io::Result<i64> {
+    data.get(pos..pos + 8)
+        .map(|bytes| i64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid("truncated metadata"))
+}
+
+impl<'a> Table<'a> {
+    /// Returns the root table of a FlatBuffers buffer.
+    fn root(data: &'a [u8]) -> io::Result<Self> {
+        Ok(Self {
+            data,
+            pos: u32_at(data, 0)? as usize,
+        })
+    }
+
+    /// Returns the position of the field `index`, if present.
+    fn field(&self, index: usize) -> io::Result<Option<usize>> {
+        let vtable = (self.pos as i64 - u32_at(self.data, self.pos)? as i32 as i64) as usize;
+        let vtable_len = self
+            .data
+            .get(vtable..vtable + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        let entry = 4 + 2 * index;
+        if entry + 2 > vtable_len {
+            return Ok(None);
+        }
+        let offset = self
+            .data
+            .get(vtable + entry..vtable + entry + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        Ok((offset != 0).then_some(self.pos + offset))
+    }
+
+    fn u8(&self, index: usize) -> io::Result<u8> {
+        Ok(match self.field(index)? {
+            Some(pos) => *self
+                .data
+                .get(pos)
+                .ok_or_else(|| invalid("truncated metadata"))?,
+            None => 0,
+        })
+    }
+
+    fn i16(&self, index: usize) -> io::Result<i16> {
+        Ok(match self.field(index)? {
+            Some(pos) => self
+                .data
+                .get(pos..pos + 2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .ok_or_else(|| invalid("truncated metadata"))?,
+            None => 0,
+        })
+    }
+
+    fn i32(&self, index: usize) -> io::Result<i32> {
+        Ok(match self.field(index)? {
+            Some(pos) => u32_at(self.data, pos)? as i32,
+            None => 0,
+        })
+    }
+
+    fn i64(&self, index: usize) -> io::Result<i64> {
+        Ok(match self.field(index)? {
+            Some(pos) => i64_at(self.data, pos)?,
+            None => 0,
+        })
+    }
+
+    /// Follows the offset stored in the field `index`.
+    fn indirect(&self, index: usize) -> io::Result<Option<usize>> {
+        match self.field(index)? {
+            Some(pos) => Ok(Some(pos + u32_at(self.data, pos)? as usize)),
+            None => Ok(None),
+        }
+    }
+
+    fn table(&self, index: usize) -> io::Result<Option<Table<'a>>> {
+        Ok(self.indirect(index)?.map(|pos| Table {
+            data: self.data,
+            pos,
+        }))
+    }
+
+    fn string(&self, index: usize) -> io::Result<String> {
+        let Some(pos) = self.indirect(index)? else {
+            return Ok(String::new());
+        };
+        let len = u32_at(self.data, pos)? as usize;
+        let bytes = self
+            .data
+            .get(pos + 4..pos + 4 + len)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Returns the position and the length of the vector of the field `index`.
+    fn vector(&self, index: usize) -> io::Result<(usize, usize)> {
+        match self.indirect(index)? {
+            Some(pos) => Ok((pos + 4, u32_at(self.data, pos)? as usize)),
+            None => Ok((0, 0)),
+        }
+    }
+
+    /// Returns the tables of the vector of the field `index`.
+    fn tables(&self, index: usize) -> io::Result<Vec<Table<'a>>> {
+        let (start, len) = self.vector(index)?;
+        (0..len)
+            .map(|i| {
+                let pos = start + 4 * i;
+                Ok(Table {
+                    data: self.data,
+                    pos: pos + u32_at(self.data, pos)? as usize,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A field of the schema, flattened in depth-first order.
+struct Field {
+    info: ColumnInfo,
+    type_tag: u8,
+    /// Width of the integers and floats in bits, and signedness of the integers.
+    bit_width: u32,
+    signed: bool,
+    /// Number of buffers of the field in the record batches.
+    buffers: usize,
+}
+
+/// Flattens the fields of the schema in depth-first order, as their nodes and buffers in the
+/// record batches.
+fn flatten(fields: Vec<Table>, prefix: &str, flat: &mut Vec<Field>) -> io::Result<()> {
+    for field in fields {
+        let name = field.string(0)?;
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        let type_tag = field.u8(2)?;
+        let ty = field.table(3)?;
+        let (ty_name, bit_width, signed, buffers) = match type_tag {
+            TYPE_NULL => ("null".to_string(), 0, false, 0),
+            TYPE_INT => {
+                let ty = ty.ok_or_else(|| invalid("integer field without type"))?;
+                let bit_width = ty.i32(0)? as u32;
+                let signed = ty.u8(1)? != 0;
+                let name = format!("{}int{}", if signed { "" } else { "u" }, bit_width);
+                (name, bit_width, signed, 2)
+            }
+            TYPE_FLOATING_POINT => {
+                let ty = ty.ok_or_else(|| invalid("float field without type"))?;
+                let bit_width = match ty.i16(0)? {
+                    0 => 16,
+                    1 => 32,
+                    _ => 64,
+                };
+                (format!("float{}", bit_width), bit_width, true, 2)
+            }
+            TYPE_BOOL => ("bool".to_string(), 1, false, 2),
+            TYPE_BINARY | TYPE_LARGE_BINARY => ("binary".to_string(), 0, false, 3),
+            TYPE_UTF8 | TYPE_LARGE_UTF8 => ("utf8".to_string(), 0, false, 3),
+            TYPE_LIST | TYPE_LARGE_LIST => ("list".to_string(), 0, false, 2),
+            TYPE_STRUCT | TYPE_FIXED_SIZE_LIST => ("struct".to_string(), 0, false, 1),
+            // The other types have two buffers: validity and values.
+            tag => (format!("type {}", tag), 0, false, 2),
+        };
+        flat.push(Field {
+            info: ColumnInfo {
+                name: name.clone(),
+                ty: ty_name,
+            },
+            type_tag,
+            bit_width,
+            signed,
+            buffers,
+        });
+        flatten(field.tables(5)?, &name, flat)?;
+    }
+    Ok(())
+}
+
+/// Location of a record batch in the file.
+struct Block {
+    /// Position of the message holding the metadata of the batch.
+    offset: usize,
+    /// Length of the metadata, after which the body of the batch starts.
+    metadata_len: usize,
+}
+
+/// Parses the footer of a Feather file: its fields, and the location of its record batches.
+fn read_footer(data: &[u8]) -> io::Result<(Vec<Field>, Vec<Block>)> {
+    if data.len() < 18 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        return Err(invalid("not a Feather version 2 file"));
+    }
+    let len = u32_at(data, data.len() - 10)? as usize;
+    let start = (data.len() - 10)
+        .checked_sub(len)
+        .ok_or_else(|| invalid("invalid footer length"))?;
+    let footer = Table::root(&data[start..data.len() - 10])?;
+    let schema = footer
+        .table(1)?
+        .ok_or_else(|| invalid("footer without schema"))?;
+    if schema.i16(0)? != 0 {
+        return Err(unsupported("big-endian Feather files are not supported"));
+    }
+    let mut fields = Vec::new();
+    flatten(schema.tables(1)?, "", &mut fields)?;
+    // Blocks are structures of an offset, a metadata length and a body length.
+    let (blocks, count) = footer.vector(3)?;
+    let batches = (0..count)
+        .map(|i| {
+            let block = blocks + 24 * i;
+            let offset = i64_at(footer.data, block)? as usize;
+            let metadata_len = u32_at(footer.data, block + 8)? as usize;
+            Ok(Block {
+                offset,
+                metadata_len,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    Ok((fields, batches))
+}
+
+/// Returns true if `data` starts like a Feather version 2 file.
+pub fn is_arrow(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Lists the fields of a Feather file, with the number of rows.
+pub fn arrow_columns(data: &[u8]) -> io::Result<(Vec<ColumnInfo>, usize)> {
+    let (fields, batches) = read_footer(data)?;
+    let mut rows = 0;
+    for block in batches {
+        rows += record_batch(data, block.offset)?.i64(0)? as usize;
+    }
+    Ok((fields.into_iter().map(|f| f.info).collect(), rows))
+}
+
+/// Returns the record batch header of the message at `offset`.
+fn record_batch(data: &[u8], offset: usize) -> io::Result<Table<'_>> {
+    // Messages are prefixed with a continuation marker and their length, or only their length in
+    // the files written before Arrow 0.15.
+    let mut start = offset + 4;
+    if u32_at(data, offset)? == 0xffff_ffff {
+        start += 4;
+    }
+    let message = Table::root(
+        data.get(start..)
+            .ok_or_else(|| invalid("record batch beyond the file"))?,
+    )?;
+    if message.u8(1)? != RECORD_BATCH {
+        return Err(invalid("block is not a record batch"));
+    }
+    message
+        .table(2)?
+        .ok_or_else(|| invalid("record batch without header"))
+}
+
+/// Reads the column `index` of a Feather file, concatenating its record batches. Null values are
+/// NaN.
+pub fn read_arrow_column(data: &[u8], index: usize) -> io::Result<Vec<f32>> {
+    let (fields, batches) = read_footer(data)?;
+    let field = &fields[index];
+    let width = match (field.type_tag, field.bit_width) {
+        (TYPE_INT, 8 | 16 | 32 | 64) | (TYPE_FLOATING_POINT, 32 | 64) => {
+            field.bit_width as usize / 8
+        }
+        _ => {
+            return Err(unsupported(format!(
+                "column {} of type {} is not numeric",
+                field.info.name, field.info.ty
+            )));
+        }
+    };
+    // Position of the node and of the buffers of the column in the record batches.
+    let node = index;
+    let buffer = fields[..index].iter().map(|f| f.buffers).sum::<usize>();
+
+    let mut values = Vec::new();
+    for block in batches {
+        let batch = record_batch(data, block.offset)?;
+        if batch.table(3)?.is_some() {
+            return Err(unsupported(
+                "compressed Feather files are not supported, write them uncompressed",
+            ));
+        }
+        let body = block.offset + block.metadata_len;
+        let (nodes, _) = batch.vector(1)?;
+        let length = i64_at(batch.data, nodes + 16 * node)? as usize;
+        let null_count = i64_at(batch.data, nodes + 16 * node + 8)?;
+        let (buffers, _) = batch.vector(2)?;
+        let buffer_at = |i: usize| -> io::Result<&[u8]> {
+            let start = body + i64_at(batch.data, buffers + 16 * i)? as usize;
+            let len = i64_at(batch.data, buffers + 16 * i + 8)? as usize;
+            data.get(start..start + len)
+                .ok_or_else(|| invalid("buffer beyond the file"))
+        };
+        let validity = buffer_at(buffer)?;
+        let samples = buffer_at(buffer + 1)?;
+        if samples.len() < length * width {
+            return Err(invalid("truncated buffer"));
+        }
+        values.extend(
+            samples
+                .chunks_exact(width)
+                .take(length)
+                .enumerate()
+                .map(|(i, bytes)| {
+                    if null_count > 0
+                        && validity
+                            .get(i / 8)
+                            .is_some_and(|&bits| bits & (1 << (i % 8)) == 0)
+                    {
+                        return f32::NAN;
+                    }
+                    match (field.type_tag, width, field.signed) {
+                        (TYPE_FLOATING_POINT, 4, _) => {
+                            f32::from_le_bytes(bytes.try_into().unwrap())
+                        }
+                        (TYPE_FLOATING_POINT, _, _) => {
+                            f64::from_le_bytes(bytes.try_into().unwrap()) as f32
+                        }
+                        (_, 1, true) => bytes[0] as i8 as f32,
+                        (_, 1, false) => bytes[0] as f32,
+                        (_, 2, true) => i16::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        (_, 2, false) => u16::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        (_, 4, true) => i32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        (_, 4, false) => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        (_, _, true) => i64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                        (_, _, false) => u64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                    }
+                }),
+        );
+    }
+    Ok(values)
+}
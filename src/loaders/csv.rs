@@ -1,17 +1,27 @@
+use super::LoadError;
 use std::io::BufRead;
 
 /// Loads a CSV file.
 ///
 /// `skip` indicates how many lines must be skipped before starting to read the values.
 /// `column` is the column number (starting from 0) containing the values.
-pub fn load_csv<R: BufRead>(reader: R, skip: usize, column: usize) -> Vec<f32> {
+pub fn load_csv<R: BufRead>(reader: R, skip: usize, column: usize) -> Result<Vec<f32>, LoadError> {
     reader
         .lines()
+        .enumerate()
         .skip(skip)
-        .map(|l| {
-            let line = l.unwrap();
-            let value = line.split(",").nth(column).unwrap();
-            value.parse::<f32>().unwrap()
-        })
+        .map(|(i, l)| parse_csv_value(&l?, i + 1, column))
         .collect()
 }
+
+/// Parses the value of `column` in `line`, the line `number` of a CSV file, counting from 1.
+pub fn parse_csv_value(line: &str, number: usize, column: usize) -> Result<f32, LoadError> {
+    let value = line
+        .split(",")
+        .nth(column)
+        .ok_or_else(|| LoadError::invalid(format!("line {}: no column {}", number, column)))?;
+    value
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| LoadError::invalid(format!("line {}: invalid value {:?}", number, value)))
+}
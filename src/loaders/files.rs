@@ -0,0 +1,249 @@
+//! Access to the trace files. In the browser, there is no file system: the files picked by the
+//! user are kept in memory, and opened by their name.
+//!
+//! Compressed files are decompressed in memory when they are opened with [`open_reader`], so the
+//! loaders read them as the uncompressed files.
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::{
+    StreamingDecoder,
+    errors::{FrameDecoderError, ReadFrameHeaderError},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex};
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Path standing for the standard input, as in `turboplot -`.
+pub const STDIN_PATH: &str = "-";
+
+#[cfg(not(target_arch = "wasm32"))]
+pub enum FileReader {
+    File(std::fs::File),
+    /// The standard input, read entirely the first time it is opened as it cannot be read twice.
+    Stdin(io::Cursor<Arc<[u8]>>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Stdin(data) => data.read(buf),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Seek for FileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Stdin(data) => data.seek(pos),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub type FileReader = io::Cursor<std::sync::Arc<[u8]>>;
+
+/// Opens the trace file at `path` for reading, or the standard input for [`STDIN_PATH`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_file(path: impl AsRef<Path>) -> io::Result<FileReader> {
+    if path.as_ref() == Path::new(STDIN_PATH) {
+        return Ok(FileReader::Stdin(io::Cursor::new(read_stdin()?)));
+    }
+    std::fs::File::open(path).map(FileReader::File)
+}
+
+/// Returns the content of the standard input, read entirely by the first call.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin() -> io::Result<Arc<[u8]>> {
+    static STDIN: Mutex<Option<Arc<[u8]>>> = Mutex::new(None);
+    let mut stdin = STDIN.lock().unwrap();
+    if let Some(data) = &*stdin {
+        return Ok(data.clone());
+    }
+    let mut data = Vec::new();
+    io::stdin().lock().read_to_end(&mut data)?;
+    let data: Arc<[u8]> = data.into();
+    *stdin = Some(data.clone());
+    Ok(data)
+}
+
+/// Opens the trace file named `path`, picked in the browser.
+#[cfg(target_arch = "wasm32")]
+pub fn open_file(path: impl AsRef<Path>) -> io::Result<FileReader> {
+    browser::FILES
+        .lock()
+        .unwrap()
+        .get(path.as_ref())
+        .map(|data| io::Cursor::new(data.clone()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not picked"))
+}
+
+/// Compression layers around the trace files, recognized by their first bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Extensions of the compressed files, which are removed to guess the format of their content.
+    pub const EXTENSIONS: [&str; 2] = ["gz", "zst"];
+
+    /// Recognizes the compression of a file from its first bytes.
+    pub fn detect(start: &[u8]) -> Option<Self> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the compression of the file at `path`, if any.
+pub fn file_compression(path: impl AsRef<Path>) -> io::Result<Option<Compression>> {
+    let mut start = Vec::new();
+    open_file(path)?.take(4).read_to_end(&mut start)?;
+    Ok(Compression::detect(&start))
+}
+
+/// Decompresses the whole content of `reader`.
+fn decompress(mut reader: impl Read, compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::Gzip => {
+            let mut data = Vec::new();
+            MultiGzDecoder::new(reader).read_to_end(&mut data)?;
+            Ok(data)
+        }
+        Compression::Zstd => {
+            let mut compressed = Vec::new();
+            reader.read_to_end(&mut compressed)?;
+            decompress_zstd(&compressed)
+        }
+    }
+}
+
+/// Decompresses the Zstandard frames of `data` one after the other, like `zstd -d`, skipping the
+/// skippable frames.
+fn decompress_zstd(mut data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    while !data.is_empty() {
+        match StreamingDecoder::new(&mut data) {
+            Ok(mut decoder) => {
+                decoder.read_to_end(&mut decompressed)?;
+            }
+            Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                length,
+                ..
+            })) => {
+                data = data.get(length as usize..).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated Zstandard frame")
+                })?;
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        }
+    }
+    Ok(decompressed)
+}
+
+/// A trace file opened for loading, decompressed in memory if it is compressed.
+pub enum TraceReader {
+    File(BufReader<FileReader>),
+    Decompressed(Cursor<Vec<u8>>),
+}
+
+impl Read for TraceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(reader) => reader.read(buf),
+            Self::Decompressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl BufRead for TraceReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::File(reader) => reader.fill_buf(),
+            Self::Decompressed(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Self::File(reader) => reader.consume(amount),
+            Self::Decompressed(reader) => reader.consume(amount),
+        }
+    }
+}
+
+impl Seek for TraceReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(reader) => reader.seek(pos),
+            Self::Decompressed(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// Reads the `len` first bytes of the file at `path`, or of its content if it is compressed.
+pub fn read_start(path: impl AsRef<Path>, len: u64) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(open_file(path)?);
+    let mut start = Vec::new();
+    match Compression::detect(reader.fill_buf()?) {
+        Some(Compression::Gzip) => {
+            MultiGzDecoder::new(reader)
+                .take(len)
+                .read_to_end(&mut start)?;
+        }
+        Some(Compression::Zstd) => {
+            StreamingDecoder::new(reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+                .take(len)
+                .read_to_end(&mut start)?;
+        }
+        None => {
+            reader.take(len).read_to_end(&mut start)?;
+        }
+    }
+    Ok(start)
+}
+
+/// Opens the trace file at `path` for loading it entirely, decompressing it if needed.
+pub fn open_reader(path: impl AsRef<Path>) -> io::Result<TraceReader> {
+    let mut reader = BufReader::new(open_file(path)?);
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Some(compression) => {
+            TraceReader::Decompressed(Cursor::new(decompress(reader, compression)?))
+        }
+        None => TraceReader::File(reader),
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use browser::add_file;
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, LazyLock, Mutex},
+    };
+
+    /// Contents of the files picked in the browser, by name.
+    pub static FILES: LazyLock<Mutex<HashMap<PathBuf, Arc<[u8]>>>> =
+        LazyLock::new(Default::default);
+
+    /// Keeps the contents of a file picked in the browser, so it can be opened as `name`.
+    pub fn add_file(name: String, data: Vec<u8>) {
+        FILES.lock().unwrap().insert(name.into(), data.into());
+    }
+}
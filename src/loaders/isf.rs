@@ -0,0 +1,350 @@
+//! Tektronix internal save format parser (`.isf`), as saved by the TDS, DPO and MSO scopes.
+//!
+//! Reference: Tektronix programmer manuals, `WFMPre` (`WFMOutpre` on the recent scopes) and
+//! `CURVe` commands. An ISF file holds the answer of the scope to `WFMPre?;CURVe?`: the preamble
+//! settings separated by `;`, followed by the curve, usually as a binary block
+//! `#<digits><length><data>`.
+
+use super::{ExplicitFormat, LoadError, decode_curve, files::read_start};
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Maximum size of the preamble, read up to the curve to open a file without loading it.
+const PREAMBLE_MAX_SIZE: u64 = 4096;
+
+/// Returns true if `data` starts with the preamble of an ISF file.
+pub fn is_isf(data: &[u8]) -> bool {
+    let data = data.trim_ascii_start();
+    data.starts_with(b":WFMP") || data.starts_with(b":WFMO")
+}
+
+/// Settings of the preamble, keyed by their header without the `:WFMPRE:` prefix.
+struct Settings(Vec<(String, String)>);
+
+impl Settings {
+    fn parse(preamble: &str) -> Self {
+        // Settings are separated by `;`, which may also appear in the quoted strings.
+        let mut commands = vec![String::new()];
+        let mut quoted = false;
+        for c in preamble.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                ';' if !quoted => commands.push(String::new()),
+                _ => commands.last_mut().unwrap().push(c),
+            }
+        }
+        let settings = commands
+            .iter()
+            .filter_map(|command| {
+                let (header, value) = command.trim().split_once(char::is_whitespace)?;
+                let header = header.rsplit(':').next()?.to_ascii_uppercase();
+                Some((header, value.trim().to_string()))
+            })
+            .collect();
+        Self(settings)
+    }
+
+    /// Returns the value of the setting whose header is `long`, or any of its abbreviations down
+    /// to `short`, as the scopes may write either.
+    fn get(&self, short: &str, long: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(header, _)| header.starts_with(short) && long.starts_with(header.as_str()))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, short: &str, long: &str) -> Result<Option<f64>, LoadError> {
+        self.get(short, long)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| LoadError::invalid(format!("invalid {} {}", long, value)))
+            })
+            .transpose()
+    }
+}
+
+/// Encoding and scaling of the curve of an ISF file, read from its preamble.
+struct IsfLayout {
+    /// Description of the waveform, such as the channel and its settings.
+    label: String,
+    /// Curve written as comma-separated numbers instead of a binary block.
+    ascii: bool,
+    format: ExplicitFormat,
+    little_endian: bool,
+    /// Scaling of the raw values: `value = (raw - YOFF) * YMULT + YZERO`.
+    y_mult: f64,
+    y_offset: f64,
+    y_zero: f64,
+    /// Number of points given by the preamble.
+    points: Option<usize>,
+    /// Sampling rate in S/s, NaN if unknown.
+    sampling_rate: f64,
+    /// Index of the trigger sample, which may be fractional or out of the curve.
+    trigger: Option<f64>,
+}
+
+impl IsfLayout {
+    fn summary(&self, path: &str) -> String {
+        let mut summary = format!("{}: Tektronix ISF, ", path);
+        if !self.label.is_empty() {
+            summary += &format!("{}, ", self.label);
+        }
+        summary += &format!(
+            "{}, {:.3} MS/s",
+            if self.ascii {
+                "ASCII".to_string()
+            } else {
+                format!("{:?}", self.format)
+            },
+            self.sampling_rate / 1e6
+        );
+        if let Some(points) = self.points {
+            summary += &format!(", {} pts", points);
+        }
+        if let Some(trigger) = self.trigger {
+            summary += &format!(", trigger at sample {:.1}", trigger);
+        }
+        summary
+    }
+
+    /// Converts the curve data to values.
+    fn decode(&self, curve: &[u8]) -> Result<Vec<f32>, LoadError> {
+        let offset = self.y_zero - self.y_offset * self.y_mult;
+        let mut values = if self.ascii {
+            String::from_utf8_lossy(curve)
+                .split(',')
+                .map(|raw| {
+                    let raw = raw.trim();
+                    raw.parse::<f64>()
+                        .map(|raw| (raw * self.y_mult + offset) as f32)
+                        .map_err(|_| LoadError::invalid(format!("invalid curve value {:?}", raw)))
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            decode_curve(curve, self.format, self.little_endian, self.y_mult, offset)
+        };
+        if let Some(points) = self.points {
+            values.truncate(points);
+        }
+        Ok(values)
+    }
+}
+
+/// Splits an ISF file into its preamble and the start of its curve, which follows the `CURVe`
+/// header.
+fn split_curve(data: &[u8]) -> Result<(String, &[u8]), LoadError> {
+    let start = data
+        .windows(4)
+        .position(|window| window.eq_ignore_ascii_case(b"CURV"))
+        .ok_or_else(|| LoadError::invalid("no curve in the ISF file"))?;
+    let preamble = String::from_utf8_lossy(&data[..start]).into_owned();
+    let header_end = data[start..]
+        .iter()
+        .position(|b| !b.is_ascii_alphabetic())
+        .map_or(data.len(), |end| start + end);
+    Ok((preamble, data[header_end..].trim_ascii_start()))
+}
+
+/// Parses the preamble of an ISF file.
+fn read_layout(preamble: &str) -> Result<IsfLayout, LoadError> {
+    let settings = Settings::parse(preamble);
+    let ascii = settings
+        .get("ENC", "ENCDG")
+        .is_some_and(|encoding| encoding.to_ascii_uppercase().starts_with("ASC"));
+    let bytes = settings.number("BYT_N", "BYT_NR")?.unwrap_or(1.0);
+    let binary_format = settings
+        .get("BN_F", "BN_FMT")
+        .unwrap_or("RI")
+        .to_ascii_uppercase();
+    let format = match (binary_format.as_str(), bytes as usize) {
+        ("RI", 1) => ExplicitFormat::Int8,
+        ("RI", 2) => ExplicitFormat::Int16,
+        ("RI", 4) => ExplicitFormat::Int32,
+        ("RP", 1) => ExplicitFormat::Uint8,
+        ("RP", 2) => ExplicitFormat::Uint16,
+        ("RP", 4) => ExplicitFormat::Uint32,
+        ("RP", 8) => ExplicitFormat::Uint64,
+        ("FP", 4) => ExplicitFormat::Fp32,
+        ("FP", 8) => ExplicitFormat::Fp64,
+        _ => {
+            return Err(LoadError::invalid(format!(
+                "unsupported ISF curve format {} with {} bytes per point",
+                binary_format, bytes
+            )));
+        }
+    };
+    let little_endian = settings
+        .get("BYT_O", "BYT_OR")
+        .is_some_and(|order| order.eq_ignore_ascii_case("LSB"));
+    let label = settings
+        .get("WFI", "WFID")
+        .map_or("", |label| label.trim_matches('"'))
+        .to_string();
+
+    // Time of sample n: XZERO + XINCR * (n - PT_OFF), the trigger being at time 0. Waveforms of
+    // other units, such as spectrums, have no sampling rate.
+    let time_axis = settings
+        .get("XUN", "XUNIT")
+        .is_none_or(|unit| unit.trim_matches('"').eq_ignore_ascii_case("s"));
+    let x_increment = settings
+        .number("XIN", "XINCR")?
+        .filter(|&x_increment| time_axis && x_increment > 0.0);
+    let sampling_rate = x_increment.map_or(f64::NAN, |x_increment| 1.0 / x_increment);
+    let trigger = x_increment
+        .map(|x_increment| {
+            Ok::<_, LoadError>(
+                settings.number("PT_O", "PT_OFF")?.unwrap_or(0.0)
+                    - settings.number("XZE", "XZERO")?.unwrap_or(0.0) / x_increment,
+            )
+        })
+        .transpose()?
+        .filter(|trigger| trigger.is_finite());
+
+    Ok(IsfLayout {
+        label,
+        ascii,
+        format,
+        little_endian,
+        y_mult: settings.number("YMU", "YMULT")?.unwrap_or(1.0),
+        y_offset: settings.number("YOF", "YOFF")?.unwrap_or(0.0),
+        y_zero: settings.number("YZE", "YZERO")?.unwrap_or(0.0),
+        points: settings
+            .number("NR_P", "NR_PT")?
+            .map(|points| points as usize),
+        sampling_rate,
+        trigger,
+    })
+}
+
+/// Returns the data of the binary block starting `curve`: `#<digits><length><data>`, or `#0`
+/// followed by the data up to the final newline.
+fn read_block(curve: &[u8]) -> Result<&[u8], LoadError> {
+    let invalid = || LoadError::invalid("invalid ISF curve block");
+    let digits = (*curve.get(1).ok_or_else(invalid)? as char)
+        .to_digit(10)
+        .ok_or_else(invalid)? as usize;
+    if digits == 0 {
+        let data = &curve[2..];
+        return Ok(data.strip_suffix(b"\n").unwrap_or(data));
+    }
+    let len = curve
+        .get(2..2 + digits)
+        .and_then(|len| String::from_utf8_lossy(len).parse::<usize>().ok())
+        .ok_or_else(invalid)?;
+    curve.get(2 + digits..2 + digits + len).ok_or_else(|| {
+        LoadError::invalid(format!(
+            "ISF curve of {} bytes extends beyond file: {} bytes left",
+            len,
+            curve.len() - 2 - digits
+        ))
+    })
+}
+
+/// Loads a Tektronix ISF file and returns its curve as a trace.
+///
+/// Raw curve values are converted using: `value = (raw - YOFF) * YMULT + YZERO`.
+pub fn load_isf<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let (preamble, curve) = split_curve(&data)?;
+    let layout = read_layout(&preamble)?;
+    let trace = if layout.ascii {
+        layout.decode(curve.trim_ascii_end())?
+    } else if curve.starts_with(b"#") {
+        layout.decode(read_block(curve)?)?
+    } else {
+        return Err(LoadError::invalid("ISF curve is not a binary block"));
+    };
+    tracing::info!(target: "loader", "{}", layout.summary(path));
+    Ok(vec![trace])
+}
+
+/// The preamble of an ISF file, read without loading its curve.
+pub struct IsfFile {
+    path: PathBuf,
+    layout: IsfLayout,
+}
+
+impl IsfFile {
+    /// Opens the file at `path` and reads its preamble.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let start = read_start(&path, PREAMBLE_MAX_SIZE)?;
+        let (preamble, _) = split_curve(&start)?;
+        let layout = read_layout(&preamble)?;
+        Ok(Self { path, layout })
+    }
+
+    /// Describes the waveform, the encoding and the sampling rate of the file in one line.
+    pub fn summary(&self) -> String {
+        self.layout.summary(&self.path.to_string_lossy())
+    }
+
+    /// Sampling rate in S/s, NaN if unknown.
+    pub fn sampling_rate(&self) -> f64 {
+        self.layout.sampling_rate
+    }
+
+    /// Index of the trigger sample in the curve, if recorded.
+    pub fn trigger(&self) -> Option<f64> {
+        self.layout.trigger
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREAMBLE: &str = ":WFMPRE:BYT_NR 2;ENCDG BIN;BN_FMT RI;BYT_OR MSB;WFID \"Ch1; DC\";\
+        NR_PT 3;XINCR 1.0E-6;PT_OFF 1;XZERO 0;XUNIT \"s\";YMULT 0.5;YOFF 2;YZERO 1;";
+
+    /// Builds an ISF file holding three big-endian 16-bit values.
+    fn isf_file() -> Vec<u8> {
+        let mut data = format!("{}:CURVE #16", PREAMBLE).into_bytes();
+        data.extend([2i16, 4, -2].iter().flat_map(|v| v.to_be_bytes()));
+        data.push(b'\n');
+        data
+    }
+
+    #[test]
+    fn curves_are_decoded() {
+        assert_eq!(
+            load_isf(isf_file().as_slice(), "a.isf").unwrap(),
+            [[1.0, 2.0, -1.0]]
+        );
+        let layout = read_layout(PREAMBLE).unwrap();
+        assert_eq!(layout.label, "Ch1; DC");
+        assert!((layout.sampling_rate - 1e6).abs() < 1e-3);
+        assert_eq!(layout.trigger, Some(1.0));
+
+        // ASCII curves, truncated to the number of points.
+        let data = b":WFMP:ENC ASC;NR_P 2;YMU 2;:CURV 1,2,3\n";
+        assert_eq!(load_isf(&data[..], "a.isf").unwrap(), [[2.0, 4.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let data = isf_file();
+        // Missing or truncated curve.
+        assert!(load_isf(&data[..PREAMBLE.len()], "a.isf").is_err());
+        assert!(load_isf(&data[..data.len() - 3], "a.isf").is_err());
+        // Invalid block header.
+        let curve = PREAMBLE.len() + ":CURVE ".len();
+        let mut bad = data.clone();
+        bad[curve + 1] = b'x';
+        assert!(load_isf(bad.as_slice(), "a.isf").is_err());
+        let mut bad = data.clone();
+        bad[curve] = b'0';
+        assert!(load_isf(bad.as_slice(), "a.isf").is_err());
+        // Unsupported format or invalid setting.
+        for (setting, value) in [("BYT_NR 2", "BYT_NR 3"), ("YMULT 0.5", "YMULT x")] {
+            let bad = format!("{}:CURVE #10", PREAMBLE.replace(setting, value));
+            assert!(load_isf(bad.as_bytes(), "a.isf").is_err());
+        }
+        // Invalid ASCII value.
+        assert!(load_isf(&b":WFMP:ENC ASC;:CURV 1,x"[..], "a.isf").is_err());
+    }
+}
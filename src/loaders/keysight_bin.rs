@@ -0,0 +1,326 @@
+//! Keysight/Agilent binary waveform file parser (`.bin`), also written by the recent Rigol scopes.
+//!
+//! Reference: Keysight InfiniiVision and Infiniium programmer's guides, "Binary Data (.bin)
+//! Format". Rigol files use the same layout with the `RG` cookie.
+
+use super::{ExplicitFormat, LoadError, decode_curve, files::open_file};
+use std::{
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Cookies of the Keysight/Agilent and Rigol files.
+const COOKIES: [&[u8; 2]; 2] = [b"AG", b"RG"];
+
+/// Size of the file header: cookie, version, file size and number of waveforms.
+const FILE_HEADER_SIZE: usize = 12;
+
+/// Fields of the waveform header read by the parser, with their offsets relative to its start.
+const BUFFERS: usize = 8;
+const POINTS: usize = 12;
+const X_INCREMENT: usize = 32;
+const LABEL: usize = 112;
+/// Size of the waveform header up to the end of the label.
+const WAVEFORM_HEADER_SIZE: usize = 128;
+
+/// Size of the waveform data header: header size, buffer type, bytes per point and buffer size.
+const DATA_HEADER_SIZE: usize = 12;
+
+/// Returns true if `data` starts like a Keysight/Agilent or Rigol binary file.
+pub fn is_keysight_bin(data: &[u8]) -> bool {
+    data.len() >= 4
+        && COOKIES.iter().any(|cookie| data.starts_with(*cookie))
+        && data[2..4].iter().all(u8::is_ascii_digit)
+}
+
+/// A data buffer of a waveform, which is loaded as a trace.
+struct Buffer {
+    /// Label of the waveform, such as the channel name.
+    label: String,
+    format: ExplicitFormat,
+    /// Position of the samples in the file.
+    offset: u64,
+    points: usize,
+}
+
+/// Location and encoding of the buffers of a BIN file, read from its headers.
+struct BinLayout {
+    cookie: String,
+    /// Sampling rate in S/s of the first waveform, NaN if unknown.
+    sampling_rate: f64,
+    buffers: Vec<Buffer>,
+}
+
+impl BinLayout {
+    fn summary(&self, path: &str) -> String {
+        format!(
+            "{}: {} BIN, {:.3} MS/s, {} buffer(s) ({}), {} pts/buffer",
+            path,
+            self.cookie,
+            self.sampling_rate / 1e6,
+            self.buffers.len(),
+            self.buffers
+                .iter()
+                .map(|b| b.label.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.buffers.first().map_or(0, |b| b.points)
+        )
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn i32_at(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a length or a count of the headers, which cannot be negative.
+fn len_at(data: &[u8], offset: usize) -> io::Result<usize> {
+    usize::try_from(i32_at(data, offset)).map_err(|_| invalid("negative length in BIN header"))
+}
+
+/// Parses the headers of a BIN file, skipping the samples.
+fn read_layout<R: Read + Seek>(reader: &mut R) -> io::Result<BinLayout> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    // Number of bytes of the file after the current position.
+    let remaining = |reader: &mut R| -> io::Result<u64> {
+        Ok(file_len.saturating_sub(reader.stream_position()?))
+    };
+    let mut header = [0; FILE_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    if !is_keysight_bin(&header) {
+        return Err(invalid("not a Keysight or Rigol BIN file"));
+    }
+    let waveforms = len_at(&header, 8)?;
+    let mut sampling_rate = f64::NAN;
+    let mut buffers = Vec::new();
+    for index in 0..waveforms {
+        // The header size is given first, so headers extended by later versions can be skipped.
+        let mut size = [0; 4];
+        reader.read_exact(&mut size)?;
+        let size = len_at(&size, 0)?;
+        if size < WAVEFORM_HEADER_SIZE {
+            return Err(invalid(format!(
+                "waveform header too small: {} bytes",
+                size
+            )));
+        }
+        if (size - 4) as u64 > remaining(reader)? {
+            return Err(invalid(format!(
+                "waveform header of {} bytes beyond the end of the file",
+                size
+            )));
+        }
+        let mut waveform = vec![0; size];
+        reader.read_exact(&mut waveform[4..])?;
+        if index == 0 {
+            let x_increment =
+                f64::from_le_bytes(waveform[X_INCREMENT..X_INCREMENT + 8].try_into().unwrap());
+            if x_increment > 0.0 {
+                sampling_rate = 1.0 / x_increment;
+            }
+        }
+        let label = &waveform[LABEL..LABEL + 16];
+        let end = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+        let label = String::from_utf8_lossy(&label[..end]).trim().to_string();
+        let points = len_at(&waveform, POINTS)?;
+        for _ in 0..len_at(&waveform, BUFFERS)? {
+            let mut data_header = [0; DATA_HEADER_SIZE];
+            reader.read_exact(&mut data_header)?;
+            let data_header_size = len_at(&data_header, 0)?.max(DATA_HEADER_SIZE);
+            let buffer_type = i16::from_le_bytes([data_header[4], data_header[5]]);
+            let bytes_per_point = i16::from_le_bytes([data_header[6], data_header[7]]);
+            let buffer_size = len_at(&data_header, 8)?;
+            reader.seek(SeekFrom::Current(
+                (data_header_size - DATA_HEADER_SIZE) as i64,
+            ))?;
+            if buffer_size as u64 > remaining(reader)? {
+                return Err(invalid(format!(
+                    "buffer of {} bytes beyond the end of the file",
+                    buffer_size
+                )));
+            }
+            // Normal, maximum, minimum and time buffers hold floats, count buffers integers and
+            // digital buffers one byte per point.
+            let format = match (buffer_type, bytes_per_point) {
+                (1..=4, 4) => ExplicitFormat::Fp32,
+                (5, 4) => ExplicitFormat::Int32,
+                (6, 1) => ExplicitFormat::Uint8,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!(
+                            "unsupported buffer type {} with {}-byte points",
+                            buffer_type, bytes_per_point
+                        ),
+                    ));
+                }
+            };
+            buffers.push(Buffer {
+                label: label.clone(),
+                format,
+                offset: reader.stream_position()?,
+                points: points.min(buffer_size / format.bytes_per_point()),
+            });
+            reader.seek(SeekFrom::Current(buffer_size as i64))?;
+        }
+    }
+    Ok(BinLayout {
+        cookie: String::from_utf8_lossy(&header[..4]).into_owned(),
+        sampling_rate,
+        buffers,
+    })
+}
+
+/// Loads a Keysight/Agilent or Rigol BIN file and returns each buffer of each waveform as a
+/// separate trace. Files of analog channels have one buffer per channel, or per segment for
+/// segmented acquisitions, and peak detect waveforms have a maximum and a minimum buffer.
+pub fn load_keysight_bin<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let layout = read_layout(&mut Cursor::new(&data))
+        .map_err(|e| LoadError::invalid(format!("invalid BIN file: {}", e)))?;
+    let traces = layout
+        .buffers
+        .iter()
+        .map(|buffer| {
+            let start = buffer.offset as usize;
+            let end = start + buffer.points * buffer.format.bytes_per_point();
+            if end > data.len() {
+                return Err(LoadError::invalid(format!(
+                    "buffer of {} extends beyond file: end offset {} > file size {}",
+                    buffer.label,
+                    end,
+                    data.len()
+                )));
+            }
+            Ok(decode_curve(
+                &data[start..end],
+                buffer.format,
+                true,
+                1.0,
+                0.0,
+            ))
+        })
+        .collect::<Result<_, _>>()?;
+    tracing::info!(target: "loader", "{}", layout.summary(path));
+    Ok(traces)
+}
+
+/// A BIN file whose buffers are read on demand.
+pub struct BinFile {
+    path: PathBuf,
+    layout: BinLayout,
+}
+
+impl BinFile {
+    /// Opens the file at `path` and reads its headers.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let layout = read_layout(&mut open_file(&path)?)?;
+        Ok(Self { path, layout })
+    }
+
+    /// Describes the sampling rate and the buffers of the file in one line.
+    pub fn summary(&self) -> String {
+        self.layout.summary(&self.path.to_string_lossy())
+    }
+
+    /// Number of buffers in the file.
+    pub fn buffers(&self) -> usize {
+        self.layout.buffers.len()
+    }
+
+    /// Sampling rate in S/s, NaN if unknown.
+    pub fn sampling_rate(&self) -> f64 {
+        self.layout.sampling_rate
+    }
+
+    /// Reads the buffer at `index`.
+    pub fn read(&self, index: usize) -> io::Result<Vec<f32>> {
+        let buffer = self.layout.buffers.get(index).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("no buffer {}", index))
+        })?;
+        let mut file = open_file(&self.path)?;
+        file.seek(SeekFrom::Start(buffer.offset))?;
+        let mut data = vec![0; buffer.points * buffer.format.bytes_per_point()];
+        file.read_exact(&mut data)?;
+        Ok(decode_curve(&data, buffer.format, true, 1.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position of the first data header in the files built by `bin_file`.
+    const DATA_HEADER: usize = FILE_HEADER_SIZE + WAVEFORM_HEADER_SIZE;
+
+    /// Builds a BIN file with one waveform holding a normal and a count buffer of two points.
+    fn bin_file() -> Vec<u8> {
+        let mut waveform = vec![0; WAVEFORM_HEADER_SIZE];
+        let mut set = |offset: usize, bytes: &[u8]| {
+            waveform[offset..offset + bytes.len()].copy_from_slice(bytes)
+        };
+        set(0, &(WAVEFORM_HEADER_SIZE as i32).to_le_bytes());
+        set(BUFFERS, &2i32.to_le_bytes());
+        set(POINTS, &2i32.to_le_bytes());
+        set(X_INCREMENT, &1e-3f64.to_le_bytes());
+        set(LABEL, b"Ch1");
+        let mut data = b"AG10\0\0\0\0".to_vec();
+        data.extend(1i32.to_le_bytes());
+        data.extend(waveform);
+        for (buffer_type, samples) in [
+            (1i16, [0.5f32.to_le_bytes(), (-2f32).to_le_bytes()]),
+            (5, [7i32.to_le_bytes(), 9i32.to_le_bytes()]),
+        ] {
+            data.extend((DATA_HEADER_SIZE as i32).to_le_bytes());
+            data.extend(buffer_type.to_le_bytes());
+            data.extend(4i16.to_le_bytes());
+            data.extend(8i32.to_le_bytes());
+            data.extend(samples.concat());
+        }
+        data
+    }
+
+    /// Overwrites the 32-bit field at `offset` of the file.
+    fn with_field(mut data: Vec<u8>, offset: usize, value: i32) -> Vec<u8> {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn buffers_are_decoded() {
+        let data = bin_file();
+        let layout = read_layout(&mut Cursor::new(&data)).unwrap();
+        assert!((layout.sampling_rate - 1e3).abs() < 1e-6);
+        assert_eq!(layout.buffers[0].label, "Ch1");
+        let traces = load_keysight_bin(data.as_slice(), "a.bin").unwrap();
+        assert_eq!(traces, [[0.5, -2.0], [7.0, 9.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let data = bin_file();
+        let load = |data: &[u8]| load_keysight_bin(data, "a.bin");
+        // Invalid cookie, or truncated headers and samples.
+        assert!(load(&data[1..]).is_err());
+        assert!(load(&data[..FILE_HEADER_SIZE + 64]).is_err());
+        assert!(load(&data[..DATA_HEADER + 4]).is_err());
+        assert!(load(&data[..data.len() - 1]).is_err());
+        // Negative number of waveforms, or more waveforms than written.
+        assert!(load(&with_field(data.clone(), 8, -1)).is_err());
+        assert!(load(&with_field(data.clone(), 8, 2)).is_err());
+        // Waveform header too small or beyond the end of the file.
+        assert!(load(&with_field(data.clone(), FILE_HEADER_SIZE, 64)).is_err());
+        assert!(load(&with_field(data.clone(), FILE_HEADER_SIZE, i32::MAX)).is_err());
+        // Buffer beyond the end of the file.
+        assert!(load(&with_field(data.clone(), DATA_HEADER + 8, i32::MAX)).is_err());
+        // Unsupported buffer type.
+        assert!(load(&with_field(data, DATA_HEADER + 4, 0x0004_0007)).is_err());
+    }
+}
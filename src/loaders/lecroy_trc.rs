@@ -0,0 +1,369 @@
+//! LeCroy binary waveform file parser (`.trc`, WAVEDESC template `LECROY_2_3`).
+//!
+//! Reference: LeCroy "Remote Control Manual", waveform template.
+
+use super::{ExplicitFormat, LoadError, decode_curve, files::open_file};
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Name of the descriptor block, at the start of the file or after a SCPI block header.
+const DESCRIPTOR_NAME: &[u8] = b"WAVEDESC";
+
+/// Size of the WAVEDESC block of the `LECROY_2_3` template.
+const DESCRIPTOR_SIZE: usize = 346;
+
+/// Maximum position of the descriptor in the file, after the SCPI block header (`#9` and nine
+/// digits) some scopes write before it.
+const MAX_DESCRIPTOR_OFFSET: usize = 32;
+
+/// Fields of the WAVEDESC block read by the parser, with their offsets relative to its start.
+const COMM_TYPE: usize = 32;
+const COMM_ORDER: usize = 34;
+const WAVE_DESCRIPTOR: usize = 36;
+const USER_TEXT: usize = 40;
+const TRIGTIME_ARRAY: usize = 48;
+const RIS_TIME_ARRAY: usize = 52;
+const RES_ARRAY1: usize = 56;
+const WAVE_ARRAY_1: usize = 60;
+const INSTRUMENT_NAME: usize = 76;
+const WAVE_ARRAY_COUNT: usize = 116;
+const SUBARRAY_COUNT: usize = 144;
+const VERTICAL_GAIN: usize = 156;
+const VERTICAL_OFFSET: usize = 160;
+const HORIZ_INTERVAL: usize = 176;
+
+/// Reads the fields of the WAVEDESC block, in the byte order of the file.
+struct Descriptor<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Descriptor<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        self.data[offset..offset + N].try_into().unwrap()
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        let bytes = self.bytes(offset);
+        if self.little_endian {
+            i16::from_le_bytes(bytes)
+        } else {
+            i16::from_be_bytes(bytes)
+        }
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        let bytes = self.bytes(offset);
+        if self.little_endian {
+            i32::from_le_bytes(bytes)
+        } else {
+            i32::from_be_bytes(bytes)
+        }
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        let bytes = self.bytes(offset);
+        if self.little_endian {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        }
+    }
+
+    /// Reads a length of a block of the file, which cannot be negative.
+    fn len(&self, offset: usize) -> Result<usize, LoadError> {
+        let len = self.i32(offset);
+        usize::try_from(len)
+            .map_err(|_| LoadError::invalid(format!("invalid block length {} in TRC header", len)))
+    }
+
+    fn string(&self, offset: usize, n: usize) -> String {
+        let bytes = &self.data[offset..offset + n];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(n);
+        String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+    }
+}
+
+/// Location and encoding of the segments of a TRC file, read from its header.
+struct TrcLayout {
+    instrument: String,
+    little_endian: bool,
+    format: ExplicitFormat,
+    /// Vertical gain and offset: `value = raw_value * gain - offset`.
+    gain: f64,
+    offset: f64,
+    /// Sampling rate in S/s, NaN if unknown.
+    sampling_rate: f64,
+    /// Position of the samples in the file.
+    data_offset: usize,
+    /// Number of segments, greater than one for sequence-mode acquisitions.
+    segments: usize,
+    /// Number of samples of each segment.
+    points: usize,
+}
+
+impl TrcLayout {
+    /// Returns the position of the samples of segment `index` in the file.
+    fn segment_range(&self, index: usize) -> Range<usize> {
+        let size = self.points * self.format.bytes_per_point();
+        let start = self.data_offset + index * size;
+        start..start + size
+    }
+
+    /// Converts the samples of a segment to values.
+    fn decode(&self, data: &[u8]) -> Vec<f32> {
+        decode_curve(
+            data,
+            self.format,
+            self.little_endian,
+            self.gain,
+            -self.offset,
+        )
+    }
+
+    fn summary(&self, path: &str) -> String {
+        format!(
+            "{}: LeCroy TRC ({}), {:?}, {:.3} MS/s, {} segment(s), {} pts/segment",
+            path,
+            self.instrument,
+            self.format,
+            self.sampling_rate / 1e6,
+            self.segments,
+            self.points
+        )
+    }
+}
+
+/// Parses the header of a TRC file. `data` must hold at least the start of the file, up to the
+/// end of the WAVEDESC block.
+fn read_layout(data: &[u8]) -> Result<TrcLayout, LoadError> {
+    let start = data[..data
+        .len()
+        .min(MAX_DESCRIPTOR_OFFSET + DESCRIPTOR_NAME.len())]
+        .windows(DESCRIPTOR_NAME.len())
+        .position(|w| w == DESCRIPTOR_NAME)
+        .ok_or_else(|| LoadError::invalid("WAVEDESC block not found: not a LeCroy TRC file"))?;
+    if data.len() < start + DESCRIPTOR_SIZE {
+        return Err(LoadError::invalid("TRC file too small for WAVEDESC block"));
+    }
+    let data = &data[start..];
+
+    // COMM_ORDER is 0 for big endian and 1 for little endian, in the byte order it describes.
+    let little_endian = u16::from_le_bytes([data[COMM_ORDER], data[COMM_ORDER + 1]]) == 1;
+    let d = Descriptor {
+        data,
+        little_endian,
+    };
+
+    let format = match d.i16(COMM_TYPE) {
+        0 => ExplicitFormat::Int8,
+        1 => ExplicitFormat::Int16,
+        comm_type => {
+            return Err(LoadError::invalid(format!(
+                "unsupported TRC sample format {}",
+                comm_type
+            )));
+        }
+    };
+
+    // The samples of the first wave array follow the descriptor and the optional blocks.
+    let data_offset = start
+        + [
+            WAVE_DESCRIPTOR,
+            USER_TEXT,
+            TRIGTIME_ARRAY,
+            RIS_TIME_ARRAY,
+            RES_ARRAY1,
+        ]
+        .into_iter()
+        .map(|field| d.len(field))
+        .sum::<Result<usize, _>>()?;
+    let wave_array_len = d.len(WAVE_ARRAY_1)?;
+    let count = d
+        .len(WAVE_ARRAY_COUNT)?
+        .min(wave_array_len / format.bytes_per_point());
+
+    // Sequence-mode acquisitions store all the segments in the wave array, one after the other.
+    let segments = d.i32(SUBARRAY_COUNT).max(1) as usize;
+    if segments > count.max(1) {
+        return Err(LoadError::invalid(format!(
+            "{} segments for {} samples in TRC header",
+            segments, count
+        )));
+    }
+    if !count.is_multiple_of(segments) {
+        return Err(LoadError::invalid(format!(
+            "{} samples cannot be split into {} segments",
+            count, segments
+        )));
+    }
+
+    let sampling_rate = 1.0 / d.f32(HORIZ_INTERVAL) as f64;
+    let sampling_rate = if sampling_rate.is_finite() && sampling_rate > 0.0 {
+        sampling_rate
+    } else {
+        f64::NAN
+    };
+
+    Ok(TrcLayout {
+        instrument: d.string(INSTRUMENT_NAME, 16),
+        little_endian,
+        format,
+        gain: d.f32(VERTICAL_GAIN) as f64,
+        offset: d.f32(VERTICAL_OFFSET) as f64,
+        sampling_rate,
+        data_offset,
+        segments,
+        points: count / segments,
+    })
+}
+
+/// Loads a LeCroy TRC file and returns all segments as separate traces.
+///
+/// For single acquisitions, returns a `Vec` with one element.
+/// For sequence-mode acquisitions, returns one trace per segment.
+///
+/// Raw samples are converted using: `voltage = raw_value * vertical_gain - vertical_offset`.
+pub fn load_lecroy_trc<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(decode_trc(&data, path)?.0)
+}
+
+/// Decodes the segments of a TRC file held in `data`, which is also the answer of the scopes to
+/// the `WAVEFORM? ALL` query, and returns them with the sampling rate in S/s (NaN if unknown).
+pub fn decode_trc(data: &[u8], path: &str) -> Result<(Vec<Vec<f32>>, f64), LoadError> {
+    let layout = read_layout(data)?;
+    let segments = (0..layout.segments)
+        .map(|index| {
+            let range = layout.segment_range(index);
+            if range.end > data.len() {
+                return Err(LoadError::invalid(format!(
+                    "segment {} extends beyond file: end offset {} > file size {}",
+                    index,
+                    range.end,
+                    data.len()
+                )));
+            }
+            Ok(layout.decode(&data[range]))
+        })
+        .collect::<Result<_, _>>()?;
+    tracing::info!(target: "loader", "{}", layout.summary(path));
+
+    Ok((segments, layout.sampling_rate))
+}
+
+/// A TRC file whose segments are read on demand, for sequence-mode acquisitions too large to be
+/// loaded at once.
+pub struct TrcFile {
+    path: PathBuf,
+    layout: TrcLayout,
+}
+
+impl TrcFile {
+    /// Opens the file at `path` and reads its header.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_file(&path)?;
+        let mut data = Vec::new();
+        file.take((MAX_DESCRIPTOR_OFFSET + DESCRIPTOR_SIZE) as u64)
+            .read_to_end(&mut data)?;
+        let layout = read_layout(&data)?;
+        Ok(Self { path, layout })
+    }
+
+    /// Describes the instrument, the sampling rate and the segments of the file in one line.
+    pub fn summary(&self) -> String {
+        self.layout.summary(&self.path.to_string_lossy())
+    }
+
+    /// Number of segments in the file.
+    pub fn segments(&self) -> usize {
+        self.layout.segments
+    }
+
+    /// Sampling rate in S/s, read from the horizontal interval of the header. NaN if unknown.
+    pub fn sampling_rate(&self) -> f64 {
+        self.layout.sampling_rate
+    }
+
+    /// Reads the segment at `index`.
+    pub fn read(&self, index: usize) -> io::Result<Vec<f32>> {
+        if index >= self.segments() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no segment {}", index),
+            ));
+        }
+        let range = self.layout.segment_range(index);
+        let mut file = open_file(&self.path)?;
+        file.seek(SeekFrom::Start(range.start as u64))?;
+        let mut data = vec![0; range.len()];
+        file.read_exact(&mut data)?;
+        Ok(self.layout.decode(&data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little-endian TRC file holding `samples` as 8-bit values in `segments` segments.
+    fn trc_file(samples: &[i8], segments: i32) -> Vec<u8> {
+        let mut data = vec![0; DESCRIPTOR_SIZE];
+        data[..DESCRIPTOR_NAME.len()].copy_from_slice(DESCRIPTOR_NAME);
+        data[COMM_ORDER..COMM_ORDER + 2].copy_from_slice(&1u16.to_le_bytes());
+        let mut set =
+            |offset: usize, bytes: [u8; 4]| data[offset..offset + 4].copy_from_slice(&bytes);
+        set(WAVE_DESCRIPTOR, (DESCRIPTOR_SIZE as i32).to_le_bytes());
+        set(WAVE_ARRAY_1, (samples.len() as i32).to_le_bytes());
+        set(WAVE_ARRAY_COUNT, (samples.len() as i32).to_le_bytes());
+        set(SUBARRAY_COUNT, segments.to_le_bytes());
+        set(VERTICAL_GAIN, 0.5f32.to_le_bytes());
+        set(VERTICAL_OFFSET, 1.0f32.to_le_bytes());
+        set(HORIZ_INTERVAL, 1e-6f32.to_le_bytes());
+        data.extend(samples.iter().map(|&s| s as u8));
+        data
+    }
+
+    /// Overwrites the 32-bit field at `offset` of the descriptor.
+    fn with_field(mut data: Vec<u8>, offset: usize, value: i32) -> Vec<u8> {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn segments_are_decoded() {
+        let (traces, sampling_rate) = decode_trc(&trc_file(&[2, -4, 0, 6], 1), "a.trc").unwrap();
+        assert_eq!(traces, [[0.0, -3.0, -1.0, 2.0]]);
+        assert!((sampling_rate - 1e6).abs() < 1.0);
+
+        // Sequence-mode acquisitions, after a SCPI block header.
+        let mut data = b"#9000000354".to_vec();
+        data.extend(trc_file(&[2, -4, 0, 6], 2));
+        let (traces, _) = decode_trc(&data, "a.trc").unwrap();
+        assert_eq!(traces, [[0.0, -3.0], [-1.0, 2.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let data = trc_file(&[2, -4, 0, 6], 1);
+        // Missing or truncated descriptor.
+        assert!(decode_trc(&data[8..], "a.trc").is_err());
+        assert!(decode_trc(&data[..100], "a.trc").is_err());
+        // Truncated samples.
+        assert!(decode_trc(&data[..data.len() - 1], "a.trc").is_err());
+        // Unsupported sample format.
+        let mut bad = data.clone();
+        bad[COMM_TYPE] = 5;
+        assert!(decode_trc(&bad, "a.trc").is_err());
+        // Negative block length.
+        assert!(decode_trc(&with_field(data.clone(), USER_TEXT, -1), "a.trc").is_err());
+        // More segments than samples, or samples not split evenly.
+        assert!(decode_trc(&with_field(data.clone(), SUBARRAY_COUNT, 5), "a.trc").is_err());
+        assert!(decode_trc(&with_field(data, SUBARRAY_COUNT, 3), "a.trc").is_err());
+    }
+}
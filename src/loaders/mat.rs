@@ -0,0 +1,414 @@
+//! MATLAB level 5 file parser (`.mat`), as exported by the PicoScope software.
+//!
+//! Reference: MathWorks "MAT-File Format", level 5 MAT-files. Only the real part of the numeric
+//! arrays is read: the other variables (cells, structures, strings, sparse arrays) are skipped.
+
+use super::{LoadError, NpyLayout, files::open_reader, npy::resolve_layout};
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+
+/// Size of the text header, subsystem offset, version and endian indicator.
+const HEADER_SIZE: usize = 128;
+
+/// Data types of the elements.
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_INT16: u32 = 3;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_SINGLE: u32 = 7;
+const MI_DOUBLE: u32 = 9;
+const MI_INT64: u32 = 12;
+const MI_UINT64: u32 = 13;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+
+/// Array classes of the numeric arrays, from `mxDOUBLE_CLASS` to `mxUINT64_CLASS`.
+const NUMERIC_CLASSES: std::ops::RangeInclusive<u8> = 6..=15;
+
+/// Name of the variable holding the sampling interval in seconds, in PicoScope exports.
+const SAMPLING_INTERVAL: &str = "Tinterval";
+
+/// A numeric array of a MAT-file.
+struct Variable {
+    name: String,
+    /// Number of rows and columns. Arrays with more dimensions have their trailing dimensions
+    /// merged in the columns.
+    rows: usize,
+    cols: usize,
+    /// Values in column-major order.
+    values: Vec<f64>,
+}
+
+impl Variable {
+    /// Splits the array into traces: a vector gives a single trace, a matrix one trace per row
+    /// or per column depending on `layout`.
+    fn into_traces(self, layout: NpyLayout) -> Vec<Vec<f32>> {
+        let values = self.values.into_iter().map(|x| x as f32);
+        if self.rows <= 1 || self.cols <= 1 {
+            return vec![values.collect()];
+        }
+        let columns: Vec<Vec<f32>> = values
+            .collect::<Vec<_>>()
+            .chunks_exact(self.rows)
+            .map(<[f32]>::to_vec)
+            .collect();
+        match resolve_layout(layout, self.rows, self.cols) {
+            NpyLayout::Rows => (0..self.rows)
+                .map(|row| columns.iter().map(|column| column[row]).collect())
+                .collect(),
+            _ => columns,
+        }
+    }
+}
+
+/// Reads the data elements of a MAT-file, in its byte order.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    /// Reads the next data element, returning its data type and its content. Returns `None` at
+    /// the end of the data.
+    fn element(&mut self) -> io::Result<Option<(u32, &'a [u8])>> {
+        if self.pos + 8 > self.data.len() {
+            return Ok(None);
+        }
+        let tag = self.u32(&self.data[self.pos..]);
+        // Small elements hold their size in the upper half of the tag, and up to 4 bytes of
+        // content in the second half.
+        if tag >> 16 != 0 {
+            let size = (tag >> 16) as usize;
+            if size > 4 {
+                return Err(invalid("invalid small data element"));
+            }
+            let content = &self.data[self.pos + 4..self.pos + 4 + size];
+            self.pos += 8;
+            return Ok(Some((tag & 0xffff, content)));
+        }
+        let size = self.u32(&self.data[self.pos + 4..]) as usize;
+        let start = self.pos + 8;
+        let content = self
+            .data
+            .get(start..start + size)
+            .ok_or_else(|| invalid("truncated data element"))?;
+        // Elements are padded to 8 bytes, except the compressed ones.
+        self.pos = if tag == MI_COMPRESSED {
+            start + size
+        } else {
+            start + size.next_multiple_of(8)
+        };
+        Ok(Some((tag, content)))
+    }
+
+    /// Decodes the numbers of an element content of data type `ty`.
+    fn numbers(&self, ty: u32, content: &[u8]) -> io::Result<Vec<f64>> {
+        macro_rules! decode {
+            ($t:ty) => {
+                content
+                    .chunks_exact(size_of::<$t>())
+                    .map(|bytes| {
+                        let bytes = bytes.try_into().unwrap();
+                        if self.little_endian {
+                            <$t>::from_le_bytes(bytes) as f64
+                        } else {
+                            <$t>::from_be_bytes(bytes) as f64
+                        }
+                    })
+                    .collect()
+            };
+        }
+        Ok(match ty {
+            MI_INT8 => decode!(i8),
+            MI_UINT8 => decode!(u8),
+            MI_INT16 => decode!(i16),
+            MI_UINT16 => decode!(u16),
+            MI_INT32 => decode!(i32),
+            MI_UINT32 => decode!(u32),
+            MI_SINGLE => decode!(f32),
+            MI_DOUBLE => decode!(f64),
+            MI_INT64 => decode!(i64),
+            MI_UINT64 => decode!(u64),
+            ty => return Err(invalid(format!("unsupported data type {}", ty))),
+        })
+    }
+
+    /// Parses the content of a matrix element. Returns `None` for the arrays which are not
+    /// numeric, or whose name is not `wanted`.
+    fn matrix(
+        &self,
+        content: &[u8],
+        wanted: &dyn Fn(&str) -> bool,
+    ) -> io::Result<Option<Variable>> {
+        let mut parser = Parser {
+            data: content,
+            pos: 0,
+            little_endian: self.little_endian,
+        };
+        let mut next = || {
+            parser
+                .element()?
+                .ok_or_else(|| invalid("truncated matrix element"))
+        };
+        let (_, flags) = next()?;
+        if flags.len() < 4 {
+            return Err(invalid("truncated array flags"));
+        }
+        let class = self.u32(flags) as u8;
+        let (_, dims) = next()?;
+        let dims = self.numbers(MI_INT32, dims)?;
+        let (_, name) = next()?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        if !NUMERIC_CLASSES.contains(&class) || !wanted(&name) {
+            return Ok(None);
+        }
+        let (ty, real) = next()?;
+        let values = self.numbers(ty, real)?;
+        let rows = dims.first().copied().unwrap_or(0.0) as usize;
+        let cols = dims.iter().skip(1).product::<f64>() as usize;
+        if rows.checked_mul(cols) != Some(values.len()) {
+            return Err(invalid(format!(
+                "{} values found for the {}x{} array {}",
+                values.len(),
+                rows,
+                cols,
+                name
+            )));
+        }
+        Ok(Some(Variable {
+            name,
+            rows,
+            cols,
+            values,
+        }))
+    }
+
+    /// Reads the numeric arrays whose name is `wanted`, decompressing the compressed elements.
+    fn variables(&mut self, wanted: &dyn Fn(&str) -> bool) -> io::Result<Vec<Variable>> {
+        let mut variables = Vec::new();
+        while let Some((ty, content)) = self.element()? {
+            match ty {
+                MI_MATRIX => variables.extend(self.matrix(content, wanted)?),
+                MI_COMPRESSED => {
+                    let mut data = Vec::new();
+                    ZlibDecoder::new(content).read_to_end(&mut data)?;
+                    let mut parser = Parser {
+                        data: &data,
+                        pos: 0,
+                        little_endian: self.little_endian,
+                    };
+                    variables.extend(parser.variables(wanted)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(variables)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads the numeric arrays of a MAT-file whose name is `wanted`.
+fn read_variables(data: &[u8], wanted: &dyn Fn(&str) -> bool) -> io::Result<Vec<Variable>> {
+    if data.len() < HEADER_SIZE || !data.starts_with(b"MATLAB 5.0 MAT-file") {
+        return Err(invalid("not a MATLAB level 5 MAT-file"));
+    }
+    // The endian indicator is "MI" written in the byte order of the file.
+    let little_endian = match &data[HEADER_SIZE - 2..HEADER_SIZE] {
+        b"IM" => true,
+        b"MI" => false,
+        _ => return Err(invalid("invalid endian indicator")),
+    };
+    Parser {
+        data: &data[HEADER_SIZE..],
+        pos: 0,
+        little_endian,
+    }
+    .variables(wanted)
+}
+
+/// Describes the numeric arrays of a MAT-file in one line.
+fn summary(path: &str, variables: &[Variable]) -> String {
+    format!(
+        "{}: MAT-file, {}",
+        path,
+        variables
+            .iter()
+            .map(|v| format!("{} ({}x{})", v.name, v.rows, v.cols))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Describes all the numeric arrays of the MAT-file at `path` in one line.
+pub fn mat_summary(path: &str) -> io::Result<String> {
+    let mut data = Vec::new();
+    open_reader(path)?.read_to_end(&mut data)?;
+    Ok(summary(path, &read_variables(&data, &|_| true)?))
+}
+
+/// Loads the numeric arrays of a MAT-file as traces: the variable named `dataset` if given, or
+/// all the arrays holding more than one value otherwise.
+///
+/// Vectors give a single trace. Matrices give one trace per row or per column, as 2D Numpy
+/// arrays with `layout`.
+pub fn load_mat<R: Read>(
+    mut reader: R,
+    path: &str,
+    dataset: Option<&str>,
+    layout: NpyLayout,
+) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let variables = read_variables(&data, &|name| dataset.is_none_or(|d| d == name))
+        .map_err(|e| LoadError::invalid(format!("invalid MAT-file: {}", e)))?;
+    let variables: Vec<_> = variables
+        .into_iter()
+        .filter(|v| dataset.is_some() || v.values.len() > 1)
+        .collect();
+    match dataset {
+        Some(name) if variables.is_empty() => {
+            return Err(LoadError::invalid(format!(
+                "no numeric array named {} found",
+                name
+            )));
+        }
+        None if variables.is_empty() => return Err(LoadError::invalid("no numeric array found")),
+        _ => {}
+    }
+    tracing::info!(target: "loader", "{}", summary(path, &variables));
+    Ok(variables
+        .into_iter()
+        .flat_map(|v| v.into_traces(layout))
+        .collect())
+}
+
+/// Returns the sampling rate in S/s of a MAT-file exported by PicoScope, given by the sampling
+/// interval it records.
+pub fn mat_sampling_rate(path: &str) -> Option<f64> {
+    let mut data = Vec::new();
+    open_reader(path).ok()?.read_to_end(&mut data).ok()?;
+    let variables = read_variables(&data, &|name| name == SAMPLING_INTERVAL).ok()?;
+    match variables.first()?.values[..] {
+        [interval] if interval > 0.0 => Some(1.0 / interval),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+
+    /// Encodes a little-endian data element, padded to 8 bytes.
+    fn element(ty: u32, content: &[u8]) -> Vec<u8> {
+        let mut data = [
+            &ty.to_le_bytes(),
+            &(content.len() as u32).to_le_bytes(),
+            content,
+        ]
+        .concat();
+        data.resize(data.len().next_multiple_of(8), 0);
+        data
+    }
+
+    /// Encodes a double array named `x` of dimensions `dims`.
+    fn matrix(dims: &[i32], values: &[f64]) -> Vec<u8> {
+        let content = [
+            element(MI_UINT32, &[6, 0, 0, 0, 0, 0, 0, 0]),
+            element(
+                MI_INT32,
+                &dims
+                    .iter()
+                    .flat_map(|d| d.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+            // Small element.
+            [(1u32 << 16 | MI_INT8).to_le_bytes(), *b"x\0\0\0"].concat(),
+            element(
+                MI_DOUBLE,
+                &values
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+        ]
+        .concat();
+        element(MI_MATRIX, &content)
+    }
+
+    /// Builds a little-endian MAT-file holding `elements`.
+    fn mat_file(elements: &[u8]) -> Vec<u8> {
+        let mut data = format!("{:116}", "MATLAB 5.0 MAT-file").into_bytes();
+        data.extend([0; 8]);
+        data.extend([0x00, 0x01]);
+        data.extend(b"IM");
+        data.extend(elements);
+        data
+    }
+
+    #[test]
+    fn arrays_are_decoded() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let file = mat_file(&matrix(&[2, 3], &values));
+        let traces = load_mat(file.as_slice(), "a.mat", None, NpyLayout::Rows).unwrap();
+        assert_eq!(traces, [[1.0, 3.0, 5.0], [2.0, 4.0, 6.0]]);
+        let traces = load_mat(file.as_slice(), "a.mat", Some("x"), NpyLayout::Columns).unwrap();
+        assert_eq!(traces, [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]);
+        assert!(load_mat(file.as_slice(), "a.mat", Some("y"), NpyLayout::Rows).is_err());
+
+        // Compressed elements, as written by MATLAB 7.
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&matrix(&[1, 3], &values[..3])).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut element = [
+            MI_COMPRESSED.to_le_bytes(),
+            (compressed.len() as u32).to_le_bytes(),
+        ]
+        .concat();
+        element.extend(compressed);
+        let file = mat_file(&element);
+        let traces = load_mat(file.as_slice(), "a.mat", None, NpyLayout::Auto).unwrap();
+        assert_eq!(traces, [[1.0, 2.0, 3.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let load = |file: &[u8]| load_mat(file, "a.mat", None, NpyLayout::Auto);
+        let file = mat_file(&matrix(&[2, 3], &values));
+        // Truncated header or element.
+        assert!(load(&file[..100]).is_err());
+        assert!(load(&file[..file.len() - 8]).is_err());
+        // Invalid endian indicator.
+        let mut bad = file.clone();
+        bad[HEADER_SIZE - 1] = b'X';
+        assert!(load(&bad).is_err());
+        // Dimensions which do not match the values, or overflow.
+        assert!(load(&mat_file(&matrix(&[2, 4], &values))).is_err());
+        assert!(load(&mat_file(&matrix(&[i32::MAX; 3], &values))).is_err());
+        // Unsupported data type.
+        let mut bad = file.clone();
+        bad[HEADER_SIZE + 8 + 16 + 16 + 8] = 8;
+        assert!(load(&bad).is_err());
+        // Truncated array flags.
+        let mut bad = file;
+        bad[HEADER_SIZE + 8 + 4] = 2;
+        assert!(load(&bad).is_err());
+    }
+}
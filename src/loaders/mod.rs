@@ -1,23 +1,109 @@
+mod arrow;
 mod csv;
+mod files;
+mod isf;
+mod keysight_bin;
+mod lecroy_trc;
+mod mat;
 mod npy;
+mod parquet;
+mod raw;
+mod sigrok;
 mod tek_wfm;
+mod trs;
+mod vcd;
+mod wav;
 
-pub use csv::load_csv;
-pub use npy::load_npy;
-pub use tek_wfm::load_tek_wfm;
+pub use csv::{load_csv, parse_csv_value};
+#[cfg(target_arch = "wasm32")]
+pub use files::add_file;
+pub use files::{STDIN_PATH, open_reader};
+pub use isf::{IsfFile, load_isf};
+pub use keysight_bin::{BinFile, load_keysight_bin};
+pub use lecroy_trc::{TrcFile, decode_trc, load_lecroy_trc};
+pub use mat::{load_mat, mat_summary};
+#[cfg(not(target_arch = "wasm32"))]
+pub use npy::map_npy;
+pub use npy::{NpyRows, load_npy};
+pub use parquet::{load_parquet, parquet_summary};
+#[cfg(not(target_arch = "wasm32"))]
+pub use raw::page_raw;
+pub use raw::{SampleType, decode_raw, load_raw};
+pub use sigrok::{load_sigrok, open_sigrok};
+pub use tek_wfm::{ExplicitFormat, WfmFile, decode_curve, load_tek_wfm};
+pub use trs::{TrsFile, load_trs};
+pub use vcd::{load_vcd, open_vcd};
+pub use wav::{WavFile, load_wav};
 
-use std::path::Path;
+use crate::{digital::LogicCapture, trace::Trace};
+use files::{Compression, file_compression, read_start};
+use isf::is_isf;
+use keysight_bin::is_keysight_bin;
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, path::Path, sync::Arc};
+
+/// Error of the loaders on the files they cannot load.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The content of the file is malformed or not supported.
+    Invalid(String),
+}
+
+impl LoadError {
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::Invalid(message.into())
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<LoadError> for io::Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(e) => e,
+            LoadError::Invalid(message) => io::Error::new(io::ErrorKind::InvalidData, message),
+        }
+    }
+}
 
 /// Possible trace formats that TurboPlot is able to load.
-#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum TraceFormat {
     Numpy,
     Csv,
     TekWfm,
+    Trs,
+    LecroyTrc,
+    Wav,
+    Mat,
+    KeysightBin,
+    Sigrok,
+    Vcd,
+    Parquet,
+    Isf,
+    /// Little-endian samples without header, of the type given by `--dtype`.
+    Raw,
 }
 
 /// How a 2D Numpy array should be interpreted.
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, clap::ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum NpyLayout {
     /// Guess from the array shape: arrays with few columns and many rows are
     /// treated as column-wise (e.g. oscilloscope `(time, voltage)` dumps),
@@ -32,17 +118,387 @@ pub enum NpyLayout {
     Rows,
 }
 
-/// Guess trace file format from its path extension.
+/// Guess trace file format from its path extension. The extensions shared by several formats and
+/// the unknown ones are told apart by the first bytes of the file, decompressed if needed.
 pub fn guess_format(path: &str) -> Option<TraceFormat> {
-    match Path::new(path)
-        .extension()?
-        .to_str()?
-        .to_lowercase()
-        .as_str()
+    let extension_of = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase)
+    };
+    let mut extension = extension_of(Path::new(path));
+    // Compressed files are recognized by the extension of their content, as in `.npy.gz`.
+    if extension
+        .as_deref()
+        .is_some_and(|extension| Compression::EXTENSIONS.contains(&extension))
+    {
+        extension = Path::new(path)
+            .file_stem()
+            .and_then(|stem| extension_of(Path::new(stem)));
+    }
+    match extension.as_deref() {
+        Some("npy") => Some(TraceFormat::Numpy),
+        Some("csv") => Some(TraceFormat::Csv),
+        Some("trs") => Some(TraceFormat::Trs),
+        Some("trc") => Some(TraceFormat::LecroyTrc),
+        Some("wav") => Some(TraceFormat::Wav),
+        Some("mat") => Some(TraceFormat::Mat),
+        Some("sr") => Some(TraceFormat::Sigrok),
+        Some("vcd") => Some(TraceFormat::Vcd),
+        Some("parquet" | "feather" | "arrow") => Some(TraceFormat::Parquet),
+        Some("isf") => Some(TraceFormat::Isf),
+        // Rigol scopes also write `.wfm` files, whose layouts are not supported: they are
+        // reported by `unrecognized_format`.
+        Some("wfm") => match sniff_format(path) {
+            Ok(format) => format.filter(|&format| format == TraceFormat::TekWfm),
+            Err(_) => Some(TraceFormat::TekWfm),
+        },
+        Some("bin") => sniff_format(path).unwrap_or(Some(TraceFormat::KeysightBin)),
+        // The samples piped to the standard input are usually text.
+        _ if path == STDIN_PATH => sniff_format(path).ok().flatten().or(Some(TraceFormat::Csv)),
+        _ => sniff_format(path).ok().flatten(),
+    }
+}
+
+/// Magic bytes of the `.wfm` files of the Rigol DS1000E/D and DS1000Z scopes. These files have
+/// no loader: their layouts have not been checked against files saved by the scopes, so they are
+/// only recognized to point to the `.bin` export, which the Keysight BIN loader reads.
+const RIGOL_WFM_MAGICS: [&[u8]; 2] = [b"\xa5\xa5\x00\x00", b"\x01\xff\xff\xff"];
+
+/// Describes why the format of the file at `path` could not be guessed, pointing to the `.bin`
+/// export of the scope for the Rigol `.wfm` files.
+pub fn unrecognized_format(path: &str) -> String {
+    match read_start(path, 4) {
+        Ok(start) if RIGOL_WFM_MAGICS.contains(&start.as_slice()) => {
+            "Rigol .wfm files are not supported, save the waveforms as .bin files on the scope"
+                .to_string()
+        }
+        _ => "unrecognized file format".to_string(),
+    }
+}
+
+/// Commands which may start a VCD file.
+const VCD_COMMANDS: [&[u8]; 6] = [
+    b"$date",
+    b"$version",
+    b"$timescale",
+    b"$comment",
+    b"$scope",
+    b"$var",
+];
+
+/// Recognizes the format of the file at `path` from its first bytes. Returns `None` for the
+/// formats without a signature, such as CSV and TRS.
+fn sniff_format(path: &str) -> io::Result<Option<TraceFormat>> {
+    let start = read_start(path, 64)?;
+    Ok(if start.starts_with(b"\x93NUMPY") {
+        Some(TraceFormat::Numpy)
+    } else if start.starts_with(b"RIFF") && start.get(8..12) == Some(b"WAVE") {
+        Some(TraceFormat::Wav)
+    } else if start.starts_with(b"PAR1") || start.starts_with(b"ARROW1") {
+        Some(TraceFormat::Parquet)
+    } else if start.starts_with(b"MATLAB 5.0 MAT-file") {
+        Some(TraceFormat::Mat)
+    } else if start.get(2..7) == Some(b":WFM#") {
+        Some(TraceFormat::TekWfm)
+    } else if is_isf(&start) {
+        Some(TraceFormat::Isf)
+    } else if is_keysight_bin(&start) {
+        Some(TraceFormat::KeysightBin)
+    } else if start
+        .windows(8)
+        .take(33)
+        .any(|window| window == b"WAVEDESC")
+    {
+        Some(TraceFormat::LecroyTrc)
+    } else if VCD_COMMANDS
+        .iter()
+        .any(|command| start.trim_ascii_start().starts_with(command))
     {
-        "npy" => Some(TraceFormat::Numpy),
-        "csv" => Some(TraceFormat::Csv),
-        "wfm" => Some(TraceFormat::TekWfm),
+        Some(TraceFormat::Vcd)
+    } else {
+        None
+    })
+}
+
+/// Loads all the frames of the file at `path`, guessing its format from its extension if `format`
+/// is `None`. Compressed files are decompressed in memory first. Malformed files are reported as
+/// errors.
+pub fn load_file(
+    path: &str,
+    format: Option<TraceFormat>,
+    npy_layout: NpyLayout,
+    skip_lines: usize,
+    column: usize,
+    dataset: Option<&str>,
+    dtype: SampleType,
+) -> Result<Vec<Vec<f32>>, String> {
+    let format = format
+        .or_else(|| guess_format(path))
+        .ok_or_else(|| unrecognized_format(path))?;
+    let reader = open_reader(path).map_err(|e| e.to_string())?;
+    match format {
+        TraceFormat::TekWfm => load_tek_wfm(reader, path),
+        TraceFormat::Numpy => load_npy(reader, path, npy_layout),
+        TraceFormat::Csv => load_csv(reader, skip_lines, column).map(|trace| vec![trace]),
+        TraceFormat::Trs => load_trs(reader),
+        TraceFormat::LecroyTrc => load_lecroy_trc(reader, path),
+        TraceFormat::Wav => load_wav(reader, path),
+        TraceFormat::Mat => load_mat(reader, path, dataset, npy_layout),
+        TraceFormat::KeysightBin => load_keysight_bin(reader, path),
+        TraceFormat::Sigrok => load_sigrok(reader, path),
+        TraceFormat::Vcd => load_vcd(reader, path),
+        TraceFormat::Parquet => load_parquet(reader, path, column, dataset),
+        TraceFormat::Isf => load_isf(reader, path),
+        TraceFormat::Raw => load_raw(reader, dtype),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Returns the sampling rate in MS/s recorded in the header of the file at `path`, for the formats
+/// whose files always record it. The traces of such files are displayed at this rate instead of
+/// the one of the options.
+pub fn file_sampling_rate(path: &str, format: TraceFormat) -> Option<f32> {
+    match format {
+        TraceFormat::Wav => WavFile::open(path)
+            .ok()
+            .map(|file| (file.sampling_rate() / 1e6) as f32)
+            .filter(|&rate| rate > 0.0),
+        TraceFormat::KeysightBin => BinFile::open(path)
+            .ok()
+            .map(|file| (file.sampling_rate() / 1e6) as f32)
+            .filter(|&rate| rate > 0.0),
+        TraceFormat::Mat => mat::mat_sampling_rate(path).map(|rate| (rate / 1e6) as f32),
+        TraceFormat::TekWfm => WfmFile::open(path)
+            .ok()
+            .map(|file| (file.sampling_rate() / 1e6) as f32)
+            .filter(|&rate| rate > 0.0),
+        TraceFormat::Isf => IsfFile::open(path)
+            .ok()
+            .map(|file| (file.sampling_rate() / 1e6) as f32)
+            .filter(|&rate| rate > 0.0),
+        TraceFormat::LecroyTrc => TrcFile::open(path)
+            .ok()
+            .map(|file| (file.sampling_rate() / 1e6) as f32)
+            .filter(|&rate| rate > 0.0),
         _ => None,
     }
 }
+
+/// Returns the index of the trigger sample recorded in the header of the file at `path`, for the
+/// oscilloscope formats recording it. The index may be fractional, or out of the trace for the
+/// acquisitions triggered before or after the record.
+pub fn file_trigger(path: &str, format: TraceFormat) -> Option<f64> {
+    match format {
+        TraceFormat::TekWfm => WfmFile::open(path).ok().and_then(|file| file.trigger()),
+        TraceFormat::Isf => IsfFile::open(path).ok().and_then(|file| file.trigger()),
+        _ => None,
+    }
+}
+
+/// Reads the digital channels of the file at `path`, for the formats holding logic analyzer
+/// captures or simulation dumps. Returns `None` for the other formats.
+pub fn open_capture(path: &str, format: TraceFormat) -> Option<io::Result<LogicCapture>> {
+    match format {
+        TraceFormat::Sigrok => Some(open_sigrok(path)),
+        TraceFormat::Vcd => Some(open_vcd(path)),
+        _ => None,
+    }
+}
+
+/// Loads all the frames of the file at `path` like [`load_file`], mapping the float32 Numpy arrays
+/// instead of copying their samples in memory, paging the huge raw files and Numpy arrays of other
+/// types, and storing the long traces of 8 or 16-bit samples as integer codes. The min/max pyramid
+/// of the long traces loaded in memory is built at once; the mapped and paged traces build theirs
+/// when first rendered zoomed out, as it reads all their samples.
+///
+/// Files which may change while they are displayed, such as the watched ones, must not be mapped
+/// (`map` false): the huge float32 arrays are paged instead.
+#[allow(clippy::too_many_arguments)]
+// Files are never mapped in the browser.
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub fn load_file_mapped(
+    path: &str,
+    format: Option<TraceFormat>,
+    npy_layout: NpyLayout,
+    skip_lines: usize,
+    column: usize,
+    dataset: Option<&str>,
+    dtype: SampleType,
+    map: bool,
+) -> Result<Vec<Trace>, String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if format.or_else(|| guess_format(path)) == Some(TraceFormat::Numpy) {
+        match map_npy(path, npy_layout, map) {
+            Ok(traces) => return Ok(traces),
+            Err(e) => tracing::debug!(target: "loader", "{} is loaded in memory: {}", path, e),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if format.or_else(|| guess_format(path)) == Some(TraceFormat::Raw)
+        && let Some(trace) = page_raw(path, dtype).map_err(|e| e.to_string())?
+    {
+        return Ok(vec![trace]);
+    }
+    load_file(path, format, npy_layout, skip_lines, column, dataset, dtype).map(|frames| {
+        frames
+            .into_iter()
+            .map(|frame| {
+                let trace = Trace::quantize(frame);
+                trace.pyramid();
+                trace
+            })
+            .collect()
+    })
+}
+
+/// A multi-frame file whose frames are read on demand, for browsing segmented acquisitions
+/// without loading them entirely.
+pub trait FrameFile {
+    /// Number of frames in the file.
+    fn frames(&self) -> usize;
+
+    /// Reads the frame at `index`.
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>>;
+}
+
+impl FrameFile for TrsFile {
+    fn frames(&self) -> usize {
+        self.header.traces
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index).map(|record| record.samples)
+    }
+}
+
+impl FrameFile for WfmFile {
+    fn frames(&self) -> usize {
+        self.frames()
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index)
+    }
+}
+
+impl FrameFile for TrcFile {
+    fn frames(&self) -> usize {
+        self.segments()
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index)
+    }
+}
+
+impl FrameFile for BinFile {
+    fn frames(&self) -> usize {
+        self.buffers()
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index)
+    }
+}
+
+impl FrameFile for WavFile {
+    fn frames(&self) -> usize {
+        self.channels()
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index)
+    }
+}
+
+impl FrameFile for NpyRows {
+    fn frames(&self) -> usize {
+        self.rows()
+    }
+
+    fn read_frame(&self, index: usize) -> io::Result<Vec<f32>> {
+        self.read(index)
+    }
+}
+
+/// Opens the file at `path` for reading its frames on demand. Fails for CSV files, for the
+/// Numpy arrays which are not row-wise, for compressed files, and for malformed files.
+pub fn open_frames(
+    path: &str,
+    format: TraceFormat,
+    npy_layout: NpyLayout,
+) -> io::Result<Arc<dyn FrameFile>> {
+    if file_compression(path)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "compressed files are loaded entirely",
+        ));
+    }
+    Ok(match format {
+        TraceFormat::TekWfm => {
+            let file = WfmFile::open(path)?;
+            tracing::info!(target: "loader", "{}", file.summary());
+            Arc::new(file)
+        }
+        TraceFormat::Numpy => Arc::new(NpyRows::open(path, npy_layout)?),
+        TraceFormat::Trs => Arc::new(TrsFile::open(path)?),
+        TraceFormat::LecroyTrc => {
+            let file = TrcFile::open(path)?;
+            tracing::info!(target: "loader", "{}", file.summary());
+            Arc::new(file)
+        }
+        TraceFormat::KeysightBin => {
+            let file = BinFile::open(path)?;
+            tracing::info!(target: "loader", "{}", file.summary());
+            Arc::new(file)
+        }
+        TraceFormat::Wav => {
+            let file = WavFile::open(path)?;
+            tracing::info!(target: "loader", "{}", file.summary());
+            Arc::new(file)
+        }
+        TraceFormat::Csv => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "CSV files hold a single trace",
+            ));
+        }
+        TraceFormat::Mat => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "MAT-files are loaded entirely",
+            ));
+        }
+        TraceFormat::Sigrok => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sigrok sessions are loaded entirely",
+            ));
+        }
+        TraceFormat::Vcd => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "VCD files are loaded entirely",
+            ));
+        }
+        TraceFormat::Parquet => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Parquet and Feather files are loaded one column at a time",
+            ));
+        }
+        TraceFormat::Isf => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "ISF files hold a single trace",
+            ));
+        }
+        TraceFormat::Raw => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "raw files hold a single trace",
+            ));
+        }
+    })
+}
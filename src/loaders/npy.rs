@@ -1,7 +1,16 @@
-use crate::loaders::NpyLayout;
-use muscat::util::read_array1_from_npy_file;
-use npyz::{DType, NpyFile};
-use std::io::BufRead;
+use crate::loaders::{LoadError, NpyLayout, files::open_file};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    loaders::SampleType,
+    trace::{MappedFile, PAGED_MIN_SAMPLES, PagedFile, Trace},
+};
+use npyz::{DType, NpyFile, Order};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Read;
+use std::{
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
 
 /// Heuristic threshold on the inner dimension for auto-detection of a
 /// column-wise 2D Numpy array. Anything with `cols <= N` is likely a
@@ -9,6 +18,21 @@ use std::io::BufRead;
 /// than a stack of tiny traces.
 const AUTO_COLUMNS_MAX_COLS: usize = 10;
 
+/// Resolves the layout of a 2D array of shape `(rows, cols)`, guessing it for
+/// [`NpyLayout::Auto`].
+pub(super) fn resolve_layout(layout: NpyLayout, rows: usize, cols: usize) -> NpyLayout {
+    match layout {
+        NpyLayout::Auto => {
+            if cols <= AUTO_COLUMNS_MAX_COLS && rows > cols {
+                NpyLayout::Columns
+            } else {
+                NpyLayout::Rows
+            }
+        }
+        other => other,
+    }
+}
+
 /// Load a numpy file as one or more traces.
 ///
 /// Supports 1D arrays (single trace) and 2D arrays. For 2D arrays, `layout`
@@ -24,91 +48,82 @@ const AUTO_COLUMNS_MAX_COLS: usize = 10;
 /// In every multi-trace case, `--frames` selects which traces to keep.
 ///
 /// Data type is automatically cast to `f32`.
-pub fn load_npy<R: BufRead>(reader: R, path: &str, layout: NpyLayout) -> Vec<Vec<f32>> {
-    let npy = NpyFile::new(reader).expect("Failed to parse numpy file");
+pub fn load_npy<R: BufRead>(
+    reader: R,
+    path: &str,
+    layout: NpyLayout,
+) -> Result<Vec<Vec<f32>>, LoadError> {
+    let npy = NpyFile::new(reader)?;
     let shape = npy.shape().to_vec();
     let dtype_descr = npy.dtype().descr();
 
     let DType::Plain(dtype) = npy.dtype().clone() else {
-        panic!("Invalid numpy data type")
+        return Err(LoadError::invalid(format!(
+            "unsupported numpy data type {}",
+            dtype_descr
+        )));
     };
 
+    macro_rules! read_flat {
+        ($t:ty) => {
+            npy.into_vec::<$t>()?
+                .into_iter()
+                .map(|x| x as f32)
+                .collect()
+        };
+    }
     let flat: Vec<f32> = match (dtype.type_char(), dtype.num_bytes()) {
-        (npyz::TypeChar::Int, Some(1)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: i8| x as f32)
-            .collect(),
-        (npyz::TypeChar::Int, Some(2)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: i16| x as f32)
-            .collect(),
-        (npyz::TypeChar::Int, Some(4)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: i32| x as f32)
-            .collect(),
-        (npyz::TypeChar::Uint, Some(1)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: u8| x as f32)
-            .collect(),
-        (npyz::TypeChar::Uint, Some(2)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: u16| x as f32)
-            .collect(),
-        (npyz::TypeChar::Uint, Some(4)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: u32| x as f32)
-            .collect(),
-        (npyz::TypeChar::Float, Some(4)) => read_array1_from_npy_file(npy).into_iter().collect(),
-        (npyz::TypeChar::Float, Some(8)) => read_array1_from_npy_file(npy)
-            .into_iter()
-            .map(|x: f64| x as f32)
-            .collect(),
-        _ => panic!("Unsupported data type"),
+        (npyz::TypeChar::Int, Some(1)) => read_flat!(i8),
+        (npyz::TypeChar::Int, Some(2)) => read_flat!(i16),
+        (npyz::TypeChar::Int, Some(4)) => read_flat!(i32),
+        (npyz::TypeChar::Uint, Some(1)) => read_flat!(u8),
+        (npyz::TypeChar::Uint, Some(2)) => read_flat!(u16),
+        (npyz::TypeChar::Uint, Some(4)) => read_flat!(u32),
+        (npyz::TypeChar::Float, Some(4)) => npy.into_vec::<f32>()?,
+        (npyz::TypeChar::Float, Some(8)) => read_flat!(f64),
+        _ => {
+            return Err(LoadError::invalid(format!(
+                "unsupported numpy data type {}",
+                dtype_descr
+            )));
+        }
     };
 
     match shape.len() {
         1 => {
-            println!("{}: NumPy {}, {} pts", path, dtype_descr, flat.len());
-            vec![flat]
+            tracing::info!(target: "loader", "{}: NumPy {}, {} pts", path, dtype_descr, flat.len());
+            Ok(vec![flat])
         }
         2 => {
             let rows = shape[0] as usize;
             let cols = shape[1] as usize;
-            assert!(rows > 0 && cols > 0, "Empty 2D numpy array: {:?}", shape);
-            assert_eq!(
-                flat.len(),
-                rows * cols,
-                "Unexpected flat buffer size for shape {:?}",
-                shape
-            );
+            if rows == 0 || cols == 0 {
+                return Err(LoadError::invalid(format!(
+                    "empty 2D numpy array: {:?}",
+                    shape
+                )));
+            }
 
             // Single-row and single-column arrays collapse to one trace
             // regardless of layout.
             if cols == 1 {
-                println!(
+                tracing::info!(
+                    target: "loader",
                     "{}: NumPy {}, 1 trace of {} pts (2D with single column)",
                     path, dtype_descr, rows
                 );
-                return vec![flat];
+                return Ok(vec![flat]);
             }
             if rows == 1 {
-                println!(
+                tracing::info!(
+                    target: "loader",
                     "{}: NumPy {}, 1 trace of {} pts (2D with single row)",
                     path, dtype_descr, cols
                 );
-                return vec![flat];
+                return Ok(vec![flat]);
             }
 
-            let resolved = match layout {
-                NpyLayout::Auto => {
-                    if cols <= AUTO_COLUMNS_MAX_COLS && rows > cols {
-                        NpyLayout::Columns
-                    } else {
-                        NpyLayout::Rows
-                    }
-                }
-                other => other,
-            };
+            let resolved = resolve_layout(layout, rows, cols);
 
             let auto_note = if matches!(layout, NpyLayout::Auto) {
                 " [auto]"
@@ -123,7 +138,8 @@ pub fn load_npy<R: BufRead>(reader: R, path: &str, layout: NpyLayout) -> Vec<Vec
                     // the shape `Rows` would produce for a `(cols, rows)`
                     // array, so the rest of the app treats both layouts
                     // uniformly.
-                    println!(
+                    tracing::info!(
+                    target: "loader",
                         "{}: NumPy {}, shape ({}, {}), column-wise{}: {} trace(s) of {} pts \
                          (transposed)",
                         path, dtype_descr, rows, cols, auto_note, cols, rows
@@ -135,18 +151,187 @@ pub fn load_npy<R: BufRead>(reader: R, path: &str, layout: NpyLayout) -> Vec<Vec
                             traces[c].push(*value);
                         }
                     }
-                    traces
+                    Ok(traces)
                 }
                 NpyLayout::Rows => {
-                    println!(
+                    tracing::info!(
+                    target: "loader",
                         "{}: NumPy {}, shape ({}, {}), row-wise{}: {} trace(s) of {} pts",
                         path, dtype_descr, rows, cols, auto_note, rows, cols
                     );
-                    flat.chunks_exact(cols).map(|c| c.to_vec()).collect()
+                    Ok(flat.chunks_exact(cols).map(|c| c.to_vec()).collect())
                 }
                 NpyLayout::Auto => unreachable!("auto already resolved above"),
             }
         }
-        _ => panic!("Unsupported numpy array dimension: {:?}", shape),
+        _ => Err(LoadError::invalid(format!(
+            "unsupported numpy array dimension: {:?}",
+            shape
+        ))),
+    }
+}
+
+/// Maps the traces of a little-endian float32 Numpy file instead of loading them: a 1D array, or
+/// the rows of a row-wise 2D array in C order. The arrays of other little-endian sample types
+/// with at least [`PAGED_MIN_SAMPLES`] samples are paged, like the float32 ones if `map` is
+/// false. Fails with [`io::ErrorKind::Unsupported`] for the other arrays, which must be loaded
+/// with [`load_npy`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn map_npy(path: &str, layout: NpyLayout, map: bool) -> io::Result<Vec<Trace>> {
+    let mut reader = BufReader::new(open_file(path)?);
+    let unsupported = |message: &str| io::Error::new(io::ErrorKind::Unsupported, message);
+    // The header length follows the magic string and the version, on 2 bytes in version 1 and
+    // on 4 bytes in later versions.
+    let mut start = [0; 12];
+    reader.read_exact(&mut start)?;
+    let data_offset = match start[6] {
+        1 => 10 + u16::from_le_bytes([start[8], start[9]]) as usize,
+        _ => 12 + u32::from_le_bytes([start[8], start[9], start[10], start[11]]) as usize,
+    };
+    let npy = NpyFile::new(BufReader::new(open_file(path)?))?;
+    let DType::Plain(descr) = npy.dtype() else {
+        return Err(unsupported("not an array of numbers"));
+    };
+    let descr = descr.to_string();
+    let dtype = match descr.as_str() {
+        "|i1" | "<i1" => SampleType::I8,
+        "|u1" | "<u1" => SampleType::U8,
+        "<i2" => SampleType::I16,
+        "<u2" => SampleType::U16,
+        "<i4" => SampleType::I32,
+        "<u4" => SampleType::U32,
+        "<f4" => SampleType::F32,
+        "<f8" => SampleType::F64,
+        _ => {
+            return Err(unsupported(
+                "not a little-endian array of supported samples",
+            ));
+        }
+    };
+    let (rows, cols) = match *npy.shape() {
+        [len] => (1, len as usize),
+        [rows, cols] if rows == 1 || cols == 1 => (1, (rows * cols) as usize),
+        [rows, cols] => {
+            let (rows, cols) = (rows as usize, cols as usize);
+            if resolve_layout(layout, rows, cols) != NpyLayout::Rows || npy.order() != Order::C {
+                return Err(unsupported("the traces are not contiguous in the file"));
+            }
+            (rows, cols)
+        }
+        _ => return Err(unsupported("not a 1D or 2D array")),
+    };
+    if cols == 0 {
+        return Err(unsupported("empty array"));
+    }
+    let row_offset = |row: usize| data_offset + row * cols * dtype.size();
+    let (traces, storage) = if dtype == SampleType::F32 && map {
+        let file = MappedFile::open(path)?;
+        let traces = (0..rows)
+            .map(|row| file.trace(row_offset(row), cols))
+            .collect::<io::Result<_>>()?;
+        (traces, "mapped")
+    } else if rows * cols >= PAGED_MIN_SAMPLES {
+        let file = PagedFile::open(path)?;
+        let traces = (0..rows)
+            .map(|row| file.trace(row_offset(row) as u64, cols, dtype))
+            .collect::<io::Result<_>>()?;
+        (traces, "paged")
+    } else {
+        return Err(unsupported("too small to be paged"));
+    };
+    tracing::info!(
+        target: "loader",
+        "{}: NumPy {}, {} trace(s) of {} pts, {}",
+        path,
+        descr,
+        rows,
+        cols,
+        storage
+    );
+    Ok(traces)
+}
+
+/// A row-wise 2D Numpy array whose traces are read on demand, for arrays too large to be loaded
+/// at once.
+pub struct NpyRows {
+    path: PathBuf,
+    rows: usize,
+    cols: usize,
+}
+
+impl NpyRows {
+    /// Opens the file at `path` and reads its header. Fails if the array is not a row-wise 2D
+    /// array in C order, as the other traces are not contiguous in the file.
+    pub fn open(path: impl AsRef<Path>, layout: NpyLayout) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let npy = NpyFile::new(BufReader::new(open_file(&path)?))?;
+        let unsupported = |message: &str| io::Error::new(io::ErrorKind::Unsupported, message);
+        let &[rows, cols] = npy.shape() else {
+            return Err(unsupported("not a 2D array"));
+        };
+        let (rows, cols) = (rows as usize, cols as usize);
+        if resolve_layout(layout, rows, cols) != NpyLayout::Rows {
+            return Err(unsupported("column-wise arrays cannot be read by trace"));
+        }
+        if npy.order() != Order::C {
+            return Err(unsupported(
+                "Fortran-ordered arrays cannot be read by trace",
+            ));
+        }
+        tracing::info!(
+                    target: "loader",
+            "{}: NumPy {}, shape ({}, {}), row-wise, read on demand",
+            path.display(),
+            npy.dtype().descr(),
+            rows,
+            cols
+        );
+        Ok(Self { path, rows, cols })
+    }
+
+    /// Number of traces in the array.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Reads the trace of row `index`.
+    pub fn read(&self, index: usize) -> io::Result<Vec<f32>> {
+        if index >= self.rows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no trace {}", index),
+            ));
+        }
+        let npy = NpyFile::new(BufReader::new(open_file(&self.path)?))?;
+        let DType::Plain(dtype) = npy.dtype() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid numpy data type",
+            ));
+        };
+        let start = (index * self.cols) as u64;
+        macro_rules! read_row {
+            ($t:ty) => {{
+                let mut data = npy
+                    .data::<$t>()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                data.seek_to(start)?;
+                data.take(self.cols).map(|x| x.map(|x| x as f32)).collect()
+            }};
+        }
+        match (dtype.type_char(), dtype.num_bytes()) {
+            (npyz::TypeChar::Int, Some(1)) => read_row!(i8),
+            (npyz::TypeChar::Int, Some(2)) => read_row!(i16),
+            (npyz::TypeChar::Int, Some(4)) => read_row!(i32),
+            (npyz::TypeChar::Uint, Some(1)) => read_row!(u8),
+            (npyz::TypeChar::Uint, Some(2)) => read_row!(u16),
+            (npyz::TypeChar::Uint, Some(4)) => read_row!(u32),
+            (npyz::TypeChar::Float, Some(4)) => read_row!(f32),
+            (npyz::TypeChar::Float, Some(8)) => read_row!(f64),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported data type",
+            )),
+        }
     }
 }
@@ -0,0 +1,889 @@
+//! Apache Parquet file parser (`.parquet`), reading the numeric columns of flat schemas. The
+//! Feather files, which hold the same columnar data, are read by the [`arrow`] module.
+//!
+//! Reference: Apache Parquet format specification and its `parquet.thrift` definitions. The
+//! metadata is encoded with the Thrift compact protocol. Pages may be uncompressed or compressed
+//! with Snappy or gzip, and hold plain, dictionary or byte stream split encoded values.
+
+use super::{LoadError, arrow, files::open_reader};
+use flate2::read::MultiGzDecoder;
+use std::io::{self, Read};
+
+/// Magic bytes at the start and at the end of the files.
+const MAGIC: &[u8; 4] = b"PAR1";
+
+/// Physical types.
+const BOOLEAN: i64 = 0;
+const INT32: i64 = 1;
+const INT64: i64 = 2;
+const INT96: i64 = 3;
+const FLOAT: i64 = 4;
+const DOUBLE: i64 = 5;
+const BYTE_ARRAY: i64 = 6;
+const FIXED_LEN_BYTE_ARRAY: i64 = 7;
+
+/// Repetition types.
+const OPTIONAL: i64 = 1;
+const REPEATED: i64 = 2;
+
+/// Compression codecs.
+const UNCOMPRESSED: i64 = 0;
+const SNAPPY: i64 = 1;
+const GZIP: i64 = 2;
+
+/// Page types.
+const DATA_PAGE: i64 = 0;
+const DICTIONARY_PAGE: i64 = 2;
+const DATA_PAGE_V2: i64 = 3;
+
+/// Maximum nesting of the metadata structures and of the schema groups.
+const MAX_DEPTH: usize = 64;
+
+/// Maximum number of values of a column, beyond which it cannot be loaded as a trace.
+const MAX_VALUES: usize = 1 << 30;
+
+/// Encodings.
+const PLAIN: i64 = 0;
+const PLAIN_DICTIONARY: i64 = 2;
+const RLE_DICTIONARY: i64 = 8;
+const BYTE_STREAM_SPLIT: i64 = 9;
+
+/// Name and type of a column.
+pub struct ColumnInfo {
+    /// Name of the column, prefixed with the names of its parent groups.
+    pub name: String,
+    /// Type of the column, as named by the format.
+    pub ty: String,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn unsupported(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.into())
+}
+
+/// A value of the Thrift compact protocol. Maps and sets are skipped.
+enum Value<'a> {
+    Bool(bool),
+    Int(i64),
+    Binary(&'a [u8]),
+    List(Vec<Value<'a>>),
+    Struct(Vec<(i16, Value<'a>)>),
+    Skipped,
+}
+
+impl<'a> Value<'a> {
+    /// Returns the field `id` of a structure.
+    fn field(&self, id: i16) -> Option<&Value<'a>> {
+        match self {
+            Value::Struct(fields) => fields.iter().find(|(i, _)| *i == id).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn int(&self, id: i16) -> Option<i64> {
+        match self.field(id)? {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, id: i16) -> Option<bool> {
+        match self.field(id)? {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn string(&self, id: i16) -> Option<String> {
+        match self.field(id)? {
+            Value::Binary(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+
+    fn list(&self, id: i16) -> &[Value<'a>] {
+        match self.field(id) {
+            Some(Value::List(values)) => values,
+            _ => &[],
+        }
+    }
+}
+
+/// Reads values of the Thrift compact protocol.
+struct Thrift<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Number of structures and containers being read.
+    depth: usize,
+}
+
+impl<'a> Thrift<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    /// Returns the number of bytes left to read.
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| invalid("truncated metadata"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("invalid varint in metadata"))
+    }
+
+    fn zigzag(&mut self) -> io::Result<i64> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.remaining() {
+            return Err(invalid("truncated metadata"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads the number of elements of a list or map, each taking at least one byte.
+    fn container_len(&mut self, len: usize) -> io::Result<usize> {
+        if len > self.remaining() {
+            return Err(invalid("truncated metadata"));
+        }
+        Ok(len)
+    }
+
+    /// Reads an element of compact type `ty` of a list or map, where booleans are written as one
+    /// byte.
+    fn element(&mut self, ty: u8) -> io::Result<Value<'a>> {
+        match ty {
+            1 | 2 => Ok(Value::Bool(self.byte()? == 1)),
+            _ => self.value(ty),
+        }
+    }
+
+    /// Reads a value of compact type `ty`.
+    fn value(&mut self, ty: u8) -> io::Result<Value<'a>> {
+        if matches!(ty, 9..=12) {
+            if self.depth == MAX_DEPTH {
+                return Err(invalid("metadata nested too deeply"));
+            }
+            self.depth += 1;
+        }
+        let value = self.nested_value(ty);
+        if matches!(ty, 9..=12) {
+            self.depth -= 1;
+        }
+        value
+    }
+
+    fn nested_value(&mut self, ty: u8) -> io::Result<Value<'a>> {
+        Ok(match ty {
+            1 => Value::Bool(true),
+            2 => Value::Bool(false),
+            3 => Value::Int(self.byte()? as i8 as i64),
+            4..=6 => Value::Int(self.zigzag()?),
+            7 => Value::Int(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            8 => {
+                let len = self.varint()? as usize;
+                Value::Binary(self.bytes(len)?)
+            }
+            9 | 10 => {
+                let header = self.byte()?;
+                let len = match header >> 4 {
+                    15 => self.varint()? as usize,
+                    len => len as usize,
+                };
+                let len = self.container_len(len)?;
+                let ty = header & 0x0f;
+                let values = (0..len)
+                    .map(|_| self.element(ty))
+                    .collect::<io::Result<_>>()?;
+                Value::List(values)
+            }
+            11 => {
+                let len = self.varint()? as usize;
+                if len > 0 {
+                    let types = self.byte()?;
+                    for _ in 0..self.container_len(len)? {
+                        self.element(types >> 4)?;
+                        self.element(types & 0x0f)?;
+                    }
+                }
+                Value::Skipped
+            }
+            12 => self.fields()?,
+            ty => return Err(invalid(format!("invalid metadata type {}", ty))),
+        })
+    }
+
+    /// Reads a structure.
+    fn structure(&mut self) -> io::Result<Value<'a>> {
+        self.value(12)
+    }
+
+    /// Reads the fields of a structure, up to its stop field.
+    fn fields(&mut self) -> io::Result<Value<'a>> {
+        let mut fields = Vec::new();
+        let mut id = 0i16;
+        loop {
+            let header = self.byte()?;
+            if header == 0 {
+                return Ok(Value::Struct(fields));
+            }
+            id = match header >> 4 {
+                0 => self.zigzag()? as i16,
+                delta => id
+                    .checked_add(delta as i16)
+                    .ok_or_else(|| invalid("invalid field identifier in metadata"))?,
+            };
+            fields.push((id, self.value(header & 0x0f)?));
+        }
+    }
+}
+
+/// Decompresses a Snappy block.
+fn snappy(data: &[u8]) -> io::Result<Vec<u8>> {
+    let truncated = || invalid("truncated Snappy block");
+    let mut reader = Thrift::new(data);
+    let len = reader.varint()? as usize;
+    // The length is only trusted once the block is decompressed.
+    let mut output = Vec::with_capacity(len.min(data.len()));
+    let mut pos = reader.pos;
+    let len_left = |output: &Vec<u8>| len - output.len();
+    let read_le = |pos: &mut usize, n: usize| -> io::Result<usize> {
+        let bytes = data.get(*pos..*pos + n).ok_or_else(truncated)?;
+        *pos += n;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, &b| (acc << 8) | b as usize))
+    };
+    while pos < data.len() {
+        let tag = data[pos];
+        pos += 1;
+        let (len, offset) = match tag & 3 {
+            0 => {
+                let len = match tag >> 2 {
+                    len @ 0..60 => len as usize,
+                    n => read_le(&mut pos, n as usize - 59)?,
+                } + 1;
+                let literal = data
+                    .get(pos..)
+                    .and_then(|d| d.get(..len))
+                    .ok_or_else(truncated)?;
+                if literal.len() > len_left(&output) {
+                    return Err(invalid("Snappy block longer than declared"));
+                }
+                output.extend_from_slice(literal);
+                pos += len;
+                continue;
+            }
+            1 => (
+                ((tag >> 2) & 7) as usize + 4,
+                ((tag as usize >> 5) << 8) | read_le(&mut pos, 1)?,
+            ),
+            2 => ((tag >> 2) as usize + 1, read_le(&mut pos, 2)?),
+            _ => ((tag >> 2) as usize + 1, read_le(&mut pos, 4)?),
+        };
+        if offset == 0 || offset > output.len() {
+            return Err(invalid("invalid Snappy copy"));
+        }
+        if len > len_left(&output) {
+            return Err(invalid("Snappy block longer than declared"));
+        }
+        // Copies may overlap their output, repeating the last bytes.
+        let start = output.len() - offset;
+        for i in 0..len {
+            output.push(output[start + i]);
+        }
+    }
+    if output.len() != len {
+        return Err(invalid("Snappy block shorter than declared"));
+    }
+    Ok(output)
+}
+
+fn decompress(data: &[u8], codec: i64) -> io::Result<Vec<u8>> {
+    match codec {
+        UNCOMPRESSED => Ok(data.to_vec()),
+        SNAPPY => snappy(data),
+        GZIP => {
+            let mut output = Vec::new();
+            MultiGzDecoder::new(data).read_to_end(&mut output)?;
+            Ok(output)
+        }
+        codec => Err(unsupported(format!(
+            "unsupported Parquet compression codec {}",
+            codec
+        ))),
+    }
+}
+
+/// Decodes `count` values of the RLE/bit-packing hybrid encoding with the given bit width.
+fn rle_hybrid(data: &[u8], bit_width: u32, count: usize) -> io::Result<Vec<u32>> {
+    if bit_width > 32 {
+        return Err(invalid(format!("invalid bit width {}", bit_width)));
+    }
+    if count > MAX_VALUES {
+        return Err(invalid(format!(
+            "{} values, more than the {} supported",
+            count, MAX_VALUES
+        )));
+    }
+    let mut reader = Thrift::new(data);
+    // Runs can repeat a value many times, but most pages hold fewer values than bits.
+    let mut values = Vec::with_capacity(count.min(data.len().saturating_mul(8)));
+    let value_bytes = bit_width.div_ceil(8) as usize;
+    while values.len() < count {
+        let header = reader.varint()? as usize;
+        if header & 1 == 0 {
+            let bytes = reader.bytes(value_bytes)?;
+            let value = bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32);
+            values.extend(std::iter::repeat_n(
+                value,
+                (header >> 1).min(count - values.len()),
+            ));
+        } else {
+            let groups = header >> 1;
+            let len = groups
+                .checked_mul(bit_width as usize)
+                .ok_or_else(|| invalid("truncated bit-packed values"))?;
+            let bytes = reader.bytes(len)?;
+            let mask = (1u64 << bit_width) - 1;
+            for i in 0..(groups * 8).min(count - values.len()) {
+                let bit = i * bit_width as usize;
+                let mut word = 0u64;
+                for (k, &b) in bytes[bit / 8..].iter().take(5).enumerate() {
+                    word |= (b as u64) << (8 * k);
+                }
+                values.push(((word >> (bit % 8)) & mask) as u32);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Decodes plainly encoded values of a physical type.
+fn plain(data: &[u8], ty: i64, count: usize) -> io::Result<Vec<f32>> {
+    let width = match ty {
+        INT32 | FLOAT => 4,
+        _ => 8,
+    };
+    if count.checked_mul(width).is_none_or(|len| data.len() < len) {
+        return Err(invalid("truncated page values"));
+    }
+    Ok(data
+        .chunks_exact(width)
+        .take(count)
+        .map(|bytes| match ty {
+            INT32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            INT64 => i64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            FLOAT => f32::from_le_bytes(bytes.try_into().unwrap()),
+            _ => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+        })
+        .collect())
+}
+
+/// A leaf column of the schema.
+struct Leaf {
+    info: ColumnInfo,
+    ty: i64,
+    /// Maximum definition and repetition levels, given by the optional and repeated fields of
+    /// its path.
+    max_definition: u32,
+    max_repetition: u32,
+}
+
+/// Lists the leaf columns of the flattened schema tree, whose first element is the root.
+fn leaves(schema: &[Value]) -> Vec<Leaf> {
+    fn walk(
+        schema: &[Value],
+        pos: &mut usize,
+        path: &[String],
+        definition: u32,
+        repetition: u32,
+        leaves: &mut Vec<Leaf>,
+    ) {
+        // Deeper groups are ignored, as the elements missing at the end of the schema.
+        if path.len() == MAX_DEPTH {
+            return;
+        }
+        let Some(element) = schema.get(*pos) else {
+            return;
+        };
+        *pos += 1;
+        let name = element.string(4).unwrap_or_default();
+        let repetition_type = element.int(3);
+        let definition = definition + (repetition_type == Some(OPTIONAL)) as u32;
+        let repetition = repetition + (repetition_type == Some(REPEATED)) as u32;
+        let path = [path, &[name]].concat();
+        match element.int(5) {
+            Some(children) if children > 0 || element.int(1).is_none() => {
+                for _ in 0..children {
+                    if *pos >= schema.len() {
+                        break;
+                    }
+                    walk(schema, pos, &path, definition, repetition, leaves);
+                }
+            }
+            _ => {
+                let ty = element.int(1).unwrap_or(-1);
+                leaves.push(Leaf {
+                    info: ColumnInfo {
+                        name: path.join("."),
+                        ty: match ty {
+                            BOOLEAN => "BOOLEAN",
+                            INT32 => "INT32",
+                            INT64 => "INT64",
+                            INT96 => "INT96",
+                            FLOAT => "FLOAT",
+                            DOUBLE => "DOUBLE",
+                            BYTE_ARRAY => "BYTE_ARRAY",
+                            FIXED_LEN_BYTE_ARRAY => "FIXED_LEN_BYTE_ARRAY",
+                            _ => "unknown",
+                        }
+                        .to_string(),
+                    },
+                    ty,
+                    max_definition: definition,
+                    max_repetition: repetition,
+                });
+            }
+        }
+    }
+    let mut leaves = Vec::new();
+    let Some(root) = schema.first() else {
+        return leaves;
+    };
+    let mut pos = 1;
+    for _ in 0..root.int(5).unwrap_or(0) {
+        if pos >= schema.len() {
+            break;
+        }
+        walk(schema, &mut pos, &[], 0, 0, &mut leaves);
+    }
+    leaves
+}
+
+/// Parses the footer of a Parquet file.
+fn read_metadata(data: &[u8]) -> io::Result<Value<'_>> {
+    if data.len() < 12 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        return Err(invalid("not a Parquet file"));
+    }
+    let len = u32::from_le_bytes(data[data.len() - 8..data.len() - 4].try_into().unwrap()) as usize;
+    let start = (data.len() - 8)
+        .checked_sub(len)
+        .ok_or_else(|| invalid("invalid metadata length"))?;
+    Thrift::new(&data[start..data.len() - 8]).structure()
+}
+
+/// Lists the leaf columns of a Parquet file, with the number of rows.
+fn parquet_columns(data: &[u8]) -> io::Result<(Vec<ColumnInfo>, usize)> {
+    let metadata = read_metadata(data)?;
+    let columns = leaves(metadata.list(2))
+        .into_iter()
+        .map(|leaf| leaf.info)
+        .collect();
+    Ok((columns, metadata.int(3).unwrap_or(0) as usize))
+}
+
+/// Reads the values of a column chunk, null values being NaN.
+fn read_chunk(data: &[u8], leaf: &Leaf, chunk: &Value) -> io::Result<Vec<f32>> {
+    let meta = chunk
+        .field(3)
+        .ok_or_else(|| invalid("column chunk without metadata"))?;
+    let codec = meta.int(4).unwrap_or(UNCOMPRESSED);
+    let count = meta.int(5).unwrap_or(0) as usize;
+    if count > MAX_VALUES {
+        return Err(invalid(format!(
+            "{} values, more than the {} supported",
+            count, MAX_VALUES
+        )));
+    }
+    let data_offset = meta.int(9).unwrap_or(0);
+    let mut pos = match meta.int(11) {
+        Some(offset) if offset > 0 && offset < data_offset => offset,
+        _ => data_offset,
+    } as usize;
+    let bit_width = 32 - leaf.max_definition.leading_zeros();
+    let mut dictionary: Option<Vec<f32>> = None;
+    // Null values are not stored, so a chunk may hold more values than bytes.
+    let mut values = Vec::with_capacity(count.min(data.len()));
+    while values.len() < count {
+        let mut reader = Thrift::new(
+            data.get(pos..)
+                .ok_or_else(|| invalid("page beyond the file"))?,
+        );
+        let header = reader.structure()?;
+        let page_start = pos + reader.pos;
+        let compressed_size = header.int(3).unwrap_or(0) as usize;
+        let page = data
+            .get(page_start..)
+            .and_then(|page| page.get(..compressed_size))
+            .ok_or_else(|| invalid("truncated page"))?;
+        pos = page_start + compressed_size;
+        match header.int(1) {
+            Some(DICTIONARY_PAGE) => {
+                let dictionary_header = header.field(7);
+                let len = dictionary_header.and_then(|h| h.int(1)).unwrap_or(0) as usize;
+                dictionary = Some(plain(&decompress(page, codec)?, leaf.ty, len)?);
+            }
+            Some(ty @ (DATA_PAGE | DATA_PAGE_V2)) => {
+                let (page_values, encoding, definitions, content) = if ty == DATA_PAGE {
+                    let page_header = header
+                        .field(5)
+                        .ok_or_else(|| invalid("data page without header"))?;
+                    let content = decompress(page, codec)?;
+                    let page_values = page_header.int(1).unwrap_or(0) as usize;
+                    // The levels are prefixed with their length.
+                    let mut content = &content[..];
+                    let mut definitions = None;
+                    if leaf.max_definition > 0 {
+                        let len = u32::from_le_bytes(
+                            content
+                                .get(..4)
+                                .ok_or_else(|| invalid("truncated levels"))?
+                                .try_into()
+                                .unwrap(),
+                        ) as usize;
+                        let levels = content
+                            .get(4..)
+                            .and_then(|levels| levels.get(..len))
+                            .ok_or_else(|| invalid("truncated levels"))?;
+                        definitions = Some(rle_hybrid(levels, bit_width, page_values)?);
+                        content = &content[4 + len..];
+                    }
+                    (
+                        page_values,
+                        page_header.int(2).unwrap_or(PLAIN),
+                        definitions,
+                        content.to_vec(),
+                    )
+                } else {
+                    let page_header = header
+                        .field(8)
+                        .ok_or_else(|| invalid("data page without header"))?;
+                    let page_values = page_header.int(1).unwrap_or(0) as usize;
+                    let definitions_len = page_header.int(5).unwrap_or(0) as usize;
+                    let repetitions_len = page_header.int(6).unwrap_or(0) as usize;
+                    let levels_len = definitions_len
+                        .checked_add(repetitions_len)
+                        .filter(|&len| len <= page.len())
+                        .ok_or_else(|| invalid("truncated levels"))?;
+                    // Only the values are compressed.
+                    let definitions = (leaf.max_definition > 0)
+                        .then(|| {
+                            rle_hybrid(&page[repetitions_len..levels_len], bit_width, page_values)
+                        })
+                        .transpose()?;
+                    let content = if page_header.bool(7).unwrap_or(true) {
+                        decompress(&page[levels_len..], codec)?
+                    } else {
+                        page[levels_len..].to_vec()
+                    };
+                    (
+                        page_values,
+                        page_header.int(4).unwrap_or(PLAIN),
+                        definitions,
+                        content,
+                    )
+                };
+                let present = definitions.as_ref().map_or(page_values, |levels| {
+                    levels.iter().filter(|&&l| l == leaf.max_definition).count()
+                });
+                let decoded = match encoding {
+                    PLAIN => plain(&content, leaf.ty, present)?,
+                    PLAIN_DICTIONARY | RLE_DICTIONARY => {
+                        let dictionary = dictionary
+                            .as_ref()
+                            .ok_or_else(|| invalid("dictionary page missing"))?;
+                        let bit_width = *content.first().unwrap_or(&0) as u32;
+                        rle_hybrid(content.get(1..).unwrap_or_default(), bit_width, present)?
+                            .into_iter()
+                            .map(|i| dictionary.get(i as usize).copied().unwrap_or(f32::NAN))
+                            .collect()
+                    }
+                    BYTE_STREAM_SPLIT => {
+                        let width = if matches!(leaf.ty, INT32 | FLOAT) {
+                            4
+                        } else {
+                            8
+                        };
+                        if present
+                            .checked_mul(width)
+                            .is_none_or(|len| content.len() < len)
+                        {
+                            return Err(invalid("truncated page values"));
+                        }
+                        let interleaved: Vec<u8> = (0..present * width)
+                            .map(|i| content[(i % width) * present + i / width])
+                            .collect();
+                        plain(&interleaved, leaf.ty, present)?
+                    }
+                    encoding => {
+                        return Err(unsupported(format!(
+                            "unsupported Parquet encoding {}",
+                            encoding
+                        )));
+                    }
+                };
+                match definitions {
+                    Some(levels) => {
+                        let mut decoded = decoded.into_iter();
+                        values.extend(levels.iter().map(|&level| {
+                            if level == leaf.max_definition {
+                                decoded.next().unwrap_or(f32::NAN)
+                            } else {
+                                f32::NAN
+                            }
+                        }));
+                    }
+                    None => values.extend(decoded),
+                }
+            }
+            // Index pages are skipped.
+            _ => {}
+        }
+    }
+    Ok(values)
+}
+
+/// Reads the column `index` of a Parquet file, concatenating its row groups.
+fn read_parquet_column(data: &[u8], index: usize) -> io::Result<Vec<f32>> {
+    let metadata = read_metadata(data)?;
+    let leaves = leaves(metadata.list(2));
+    let leaf = &leaves[index];
+    if leaf.max_repetition > 0 {
+        return Err(unsupported(format!(
+            "column {} is repeated, only flat columns are supported",
+            leaf.info.name
+        )));
+    }
+    if !matches!(leaf.ty, INT32 | INT64 | FLOAT | DOUBLE) {
+        return Err(unsupported(format!(
+            "column {} of type {} is not numeric",
+            leaf.info.name, leaf.info.ty
+        )));
+    }
+    let mut values = Vec::new();
+    for row_group in metadata.list(4) {
+        let chunk = row_group
+            .list(1)
+            .get(index)
+            .ok_or_else(|| invalid("row group without the column"))?;
+        values.extend(read_chunk(data, leaf, chunk)?);
+    }
+    Ok(values)
+}
+
+/// Describes the columns of a Parquet or Feather file in one line.
+fn summary(path: &str, format: &str, columns: &[ColumnInfo], rows: usize) -> String {
+    format!(
+        "{}: {}, {} rows, columns {}",
+        path,
+        format,
+        rows,
+        columns
+            .iter()
+            .map(|c| format!("{} ({})", c.name, c.ty))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Lists the columns of a Parquet or Feather file, with the number of rows and the name of the
+/// format.
+fn columns(data: &[u8]) -> io::Result<(Vec<ColumnInfo>, usize, &'static str)> {
+    if arrow::is_arrow(data) {
+        let (columns, rows) = arrow::arrow_columns(data)?;
+        Ok((columns, rows, "Feather"))
+    } else {
+        let (columns, rows) = parquet_columns(data)?;
+        Ok((columns, rows, "Parquet"))
+    }
+}
+
+/// Describes the columns of the Parquet or Feather file at `path` in one line.
+pub fn parquet_summary(path: &str) -> io::Result<String> {
+    let mut data = Vec::new();
+    open_reader(path)?.read_to_end(&mut data)?;
+    let (columns, rows, format) = columns(&data)?;
+    Ok(summary(path, format, &columns, rows))
+}
+
+/// Loads a numeric column of a Parquet or Feather file as a trace: the column named `name` if
+/// given, or the column at `index` otherwise. Null values are loaded as NaN.
+pub fn load_parquet<R: Read>(
+    mut reader: R,
+    path: &str,
+    index: usize,
+    name: Option<&str>,
+) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let (columns, rows, format) = columns(&data)
+        .map_err(|e| LoadError::invalid(format!("invalid Parquet or Feather file: {}", e)))?;
+    let index = match name {
+        Some(name) => columns
+            .iter()
+            .position(|c| c.name == name)
+            .ok_or_else(|| LoadError::invalid(format!("no column named {} found", name)))?,
+        None if index < columns.len() => index,
+        None => {
+            return Err(LoadError::invalid(format!(
+                "column {} not found, the file has {} columns",
+                index,
+                columns.len()
+            )));
+        }
+    };
+    tracing::info!(target: "loader", "{}", summary(path, format, &columns, rows));
+    let values = if format == "Feather" {
+        arrow::read_arrow_column(&data, index)
+    } else {
+        read_parquet_column(&data, index)
+    }
+    .map_err(|e| {
+        LoadError::invalid(format!(
+            "failed to read column {}: {}",
+            columns[index].name, e
+        ))
+    })?;
+    Ok(vec![values])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snappy_literals_and_copies() {
+        // "abc" as a literal, then a copy of 9 bytes at offset 3 overlapping its output.
+        let block = [0x0c, 0x08, b'a', b'b', b'c', 0x15, 0x03];
+        assert_eq!(snappy(&block).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn snappy_rejects_malformed_blocks() {
+        // Copy before any output.
+        assert!(snappy(&[0x04, 0x15, 0x03]).is_err());
+        // Literal longer than the block.
+        assert!(snappy(&[0x03, 0x08, b'a']).is_err());
+        // Output longer or shorter than declared.
+        assert!(snappy(&[0x02, 0x08, b'a', b'b', b'c']).is_err());
+        assert!(snappy(&[0x04, 0x08, b'a', b'b', b'c']).is_err());
+        // Huge declared length.
+        assert!(snappy(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0x00, b'a']).is_err());
+    }
+
+    #[test]
+    fn rle_hybrid_runs_and_bit_packing() {
+        // Run of four 5s with a bit width of 3.
+        assert_eq!(rle_hybrid(&[0x08, 0x05], 3, 4).unwrap(), [5, 5, 5, 5]);
+        // One group of 0 to 7 bit-packed on 3 bits, as in the specification.
+        let packed = [0x03, 0x88, 0xc6, 0xfa];
+        assert_eq!(rle_hybrid(&packed, 3, 8).unwrap(), [0, 1, 2, 3, 4, 5, 6, 7]);
+        // The padding of the last group is dropped.
+        assert_eq!(rle_hybrid(&packed, 3, 5).unwrap(), [0, 1, 2, 3, 4]);
+        // Runs and groups follow each other.
+        assert_eq!(
+            rle_hybrid(&[0x04, 0x07, 0x03, 0x88, 0xc6, 0xfa], 3, 4).unwrap(),
+            [7, 7, 0, 1]
+        );
+    }
+
+    #[test]
+    fn rle_hybrid_rejects_malformed_values() {
+        assert!(rle_hybrid(&[0x03, 0x88], 3, 8).is_err());
+        assert!(rle_hybrid(&[0x08, 0x05], 33, 4).is_err());
+        assert!(rle_hybrid(&[0x08, 0x05], 3, usize::MAX).is_err());
+        // Bit-packed groups whose size overflows.
+        assert!(
+            rle_hybrid(
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+                32,
+                8
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn thrift_structure() {
+        let data = [
+            // Field 1, i32 150.
+            0x15, 0xac, 0x02, //
+            // Field 2, binary "abc".
+            0x18, 0x03, b'a', b'b', b'c', //
+            // Field 4, list of the i32 1 and -1.
+            0x29, 0x25, 0x02, 0x01, //
+            // Field 100 with a long header, i32 7.
+            0x05, 0xc8, 0x01, 0x0e, //
+            // Field 101, true.
+            0x11, //
+            // Stop.
+            0x00,
+        ];
+        let mut reader = Thrift::new(&data);
+        let value = reader.structure().unwrap();
+        assert_eq!(reader.pos, data.len());
+        assert_eq!(value.int(1), Some(150));
+        assert_eq!(value.string(2).as_deref(), Some("abc"));
+        let list: Vec<_> = value
+            .list(4)
+            .iter()
+            .map(|v| match v {
+                Value::Int(i) => *i,
+                _ => panic!("not an integer"),
+            })
+            .collect();
+        assert_eq!(list, [1, -1]);
+        assert_eq!(value.int(100), Some(7));
+        assert_eq!(value.bool(101), Some(true));
+        assert!(value.field(3).is_none());
+    }
+
+    #[test]
+    fn thrift_rejects_malformed_metadata() {
+        // Truncated structure.
+        assert!(Thrift::new(&[0x15, 0xac]).structure().is_err());
+        // List longer than the data.
+        let list = [0x19, 0xf5, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x00];
+        assert!(Thrift::new(&list).structure().is_err());
+        // Map of booleans longer than the data.
+        let map = [0x1b, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x11, 0x00];
+        assert!(Thrift::new(&map).structure().is_err());
+        // Structures nested too deeply.
+        let mut nested = vec![0x1c; 1000];
+        nested.extend(vec![0x00; 1001]);
+        assert!(Thrift::new(&nested).structure().is_err());
+        // Field identifier overflow.
+        let overflow = [0x05, 0xfe, 0xff, 0x03, 0x00, 0xf5, 0x00, 0x00];
+        assert!(Thrift::new(&overflow).structure().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Raw binary samples without header, such as the dumps of acquisition tools or the samples piped
+//! from other command-line tools. The samples are little-endian, of the type given by `--dtype`.
+
+use super::{ExplicitFormat, LoadError, decode_curve};
+#[cfg(not(target_arch = "wasm32"))]
+use super::{STDIN_PATH, files::file_compression};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::trace::{PAGED_MIN_SAMPLES, PagedFile, Trace};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Type of the samples of raw files.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SampleType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    #[default]
+    F32,
+    F64,
+}
+
+impl SampleType {
+    pub const ALL: [Self; 8] = [
+        Self::I8,
+        Self::U8,
+        Self::I16,
+        Self::U16,
+        Self::I32,
+        Self::U32,
+        Self::F32,
+        Self::F64,
+    ];
+
+    fn format(self) -> ExplicitFormat {
+        match self {
+            Self::I8 => ExplicitFormat::Int8,
+            Self::U8 => ExplicitFormat::Uint8,
+            Self::I16 => ExplicitFormat::Int16,
+            Self::U16 => ExplicitFormat::Uint16,
+            Self::I32 => ExplicitFormat::Int32,
+            Self::U32 => ExplicitFormat::Uint32,
+            Self::F32 => ExplicitFormat::Fp32,
+            Self::F64 => ExplicitFormat::Fp64,
+        }
+    }
+
+    /// Size of a sample, in bytes.
+    pub fn size(self) -> usize {
+        self.format().bytes_per_point()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::I8 => "i8",
+            Self::U8 => "u8",
+            Self::I16 => "i16",
+            Self::U16 => "u16",
+            Self::I32 => "i32",
+            Self::U32 => "u32",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        }
+    }
+}
+
+/// Converts the whole samples at the start of `data`, and returns them with the number of bytes
+/// consumed. The bytes of an incomplete last sample are left.
+pub fn decode_raw(data: &[u8], dtype: SampleType) -> (Vec<f32>, usize) {
+    let samples = decode_curve(data, dtype.format(), true, 1.0, 0.0);
+    let consumed = samples.len() * dtype.size();
+    (samples, consumed)
+}
+
+/// Loads a raw file as a single trace of samples of type `dtype`.
+pub fn load_raw<R: Read>(mut reader: R, dtype: SampleType) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let (trace, consumed) = decode_raw(&data, dtype);
+    if consumed != data.len() {
+        return Err(size_error(data.len() as u64, dtype));
+    }
+    Ok(vec![trace])
+}
+
+/// Returns the trace of the raw file at `path` read by pages, if it has at least
+/// [`PAGED_MIN_SAMPLES`] samples. Returns `None` for the smaller files, the compressed files and
+/// the standard input, which must be loaded with [`load_raw`].
+#[cfg(not(target_arch = "wasm32"))]
+pub fn page_raw(path: &str, dtype: SampleType) -> Result<Option<Trace>, LoadError> {
+    if path == STDIN_PATH || file_compression(path)?.is_some() {
+        return Ok(None);
+    }
+    let file = PagedFile::open(path)?;
+    if file.len() < (PAGED_MIN_SAMPLES * dtype.size()) as u64 {
+        return Ok(None);
+    }
+    if !file.len().is_multiple_of(dtype.size() as u64) {
+        return Err(size_error(file.len(), dtype));
+    }
+    let len = (file.len() / dtype.size() as u64) as usize;
+    tracing::info!(target: "loader", "{}: {} raw {} samples, paged", path, len, dtype.name());
+    Ok(Some(file.trace(0, len, dtype)?))
+}
+
+fn size_error(size: u64, dtype: SampleType) -> LoadError {
+    LoadError::invalid(format!(
+        "size of {} bytes is not a multiple of the {} bytes of the {} samples",
+        size,
+        dtype.size(),
+        dtype.name()
+    ))
+}
@@ -0,0 +1,265 @@
+//! Sigrok session file parser (`.sr`), as saved by PulseView and sigrok-cli.
+//!
+//! Reference: sigrok wiki, "File format:Sigrok/v2". A session is a zip archive holding a
+//! `metadata` file describing the channels, the logic samples in `logic-1-<chunk>` files, and the
+//! float32 samples of each analog channel in `analog-1-<channel>-<chunk>` files.
+
+use super::{LoadError, files::open_file};
+use crate::digital::{DigitalLane, LogicCapture, LogicChannel, LogicLane};
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Seek},
+};
+use zip::ZipArchive;
+
+/// Device section of the metadata describing the capture.
+const DEVICE_SECTION: &str = "device 1";
+
+/// Maximum number of logic and analog channels, far above the sigrok devices, so corrupt
+/// metadata is rejected.
+const MAX_CHANNELS: usize = 4096;
+
+/// Parses the `[device 1]` section of the metadata, keyed by name.
+fn read_metadata<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> io::Result<HashMap<String, String>> {
+    let mut text = String::new();
+    archive.by_name("metadata")?.read_to_string(&mut text)?;
+    let mut section = String::new();
+    let mut entries = HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.to_string();
+        } else if section == DEVICE_SECTION
+            && let Some((key, value)) = line.split_once('=')
+        {
+            entries.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    if entries.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no device described in the sigrok metadata",
+        ));
+    }
+    Ok(entries)
+}
+
+/// Parses a sampling rate written by sigrok, such as `24 MHz` or `500 kHz`, in S/s.
+fn parse_sampling_rate(text: &str) -> Option<f64> {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let factor = match unit.trim() {
+        "Hz" | "" => 1.0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => return None,
+    };
+    value.parse::<f64>().ok().map(|value| value * factor)
+}
+
+/// Concatenates the chunks of the file named `name` in the archive, which may be stored whole or
+/// split in `<name>-1`, `<name>-2`... chunks.
+fn read_chunks<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> io::Result<Vec<u8>> {
+    let prefix = format!("{}-", name);
+    let mut chunks: Vec<(usize, String)> = archive
+        .file_names()
+        .filter_map(|file| {
+            if file == name {
+                Some((0, file.to_string()))
+            } else {
+                let index = file.strip_prefix(&prefix)?.parse().ok()?;
+                Some((index, file.to_string()))
+            }
+        })
+        .collect();
+    chunks.sort();
+    let mut data = Vec::new();
+    for (_, file) in chunks {
+        archive.by_name(&file)?.read_to_end(&mut data)?;
+    }
+    Ok(data)
+}
+
+/// Extracts the level of channel `bit` from the logic samples of `unit_size` bytes.
+fn logic_lane(data: &[u8], unit_size: usize, bit: usize) -> DigitalLane {
+    let (byte, mask) = (bit / 8, 1 << (bit % 8));
+    let mut levels = data
+        .chunks_exact(unit_size)
+        .map(|unit| unit[byte] & mask != 0);
+    let initial = levels.next().unwrap_or(false);
+    let mut level = initial;
+    let mut edges = Vec::new();
+    for (i, sample) in levels.enumerate() {
+        if sample != level {
+            level = sample;
+            edges.push(i + 1);
+        }
+    }
+    DigitalLane::from_edges(initial, edges)
+}
+
+/// Reads the channels of the sigrok session at `path`.
+pub fn open_sigrok(path: &str) -> io::Result<LogicCapture> {
+    read_sigrok(BufReader::new(open_file(path)?))
+}
+
+/// Reads the channels of a sigrok session.
+fn read_sigrok<R: Read + Seek>(reader: R) -> io::Result<LogicCapture> {
+    let mut archive = ZipArchive::new(reader)?;
+    let metadata = read_metadata(&mut archive)?;
+    let count = |key: &str| {
+        metadata
+            .get(key)
+            .map_or(Ok(0), |value| value.parse::<usize>())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", key, e)))
+    };
+    let probes = count("total probes")?;
+    let analog_probes = count("total analog")?;
+    let channels = probes.saturating_add(analog_probes);
+    if channels > MAX_CHANNELS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} channels in the sigrok metadata", channels),
+        ));
+    }
+
+    let mut logic = Vec::new();
+    let mut logic_samples = 0;
+    if probes > 0
+        && let Some(capture_file) = metadata.get("capturefile")
+    {
+        let unit_size = count("unitsize")?.max(probes.div_ceil(8));
+        let data = read_chunks(&mut archive, capture_file)?;
+        logic_samples = data.len() / unit_size;
+        // Only the enabled channels are named in the metadata.
+        for bit in 0..probes {
+            if let Some(name) = metadata.get(&format!("probe{}", bit + 1)) {
+                logic.push(LogicChannel {
+                    name: name.clone(),
+                    lane: LogicLane::Bit(logic_lane(&data, unit_size, bit)),
+                });
+            }
+        }
+    }
+
+    // Analog channels are numbered after the logic ones.
+    let mut analog = Vec::new();
+    for index in 1..=probes + analog_probes {
+        if let Some(name) = metadata.get(&format!("analog{}", index)) {
+            let data = read_chunks(&mut archive, &format!("analog-1-{}", index))?;
+            let samples = data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            analog.push((name.clone(), samples));
+        }
+    }
+
+    Ok(LogicCapture {
+        format: "sigrok session",
+        sampling_rate: metadata
+            .get("samplerate")
+            .and_then(|rate| parse_sampling_rate(rate)),
+        analog,
+        logic,
+        logic_samples,
+    })
+}
+
+/// Loads a sigrok session and returns its analog channels as traces. Captures without analog
+/// channels return their digital channels instead, as traces of zeros and ones.
+pub fn load_sigrok<R: Read + Seek>(reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let capture = read_sigrok(reader)
+        .map_err(|e| LoadError::invalid(format!("invalid sigrok session: {}", e)))?;
+    tracing::info!(target: "loader", "{}", capture.summary(path));
+    Ok(capture.into_traces())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    const METADATA: &str = "[global]\nsigrok version=0.5.2\n\n[device 1]\ncapturefile=logic-1\n\
+        total probes=2\nsamplerate=1 MHz\ntotal analog=1\nprobe1=D0\nprobe2=D1\nunitsize=1\n\
+        analog3=A0\n";
+
+    /// Builds a session archive holding `files`, with their names.
+    fn session(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Builds a session of two logic channels and one analog channel, the logic samples
+    /// being split in two chunks.
+    fn sigrok_file(metadata: &str) -> Vec<u8> {
+        let analog: Vec<u8> = [0.5f32, -1.0, 2.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        session(&[
+            ("version", b"2"),
+            ("metadata", metadata.as_bytes()),
+            ("logic-1-1", &[0b00, 0b01]),
+            ("logic-1-2", &[0b11, 0b10]),
+            ("analog-1-3-1", &analog),
+        ])
+    }
+
+    #[test]
+    fn channels_are_decoded() {
+        let capture = read_sigrok(Cursor::new(sigrok_file(METADATA))).unwrap();
+        assert_eq!(capture.sampling_rate, Some(1e6));
+        assert_eq!(capture.logic_samples, 4);
+        let LogicLane::Bit(lane) = &capture.logic[1].lane else {
+            panic!("D1 is not a logic level");
+        };
+        assert_eq!((lane.initial(), lane.edges()), (false, &[2][..]));
+        assert_eq!(capture.into_traces(), [[0.5, -1.0, 2.0]]);
+
+        // Captures without analog channels give their logic levels.
+        let metadata = METADATA.replace("analog3=A0", "");
+        let traces = load_sigrok(Cursor::new(sigrok_file(&metadata)), "a.sr").unwrap();
+        assert_eq!(traces, [[0.0, 1.0, 1.0, 0.0], [0.0, 0.0, 1.0, 1.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let load = |data: Vec<u8>| load_sigrok(Cursor::new(data), "a.sr");
+        // Not an archive, or truncated.
+        assert!(load(METADATA.as_bytes().to_vec()).is_err());
+        let data = sigrok_file(METADATA);
+        assert!(load(data[..data.len() - 10].to_vec()).is_err());
+        // Missing metadata, or no device described.
+        assert!(load(session(&[("version", b"2")])).is_err());
+        assert!(load(sigrok_file("[global]\nsigrok version=0.5.2\n")).is_err());
+        // Invalid or huge channel counts.
+        assert!(
+            load(sigrok_file(
+                &METADATA.replace("total probes=2", "total probes=x")
+            ))
+            .is_err()
+        );
+        let metadata = METADATA.replace("total probes=2", &format!("total probes={}", usize::MAX));
+        assert!(load(sigrok_file(&metadata)).is_err());
+    }
+
+    #[test]
+    fn sampling_rates() {
+        assert_eq!(parse_sampling_rate("24 MHz"), Some(24e6));
+        assert_eq!(parse_sampling_rate("500 kHz"), Some(500e3));
+        assert_eq!(parse_sampling_rate("100"), Some(100.0));
+        assert_eq!(parse_sampling_rate("1 THz"), None);
+    }
+}
@@ -2,7 +2,12 @@
 //!
 //! Reference: Tektronix "Reference Waveform File Format" manual (077-0220-11)
 
-use std::io::Read;
+use super::{LoadError, files::open_file};
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 /// WFM file format version.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -14,7 +19,7 @@ enum WfmVersion {
 
 /// Curve data encoding format (explicit dimension).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ExplicitFormat {
+pub enum ExplicitFormat {
     Int16,
     Int32,
     Uint32,
@@ -23,20 +28,61 @@ enum ExplicitFormat {
     Fp64,
     Uint8,
     Int8,
+    /// Not used by WFM files, but by the curves of ISF files.
+    Uint16,
 }
 
 impl ExplicitFormat {
     /// Returns the number of bytes for one data point.
-    fn bytes_per_point(self) -> usize {
+    pub fn bytes_per_point(self) -> usize {
         match self {
             Self::Int8 | Self::Uint8 => 1,
-            Self::Int16 => 2,
+            Self::Int16 | Self::Uint16 => 2,
             Self::Int32 | Self::Uint32 | Self::Fp32 => 4,
             Self::Uint64 | Self::Fp64 => 8,
         }
     }
 }
 
+/// Converts raw curve data points to values, using `value = raw_value * scale + offset`.
+///
+/// Trailing bytes not forming a complete data point are ignored. Also used for the curves read
+/// from the scopes over SCPI, which use the same encodings.
+pub fn decode_curve(
+    data: &[u8],
+    format: ExplicitFormat,
+    little_endian: bool,
+    scale: f64,
+    offset: f64,
+) -> Vec<f32> {
+    macro_rules! decode {
+        ($t:ty, $bytes:expr) => {{
+            let bytes = $bytes.try_into().unwrap();
+            if little_endian {
+                <$t>::from_le_bytes(bytes) as f64
+            } else {
+                <$t>::from_be_bytes(bytes) as f64
+            }
+        }};
+    }
+    data.chunks_exact(format.bytes_per_point())
+        .map(|bytes| {
+            let raw = match format {
+                ExplicitFormat::Int8 => bytes[0] as i8 as f64,
+                ExplicitFormat::Uint8 => bytes[0] as f64,
+                ExplicitFormat::Int16 => decode!(i16, bytes),
+                ExplicitFormat::Uint16 => decode!(u16, bytes),
+                ExplicitFormat::Int32 => decode!(i32, bytes),
+                ExplicitFormat::Uint32 => decode!(u32, bytes),
+                ExplicitFormat::Uint64 => decode!(u64, bytes),
+                ExplicitFormat::Fp32 => decode!(f32, bytes),
+                ExplicitFormat::Fp64 => decode!(f64, bytes),
+            };
+            (raw * scale + offset) as f32
+        })
+        .collect()
+}
+
 /// Binary parser with configurable byte order.
 struct WfmParser {
     data: Vec<u8>,
@@ -57,105 +103,62 @@ impl WfmParser {
         self.pos += n;
     }
 
-    fn read_u8(&mut self) -> u8 {
-        let v = self.data[self.pos];
-        self.pos += 1;
-        v
+    /// Reads the next `n` bytes, failing if the header is truncated.
+    fn read_bytes(&mut self, n: usize) -> Result<&[u8], LoadError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| LoadError::invalid("truncated WFM header"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.read_bytes(1)?[0])
     }
 
-    fn read_u32(&mut self) -> u32 {
-        let bytes: [u8; 4] = self.data[self.pos..self.pos + 4].try_into().unwrap();
-        self.pos += 4;
-        if self.little_endian {
+    fn read_u32(&mut self) -> Result<u32, LoadError> {
+        let little_endian = self.little_endian;
+        let bytes: [u8; 4] = self.read_bytes(4)?.try_into().unwrap();
+        Ok(if little_endian {
             u32::from_le_bytes(bytes)
         } else {
             u32::from_be_bytes(bytes)
-        }
+        })
     }
 
-    fn read_f64(&mut self) -> f64 {
-        let bytes: [u8; 8] = self.data[self.pos..self.pos + 8].try_into().unwrap();
-        self.pos += 8;
-        if self.little_endian {
+    fn read_f64(&mut self) -> Result<f64, LoadError> {
+        let little_endian = self.little_endian;
+        let bytes: [u8; 8] = self.read_bytes(8)?.try_into().unwrap();
+        Ok(if little_endian {
             f64::from_le_bytes(bytes)
         } else {
             f64::from_be_bytes(bytes)
-        }
+        })
     }
 
-    fn read_string(&mut self, n: usize) -> String {
-        let bytes = &self.data[self.pos..self.pos + n];
-        self.pos += n;
+    fn read_string(&mut self, n: usize) -> Result<String, LoadError> {
+        let bytes = self.read_bytes(n)?;
         let end = bytes.iter().position(|&b| b == 0).unwrap_or(n);
-        String::from_utf8_lossy(&bytes[..end]).to_string()
-    }
-
-    /// Read a single raw curve data point at the given byte offset in the file.
-    fn read_sample_at(&self, offset: usize, format: ExplicitFormat) -> f64 {
-        match format {
-            ExplicitFormat::Int8 => self.data[offset] as i8 as f64,
-            ExplicitFormat::Uint8 => self.data[offset] as f64,
-            ExplicitFormat::Int16 => {
-                let bytes: [u8; 2] = self.data[offset..offset + 2].try_into().unwrap();
-                if self.little_endian {
-                    i16::from_le_bytes(bytes) as f64
-                } else {
-                    i16::from_be_bytes(bytes) as f64
-                }
-            }
-            ExplicitFormat::Int32 => {
-                let bytes: [u8; 4] = self.data[offset..offset + 4].try_into().unwrap();
-                if self.little_endian {
-                    i32::from_le_bytes(bytes) as f64
-                } else {
-                    i32::from_be_bytes(bytes) as f64
-                }
-            }
-            ExplicitFormat::Uint32 => {
-                let bytes: [u8; 4] = self.data[offset..offset + 4].try_into().unwrap();
-                if self.little_endian {
-                    u32::from_le_bytes(bytes) as f64
-                } else {
-                    u32::from_be_bytes(bytes) as f64
-                }
-            }
-            ExplicitFormat::Uint64 => {
-                let bytes: [u8; 8] = self.data[offset..offset + 8].try_into().unwrap();
-                if self.little_endian {
-                    u64::from_le_bytes(bytes) as f64
-                } else {
-                    u64::from_be_bytes(bytes) as f64
-                }
-            }
-            ExplicitFormat::Fp32 => {
-                let bytes: [u8; 4] = self.data[offset..offset + 4].try_into().unwrap();
-                if self.little_endian {
-                    f32::from_le_bytes(bytes) as f64
-                } else {
-                    f32::from_be_bytes(bytes) as f64
-                }
-            }
-            ExplicitFormat::Fp64 => {
-                let bytes: [u8; 8] = self.data[offset..offset + 8].try_into().unwrap();
-                if self.little_endian {
-                    f64::from_le_bytes(bytes)
-                } else {
-                    f64::from_be_bytes(bytes)
-                }
-            }
-        }
+        Ok(String::from_utf8_lossy(&bytes[..end]).to_string())
     }
 
     /// Read a WfmCurveObject (30 bytes) and return (data_start, postcharge_start, postcharge_stop).
     /// All offsets are local to the frame's portion of the curve buffer.
-    fn read_curve_object(&mut self) -> (usize, usize, usize) {
+    fn read_curve_object(&mut self) -> Result<(usize, usize, usize), LoadError> {
         self.skip(4 + 4 + 2); // state_flags, type_of_checksum, checksum
-        let _precharge_start = self.read_u32();
-        let data_start = self.read_u32() as usize;
-        let postcharge_start = self.read_u32() as usize;
-        let postcharge_stop = self.read_u32() as usize;
+        let _precharge_start = self.read_u32()?;
+        let data_start = self.read_u32()? as usize;
+        let postcharge_start = self.read_u32()? as usize;
+        let postcharge_stop = self.read_u32()? as usize;
         self.skip(4); // end_of_curve
-        (data_start, postcharge_start, postcharge_stop)
+        if data_start > postcharge_start {
+            return Err(LoadError::invalid(format!(
+                "invalid WFM curve: data starts at {} after its end at {}",
+                data_start, postcharge_start
+            )));
+        }
+        Ok((data_start, postcharge_start, postcharge_stop))
     }
 
     /// Skip an explicit dimension's user view data section.
@@ -173,38 +176,95 @@ impl WfmParser {
     }
 }
 
-/// Loads a Tektronix WFM file and returns all frames as separate traces.
-///
-/// For single-frame files, returns a `Vec` with one element.
-/// For FastFrame files, returns one trace per frame.
-///
-/// Raw curve data is converted using: `voltage = raw_value * scale + offset`
-/// where scale and offset come from the explicit dimension 1 header.
-pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
-    let mut data = Vec::new();
-    reader
-        .read_to_end(&mut data)
-        .expect("Failed to read WFM file");
-    assert!(
-        data.len() >= 78,
-        "WFM file too small for static file header"
-    );
+/// Size of the static file information, at the start of the file.
+const STATIC_HEADER_SIZE: usize = 78;
 
-    let mut p = WfmParser::new(data);
+/// Location and encoding of the frames of a WFM file, read from its header.
+struct WfmLayout {
+    version: WfmVersion,
+    little_endian: bool,
+    format: ExplicitFormat,
+    /// Scale and offset of the explicit dimension 1 (voltage axis).
+    scale: f64,
+    offset: f64,
+    /// Sampling rate in S/s, NaN if unknown.
+    sampling_rate: f64,
+    /// Index of the trigger sample in the frames, which may be fractional or out of the frames.
+    trigger: Option<f64>,
+    curve_buffer_offset: usize,
+    /// Curve objects of the frames: (data_start, postcharge_start, postcharge_stop).
+    frame_offsets: Vec<(usize, usize, usize)>,
+}
 
-    // ==== Static file information (78 bytes) ====
+impl WfmLayout {
+    /// Returns the position of the curve data of frame `index` in the file.
+    ///
+    /// Curve offsets in each WfmCurveObject are LOCAL to that frame's portion of the contiguous
+    /// curve buffer. The frame stride (postcharge_stop from frame 0) gives the size of each
+    /// frame's region inside the buffer.
+    fn curve_range(&self, index: usize) -> Range<usize> {
+        let frame_stride = self.frame_offsets[0].2;
+        let (data_start, postcharge_start, _) = self.frame_offsets[index];
+        let frame_base = self.curve_buffer_offset + index * frame_stride;
+        frame_base + data_start..frame_base + postcharge_start
+    }
 
-    // Byte order verification (2 bytes).
-    let byte_order_raw = u16::from_le_bytes([p.data[0], p.data[1]]);
+    /// Converts the curve data of a frame to values.
+    fn decode(&self, data: &[u8]) -> Vec<f32> {
+        decode_curve(
+            data,
+            self.format,
+            self.little_endian,
+            self.scale,
+            self.offset,
+        )
+    }
+
+    fn summary(&self, path: &str) -> String {
+        let mut summary = format!(
+            "{}: Tektronix WFM {:?}, {:?}, {:.3} MS/s, {} frame(s), {} pts/frame",
+            path,
+            self.version,
+            self.format,
+            self.sampling_rate / 1e6,
+            self.frame_offsets.len(),
+            self.curve_range(0).len() / self.format.bytes_per_point()
+        );
+        if let Some(trigger) = self.trigger {
+            summary += &format!(", trigger at sample {:.1}", trigger);
+        }
+        summary
+    }
+}
+
+/// Verifies the byte order mark at the start of the file and sets the byte order of the parser
+/// accordingly.
+fn read_byte_order(p: &mut WfmParser) -> Result<(), LoadError> {
+    p.pos = 0;
+    let byte_order_raw = u16::from_le_bytes(p.read_bytes(2)?.try_into().unwrap());
     p.little_endian = match byte_order_raw {
         0x0F0F => true,
         0xF0F0 => false,
-        _ => panic!("Invalid WFM byte order: 0x{:04X}", byte_order_raw),
+        _ => {
+            return Err(LoadError::invalid(format!(
+                "invalid WFM byte order: 0x{:04X}",
+                byte_order_raw
+            )));
+        }
     };
-    p.pos = 2;
+    Ok(())
+}
+
+/// Parses the header of a WFM file. `p.data` must hold at least the whole header, up to the curve
+/// buffer.
+fn read_layout(p: &mut WfmParser) -> Result<WfmLayout, LoadError> {
+    // ==== Static file information (78 bytes) ====
+
+    // Byte order verification (2 bytes).
+    read_byte_order(p)?;
 
     // Version string (8 bytes)
-    let version_str = p.read_string(8);
+    let version_str = p.read_string(8)?;
     let version = if version_str.contains("WFM#001") {
         WfmVersion::V1
     } else if version_str.contains("WFM#002") {
@@ -212,22 +272,25 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
     } else if version_str.contains("WFM#003") {
         WfmVersion::V3
     } else {
-        panic!("Unsupported WFM version: {}", version_str);
+        return Err(LoadError::invalid(format!(
+            "unsupported WFM version: {}",
+            version_str
+        )));
     };
 
     p.skip(1 + 4); // num_digits_in_byte_count, bytes_to_eof
-    let bytes_per_point = p.read_u8() as usize;
-    let curve_buffer_offset = p.read_u32() as usize;
+    let bytes_per_point = p.read_u8()? as usize;
+    let curve_buffer_offset = p.read_u32()? as usize;
     p.skip(4 + 4 + 8 + 4); // hz_zoom_scale, hz_zoom_pos, vt_zoom_scale, vt_zoom_pos
     p.skip(32); // waveform_label
-    let n_fast_frames_minus_one = p.read_u32();
+    let n_fast_frames_minus_one = p.read_u32()?;
     p.skip(2); // wfm_header_size
 
     // ==== Waveform header ====
     // set_type(4) + wfm_cnt(4) + acq_counter(8) + trans_counter(8) + slot_id(4) +
     // is_static(4) + update_spec_count(4) + imp_dim_ref_count(4) + exp_dim_ref_count(4)
     p.skip(4 + 4 + 8 + 8 + 4 + 4 + 4 + 4 + 4);
-    let data_type = p.read_u32(); // data_type
+    let data_type = p.read_u32()?; // data_type
     // gen_purpose_counter(8) + accum_count(4) + target_accum(4) + curve_ref_count(4) +
     // num_req_ff(4) + num_acq_ff(4)
     p.skip(8 + 4 + 4 + 4 + 4 + 4);
@@ -237,11 +300,11 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
     p.skip(4 + 8); // pix_map_display_format, pix_map_max_value
 
     // ==== Explicit Dimension 1 (voltage axis) ====
-    let exp_dim1_scale = p.read_f64();
-    let exp_dim1_offset = p.read_f64();
+    let exp_dim1_scale = p.read_f64()?;
+    let exp_dim1_offset = p.read_f64()?;
     p.skip(4 + 20); // dim_size, units
     p.skip(8 + 8 + 8 + 8); // extent_min, extent_max, resolution, ref_point
-    let exp_dim1_format_raw = p.read_u32();
+    let exp_dim1_format_raw = p.read_u32()?;
     p.skip(4 + 4 * 5); // storage_type, n_value, over_range, under_range, high_range, low_range
     p.skip_user_view(version);
 
@@ -250,10 +313,11 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
     p.skip_user_view(version);
 
     // ==== Implicit Dimension 1 (time axis) ====
-    let imp_dim1_scale = p.read_f64();
-    // offset(8) + size(4) + units(20) + extent_min(8) + extent_max(8) +
+    let imp_dim1_scale = p.read_f64()?;
+    let imp_dim1_offset = p.read_f64()?;
+    // size(4) + units(20) + extent_min(8) + extent_max(8) +
     // resolution(8) + ref_point(8) + spacing(4)
-    p.skip(8 + 4 + 20 + 8 + 8 + 8 + 8 + 4);
+    p.skip(4 + 20 + 8 + 8 + 8 + 8 + 4);
     p.skip_user_view(version);
 
     // ==== Implicit Dimension 2 (skip entirely: 76 bytes description + user view) ====
@@ -267,14 +331,14 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
     p.skip(24); // real_point_offset(4) + tt_offset(8) + frac_sec(8) + gmt_sec(4)
 
     // ==== WfmCurveObject (first frame, 30 bytes) ====
-    let mut frame_offsets = vec![p.read_curve_object()];
+    let mut frame_offsets = vec![p.read_curve_object()?];
 
     // ==== FastFrame additional frames ====
     if n_fast_frames_minus_one > 0 {
         let n = n_fast_frames_minus_one as usize;
         p.skip(n * 24); // N-1 additional WfmUpdateSpecs
         for _ in 0..n {
-            frame_offsets.push(p.read_curve_object());
+            frame_offsets.push(p.read_curve_object()?);
         }
     }
 
@@ -289,56 +353,28 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
         (5, _) => ExplicitFormat::Fp64,
         (6, WfmVersion::V2 | WfmVersion::V3) => ExplicitFormat::Uint8,
         (7, WfmVersion::V2 | WfmVersion::V3) => ExplicitFormat::Int8,
-        _ => panic!(
-            "Unsupported explicit format {} for version {:?}",
-            exp_dim1_format_raw, version
-        ),
+        _ => {
+            return Err(LoadError::invalid(format!(
+                "unsupported WFM explicit format {} for version {:?}",
+                exp_dim1_format_raw, version
+            )));
+        }
     };
 
-    assert_eq!(
-        bytes_per_point,
-        format.bytes_per_point(),
-        "Bytes per point mismatch: header says {} but format {:?} requires {}",
-        bytes_per_point,
-        format,
-        format.bytes_per_point()
-    );
-
-    if data_type == 5 {
-        println!("Warning: Waveform database format. May not display as a simple trace.");
+    if bytes_per_point != format.bytes_per_point() {
+        return Err(LoadError::invalid(format!(
+            "WFM bytes per point mismatch: header says {} but format {:?} requires {}",
+            bytes_per_point,
+            format,
+            format.bytes_per_point()
+        )));
     }
 
-    // ==== Read curve data for each frame ====
-    // Curve offsets in each WfmCurveObject are LOCAL to that frame's portion of the
-    // contiguous curve buffer.  The frame stride (postcharge_stop from frame 0) gives
-    // the size of each frame's region inside the buffer.
-
-    let total_frames = frame_offsets.len();
-    let frame_stride = frame_offsets[0].2;
-    let mut all_frames = Vec::with_capacity(total_frames);
-
-    for (frame_idx, &(data_start, postcharge_start, _)) in frame_offsets.iter().enumerate() {
-        let frame_base = curve_buffer_offset + frame_idx * frame_stride;
-        let curve_data_start = frame_base + data_start;
-        let curve_data_end = frame_base + postcharge_start;
-
-        assert!(
-            curve_data_end <= p.data.len(),
-            "Frame {} curve data extends beyond file: end offset {} > file size {}",
-            frame_idx,
-            curve_data_end,
-            p.data.len()
+    if data_type == 5 {
+        tracing::warn!(
+            target: "loader",
+            "Waveform database format. May not display as a simple trace."
         );
-
-        let num_points = (curve_data_end - curve_data_start) / bytes_per_point;
-
-        let mut samples = Vec::with_capacity(num_points);
-        for i in 0..num_points {
-            let offset = curve_data_start + i * bytes_per_point;
-            let raw = p.read_sample_at(offset, format);
-            samples.push((raw * exp_dim1_scale + exp_dim1_offset) as f32);
-        }
-        all_frames.push(samples);
     }
 
     let sampling_rate = if imp_dim1_scale > 0.0 {
@@ -346,20 +382,119 @@ pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Vec<Vec<f32>> {
     } else {
         f64::NAN
     };
-    let pts = if let Some(first) = all_frames.first() {
-        first.len()
-    } else {
-        0
-    };
-    println!(
-        "{}: Tektronix WFM {:?}, {:?}, {:.3} MS/s, {} frame(s), {} pts/frame",
-        path,
+    // The time of the first point is the offset of the time axis, the trigger being at time 0.
+    let trigger = Some(-imp_dim1_offset * sampling_rate).filter(|t| t.is_finite());
+
+    Ok(WfmLayout {
         version,
+        little_endian: p.little_endian,
         format,
-        sampling_rate / 1e6,
-        total_frames,
-        pts
-    );
+        scale: exp_dim1_scale,
+        offset: exp_dim1_offset,
+        sampling_rate,
+        trigger,
+        curve_buffer_offset,
+        frame_offsets,
+    })
+}
+
+/// Loads a Tektronix WFM file and returns all frames as separate traces.
+///
+/// For single-frame files, returns a `Vec` with one element.
+/// For FastFrame files, returns one trace per frame.
+///
+/// Raw curve data is converted using: `voltage = raw_value * scale + offset`
+/// where scale and offset come from the explicit dimension 1 header.
+pub fn load_tek_wfm<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() < STATIC_HEADER_SIZE {
+        return Err(LoadError::invalid(
+            "WFM file too small for static file header",
+        ));
+    }
+
+    let mut p = WfmParser::new(data);
+    let layout = read_layout(&mut p)?;
 
-    all_frames
+    // ==== Read curve data for each frame ====
+    let all_frames = (0..layout.frame_offsets.len())
+        .map(|frame_idx| {
+            let range = layout.curve_range(frame_idx);
+            let curve = p.data.get(range.clone()).ok_or_else(|| {
+                LoadError::invalid(format!(
+                    "frame {} curve data extends beyond file: end offset {} > file size {}",
+                    frame_idx,
+                    range.end,
+                    p.data.len()
+                ))
+            })?;
+            Ok(layout.decode(curve))
+        })
+        .collect::<Result<_, LoadError>>()?;
+    tracing::info!(target: "loader", "{}", layout.summary(path));
+
+    Ok(all_frames)
+}
+
+/// A WFM file whose frames are read on demand, for FastFrame acquisitions too large to be loaded
+/// at once.
+pub struct WfmFile {
+    path: PathBuf,
+    layout: WfmLayout,
+}
+
+impl WfmFile {
+    /// Opens the file at `path` and reads its header.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = open_file(&path)?;
+        let mut data = vec![0; STATIC_HEADER_SIZE];
+        file.read_exact(&mut data)?;
+        // The header ends where the curve buffer starts.
+        let mut p = WfmParser::new(data);
+        read_byte_order(&mut p)?;
+        p.pos = 16;
+        let header_size = (p.read_u32()? as usize).max(STATIC_HEADER_SIZE);
+        p.data.resize(header_size, 0);
+        file.read_exact(&mut p.data[STATIC_HEADER_SIZE..])?;
+        let layout = read_layout(&mut p)?;
+        Ok(Self { path, layout })
+    }
+
+    /// Describes the format, the sampling rate and the frames of the file in one line.
+    pub fn summary(&self) -> String {
+        self.layout.summary(&self.path.to_string_lossy())
+    }
+
+    /// Number of frames in the file.
+    pub fn frames(&self) -> usize {
+        self.layout.frame_offsets.len()
+    }
+
+    /// Sampling rate in S/s, NaN if unknown.
+    pub fn sampling_rate(&self) -> f64 {
+        self.layout.sampling_rate
+    }
+
+    /// Index of the trigger sample in the frames, if recorded.
+    pub fn trigger(&self) -> Option<f64> {
+        self.layout.trigger
+    }
+
+    /// Reads the frame at `index`.
+    pub fn read(&self, index: usize) -> io::Result<Vec<f32>> {
+        if index >= self.frames() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no frame {}", index),
+            ));
+        }
+        let range = self.layout.curve_range(index);
+        let mut file = open_file(&self.path)?;
+        file.seek(SeekFrom::Start(range.start as u64))?;
+        let mut data = vec![0; range.len()];
+        file.read_exact(&mut data)?;
+        Ok(self.layout.decode(&data))
+    }
 }
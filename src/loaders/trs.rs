@@ -0,0 +1,390 @@
+//! Riscure Inspector TRS trace set parser.
+//!
+//! A TRS file starts with a header made of tag-length-value objects, followed by fixed-size trace
+//! records. Each record holds a title, the data bytes of the trace (typically the plaintext,
+//! ciphertext or key of the captured operation) and the samples. As records have a fixed size,
+//! any trace can be read without reading the whole file.
+
+use super::{
+    LoadError,
+    files::{FileReader, open_file},
+};
+use std::{
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Header tags.
+const TAG_NUMBER_OF_TRACES: u8 = 0x41;
+const TAG_NUMBER_OF_SAMPLES: u8 = 0x42;
+const TAG_SAMPLE_CODING: u8 = 0x43;
+const TAG_DATA_LENGTH: u8 = 0x44;
+const TAG_TITLE_SPACE: u8 = 0x45;
+const TAG_GLOBAL_TITLE: u8 = 0x46;
+const TAG_DESCRIPTION: u8 = 0x47;
+const TAG_SCALE_X: u8 = 0x4B;
+const TAG_SCALE_Y: u8 = 0x4C;
+const TAG_TRACE_PARAMETER_DEFINITIONS: u8 = 0x77;
+const TAG_TRACE_BLOCK: u8 = 0x5F;
+
+/// Encoding of the samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleCoding {
+    Int8,
+    Int16,
+    Int32,
+    Float32,
+}
+
+impl SampleCoding {
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0x01 => Ok(Self::Int8),
+            0x02 => Ok(Self::Int16),
+            0x04 => Ok(Self::Int32),
+            0x14 => Ok(Self::Float32),
+            _ => Err(invalid(format!("unsupported sample coding 0x{:02X}", byte))),
+        }
+    }
+
+    /// Returns the number of bytes of one sample.
+    fn size(self) -> usize {
+        match self {
+            Self::Int8 => 1,
+            Self::Int16 => 2,
+            Self::Int32 | Self::Float32 => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::Int8 => bytes[0] as i8 as f32,
+            Self::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            Self::Int32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+            Self::Float32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+        }
+    }
+}
+
+/// Named field of the trace data bytes, declared by TRS version 2 files.
+#[derive(Debug, Clone)]
+pub struct TraceParameter {
+    pub name: String,
+    /// Offset of the field in the data bytes.
+    pub offset: usize,
+    /// Length of the field, in bytes.
+    pub len: usize,
+}
+
+/// Header of a TRS file.
+#[derive(Debug, Clone)]
+pub struct TrsHeader {
+    pub traces: usize,
+    pub samples: usize,
+    coding: SampleCoding,
+    /// Number of data bytes of each trace.
+    pub data_len: usize,
+    /// Number of title bytes of each trace.
+    pub title_len: usize,
+    pub global_title: String,
+    pub description: String,
+    /// Time between two samples, in seconds.
+    pub x_scale: Option<f32>,
+    /// Factor applied to the samples.
+    y_scale: f32,
+    /// Named fields of the data bytes, if declared.
+    pub parameters: Vec<TraceParameter>,
+    /// Position of the first trace record in the file.
+    records_offset: u64,
+    /// Size of a trace record, in bytes.
+    record_size: usize,
+}
+
+impl TrsHeader {
+    /// Computes the size of a trace record, in bytes, or `None` if it overflows.
+    fn checked_record_size(&self) -> Option<usize> {
+        self.samples
+            .checked_mul(self.coding.size())?
+            .checked_add(self.title_len)?
+            .checked_add(self.data_len)
+    }
+}
+
+/// A trace of a TRS file.
+pub struct TrsRecord {
+    pub title: String,
+    pub data: Vec<u8>,
+    pub samples: Vec<f32>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a little-endian unsigned integer of 1 to 8 bytes.
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+/// Parses the trace parameter definitions object of TRS version 2 files.
+fn parse_parameters(value: &[u8]) -> io::Result<Vec<TraceParameter>> {
+    let truncated = || invalid("truncated trace parameter definitions".to_string());
+    let mut pos = 0;
+    let mut take = |n: usize| -> io::Result<&[u8]> {
+        let bytes = value.get(pos..pos + n).ok_or_else(truncated)?;
+        pos += n;
+        Ok(bytes)
+    };
+    let count = le_uint(take(2)?) as usize;
+    let mut parameters = Vec::with_capacity(count);
+    for _ in 0..count {
+        let name_len = le_uint(take(2)?) as usize;
+        let name = String::from_utf8_lossy(take(name_len)?).to_string();
+        let kind = take(1)?[0];
+        let len = le_uint(take(2)?) as usize;
+        let offset = le_uint(take(2)?) as usize;
+        let element_size = match kind {
+            // Byte, string and boolean.
+            0x01 | 0x20 | 0x31 => 1,
+            // Short, int, long, float and double.
+            0x02 | 0x04 | 0x08 => kind as usize,
+            0x14 => 4,
+            0x18 => 8,
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported parameter type 0x{:02X}",
+                    kind
+                )));
+            }
+        };
+        parameters.push(TraceParameter {
+            name,
+            offset,
+            len: len * element_size,
+        });
+    }
+    Ok(parameters)
+}
+
+/// Reads the header of a TRS file, leaving `reader` at the first trace record. The sizes declared
+/// by the header are checked against the length of the file.
+fn read_header<R: Read + Seek>(reader: &mut R) -> io::Result<TrsHeader> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut traces = None;
+    let mut samples = None;
+    let mut coding = SampleCoding::Float32;
+    let mut header = TrsHeader {
+        traces: 0,
+        samples: 0,
+        coding,
+        data_len: 0,
+        title_len: 0,
+        global_title: String::new(),
+        description: String::new(),
+        x_scale: None,
+        y_scale: 1.0,
+        parameters: Vec::new(),
+        records_offset: 0,
+        record_size: 0,
+    };
+    let mut offset = 0u64;
+    loop {
+        let mut tag_len = [0; 2];
+        reader.read_exact(&mut tag_len)?;
+        offset += 2;
+        let [tag, len] = tag_len;
+        let len = if len & 0x80 != 0 {
+            let mut bytes = vec![0; (len & 0x7F) as usize];
+            if bytes.len() > 8 {
+                return Err(invalid(format!("invalid length of object 0x{:02X}", tag)));
+            }
+            reader.read_exact(&mut bytes)?;
+            offset += bytes.len() as u64;
+            le_uint(&bytes)
+        } else {
+            len as u64
+        };
+        if len > file_len.saturating_sub(offset) {
+            return Err(invalid(format!(
+                "object 0x{:02X} of {} bytes beyond the end of the file",
+                tag, len
+            )));
+        }
+        let mut value = vec![0; len as usize];
+        reader.read_exact(&mut value)?;
+        offset += len;
+        let float = || {
+            value
+                .get(..4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        };
+        match tag {
+            TAG_NUMBER_OF_TRACES => traces = Some(le_uint(&value) as usize),
+            TAG_NUMBER_OF_SAMPLES => samples = Some(le_uint(&value) as usize),
+            TAG_SAMPLE_CODING => {
+                coding = SampleCoding::from_byte(*value.first().unwrap_or(&0))?;
+            }
+            TAG_DATA_LENGTH => header.data_len = le_uint(&value) as usize,
+            TAG_TITLE_SPACE => header.title_len = le_uint(&value) as usize,
+            TAG_GLOBAL_TITLE => header.global_title = String::from_utf8_lossy(&value).to_string(),
+            TAG_DESCRIPTION => header.description = String::from_utf8_lossy(&value).to_string(),
+            TAG_SCALE_X => header.x_scale = float().filter(|&x| x > 0.0),
+            TAG_SCALE_Y => header.y_scale = float().filter(|&y| y != 0.0).unwrap_or(1.0),
+            TAG_TRACE_PARAMETER_DEFINITIONS => header.parameters = parse_parameters(&value)?,
+            TAG_TRACE_BLOCK => break,
+            // Other objects are not needed for display.
+            _ => {}
+        }
+    }
+    header.traces = traces.ok_or_else(|| invalid("missing number of traces".to_string()))?;
+    header.samples = samples.ok_or_else(|| invalid("missing number of samples".to_string()))?;
+    header.coding = coding;
+    header.records_offset = offset;
+    header.record_size = header
+        .checked_record_size()
+        .ok_or_else(|| invalid("trace records too large".to_string()))?;
+    let records_len = (header.record_size as u64).checked_mul(header.traces as u64);
+    if records_len.is_none_or(|len| len > file_len - offset) {
+        return Err(invalid(format!(
+            "{} traces of {} bytes do not fit in the file",
+            header.traces, header.record_size
+        )));
+    }
+    Ok(header)
+}
+
+/// Reads the trace record at the current position of `reader`.
+fn read_record<R: Read>(reader: &mut R, header: &TrsHeader) -> io::Result<TrsRecord> {
+    let mut bytes = vec![0; header.record_size];
+    reader.read_exact(&mut bytes)?;
+    let (title, rest) = bytes.split_at(header.title_len);
+    let (data, samples) = rest.split_at(header.data_len);
+    Ok(TrsRecord {
+        title: String::from_utf8_lossy(title)
+            .trim_end_matches(['\0', ' '])
+            .to_string(),
+        data: data.to_vec(),
+        samples: samples
+            .chunks_exact(header.coding.size())
+            .map(|b| header.coding.decode(b) * header.y_scale)
+            .collect(),
+    })
+}
+
+/// Loads a TRS file and returns all its traces.
+pub fn load_trs<R: Read + Seek>(mut reader: R) -> Result<Vec<Vec<f32>>, LoadError> {
+    let header = read_header(&mut reader)
+        .map_err(|e| LoadError::invalid(format!("failed to read TRS header: {}", e)))?;
+    (0..header.traces)
+        .map(|i| {
+            read_record(&mut reader, &header)
+                .map(|record| record.samples)
+                .map_err(|e| LoadError::invalid(format!("failed to read trace {}: {}", i, e)))
+        })
+        .collect()
+}
+
+/// A TRS file whose traces are read on demand.
+pub struct TrsFile {
+    pub path: PathBuf,
+    pub header: TrsHeader,
+}
+
+impl TrsFile {
+    /// Opens the file at `path` and reads its header.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let header = read_header(&mut BufReader::new(open_file(&path)?))?;
+        Ok(Self { path, header })
+    }
+
+    /// Opens the file positioned at the record of the trace at `index`.
+    fn seek(&self, index: usize) -> io::Result<FileReader> {
+        if index >= self.header.traces {
+            return Err(invalid(format!("no trace {}", index)));
+        }
+        let mut file = open_file(&self.path)?;
+        file.seek(SeekFrom::Start(
+            self.header.records_offset + (index * self.header.record_size) as u64,
+        ))?;
+        Ok(file)
+    }
+
+    /// Reads the trace at `index`.
+    pub fn read(&self, index: usize) -> io::Result<TrsRecord> {
+        read_record(&mut BufReader::new(self.seek(index)?), &self.header)
+    }
+
+    /// Reads the data bytes of the trace at `index`, without its samples.
+    pub fn read_data(&self, index: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; self.header.title_len + self.header.data_len];
+        self.seek(index)?.read_exact(&mut bytes)?;
+        Ok(bytes.split_off(self.header.title_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Encodes a header object with a short length.
+    fn object(tag: u8, value: &[u8]) -> Vec<u8> {
+        [&[tag, value.len() as u8], value].concat()
+    }
+
+    /// Builds a TRS file of two traces of three 16-bit samples, with one data byte each.
+    fn trs_file() -> Vec<u8> {
+        let mut data = [
+            object(TAG_NUMBER_OF_TRACES, &[2]),
+            object(TAG_NUMBER_OF_SAMPLES, &[3, 0]),
+            object(TAG_SAMPLE_CODING, &[0x02]),
+            object(TAG_DATA_LENGTH, &[1]),
+            object(TAG_SCALE_Y, &0.5f32.to_le_bytes()),
+            object(TAG_TRACE_BLOCK, &[]),
+        ]
+        .concat();
+        for (byte, samples) in [(0xaa, [2i16, -4, 6]), (0xbb, [0, 8, -2])] {
+            data.push(byte);
+            data.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+        }
+        data
+    }
+
+    #[test]
+    fn traces_are_decoded() {
+        let traces = load_trs(Cursor::new(trs_file())).unwrap();
+        assert_eq!(traces, [[1.0, -2.0, 3.0], [0.0, 4.0, -1.0]]);
+
+        let mut reader = Cursor::new(trs_file());
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!((header.traces, header.samples, header.data_len), (2, 3, 1));
+        let record = read_record(&mut reader, &header).unwrap();
+        assert_eq!(record.data, [0xaa]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let data = trs_file();
+        // Truncated header or records.
+        assert!(load_trs(Cursor::new(&data[..5])).is_err());
+        assert!(load_trs(Cursor::new(&data[..data.len() - 1])).is_err());
+        // Missing number of traces.
+        assert!(load_trs(Cursor::new(&data[3..])).is_err());
+        // Unsupported sample coding.
+        let mut bad = data.clone();
+        bad[9] = 0x03;
+        assert!(load_trs(Cursor::new(bad)).is_err());
+        // Object or trace count beyond the end of the file.
+        let mut bad = data.clone();
+        bad[4] = 0x88;
+        assert!(load_trs(Cursor::new(bad)).is_err());
+        let mut bad = data;
+        bad[2] = 0xff;
+        assert!(load_trs(Cursor::new(bad)).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Value Change Dump parser (`.vcd`), as written by Verilog and VHDL simulators.
+//!
+//! Reference: IEEE 1364-2005, section 18 "Value change dump (VCD) files". The single-bit variables
+//! are loaded as logic levels, the vectors as buses, and the real variables as analog traces.
+
+use super::{LoadError, files::open_reader};
+use crate::digital::{BusLane, DigitalLane, LogicCapture, LogicChannel, LogicLane};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+};
+
+/// Maximum number of time steps of a dump, beyond which it cannot be loaded as traces.
+const MAX_SAMPLES: u64 = 1 << 28;
+
+/// Maximum number of bits of a vector, far above the buses of the designs, so corrupt headers
+/// are rejected.
+const MAX_VECTOR_SIZE: usize = 1 << 16;
+
+/// Kind of values of a variable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VarKind {
+    /// Vector of the given number of bits.
+    Bits(usize),
+    Real,
+    /// Strings and the other extensions, which are not displayed.
+    Other,
+}
+
+/// A variable declared in the header.
+struct Var {
+    /// Name of the variable, prefixed with the names of its scopes.
+    name: String,
+    /// Identifier code of the variable in the value changes, shared by aliased variables.
+    id: String,
+    kind: VarKind,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads the tokens of a command up to its `$end`.
+fn command<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    tokens
+        .by_ref()
+        .take_while(|&token| token != "$end")
+        .collect()
+}
+
+/// Parses the `$timescale` command, such as `1ps` or `10 ns`, in seconds.
+fn parse_timescale(text: &str) -> io::Result<f64> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let factor = match unit.trim() {
+        "s" => 1.0,
+        "ms" => 1e-3,
+        "us" => 1e-6,
+        "ns" => 1e-9,
+        "ps" => 1e-12,
+        "fs" => 1e-15,
+        _ => return Err(invalid(format!("invalid timescale {}", text))),
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| invalid(format!("invalid timescale {}", text)))?;
+    Ok(value * factor)
+}
+
+/// Writes the bits of a vector value in hexadecimal. Values shorter than the vector are extended
+/// with zeros, or with `x` or `z` when they start with it. Digits with unknown bits are written
+/// `x`, and digits whose bits are all floating `z`.
+fn hex_value(bits: &str, size: usize) -> String {
+    let bits = bits.to_ascii_lowercase();
+    let fill = match bits.as_bytes().first() {
+        Some(&b) if b == b'x' || b == b'z' => b,
+        _ => b'0',
+    };
+    let mut padded = vec![fill; size.saturating_sub(bits.len())];
+    padded.extend_from_slice(bits.as_bytes());
+    let mut padded = [
+        vec![b'0'; padded.len().next_multiple_of(4) - padded.len()],
+        padded,
+    ]
+    .concat();
+    if padded.is_empty() {
+        padded = vec![b'x'; 4];
+    }
+    padded
+        .chunks(4)
+        .map(|digit| {
+            if digit.iter().all(|&b| b == b'0' || b == b'1') {
+                let value = digit.iter().fold(0, |acc, &b| acc * 2 + (b == b'1') as u32);
+                char::from_digit(value, 16).unwrap()
+            } else if digit.iter().all(|&b| b == b'z') {
+                'z'
+            } else {
+                'x'
+            }
+        })
+        .collect()
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Builds the logic level of a single-bit variable from its value changes, given as sample
+/// indices in increasing order with the value. Unknown and floating values are read as low.
+fn bit_lane(changes: &[(usize, &str)]) -> DigitalLane {
+    let mut initial = false;
+    let mut level = false;
+    let mut edges = Vec::new();
+    for &(index, value) in changes {
+        let high = value.ends_with('1');
+        if index == 0 {
+            initial = high;
+        } else if high != level {
+            // Glitches within a time step cancel out.
+            if edges.last() == Some(&index) {
+                edges.pop();
+            } else {
+                edges.push(index);
+            }
+        }
+        level = high;
+    }
+    DigitalLane::from_edges(initial, edges)
+}
+
+/// Builds the values of a vector from its value changes. The value is unknown until the first
+/// change.
+fn bus_lane(changes: &[(usize, &str)], size: usize) -> BusLane {
+    let mut values: Vec<(usize, String)> = vec![(0, hex_value("x", size))];
+    for &(index, bits) in changes {
+        let value = hex_value(bits, size);
+        let last = values.last_mut().unwrap();
+        if last.0 == index {
+            last.1 = value;
+        } else if last.1 != value {
+            values.push((index, value));
+        }
+    }
+    // The last value of a time step may be the one of the previous step.
+    values.dedup_by(|next, previous| next.1 == previous.1);
+    BusLane::new(values)
+}
+
+/// Samples a real variable from its value changes, holding each value until the next change.
+fn real_samples(changes: &[(usize, &str)], samples: usize) -> Vec<f32> {
+    let mut result = vec![0.0; samples];
+    for (k, &(index, value)) in changes.iter().enumerate() {
+        let end = changes.get(k + 1).map_or(samples, |&(e, _)| e);
+        result[index..end].fill(value.parse().unwrap_or(0.0));
+    }
+    result
+}
+
+/// Parses a VCD file. The time steps of the dump are the multiples of the greatest common divisor
+/// of the change times, which gives the sampling rate.
+fn read_vcd(text: &str) -> io::Result<LogicCapture> {
+    let mut tokens = text.split_ascii_whitespace();
+    let mut timescale = None;
+    let mut scopes: Vec<&str> = Vec::new();
+    let mut vars = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            "$timescale" => timescale = Some(parse_timescale(&command(&mut tokens).concat())?),
+            "$scope" => {
+                let scope = command(&mut tokens);
+                scopes.push(scope.get(1).copied().unwrap_or_default());
+            }
+            "$upscope" => {
+                command(&mut tokens);
+                scopes.pop();
+            }
+            "$var" => {
+                let var = command(&mut tokens);
+                let [ty, size, id, reference @ ..] = &var[..] else {
+                    return Err(invalid(format!("invalid variable: {}", var.join(" "))));
+                };
+                let kind = match *ty {
+                    "real" | "realtime" => VarKind::Real,
+                    "string" | "event" => VarKind::Other,
+                    _ => VarKind::Bits(
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size <= MAX_VECTOR_SIZE)
+                            .ok_or_else(|| invalid(format!("invalid size of {}", id)))?,
+                    ),
+                };
+                let name = scopes
+                    .iter()
+                    .copied()
+                    .chain([reference.concat().as_str()])
+                    .collect::<Vec<_>>()
+                    .join(".");
+                vars.push(Var {
+                    name,
+                    id: id.to_string(),
+                    kind,
+                });
+            }
+            "$enddefinitions" => {
+                command(&mut tokens);
+                break;
+            }
+            _ if token.starts_with('$') => {
+                command(&mut tokens);
+            }
+            _ => return Err(invalid(format!("unexpected {} in the header", token))),
+        }
+    }
+
+    // Value changes of each identifier code, with their time.
+    let mut changes: HashMap<&str, Vec<(u64, &str)>> = HashMap::new();
+    let mut times = Vec::new();
+    let mut time = 0;
+    while let Some(token) = tokens.next() {
+        let (first, rest) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+        // Changes before the first time are at time 0.
+        if first != "#" && first != "$" && times.is_empty() {
+            times.push(0);
+        }
+        match first {
+            "#" => {
+                let next = rest
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid time {}", token)))?;
+                if times.last().is_some_and(|&last| next < last) {
+                    return Err(invalid(format!("time {} goes back after #{}", token, time)));
+                }
+                time = next;
+                times.push(time);
+            }
+            "$" if token == "$comment" => {
+                command(&mut tokens);
+            }
+            // The other commands of this section only delimit value changes.
+            "$" => {}
+            "b" | "B" | "r" | "R" | "s" | "S" => {
+                let id = tokens
+                    .next()
+                    .ok_or_else(|| invalid(format!("no identifier after {}", token)))?;
+                changes.entry(id).or_default().push((time, rest));
+            }
+            _ => changes.entry(rest).or_default().push((time, first)),
+        }
+    }
+
+    let start = times.first().copied().unwrap_or(0);
+    let end = times.last().copied().unwrap_or(0);
+    // The times are checked to increase as they are read, so they are all after the start.
+    let since_start = |t: u64| {
+        t.checked_sub(start)
+            .ok_or_else(|| invalid(format!("time #{} before the start #{}", t, start)))
+    };
+    let mut step = 0;
+    for &t in &times {
+        step = gcd(step, since_start(t)?);
+    }
+    let step = step.max(1);
+    let steps = (since_start(end)? / step).saturating_add(1);
+    if steps > MAX_SAMPLES {
+        return Err(invalid(format!(
+            "{} time steps, more than the {} supported",
+            steps, MAX_SAMPLES
+        )));
+    }
+    let samples = steps as usize;
+
+    let mut analog = Vec::new();
+    let mut logic = Vec::new();
+    for var in vars {
+        let changes: Vec<(usize, &str)> = changes
+            .get(var.id.as_str())
+            .map(|changes| {
+                changes
+                    .iter()
+                    .map(|&(t, value)| Ok(((since_start(t)? / step) as usize, value)))
+                    .collect::<io::Result<_>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        match var.kind {
+            VarKind::Bits(1) => logic.push(LogicChannel {
+                name: var.name,
+                lane: LogicLane::Bit(bit_lane(&changes)),
+            }),
+            VarKind::Bits(size) => logic.push(LogicChannel {
+                name: var.name,
+                lane: LogicLane::Bus(bus_lane(&changes, size)),
+            }),
+            VarKind::Real => analog.push((var.name, real_samples(&changes, samples))),
+            VarKind::Other => {}
+        }
+    }
+
+    Ok(LogicCapture {
+        format: "VCD",
+        sampling_rate: timescale.map(|timescale| 1.0 / (timescale * step as f64)),
+        analog,
+        logic,
+        logic_samples: samples,
+    })
+}
+
+/// Reads the variables of the VCD file at `path`.
+pub fn open_vcd(path: &str) -> io::Result<LogicCapture> {
+    let mut data = Vec::new();
+    open_reader(path)?.read_to_end(&mut data)?;
+    read_vcd(&String::from_utf8_lossy(&data))
+}
+
+/// Loads a VCD file and returns its real variables as traces. Dumps without real variables return
+/// their other variables instead, as traces of zeros and ones or of the vector values.
+pub fn load_vcd<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let capture = read_vcd(&String::from_utf8_lossy(&data))
+        .map_err(|e| LoadError::invalid(format!("invalid VCD file: {}", e)))?;
+    tracing::info!(target: "loader", "{}", capture.summary(path));
+    Ok(capture.into_traces())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "$timescale 1ns $end\n$scope module top $end\n$var wire 1 ! clk $end\n\
+        $var wire 4 \" bus [3:0] $end\n$var real 64 # v $end\n$upscope $end\n\
+        $enddefinitions $end\n";
+
+    const CHANGES: &str = "#0\n0!\nb0 \"\nr0.5 #\n#10\n1!\nb1010 \"\n#20\n0!\nr1.5 #\n#30\n";
+
+    #[test]
+    fn variables_are_decoded() {
+        let capture = read_vcd(&format!("{}{}", HEADER, CHANGES)).unwrap();
+        assert_eq!(capture.sampling_rate, Some(1e8));
+        assert_eq!(capture.logic_samples, 4);
+        assert_eq!(capture.logic[0].name, "top.clk");
+        let LogicLane::Bit(lane) = &capture.logic[0].lane else {
+            panic!("clk is not a logic level");
+        };
+        assert_eq!((lane.initial(), lane.edges()), (false, &[1, 2][..]));
+        assert_eq!(capture.logic[1].lane.samples(4), [0.0, 10.0, 10.0, 10.0]);
+        assert_eq!(capture.into_traces(), [[0.5, 0.5, 1.5, 1.5]]);
+
+        // Dumps without real variables give their other variables.
+        let header = HEADER.replace("$var real 64 # v $end", "");
+        let data = format!("{}{}", header, CHANGES);
+        let traces = load_vcd(data.as_bytes(), "a.vcd").unwrap();
+        assert_eq!(traces, [[0.0, 1.0, 0.0, 0.0], [0.0, 10.0, 10.0, 10.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let load = |header: &str, changes: &str| read_vcd(&format!("{}{}", header, changes));
+        for (from, to) in [
+            ("1ns", "1xs"),
+            ("$var wire 1 ! clk $end", "$var wire 1 $end"),
+            ("$var wire 1 ! clk $end", "$var wire x ! clk $end"),
+            ("$var wire 1 ! clk $end", "$var wire 4294967296 ! clk $end"),
+            ("$upscope", "upscope"),
+        ] {
+            assert!(load(&HEADER.replace(from, to), CHANGES).is_err(), "{}", to);
+        }
+        for changes in ["#x\n", "#10\n#5\n", "#0\nb1", "#0\n#1\n#1000000000\n"] {
+            assert!(load(HEADER, changes).is_err(), "{}", changes);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! WAVE audio file parser (`.wav`): PCM and IEEE float samples, with any number of channels.
+//!
+//! Reference: Microsoft "Multimedia Programming Interface and Data Specifications 1.0", and the
+//! `WAVEFORMATEXTENSIBLE` structure for files with more than two channels or 24-bit samples.
+
+use super::{LoadError, files::open_file};
+use std::{
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// Format tags of the `fmt ` chunk.
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+/// The actual format tag is given by the first two bytes of the sub-format GUID.
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Position of the sub-format GUID in the `fmt ` chunk of extensible files.
+const SUB_FORMAT: usize = 24;
+
+/// Encoding of the samples of a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    Uint8,
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> usize {
+        match self {
+            Self::Uint8 => 1,
+            Self::Int16 => 2,
+            Self::Int24 => 3,
+            Self::Int32 | Self::Float32 => 4,
+            Self::Float64 => 8,
+        }
+    }
+
+    /// Decodes a little-endian sample. Integer samples are scaled to the full scale, so all the
+    /// formats give values in [-1, 1].
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::Uint8 => (bytes[0] as f32 - 128.0) / 128.0,
+            Self::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            Self::Int24 => {
+                // The sample is shifted to the top of an i32 to extend its sign.
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.0
+            }
+            Self::Int32 => {
+                (i32::from_le_bytes(bytes.try_into().unwrap()) as f64 / 2147483648.0) as f32
+            }
+            Self::Float32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+            Self::Float64 => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+        }
+    }
+}
+
+/// Location and encoding of the samples of a WAV file, read from its header.
+struct WavLayout {
+    format: SampleFormat,
+    channels: usize,
+    /// Sampling rate in S/s.
+    sampling_rate: u32,
+    /// Position of the `data` chunk content in the file.
+    data_offset: u64,
+    /// Number of samples of each channel.
+    points: usize,
+}
+
+impl WavLayout {
+    /// Size of the samples of all the channels at a given time.
+    fn block_size(&self) -> usize {
+        self.channels * self.format.bytes()
+    }
+
+    /// Splits the interleaved samples of the `data` chunk by channel.
+    fn decode(&self, data: &[u8]) -> Vec<Vec<f32>> {
+        let bytes = self.format.bytes();
+        let mut channels = vec![Vec::with_capacity(self.points); self.channels];
+        for block in data.chunks_exact(self.block_size()) {
+            for (channel, sample) in channels.iter_mut().zip(block.chunks_exact(bytes)) {
+                channel.push(self.format.decode(sample));
+            }
+        }
+        channels
+    }
+
+    /// Decodes the samples of `channel` only.
+    fn decode_channel(&self, data: &[u8], channel: usize) -> Vec<f32> {
+        let bytes = self.format.bytes();
+        data.chunks_exact(self.block_size())
+            .map(|block| {
+                self.format
+                    .decode(&block[channel * bytes..(channel + 1) * bytes])
+            })
+            .collect()
+    }
+
+    fn summary(&self, path: &str) -> String {
+        format!(
+            "{}: WAV, {:?}, {:.3} MS/s, {} channel(s), {} pts/channel",
+            path,
+            self.format,
+            self.sampling_rate as f64 / 1e6,
+            self.channels,
+            self.points
+        )
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads the `fmt ` chunk content.
+fn read_format(chunk: &[u8]) -> io::Result<(SampleFormat, usize, u32)> {
+    if chunk.len() < 16 {
+        return Err(invalid("fmt chunk too small"));
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sampling_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+    let block_align = u16_at(12) as usize;
+    if tag == FORMAT_EXTENSIBLE {
+        if chunk.len() < SUB_FORMAT + 2 {
+            return Err(invalid("extensible fmt chunk too small"));
+        }
+        tag = u16_at(SUB_FORMAT);
+    }
+    if channels == 0 || !block_align.is_multiple_of(channels) {
+        return Err(invalid(format!(
+            "invalid block size {} for {} channels",
+            block_align, channels
+        )));
+    }
+    // Samples with fewer valid bits than their container are decoded with the size of the
+    // container, which holds the valid bits in its most significant bits.
+    let format = match (tag, block_align / channels) {
+        (FORMAT_PCM, 1) => SampleFormat::Uint8,
+        (FORMAT_PCM, 2) => SampleFormat::Int16,
+        (FORMAT_PCM, 3) => SampleFormat::Int24,
+        (FORMAT_PCM, 4) => SampleFormat::Int32,
+        (FORMAT_IEEE_FLOAT, 4) => SampleFormat::Float32,
+        (FORMAT_IEEE_FLOAT, 8) => SampleFormat::Float64,
+        (tag, bytes) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported WAV format {} with {}-byte samples", tag, bytes),
+            ));
+        }
+    };
+    Ok((format, channels, sampling_rate))
+}
+
+/// Parses the chunks of a WAV file up to the `data` chunk.
+fn read_layout<R: Read + Seek>(reader: &mut R) -> io::Result<WavLayout> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(invalid("not a RIFF WAVE file"));
+    }
+    let mut format = None;
+    loop {
+        let mut chunk = [0; 8];
+        reader.read_exact(&mut chunk).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("data chunk not found"),
+            _ => e,
+        })?;
+        let size = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as u64;
+        match &chunk[..4] {
+            b"fmt " => {
+                if size > file_len - reader.stream_position()? {
+                    return Err(invalid("fmt chunk beyond the end of the file"));
+                }
+                let mut content = vec![0; size as usize];
+                reader.read_exact(&mut content)?;
+                format = Some(read_format(&content)?);
+                reader.seek(SeekFrom::Current((size % 2) as i64))?;
+            }
+            b"data" => {
+                let (format, channels, sampling_rate) =
+                    format.ok_or_else(|| invalid("data chunk before fmt chunk"))?;
+                let data_offset = reader.stream_position()?;
+                // Recorders which are interrupted leave the size of the chunk unset: the samples
+                // then extend to the end of the file.
+                let size = size.min(file_len - data_offset) as usize;
+                return Ok(WavLayout {
+                    format,
+                    channels,
+                    sampling_rate,
+                    data_offset,
+                    points: size / (channels * format.bytes()),
+                });
+            }
+            // Chunks are padded to an even size.
+            _ => {
+                reader.seek(SeekFrom::Current((size + size % 2) as i64))?;
+            }
+        }
+    }
+}
+
+/// Loads a WAV file and returns each of its channels as a separate trace.
+///
+/// Integer samples are scaled to the full scale of their format, so all the traces have values
+/// in [-1, 1].
+pub fn load_wav<R: Read>(mut reader: R, path: &str) -> Result<Vec<Vec<f32>>, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let layout = read_layout(&mut Cursor::new(&data))
+        .map_err(|e| LoadError::invalid(format!("invalid WAV file: {}", e)))?;
+    let start = layout.data_offset as usize;
+    let channels = layout.decode(&data[start..start + layout.points * layout.block_size()]);
+    tracing::info!(target: "loader", "{}", layout.summary(path));
+    Ok(channels)
+}
+
+/// A WAV file whose channels are read on demand.
+pub struct WavFile {
+    path: PathBuf,
+    layout: WavLayout,
+}
+
+impl WavFile {
+    /// Opens the file at `path` and reads its header.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let layout = read_layout(&mut open_file(&path)?)?;
+        Ok(Self { path, layout })
+    }
+
+    /// Describes the format, the sampling rate and the channels of the file in one line.
+    pub fn summary(&self) -> String {
+        self.layout.summary(&self.path.to_string_lossy())
+    }
+
+    /// Number of channels in the file.
+    pub fn channels(&self) -> usize {
+        self.layout.channels
+    }
+
+    /// Sampling rate in S/s.
+    pub fn sampling_rate(&self) -> f64 {
+        self.layout.sampling_rate as f64
+    }
+
+    /// Reads the samples of `channel`.
+    pub fn read(&self, channel: usize) -> io::Result<Vec<f32>> {
+        if channel >= self.channels() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no channel {}", channel),
+            ));
+        }
+        let mut file = open_file(&self.path)?;
+        file.seek(SeekFrom::Start(self.layout.data_offset))?;
+        let mut data = vec![0; self.layout.points * self.layout.block_size()];
+        file.read_exact(&mut data)?;
+        Ok(self.layout.decode_channel(&data, channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a WAV file holding `frames` of 16-bit samples, one per channel.
+    fn wav_file(frames: &[[i16; 2]]) -> Vec<u8> {
+        let data: Vec<u8> = frames
+            .iter()
+            .flatten()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let mut file = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        file.extend(16u32.to_le_bytes());
+        file.extend(FORMAT_PCM.to_le_bytes());
+        file.extend(2u16.to_le_bytes());
+        file.extend(48000u32.to_le_bytes());
+        file.extend((48000u32 * 4).to_le_bytes());
+        file.extend(4u16.to_le_bytes());
+        file.extend(16u16.to_le_bytes());
+        // Unknown chunks are skipped with their padding byte.
+        file.extend(b"LIST\x01\0\0\0x\0");
+        file.extend(b"data");
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn channels_are_decoded() {
+        let file = wav_file(&[[16384, -32768], [0, 8192]]);
+        let layout = read_layout(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(layout.sampling_rate, 48000);
+        let channels = load_wav(file.as_slice(), "a.wav").unwrap();
+        assert_eq!(channels, [[0.5, 0.0], [-1.0, 0.25]]);
+        // Samples past a truncated data chunk are dropped.
+        let channels = load_wav(&file[..file.len() - 3], "a.wav").unwrap();
+        assert_eq!(channels, [[0.5], [-1.0]]);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let file = wav_file(&[[16384, -32768], [0, 8192]]);
+        // Truncated header, or missing data chunk.
+        assert!(load_wav(&file[..10], "a.wav").is_err());
+        assert!(load_wav(&file[..30], "a.wav").is_err());
+        assert!(load_wav(&file[..44], "a.wav").is_err());
+        // Not a WAVE file.
+        let mut bad = file.clone();
+        bad[8] = b'X';
+        assert!(load_wav(bad.as_slice(), "a.wav").is_err());
+        // fmt chunk too small or beyond the end of the file.
+        let mut bad = file.clone();
+        bad[16] = 8;
+        assert!(load_wav(bad.as_slice(), "a.wav").is_err());
+        bad[19] = 0xff;
+        assert!(load_wav(bad.as_slice(), "a.wav").is_err());
+        // No channel, or unsupported sample format.
+        let mut bad = file.clone();
+        bad[22] = 0;
+        assert!(load_wav(bad.as_slice(), "a.wav").is_err());
+        let mut bad = file;
+        bad[20] = 2;
+        assert!(load_wav(bad.as_slice(), "a.wav").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! Diagnostic messages, printed on the standard error.
+//!
+//! Messages are emitted with [`tracing`]. The loaders, the renderers and the tile rendering use
+//! the `loader`, `renderer` and `tiling` targets, so their messages can be selected with the
+//! `RUST_LOG` environment variable, `RUST_LOG=tiling=trace` for instance.
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{EnvFilter, fmt::time::Uptime};
+
+/// Targets of the messages of TurboPlot, besides the modules of the crate.
+const TARGETS: [&str; 4] = ["turboplot", "loader", "renderer", "tiling"];
+
+/// Installs the global subscriber. Messages of TurboPlot are shown from the info level, one
+/// level more for each `verbose` and one level less for each `quiet`. Messages of the other
+/// crates are shown from the warning level at most. `RUST_LOG` replaces these filters when set.
+pub fn init(verbose: u8, quiet: u8) {
+    let level = match 2 + verbose as i32 - quiet as i32 {
+        ..=-1 => LevelFilter::OFF,
+        0 => LevelFilter::ERROR,
+        1 => LevelFilter::WARN,
+        2 => LevelFilter::INFO,
+        3 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        let mut directives = level.min(LevelFilter::WARN).to_string();
+        for target in TARGETS {
+            directives.push_str(&format!(",{}={}", target, level));
+        }
+        EnvFilter::new(directives)
+    });
+    // Fails if a subscriber is already installed, by an application using the library.
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_timer(Uptime::default())
+        .try_init();
+}
@@ -1,197 +1,72 @@
-use crate::{
-    filtering::Filtering,
-    input::{Args, FileManager, FileManagerResult},
-    loaders::{TraceFormat, guess_format, load_csv, load_npy, load_tek_wfm},
-    multi_viewer::MultiViewer,
-};
-use biquad::ToHertz;
-use clap::Parser;
+#[cfg(not(target_arch = "wasm32"))]
 use eframe::egui;
+#[cfg(not(target_arch = "wasm32"))]
 use egui::Vec2;
-use std::{fs::File, io::BufReader, sync::Arc};
-
-mod camera;
-mod filtering;
-mod input;
-mod loaders;
-mod multi_viewer;
-mod renderer;
-mod sync_features;
-mod tiling;
-mod util;
-mod viewer;
-
-/// Application state: selecting files, viewing traces, or closing.
-enum AppState {
-    /// File selection state with the file manager.
-    Selection(Box<FileManager>),
-    /// Viewing state with the multi-viewer.
-    Viewing(MultiViewer),
-    /// Application is closing.
-    Closing,
-}
-
-/// Main application wrapper that handles file selection and viewing states.
-struct TurboPlotApp {
-    state: AppState,
-}
-
-impl TurboPlotApp {
-    fn new(ctx: &egui::Context, args: Args) -> Self {
-        let state = if args.paths.is_empty() {
-            // No files provided, show file manager
-            AppState::Selection(Box::new(FileManager::new(args)))
-        } else {
-            // Files were provided via command line, load and go to viewing
-            match Self::load_and_create_viewer(ctx, &args) {
-                Some(viewer) => AppState::Viewing(viewer),
-                None => {
-                    // Failed to load, show file manager
-                    AppState::Selection(Box::new(FileManager::new(args)))
-                }
-            }
-        };
-
-        Self { state }
-    }
-
-    /// Loads traces from args and creates a MultiViewer if successful.
-    fn load_and_create_viewer(ctx: &egui::Context, args: &Args) -> Option<MultiViewer> {
-        let (labels, traces) = Self::load_traces(args);
-        if traces.is_empty() {
-            return None;
-        }
-
-        println!(
-            "Using {} GPU threads and {} CPU threads.",
-            args.gpu,
-            args.cpu_threads()
-        );
-
-        Some(MultiViewer::new(
-            ctx,
-            labels,
-            Arc::new(traces),
-            args.sampling_rate,
-            args.gpu,
-            args.cpu_threads(),
-        ))
-    }
-
-    /// Loads traces from the given args. Returns (labels, traces) where labels
-    /// may differ from the input paths when a single file produces multiple
-    /// traces we call frames (e.g. multi-frame WFM or 2D numpy files).
-    fn load_traces(args: &Args) -> (Vec<String>, Vec<Arc<Vec<f32>>>) {
-        let mut labels = Vec::new();
-        let mut traces = Vec::new();
-        for path in &args.paths {
-            let Some(format) = args.format.or_else(|| guess_format(path)) else {
-                println!("Unrecognized file extension: {}", path);
-                continue;
-            };
-
-            let file = match File::open(path) {
-                Ok(f) => f,
-                Err(e) => {
-                    println!("Failed to open file {}: {}", path, e);
-                    continue;
-                }
-            };
-            let buf_reader = BufReader::new(file);
-
-            // All loaders return Vec<Vec<f32>> (one or more traces per file)
-            let mut frames = match format {
-                TraceFormat::TekWfm => load_tek_wfm(buf_reader, path),
-                TraceFormat::Numpy => load_npy(buf_reader, path, args.npy_layout),
-                TraceFormat::Csv => vec![load_csv(buf_reader, args.skip_lines, args.column)],
-            };
-
-            let n = frames.len();
-            let selection = args.frame_selection();
-
-            for (i, mut frame) in frames.drain(..).enumerate() {
-                if let Some(ref sel) = selection
-                    && !sel.contains(&i)
-                {
-                    continue;
-                }
-                if let Some(filter) = args.filter {
-                    frame.apply_filter(filter, args.sampling_rate.mhz(), args.cutoff_freq.khz());
-                }
-                if n > 1 {
-                    labels.push(format!("{} [frame {}]", path, i));
-                } else {
-                    labels.push(path.clone());
-                }
-                traces.push(Arc::new(frame));
-            }
-        }
-
-        // Safety net: even with --frames, clamp the total number of viewers we
-        // are about to spawn to something sane so the UI stays responsive.
-        if traces.len() > Self::MAX_VIEWERS {
-            eprintln!(
-                "Warning: {} traces selected, keeping only the first {} to keep the UI responsive.",
-                traces.len(),
-                Self::MAX_VIEWERS
-            );
-            labels.truncate(Self::MAX_VIEWERS);
-            traces.truncate(Self::MAX_VIEWERS);
-        }
-
-        (labels, traces)
-    }
+#[cfg(not(target_arch = "wasm32"))]
+use turboplot::{
+    app::TurboPlotApp,
+    automation::Automation,
+    batch,
+    colorizer::TileColorizer,
+    input::{Cli, Mode},
+    thumbnails, tile_server,
+};
 
-    /// Hard cap on the total number of concurrent viewers (after `--frames`).
-    /// Each viewer gets its own viewport, toolbar and rendering queue; beyond
-    /// this the UI becomes unusable.
-    const MAX_VIEWERS: usize = 64;
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    turboplot::web::start();
 }
 
-impl eframe::App for TurboPlotApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        match &mut self.state {
-            AppState::Selection(file_manager) => match file_manager.update(ctx) {
-                FileManagerResult::Selected(args) => {
-                    // Load traces and transition to viewing state
-                    if let Some(viewer) = Self::load_and_create_viewer(ctx, &args) {
-                        self.state = AppState::Viewing(viewer);
-                    }
-                }
-                FileManagerResult::Cancelled => {
-                    // Transition to closing state
-                    self.state = AppState::Closing;
-                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let mode = Cli::parse_with_config().unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    let mut args = match mode {
+        Mode::View(args) => *args,
+        Mode::Render(render) => std::process::exit(batch::render(&render)),
+        Mode::Convert(convert) => std::process::exit(batch::convert(&convert)),
+        Mode::Info(info) => std::process::exit(batch::info(&info)),
+        Mode::Bench(bench) => std::process::exit(batch::bench(&bench)),
+        Mode::Thumbnails(thumbnails) => std::process::exit(thumbnails::run(&thumbnails)),
+        Mode::Serve(serve) => std::process::exit(tile_server::run(&serve)),
+    };
+    let commands = match &args.script {
+        Some(path) => match Automation::from_file(path) {
+            Ok(automation) => {
+                args.paths.extend(automation.paths);
+                if let Some(sampling_rate) = automation.sampling_rate {
+                    args.trace.sampling_rate = sampling_rate;
                 }
-                FileManagerResult::Pending => {}
-            },
-            AppState::Viewing(viewer) => {
-                egui::CentralPanel::default()
-                    .frame(egui::Frame::default().outer_margin(0.0))
-                    .show(ctx, |ui| {
-                        viewer.update(ctx, ui);
-                    });
+                automation.commands
             }
-            AppState::Closing => {
-                // Do nothing, app is closing
+            Err(e) => {
+                eprintln!("Script error: {}", e);
+                std::process::exit(1);
             }
-        }
-    }
-}
-
-fn main() {
-    let args = Args::parse();
+        },
+        None => Vec::new(),
+    };
 
+    // The size and position of the window are restored from the previous launch, if any.
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default(),
-        window_builder: Some(Box::new(|w| w.with_inner_size(Vec2::new(1280.0, 512.0)))),
+        viewport: egui::ViewportBuilder::default().with_inner_size(Vec2::new(1280.0, 512.0)),
         ..Default::default()
     };
 
     eframe::run_native(
         "TurboPlot",
         options,
-        Box::new(move |cc| Ok(Box::new(TurboPlotApp::new(&cc.egui_ctx, args)))),
+        Box::new(move |cc| {
+            TileColorizer::install(&cc.egui_ctx, cc.wgpu_render_state.as_ref());
+            Ok(Box::new(TurboPlotApp::new(
+                &cc.egui_ctx,
+                args,
+                commands,
+                cc.storage,
+            )))
+        }),
     )
     .unwrap();
 }
@@ -1,155 +1,1647 @@
 use crate::{
-    renderer::{CpuRenderer, GpuRenderer, Renderer},
+    alignment_dialog::{AlignmentDialog, AlignmentDialogResult, AlignmentSource},
+    automation::Command,
+    camera::Camera,
+    config::KeyBindings,
+    difference::{
+        DifferenceDialog, DifferenceDialogResult, DifferenceLink, SourcesState, difference,
+    },
+    digital::LogicChannel,
+    dtw::{DtwDialog, DtwDialogResult},
+    error_dialog::{ErrorDialog, ErrorDialogResult},
+    events::Event,
+    expression_dialog::{ExpressionDialog, ExpressionDialogResult, ExpressionSource},
+    format::NumberFormat,
+    group_average::{GroupAverageDialog, GroupAverageDialogResult},
+    input::FileSettings,
+    layout::Layout,
+    live::Live,
+    loaders::{FrameFile, TrsFile},
+    processing::{NewTrace, ProcessingJob, ProcessingStep},
+    remote::{RemotePeer, RemoteServer},
+    renderer::CpuRenderer,
+    settings::Settings,
     sync_features::SyncFeatures,
-    tiling::{Tiling, TilingRenderer},
-    viewer::Viewer,
+    tiling::{ColorScale, SharedTraces, Tiling, TilingRenderer},
+    tiling_policy::TilingPolicy,
+    trace::Trace,
+    transforms::mean_std,
+    util::Fixed,
+    viewer::{Viewer, ViewerUpdateStatus},
+    watch::{FileWatch, FolderWatch, ReloadRequest, Reloader},
 };
-use egui::{Rect, pos2};
+use egui::{Align2, Color32, CursorIcon, FontId, Rect, Sense, Stroke, epaint::Hsva, pos2, vec2};
 use std::{
-    sync::{Arc, Condvar, Mutex},
-    thread,
+    collections::{HashSet, VecDeque},
+    path::Path,
+    sync::{Arc, Condvar, Mutex, RwLock},
+    time::Duration,
 };
 
+/// Fractional lags smaller than this, in samples, are not compensated by interpolation.
+const MIN_FRACTIONAL_LAG: f64 = 1e-3;
+
+/// Height of a viewer with the default relative height, in points, below which the viewers of
+/// the main window do not share its height anymore but are scrolled.
+const MIN_VIEWER_HEIGHT: f32 = 150.0;
+
+/// Height of the thinnest viewer, in points, leaving room for the toolbar.
+const STRIP_HEIGHT: f32 = 56.0;
+
+/// Height of the splitters between the viewers, in points.
+const SPLITTER_HEIGHT: f32 = 6.0;
+
+/// Width of the scroll bar of the viewers, in points.
+const SCROLLBAR_WIDTH: f32 = 10.0;
+
+/// Shortcut of the "Open…" menu entry.
+const OPEN_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::O);
+
+/// Number of tiles rendered at each update in the browser. More would slow down the interface.
+#[cfg(target_arch = "wasm32")]
+const TILES_PER_UPDATE: usize = 4;
+
+/// Spawns the threads rendering the tiles. The GPU threads render with the CPU if no GPU
+/// supports compute shaders, or if their GPU renderer cannot be created.
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_renderers(
+    ctx: &egui::Context,
+    shared_tiling: &Arc<(Mutex<Tiling>, Condvar)>,
+    traces: &SharedTraces,
+    gpu_threads: usize,
+    cpu_threads: usize,
+) {
+    use crate::{
+        renderer::{GpuRenderer, Renderer},
+        util::panic_message,
+    };
+    use std::{panic, thread};
+
+    let gpu_available = gpu_threads > 0 && GpuRenderer::is_available();
+    if gpu_threads > 0 && !gpu_available {
+        tracing::warn!(
+            target: "renderer",
+            "No GPU supports compute shaders, the tiles are rendered by the CPU."
+        );
+    }
+    for i in 0..gpu_threads + cpu_threads {
+        let shared_tiling = shared_tiling.clone();
+        let traces = traces.clone();
+        let ctx = ctx.clone();
+        let gpu = gpu_available && i < gpu_threads;
+        thread::spawn(move || {
+            let renderer: Box<dyn Renderer> =
+                match gpu.then(|| panic::catch_unwind(GpuRenderer::new)) {
+                    Some(Ok(renderer)) => Box::new(renderer),
+                    Some(Err(payload)) => {
+                        shared_tiling.0.lock().unwrap().report_failure(
+                            None,
+                            format!(
+                                "The GPU renderer could not be created, the tiles are rendered by \
+                             the CPU instead: {}",
+                                panic_message(&*payload)
+                            ),
+                        );
+                        Box::new(CpuRenderer::new())
+                    }
+                    None => Box::new(CpuRenderer::new()),
+                };
+            TilingRenderer::new(shared_tiling, traces, renderer, &ctx).render_loop();
+        });
+    }
+}
+
+/// Calculation of the mean and standard deviation of the frames loaded from a file.
+struct FrameStatistics {
+    job: ProcessingJob<(Vec<f32>, Vec<f32>)>,
+    /// Path of the file.
+    path: String,
+    /// Number of frames.
+    count: usize,
+    /// Sampling rate of the frames, in MS/s.
+    sampling_rate: f32,
+}
+
 /// Split window space to display multiple traces using multiple [`Viewer`]. When enabled,
 /// synchronizes the camera of the different viewers.
 pub struct MultiViewer {
     /// Displayed viewers, first at window top, last at bottom.
     viewers: Vec<Viewer>,
+    /// Arrangement of the viewers in the main window.
+    layout: Layout,
+    /// Layout of the previous update, to detect the changes.
+    previous_layout: Layout,
+    /// Vertical scrolling of the viewers of the main window, in points, when they do not fit in
+    /// its height.
+    scroll: f32,
     /// Selects which camera features should be synchronized.
     sync: SyncFeatures,
+    /// Cameras of the viewers at the beginning of the update, for applying the changes done
+    /// since to the other viewers in the relative synchronization mode.
+    previous_cameras: Vec<Camera>,
+    /// Rendering tiles shared between the viewers and the renderers.
+    shared_tiling: Arc<(Mutex<Tiling>, Condvar)>,
+    /// Traces of all viewers, shared with the renderers.
+    traces: SharedTraces,
+    /// Dialog for creating a new trace from an expression, when opened.
+    expression_dialog: Option<ExpressionDialog>,
+    /// Dialog for aligning a trace to another one, or all the traces to a pattern, when opened.
+    alignment_dialog: Option<AlignmentDialog>,
+    /// Dialog for creating the difference between two traces, when opened.
+    difference_dialog: Option<DifferenceDialog>,
+    /// Dialog for warping a trace onto another one, when opened.
+    dtw_dialog: Option<DtwDialog>,
+    /// Dialog for comparing the averages of two groups of traces, when opened.
+    group_average_dialog: Option<GroupAverageDialog>,
+    /// Viewers displaying the difference between two other viewers, kept up to date.
+    differences: Vec<DifferenceLink>,
+    /// Events marked in all the viewers.
+    events: Arc<Vec<Event>>,
+    /// Running calculation of the statistics of the frames of a file.
+    frame_statistics: Option<FrameStatistics>,
+    /// Automation commands remaining to execute, in order.
+    commands: VecDeque<Command>,
+    /// Index of the viewer targeted by the automation commands.
+    command_target: usize,
+    /// First error of the automation commands since the last reply to a remote client.
+    command_error: Option<String>,
+    /// Server receiving automation commands from remote clients.
+    remote: Option<RemoteServer>,
+    /// Another instance following the camera changes done by the user.
+    peer: Option<RemotePeer>,
+    /// Directory whose new trace files are displayed as they appear.
+    watch: Option<FolderWatch>,
+    /// Loads the files of the viewers again, replacing their traces.
+    reloader: Reloader,
+    /// Opened files polled for changes, loaded again when they change on disk.
+    file_watch: Option<FileWatch>,
+    /// Color scale of the viewers created later.
+    color_scale: ColorScale,
+    /// Keyboard shortcuts of the viewers.
+    keys: KeyBindings,
+    /// Formatting of the numbers displayed by the viewers.
+    format: NumberFormat,
+    /// Chooses the width of the tiles of the viewers.
+    tiling_policy: TilingPolicy,
+    /// Failures of the loaders and renderers, reported to the user.
+    errors: ErrorDialog,
+    /// Set when the user asks for opening other files from the menu bar.
+    open_request: bool,
+    /// Number of files given on the command line which are not opened yet.
+    pending_files: usize,
+    /// Set when the user asks for opening the next pending files from the menu bar.
+    pending_request: bool,
+    /// Renderer of the tiles, in the browser where there are no rendering threads.
+    #[cfg(target_arch = "wasm32")]
+    renderer: TilingRenderer,
 }
 
 impl MultiViewer {
+    /// Hard cap on the total number of concurrent viewers (after `--frames`).
+    /// Each viewer gets its own viewport, toolbar and rendering queue; beyond
+    /// this the UI becomes unusable.
+    pub const MAX_VIEWERS: usize = 64;
+
+    /// Creates a viewer for each trace, with the sampling rate at the same index of
+    /// `sampling_rates`. The load-time processing `steps` are applied in background, and their
+    /// result replaces the loaded traces.
     pub fn new(
         ctx: &egui::Context,
         labels: Vec<String>,
-        traces: Arc<Vec<Arc<Vec<f32>>>>,
-        sampling_rate: f32,
+        traces: Vec<Arc<Trace>>,
+        sampling_rates: Vec<f32>,
+        steps: Vec<ProcessingStep>,
         gpu_threads: usize,
         cpu_threads: usize,
     ) -> Self {
         let shared_tiling = Arc::new((Mutex::new(Tiling::new()), Condvar::new()));
+        let traces: SharedTraces = Arc::new(RwLock::new(traces));
 
         let viewers = labels
             .iter()
-            .zip(traces.iter())
             .enumerate()
-            .map(|(i, (label, trace))| {
+            .map(|(i, label)| {
                 Viewer::new(
                     i as u32,
                     ctx,
                     shared_tiling.clone(),
+                    traces.clone(),
                     label.clone(),
-                    trace.clone(),
-                    sampling_rate,
+                    sampling_rates[i],
+                    steps.clone(),
                 )
             })
             .collect();
 
-        // Spawn GPU rendering threads
-        for _ in 0..gpu_threads {
-            let shared_tiling_clone = shared_tiling.clone();
-            let traces_clone = traces.clone();
-            thread::spawn(move || {
-                let renderer: Box<dyn Renderer> = Box::new(GpuRenderer::new());
-                TilingRenderer::new(shared_tiling_clone, traces_clone, renderer).render_loop();
+        #[cfg(not(target_arch = "wasm32"))]
+        spawn_renderers(ctx, &shared_tiling, &traces, gpu_threads, cpu_threads);
+        // Threads cannot be spawned in the browser, the tiles are rendered during the updates.
+        #[cfg(target_arch = "wasm32")]
+        let renderer = {
+            let _ = (gpu_threads, cpu_threads);
+            let renderer = Box::new(CpuRenderer::new());
+            TilingRenderer::new(shared_tiling.clone(), traces.clone(), renderer, ctx)
+        };
+
+        Self {
+            viewers,
+            layout: Layout::default(),
+            previous_layout: Layout::default(),
+            scroll: 0.0,
+            sync: SyncFeatures::new(),
+            previous_cameras: Vec::new(),
+            shared_tiling,
+            traces,
+            expression_dialog: None,
+            alignment_dialog: None,
+            difference_dialog: None,
+            dtw_dialog: None,
+            group_average_dialog: None,
+            differences: Vec::new(),
+            events: Arc::new(Vec::new()),
+            frame_statistics: None,
+            commands: VecDeque::new(),
+            command_target: 0,
+            command_error: None,
+            remote: None,
+            peer: None,
+            watch: None,
+            reloader: Reloader::default(),
+            file_watch: None,
+            color_scale: ColorScale::default(),
+            keys: KeyBindings::default(),
+            format: NumberFormat::default(),
+            tiling_policy: TilingPolicy::default(),
+            errors: ErrorDialog::new(egui::Id::new("error_dialog")),
+            open_request: false,
+            pending_files: 0,
+            pending_request: false,
+            #[cfg(target_arch = "wasm32")]
+            renderer,
+        }
+    }
+
+    /// Color scale of the first viewer.
+    pub fn color_scale(&self) -> ColorScale {
+        self.viewers[0].color_scale()
+    }
+
+    /// Number of viewers, in the main window or in their own windows.
+    pub fn viewer_count(&self) -> usize {
+        self.viewers.len()
+    }
+
+    /// Gives the viewers without bookmarks yet the camera bookmarks saved for their traces in
+    /// `settings`.
+    pub fn restore_bookmarks(&mut self, settings: &Settings) {
+        for viewer in &mut self.viewers {
+            if viewer.bookmarks().iter().any(Option::is_some) {
+                continue;
+            }
+            if let Some(bookmarks) = viewer
+                .bookmark_key()
+                .and_then(|key| settings.bookmarks(&key))
+            {
+                viewer.set_bookmarks(*bookmarks);
+            }
+        }
+    }
+
+    /// Saves the camera bookmarks of the viewers in `settings`, for the next launches.
+    pub fn save_bookmarks(&self, settings: &mut Settings) {
+        for viewer in &self.viewers {
+            if let Some(key) = viewer.bookmark_key() {
+                settings.set_bookmarks(key, *viewer.bookmarks());
+            }
+        }
+    }
+
+    /// Returns true once if the user asked for opening other files.
+    pub fn take_open_request(&mut self) -> bool {
+        std::mem::take(&mut self.open_request)
+    }
+
+    /// Sets the number of files given on the command line which are not opened yet, opened from
+    /// the menu bar.
+    pub fn set_pending_files(&mut self, count: usize) {
+        self.pending_files = count;
+    }
+
+    /// Returns true once if the user asked for opening the next pending files.
+    pub fn take_pending_request(&mut self) -> bool {
+        std::mem::take(&mut self.pending_request)
+    }
+
+    /// Shows the menu bar of the main window.
+    pub fn menu_bar(&mut self, ui: &mut egui::Ui) {
+        if self.viewers.len() < Self::MAX_VIEWERS
+            && ui.input_mut(|i| i.consume_shortcut(&OPEN_SHORTCUT))
+        {
+            self.open_request = true;
+        }
+        egui::MenuBar::new().ui(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                let shortcut = ui.ctx().format_shortcut(&OPEN_SHORTCUT);
+                if ui
+                    .add_enabled(
+                        self.viewers.len() < Self::MAX_VIEWERS,
+                        egui::Button::new("Open…").shortcut_text(shortcut),
+                    )
+                    .on_hover_text("Open other files, displayed below the current traces")
+                    .on_disabled_hover_text("No more traces can be displayed")
+                    .clicked()
+                {
+                    self.open_request = true;
+                    ui.close();
+                }
+                if self.pending_files > 0
+                    && ui
+                        .button(format!("Open next files ({} left)", self.pending_files))
+                        .on_hover_text(
+                            "Open the next files given on the command line, below the current \
+                             traces",
+                        )
+                        .clicked()
+                {
+                    self.pending_request = true;
+                    ui.close();
+                }
+                // In the browser, the files are kept in memory and do not change.
+                if cfg!(not(target_arch = "wasm32")) {
+                    let mut watching = self.file_watch.is_some();
+                    if ui
+                        .checkbox(&mut watching, "Reload changed files")
+                        .on_hover_text(
+                            "Load the opened files again when they change on disk, keeping the \
+                             camera and processing of their traces",
+                        )
+                        .changed()
+                    {
+                        self.set_file_watch(ui.ctx(), watching);
+                    }
+                }
             });
+        });
+    }
+
+    /// Sampling rate of the first viewer, in MS/s.
+    pub fn sampling_rate(&self) -> f32 {
+        self.viewers[0].sampling_rate()
+    }
+
+    /// Sets the color scale of all the viewers, and of the viewers created later.
+    pub fn set_color_scale(&mut self, color_scale: ColorScale) {
+        self.color_scale = color_scale;
+        for viewer in &mut self.viewers {
+            viewer.set_color_scale(color_scale);
+        }
+    }
+
+    /// Sets the keyboard shortcuts of the viewers.
+    pub fn set_keys(&mut self, keys: KeyBindings) {
+        self.keys = keys;
+        for viewer in &mut self.viewers {
+            viewer.set_keys(keys);
+        }
+    }
+
+    /// Sets the formatting of the numbers displayed by the viewers.
+    pub fn set_format(&mut self, format: NumberFormat) {
+        self.format = format;
+        for viewer in &mut self.viewers {
+            viewer.set_format(format);
+        }
+    }
+
+    /// Sets the maximum number of rendered tiles kept in cache after leaving the view.
+    pub fn set_tile_cache(&mut self, size: usize) {
+        self.shared_tiling.0.lock().unwrap().set_cache_size(size);
+    }
+
+    /// Sets how the width of the tiles of the viewers is chosen.
+    pub fn set_tiling_policy(&mut self, policy: TilingPolicy) {
+        self.tiling_policy = policy;
+        for viewer in &mut self.viewers {
+            viewer.set_tiling_policy(policy);
+        }
+    }
+
+    /// Sets the file the trace of viewer `index` has been loaded from, and its frame index in
+    /// multi-trace files.
+    pub fn set_source(&mut self, index: usize, path: String, frame: Option<usize>) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_source(path, frame);
+        }
+    }
+
+    /// Allows loading the trace of viewer `index` again from its file, with the load `settings`
+    /// of the file.
+    pub fn set_reload(&mut self, index: usize, settings: FileSettings) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_reload(settings);
+        }
+    }
+
+    /// Displays the last traces received from `live` in viewer `index`, as they arrive.
+    pub fn set_live(&mut self, index: usize, live: Live) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_live(live);
+        }
+    }
+
+    /// Marks the trace of viewer `index` as a frame of the multi-trace file at `path`, so the
+    /// statistics of the frames of the file can be displayed.
+    pub fn set_frame_group(&mut self, index: usize, path: String) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_frame_group(path);
+        }
+    }
+
+    /// Marks `events` in all the viewers, replacing the previous ones.
+    pub fn set_events(&mut self, events: Vec<Event>) {
+        self.events = Arc::new(events);
+        for viewer in &mut self.viewers {
+            viewer.set_events(self.events.clone());
+        }
+    }
+
+    /// Paints the array loaded from `path` over the traces of all the viewers.
+    pub fn add_overlay(&mut self, path: &str, samples: Arc<Vec<f32>>) {
+        let label = Path::new(path)
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string());
+        for viewer in &mut self.viewers {
+            viewer.add_overlay(label.clone(), samples.clone());
+        }
+    }
+
+    /// Marks the trigger recorded in the file of viewer `index` at sample `sample` of its trace.
+    pub fn set_trigger(&mut self, index: usize, sample: Option<f64>) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_trigger(sample);
         }
+    }
 
-        // Spawn CPU rendering threads
-        for _ in 0..cpu_threads {
-            let shared_tiling_clone = shared_tiling.clone();
-            let traces_clone = traces.clone();
-            thread::spawn(move || {
-                let renderer: Box<dyn Renderer> = Box::new(CpuRenderer::new());
-                TilingRenderer::new(shared_tiling_clone, traces_clone, renderer).render_loop();
+    /// Sets the digital channels captured with the trace of viewer `index`.
+    pub fn set_logic_channels(&mut self, index: usize, channels: Vec<LogicChannel>) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_logic_channels(channels);
+        }
+    }
+
+    /// Allows inspecting the data bytes of the trace of viewer `index`, loaded as frame `frame`
+    /// of the TRS `file`.
+    pub fn set_trace_data(&mut self, index: usize, file: Arc<TrsFile>, frame: usize) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_trace_data(file, frame);
+        }
+    }
+
+    /// Allows navigating between the frames of `file` loaded from `path` in viewer `index`, its
+    /// trace being frame `frame`.
+    pub fn set_frame_browser(
+        &mut self,
+        index: usize,
+        path: String,
+        file: Arc<dyn FrameFile>,
+        frame: usize,
+    ) {
+        if let Some(viewer) = self.viewers.get_mut(index) {
+            viewer.set_frame_browser(path, file, frame);
+        }
+    }
+
+    /// Reports a failure which is not related to a displayed trace, such as a file which could
+    /// not be loaded.
+    pub fn report_failure(&mut self, message: String) {
+        self.errors.push(message, None);
+    }
+
+    /// Queues automation commands, executed once the viewers are ready.
+    pub fn run_commands(&mut self, commands: Vec<Command>) {
+        self.commands.extend(commands);
+    }
+
+    /// Executes the automation commands received by `server`.
+    pub fn set_remote(&mut self, server: RemoteServer) {
+        self.remote = Some(server);
+    }
+
+    /// Sends the camera changes done by the user to `peer`.
+    pub fn set_peer(&mut self, peer: RemotePeer) {
+        self.peer = Some(peer);
+    }
+
+    /// Loads the opened files again when they change on disk, if `enabled`.
+    pub fn set_file_watch(&mut self, ctx: &egui::Context, enabled: bool) {
+        if enabled != self.file_watch.is_some() {
+            self.file_watch = enabled.then(|| FileWatch::start(ctx));
+        }
+        if enabled {
+            self.viewers.iter_mut().for_each(Viewer::unmap);
+        }
+    }
+
+    /// Displays the new trace files of the directory watched by `watch`.
+    pub fn set_watch(&mut self, watch: FolderWatch) {
+        self.watch = Some(watch);
+    }
+
+    /// Records the error of an automation command, reported to the next remote client.
+    fn command_failed(&mut self, error: String) {
+        tracing::warn!("Automation: {}", error);
+        self.command_error.get_or_insert(error);
+    }
+
+    /// Executes the queued automation commands. A command is executed only once all viewers have
+    /// finished processing their traces, so it applies to the result of the previous commands.
+    fn execute_commands(&mut self, ctx: &egui::Context, viewports: &[Rect]) {
+        while !self.commands.is_empty() {
+            // Viewers added during this update have no viewport yet.
+            if self.viewers.iter().any(|v| v.is_busy()) || viewports.len() < self.viewers.len() {
+                ctx.request_repaint_after(Duration::from_millis(100));
+                return;
+            }
+            match self.commands.pop_front().unwrap() {
+                Command::Select(index) => {
+                    if index < self.viewers.len() {
+                        self.command_target = index;
+                    } else {
+                        self.command_failed(format!("no viewer at index {}", index));
+                    }
+                }
+                Command::Load {
+                    label,
+                    trace,
+                    sampling_rate,
+                } => {
+                    let sampling_rate =
+                        sampling_rate.unwrap_or_else(|| self.viewers[0].sampling_rate());
+                    self.add_viewer(
+                        ctx,
+                        NewTrace {
+                            label,
+                            trace,
+                            sampling_rate,
+                        },
+                    );
+                    self.command_target = self.viewers.len() - 1;
+                }
+                Command::AddMarker { time, label } => {
+                    let sampling_rate = self.viewers[self.command_target].sampling_rate();
+                    let mut events = (*self.events).clone();
+                    events.push(Event {
+                        sample: time * sampling_rate as f64,
+                        label,
+                    });
+                    events.sort_by(|a, b| a.sample.total_cmp(&b.sample));
+                    self.set_events(events);
+                }
+                Command::Reply(reply) => {
+                    let result = match self.command_error.take() {
+                        Some(error) => Err(error),
+                        None => Ok(self.command_target),
+                    };
+                    // The client may have disconnected.
+                    let _ = reply.send(result);
+                }
+                Command::Quit => {
+                    self.commands.clear();
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                Command::SyncCamera { start, end } => {
+                    let i = self.command_target;
+                    let show = Command::Show { start, end };
+                    if let Err(e) = self.viewers[i].execute(ctx, &show, viewports[i]) {
+                        self.command_failed(format!("{:?} failed: {}", show, e));
+                    }
+                    self.sync(i);
+                }
+                command => {
+                    let i = self.command_target;
+                    if let Err(e) = self.viewers[i].execute(ctx, &command, viewports[i]) {
+                        self.command_failed(format!("{:?} failed: {}", command, e));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds a viewer displaying a new trace, at the bottom of the window.
+    fn add_viewer(&mut self, ctx: &egui::Context, new_trace: NewTrace) {
+        self.add_processed_viewer(ctx, new_trace, Vec::new());
+    }
+
+    /// Adds a viewer displaying a new trace, at the bottom of the window. The load-time
+    /// processing `steps` are applied in background, and their result replaces the trace.
+    fn add_processed_viewer(
+        &mut self,
+        ctx: &egui::Context,
+        new_trace: NewTrace,
+        steps: Vec<ProcessingStep>,
+    ) {
+        self.push_viewer(
+            ctx,
+            new_trace.label,
+            Arc::new(new_trace.trace.into()),
+            new_trace.sampling_rate,
+            steps,
+        );
+    }
+
+    /// Adds a viewer for each of the `traces` opened while viewing, at the bottom of the window,
+    /// with the sampling rate at the same index of `sampling_rates`. The load-time processing
+    /// `steps` are applied in background, and their result replaces the traces.
+    pub fn add_traces(
+        &mut self,
+        ctx: &egui::Context,
+        labels: Vec<String>,
+        traces: Vec<Arc<Trace>>,
+        sampling_rates: Vec<f32>,
+        steps: Vec<ProcessingStep>,
+    ) {
+        for ((label, trace), sampling_rate) in labels.into_iter().zip(traces).zip(sampling_rates) {
+            self.push_viewer(ctx, label, trace, sampling_rate, steps.clone());
+        }
+    }
+
+    /// Adds a viewer displaying `trace`, with the display settings of the others.
+    fn push_viewer(
+        &mut self,
+        ctx: &egui::Context,
+        label: String,
+        trace: Arc<Trace>,
+        sampling_rate: f32,
+        steps: Vec<ProcessingStep>,
+    ) {
+        let id = {
+            let mut traces = self.traces.write().unwrap();
+            traces.push(trace);
+            traces.len() - 1
+        };
+        self.viewers.push(Viewer::new(
+            id as u32,
+            ctx,
+            self.shared_tiling.clone(),
+            self.traces.clone(),
+            label,
+            sampling_rate,
+            steps,
+        ));
+        let viewer = self.viewers.last_mut().unwrap();
+        viewer.set_events(self.events.clone());
+        viewer.set_color_scale(self.color_scale);
+        viewer.set_keys(self.keys);
+        viewer.set_format(self.format);
+        viewer.set_tiling_policy(self.tiling_policy);
+    }
+
+    /// Closes viewer `index` and frees its trace. The last viewer cannot be closed. The dialogs
+    /// are closed, as they refer to the viewers by index.
+    fn remove_viewer(&mut self, index: usize) {
+        if self.viewers.len() < 2 {
+            return;
+        }
+        let id = self.viewers.remove(index).id();
+        // Identifiers of the other traces must not change.
+        self.traces.write().unwrap()[id as usize] = Arc::new(Vec::new().into());
+        self.shared_tiling.0.lock().unwrap().remove_trace(id);
+        self.differences
+            .retain(|link| link.a != index && link.b != index && link.target != Some(index));
+        let shift = |i: &mut usize| {
+            if *i > index {
+                *i -= 1;
+            }
+        };
+        for link in &mut self.differences {
+            shift(&mut link.a);
+            shift(&mut link.b);
+            if let Some(target) = &mut link.target {
+                shift(target);
+            }
+        }
+        if self.command_target == index {
+            self.command_target = 0;
+        }
+        shift(&mut self.command_target);
+        self.expression_dialog = None;
+        self.alignment_dialog = None;
+        self.difference_dialog = None;
+        self.dtw_dialog = None;
+        self.group_average_dialog = None;
+    }
+
+    /// Shows the failures of the loaders and renderers, and closes the viewer of a trace when
+    /// asked to, from the error dialog or from its toolbar.
+    fn update_errors(&mut self, ctx: &egui::Context) {
+        let failures = self.shared_tiling.0.lock().unwrap().take_failures();
+        for failure in failures {
+            self.errors.push(failure.message, failure.id);
+        }
+        for viewer in &mut self.viewers {
+            for message in viewer.take_failures() {
+                self.errors.push(message, Some(viewer.id()));
+            }
+        }
+        let ids: Vec<u32> = self.viewers.iter().map(Viewer::id).collect();
+        let removable = |id| ids.len() > 1 && ids.contains(&id);
+        if let ErrorDialogResult::Remove(id) = self.errors.show(ctx, removable)
+            && let Some(index) = ids.iter().position(|&i| i == id)
+        {
+            self.remove_viewer(index);
+        }
+        // Viewers closed from their toolbar are removed as well.
+        if let Some(index) = self.viewers.iter_mut().position(Viewer::take_close_request) {
+            self.remove_viewer(index);
+        }
+    }
+
+    /// Displays the files which appeared in the watched directory, in new viewers or replacing the
+    /// trace of the last viewer.
+    fn update_watch(&mut self, ctx: &egui::Context) {
+        let Some(watch) = &self.watch else {
+            return;
+        };
+        let (replace, steps) = (watch.replace, watch.steps.clone());
+        while let Some(file) = self.watch.as_ref().and_then(FolderWatch::try_receive) {
+            let traces = match file.traces {
+                Ok(traces) => traces,
+                Err(e) => {
+                    self.report_failure(format!("Failed to load {}: {}", file.path, e));
+                    continue;
+                }
+            };
+            if replace {
+                // Only the first selected trace is displayed, in place of the previous file.
+                let trace = traces.into_iter().next().unwrap();
+                let viewer = self.viewers.last_mut().unwrap();
+                viewer.set_label(trace.label);
+                viewer.set_source(file.path, trace.frame);
+                viewer.set_sampling_rate(file.sampling_rate);
+                viewer.set_original_trace(ctx, Arc::new(trace.samples.into()));
+                continue;
+            }
+            for trace in traces {
+                self.add_processed_viewer(
+                    ctx,
+                    NewTrace {
+                        label: trace.label,
+                        trace: trace.samples,
+                        sampling_rate: file.sampling_rate,
+                    },
+                    steps.clone(),
+                );
+                self.viewers
+                    .last_mut()
+                    .unwrap()
+                    .set_source(file.path.clone(), trace.frame);
+            }
+        }
+    }
+
+    /// Loads the files of the viewers again when asked by the user or when they change on disk,
+    /// and replaces the traces of the viewers once loaded.
+    fn update_reload(&mut self, ctx: &egui::Context) {
+        let mut changed: HashSet<String> = HashSet::new();
+        if let Some(watch) = &self.file_watch {
+            watch.set_paths(
+                self.viewers
+                    .iter()
+                    .filter_map(|v| Some(v.reload_source()?.0.to_string()))
+                    .collect(),
+            );
+            while let Some(path) = watch.try_receive() {
+                changed.insert(path);
+            }
+        }
+        // The frames of a file are loaded together.
+        let mut requests: Vec<ReloadRequest> = Vec::new();
+        for viewer in &mut self.viewers {
+            let requested = viewer.take_reload_request();
+            let Some((path, frame, settings)) = viewer.reload_source() else {
+                continue;
+            };
+            if !requested && !changed.contains(path) {
+                continue;
+            }
+            let target = (viewer.id(), frame);
+            match requests
+                .iter_mut()
+                .find(|r| r.path == path && r.settings == *settings)
+            {
+                Some(request) => request.targets.push(target),
+                None => requests.push(ReloadRequest {
+                    path: path.to_string(),
+                    settings: settings.clone(),
+                    targets: vec![target],
+                }),
+            }
+        }
+        for request in requests {
+            self.reloader.reload(ctx, request);
+        }
+        while let Some(reloaded) = self.reloader.try_receive() {
+            // The viewer may have been closed meanwhile.
+            let Some(viewer) = self.viewers.iter_mut().find(|v| v.id() == reloaded.id) else {
+                continue;
+            };
+            match reloaded.samples {
+                Ok(samples) => viewer.set_original_trace(ctx, Arc::new(samples.into())),
+                Err(e) => self.report_failure(format!("Failed to reload {}: {}", reloaded.path, e)),
+            }
+        }
+    }
+
+    /// Shows the expression dialog if it is opened, and adds the trace it creates.
+    fn update_expression_dialog(&mut self, ctx: &egui::Context) {
+        if self.viewers.iter_mut().any(|v| v.take_expression_request()) {
+            self.expression_dialog =
+                Some(ExpressionDialog::new(egui::Id::new("expression_dialog")));
+        }
+        let Some(dialog) = &mut self.expression_dialog else {
+            return;
+        };
+        let sources: Vec<_> = self
+            .viewers
+            .iter()
+            .map(|v| ExpressionSource {
+                label: v.label().to_string(),
+                trace: v.trace(),
+                sampling_rate: v.trace_sampling_rate(),
+            })
+            .collect();
+        match dialog.show(ctx, &sources) {
+            ExpressionDialogResult::Pending => {}
+            ExpressionDialogResult::Created(new_trace) => {
+                self.expression_dialog = None;
+                self.add_viewer(ctx, new_trace);
+            }
+            ExpressionDialogResult::Cancelled => self.expression_dialog = None,
+        }
+    }
+
+    /// Shows the group averages dialog if it is opened, and adds the traces it creates.
+    fn update_group_average_dialog(&mut self, ctx: &egui::Context) {
+        if self
+            .viewers
+            .iter_mut()
+            .any(|v| v.take_group_average_request())
+        {
+            self.group_average_dialog = Some(GroupAverageDialog::new(egui::Id::new(
+                "group_average_dialog",
+            )));
+        }
+        let Some(dialog) = &mut self.group_average_dialog else {
+            return;
+        };
+        let sources: Vec<_> = self
+            .viewers
+            .iter()
+            .map(|v| ExpressionSource {
+                label: v.label().to_string(),
+                trace: v.trace(),
+                sampling_rate: v.trace_sampling_rate(),
+            })
+            .collect();
+        match dialog.show(ctx, &sources) {
+            GroupAverageDialogResult::Pending => {}
+            GroupAverageDialogResult::Created(new_traces) => {
+                self.group_average_dialog = None;
+                for new_trace in new_traces {
+                    self.add_viewer(ctx, new_trace);
+                }
+            }
+            GroupAverageDialogResult::Cancelled => self.group_average_dialog = None,
+        }
+    }
+
+    /// Shows the alignment dialog if it is opened, and applies the offsets it finds.
+    fn update_alignment_dialog(&mut self, ctx: &egui::Context) {
+        let id = egui::Id::new("alignment_dialog");
+        if let Some(target) = self.viewers.iter_mut().position(|v| v.take_align_request()) {
+            self.alignment_dialog = Some(AlignmentDialog::new(id, target));
+        }
+        if let Some(reference) = self
+            .viewers
+            .iter_mut()
+            .position(|v| v.take_align_all_request())
+        {
+            self.alignment_dialog = Some(AlignmentDialog::all(id, reference));
+        }
+        let Some(dialog) = &mut self.alignment_dialog else {
+            return;
+        };
+        let sources: Vec<_> = self
+            .viewers
+            .iter()
+            .map(|v| AlignmentSource {
+                label: v.label().to_string(),
+                trace: v.trace(),
+                selection: v.selection(),
+            })
+            .collect();
+        match dialog.show(ctx, &sources) {
+            AlignmentDialogResult::Pending => {}
+            AlignmentDialogResult::Apply { reference, lags } => {
+                self.alignment_dialog = None;
+                for (target, lag) in lags {
+                    // The integer part of the lag is applied as an offset, and the fraction of
+                    // sample by interpolating the trace.
+                    let whole = lag.round();
+                    let offset = self.viewers[reference].x_offset() + Fixed::from_num(whole);
+                    self.viewers[target].set_x_offset(offset);
+                    if (lag - whole).abs() >= MIN_FRACTIONAL_LAG {
+                        self.viewers[target].add_step(
+                            ctx,
+                            ProcessingStep::Shift {
+                                samples: (lag - whole) as f32,
+                            },
+                        );
+                    }
+                    tracing::info!(
+                        "Aligned {} to {} with a lag of {} samples",
+                        self.viewers[target].label(),
+                        self.viewers[reference].label(),
+                        lag
+                    );
+                }
+            }
+            AlignmentDialogResult::Cancelled => self.alignment_dialog = None,
+        }
+    }
+
+    /// Shows the DTW dialog if it is opened, and adds the warped traces it creates.
+    fn update_dtw_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(target) = self.viewers.iter_mut().position(|v| v.take_dtw_request()) {
+            self.dtw_dialog = Some(DtwDialog::new(egui::Id::new("dtw_dialog"), target));
+        }
+        let Some(dialog) = &mut self.dtw_dialog else {
+            return;
+        };
+        let sources: Vec<_> = self
+            .viewers
+            .iter()
+            .map(|v| AlignmentSource {
+                label: v.label().to_string(),
+                trace: v.trace(),
+                selection: v.selection(),
+            })
+            .collect();
+        match dialog.show(ctx, &sources) {
+            DtwDialogResult::Pending => {}
+            DtwDialogResult::Create {
+                target,
+                reference,
+                start,
+                warped,
+            } => {
+                let offset = self.viewers[reference].x_offset() - Fixed::from_num(start);
+                let new_trace = NewTrace {
+                    label: format!("{} [DTW]", self.viewers[target].label()),
+                    trace: warped,
+                    sampling_rate: self.viewers[reference].trace_sampling_rate(),
+                };
+                self.add_viewer(ctx, new_trace);
+                // The warped samples are displayed under the reference samples they match.
+                self.viewers.last_mut().unwrap().set_x_offset(offset);
+            }
+            DtwDialogResult::Cancelled => self.dtw_dialog = None,
+        }
+    }
+
+    /// Starts the calculation of the mean and standard deviation of the frames of a file when
+    /// requested by a viewer, and adds viewers displaying them once calculated. The displayed
+    /// traces of the frames are used, so the statistics include their processing.
+    fn update_frame_statistics(&mut self, ctx: &egui::Context) {
+        if let Some(i) = self
+            .viewers
+            .iter_mut()
+            .position(|v| v.take_frame_statistics_request())
+            && let Some(path) = self.viewers[i].frame_group()
+        {
+            let frames: Vec<_> = self
+                .viewers
+                .iter()
+                .filter(|v| v.frame_group() == Some(path))
+                .map(|v| v.trace())
+                .collect();
+            let count = frames.len();
+            let job = ProcessingJob::spawn(ctx, move |_| {
+                let frames: Vec<&[f32]> = frames.iter().map(|f| &f[..]).collect();
+                Some(mean_std(&frames))
+            });
+            self.frame_statistics = Some(FrameStatistics {
+                job,
+                path: path.to_string(),
+                count,
+                sampling_rate: self.viewers[i].trace_sampling_rate(),
             });
         }
+        if let Some(statistics) = &self.frame_statistics
+            && let Some(result) = statistics.job.try_take()
+        {
+            let FrameStatistics {
+                path,
+                count,
+                sampling_rate,
+                ..
+            } = self.frame_statistics.take().unwrap();
+            let (mean, std) = match result {
+                Ok(statistics) => statistics,
+                Err(e) => {
+                    self.errors
+                        .push(format!("Frame statistics failed: {}", e), None);
+                    return;
+                }
+            };
+            self.add_viewer(
+                ctx,
+                NewTrace {
+                    label: format!("{} [mean of {}]", path, count),
+                    trace: mean,
+                    sampling_rate,
+                },
+            );
+            self.add_viewer(
+                ctx,
+                NewTrace {
+                    label: format!("{} [std of {}]", path, count),
+                    trace: std,
+                    sampling_rate,
+                },
+            );
+        }
+    }
 
-        Self {
-            viewers,
-            sync: SyncFeatures::new(),
+    /// Shows the difference dialog if it is opened.
+    fn update_difference_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(a) = self
+            .viewers
+            .iter_mut()
+            .position(|v| v.take_difference_request())
+        {
+            self.difference_dialog =
+                Some(DifferenceDialog::new(egui::Id::new("difference_dialog"), a));
+        }
+        let Some(dialog) = &mut self.difference_dialog else {
+            return;
+        };
+        let labels: Vec<_> = self.viewers.iter().map(|v| v.label().to_string()).collect();
+        match dialog.show(ctx, &labels) {
+            DifferenceDialogResult::Pending => {}
+            DifferenceDialogResult::Create { a, b } => {
+                self.difference_dialog = None;
+                self.differences.push(DifferenceLink::new(a, b));
+            }
+            DifferenceDialogResult::Cancelled => self.difference_dialog = None,
+        }
+    }
+
+    /// Calculates the differences again when their sources have changed, and updates or creates
+    /// the viewers displaying them.
+    fn update_differences(&mut self, ctx: &egui::Context) {
+        let mut created = Vec::new();
+        for (k, link) in self.differences.iter_mut().enumerate() {
+            let (a, b) = (&self.viewers[link.a], &self.viewers[link.b]);
+            let state = SourcesState {
+                generations: (a.generation(), b.generation()),
+                offsets: (a.x_offset(), b.x_offset()),
+            };
+            if link.state != Some(state) {
+                // Samples a[i] and b[i + lag] are displayed at the same position.
+                let lag = (state.offsets.1 - state.offsets.0)
+                    .round()
+                    .to_num::<isize>();
+                let (trace_a, trace_b) = (a.trace(), b.trace());
+                link.state = Some(state);
+                link.job = Some(ProcessingJob::spawn(ctx, move |_| {
+                    Some(difference(&trace_a, &trace_b, lag))
+                }));
+            }
+            let Some(result) = link.job.as_ref().and_then(|job| job.try_take()) else {
+                continue;
+            };
+            link.job = None;
+            let (trace, start) = match result {
+                Ok(difference) => difference,
+                Err(e) => {
+                    tracing::warn!("Difference failed: {}", e);
+                    continue;
+                }
+            };
+            if trace.is_empty() {
+                tracing::warn!("The traces of the difference have no samples in common");
+                continue;
+            }
+            let offset = state.offsets.0 - Fixed::from_num(start);
+            match link.target {
+                Some(target) => {
+                    self.viewers[target].set_original_trace(ctx, Arc::new(trace.into()));
+                    self.viewers[target].set_x_offset(offset);
+                }
+                None => created.push((k, trace, offset)),
+            }
+        }
+        for (k, trace, offset) in created {
+            let (a, b) = (self.differences[k].a, self.differences[k].b);
+            // Labels are usually paths, which are too long to be combined.
+            let name = |label: &str| {
+                Path::new(label)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| label.to_string())
+            };
+            let label = format!(
+                "{} − {}",
+                name(self.viewers[a].label()),
+                name(self.viewers[b].label())
+            );
+            let sampling_rate = self.viewers[a].trace_sampling_rate();
+            self.add_viewer(
+                ctx,
+                NewTrace {
+                    label,
+                    trace,
+                    sampling_rate,
+                },
+            );
+            let target = self.viewers.len() - 1;
+            self.viewers[target].set_x_offset(offset);
+            self.differences[k].target = Some(target);
         }
     }
 
     /// Copy settings from viewer number `index` to others.
     fn sync(&mut self, index: usize) {
-        let source_camera = *self.viewers[index].get_camera();
-        for viewer in self
+        let targets: Vec<usize> = (0..self.viewers.len()).collect();
+        self.copy_camera(index, self.sync, &targets);
+        self.mirror_tools(index, self.sync);
+    }
+
+    /// Synchronizes the other viewers to viewer number `index`, whose trace has been fitted. When
+    /// only the X axis is synchronized, the other viewers are then fitted vertically to their
+    /// visible samples, so that none of them is clipped.
+    fn sync_autoscale(&mut self, ctx: &egui::Context, index: usize) {
+        self.sync(index);
+        let sync_x = self.sync.shift_x || self.sync.scale_x;
+        let sync_y = self.sync.shift_y || self.sync.scale_y;
+        if sync_x && !sync_y {
+            for (_, viewer) in self
+                .viewers
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| *i != index)
+            {
+                viewer.request_autoscale_y();
+            }
+            ctx.request_repaint();
+        }
+    }
+
+    /// Mirrors the tool, the selection and the regions of viewer number `index` to the others,
+    /// following the `features` to synchronize. The times are shifted by the offsets of the
+    /// traces, so that aligned traces get the same selections.
+    fn mirror_tools(&mut self, index: usize, features: SyncFeatures) {
+        let state = self.viewers[index].tool_state();
+        let regions = self.viewers[index].regions().to_vec();
+        let source_offset = self.viewers[index].x_offset();
+        for (_, viewer) in self
             .viewers
             .iter_mut()
             .enumerate()
             .filter(|(i, _)| *i != index)
-            .map(|(_, viewer)| viewer)
         {
+            let offset = viewer.x_offset() - source_offset;
+            if features.tool || features.selection {
+                viewer.mirror_tool(&state, features, offset);
+            }
+            if features.markers {
+                viewer.mirror_regions(&regions, offset.to_num());
+            }
+        }
+    }
+
+    /// Mirrors the tools and the regions changed by the user in a viewer to the others, when
+    /// they are synchronized. Returns true if the viewers have been changed.
+    fn sync_tools(&mut self) -> bool {
+        let tool_changed: Vec<bool> = self
+            .viewers
+            .iter_mut()
+            .map(Viewer::take_tool_change)
+            .collect();
+        let regions_changed: Vec<bool> = self
+            .viewers
+            .iter_mut()
+            .map(Viewer::take_regions_change)
+            .collect();
+        let mut synchronized = false;
+        if let Some(index) = tool_changed.iter().position(|&changed| changed)
+            && (self.sync.tool || self.sync.selection)
+        {
+            let features = SyncFeatures {
+                markers: false,
+                ..self.sync
+            };
+            self.mirror_tools(index, features);
+            synchronized = true;
+        }
+        if let Some(index) = regions_changed.iter().position(|&changed| changed)
+            && self.sync.markers
+        {
+            let features = SyncFeatures {
+                tool: false,
+                selection: false,
+                ..self.sync
+            };
+            self.mirror_tools(index, features);
+            synchronized = true;
+        }
+        synchronized
+    }
+
+    /// Copies the `features` of the camera of viewer number `index` to the viewers whose number
+    /// is in `targets`. In the relative mode, the changes of the camera since the beginning of
+    /// the update are applied instead: the shifts are moved by the same amounts and the scales
+    /// multiplied by the same factors.
+    fn copy_camera(&mut self, index: usize, features: SyncFeatures, targets: &[usize]) {
+        let source_camera = *self.viewers[index].get_camera();
+        let source_offset = self.viewers[index].x_offset();
+        let previous = self.previous_cameras.get(index).copied();
+        for &target in targets.iter().filter(|&&i| i != index) {
+            let viewer = &mut self.viewers[target];
             let mut camera = *viewer.get_camera();
-            if self.sync.shift_x {
-                camera.shift.x = source_camera.shift.x;
+            if features.relative
+                && let Some(previous) = previous
+            {
+                if features.shift_x {
+                    camera.shift.x += source_camera.shift.x - previous.shift.x;
+                }
+                if features.shift_y {
+                    camera.shift.y += source_camera.shift.y - previous.shift.y;
+                }
+                if features.scale_x {
+                    camera.scale.x = camera.scale.x * source_camera.scale.x / previous.scale.x;
+                }
+                if features.scale_y {
+                    camera.scale.y = camera.scale.y * source_camera.scale.y / previous.scale.y;
+                }
+                viewer.set_camera(camera);
+                // The changes are applied once, even if the viewers are synchronized again.
+                if let Some(previous) = self.previous_cameras.get_mut(target) {
+                    *previous = camera;
+                }
+                continue;
             }
-            if self.sync.shift_y {
+            if features.shift_x {
+                // Aligned traces keep their offset.
+                camera.shift.x = source_camera.shift.x - source_offset + viewer.x_offset();
+            }
+            if features.shift_y {
                 camera.shift.y = source_camera.shift.y;
             }
-            if self.sync.scale_x {
+            if features.scale_x {
                 camera.scale.x = source_camera.scale.x;
             }
-            if self.sync.scale_y {
+            if features.scale_y {
                 camera.scale.y = source_camera.scale.y;
             }
             viewer.set_camera(camera);
         }
+        if features.relative
+            && let Some(previous) = self.previous_cameras.get_mut(index)
+        {
+            *previous = source_camera;
+        }
+    }
+
+    /// Tints the `overlaid` viewers which are not tinted yet, each with its own color, and gives
+    /// them the camera of the others.
+    fn overlay(&mut self, overlaid: &[usize]) {
+        let camera_source = overlaid
+            .iter()
+            .copied()
+            .find(|&i| self.viewers[i].is_overlaid());
+        for &index in overlaid {
+            if !self.viewers[index].is_overlaid() {
+                self.viewers[index].set_overlay_color(Some(overlay_color(index)));
+                if let Some(source) = camera_source {
+                    self.copy_camera(source, SyncFeatures::new(), &[index]);
+                }
+            }
+        }
+    }
+
+    /// Paints the label of each of the `overlaid` viewers with its tint, at the top right of
+    /// `viewport`, below the toolbar.
+    fn paint_legend(&self, ui: &egui::Ui, overlaid: &[usize], viewport: Rect) {
+        let painter = ui.painter().with_clip_rect(viewport);
+        let mut pos = viewport.right_top() + vec2(-12.0, STRIP_HEIGHT + 8.0);
+        for &index in overlaid {
+            let viewer = &self.viewers[index];
+            let color = viewer.overlay_color().unwrap_or(Color32::WHITE);
+            let rect = painter.text(
+                pos,
+                Align2::RIGHT_TOP,
+                viewer.label(),
+                FontId::proportional(14.0),
+                color,
+            );
+            pos.y += rect.height() + 2.0;
+        }
+    }
+
+    /// Shows each detached viewer in its own window, and synchronizes the other viewers to it.
+    /// Returns the viewport and update status of each viewer shown in its own window, `None` for
+    /// the others. A viewer whose window is closed returns to the main window at the next update.
+    fn update_detached(&mut self, ctx: &egui::Context) -> Vec<Option<(Rect, ViewerUpdateStatus)>> {
+        let n = self.viewers.len();
+        let mut viewports: Vec<_> = (0..n).map(|_| None).collect();
+        for (index, viewport) in viewports.iter_mut().enumerate() {
+            if !self.viewers[index].is_detached() {
+                continue;
+            }
+            let viewer = &mut self.viewers[index];
+            let sync = &mut self.sync;
+            let builder = egui::ViewportBuilder::default()
+                .with_title(viewer.label())
+                .with_inner_size([1280.0, 400.0]);
+            let id = egui::ViewportId::from_hash_of(("viewer", viewer.id()));
+            let (rect, status, sync_enabled) =
+                ctx.show_viewport_immediate(id, builder, |ctx, _| {
+                    egui::CentralPanel::default()
+                        .frame(egui::Frame::default().outer_margin(0.0))
+                        .show(ctx, |ui| {
+                            let rect = Rect::from_min_size(pos2(0.0, 0.0), ui.available_size());
+                            let status = viewer.update(ctx, ui, rect);
+                            let prev_sync = *sync;
+                            viewer.paint_toolbar(
+                                ctx,
+                                if n > 1 { Some(sync) } else { None },
+                                None,
+                                rect,
+                            );
+                            let sync_enabled = (!prev_sync & *sync).any();
+                            viewer.paint_waveform(
+                                ctx,
+                                ui,
+                                rect,
+                                !status.zooming && !status.dragging_y,
+                                true,
+                            );
+                            if ctx.input(|i| i.viewport().close_requested()) {
+                                viewer.set_detached(false);
+                            }
+                            (rect, status, sync_enabled)
+                        })
+                        .inner
+                });
+            if status.autoscaled {
+                self.sync_autoscale(ctx, index);
+            } else if sync_enabled || (self.sync.any() && status.is_moving()) {
+                self.sync(index);
+            }
+            *viewport = Some((rect, status));
+        }
+        viewports
+    }
+
+    /// Returns the height in points of the rows of relative height 1 among the `rows` of viewers,
+    /// sharing the `height` of the main window, and whether they fit in it. When they do not fit,
+    /// they are scrolled. The relative height of a row is the one of its first viewer.
+    fn height_unit(&self, rows: &[&[usize]], height: f32) -> (f32, bool) {
+        let total: f32 = rows.iter().map(|row| self.viewers[row[0]].height()).sum();
+        if total * MIN_VIEWER_HEIGHT <= height {
+            (height / total, true)
+        } else {
+            (MIN_VIEWER_HEIGHT, false)
+        }
+    }
+
+    /// Shows the scroll bar of the viewers, at the right of the `area` of the main window, for a
+    /// `total` height of the viewers. The bar is dragged, or scrolled with the mouse wheel.
+    fn scrollbar(&mut self, ui: &mut egui::Ui, area: Rect, total: f32) {
+        let size = area.size();
+        let bar = Rect::from_min_max(pos2(area.right() - SCROLLBAR_WIDTH, area.top()), area.max);
+        let handle_height = (size.y * size.y / total).max(20.0);
+        let max_scroll = total - size.y;
+        let response = ui.interact(bar, ui.id().with("viewers_scrollbar"), Sense::drag());
+        if response.dragged() {
+            self.scroll += response.drag_delta().y * max_scroll / (size.y - handle_height);
+        }
+        if response.hovered() {
+            self.scroll -= ui.input(|i| i.smooth_scroll_delta.y);
+        }
+        self.scroll = self.scroll.clamp(0.0, max_scroll);
+        let handle_top = area.top() + self.scroll / max_scroll * (size.y - handle_height);
+        let handle = Rect::from_min_size(
+            pos2(bar.left() + 2.0, handle_top),
+            vec2(SCROLLBAR_WIDTH - 4.0, handle_height),
+        );
+        let visuals = ui.style().interact(&response);
+        ui.painter()
+            .rect_filled(bar, 0.0, ui.visuals().extreme_bg_color);
+        ui.painter().rect_filled(handle, 3.0, visuals.bg_fill);
+    }
+
+    /// Handles the splitters between the `rows` of viewers: dragging a splitter resizes the row
+    /// above it, taking the height of the row below when the rows `fit` in the main window, and
+    /// double-clicking it gives back the same height to all the rows. `unit` is the height of a
+    /// row of relative height 1, in points.
+    fn splitters(
+        &mut self,
+        ui: &mut egui::Ui,
+        rows: &[&[usize]],
+        viewports: &[Rect],
+        unit: f32,
+        fits: bool,
+    ) {
+        let min = STRIP_HEIGHT / unit;
+        for pair in rows.windows(2) {
+            let (above, below) = (pair[0][0], pair[1][0]);
+            let row = viewports[above].union(viewports[*pair[0].last().unwrap()]);
+            let y = row.bottom();
+            let rect =
+                Rect::from_center_size(pos2(row.center().x, y), vec2(row.width(), SPLITTER_HEIGHT));
+            let response = ui
+                .interact(
+                    rect,
+                    ui.id().with(("splitter", above)),
+                    Sense::click_and_drag(),
+                )
+                .on_hover_cursor(CursorIcon::ResizeVertical);
+            if response.double_clicked() {
+                for viewer in &mut self.viewers {
+                    viewer.set_height(1.0);
+                }
+            } else if response.dragged() {
+                let delta = response.drag_delta().y / unit;
+                let height = self.viewers[above].height();
+                if fits {
+                    // The viewer below gives or takes the height, so the viewers still fit.
+                    let other = self.viewers[below].height();
+                    if min - height <= other - min {
+                        let delta = delta.clamp(min - height, other - min);
+                        self.viewers[above].set_height(height + delta);
+                        self.viewers[below].set_height(other - delta);
+                    }
+                } else {
+                    self.viewers[above].set_height((height + delta).max(min));
+                }
+            }
+            if response.hovered() || response.dragged() {
+                ui.painter().hline(
+                    rect.x_range(),
+                    y,
+                    Stroke::new(2.0, ui.visuals().selection.bg_fill),
+                );
+            }
+        }
     }
 
-    /// Updates and paints all the viewers.
+    /// Updates and paints all the viewers, in the main window or in their own windows.
     pub fn update(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
-        let size = ui.available_size();
+        // Viewers closed from the error dialog are removed before laying out the others.
+        self.update_errors(ctx);
+        self.previous_cameras = self.viewers.iter().map(|v| *v.get_camera()).collect();
+        let detached = self.update_detached(ctx);
+        let attached: Vec<usize> = (0..detached.len())
+            .filter(|&i| detached[i].is_none())
+            .collect();
+        // The viewers are laid out below the menu bar.
+        let area = ui.available_rect_before_wrap();
+        let size = area.size();
         let n = self.viewers.len();
-        let h = size.y / n as f32;
+        if attached.is_empty() {
+            ui.centered_and_justified(|ui| {
+                ui.weak("All the traces are shown in their own windows.");
+            });
+        }
 
         // Calculate the viewport for each viewer.
         // We need viewports for both update and paint.
-        let viewports: Vec<_> = (0..self.viewers.len())
-            .map(|i| Rect::from_min_max(pos2(0.0, i as f32 * h), pos2(size.x, (i + 1) as f32 * h)))
+        let mut viewports: Vec<_> = detached
+            .iter()
+            .map(|viewport| viewport.as_ref().map_or(Rect::NOTHING, |(rect, _)| *rect))
+            .collect();
+        // The layout menu is hidden with a single viewer, which cannot stay overlaid.
+        if n < 2 && self.layout == Layout::Overlay {
+            self.layout = Layout::default();
+        }
+        // Relative heights are reset when the layout changes, as the rows are not the same.
+        if self.layout != self.previous_layout {
+            self.previous_layout = self.layout;
+            for viewer in &mut self.viewers {
+                viewer.set_height(1.0);
+            }
+        }
+        // Overlaid viewers share the whole window, the first one painting the background and the
+        // last one showing its toolbar and receiving the mouse events. The color scales are
+        // restored when leaving the overlay, or when a viewer is detached.
+        let overlay = self.layout == Layout::Overlay;
+        for (index, viewer) in self.viewers.iter_mut().enumerate() {
+            if !overlay || detached[index].is_some() {
+                viewer.set_overlay_color(None);
+            }
+        }
+        if overlay {
+            self.overlay(&attached);
+        }
+        let columns = self.layout.columns(attached.len());
+        let rows: Vec<&[usize]> = if overlay && !attached.is_empty() {
+            vec![&attached]
+        } else {
+            attached.chunks(columns).collect()
+        };
+        let (unit, fits) = self.height_unit(&rows, size.y);
+        let total: f32 = rows
+            .iter()
+            .map(|row| self.viewers[row[0]].height() * unit)
+            .sum();
+        let width = if total > size.y {
+            self.scrollbar(ui, area, total);
+            size.x - SCROLLBAR_WIDTH
+        } else {
+            self.scroll = 0.0;
+            size.x
+        };
+        let cell_width = width / columns as f32;
+        let mut top = area.top() - self.scroll;
+        for row in &rows {
+            let height = self.viewers[row[0]].height() * unit;
+            for (column, &i) in row.iter().enumerate() {
+                let column = if overlay { 0 } else { column };
+                viewports[i] = Rect::from_min_size(
+                    pos2(area.left() + column as f32 * cell_width, top),
+                    vec2(cell_width, height),
+                );
+            }
+            top += height;
+        }
+        // The viewers scrolled out of the window are not updated, and the toolbars of the
+        // viewers partially scrolled out are hidden.
+        let shown: Vec<usize> = attached
+            .iter()
+            .copied()
+            .filter(|&i| viewports[i].intersects(area))
+            .collect();
+        let shown_rows: Vec<&[usize]> = rows
+            .iter()
+            .copied()
+            .filter(|row| viewports[row[0]].intersects(area))
             .collect();
 
         // Call update of each viewer, don't do the painting yet because we might change viewer
         // settings afterwards for synchronization.
-        let status: Vec<_> = self
-            .viewers
-            .iter_mut()
-            .zip(viewports.iter())
-            .map(|(viewer, viewport)| viewer.update(ctx, ui, *viewport))
+        let status: Vec<_> = shown
+            .iter()
+            .map(|&i| self.viewers[i].update(ctx, ui, viewports[i]))
             .collect();
 
         // If some viewer changes and synchronization is performed, we use this flag to prevent
         // other viewers to request tiles while dragging or zooming is not finished yet.
         let mut allow_tile_requests_for_all = true;
+        let mut synchronized = false;
+        // Viewer whose camera is changed by the user, followed by the peer.
+        let mut moved = detached
+            .iter()
+            .position(|viewport| viewport.as_ref().is_some_and(|(_, s)| s.is_moving()));
 
-        if self.sync.any() {
-            // Check if a viewer has changing camera settings
-            if let Some((sync_index, status)) = status
-                .iter()
-                .enumerate()
-                .find(|(_, status)| status.dragging_x || status.dragging_y || status.zooming)
-            {
+        // Check if a viewer has changing camera settings
+        if let Some((k, status)) = status.iter().enumerate().find(|(_, s)| s.is_moving()) {
+            moved = Some(shown[k]);
+            if overlay {
+                self.copy_camera(shown[k], SyncFeatures::new(), &attached);
+            }
+            if self.sync.any() {
                 // dragging_x is not used here, it is ok to request for tiles when dragging along
                 // X-axis. Since the scale does not change, only missing tiles on the left or right
                 // will be requested, which is not heavy.
                 allow_tile_requests_for_all &= !status.zooming && !status.dragging_y;
-                // Viewer number sync_index has changed, we must copy settings to others.
-                self.sync(sync_index);
+                // Viewer number shown[k] has changed, we must copy settings to others.
+                self.sync(shown[k]);
+                synchronized = true;
             }
         }
 
         // Paint all toolbars first: if we detect that synchronization is turned on we have to
         // perform sync before painting waveforms.
         let mut sync_index = None;
-        for (index, (viewer, viewport)) in self.viewers.iter_mut().zip(viewports.iter()).enumerate()
-        {
+        for &index in &shown {
+            let top = viewports[index].top();
+            if top < area.top() - 0.5 || top + STRIP_HEIGHT > area.bottom() {
+                continue;
+            }
+            if overlay && attached.last() != Some(&index) {
+                continue;
+            }
             let prev_sync = self.sync;
-            viewer.paint_toolbar(
+            self.viewers[index].paint_toolbar(
                 ctx,
                 if n > 1 { Some(&mut self.sync) } else { None },
-                *viewport,
+                if n > 1 { Some(&mut self.layout) } else { None },
+                viewports[index],
             );
 
             if (!prev_sync & self.sync).any() {
@@ -159,23 +1651,89 @@ impl MultiViewer {
         }
 
         if let Some(sync_index) = sync_index {
-            self.sync(sync_index)
+            self.sync(sync_index);
+            synchronized = true;
         }
-
-        // Now that all viewers have been updated and synchronized, we can paint them.
-        for ((viewer, viewport), status) in self
+        if let Some(k) = status.iter().position(|s| s.autoscaled) {
+            self.sync_autoscale(ctx, shown[k]);
+            synchronized = true;
+        }
+        if self
             .viewers
             .iter_mut()
-            .zip(viewports.iter())
-            .zip(status.iter())
+            .any(Viewer::take_autoscale_all_request)
         {
+            for viewer in &mut self.viewers {
+                viewer.request_autoscale_y();
+            }
+            ctx.request_repaint();
+        }
+        synchronized |= self.sync_tools();
+        // The detached viewers have already been painted, they are painted again with the new
+        // camera settings.
+        if synchronized && attached.len() < n {
+            ctx.request_repaint();
+        }
+
+        self.update_expression_dialog(ctx);
+        self.update_alignment_dialog(ctx);
+        self.update_dtw_dialog(ctx);
+        self.update_frame_statistics(ctx);
+        self.update_group_average_dialog(ctx);
+        self.update_difference_dialog(ctx);
+        self.update_differences(ctx);
+        self.update_watch(ctx);
+        self.update_reload(ctx);
+        if let Some(remote) = &self.remote {
+            for commands in remote.receive() {
+                self.commands.extend(commands);
+            }
+        }
+        self.execute_commands(ctx, &viewports);
+        let new_traces: Vec<_> = self
+            .viewers
+            .iter_mut()
+            .filter_map(|v| v.take_new_trace())
+            .collect();
+        for new_trace in new_traces {
+            self.add_viewer(ctx, new_trace);
+        }
+
+        // Now that all viewers have been updated and synchronized, we can paint them.
+        for (&index, status) in shown.iter().zip(status.iter()) {
             let allow_tile_requests = !status.zooming && !status.dragging_y;
-            viewer.paint_waveform(
+            self.viewers[index].paint_waveform(
                 ctx,
                 ui,
-                *viewport,
+                viewports[index],
                 allow_tile_requests && allow_tile_requests_for_all,
+                !overlay || attached.first() == Some(&index),
             );
         }
+        if overlay && let Some(&first) = attached.first() {
+            self.paint_legend(ui, &attached, viewports[first]);
+        }
+
+        self.splitters(ui, &shown_rows, &viewports, unit, fits);
+
+        if let (Some(peer), Some(index)) = (&self.peer, moved) {
+            let ppp = ctx.pixels_per_point();
+            let (start, end) = self.viewers[index].shown_interval(ppp, viewports[index]);
+            peer.send_camera(start, end);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.renderer.render_pending_tiles(TILES_PER_UPDATE);
+            if self.shared_tiling.0.lock().unwrap().has_pending() {
+                ctx.request_repaint();
+            }
+        }
     }
 }
+
+/// Returns the tint of the viewer number `index` when the traces are overlaid. The hues are spread
+/// by the golden ratio so that the traces loaded together have distinct colors.
+fn overlay_color(index: usize) -> Color32 {
+    Hsva::new((index as f32 * 0.618_034).fract(), 0.85, 1.0, 1.0).into()
+}
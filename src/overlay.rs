@@ -0,0 +1,260 @@
+//! Analysis results overlaid on a trace.
+//!
+//! Side-channel analysis produces arrays with one value per sample or per group of samples, such
+//! as a CPA correlation or a TVLA t-statistic. Such an array can be loaded and painted over a
+//! viewer with its own vertical scale and color, time-aligned to the trace, so leakage can be
+//! inspected in context.
+
+use crate::{
+    camera::Camera,
+    loaders::{NpyLayout, SampleType, load_file},
+    util::Fixed,
+};
+use egui::{Align2, Color32, DragValue, FontId, Painter, Rect, Stroke, Ui, pos2, vec2};
+use egui_file_dialog::FileDialog;
+use std::{path::Path, sync::Arc};
+
+/// Colors given to the overlays, in loading order.
+const COLORS: [Color32; 4] = [
+    Color32::from_rgb(255, 140, 0),
+    Color32::from_rgb(0, 200, 255),
+    Color32::from_rgb(255, 80, 160),
+    Color32::from_rgb(160, 255, 80),
+];
+
+/// Vertical margin above and below the overlay, relative to the viewport height.
+const MARGIN: f32 = 0.1;
+
+/// Loads a single array from `path`, in one of the trace formats.
+pub fn load_overlay(path: &str) -> Result<Vec<f32>, String> {
+    let mut arrays = load_file(path, None, NpyLayout::Auto, 0, 0, None, SampleType::F32)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    match arrays.len() {
+        1 if !arrays[0].is_empty() => Ok(arrays.pop().unwrap()),
+        1 => Err(format!("{}: empty array", path)),
+        n => Err(format!("{}: {} arrays found, expected one", path, n)),
+    }
+}
+
+/// An array painted over the trace of a viewer.
+pub struct Overlay {
+    pub label: String,
+    pub samples: Arc<Vec<f32>>,
+    pub color: Color32,
+    pub visible: bool,
+    /// Trace sample index of the first value.
+    pub start: f64,
+    /// Number of trace samples between two values.
+    pub step: f64,
+    /// Values painted at the bottom and the top of the viewport margins.
+    pub range: [f32; 2],
+}
+
+impl Overlay {
+    /// Creates an overlay spanning the whole trace of `trace_len` samples, with a vertical scale
+    /// fitting the values.
+    pub fn new(label: String, samples: Arc<Vec<f32>>, trace_len: usize, color: Color32) -> Self {
+        let (min, max) = samples
+            .iter()
+            .filter(|x| x.is_finite())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &x| {
+                (min.min(x), max.max(x))
+            });
+        let range = if min < max {
+            [min, max]
+        } else if min.is_finite() {
+            [min - 1.0, min + 1.0]
+        } else {
+            [-1.0, 1.0]
+        };
+        Self {
+            label,
+            step: trace_len.max(1) as f64 / samples.len().max(1) as f64,
+            samples,
+            color,
+            visible: true,
+            start: 0.0,
+            range,
+        }
+    }
+
+    /// Paints the values as a line over the trace, with the overlay vertical scale. When there are
+    /// many values per pixel column, the minimum and maximum of each column are joined.
+    fn paint(&self, camera: &Camera, ppp: f32, painter: &Painter, viewport: &Rect) {
+        let [low, high] = self.range;
+        let bottom = viewport.max.y - viewport.height() * MARGIN;
+        let height = viewport.height() * (1.0 - 2.0 * MARGIN);
+        let to_y = |v: f32| bottom - (v - low) / (high - low) * height;
+        if low < 0.0 && high > 0.0 {
+            painter.hline(
+                viewport.x_range(),
+                to_y(0.0),
+                Stroke::new(1.0, self.color.gamma_multiply(0.3)),
+            );
+        }
+
+        // Values around the visible part of the trace.
+        let x0 = camera.screen_to_world_x(viewport, ppp, 0.0).to_num::<f64>();
+        let x1 = camera
+            .screen_to_world_x(viewport, ppp, viewport.width())
+            .to_num::<f64>();
+        let n = self.samples.len() as f64;
+        let i0 = ((x0 - self.start) / self.step).floor().clamp(0.0, n) as usize;
+        let i1 = ((x1 - self.start) / self.step + 2.0).ceil().clamp(0.0, n) as usize;
+
+        let mut points = Vec::new();
+        let mut column: Option<(i32, f32, f32)> = None;
+        for i in i0..i1 {
+            let v = self.samples[i];
+            if !v.is_finite() {
+                continue;
+            }
+            let x = camera.world_to_screen_x(
+                viewport,
+                ppp,
+                Fixed::from_num(self.start + i as f64 * self.step),
+            );
+            let px = (x * ppp).floor() as i32;
+            match &mut column {
+                Some((c, min, max)) if *c == px => {
+                    *min = min.min(v);
+                    *max = max.max(v);
+                }
+                _ => {
+                    if let Some((c, min, max)) = column {
+                        let cx = c as f32 / ppp;
+                        points.push(pos2(cx, to_y(min)));
+                        points.push(pos2(cx, to_y(max)));
+                    }
+                    column = Some((px, v, v));
+                }
+            }
+        }
+        if let Some((c, min, max)) = column {
+            let cx = c as f32 / ppp;
+            points.push(pos2(cx, to_y(min)));
+            points.push(pos2(cx, to_y(max)));
+        }
+        painter
+            .with_clip_rect(*viewport)
+            .line(points, Stroke::new(1.5, self.color));
+    }
+}
+
+/// Overlays of a viewer, with the file dialog for loading new ones.
+pub struct Overlays {
+    pub items: Vec<Overlay>,
+    file_dialog: FileDialog,
+    /// Error of the last loading attempt.
+    error: Option<String>,
+}
+
+impl Overlays {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            file_dialog: FileDialog::new(),
+            error: None,
+        }
+    }
+
+    /// Adds an overlay spanning the trace of `trace_len` samples.
+    pub fn add(&mut self, label: String, samples: Arc<Vec<f32>>, trace_len: usize) {
+        let color = COLORS[self.items.len() % COLORS.len()];
+        self.items
+            .push(Overlay::new(label, samples, trace_len, color));
+    }
+
+    /// Rescales the overlay positions when the sampling rate of the trace is multiplied by `k`.
+    pub fn rescale(&mut self, k: f64) {
+        for overlay in &mut self.items {
+            overlay.start *= k;
+            overlay.step *= k;
+        }
+    }
+
+    /// Updates the file dialog, and loads the picked file.
+    pub fn update_dialog(&mut self, ctx: &egui::Context, trace_len: usize) {
+        self.file_dialog.update(ctx);
+        if let Some(path) = self.file_dialog.take_picked() {
+            let path = path.to_string_lossy().to_string();
+            match load_overlay(&path) {
+                Ok(samples) => {
+                    let label = Path::new(&path)
+                        .file_name()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or(path);
+                    self.add(label, Arc::new(samples), trace_len);
+                    self.error = None;
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+
+    /// Shows the overlay list, where their position, scale and color can be edited.
+    pub fn ui(&mut self, ui: &mut Ui) {
+        let mut removed = None;
+        for (i, overlay) in self.items.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut overlay.visible, &overlay.label);
+                    ui.color_edit_button_srgba(&mut overlay.color);
+                    if ui.small_button("🗙").on_hover_text("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("First sample:");
+                    ui.add(DragValue::new(&mut overlay.start).speed(1.0));
+                    ui.label("Samples per value:");
+                    ui.add(
+                        DragValue::new(&mut overlay.step)
+                            .range(1e-6..=f64::MAX)
+                            .speed(0.01),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let [low, high] = &mut overlay.range;
+                    ui.label("Y range:");
+                    let speed = (*high - *low).abs() * 0.005;
+                    ui.add(DragValue::new(low).speed(speed));
+                    ui.add(DragValue::new(high).speed(speed));
+                });
+            });
+            ui.separator();
+        }
+        if let Some(i) = removed {
+            self.items.remove(i);
+        }
+        if ui.button("Load array…").clicked() {
+            self.file_dialog.pick_file();
+            ui.close();
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+    }
+
+    /// Paints the visible overlays, and their labels and ranges at the right of the viewport.
+    pub fn paint(&self, camera: &Camera, ppp: f32, painter: &Painter, viewport: &Rect) {
+        let mut y = viewport.min.y + 60.0;
+        for overlay in self.items.iter().filter(|o| o.visible) {
+            if overlay.range[0] < overlay.range[1] {
+                overlay.paint(camera, ppp, painter, viewport);
+            }
+            let text = format!(
+                "{} [{}, {}]",
+                overlay.label, overlay.range[0], overlay.range[1]
+            );
+            painter.text(
+                pos2(viewport.max.x, y) - vec2(10.0, 0.0),
+                Align2::RIGHT_TOP,
+                text,
+                FontId::proportional(12.0),
+                overlay.color,
+            );
+            y += 16.0;
+        }
+    }
+}
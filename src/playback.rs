@@ -0,0 +1,120 @@
+//! Playback of a trace.
+//!
+//! Long captures can be reviewed like a recording: the camera scrolls through the trace at a
+//! constant rate, either in real time at the sampling rate (possibly slowed down), or at a given
+//! number of samples per second.
+
+use crate::format::NumberFormat;
+use egui::{DragValue, Ui};
+
+/// Unit of the playback speed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackUnit {
+    /// The speed is a factor of the real time, given by the sampling rate.
+    RealTime,
+    /// The speed is a number of samples per second.
+    SamplesPerSecond,
+}
+
+impl PlaybackUnit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RealTime => "× real time",
+            Self::SamplesPerSecond => "samples/s",
+        }
+    }
+}
+
+/// Playback state of a viewer.
+pub struct Playback {
+    /// When true, the camera scrolls through the trace.
+    pub playing: bool,
+    /// Speed, in [`Playback::unit`].
+    pub speed: f64,
+    pub unit: PlaybackUnit,
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self {
+            playing: false,
+            speed: 1e-6,
+            unit: PlaybackUnit::RealTime,
+        }
+    }
+
+    /// Number of samples the camera moves per second, for a trace sampled at `sampling_rate`
+    /// MS/s.
+    pub fn samples_per_second(&self, sampling_rate: f32) -> f64 {
+        match self.unit {
+            PlaybackUnit::RealTime => self.speed * sampling_rate as f64 * 1e6,
+            PlaybackUnit::SamplesPerSecond => self.speed,
+        }
+    }
+
+    /// Doubles the speed if `faster`, halves it otherwise.
+    fn change_speed(&mut self, faster: bool) {
+        self.speed = if faster {
+            self.speed * 2.0
+        } else {
+            self.speed / 2.0
+        };
+    }
+
+    /// Shows the speed settings.
+    pub fn ui(&mut self, ui: &mut Ui, sampling_rate: f32, format: &NumberFormat) {
+        egui::Grid::new("playback").num_columns(2).show(ui, |ui| {
+            ui.label("Speed:");
+            ui.horizontal(|ui| {
+                if ui.small_button("½×").on_hover_text("Half speed").clicked() {
+                    self.change_speed(false);
+                }
+                let drag_speed = self.speed * 0.01;
+                ui.add(
+                    DragValue::new(&mut self.speed)
+                        .range(1e-12..=1e12)
+                        .speed(drag_speed),
+                );
+                if ui
+                    .small_button("2×")
+                    .on_hover_text("Double speed")
+                    .clicked()
+                {
+                    self.change_speed(true);
+                }
+            });
+            ui.end_row();
+            ui.label("Unit:");
+            ui.horizontal(|ui| {
+                let previous = self.unit;
+                for unit in [PlaybackUnit::RealTime, PlaybackUnit::SamplesPerSecond] {
+                    ui.selectable_value(&mut self.unit, unit, unit.name());
+                }
+                // The speed is converted, so changing the unit does not change the playback rate.
+                if self.unit != previous {
+                    let rate = sampling_rate as f64 * 1e6;
+                    self.speed = match self.unit {
+                        PlaybackUnit::RealTime => self.speed / rate,
+                        PlaybackUnit::SamplesPerSecond => self.speed * rate,
+                    };
+                }
+            });
+            ui.end_row();
+        });
+        // Equivalent speed in the other unit.
+        let samples_per_second = self.samples_per_second(sampling_rate);
+        ui.label(match self.unit {
+            PlaybackUnit::RealTime => format.quantity(samples_per_second, "samples/s"),
+            PlaybackUnit::SamplesPerSecond => format!(
+                "{} of trace per second",
+                format.quantity(samples_per_second / (sampling_rate as f64 * 1e6), "s")
+            ),
+        });
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,486 @@
+//! Runtime trace processing.
+//!
+//! Processing steps are applied to the original trace in background threads, so the user
+//! interface remains responsive while huge traces are being transformed. The progress of the
+//! processing is reported to the user interface, which can cancel it.
+
+use crate::{
+    filtering::{Filter, FilterSpec},
+    transforms::{
+        Normalization, decimate, envelope_hilbert, envelope_rectify, moving_average, normalize,
+        savitzky_golay, shift, sliding_variance, upsample,
+    },
+    util::panic_message,
+    wavelet::{ThresholdRule, Wavelet, denoise},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{Receiver, TryRecvError, channel},
+    },
+};
+
+/// A processing operation which can be applied to a trace at runtime.
+///
+/// Steps are stored in processing presets as tables tagged by the step name, for instance
+/// `{ step = "moving-average", window = 16 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "step",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case"
+)]
+pub enum ProcessingStep {
+    /// Digital filter.
+    Filter(FilterSpec),
+    /// Centered moving average over a number of samples.
+    MovingAverage { window: usize },
+    /// Savitzky–Golay smoothing, with an odd window length and a polynomial order.
+    SavitzkyGolay { window: usize, order: usize },
+    /// Wavelet-threshold denoising over a number of decomposition levels.
+    WaveletDenoise {
+        wavelet: Wavelet,
+        level: usize,
+        rule: ThresholdRule,
+    },
+    /// Anti-aliased decimation, dividing the sampling rate by `factor` (which can be fractional).
+    Decimate { factor: f32 },
+    /// Band-limited interpolation, multiplying the sampling rate by `factor`.
+    Upsample { factor: usize },
+    /// Band-limited shift by a fractional number of samples: output sample `i` is the input
+    /// interpolated at `i + samples`.
+    Shift { samples: f32 },
+    /// Envelope extraction (AM demodulation).
+    Envelope(EnvelopeMethod),
+    /// Variance in a centered sliding window of a number of samples.
+    SlidingVariance { window: usize },
+    /// Standard deviation in a centered sliding window of a number of samples.
+    SlidingStdDev { window: usize },
+    /// Subtraction of the trace mean value.
+    RemoveDc,
+    /// Absolute value of each sample.
+    Abs,
+    /// Square of each sample.
+    Square,
+    /// Normalization, with statistics calculated on the whole trace or on a window given as
+    /// `(start, end)` sample indices.
+    Normalize {
+        method: Normalization,
+        window: Option<(usize, usize)>,
+    },
+}
+
+/// Envelope extraction methods.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "method",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case"
+)]
+pub enum EnvelopeMethod {
+    /// Magnitude of the analytic signal, calculated with a Hilbert transformer.
+    Hilbert,
+    /// Rectification followed by a low-pass filter with the given cutoff frequency in kHz.
+    Rectify { cutoff_freq: f32 },
+}
+
+impl EnvelopeMethod {
+    pub fn name(&self) -> &str {
+        match self {
+            EnvelopeMethod::Hilbert => "Hilbert",
+            EnvelopeMethod::Rectify { .. } => "Rectify + low-pass",
+        }
+    }
+}
+
+impl ProcessingStep {
+    /// Short description of the step, displayed in the user interface.
+    pub fn name(&self) -> String {
+        match self {
+            ProcessingStep::Filter(spec) if spec.filter == Filter::Comb => format!(
+                "Comb {} kHz ×{} Q {}",
+                spec.cutoff_freq, spec.notches, spec.q
+            ),
+            ProcessingStep::Filter(spec) if spec.q == FilterSpec::DEFAULT_Q => {
+                format!("{} {} kHz", spec.filter.name(), spec.cutoff_freq)
+            }
+            ProcessingStep::Filter(spec) => {
+                format!(
+                    "{} {} kHz Q {}",
+                    spec.filter.name(),
+                    spec.cutoff_freq,
+                    spec.q
+                )
+            }
+            ProcessingStep::MovingAverage { window } => format!("Moving average {}", window),
+            ProcessingStep::SavitzkyGolay { window, order } => {
+                format!("Savitzky–Golay {} (order {})", window, order)
+            }
+            ProcessingStep::WaveletDenoise {
+                wavelet,
+                level,
+                rule,
+            } => format!(
+                "Wavelet denoise ({}, level {}, {})",
+                wavelet.name(),
+                level,
+                rule.name()
+            ),
+            ProcessingStep::Decimate { factor } => format!("Decimate ×{}", factor),
+            ProcessingStep::Upsample { factor } => format!("Upsample ×{}", factor),
+            ProcessingStep::Shift { samples } => format!("Shift {:+.3} samples", samples),
+            ProcessingStep::Envelope(EnvelopeMethod::Hilbert) => "Envelope (Hilbert)".to_string(),
+            ProcessingStep::Envelope(EnvelopeMethod::Rectify { cutoff_freq }) => {
+                format!("Envelope (rectify, {} kHz)", cutoff_freq)
+            }
+            ProcessingStep::SlidingVariance { window } => format!("Sliding variance {}", window),
+            ProcessingStep::SlidingStdDev { window } => format!("Sliding σ {}", window),
+            ProcessingStep::RemoveDc => "Remove DC".to_string(),
+            ProcessingStep::Abs => "Absolute value".to_string(),
+            ProcessingStep::Square => "Square".to_string(),
+            ProcessingStep::Normalize { method, window } => match window {
+                Some((start, end)) => format!("Normalize ({}, {}..{})", method.name(), start, end),
+                None => format!("Normalize ({})", method.name()),
+            },
+        }
+    }
+
+    /// Output sampling rate divided by the input sampling rate.
+    pub fn rate_ratio(&self) -> f64 {
+        match self {
+            ProcessingStep::Decimate { factor } => 1.0 / *factor as f64,
+            ProcessingStep::Upsample { factor } => *factor as f64,
+            _ => 1.0,
+        }
+    }
+
+    /// Checks that the step can be applied to a trace sampled at `sampling_rate` MS/s: its
+    /// parameters must be valid, as checked by [`ProcessingStep::check_parameters`], and its
+    /// frequencies below the Nyquist frequency.
+    pub fn check(&self, sampling_rate: f32) -> Result<(), String> {
+        self.check_parameters()?;
+        match self {
+            ProcessingStep::Filter(spec) => spec
+                .coefficients(sampling_rate)
+                .map(|_| ())
+                .map_err(|e| spec.error_message(e, sampling_rate)),
+            ProcessingStep::Envelope(EnvelopeMethod::Rectify { cutoff_freq })
+                if *cutoff_freq * 1e-3 >= sampling_rate / 2.0 =>
+            {
+                Err(format!(
+                    "{}: the cutoff frequency must be below the Nyquist frequency ({} kHz)",
+                    self.name(),
+                    sampling_rate * 500.0
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the parameters of the step which do not depend on the sampling rate, such as the
+    /// parameters of the steps read from the presets of the configuration file.
+    pub fn check_parameters(&self) -> Result<(), String> {
+        let error = match *self {
+            ProcessingStep::MovingAverage { window }
+            | ProcessingStep::SlidingVariance { window }
+            | ProcessingStep::SlidingStdDev { window }
+                if window < 1 =>
+            {
+                "the window must have at least one sample"
+            }
+            ProcessingStep::SavitzkyGolay { window, order }
+                if window % 2 == 0 || window <= order =>
+            {
+                "the window length must be odd and greater than the order"
+            }
+            ProcessingStep::WaveletDenoise { level, .. } if !(1..=16).contains(&level) => {
+                "the level must be between 1 and 16"
+            }
+            ProcessingStep::Decimate { factor } if !(factor.is_finite() && factor >= 1.0) => {
+                "the factor must be a number greater than or equal to 1"
+            }
+            ProcessingStep::Upsample { factor } if factor < 1 => "the factor must be at least 1",
+            ProcessingStep::Shift { samples } if !samples.is_finite() => {
+                "the shift must be a finite number"
+            }
+            ProcessingStep::Envelope(EnvelopeMethod::Rectify { cutoff_freq })
+                if !(cutoff_freq.is_finite() && cutoff_freq > 0.0) =>
+            {
+                "the cutoff frequency must be positive"
+            }
+            ProcessingStep::Normalize {
+                window: Some((start, end)),
+                ..
+            } if start >= end => "the window must end after its start",
+            _ => return Ok(()),
+        };
+        Err(format!("{}: {}", self.name(), error))
+    }
+
+    /// Returns a processed copy of `trace`, sampled at `sampling_rate` MS/s. Fails if the step
+    /// cannot be applied at this sampling rate, as reported by [`ProcessingStep::check`].
+    pub fn apply(&self, trace: &[f32], sampling_rate: f32) -> Result<Vec<f32>, String> {
+        self.check(sampling_rate)?;
+        Ok(match self {
+            ProcessingStep::Filter(spec) => spec
+                .apply(trace, sampling_rate)
+                .map_err(|e| spec.error_message(e, sampling_rate))?,
+            ProcessingStep::MovingAverage { window } => moving_average(trace, *window),
+            ProcessingStep::SavitzkyGolay { window, order } => {
+                savitzky_golay(trace, *window, *order)
+            }
+            ProcessingStep::WaveletDenoise {
+                wavelet,
+                level,
+                rule,
+            } => denoise(trace, *wavelet, *level, *rule),
+            ProcessingStep::Decimate { .. } => decimate(trace, self.rate_ratio()),
+            ProcessingStep::Upsample { factor } => upsample(trace, *factor),
+            ProcessingStep::Shift { samples } => shift(trace, *samples as f64),
+            ProcessingStep::Envelope(EnvelopeMethod::Hilbert) => envelope_hilbert(trace),
+            ProcessingStep::Envelope(EnvelopeMethod::Rectify { cutoff_freq }) => {
+                envelope_rectify(trace, cutoff_freq * 1e-3 / sampling_rate)
+            }
+            ProcessingStep::SlidingVariance { window } => sliding_variance(trace, *window),
+            ProcessingStep::SlidingStdDev { window } => sliding_variance(trace, *window)
+                .into_iter()
+                .map(f32::sqrt)
+                .collect(),
+            ProcessingStep::RemoveDc => {
+                let mean = trace.iter().map(|&x| x as f64).sum::<f64>() / trace.len().max(1) as f64;
+                trace.iter().map(|&x| (x as f64 - mean) as f32).collect()
+            }
+            ProcessingStep::Abs => trace.iter().map(|x| x.abs()).collect(),
+            ProcessingStep::Square => trace.iter().map(|x| x * x).collect(),
+            ProcessingStep::Normalize { method, window } => {
+                normalize(trace, *method, window.map(|(start, end)| start..end))
+            }
+        })
+    }
+}
+
+/// Checks that all `steps` can be applied in order to a trace sampled at `sampling_rate` MS/s,
+/// each step receiving the sampling rate of its input.
+pub fn check_steps(steps: &[ProcessingStep], sampling_rate: f32) -> Result<(), String> {
+    let mut sampling_rate = sampling_rate;
+    for step in steps {
+        step.check(sampling_rate)?;
+        sampling_rate *= step.rate_ratio() as f32;
+    }
+    Ok(())
+}
+
+/// Applies all `steps` in order to `trace`, sampled at `sampling_rate` MS/s.
+///
+/// Each step receives the sampling rate of its input, which may have been changed by previous
+/// steps. The completed fraction is reported to `progress`, and `None` is returned if the
+/// processing is cancelled. Filters report their progress regularly, the other steps only when
+/// they are finished. Fails if a step cannot be applied, as checked by [`check_steps`].
+pub fn apply_steps(
+    trace: &[f32],
+    steps: &[ProcessingStep],
+    sampling_rate: f32,
+    progress: &Progress,
+) -> Result<Option<Vec<f32>>, String> {
+    let mut result = trace.to_vec();
+    let mut sampling_rate = sampling_rate;
+    let n = steps.len() as f32;
+    for (i, step) in steps.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Ok(None);
+        }
+        result = match step {
+            ProcessingStep::Filter(spec) => {
+                let filtered = spec
+                    .apply_with_progress(&result, sampling_rate, |fraction| {
+                        progress.set((i as f32 + fraction) / n);
+                        !progress.is_cancelled()
+                    })
+                    .map_err(|e| spec.error_message(e, sampling_rate))?;
+                let Some(filtered) = filtered else {
+                    return Ok(None);
+                };
+                filtered
+            }
+            _ => step.apply(&result, sampling_rate)?,
+        };
+        progress.set((i + 1) as f32 / n);
+        sampling_rate *= step.rate_ratio() as f32;
+    }
+    Ok(Some(result))
+}
+
+/// Output sampling rate divided by the input sampling rate, for a sequence of steps.
+pub fn steps_rate_ratio(steps: &[ProcessingStep]) -> f64 {
+    steps.iter().map(|step| step.rate_ratio()).product()
+}
+
+/// Progress of a processing job, shared between the processing thread and the user interface.
+#[derive(Default)]
+pub struct Progress {
+    /// Completed fraction, stored as `f32` bits.
+    fraction: AtomicU32,
+    /// Set by the user interface to abort the processing.
+    cancelled: AtomicBool,
+    /// Start and length of the part of the job reported by `set`, stored as `f32` bits. Zero
+    /// length means the whole job.
+    scope: [AtomicU32; 2],
+}
+
+impl Progress {
+    /// Returns the completed fraction, between 0 and 1.
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.fraction.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, fraction: f32) {
+        let start = f32::from_bits(self.scope[0].load(Ordering::Relaxed));
+        let len = f32::from_bits(self.scope[1].load(Ordering::Relaxed));
+        let fraction = if len > 0.0 {
+            start + fraction * len
+        } else {
+            fraction
+        };
+        self.fraction.store(fraction.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Maps the fractions given to subsequent [`Progress::set`] calls to `start..start + len`, so
+    /// a job can run several processings reporting their own progress.
+    pub fn set_scope(&self, start: f32, len: f32) {
+        self.scope[0].store(start.to_bits(), Ordering::Relaxed);
+        self.scope[1].store(len.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// A trace processing running in a background thread, producing a `T`.
+pub struct ProcessingJob<T = Vec<f32>> {
+    receiver: Receiver<Result<T, String>>,
+    progress: Arc<Progress>,
+}
+
+impl<T: Send + 'static> ProcessingJob<T> {
+    /// Spawns a thread running `process`, which returns `None` if it has been cancelled. A repaint
+    /// of `ctx` is requested when the processing finishes, so the result can be collected. A panic
+    /// of `process` is caught and reported as the result of the job.
+    pub fn spawn<F>(ctx: &egui::Context, process: F) -> Self
+    where
+        F: FnOnce(&Progress) -> Option<T> + Send + 'static,
+    {
+        Self::try_spawn(ctx, move |progress| Ok(process(progress)))
+    }
+
+    /// Same as [`ProcessingJob::spawn`], for a processing which can fail. Its error is reported as
+    /// the result of the job.
+    pub fn try_spawn<F>(ctx: &egui::Context, process: F) -> Self
+    where
+        F: FnOnce(&Progress) -> Result<Option<T>, String> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let progress = Arc::new(Progress::default());
+        let thread_progress = progress.clone();
+        let ctx = ctx.clone();
+        let run = move || {
+            let result = match catch_unwind(AssertUnwindSafe(|| process(&thread_progress))) {
+                Ok(Ok(Some(result))) => Ok(result),
+                Ok(Ok(None)) => return,
+                Ok(Err(e)) => Err(e),
+                Err(payload) => Err(panic_message(payload.as_ref())),
+            };
+            // The receiver may have been dropped if the job has been discarded.
+            let _ = sender.send(result);
+            ctx.request_repaint();
+        };
+        // Threads cannot be spawned in the browser: the processing blocks the interface instead.
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(run);
+        #[cfg(target_arch = "wasm32")]
+        run();
+        Self { receiver, progress }
+    }
+}
+
+impl<T> ProcessingJob<T> {
+    /// Returns the completed fraction of the processing, between 0 and 1.
+    pub fn progress(&self) -> f32 {
+        self.progress.get()
+    }
+
+    /// Aborts the processing. The job must be discarded afterwards.
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    /// Returns the processing result if the processing has finished, or the error message if it
+    /// has failed.
+    pub fn try_take(&self) -> Option<Result<T, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("the processing was interrupted".into())),
+        }
+    }
+}
+
+/// Cancels the processing when the job is discarded, for instance when it is replaced by a new
+/// one, so the processing thread does not keep running for nothing.
+impl<T> Drop for ProcessingJob<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// A trace derived at runtime from the loaded ones, to be displayed by a new viewer.
+pub struct NewTrace {
+    pub label: String,
+    pub trace: Vec<f32>,
+    /// Sampling rate in MS/s.
+    pub sampling_rate: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_parameters_are_reported() {
+        let invalid = [
+            ProcessingStep::MovingAverage { window: 0 },
+            ProcessingStep::SavitzkyGolay {
+                window: 4,
+                order: 2,
+            },
+            ProcessingStep::SavitzkyGolay {
+                window: 3,
+                order: 3,
+            },
+            ProcessingStep::Decimate { factor: 0.0 },
+            ProcessingStep::Decimate { factor: f32::NAN },
+            ProcessingStep::Upsample { factor: 0 },
+            ProcessingStep::Normalize {
+                method: Normalization::ZScore,
+                window: Some((10, 10)),
+            },
+            ProcessingStep::Envelope(EnvelopeMethod::Rectify { cutoff_freq: 600.0 }),
+        ];
+        for step in invalid {
+            assert!(step.check(1.0).is_err(), "{}", step.name());
+            assert!(step.apply(&[0.0; 16], 1.0).is_err(), "{}", step.name());
+        }
+        let valid = ProcessingStep::SavitzkyGolay {
+            window: 5,
+            order: 2,
+        };
+        assert_eq!(valid.apply(&[1.0; 16], 1.0).unwrap(), [1.0; 16]);
+    }
+}
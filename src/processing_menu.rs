@@ -0,0 +1,655 @@
+//! Processing menu of the viewer toolbar.
+
+use crate::{
+    config::Config,
+    digital::Thresholds,
+    processing::{EnvelopeMethod, ProcessingStep},
+    transforms::Normalization,
+    wavelet::{ThresholdRule, Wavelet},
+};
+use egui::{Button, Checkbox, Color32, ComboBox, DragValue, TextEdit, Ui};
+use std::ops::Range;
+
+/// Action selected by the user in the processing menu.
+pub enum ProcessingAction {
+    /// Open the filter designer to add a new filter.
+    OpenFilterDesigner,
+    /// Open the filter designer to edit the filter step at the given index.
+    EditFilter(usize),
+    /// Append a step to the processing of the trace.
+    Add(ProcessingStep),
+    /// Append the steps of a preset to the processing of the trace.
+    AddPreset(Vec<ProcessingStep>),
+    /// Apply a step to the displayed trace, and display the result in a new viewer.
+    NewView(ProcessingStep),
+    /// Show the digital conversion of the trace with the given thresholds, or hide it.
+    SetDigital(Option<Thresholds>),
+    /// Remove the step at the given index.
+    Remove(usize),
+    /// Swap the steps at the given indices.
+    Swap(usize, usize),
+    /// Remove all processing steps, restoring the original trace.
+    Reset,
+    /// Open the dialog for creating a new trace from an expression.
+    NewTraceFromExpression,
+    /// Open the dialog for processing the trace with an external script.
+    RunScript,
+    /// Open the dialog for creating the difference between this trace and another one.
+    NewDifferenceTrace,
+    /// Display the mean and standard deviation of the frames loaded from the same file.
+    FrameStatistics,
+    /// Open the dialog comparing the averages of two groups of traces.
+    GroupAverages,
+}
+
+/// Unit of a length parameter.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LengthUnit {
+    Samples,
+    Microseconds,
+}
+
+impl LengthUnit {
+    fn name(&self) -> &str {
+        match self {
+            LengthUnit::Samples => "samples",
+            LengthUnit::Microseconds => "µs",
+        }
+    }
+
+    /// Converts a length expressed in this unit to a number of samples.
+    fn to_samples(self, length: f32, sampling_rate: f32) -> usize {
+        match self {
+            LengthUnit::Samples => length.round() as usize,
+            LengthUnit::Microseconds => (length * sampling_rate).round() as usize,
+        }
+    }
+}
+
+/// How the decimation is specified.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DecimationMode {
+    /// Decimation factor.
+    Factor,
+    /// Target sampling rate in MS/s.
+    TargetRate,
+}
+
+impl DecimationMode {
+    fn name(&self) -> &str {
+        match self {
+            DecimationMode::Factor => "factor",
+            DecimationMode::TargetRate => "MS/s",
+        }
+    }
+}
+
+/// Parameters of the processing operations, edited in the menu before being applied.
+pub struct ProcessingMenu {
+    /// Moving average window length, expressed in `smooth_unit`.
+    smooth_length: f32,
+    smooth_unit: LengthUnit,
+    /// Savitzky–Golay window length, in samples.
+    savgol_window: usize,
+    /// Savitzky–Golay polynomial order.
+    savgol_order: usize,
+    /// Wavelet denoising parameters.
+    wavelet: Wavelet,
+    wavelet_level: usize,
+    wavelet_rule: ThresholdRule,
+    /// Decimation factor or target sampling rate, depending on `decimation_mode`.
+    decimation: f32,
+    decimation_mode: DecimationMode,
+    /// Upsampling factor.
+    upsampling: usize,
+    /// Envelope extraction method.
+    envelope: EnvelopeMethod,
+    /// Sliding variance or standard deviation window length, expressed in `deviation_unit`.
+    deviation_length: f32,
+    deviation_unit: LengthUnit,
+    /// Calculate the variance instead of the standard deviation.
+    deviation_variance: bool,
+    /// Thresholds of the digital conversion, initialized from the trace range when the menu is
+    /// first shown.
+    digital: Option<Thresholds>,
+    /// Normalization method.
+    normalization: Normalization,
+    /// Calculate the normalization statistics on the selection only.
+    normalize_selection: bool,
+    /// Configuration file content, read again each time the menu is opened so presets saved from
+    /// other viewers are listed.
+    config: Config,
+    /// Name of the selected preset.
+    preset: Option<String>,
+    /// Name under which the current processing chain is saved.
+    preset_name: String,
+    /// Error of the last configuration file access.
+    preset_error: Option<String>,
+}
+
+impl ProcessingMenu {
+    pub fn new() -> Self {
+        Self {
+            smooth_length: 16.0,
+            smooth_unit: LengthUnit::Samples,
+            savgol_window: 11,
+            savgol_order: 3,
+            wavelet: Wavelet::Db4,
+            wavelet_level: 5,
+            wavelet_rule: ThresholdRule::UniversalSoft,
+            decimation: 2.0,
+            decimation_mode: DecimationMode::Factor,
+            upsampling: 2,
+            envelope: EnvelopeMethod::Hilbert,
+            deviation_length: 64.0,
+            deviation_unit: LengthUnit::Samples,
+            deviation_variance: false,
+            digital: None,
+            normalization: Normalization::ZScore,
+            normalize_selection: false,
+            config: Config::default(),
+            preset: None,
+            preset_name: String::new(),
+            preset_error: None,
+        }
+    }
+
+    /// Reads the presets from the configuration file. Must be called when the menu is opened.
+    pub fn reload_presets(&mut self) {
+        match Config::load() {
+            Ok(config) => {
+                self.config = config;
+                self.preset_error = None;
+            }
+            Err(e) => self.preset_error = Some(e),
+        }
+    }
+
+    /// Saves `steps` as the preset `name`, replacing any preset with the same name.
+    fn save_preset(&mut self, name: String, steps: &[ProcessingStep]) {
+        let result = Config::load().and_then(|mut config| {
+            config.save_preset(&name, steps)?;
+            Ok(config)
+        });
+        match result {
+            Ok(config) => {
+                self.config = config;
+                self.preset = Some(name);
+                self.preset_error = None;
+            }
+            Err(e) => self.preset_error = Some(e),
+        }
+    }
+
+    /// Removes the preset `name` from the configuration file.
+    fn delete_preset(&mut self, name: &str) {
+        let result = Config::load().and_then(|mut config| {
+            config.delete_preset(name)?;
+            Ok(config)
+        });
+        match result {
+            Ok(config) => {
+                self.config = config;
+                self.preset = None;
+                self.preset_error = None;
+            }
+            Err(e) => self.preset_error = Some(e),
+        }
+    }
+
+    /// Shows the menu content and returns the action selected by the user, if any.
+    ///
+    /// `sampling_rate` is the sampling rate in MS/s of the processed trace, used to convert time
+    /// lengths into samples. `steps` is the current processing chain, listed so it can be
+    /// edited. `selection` is the range of samples selected with the Range tool, if any.
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        sampling_rate: f32,
+        steps: &[ProcessingStep],
+        selection: Option<Range<usize>>,
+        trace_range: [f32; 2],
+        frames: bool,
+    ) -> Option<ProcessingAction> {
+        let mut action = None;
+        if !steps.is_empty() {
+            egui::Grid::new("processing_steps")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (i, step) in steps.iter().enumerate() {
+                        ui.label(format!("{}. {}", i + 1, step.name()));
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(i > 0, Button::new("⏶").small()).clicked() {
+                                action = Some(ProcessingAction::Swap(i - 1, i));
+                            }
+                            if ui
+                                .add_enabled(i + 1 < steps.len(), Button::new("⏷").small())
+                                .clicked()
+                            {
+                                action = Some(ProcessingAction::Swap(i, i + 1));
+                            }
+                            if matches!(step, ProcessingStep::Filter(_))
+                                && ui.small_button("✏").on_hover_text("Edit").clicked()
+                            {
+                                action = Some(ProcessingAction::EditFilter(i));
+                            }
+                            if ui.small_button("🗙").on_hover_text("Remove").clicked() {
+                                action = Some(ProcessingAction::Remove(i));
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+            ui.separator();
+        }
+
+        if ui.button("Filter…").clicked() {
+            action = Some(ProcessingAction::OpenFilterDesigner);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Moving average:");
+            ui.add(
+                DragValue::new(&mut self.smooth_length)
+                    .range(1.0..=1e9)
+                    .speed(1.0),
+            );
+            ComboBox::from_id_salt("smooth_unit")
+                .selected_text(self.smooth_unit.name())
+                .show_ui(ui, |ui| {
+                    for x in [LengthUnit::Samples, LengthUnit::Microseconds] {
+                        ui.selectable_value(&mut self.smooth_unit, x, x.name());
+                    }
+                });
+            let window = self
+                .smooth_unit
+                .to_samples(self.smooth_length, sampling_rate);
+            if ui
+                .add_enabled(window > 1, Button::new("Apply"))
+                .on_hover_text(format!("{} samples", window))
+                .clicked()
+            {
+                action = Some(ProcessingAction::Add(ProcessingStep::MovingAverage {
+                    window,
+                }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Savitzky–Golay:");
+            ui.add(
+                DragValue::new(&mut self.savgol_window)
+                    .range(3..=1001)
+                    .speed(2.0)
+                    .prefix("window "),
+            );
+            ui.add(
+                DragValue::new(&mut self.savgol_order)
+                    .range(0..=10)
+                    .prefix("order "),
+            );
+            let valid = self.savgol_window % 2 == 1 && self.savgol_window > self.savgol_order;
+            if ui
+                .add_enabled(valid, Button::new("Apply"))
+                .on_disabled_hover_text("Window length must be odd and greater than the order.")
+                .clicked()
+            {
+                action = Some(ProcessingAction::Add(ProcessingStep::SavitzkyGolay {
+                    window: self.savgol_window,
+                    order: self.savgol_order,
+                }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Wavelet denoise:");
+            ComboBox::from_id_salt("wavelet")
+                .selected_text(self.wavelet.name())
+                .show_ui(ui, |ui| {
+                    for x in [Wavelet::Haar, Wavelet::Db2, Wavelet::Db4] {
+                        ui.selectable_value(&mut self.wavelet, x, x.name());
+                    }
+                });
+            ui.add(
+                DragValue::new(&mut self.wavelet_level)
+                    .range(1..=16)
+                    .prefix("level "),
+            );
+            ComboBox::from_id_salt("wavelet_rule")
+                .selected_text(self.wavelet_rule.name())
+                .show_ui(ui, |ui| {
+                    for x in [
+                        ThresholdRule::UniversalHard,
+                        ThresholdRule::UniversalSoft,
+                        ThresholdRule::Bayes,
+                    ] {
+                        ui.selectable_value(&mut self.wavelet_rule, x, x.name());
+                    }
+                });
+            if ui.button("Apply").clicked() {
+                action = Some(ProcessingAction::Add(ProcessingStep::WaveletDenoise {
+                    wavelet: self.wavelet,
+                    level: self.wavelet_level,
+                    rule: self.wavelet_rule,
+                }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Decimate:");
+            ui.add(
+                DragValue::new(&mut self.decimation)
+                    .range(0.001..=1e9)
+                    .speed(0.1),
+            );
+            ComboBox::from_id_salt("decimation_mode")
+                .selected_text(self.decimation_mode.name())
+                .show_ui(ui, |ui| {
+                    for x in [DecimationMode::Factor, DecimationMode::TargetRate] {
+                        ui.selectable_value(&mut self.decimation_mode, x, x.name());
+                    }
+                });
+            let factor = match self.decimation_mode {
+                DecimationMode::Factor => self.decimation,
+                DecimationMode::TargetRate => sampling_rate / self.decimation,
+            };
+            if ui
+                .add_enabled(factor > 1.0, Button::new("Apply"))
+                .on_hover_text(format!(
+                    "Factor {}, resulting in {} MS/s",
+                    factor,
+                    sampling_rate / factor
+                ))
+                .clicked()
+            {
+                action = Some(ProcessingAction::Add(ProcessingStep::Decimate { factor }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Upsample:");
+            ui.add(DragValue::new(&mut self.upsampling).range(2..=64));
+            if ui
+                .button("Apply")
+                .on_hover_text(format!(
+                    "Resulting in {} MS/s",
+                    sampling_rate * self.upsampling as f32
+                ))
+                .clicked()
+            {
+                action = Some(ProcessingAction::Add(ProcessingStep::Upsample {
+                    factor: self.upsampling,
+                }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Envelope:");
+            ComboBox::from_id_salt("envelope_method")
+                .selected_text(self.envelope.name())
+                .show_ui(ui, |ui| {
+                    for x in [
+                        EnvelopeMethod::Hilbert,
+                        EnvelopeMethod::Rectify {
+                            cutoff_freq: sampling_rate * 50.0,
+                        },
+                    ] {
+                        // Keep the current cutoff frequency when re-selecting the same method.
+                        let selected =
+                            std::mem::discriminant(&self.envelope) == std::mem::discriminant(&x);
+                        if ui.selectable_label(selected, x.name()).clicked() && !selected {
+                            self.envelope = x;
+                        }
+                    }
+                });
+            let mut valid = true;
+            if let EnvelopeMethod::Rectify { cutoff_freq } = &mut self.envelope {
+                ui.add(
+                    DragValue::new(cutoff_freq)
+                        .suffix(" kHz")
+                        .range(0.001..=1000e6)
+                        .speed(10.0),
+                );
+                valid = *cutoff_freq < sampling_rate * 500.0;
+            }
+            if ui.add_enabled(valid, Button::new("Apply")).clicked() {
+                action = Some(ProcessingAction::Add(ProcessingStep::Envelope(
+                    self.envelope,
+                )));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Sliding:");
+            ComboBox::from_id_salt("deviation_kind")
+                .selected_text(if self.deviation_variance {
+                    "variance"
+                } else {
+                    "σ"
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.deviation_variance, false, "σ");
+                    ui.selectable_value(&mut self.deviation_variance, true, "variance");
+                });
+            ui.add(
+                DragValue::new(&mut self.deviation_length)
+                    .range(2.0..=1e9)
+                    .speed(1.0),
+            );
+            ComboBox::from_id_salt("deviation_unit")
+                .selected_text(self.deviation_unit.name())
+                .show_ui(ui, |ui| {
+                    for x in [LengthUnit::Samples, LengthUnit::Microseconds] {
+                        ui.selectable_value(&mut self.deviation_unit, x, x.name());
+                    }
+                });
+            let window = self
+                .deviation_unit
+                .to_samples(self.deviation_length, sampling_rate);
+            let step = if self.deviation_variance {
+                ProcessingStep::SlidingVariance { window }
+            } else {
+                ProcessingStep::SlidingStdDev { window }
+            };
+            if ui
+                .add_enabled(window > 1, Button::new("Apply"))
+                .on_hover_text(format!("{} samples", window))
+                .clicked()
+            {
+                action = Some(ProcessingAction::Add(step));
+            }
+            if ui
+                .add_enabled(window > 1, Button::new("New view"))
+                .on_hover_text("Display the result in a new view, below the current one.")
+                .clicked()
+            {
+                action = Some(ProcessingAction::NewView(step));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Remove DC").clicked() {
+                action = Some(ProcessingAction::Add(ProcessingStep::RemoveDc));
+            }
+            if ui.button("Absolute value").clicked() {
+                action = Some(ProcessingAction::Add(ProcessingStep::Abs));
+            }
+            if ui.button("Square").clicked() {
+                action = Some(ProcessingAction::Add(ProcessingStep::Square));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Normalize:");
+            ComboBox::from_id_salt("normalization")
+                .selected_text(self.normalization.name())
+                .show_ui(ui, |ui| {
+                    for x in [Normalization::ZScore, Normalization::MinMax] {
+                        ui.selectable_value(&mut self.normalization, x, x.name());
+                    }
+                });
+            ui.add_enabled(
+                selection.is_some(),
+                Checkbox::new(&mut self.normalize_selection, "On selection"),
+            )
+            .on_disabled_hover_text("Select a range with the Range tool first.");
+            if ui.button("Apply").clicked() {
+                let window = selection
+                    .clone()
+                    .filter(|_| self.normalize_selection)
+                    .map(|r| (r.start, r.end));
+                action = Some(ProcessingAction::Add(ProcessingStep::Normalize {
+                    method: self.normalization,
+                    window,
+                }));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Digital lane:");
+            // Thresholds at 40% and 60% of the trace range.
+            let auto = || {
+                let [min, max] = trace_range;
+                Thresholds {
+                    low: min + (max - min) * 0.4,
+                    high: min + (max - min) * 0.6,
+                }
+            };
+            let thresholds = self.digital.get_or_insert_with(auto);
+            let speed = (trace_range[1] - trace_range[0]) / 200.0;
+            ui.add(
+                DragValue::new(&mut thresholds.low)
+                    .speed(speed)
+                    .prefix("low "),
+            );
+            ui.add(
+                DragValue::new(&mut thresholds.high)
+                    .speed(speed)
+                    .prefix("high "),
+            );
+            if ui
+                .button("Auto")
+                .on_hover_text("Thresholds at 40% and 60% of the trace range.")
+                .clicked()
+            {
+                *thresholds = auto();
+            }
+            let valid = thresholds.low <= thresholds.high;
+            if ui
+                .add_enabled(valid, Button::new("Show"))
+                .on_hover_text(
+                    "Display the trace as a logic level under the waveform. The level goes high \
+                     above the high threshold, and low below the low threshold.",
+                )
+                .clicked()
+            {
+                action = Some(ProcessingAction::SetDigital(Some(*thresholds)));
+            }
+            if ui.button("Hide").clicked() {
+                action = Some(ProcessingAction::SetDigital(None));
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Preset:");
+            ComboBox::from_id_salt("preset")
+                .selected_text(self.preset.as_deref().unwrap_or("None"))
+                .show_ui(ui, |ui| {
+                    for name in self.config.presets.keys() {
+                        ui.selectable_value(&mut self.preset, Some(name.clone()), name);
+                    }
+                });
+            let steps = self
+                .preset
+                .as_ref()
+                .and_then(|name| self.config.presets.get(name));
+            let apply = ui.add_enabled(steps.is_some(), Button::new("Apply"));
+            let apply = match steps {
+                Some(steps) => apply.on_hover_text(
+                    steps
+                        .iter()
+                        .map(|step| step.name())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ),
+                None => apply,
+            };
+            if apply.clicked()
+                && let Some(steps) = steps
+            {
+                action = Some(ProcessingAction::AddPreset(steps.clone()));
+            }
+            if ui
+                .add_enabled(steps.is_some(), Button::new("Delete"))
+                .clicked()
+                && let Some(name) = self.preset.clone()
+            {
+                self.delete_preset(&name);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                TextEdit::singleline(&mut self.preset_name)
+                    .hint_text("Preset name")
+                    .desired_width(120.0),
+            );
+            let name = self.preset_name.trim();
+            if ui
+                .add_enabled(
+                    !steps.is_empty() && !name.is_empty(),
+                    Button::new("Save chain"),
+                )
+                .on_hover_text("Save the processing chain as a preset in the configuration file")
+                .clicked()
+            {
+                self.save_preset(name.to_string(), steps);
+            }
+        });
+        if let Some(error) = &self.preset_error {
+            ui.colored_label(Color32::RED, error);
+        }
+
+        ui.separator();
+        if ui.button("New trace from expression…").clicked() {
+            action = Some(ProcessingAction::NewTraceFromExpression);
+        }
+        if ui.button("Difference with another trace…").clicked() {
+            action = Some(ProcessingAction::NewDifferenceTrace);
+        }
+        if ui.button("Group averages…").clicked() {
+            action = Some(ProcessingAction::GroupAverages);
+        }
+        if frames
+            && ui
+                .button("Mean and std of frames")
+                .on_hover_text(
+                    "Display the mean and standard deviation of the frames loaded from this file",
+                )
+                .clicked()
+        {
+            action = Some(ProcessingAction::FrameStatistics);
+        }
+        if ui.button("Run script…").clicked() {
+            action = Some(ProcessingAction::RunScript);
+        }
+        if ui
+            .add_enabled(!steps.is_empty(), Button::new("Reset"))
+            .clicked()
+        {
+            action = Some(ProcessingAction::Reset);
+        }
+
+        // Keep the menu open while the chain is being edited.
+        if action.is_some()
+            && !matches!(
+                action,
+                Some(ProcessingAction::Remove(_) | ProcessingAction::Swap(..))
+            )
+        {
+            ui.close();
+        }
+        action
+    }
+}
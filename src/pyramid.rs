@@ -0,0 +1,271 @@
+//! Multiresolution min/max pyramid of the long traces, rendered instead of their samples by the
+//! zoomed-out tiles.
+//!
+//! Each level holds the lowest and highest sample of the buckets of 2^k samples of the trace, for
+//! k from [`FIRST_LEVEL`]. The tiles covering many samples per pixel column render the level
+//! whose buckets are just small enough to give several buckets per column, as a trace alternating
+//! the minimum and the maximum of each bucket. The envelope of the samples is drawn exactly, but
+//! the segments between the extrema are longer than the ones between the samples: the densities
+//! of each column are scaled so they add up as for the samples, using the total variation of the
+//! buckets, and are approximated as spread evenly between the extrema. A tile of a billion-sample
+//! view then renders a few million values instead of reading the whole trace.
+
+use crate::{
+    renderer::{ChunkOrigin, RenderJob},
+    trace::{Trace, TraceChunk},
+};
+use std::ops::Range;
+
+/// Traces with fewer samples are always rendered from their samples, which are quick to read.
+pub const PYRAMID_MIN_SAMPLES: usize = 1 << 24;
+
+/// Binary logarithm of the number of samples in the buckets of the first level.
+const FIRST_LEVEL: u32 = 6;
+
+/// Minimum number of buckets in each pixel column of the tiles rendered from a level, below which
+/// the densities would be too coarse.
+const COLUMN_BUCKETS: f64 = 16.0;
+
+/// Number of samples of the trace read at once when building the first level, a multiple of the
+/// buckets.
+const BLOCK_SAMPLES: usize = 1 << 20;
+
+pub struct Pyramid {
+    /// Minimum and maximum of each bucket, interleaved, from the level of the smallest buckets.
+    levels: Vec<Vec<f32>>,
+    /// Sum of the absolute differences between the consecutive samples of each bucket, and
+    /// between its last sample and the first one of the next bucket, for each level.
+    variations: Vec<Vec<f32>>,
+}
+
+/// Level of a [`Pyramid`] to render.
+pub struct PyramidLevel<'a> {
+    /// Index of the level, from the level of the smallest buckets.
+    index: usize,
+    /// Number of samples in each bucket.
+    bucket: usize,
+    /// Minimum and maximum of each bucket, interleaved.
+    extrema: &'a [f32],
+    variations: &'a [f32],
+}
+
+impl Pyramid {
+    /// Builds the pyramid of `trace`, reading all its samples once.
+    pub fn build(trace: &Trace) -> Self {
+        let buckets = trace.len().div_ceil(1 << FIRST_LEVEL);
+        let mut first = FirstLevel {
+            extrema: Vec::with_capacity(buckets * 2),
+            variations: Vec::with_capacity(buckets),
+            last: None,
+        };
+        for start in (0..trace.len()).step_by(BLOCK_SAMPLES) {
+            let end = (start + BLOCK_SAMPLES).min(trace.len());
+            match trace.read_chunk(start..end).chunk() {
+                TraceChunk::F32(samples) => first.extend(samples, |sample| sample),
+                TraceChunk::I8 {
+                    codes,
+                    scale,
+                    offset,
+                } => first.extend(codes, |code| code as f32 * scale + offset),
+                TraceChunk::I16 {
+                    codes,
+                    scale,
+                    offset,
+                } => first.extend(codes, |code| code as f32 * scale + offset),
+            }
+        }
+        let mut levels = vec![first.extrema];
+        let mut variations = vec![first.variations];
+        while let Some(level) = levels.last().filter(|level| level.len() > 2) {
+            let next = level
+                .chunks(4)
+                .flat_map(|pair| match *pair {
+                    [min_a, max_a, min_b, max_b] => [min_a.min(min_b), max_a.max(max_b)],
+                    _ => [pair[0], pair[1]],
+                })
+                .collect();
+            levels.push(next);
+            let next = variations
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| pair.iter().sum())
+                .collect();
+            variations.push(next);
+        }
+        Self { levels, variations }
+    }
+
+    /// Returns true if the pixel columns of `samples_per_column` samples are rendered from a
+    /// level of the pyramid of long traces.
+    pub fn covers(samples_per_column: f64) -> bool {
+        samples_per_column >= COLUMN_BUCKETS * (1 << FIRST_LEVEL) as f64
+    }
+
+    /// Returns the level to render for pixel columns of `samples_per_column` samples, `None` if
+    /// they are too narrow and the samples must be rendered.
+    pub fn level(&self, samples_per_column: f64) -> Option<PyramidLevel<'_>> {
+        if !Self::covers(samples_per_column) {
+            return None;
+        }
+        let k = (samples_per_column / COLUMN_BUCKETS).log2().floor() as usize;
+        let index = (k - FIRST_LEVEL as usize).min(self.levels.len() - 1);
+        Some(PyramidLevel {
+            index,
+            bucket: 1 << (FIRST_LEVEL as usize + index),
+            extrema: &self.levels[index],
+            variations: &self.variations[index],
+        })
+    }
+}
+
+/// First level of a [`Pyramid`], built from the consecutive chunks of the trace.
+struct FirstLevel {
+    extrema: Vec<f32>,
+    variations: Vec<f32>,
+    /// Last sample of the previous chunk.
+    last: Option<f32>,
+}
+
+impl FirstLevel {
+    /// Adds the buckets of the samples converted to floats by `value`. The chunks are made of
+    /// whole buckets, but the last one.
+    fn extend<T: Copy>(&mut self, samples: &[T], value: impl Fn(T) -> f32) {
+        for bucket in samples.chunks(1 << FIRST_LEVEL) {
+            let mut min = f32::INFINITY;
+            let mut max = f32::NEG_INFINITY;
+            let mut variation = 0.0;
+            for (i, sample) in bucket.iter().map(|&sample| value(sample)).enumerate() {
+                min = min.min(sample);
+                max = max.max(sample);
+                if let Some(last) = self.last {
+                    // The step from the last sample of the previous bucket belongs to it.
+                    let step = (sample - last).abs() as f64;
+                    match (i, self.variations.last_mut()) {
+                        (0, Some(previous)) => *previous += step as f32,
+                        _ => variation += step,
+                    }
+                }
+                self.last = Some(sample);
+            }
+            self.extrema.extend([min, max]);
+            self.variations.push(variation as f32);
+        }
+    }
+}
+
+impl<'a> PyramidLevel<'a> {
+    /// Returns the rendering of the samples in `range` of the trace of serial number `serial`,
+    /// from the extrema of the buckets covering them, like the samples would be rendered in pixel
+    /// columns of `samples_per_column` samples, with the `w`, `h`, `offset` and `scale_y`
+    /// parameters of a [`RenderJob`].
+    pub fn tile(
+        &self,
+        serial: u64,
+        range: Range<usize>,
+        samples_per_column: f64,
+        (w, h, offset, scale_y): (u32, u32, f32, f32),
+    ) -> PyramidTile<'a> {
+        let buckets = range.start / self.bucket..range.end.div_ceil(self.bucket);
+        PyramidTile {
+            // Two values per bucket.
+            chunk_samples: (w as f64 * samples_per_column * 2.0 / self.bucket as f64) as u32,
+            extrema: &self.extrema[buckets.start * 2..buckets.end * 2],
+            variations: &self.variations[buckets.clone()],
+            bucket: self.bucket,
+            origin: ChunkOrigin {
+                source: (serial, self.index as u32 + 1),
+                start: buckets.start * 2,
+            },
+            w,
+            h,
+            offset,
+            scale_y,
+        }
+    }
+}
+
+/// Rendering of a tile from a [`PyramidLevel`]: the extrema of the buckets are rendered by the
+/// job returned by [`PyramidTile::job`], and the densities of its columns are then weighted by
+/// [`PyramidTile::weight`].
+pub struct PyramidTile<'a> {
+    chunk_samples: u32,
+    /// Minimum and maximum of the buckets covering the tile, interleaved.
+    extrema: &'a [f32],
+    variations: &'a [f32],
+    /// Number of samples in each bucket.
+    bucket: usize,
+    origin: ChunkOrigin,
+    w: u32,
+    h: u32,
+    offset: f32,
+    scale_y: f32,
+}
+
+impl PyramidTile<'_> {
+    /// Returns the rendering of the extrema of the buckets, as a trace.
+    pub fn job(&self) -> RenderJob<'_> {
+        RenderJob {
+            chunk_samples: self.chunk_samples,
+            trace: TraceChunk::F32(self.extrema),
+            origin: Some(self.origin),
+            w: self.w,
+            h: self.h,
+            offset: self.offset,
+            scale_y: self.scale_y,
+        }
+    }
+
+    /// Weights the densities rendered by the job of the tile, column by column.
+    pub fn weight(&self, data: &mut [u32]) {
+        let Self {
+            chunk_samples,
+            extrema,
+            variations,
+            w,
+            h,
+            offset,
+            scale_y,
+            ..
+        } = *self;
+        // The segments of each column add one density to the pixels they cross, about as many as
+        // their height, or to the nearest border pixel if they are outside of the tile. The
+        // densities of each column are scaled so they add up as for the samples, the segments
+        // between the samples of each bucket crossing the pixels between its extrema as many
+        // times as its variation.
+        let h_mid = h as f32 / 2.0;
+        let row = |sample: f32| h_mid - ((sample + offset) * scale_y);
+        let bucket_density = |index: usize| {
+            let (a, b) = (row(extrema[index * 2]), row(extrema[index * 2 + 1]));
+            let (top, bottom) = (a.min(b), a.max(b));
+            let visible = bottom.clamp(0.0, h as f32) - top.clamp(0.0, h as f32);
+            let height = bottom - top;
+            let segments = self.bucket as f64;
+            if height > 0.0 {
+                let fraction = (visible / height) as f64;
+                variations[index] as f64 * scale_y.abs() as f64 * fraction
+                    + segments * (1.0 - fraction)
+            } else if top < 0.0 || top >= h as f32 || top == top.round() {
+                // Flat buckets are drawn on a pixel row only if they are outside of the tile or
+                // exactly on a row.
+                segments
+            } else {
+                0.0
+            }
+        };
+        for x in 0..w as usize {
+            let start = (extrema.len() - 2).min(chunk_samples as usize * x / w as usize);
+            let end = (extrema.len() - 1).min(chunk_samples as usize * (x + 1) / w as usize);
+            // Each segment between two extrema stands for half the segments of a bucket.
+            let expected: f64 = (start..end).map(|i| bucket_density(i / 2) / 2.0).sum();
+            let column = &mut data[x * h as usize..(x + 1) * h as usize];
+            let drawn: f64 = column.iter().map(|&density| density as f64).sum();
+            if drawn > 0.0 {
+                let weight = expected / drawn;
+                for density in column {
+                    *density = (*density as f64 * weight).round() as u32;
+                }
+            }
+        }
+    }
+}
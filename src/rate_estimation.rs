@@ -0,0 +1,245 @@
+//! Estimation of the sampling rate from a tone of known frequency.
+//!
+//! When the scope metadata is missing or wrong, time measurements can be fixed by selecting a
+//! region containing a tone whose frequency is known, such as the clock of the device. The tone
+//! frequency is measured in cycles per sample, and the sampling rate is the known frequency
+//! divided by this measurement.
+
+use crate::{
+    format::NumberFormat,
+    processing::{ProcessingJob, Progress},
+    trace::Trace,
+};
+use egui::{Button, DragValue, ProgressBar, Ui};
+use rustfft::{FftPlanner, num_complex::Complex};
+use std::{f64::consts::PI, ops::Range, sync::Arc, time::Duration};
+
+/// Maximum number of samples used for the measurement, bounding the memory used by the FFT.
+const MAX_SAMPLES: usize = 1 << 22;
+
+/// Number of golden-section iterations refining the spectrum peak.
+const REFINE_ITERATIONS: usize = 40;
+
+/// Returns the frequency of the strongest tone of `samples`, in cycles per sample.
+///
+/// The samples are centered and weighted by a Hann window. The strongest bin of the spectrum is
+/// found with an FFT, and refined between the neighbor bins by maximizing the magnitude of the
+/// discrete-time Fourier transform. Only the first [`MAX_SAMPLES`] samples are used. Returns
+/// `Some(None)` if the samples are too few or flat, and `None` if the processing is cancelled.
+pub fn dominant_frequency(samples: &[f32], progress: &Progress) -> Option<Option<f64>> {
+    let samples = &samples[..samples.len().min(MAX_SAMPLES)];
+    let n = samples.len();
+    if n < 4 {
+        return Some(None);
+    }
+    let mean = samples.iter().map(|&x| x as f64).sum::<f64>() / n as f64;
+    let windowed: Vec<f64> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let hann = 0.5 - 0.5 * (2.0 * PI * (i as f64 + 0.5) / n as f64).cos();
+            (x as f64 - mean) * hann
+        })
+        .collect();
+
+    let size = n.next_power_of_two();
+    let mut spectrum: Vec<Complex<f64>> = windowed.iter().map(|&x| Complex::new(x, 0.0)).collect();
+    spectrum.resize(size, Complex::default());
+    FftPlanner::new()
+        .plan_fft_forward(size)
+        .process(&mut spectrum);
+    if progress.is_cancelled() {
+        return None;
+    }
+    progress.set(0.5);
+    // The DC bin and its neighbor are skipped: the Hann window spreads any residual offset or
+    // slow drift over them.
+    let (peak, magnitude) = spectrum[2..=size / 2]
+        .iter()
+        .enumerate()
+        .map(|(k, c)| (k + 2, c.norm_sqr()))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if magnitude == 0.0 {
+        return Some(None);
+    }
+
+    // Squared magnitude of the DTFT at a fractional frequency in cycles per sample.
+    let at = |frequency: f64| {
+        let step = Complex::from_polar(1.0, -2.0 * PI * frequency);
+        let mut phasor = Complex::new(1.0, 0.0);
+        let mut sum = Complex::<f64>::default();
+        for &x in &windowed {
+            sum += phasor * x;
+            phasor *= step;
+        }
+        sum.norm_sqr()
+    };
+    let (mut lo, mut hi) = (
+        (peak - 1) as f64 / size as f64,
+        (peak + 1) as f64 / size as f64,
+    );
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let mut x1 = hi - ratio * (hi - lo);
+    let mut x2 = lo + ratio * (hi - lo);
+    let (mut f1, mut f2) = (at(x1), at(x2));
+    for i in 0..REFINE_ITERATIONS {
+        if progress.is_cancelled() {
+            return None;
+        }
+        progress.set(0.5 + 0.5 * i as f32 / REFINE_ITERATIONS as f32);
+        if f1 < f2 {
+            lo = x1;
+            (x1, f1) = (x2, f2);
+            x2 = lo + ratio * (hi - lo);
+            f2 = at(x2);
+        } else {
+            hi = x2;
+            (x2, f2) = (x1, f1);
+            x1 = hi - ratio * (hi - lo);
+            f1 = at(x1);
+        }
+    }
+    progress.set(1.0);
+    Some(Some((lo + hi) / 2.0))
+}
+
+/// Estimate applied by the user.
+pub enum RateEstimate {
+    /// Sampling rate of the trace, in MS/s.
+    SamplingRate(f32),
+    /// The tone is the clock of the device: its period and the first sample of the measured
+    /// region, in samples.
+    Clock { period: f64, origin: usize },
+}
+
+/// Sampling rate estimator of a viewer, shown in a popup of the toolbar.
+pub struct RateEstimator {
+    /// Known frequency of the tone, in kHz.
+    frequency: f32,
+    /// Running measurement.
+    job: Option<ProcessingJob<Option<f64>>>,
+    /// Measured tone frequency in cycles per sample, or `None` if the measurement failed.
+    cycles: Option<Option<f64>>,
+    /// First sample of the measured region.
+    start: usize,
+}
+
+impl RateEstimator {
+    pub fn new() -> Self {
+        Self {
+            frequency: 1000.0,
+            job: None,
+            cycles: None,
+            start: 0,
+        }
+    }
+
+    /// Shows the estimator and returns the estimate applied by the user, if any.
+    ///
+    /// `sampling_rate` is the current sampling rate of `trace` in MS/s, and `selection` the range
+    /// of samples selected with the Range tool, if any.
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        trace: &Arc<Trace>,
+        sampling_rate: f32,
+        selection: Option<Range<usize>>,
+        format: &NumberFormat,
+    ) -> Option<RateEstimate> {
+        if let Some(job) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            self.job = None;
+            self.cycles = Some(
+                result
+                    .inspect_err(|e| tracing::warn!("Rate estimation failed: {}", e))
+                    .unwrap_or(None),
+            );
+        }
+
+        let mut result = None;
+        ui.horizontal(|ui| {
+            ui.label("Known tone frequency:");
+            if ui
+                .add(
+                    DragValue::new(&mut self.frequency)
+                        .range(0.001..=f32::MAX)
+                        .suffix(" kHz"),
+                )
+                .changed()
+            {
+                self.cycles = None;
+            }
+        });
+        if selection.is_none() {
+            ui.label("Select a region containing the tone with the Range tool.");
+        }
+        ui.horizontal(|ui| {
+            if let Some(job) = &self.job {
+                ui.add(
+                    ProgressBar::new(job.progress())
+                        .desired_width(100.0)
+                        .show_percentage()
+                        .animate(true),
+                );
+                ui.ctx().request_repaint_after(Duration::from_millis(100));
+                if ui.button("Cancel").clicked() {
+                    // Dropping the job cancels it.
+                    self.job = None;
+                }
+            } else if ui
+                .add_enabled(selection.is_some(), Button::new("Measure"))
+                .on_hover_text("Measure the frequency of the strongest tone of the Range selection")
+                .clicked()
+                && let Some(selection) = selection
+            {
+                let trace = trace.clone();
+                let end = selection.end.min(trace.len());
+                let start = selection.start.min(end);
+                self.cycles = None;
+                self.start = start;
+                self.job = Some(ProcessingJob::spawn(ui.ctx(), move |progress| {
+                    dominant_frequency(&trace[start..end], progress)
+                }));
+            }
+        });
+        match self.cycles {
+            Some(Some(cycles)) => {
+                let rate = self.frequency as f64 * 1e-3 / cycles;
+                ui.label(format!(
+                    "Measured tone: {} at {} MS/s",
+                    format.quantity(cycles * sampling_rate as f64 * 1e6, "Hz"),
+                    sampling_rate
+                ));
+                ui.label(format!(
+                    "Estimated sampling rate: {:.6} MS/s ({:+.4} %)",
+                    rate,
+                    (rate / sampling_rate as f64 - 1.0) * 100.0
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        result = Some(RateEstimate::SamplingRate(rate as f32));
+                    }
+                    if ui
+                        .button("Use as clock")
+                        .on_hover_text(
+                            "Step the view by clock cycles with the [ and ] keys, and show the \
+                            cycle boundaries",
+                        )
+                        .clicked()
+                    {
+                        result = Some(RateEstimate::Clock {
+                            period: 1.0 / cycles,
+                            origin: self.start,
+                        });
+                    }
+                });
+            }
+            Some(None) => {
+                ui.label("No tone found in the selection.");
+            }
+            None => {}
+        }
+        result
+    }
+}
@@ -0,0 +1,345 @@
+//! Labeled regions of a trace, exported for downstream analysis.
+//!
+//! Visual triage finds where the operations of interest are, such as the rounds of a cipher. The
+//! regions tagged in a viewer can be saved as JSON or CSV, so attack scripts can crop the traces
+//! to the same sample ranges. The analysis export adds the times, the sampling rate and the hash
+//! of the source file, so scripts can check they window the right capture.
+
+use crate::{camera::Camera, processing::ProcessingJob, sha256::hash_file, util::Fixed};
+use egui::{
+    Align2, Button, Color32, FontId, Painter, ProgressBar, Rect, Stroke, StrokeKind, Ui, pos2,
+};
+use egui_file_dialog::FileDialog;
+use serde_json::json;
+use std::{fmt::Write, fs, ops::Range, time::Duration};
+
+/// A labeled range of samples.
+#[derive(Clone, PartialEq)]
+pub struct Region {
+    /// First sample of the region, in samples of the displayed trace.
+    pub start: f64,
+    /// End of the region (excluded), in samples of the displayed trace.
+    pub end: f64,
+    pub label: String,
+}
+
+/// File and frame index a trace has been loaded from.
+#[derive(Clone)]
+pub struct TraceSource {
+    pub path: String,
+    /// Index of the trace in multi-trace files.
+    pub frame: Option<usize>,
+}
+
+/// Trace description written by the analysis export.
+pub struct ExportInfo<'a> {
+    /// Sampling rate of the displayed trace divided by the sampling rate of the loaded trace.
+    pub rate_ratio: f64,
+    /// Sampling rate of the loaded trace, in MS/s.
+    pub sampling_rate: f32,
+    pub source: Option<&'a TraceSource>,
+    /// Range selection, in samples of the displayed trace.
+    pub selection: Option<Range<usize>>,
+}
+
+/// File formats of the region export.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RegionFormat {
+    Json,
+    Csv,
+    /// JSON object with the regions and the Range selection in samples and seconds, the sampling
+    /// rate and the source file hash.
+    Analysis,
+}
+
+impl RegionFormat {
+    fn name(&self) -> &str {
+        match self {
+            RegionFormat::Json => "JSON",
+            RegionFormat::Csv => "CSV",
+            RegionFormat::Analysis => "JSON for analysis scripts",
+        }
+    }
+
+    fn default_file_name(&self) -> &str {
+        match self {
+            RegionFormat::Json => "regions.json",
+            RegionFormat::Csv => "regions.csv",
+            RegionFormat::Analysis => "markers.json",
+        }
+    }
+}
+
+/// Regions of a viewer, with the file dialog for exporting them.
+pub struct Regions {
+    pub items: Vec<Region>,
+    /// Label of the next region added from the Range selection.
+    new_label: String,
+    format: RegionFormat,
+    file_dialog: FileDialog,
+    /// Running analysis export, hashing the source file.
+    job: Option<ProcessingJob<Result<String, String>>>,
+    /// Result of the last export attempt.
+    status: Option<Result<String, String>>,
+}
+
+impl Regions {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            new_label: String::new(),
+            format: RegionFormat::Json,
+            file_dialog: FileDialog::new(),
+            job: None,
+            status: None,
+        }
+    }
+
+    /// Rescales the regions when the sampling rate of the trace is multiplied by `k`.
+    pub fn rescale(&mut self, k: f64) {
+        for region in &mut self.items {
+            region.start *= k;
+            region.end *= k;
+        }
+    }
+
+    /// Updates the file dialog, and exports the regions to the picked file. The ranges are
+    /// exported in samples of the loaded trace.
+    pub fn update_dialog(&mut self, ctx: &egui::Context, info: ExportInfo) {
+        if let Some(status) = self.job.as_ref().and_then(|job| job.try_take()) {
+            self.job = None;
+            self.status = Some(status.and_then(|status| status));
+        }
+        self.file_dialog.update(ctx);
+        let Some(path) = self.file_dialog.take_picked() else {
+            return;
+        };
+        let to_loaded = |x: f64| (x / info.rate_ratio).round().max(0.0) as usize;
+        let regions: Vec<_> = self
+            .items
+            .iter()
+            .map(|r| (to_loaded(r.start), to_loaded(r.end), r.label.clone()))
+            .collect();
+        let count = regions.len();
+        let result = match self.format {
+            RegionFormat::Json => fs::write(&path, regions_to_json(&regions)),
+            RegionFormat::Csv => fs::write(&path, regions_to_csv(&regions)),
+            RegionFormat::Analysis => {
+                let selection = info
+                    .selection
+                    .map(|s| to_loaded(s.start as f64)..to_loaded(s.end as f64));
+                let source = info.source.cloned();
+                let sampling_rate = info.sampling_rate as f64 * 1e6;
+                self.status = None;
+                self.job = Some(ProcessingJob::spawn(ctx, move |progress| {
+                    let hash = match &source {
+                        Some(source) => match hash_file(&source.path, progress)? {
+                            Ok(hash) => Some(hash),
+                            Err(e) => return Some(Err(format!("{}: {}", source.path, e))),
+                        },
+                        None => None,
+                    };
+                    let text = analysis_json(
+                        &regions,
+                        selection,
+                        sampling_rate,
+                        source.as_ref(),
+                        hash.as_deref(),
+                    );
+                    Some(
+                        fs::write(&path, text)
+                            .map(|()| format!("Exported {} regions", count))
+                            .map_err(|e| format!("Export failed: {}", e)),
+                    )
+                }));
+                return;
+            }
+        };
+        self.status = Some(
+            result
+                .map(|()| format!("Exported {} regions", count))
+                .map_err(|e| format!("Export failed: {}", e)),
+        );
+    }
+
+    /// Shows the region list, where the regions can be renamed or removed, and the buttons for
+    /// tagging the Range `selection` and exporting the regions.
+    pub fn ui(&mut self, ui: &mut Ui, selection: Option<Range<usize>>) {
+        let mut removed = None;
+        for (i, region) in self.items.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{:.0}..{:.0}", region.start, region.end));
+                    ui.text_edit_singleline(&mut region.label);
+                    if ui.small_button("🗙").on_hover_text("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            });
+        }
+        if let Some(i) = removed {
+            self.items.remove(i);
+        }
+        if !self.items.is_empty() {
+            ui.separator();
+        }
+        ui.horizontal(|ui| {
+            ui.label("Label:");
+            ui.text_edit_singleline(&mut self.new_label);
+            if ui
+                .add_enabled(selection.is_some(), Button::new("Tag selection"))
+                .on_disabled_hover_text("Select a region with the Range tool")
+                .clicked()
+                && let Some(selection) = &selection
+            {
+                self.items.push(Region {
+                    start: selection.start as f64,
+                    end: selection.end as f64,
+                    label: std::mem::take(&mut self.new_label),
+                });
+                self.items.sort_by(|a, b| a.start.total_cmp(&b.start));
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("region_format")
+                .selected_text(self.format.name())
+                .show_ui(ui, |ui| {
+                    for x in [
+                        RegionFormat::Json,
+                        RegionFormat::Csv,
+                        RegionFormat::Analysis,
+                    ] {
+                        ui.selectable_value(&mut self.format, x, x.name());
+                    }
+                });
+            // The analysis export can be used for the Range selection alone.
+            let exportable = !self.items.is_empty()
+                || (self.format == RegionFormat::Analysis && selection.is_some());
+            if ui
+                .add_enabled(exportable && self.job.is_none(), Button::new("Export…"))
+                .clicked()
+            {
+                self.file_dialog.config_mut().default_file_name =
+                    self.format.default_file_name().to_string();
+                self.file_dialog.save_file();
+                ui.close();
+            }
+        });
+        if let Some(job) = &self.job {
+            let mut cancel = false;
+            ui.horizontal(|ui| {
+                ui.label("Hashing the source file…");
+                ui.add(
+                    ProgressBar::new(job.progress())
+                        .desired_width(100.0)
+                        .show_percentage()
+                        .animate(true),
+                );
+                ui.ctx().request_repaint_after(Duration::from_millis(100));
+                cancel = ui.button("Cancel").clicked();
+            });
+            if cancel {
+                // Dropping the job cancels it.
+                self.job = None;
+            }
+        }
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(Color32::RED, error);
+            }
+            None => {}
+        }
+    }
+
+    /// Paints the regions as translucent bands, with their labels at the top.
+    pub fn paint(&self, camera: &Camera, ppp: f32, painter: &Painter, viewport: &Rect) {
+        let color = Color32::from_rgb(120, 200, 255);
+        let painter = painter.with_clip_rect(*viewport);
+        for region in &self.items {
+            let x = |sample: f64| camera.world_to_screen_x(viewport, ppp, Fixed::from_num(sample));
+            let (left, right) = (x(region.start), x(region.end));
+            if right < 0.0 || left > viewport.width() {
+                continue;
+            }
+            let rect = Rect::from_x_y_ranges(left..=right, viewport.y_range());
+            painter.rect(
+                rect,
+                0.0,
+                color.gamma_multiply(0.08),
+                Stroke::new(1.0, color.gamma_multiply(0.4)),
+                StrokeKind::Inside,
+            );
+            painter.text(
+                pos2(left.max(0.0) + 3.0, viewport.top() + 3.0),
+                Align2::LEFT_TOP,
+                &region.label,
+                FontId::proportional(12.0),
+                color,
+            );
+        }
+    }
+}
+
+/// Formats `(start, end, label)` regions as CSV, with a header line. Labels are quoted when
+/// needed.
+fn regions_to_csv(regions: &[(usize, usize, String)]) -> String {
+    let mut text = String::from("start,end,label\n");
+    for (start, end, label) in regions {
+        let label = if label.contains([',', '"', '\n']) {
+            format!("\"{}\"", label.replace('"', "\"\""))
+        } else {
+            label.to_string()
+        };
+        writeln!(text, "{},{},{}", start, end, label).unwrap();
+    }
+    text
+}
+
+/// Formats `(start, end, label)` regions as a JSON array of objects.
+fn regions_to_json(regions: &[(usize, usize, String)]) -> String {
+    let regions: Vec<_> = regions
+        .iter()
+        .map(|(start, end, label)| json!({"start": start, "end": end, "label": label}))
+        .collect();
+    serde_json::to_string_pretty(&regions).unwrap() + "\n"
+}
+
+/// Formats the regions and the Range `selection`, in samples of the loaded trace, as a JSON
+/// object with their times in seconds, the sampling rate in samples per second, and the source
+/// of the trace with its SHA-256 `hash`. Missing values are `null`.
+fn analysis_json(
+    regions: &[(usize, usize, String)],
+    selection: Option<Range<usize>>,
+    sampling_rate: f64,
+    source: Option<&TraceSource>,
+    hash: Option<&str>,
+) -> String {
+    let range = |start: usize, end: usize| {
+        json!({
+            "start": start,
+            "end": end,
+            "start_time": start as f64 / sampling_rate,
+            "end_time": end as f64 / sampling_rate,
+        })
+    };
+    let regions: Vec<_> = regions
+        .iter()
+        .map(|(start, end, label)| {
+            let mut region = range(*start, *end);
+            region["label"] = json!(label);
+            region
+        })
+        .collect();
+    let analysis = json!({
+        "source": source.map(|s| &s.path),
+        "frame": source.and_then(|s| s.frame),
+        "sha256": hash,
+        "sampling_rate": sampling_rate,
+        "selection": selection.map(|s| range(s.start, s.end)),
+        "regions": regions,
+    });
+    serde_json::to_string_pretty(&analysis).unwrap() + "\n"
+}
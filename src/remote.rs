@@ -0,0 +1,563 @@
+//! Remote control of the viewer over JSON-RPC, so acquisition scripts and test benches can drive
+//! it during campaigns.
+//!
+//! With `--remote`, TurboPlot listens on a localhost TCP port (`127.0.0.1:PORT`) or on a unix
+//! socket (any other address, taken as a path). Requests are not authenticated: other hosts are
+//! refused unless `--remote-any-host` is given. Each line sent by a client is a JSON-RPC 2.0
+//! request, answered by a line once its commands have been executed, including the processing they
+//! start. The connection is closed at the first line which is not JSON, and HTTP requests are
+//! refused, so web pages cannot send requests. At most 16 clients are served at the same time.
+//! With Python:
+//!
+//! ```python
+//! sock = socket.create_connection(("127.0.0.1", 9000))
+//! f = sock.makefile("rw")
+//! f.write('{"jsonrpc": "2.0", "id": 1, "method": "load_file", "params": {"path": "capture.npy"}}\n')
+//! f.flush()
+//! print(f.readline())  # {"jsonrpc":"2.0","id":1,"result":{"viewer":0}}
+//! ```
+//!
+//! Requests are translated to the automation commands of [`crate::automation`], and use the same
+//! units: times in µs from the first sample, frequencies in kHz and sampling rates in MS/s. All
+//! methods accept a `viewer` parameter selecting the viewer targeted by the request and the next
+//! ones. The result gives the index of the targeted viewer.
+//!
+//! With `--sync-peer`, TurboPlot follows the camera of another instance started with `--remote`:
+//! the time interval displayed by a view moved by the user is sent to the server of the peer as a
+//! `sync_camera` notification. The peer displays it in its targeted viewer, and synchronizes its
+//! other viewers according to its Sync options. Starting both instances with `--remote` and
+//! `--sync-peer` on each other's address synchronizes them both ways.
+
+use crate::{
+    automation::Command,
+    filtering::{Filter, FilterSpec},
+    loaders::{NpyLayout, SampleType, guess_format, load_file, open_frames, unrecognized_format},
+    processing::ProcessingStep,
+};
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+/// Error of the execution of valid commands.
+const EXECUTION_ERROR: i32 = -32000;
+
+/// Maximum number of clients served at the same time. Other clients are disconnected.
+const MAX_CLIENTS: usize = 16;
+
+/// Delay before accepting clients again after a failure, such as too many open files.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Server receiving remote requests in background, as automation commands.
+pub struct RemoteServer {
+    address: String,
+    receiver: Receiver<Vec<Command>>,
+}
+
+impl RemoteServer {
+    /// Starts listening on `address`: `HOST:PORT` for a TCP port, a path for a unix socket.
+    /// Received requests wake up the user interface.
+    ///
+    /// The requests are not authenticated, so TCP addresses must be loopback ones unless
+    /// `any_host` is set.
+    pub fn start(ctx: &egui::Context, address: &str, any_host: bool) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        if is_tcp_address(address) {
+            let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+            if addresses.iter().any(|a| !a.ip().is_loopback()) {
+                if !any_host {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "not a loopback address, use --remote-any-host to accept requests from \
+                        other hosts",
+                    ));
+                }
+                tracing::warn!(
+                    "Remote control accepting unauthenticated requests from other hosts on {}",
+                    address
+                );
+            }
+            let listener = TcpListener::bind(&addresses[..])?;
+            spawn_server(
+                ctx,
+                sender,
+                move || listener.accept().map(|(stream, _)| stream),
+                TcpStream::try_clone,
+            );
+        } else {
+            listen_unix(ctx, address, sender)?;
+        }
+        Ok(Self {
+            address: address.to_string(),
+            receiver,
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the commands of the requests received since the last call. The commands of each
+    /// request include a [`Command::Reply`], answering the client once they are executed.
+    pub fn receive(&self) -> Vec<Vec<Command>> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+/// Returns true if `address` is `HOST:PORT`, false if it is the path of a unix socket.
+fn is_tcp_address(address: &str) -> bool {
+    address.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty() && !host.contains('/') && port.parse::<u16>().is_ok()
+    })
+}
+
+/// Connection to the remote server of another instance, following the camera of this one.
+pub struct RemotePeer {
+    sender: Sender<(f64, f64)>,
+}
+
+impl RemotePeer {
+    /// Starts sending the camera changes to the server at `address`, in background. The
+    /// connection is opened at the first change, and opened again after a failure.
+    pub fn connect(address: &str) -> Self {
+        let (sender, receiver) = mpsc::channel::<(f64, f64)>();
+        let address = address.to_string();
+        thread::spawn(move || {
+            let mut stream = None;
+            let mut failed = false;
+            while let Ok(interval) = receiver.recv() {
+                // Only the last displayed interval matters.
+                let (start, end) = receiver.try_iter().last().unwrap_or(interval);
+                if stream.is_none() {
+                    match connect_peer(&address) {
+                        Ok(s) => {
+                            tracing::info!("Following camera changes on {}", address);
+                            stream = Some(s);
+                            failed = false;
+                        }
+                        Err(e) if !failed => {
+                            tracing::warn!("Failed to connect to {}: {}", address, e);
+                            failed = true;
+                        }
+                        Err(_) => {}
+                    }
+                }
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "sync_camera",
+                    "params": {"start": start, "end": end},
+                });
+                if let Some(s) = &mut stream
+                    && writeln!(s, "{}", notification).is_err()
+                {
+                    tracing::warn!("Connection to {} lost", address);
+                    stream = None;
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    /// Sends the time interval displayed by a viewer moved by the user, in µs.
+    pub fn send_camera(&self, start: f64, end: f64) {
+        let _ = self.sender.send((start, end));
+    }
+}
+
+/// Connects to the remote server at `address`, a TCP port or a unix socket.
+fn connect_peer(address: &str) -> io::Result<Box<dyn Write + Send>> {
+    if is_tcp_address(address) {
+        return Ok(Box::new(TcpStream::connect(address)?));
+    }
+    #[cfg(unix)]
+    {
+        Ok(Box::new(std::os::unix::net::UnixStream::connect(address)?))
+    }
+    #[cfg(not(unix))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are not supported on this platform, use HOST:PORT",
+    ))
+}
+
+/// Listens on the unix socket at `path`, replacing a socket left by a previous run.
+#[cfg(unix)]
+fn listen_unix(ctx: &egui::Context, path: &str, sender: Sender<Vec<Command>>) -> io::Result<()> {
+    use std::{
+        fs,
+        os::unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    };
+    if fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    spawn_server(
+        ctx,
+        sender,
+        move || listener.accept().map(|(stream, _)| stream),
+        UnixStream::try_clone,
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_: &egui::Context, _: &str, _: Sender<Vec<Command>>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets are not supported on this platform, use HOST:PORT",
+    ))
+}
+
+/// Serves each client returned by `accept` in its own thread, up to [`MAX_CLIENTS`] at the same
+/// time.
+fn spawn_server<S: Read + Write + Send + 'static>(
+    ctx: &egui::Context,
+    sender: Sender<Vec<Command>>,
+    accept: impl Fn() -> io::Result<S> + Send + 'static,
+    try_clone: fn(&S) -> io::Result<S>,
+) {
+    let ctx = ctx.clone();
+    let clients = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        loop {
+            let stream = match accept() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Remote control failed to accept a client: {}", e);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            if clients.fetch_add(1, Ordering::AcqRel) >= MAX_CLIENTS {
+                clients.fetch_sub(1, Ordering::AcqRel);
+                tracing::warn!(
+                    "Remote control client refused: {} clients already connected",
+                    MAX_CLIENTS
+                );
+                continue;
+            }
+            let (sender, ctx, clients) = (sender.clone(), ctx.clone(), clients.clone());
+            thread::spawn(move || {
+                if let Ok(reader) = try_clone(&stream) {
+                    serve(BufReader::new(reader), stream, &sender, &ctx);
+                }
+                clients.fetch_sub(1, Ordering::AcqRel);
+            });
+        }
+    });
+}
+
+/// Methods starting the HTTP requests, which are refused.
+const HTTP_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Answers the requests of a client until it disconnects.
+///
+/// The connection is closed at the first line which is not JSON, after answering a parse error,
+/// and without answer if the client starts with an HTTP request. Web pages can send HTTP requests
+/// to local ports: their JSON body must not be executed.
+fn serve(
+    reader: impl BufRead,
+    mut writer: impl Write,
+    sender: &Sender<Vec<Command>>,
+    ctx: &egui::Context,
+) {
+    for (i, line) in reader.lines().enumerate() {
+        let Ok(line) = line else {
+            return;
+        };
+        if i == 0 && is_http_request(&line) {
+            tracing::warn!("Remote control refused an HTTP request: {}", line.trim());
+            return;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = writeln!(
+                    writer,
+                    "{}",
+                    response(Value::Null, Err((PARSE_ERROR, e.to_string())))
+                );
+                return;
+            }
+        };
+        if let Some(response) = handle(request, sender, ctx)
+            && writeln!(writer, "{}", response).is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Returns true if `line` is the request line of an HTTP request, as in `POST / HTTP/1.1`.
+fn is_http_request(line: &str) -> bool {
+    line.split_once(' ')
+        .is_some_and(|(method, _)| HTTP_METHODS.contains(&method))
+}
+
+/// Handles a request, waiting for its commands to be executed. Returns the response, or `None`
+/// for notifications, which have no identifier.
+fn handle(request: Value, sender: &Sender<Vec<Command>>, ctx: &egui::Context) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let result = match request.get("method").and_then(Value::as_str) {
+        Some(method) => {
+            let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+            commands(method, &params).and_then(|commands| execute(commands, sender, ctx))
+        }
+        None => Err((INVALID_REQUEST, "missing method".to_string())),
+    };
+    id.map(|id| response(id, result))
+}
+
+/// Sends `commands` to the viewer and waits for their execution.
+fn execute(
+    mut commands: Vec<Command>,
+    sender: &Sender<Vec<Command>>,
+    ctx: &egui::Context,
+) -> Result<Value, (i32, String)> {
+    let (reply, result) = mpsc::channel();
+    // The reply of the quit request is sent before closing.
+    let quit = matches!(commands.last(), Some(Command::Quit));
+    let index = if quit {
+        commands.len() - 1
+    } else {
+        commands.len()
+    };
+    commands.insert(index, Command::Reply(reply));
+    let closed = || (EXECUTION_ERROR, "the viewer has been closed".to_string());
+    sender.send(commands).map_err(|_| closed())?;
+    ctx.request_repaint();
+    match result.recv().map_err(|_| closed())? {
+        Ok(viewer) => Ok(json!({"viewer": viewer})),
+        Err(e) => Err((EXECUTION_ERROR, e)),
+    }
+}
+
+/// Builds a JSON-RPC response.
+fn response(id: Value, result: Result<Value, (i32, String)>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    }
+}
+
+/// Sends an error to the clients waiting for the execution of `commands`.
+pub fn reject(commands: Vec<Command>, message: &str) {
+    for command in commands {
+        if let Command::Reply(reply) = command {
+            let _ = reply.send(Err(message.to_string()));
+        }
+    }
+}
+
+/// Returns the parameter `key`, if present.
+fn optional<T>(
+    params: &Value,
+    key: &str,
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Result<Option<T>, (i32, String)> {
+    params
+        .get(key)
+        .filter(|value| !value.is_null())
+        .map(|value| {
+            convert(value).ok_or_else(|| (INVALID_PARAMS, format!("invalid parameter {}", key)))
+        })
+        .transpose()
+}
+
+/// Returns the parameter `key`, which must be present.
+fn required<T>(
+    params: &Value,
+    key: &str,
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Result<T, (i32, String)> {
+    optional(params, key, convert)?
+        .ok_or_else(|| (INVALID_PARAMS, format!("missing parameter {}", key)))
+}
+
+/// Converts a JSON number to `f64`, rejecting the infinite values which could be obtained from
+/// huge numbers.
+fn number(value: &Value) -> Option<f64> {
+    value.as_f64().filter(|x| x.is_finite())
+}
+
+fn index(value: &Value) -> Option<usize> {
+    value
+        .as_f64()
+        .filter(|x| *x >= 0.0 && x.fract() == 0.0)
+        .map(|x| x as usize)
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// Translates a request to automation commands.
+fn commands(method: &str, params: &Value) -> Result<Vec<Command>, (i32, String)> {
+    let mut commands = Vec::new();
+    if let Some(viewer) = optional(params, "viewer", index)? {
+        commands.push(Command::Select(viewer));
+    }
+    match method {
+        "load_file" => {
+            let path = required(params, "path", string)?;
+            let frame = optional(params, "frame", index)?.unwrap_or(0);
+            let sampling_rate = optional(params, "sampling_rate", |value| {
+                number(value).filter(|&rate| rate > 0.0)
+            })?;
+            let (trace, frames) =
+                load(&path, frame).map_err(|e| (EXECUTION_ERROR, format!("{}: {}", path, e)))?;
+            commands.push(Command::Load {
+                label: if frames > 1 {
+                    format!("{} [frame {}]", path, frame)
+                } else {
+                    path
+                },
+                trace,
+                sampling_rate: sampling_rate.map(|x| x as f32),
+            });
+        }
+        "select" => {
+            required(params, "viewer", index)?;
+        }
+        "set_camera" => {
+            commands.push(Command::Show {
+                start: required(params, "start", number)?,
+                end: required(params, "end", number)?,
+            });
+            if optional(params, "autoscale_y", Value::as_bool)? == Some(true) {
+                commands.push(Command::AutoscaleY);
+            }
+        }
+        "sync_camera" => commands.push(Command::SyncCamera {
+            start: required(params, "start", number)?,
+            end: required(params, "end", number)?,
+        }),
+        "autoscale" => commands.push(Command::Autoscale),
+        "add_marker" => commands.push(Command::AddMarker {
+            time: required(params, "time", number)?,
+            label: optional(params, "label", string)?.unwrap_or_default(),
+        }),
+        "apply_filter" => {
+            let filter = required(params, "filter", |v| {
+                v.as_str().and_then(|s| Filter::from_str(s, true).ok())
+            })?;
+            let q = optional(params, "q", number)?.map_or(FilterSpec::DEFAULT_Q, |q| q as f32);
+            commands.push(Command::Process(ProcessingStep::Filter(FilterSpec {
+                filter,
+                cutoff_freq: required(params, "cutoff_freq", number)? as f32,
+                q,
+                notches: FilterSpec::DEFAULT_NOTCHES,
+            })));
+        }
+        "reset_processing" => commands.push(Command::ResetProcessing),
+        "select_range" => commands.push(Command::SelectRange {
+            start: required(params, "start", number)?,
+            end: required(params, "end", number)?,
+        }),
+        "clear_range" => commands.push(Command::ClearRange),
+        "export_png" => commands.push(Command::ExportImage(PathBuf::from(required(
+            params, "path", string,
+        )?))),
+        "export_npy" => commands.push(Command::ExportNpy(PathBuf::from(required(
+            params, "path", string,
+        )?))),
+        "quit" => commands.push(Command::Quit),
+        _ => {
+            return Err((METHOD_NOT_FOUND, format!("unknown method {}", method)));
+        }
+    }
+    Ok(commands)
+}
+
+/// Loads the frame at `index` of the file at `path`. Returns the frame and the number of frames
+/// of the file.
+fn load(path: &str, index: usize) -> Result<(Vec<f32>, usize), String> {
+    let format = guess_format(path).ok_or_else(|| unrecognized_format(path))?;
+    // Frames are read on demand when possible, so a frame of a large acquisition is loaded
+    // quickly.
+    if let Ok(file) = open_frames(path, format, NpyLayout::Auto) {
+        let frame = file.read_frame(index).map_err(|e| e.to_string())?;
+        return Ok((frame, file.frames()));
+    }
+    let mut frames = load_file(
+        path,
+        Some(format),
+        NpyLayout::Auto,
+        0,
+        0,
+        None,
+        SampleType::F32,
+    )?;
+    let count = frames.len();
+    if index >= count {
+        return Err(format!("no frame {} in {}", index, path));
+    }
+    Ok((frames.swap_remove(index), count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves `input` to a client, and returns the response and the number of received requests.
+    fn serve_input(input: &str) -> (String, usize) {
+        let (sender, receiver) = mpsc::channel();
+        let mut output = Vec::new();
+        serve(
+            input.as_bytes(),
+            &mut output,
+            &sender,
+            &egui::Context::default(),
+        );
+        (
+            String::from_utf8(output).unwrap(),
+            receiver.try_iter().count(),
+        )
+    }
+
+    #[test]
+    fn http_requests_are_refused() {
+        let request = "POST / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n\
+            {\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"export_npy\", \
+            \"params\": {\"path\": \"out.npy\"}}\n";
+        assert_eq!(serve_input(request), (String::new(), 0));
+    }
+
+    #[test]
+    fn connection_is_closed_after_a_parse_error() {
+        let request = "not json\n\
+            {\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"export_npy\", \
+            \"params\": {\"path\": \"out.npy\"}}\n";
+        let (response, requests) = serve_input(request);
+        assert_eq!(requests, 0);
+        assert_eq!(response.lines().count(), 1);
+        assert!(response.contains(&PARSE_ERROR.to_string()));
+    }
+}
@@ -0,0 +1,58 @@
+//! Headless rendering of traces as density images, for tools reusing the renderer without opening
+//! the viewer.
+//!
+//! ```no_run
+//! use turboplot::{
+//!     export::write_png,
+//!     render::render_trace,
+//!     tiling::ColorScale,
+//! };
+//!
+//! let trace: Vec<f32> = (0..100_000).map(|i| (i as f32 * 0.01).sin()).collect();
+//! let image = render_trace(&trace, 1024, 256, ColorScale::default()).unwrap();
+//! write_png("trace.png".as_ref(), &image).unwrap();
+//! ```
+
+use crate::{
+    export::{ImageSettings, render_image},
+    tiling::ColorScale,
+};
+use egui::ColorImage;
+
+/// Returns the settings rendering the whole `trace` in an image of `width` × `height` pixels,
+/// with the same vertical fit as the autoscale of the viewer.
+pub fn fit_settings(
+    trace: &[f32],
+    width: u32,
+    height: u32,
+    color_scale: ColorScale,
+) -> ImageSettings {
+    let (min, max) = trace
+        .iter()
+        .filter(|x| x.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &x| {
+            (min.min(x), max.max(x))
+        });
+    let range = if min < max { max - min } else { 1.0 };
+    ImageSettings {
+        samples_per_column: trace.len() as f32 / width as f32,
+        height,
+        offset: -min.midpoint(max),
+        scale_y: height as f32 * 0.75 / range,
+        color_scale,
+    }
+}
+
+/// Renders the whole `trace` as a density image of `width` × `height` pixels with the CPU
+/// renderer, fitted with [`fit_settings`].
+///
+/// Returns `None` if the trace has less than two samples.
+pub fn render_trace(
+    trace: &[f32],
+    width: u32,
+    height: u32,
+    color_scale: ColorScale,
+) -> Option<ColorImage> {
+    let settings = fit_settings(trace, width, height, color_scale);
+    render_image(trace, 0..trace.len(), settings)
+}
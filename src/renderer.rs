@@ -1,27 +1,91 @@
+use crate::trace::TraceChunk;
 use eframe::wgpu::{
-    self, Backends, BindGroup, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-    BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline,
-    Device, Instance, InstanceDescriptor, MapMode, Queue, ShaderStages,
+    self, BindGroup, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipeline, Device, Instance,
+    InstanceDescriptor, MapMode, Queue, ShaderStages,
+};
+use std::{
+    num::NonZeroU64,
+    ops::{Range, Sub},
+    sync::{Mutex, mpsc},
 };
-use std::{num::NonZeroU64, ops::Sub};
 
-/// Maximum number of f32 trace segments that can be sent to the GPU at once.
+/// Maximum number of trace segments that can be sent to the GPU at once.
 pub const RENDERER_MAX_TRACE_SIZE: usize = 8 * 1024 * 1024 * 4;
-/// Maximum number of u32 pixels that can be calculated by the compute shader.
-const RENDERER_MAX_PIXELS: usize = 524288;
+/// Maximum number of u32 pixels that can be calculated by the compute shader in a submission.
+pub const RENDERER_MAX_PIXELS: usize = 4 * 1024 * 1024;
+/// Maximum number of jobs rendered in a submission, each with its own parameters.
+const RENDERER_MAX_BATCH: usize = 32;
 /// Workgroup size defined in the shader.
 const RENDERER_WORKGROUP_SIZE: usize = 64;
 
 pub trait Renderer {
-    fn render(
-        &self,
-        chunk_samples: u32,
-        trace: &[f32],
-        w: u32,
-        h: u32,
-        offset: f32,
-        scale_y: f32,
-    ) -> Vec<u32>;
+    /// Calculates the density of the segments of the trace chunk of `job` crossing each pixel,
+    /// column by column.
+    fn render(&self, job: RenderJob) -> Vec<u32>;
+
+    /// Number of jobs worth rendering together by [`Renderer::render_batch`].
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Starts rendering several jobs, whose densities are returned in the same order by
+    /// [`PendingBatch::wait`]. The GPU renderer returns before the jobs are rendered, so the next
+    /// ones can be prepared meanwhile.
+    fn start_batch(&self, jobs: Vec<RenderJob>) -> PendingBatch<'_> {
+        PendingBatch::Rendered(jobs.into_iter().map(|job| self.render(job)).collect())
+    }
+}
+
+/// Densities of the jobs started by [`Renderer::start_batch`].
+pub enum PendingBatch<'a> {
+    /// The jobs have already been rendered.
+    Rendered(Vec<Vec<u32>>),
+    /// The jobs have been submitted to the GPU.
+    Submitted(Vec<Submission<'a>>),
+}
+
+impl PendingBatch<'_> {
+    /// Returns the densities of the jobs, waiting for the GPU to render them if needed.
+    pub fn wait(self) -> Vec<Vec<u32>> {
+        match self {
+            PendingBatch::Rendered(densities) => densities,
+            PendingBatch::Submitted(submissions) => submissions
+                .into_iter()
+                .flat_map(|submission| submission.wait())
+                .collect(),
+        }
+    }
+}
+
+/// Rendering of a trace chunk.
+pub struct RenderJob<'a> {
+    /// Number of samples covered by the rendered pixels, spread evenly between the columns.
+    pub chunk_samples: u32,
+    /// The samples to render, which may extend beyond `chunk_samples`.
+    pub trace: TraceChunk<'a>,
+    /// Where the samples come from, so the GPU renderer can keep them for the next chunks taken
+    /// from the same samples. `None` for the chunks rendered once.
+    pub origin: Option<ChunkOrigin>,
+    /// Rendered chunk width.
+    pub w: u32,
+    /// Rendered chunk height.
+    pub h: u32,
+    /// Y offset, added to the samples before rendering.
+    pub offset: f32,
+    /// Y-axis scaling coefficient.
+    pub scale_y: f32,
+}
+
+/// Position of a trace chunk in the samples it is taken from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkOrigin {
+    /// Identifies the samples the chunk is taken from, which must never change: the serial
+    /// number of the samples of a trace, see [`crate::trace::Trace::origin`], and 0, or the serial
+    /// number of a trace and the index of a level of its pyramid plus one.
+    pub source: (u64, u32),
+    /// Index of the first sample of the chunk in its source.
+    pub start: usize,
 }
 
 pub struct GpuRenderer {
@@ -29,16 +93,21 @@ pub struct GpuRenderer {
     device: Device,
     /// Processing queue.
     queue: Queue,
-    /// Buffer storing trace data, written by the CPU and copied to the GPU in `input_buffer`.
-    download_input_buffer: Buffer,
-    /// Buffer storing trace data, accessed by the compute shader.
+    /// Buffer storing trace data, accessed by the compute shader. Used as a ring buffer holding
+    /// the samples of the last rendered chunks: the word `w` of the samples of a source is stored
+    /// at `w % RENDERER_MAX_TRACE_SIZE`.
     input_buffer: Buffer,
+    /// Words of the samples held in the input buffer.
+    resident: Mutex<Option<Resident>>,
     /// Compute shader result buffer.
     output_buffer: Buffer,
-    /// Result buffer copied from GPU to CPU.
-    download_output_buffer: Buffer,
-    /// Buffer for the shader parameters
+    /// Result buffers copied from GPU to CPU which are not used by a submission, created when
+    /// all the buffers are used.
+    download_buffers: Mutex<Vec<Buffer>>,
+    /// Buffer for the shader parameters of each job of a submission, bound at a dynamic offset.
     params_buffer: Buffer,
+    /// Distance between the parameters of consecutive jobs in the parameters buffer.
+    params_stride: usize,
     /// Compute pipeline
     pipeline: ComputePipeline,
     /// Shader data binding
@@ -63,32 +132,81 @@ struct Params {
     /// Y offset.
     /// This value is added to the trace samples before rendering.
     offset: f32,
+    /// Encoding of the samples in the trace buffer: 0 for f32, 1 for i8 and 2 for i16 codes,
+    /// packed in little-endian order.
+    encoding: u32,
+    /// Scale of the codes of quantized samples.
+    code_scale: f32,
+    /// Offset of the codes of quantized samples.
+    code_offset: f32,
+    /// Position in the input buffer of the first sample of the chunk, in samples.
+    first: u32,
+    /// Number of samples the input buffer can hold.
+    capacity: u32,
+    /// Position in the output buffer of the first pixel of the job.
+    output_offset: u32,
 }
 
-impl GpuRenderer {
-    pub fn new() -> Self {
-        let instance = Instance::new(&InstanceDescriptor::default());
-        let mut adapters: Vec<_> = instance.enumerate_adapters(Backends::PRIMARY);
-        // There can be multiple adapters, we don't want to select a Cpu adapter if a Gpu one is
-        // available. We sort them and select the best.
-        adapters.sort_by_key(|x| match x.get_info().device_type {
+/// Samples held in the input buffer of [`GpuRenderer`].
+struct Resident {
+    /// Source of the samples, see [`ChunkOrigin::source`].
+    source: (u64, u32),
+    /// Range of the words of the samples held in the buffer, whose samples are all known.
+    words: Range<usize>,
+}
+
+/// Returns the best adapter supporting compute shaders: a discrete GPU if any, then an
+/// integrated one, and so on.
+///
+/// In the browser, the renderers cannot wait for the GPU, as WebGPU buffers are only mapped
+/// asynchronously, so no adapter is ever returned.
+fn compute_adapter(instance: &Instance) -> Option<wgpu::Adapter> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let adapters = instance.enumerate_adapters(wgpu::Backends::PRIMARY);
+    #[cfg(target_arch = "wasm32")]
+    let adapters: Vec<wgpu::Adapter> = {
+        let _ = instance;
+        Vec::new()
+    };
+    adapters
+        .into_iter()
+        .filter(|x| {
+            x.get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        })
+        .min_by_key(|x| match x.get_info().device_type {
             wgpu::DeviceType::Other => 4,
             wgpu::DeviceType::IntegratedGpu => 1,
             wgpu::DeviceType::DiscreteGpu => 0,
             wgpu::DeviceType::VirtualGpu => 3,
             wgpu::DeviceType::Cpu => 2,
-        });
-        let adapter = adapters[0].clone();
-        println!("Running on Adapter: {:#?}", adapter.get_info());
-
-        // Check that the adapter support compute shaders
-        let downlevel_capabilities = adapter.get_downlevel_capabilities();
-        if !downlevel_capabilities
-            .flags
-            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
-        {
-            panic!("Adapter does not support compute shaders");
-        }
+        })
+}
+
+impl GpuRenderer {
+    /// Returns true if an adapter supporting compute shaders is available, in which case
+    /// [`GpuRenderer::new`] can be called.
+    pub fn is_available() -> bool {
+        compute_adapter(&Instance::new(&InstanceDescriptor::default())).is_some()
+    }
+
+    /// Creates a renderer on the best available adapter.
+    ///
+    /// Panics if no adapter supports compute shaders, see [`GpuRenderer::is_available`].
+    pub fn new() -> Self {
+        let instance = Instance::new(&InstanceDescriptor::default());
+        let adapter =
+            compute_adapter(&instance).expect("No adapter supporting compute shaders found");
+        let info = adapter.get_info();
+        tracing::info!(
+            target: "renderer",
+            "Running on adapter {} ({:?}, {:?})",
+            info.name,
+            info.device_type,
+            info.backend
+        );
+        tracing::debug!(target: "renderer", "Adapter details: {:#?}", info);
 
         // Create the device and processing queue.
         let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
@@ -103,13 +221,6 @@ impl GpuRenderer {
         let trace_buffer_size = (RENDERER_MAX_TRACE_SIZE * 4) as u64;
         let pixel_buffer_size = (RENDERER_MAX_PIXELS * 4) as u64;
 
-        let download_input_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("download_input_buffer"),
-            size: trace_buffer_size,
-            usage: BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-
         let input_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("input_buffer"),
             size: trace_buffer_size,
@@ -124,16 +235,13 @@ impl GpuRenderer {
             mapped_at_creation: false,
         });
 
-        let download_output_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("download_output_buffer"),
-            size: pixel_buffer_size,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let download_buffers = Mutex::new(Vec::new());
 
+        let params_stride = size_of::<Params>()
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as usize);
         let params_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("params_buffer"),
-            size: size_of::<Params>() as u64,
+            size: (RENDERER_MAX_BATCH * params_stride) as u64,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -175,7 +283,7 @@ impl GpuRenderer {
                     visibility: ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
+                        has_dynamic_offset: true,
                         min_binding_size: Some(
                             NonZeroU64::new(size_of::<Params>() as u64).unwrap(),
                         ),
@@ -214,7 +322,11 @@ impl GpuRenderer {
                 },
                 BindGroupEntry {
                     binding: 2,
-                    resource: params_buffer.as_entire_binding(),
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &params_buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(size_of::<Params>() as u64),
+                    }),
                 },
             ],
         });
@@ -222,116 +334,353 @@ impl GpuRenderer {
         Self {
             device,
             queue,
-            download_input_buffer,
             input_buffer,
+            resident: Mutex::new(None),
             output_buffer,
-            download_output_buffer,
+            download_buffers,
             params_buffer,
+            params_stride,
             pipeline,
             bind_group,
         }
     }
 
-    /// Wait for the GPU to finish work that has been submitted.
-    fn wait(&self) {
-        self.device
-            .poll(wgpu::PollType::wait_indefinitely())
-            .unwrap();
+    /// Writes the words of the samples of the chunks of `traces` which are not held in the input
+    /// buffer yet, keeping the other ones for the next chunks of their source. The chunks are
+    /// taken from the same source, and span less than the capacity of the buffer. Returns the
+    /// position of the first sample of each chunk in the buffer, and the number of samples the
+    /// buffer can hold.
+    fn load_traces(&self, traces: &[(TraceChunk, Option<ChunkOrigin>)]) -> (Vec<u32>, u32) {
+        let per_word = samples_per_word(traces[0].0);
+        let sample_size = 4 / per_word;
+        let capacity = RENDERER_MAX_TRACE_SIZE;
+        let source = traces[0].1.map(|origin| origin.source);
+        // Chunks sorted by position, with their samples as bytes.
+        let mut chunks: Vec<(Range<usize>, &[u8])> = traces
+            .iter()
+            .map(|(trace, origin)| {
+                let bytes: &[u8] = match trace {
+                    TraceChunk::F32(samples) => bytemuck::cast_slice(samples),
+                    TraceChunk::I8 { codes, .. } => bytemuck::cast_slice(codes),
+                    TraceChunk::I16 { codes, .. } => bytemuck::cast_slice(codes),
+                };
+                let start = origin.map_or(0, |origin| origin.start);
+                (start..start + trace.len(), bytes)
+            })
+            .collect();
+        chunks.sort_by_key(|(samples, _)| samples.start);
+        // The first and last words of the chunks may hold samples out of them, which are
+        // unknown. The chunks sharing words are written together.
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        let mut known: Vec<Range<usize>> = Vec::new();
+        let mut covered: Option<Range<usize>> = None;
+        for (samples, _) in &chunks {
+            let words = samples.start / per_word..samples.end.div_ceil(per_word);
+            match runs.last_mut() {
+                Some(run) if words.start < run.end => run.end = run.end.max(words.end),
+                _ => runs.push(words),
+            }
+            match &mut covered {
+                Some(covered) if samples.start <= covered.end => {
+                    covered.end = covered.end.max(samples.end)
+                }
+                _ => {
+                    known.extend(covered.replace(samples.clone()));
+                }
+            }
+        }
+        known.extend(covered);
+        let mut known: Vec<Range<usize>> = known
+            .into_iter()
+            .map(|samples| samples.start.div_ceil(per_word)..samples.end / per_word)
+            .filter(|words| !words.is_empty())
+            .collect();
+        let needed = runs[0].start..runs.last().unwrap().end;
+        assert!(needed.len() <= capacity);
+        let mut resident = self.resident.lock().unwrap();
+        let kept = match &*resident {
+            // Words too far from the chunks are overwritten by their words.
+            Some(resident) if Some(resident.source) == source => {
+                resident
+                    .words
+                    .start
+                    .max(needed.end.saturating_sub(capacity))
+                    ..resident.words.end.min(needed.start + capacity)
+            }
+            _ => needed.start..needed.start,
+        };
+        let uploads = runs.iter().flat_map(|run| {
+            if kept.is_empty() {
+                [run.clone(), run.end..run.end]
+            } else {
+                [
+                    run.start..kept.start.clamp(run.start, run.end),
+                    kept.end.clamp(run.start, run.end)..run.end,
+                ]
+            }
+        });
+        for words in uploads.filter(|words| !words.is_empty()) {
+            // Samples out of the chunks are left to zero.
+            let mut data = vec![0; words.len() * 4];
+            let first = words.start * per_word;
+            let last = words.end * per_word;
+            for (samples, bytes) in &chunks {
+                let (from, to) = (samples.start.max(first), samples.end.min(last));
+                if from < to {
+                    let position = (from - first) * sample_size;
+                    data[position..position + (to - from) * sample_size].copy_from_slice(
+                        &bytes[(from - samples.start) * sample_size
+                            ..(to - samples.start) * sample_size],
+                    );
+                }
+            }
+            // The words wrap around the end of the buffer.
+            let split = (capacity - words.start % capacity).min(words.len()) * 4;
+            self.queue.write_buffer(
+                &self.input_buffer,
+                (words.start % capacity * 4) as u64,
+                &data[..split],
+            );
+            if split < data.len() {
+                self.queue
+                    .write_buffer(&self.input_buffer, 0, &data[split..]);
+            }
+        }
+        // Only one range of known words is remembered, the longest one once merged with the
+        // kept words.
+        known.push(kept);
+        known.sort_by_key(|words| words.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for words in known.into_iter().filter(|words| !words.is_empty()) {
+            match merged.last_mut() {
+                Some(last) if words.start <= last.end => last.end = last.end.max(words.end),
+                _ => merged.push(words),
+            }
+        }
+        *resident = source.map(|source| Resident {
+            source,
+            words: merged
+                .into_iter()
+                .max_by_key(|words| words.len())
+                .unwrap_or(0..0),
+        });
+        let capacity = capacity * per_word;
+        let first = traces
+            .iter()
+            .map(|(_, origin)| (origin.map_or(0, |origin| origin.start) % capacity) as u32)
+            .collect();
+        (first, capacity as u32)
     }
+}
 
-    /// Load trace data in the download input buffer.
-    fn load_trace(&self, trace: &[f32]) {
-        assert!(trace.len() <= RENDERER_MAX_TRACE_SIZE);
-        let slice = self.download_input_buffer.slice(..);
-        slice.map_async(MapMode::Write, |_| {});
-        self.wait();
-        let mut data = slice.get_mapped_range_mut();
-        let data_f32 = bytemuck::cast_slice_mut(&mut data);
-        data_f32[0..trace.len()].copy_from_slice(trace);
-        drop(data);
-        self.download_input_buffer.unmap();
+impl Default for GpuRenderer {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    /// Copy result buffer
-    pub fn read_result(&self, dst: &mut [u32]) {
-        let buffer_slice = self.download_output_buffer.slice(..);
-        buffer_slice.map_async(MapMode::Read, |_| {});
-        self.wait();
-        let data = buffer_slice.get_mapped_range();
-        let data_u32 = bytemuck::cast_slice(&data);
-        dst.copy_from_slice(&data_u32[0..dst.len()]);
-        drop(data);
-        self.download_output_buffer.unmap();
+/// Returns the number of samples of `trace` held by each word of the input buffer of
+/// [`GpuRenderer`].
+fn samples_per_word(trace: TraceChunk) -> usize {
+    match trace {
+        TraceChunk::F32(_) => 1,
+        TraceChunk::I8 { .. } => 4,
+        TraceChunk::I16 { .. } => 2,
     }
 }
 
-impl Renderer for GpuRenderer {
-    fn render(
-        &self,
-        chunk_samples: u32,
-        trace: &[f32],
-        w: u32,
-        h: u32,
-        offset: f32,
-        scale_y: f32,
-    ) -> Vec<u32> {
-        debug_assert!(trace.len() >= 2);
-        self.load_trace(trace);
+impl GpuRenderer {
+    /// Submits the rendering of jobs of the same source, one dispatch per job.
+    fn submit(&self, jobs: &[RenderJob]) -> Submission<'_> {
+        let traces: Vec<(TraceChunk, Option<ChunkOrigin>)> =
+            jobs.iter().map(|job| (job.trace, job.origin)).collect();
+        let (first, capacity) = self.load_traces(&traces);
 
         // The command encoder allows us to record commands that we will later submit to the GPU.
         let mut commands = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        commands.copy_buffer_to_buffer(
-            &self.download_input_buffer,
-            0,
-            &self.input_buffer,
-            0,
-            (trace.len() * 4) as u64,
-        );
-
         let mut compute_pass = commands.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: None,
             timestamp_writes: None,
         });
 
         compute_pass.set_pipeline(&self.pipeline);
-        compute_pass.set_bind_group(0, &self.bind_group, &[]);
 
-        let pixel_count = w * h;
-        let workgroup_count = pixel_count.div_ceil(RENDERER_WORKGROUP_SIZE as u32);
-        compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+        let mut output_offset = 0;
+        for (i, (job, first)) in jobs.iter().zip(first).enumerate() {
+            debug_assert!(job.trace.len() >= 2);
+            let (encoding, code_scale, code_offset) = match job.trace {
+                TraceChunk::F32(_) => (0, 1.0, 0.0),
+                TraceChunk::I8 { scale, offset, .. } => (1, scale, offset),
+                TraceChunk::I16 { scale, offset, .. } => (2, scale, offset),
+            };
+            let pixel_count = job.w * job.h;
+            let params = Params {
+                chunk_samples: job.chunk_samples,
+                trace_samples: job.trace.len() as u32,
+                pixel_count,
+                w: job.w,
+                h: job.h,
+                scale_y: job.scale_y,
+                offset: job.offset,
+                encoding,
+                code_scale,
+                code_offset,
+                first,
+                capacity,
+                output_offset,
+            };
+            let params_offset = i * self.params_stride;
+            self.queue.write_buffer(
+                &self.params_buffer,
+                params_offset as u64,
+                bytemuck::cast_slice(&[params]),
+            );
+            compute_pass.set_bind_group(0, &self.bind_group, &[params_offset as u32]);
+            let workgroup_count = pixel_count.div_ceil(RENDERER_WORKGROUP_SIZE as u32);
+            compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+            output_offset += pixel_count;
+        }
         drop(compute_pass); // Get back access to commands encoder
 
-        commands.copy_buffer_to_buffer(
-            &self.output_buffer,
-            0,
-            &self.download_output_buffer,
-            0,
-            (pixel_count * 4) as u64,
-        );
+        let download_buffer = self.download_buffers.lock().unwrap().pop();
+        let download_buffer = download_buffer.unwrap_or_else(|| {
+            self.device.create_buffer(&BufferDescriptor {
+                label: Some("download_buffer"),
+                size: (RENDERER_MAX_PIXELS * 4) as u64,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+        let size = (output_offset * 4) as u64;
+        commands.copy_buffer_to_buffer(&self.output_buffer, 0, &download_buffer, 0, size);
+
+        let index = self.queue.submit([commands.finish()]);
+
+        // The buffer is mapped once the submission has completed, without waiting for it.
+        let (sender, mapped) = mpsc::channel();
+        download_buffer
+            .slice(..size)
+            .map_async(MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        Submission {
+            renderer: self,
+            index,
+            download_buffer,
+            size,
+            pixel_counts: jobs.iter().map(|job| (job.w * job.h) as usize).collect(),
+            mapped,
+        }
+    }
+}
 
-        let command_buffer = commands.finish();
-        let params = Params {
-            chunk_samples,
-            trace_samples: trace.len() as u32,
-            pixel_count,
-            w,
-            h,
-            scale_y,
-            offset,
-        };
-        self.queue
-            .write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
-        self.queue.submit([command_buffer]);
+/// Jobs submitted to the GPU by [`GpuRenderer`], whose densities are read back once rendered.
+pub struct Submission<'a> {
+    renderer: &'a GpuRenderer,
+    index: wgpu::SubmissionIndex,
+    /// Result buffer the densities are copied to, given back to the renderer once read.
+    download_buffer: Buffer,
+    /// Size of the densities in the result buffer, in bytes.
+    size: u64,
+    /// Number of pixels of each job.
+    pixel_counts: Vec<usize>,
+    /// Receives the result of the mapping of the result buffer.
+    mapped: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
 
-        let mut result = vec![0; (w * h) as usize];
-        self.read_result(&mut result);
+impl Submission<'_> {
+    /// Waits for the jobs to be rendered and returns their densities.
+    fn wait(self) -> Vec<Vec<u32>> {
+        // Returns once the mapping callback has been called.
+        self.renderer
+            .device
+            .poll(wgpu::PollType::Wait {
+                submission_index: Some(self.index),
+                timeout: None,
+            })
+            .unwrap();
+        self.mapped
+            .recv()
+            .unwrap()
+            .expect("Failed to read the rendered densities");
+        let slice = self.download_buffer.slice(..self.size);
+        let data = slice.get_mapped_range();
+        let mut densities: &[u32] = bytemuck::cast_slice(&data);
+        let result = self
+            .pixel_counts
+            .iter()
+            .map(|&count| {
+                let (data, rest) = densities.split_at(count);
+                densities = rest;
+                data.to_vec()
+            })
+            .collect();
+        drop(data);
+        self.download_buffer.unmap();
+        self.renderer
+            .download_buffers
+            .lock()
+            .unwrap()
+            .push(self.download_buffer);
         result
     }
 }
 
+impl Renderer for GpuRenderer {
+    fn render(&self, job: RenderJob) -> Vec<u32> {
+        self.start_batch(vec![job]).wait().remove(0)
+    }
+
+    fn batch_size(&self) -> usize {
+        RENDERER_MAX_BATCH
+    }
+
+    /// Submits the consecutive jobs of the same source together, as long as their samples and
+    /// pixels fit in the buffers. The jobs without origin are submitted alone.
+    fn start_batch(&self, jobs: Vec<RenderJob>) -> PendingBatch<'_> {
+        let mut submissions = Vec::new();
+        let mut rest = jobs.as_slice();
+        while !rest.is_empty() {
+            let words = |job: &RenderJob| {
+                let per_word = samples_per_word(job.trace);
+                let start = job.origin.map_or(0, |origin| origin.start);
+                start / per_word..(start + job.trace.len()).div_ceil(per_word)
+            };
+            let mut span = words(&rest[0]);
+            let mut pixels = (rest[0].w * rest[0].h) as usize;
+            let mut count = 1;
+            while let Some(job) = rest
+                .get(count)
+                .filter(|_| count < RENDERER_MAX_BATCH && rest[0].origin.is_some())
+            {
+                let job_words = words(job);
+                let same_source = job.origin.map(|origin| origin.source)
+                    == rest[0].origin.map(|origin| origin.source);
+                let span_end = span.end.max(job_words.end);
+                let span_start = span.start.min(job_words.start);
+                let job_pixels = (job.w * job.h) as usize;
+                if !same_source
+                    || span_end - span_start > RENDERER_MAX_TRACE_SIZE
+                    || pixels + job_pixels > RENDERER_MAX_PIXELS
+                {
+                    break;
+                }
+                span = span_start..span_end;
+                pixels += job_pixels;
+                count += 1;
+            }
+            let (submission, next) = rest.split_at(count);
+            submissions.push(self.submit(submission));
+            rest = next;
+        }
+        PendingBatch::Submitted(submissions)
+    }
+}
+
+#[derive(Default)]
 pub struct CpuRenderer {}
 
 impl CpuRenderer {
@@ -341,55 +690,79 @@ impl CpuRenderer {
 }
 
 impl Renderer for CpuRenderer {
-    fn render(
-        &self,
-        chunk_samples: u32,
-        trace: &[f32],
-        w: u32,
-        h: u32,
-        offset: f32,
-        scale_y: f32,
-    ) -> Vec<u32> {
+    fn render(&self, job: RenderJob) -> Vec<u32> {
+        let RenderJob {
+            chunk_samples,
+            trace,
+            w,
+            h,
+            offset,
+            scale_y,
+            ..
+        } = job;
         debug_assert!(trace.len() >= 2);
-        let mut result = vec![0; (w * h) as usize];
-        // Implementation using difference array for fast density calculation.
-        // Optimization suggested by ProgramCrafter in:
-        // https://github.com/Ledger-Donjon/turboplot/issues/3.
-        let h_mid = (h as f32) / 2.0;
-        // Difference array is created once and reused for each pixel column.
-        let mut diff = vec![0i32; h as usize + 1];
-        for x in 0..w {
-            // Reset difference array
-            for v in diff.iter_mut() {
-                *v = 0;
-            }
-            let i_start = trace
-                .len()
-                .sub(2)
-                .min((chunk_samples as usize * x as usize) / w as usize);
-            let i_end = trace
-                .len()
-                .sub(1)
-                .min((chunk_samples as usize * (x as usize + 1)) / w as usize);
-            for i in i_start..i_end {
-                let y0 = h_mid - ((trace[i] + offset) * scale_y);
-                let y1 = h_mid - ((trace[i + 1] + offset) * scale_y);
-                // Sort and round
-                let (y0, y1) = (y0.min(y1).ceil() as i32, y0.max(y1).floor() as i32);
-                // Clamp to image bounds
-                let y0 = y0.clamp(0, (h - 1) as i32) as usize;
-                let y1 = y1.clamp(0, (h - 1) as i32) as usize;
-                diff[y0] += 1;
-                diff[y1 + 1] -= 1;
-            }
+        let params = (chunk_samples, w, h, offset, scale_y);
+        match trace {
+            TraceChunk::F32(samples) => render_cpu(samples, |sample| sample, params),
+            TraceChunk::I8 {
+                codes,
+                scale,
+                offset,
+            } => render_cpu(codes, |code| code as f32 * scale + offset, params),
+            TraceChunk::I16 {
+                codes,
+                scale,
+                offset,
+            } => render_cpu(codes, |code| code as f32 * scale + offset, params),
+        }
+    }
+}
 
-            let mut density = 0i32;
-            for y in 0..h {
-                density += diff[y as usize];
-                debug_assert!(density >= 0);
-                result[(x as i32 * h as i32 + y as i32) as usize] = density as u32;
-            }
+/// Renders the samples converted to floats by `value`, with the `chunk_samples`, `w`, `h`,
+/// `offset` and `scale_y` parameters of a [`RenderJob`].
+fn render_cpu<T: Copy>(
+    trace: &[T],
+    value: impl Fn(T) -> f32,
+    (chunk_samples, w, h, offset, scale_y): (u32, u32, u32, f32, f32),
+) -> Vec<u32> {
+    let mut result = vec![0; (w * h) as usize];
+    // Implementation using difference array for fast density calculation.
+    // Optimization suggested by ProgramCrafter in:
+    // https://github.com/Ledger-Donjon/turboplot/issues/3.
+    let h_mid = (h as f32) / 2.0;
+    // Difference array is created once and reused for each pixel column.
+    let mut diff = vec![0i32; h as usize + 1];
+    for x in 0..w {
+        // Reset difference array
+        for v in diff.iter_mut() {
+            *v = 0;
+        }
+        let i_start = trace
+            .len()
+            .sub(2)
+            .min((chunk_samples as usize * x as usize) / w as usize);
+        let i_end = trace
+            .len()
+            .sub(1)
+            .min((chunk_samples as usize * (x as usize + 1)) / w as usize);
+        for i in i_start..i_end {
+            let y0 = h_mid - ((value(trace[i]) + offset) * scale_y);
+            let y1 = h_mid - ((value(trace[i + 1]) + offset) * scale_y);
+            // Sort and round
+            let (y0, y1) = (y0.min(y1).ceil() as i32, y0.max(y1).floor() as i32);
+            // Clamp to image bounds
+            let y0 = y0.clamp(0, (h - 1) as i32) as usize;
+            let y1 = y1.clamp(0, (h - 1) as i32) as usize;
+            diff[y0] += 1;
+            diff[y1 + 1] -= 1;
+        }
+
+        let mut density = 0i32;
+        for y in 0..h {
+            density += diff[y as usize];
+            debug_assert!(density >= 0);
+            result[(x as i32 * h as i32 + y as i32) as usize] = density as u32;
         }
-        result
     }
+    result
 }
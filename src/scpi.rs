@@ -0,0 +1,376 @@
+//! Live capture from benchtop scopes over SCPI.
+//!
+//! The scope is reached either with the raw SCPI socket of LXI instruments (`scpi://HOST[:PORT]`,
+//! port 5025 by default), or with the VXI-11 protocol (`vxi11://HOST`). The channel is given as
+//! the address path, `CH1` by default: `scpi://192.168.1.10/CH2`.
+//!
+//! Waveforms are read with the Tektronix programming commands (`WFMOutpre?` and `CURVe?`, also
+//! understood by many other scopes), and scaled like the curves of WFM files.
+
+use crate::loaders::{ExplicitFormat, decode_curve};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default port of the raw SCPI socket of LXI instruments.
+pub const SCPI_PORT: u16 = 5025;
+
+/// Timeout of the instrument operations.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Scope address parsed from a live source.
+pub struct ScopeAddress {
+    pub protocol: Protocol,
+    pub host: String,
+    pub port: u16,
+    /// Channel to capture, e.g. `CH1`.
+    pub channel: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Raw SCPI socket.
+    Socket,
+    Vxi11,
+}
+
+impl ScopeAddress {
+    /// Parses `scpi://HOST[:PORT][/CHANNEL]` or `vxi11://HOST[/CHANNEL]`. Returns `None` if
+    /// `source` is not a scope address.
+    pub fn parse(source: &str) -> Option<Self> {
+        let (protocol, rest) = if let Some(rest) = source.strip_prefix("scpi://") {
+            (Protocol::Socket, rest)
+        } else {
+            (Protocol::Vxi11, source.strip_prefix("vxi11://")?)
+        };
+        let (host, channel) = rest.split_once('/').unwrap_or((rest, "CH1"));
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) if protocol == Protocol::Socket => (host, port.parse().ok()?),
+            _ => (host, SCPI_PORT),
+        };
+        Some(Self {
+            protocol,
+            host: host.to_string(),
+            port,
+            channel: channel.to_string(),
+        })
+    }
+}
+
+/// Connection to an instrument.
+pub trait Instrument {
+    /// Sends a command.
+    fn write(&mut self, command: &str) -> io::Result<()>;
+    /// Reads a complete response.
+    fn read(&mut self) -> io::Result<Vec<u8>>;
+
+    /// Sends a query and returns its response.
+    fn query(&mut self, command: &str) -> io::Result<Vec<u8>> {
+        self.write(command)?;
+        self.read()
+    }
+
+    /// Sends a query and parses its response as a number.
+    fn query_f64(&mut self, command: &str) -> io::Result<f64> {
+        let response = self.query(command)?;
+        let text = String::from_utf8_lossy(&response);
+        // Some scopes answer with the header, e.g. ":WFMOUTPRE:YMULT 4.0E-3".
+        let value = text.split_whitespace().last().unwrap_or_default();
+        value.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid response to {}: '{}'", command, text.trim()),
+            )
+        })
+    }
+}
+
+/// Raw SCPI socket. Responses end with a newline, except the definite length blocks which are
+/// read by length since they may contain newlines.
+struct SocketInstrument {
+    reader: BufReader<TcpStream>,
+}
+
+impl SocketInstrument {
+    fn connect(host: &str, port: u16) -> io::Result<Self> {
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+        })
+    }
+}
+
+impl Instrument for SocketInstrument {
+    fn write(&mut self, command: &str) -> io::Result<()> {
+        self.reader
+            .get_mut()
+            .write_all(format!("{}\n", command).as_bytes())
+    }
+
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut first = [0];
+        self.reader.read_exact(&mut first)?;
+        let mut response = first.to_vec();
+        if first[0] == b'#' {
+            let mut digits = [0];
+            self.reader.read_exact(&mut digits)?;
+            let digits = (digits[0] as char).to_digit(10).unwrap_or(0) as usize;
+            let mut len = vec![0; digits];
+            self.reader.read_exact(&mut len)?;
+            let len: usize = String::from_utf8_lossy(&len).parse().unwrap_or(0);
+            let mut data = vec![0; len];
+            self.reader.read_exact(&mut data)?;
+            response.push(b'0' + digits as u8);
+            response.extend(format!("{:0width$}", len, width = digits).bytes());
+            response.extend(data);
+        }
+        // Rest of the response, or terminator of the block.
+        self.reader.read_until(b'\n', &mut response)?;
+        Ok(response)
+    }
+}
+
+/// VXI-11 core channel, with ONC RPC calls over TCP.
+struct Vxi11Instrument {
+    stream: TcpStream,
+    /// Link identifier returned by `create_link`.
+    link: u32,
+    /// Transaction identifier of the last RPC call.
+    xid: u32,
+}
+
+/// RPC program number and version of the VXI-11 core channel.
+const VXI11_CORE: (u32, u32) = (0x0607af, 1);
+/// RPC program number and version of the port mapper.
+const PORTMAPPER: (u32, u32) = (100000, 2);
+
+/// Flag of `device_write`, marking the end of the message.
+const VXI11_END_FLAG: u32 = 8;
+/// Reason of `device_read`, set when the end of the message has been read.
+const VXI11_END_REASON: u32 = 4;
+
+impl Vxi11Instrument {
+    fn connect(host: &str) -> io::Result<Self> {
+        // The port of the core channel is given by the port mapper.
+        let mut mapper = Self::open(host, 111)?;
+        let mut args = Vec::new();
+        for x in [VXI11_CORE.0, VXI11_CORE.1, 6, 0] {
+            args.extend(x.to_be_bytes());
+        }
+        let reply = mapper.call(PORTMAPPER, 3, &args)?;
+        let port = read_u32(&reply, 0)?;
+        if port == 0 || port > u16::MAX as u32 {
+            return Err(io::Error::other("VXI-11 core channel not registered"));
+        }
+
+        let mut instrument = Self::open(host, port as u16)?;
+        let mut args = Vec::new();
+        // Client identifier, no lock, lock timeout.
+        for x in [0, 0, 0] {
+            args.extend(u32::to_be_bytes(x));
+        }
+        write_opaque(&mut args, b"inst0");
+        let reply = instrument.call(VXI11_CORE, 10, &args)?;
+        check_error(&reply)?;
+        instrument.link = read_u32(&reply, 4)?;
+        Ok(instrument)
+    }
+
+    fn open(host: &str, port: u16) -> io::Result<Self> {
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            stream,
+            link: 0,
+            xid: 0,
+        })
+    }
+
+    /// Calls the procedure `procedure` of `program` with the encoded `args`, and returns the
+    /// encoded results.
+    fn call(&mut self, program: (u32, u32), procedure: u32, args: &[u8]) -> io::Result<Vec<u8>> {
+        self.xid = self.xid.wrapping_add(1);
+        let mut message = Vec::new();
+        // Call message, RPC version 2, no credentials and no verifier.
+        for x in [self.xid, 0, 2, program.0, program.1, procedure, 0, 0, 0, 0] {
+            message.extend(x.to_be_bytes());
+        }
+        message.extend(args);
+        // Single fragment record.
+        let mark = 0x8000_0000 | message.len() as u32;
+        self.stream.write_all(&mark.to_be_bytes())?;
+        self.stream.write_all(&message)?;
+
+        let mut reply = Vec::new();
+        loop {
+            let mut mark = [0; 4];
+            self.stream.read_exact(&mut mark)?;
+            let mark = u32::from_be_bytes(mark);
+            let mut fragment = vec![0; (mark & 0x7fff_ffff) as usize];
+            self.stream.read_exact(&mut fragment)?;
+            reply.extend(fragment);
+            if mark & 0x8000_0000 != 0 {
+                break;
+            }
+        }
+        // Transaction id, reply, accepted, verifier flavor and body, success.
+        if read_u32(&reply, 0)? != self.xid
+            || read_u32(&reply, 4)? != 1
+            || read_u32(&reply, 8)? != 0
+        {
+            return Err(io::Error::other("RPC call rejected"));
+        }
+        let verifier_len = read_u32(&reply, 16)? as usize;
+        let status = 20 + verifier_len.next_multiple_of(4);
+        if read_u32(&reply, status)? != 0 {
+            return Err(io::Error::other("RPC call failed"));
+        }
+        Ok(reply[status + 4..].to_vec())
+    }
+}
+
+impl Instrument for Vxi11Instrument {
+    fn write(&mut self, command: &str) -> io::Result<()> {
+        let mut args = Vec::new();
+        let timeout = TIMEOUT.as_millis() as u32;
+        for x in [self.link, timeout, timeout, VXI11_END_FLAG] {
+            args.extend(x.to_be_bytes());
+        }
+        write_opaque(&mut args, format!("{}\n", command).as_bytes());
+        let reply = self.call(VXI11_CORE, 11, &args)?;
+        check_error(&reply)
+    }
+
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut response = Vec::new();
+        let timeout = TIMEOUT.as_millis() as u32;
+        loop {
+            let mut args = Vec::new();
+            // Link, request size, timeouts, flags and termination character.
+            for x in [self.link, 1 << 20, timeout, timeout, 0, 0] {
+                args.extend(x.to_be_bytes());
+            }
+            let reply = self.call(VXI11_CORE, 12, &args)?;
+            check_error(&reply)?;
+            let reason = read_u32(&reply, 4)?;
+            let len = read_u32(&reply, 8)? as usize;
+            let data = reply
+                .get(12..12 + len)
+                .ok_or_else(|| io::Error::other("truncated VXI-11 response"))?;
+            response.extend(data);
+            if reason & VXI11_END_REASON != 0 {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+impl Drop for Vxi11Instrument {
+    fn drop(&mut self) {
+        if self.link != 0 {
+            let _ = self.call(VXI11_CORE, 23, &self.link.to_be_bytes());
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| io::Error::other("truncated RPC reply"))
+}
+
+/// Appends variable length opaque data, padded to a multiple of 4 bytes.
+fn write_opaque(buffer: &mut Vec<u8>, data: &[u8]) {
+    buffer.extend((data.len() as u32).to_be_bytes());
+    buffer.extend(data);
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+/// Checks the error code at the beginning of the results of the VXI-11 procedures.
+fn check_error(reply: &[u8]) -> io::Result<()> {
+    match read_u32(reply, 0)? {
+        0 => Ok(()),
+        e => Err(io::Error::other(format!("VXI-11 error {}", e))),
+    }
+}
+
+/// Returns the data of the definite length block `#<digits><length><data>` of `response`.
+pub fn parse_block(response: &[u8]) -> io::Result<&[u8]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid binary block");
+    let start = response
+        .iter()
+        .position(|&b| b == b'#')
+        .ok_or_else(invalid)?;
+    let digits = (*response.get(start + 1).ok_or_else(invalid)? as char)
+        .to_digit(10)
+        .ok_or_else(invalid)? as usize;
+    let len = response
+        .get(start + 2..start + 2 + digits)
+        .and_then(|len| String::from_utf8_lossy(len).parse::<usize>().ok())
+        .ok_or_else(invalid)?;
+    response
+        .get(start + 2 + digits..start + 2 + digits + len)
+        .ok_or_else(invalid)
+}
+
+/// Connects to the instrument at `address`.
+pub fn connect(address: &ScopeAddress) -> io::Result<Box<dyn Instrument>> {
+    Ok(match address.protocol {
+        Protocol::Socket => Box::new(SocketInstrument::connect(&address.host, address.port)?),
+        Protocol::Vxi11 => Box::new(Vxi11Instrument::connect(&address.host)?),
+    })
+}
+
+/// Selects the whole record of `channel` for [`read_curve`], as signed 16-bit big-endian
+/// integers.
+pub fn select_curve(instrument: &mut dyn Instrument, channel: &str) -> io::Result<()> {
+    instrument.write(&format!("DATA:SOURCE {}", channel))?;
+    instrument.write("DATA:ENCDG RIBINARY")?;
+    instrument.write("WFMOUTPRE:BYT_NR 2")?;
+    let record_length = instrument.query_f64("HORIZONTAL:RECORDLENGTH?")?;
+    instrument.write("DATA:START 1")?;
+    instrument.write(&format!("DATA:STOP {}", record_length as u64))
+}
+
+/// Reads the curve selected by [`select_curve`], and returns it with the sampling rate in MS/s.
+pub fn read_curve(instrument: &mut dyn Instrument) -> io::Result<(Vec<f32>, Option<f32>)> {
+    // The scaling can change between captures if the scope settings are changed.
+    let y_mult = instrument.query_f64("WFMOUTPRE:YMULT?")?;
+    let y_off = instrument.query_f64("WFMOUTPRE:YOFF?")?;
+    let y_zero = instrument.query_f64("WFMOUTPRE:YZERO?")?;
+    let x_incr = instrument.query_f64("WFMOUTPRE:XINCR?")?;
+    let response = instrument.query("CURVE?")?;
+    // value = (raw - YOFF) * YMULT + YZERO
+    let trace = decode_curve(
+        parse_block(&response)?,
+        ExplicitFormat::Int16,
+        false,
+        y_mult,
+        y_zero - y_off * y_mult,
+    );
+    let sampling_rate = (x_incr > 0.0).then(|| (1e-6 / x_incr) as f32);
+    Ok((trace, sampling_rate))
+}
+
+/// Captures waveforms of the scope at `address` every `interval`, and gives each one to
+/// `deliver` with the sampling rate in MS/s. Stops when `deliver` returns false, or on error.
+pub fn acquire(
+    address: &ScopeAddress,
+    interval: Duration,
+    mut deliver: impl FnMut(Vec<f32>, Option<f32>) -> bool,
+) -> io::Result<()> {
+    let mut instrument = connect(address)?;
+    select_curve(instrument.as_mut(), &address.channel)?;
+    loop {
+        let start = Instant::now();
+        let (trace, sampling_rate) = read_curve(instrument.as_mut())?;
+        if !deliver(trace, sampling_rate) {
+            return Ok(());
+        }
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+}
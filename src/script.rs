@@ -0,0 +1,360 @@
+//! Custom processing with external scripts.
+//!
+//! The samples are written to a temporary NumPy file and the script is run as
+//! `python3 script.py INPUT OUTPUT`. The script must save the processed samples as a 1D float
+//! array to `OUTPUT`, which is displayed as a new trace. The sampling rate of the input samples,
+//! in MS/s, is given in the `TURBOPLOT_SAMPLING_RATE` environment variable.
+//!
+//! For instance:
+//!
+//! ```python
+//! import sys
+//! import numpy as np
+//!
+//! trace = np.load(sys.argv[1])
+//! np.save(sys.argv[2], trace - np.median(trace))
+//! ```
+
+use crate::{
+    export::write_npy,
+    processing::{NewTrace, ProcessingJob, Progress},
+    trace::Trace,
+};
+use egui::{Button, Checkbox, Color32, Id, Modal, ProgressBar, TextEdit};
+use egui_file_dialog::{DialogState, FileDialog};
+use npyz::{DType, NpyFile, TypeChar};
+use std::{
+    fs,
+    io::{BufReader, Read},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+/// Interpreter used by default to run the scripts.
+pub const DEFAULT_INTERPRETER: &str = "python3";
+
+/// Period at which the running script is checked for completion or cancellation.
+const POLL_PERIOD: Duration = Duration::from_millis(50);
+
+/// Maximum number of lines of the script error output displayed in the dialog.
+const ERROR_MAX_LINES: usize = 10;
+
+/// Runs `script` with `interpreter` on `samples`, sampled at `sampling_rate` MS/s, and returns
+/// the samples saved by the script. Returns `None` if the processing is cancelled, in which case
+/// the script process is killed.
+pub fn run_script(
+    interpreter: &str,
+    script: &Path,
+    samples: &[f32],
+    sampling_rate: f32,
+    progress: &Progress,
+) -> Option<Result<Vec<f32>, String>> {
+    // The files are written to a private directory, removed with them when dropped, so other
+    // users cannot replace them and several scripts can run at the same time.
+    let dir = match tempfile::Builder::new().prefix("turboplot-").tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Some(Err(format!(
+                "Failed to create a temporary directory: {}",
+                e
+            )));
+        }
+    };
+    let input = dir.path().join("input.npy");
+    let output = dir.path().join("output.npy");
+
+    match write_npy(&input, samples, 0..samples.len()) {
+        Ok(()) => execute(
+            interpreter,
+            script,
+            &input,
+            &output,
+            sampling_rate,
+            progress,
+        ),
+        Err(e) => Some(Err(format!("Failed to write the script input: {}", e))),
+    }
+}
+
+/// Runs the script process and loads its output once it has exited successfully.
+fn execute(
+    interpreter: &str,
+    script: &Path,
+    input: &Path,
+    output: &Path,
+    sampling_rate: f32,
+    progress: &Progress,
+) -> Option<Result<Vec<f32>, String>> {
+    let mut child = match Command::new(interpreter)
+        .arg(script)
+        .arg(input)
+        .arg(output)
+        .env("TURBOPLOT_SAMPLING_RATE", sampling_rate.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Some(Err(format!("Failed to run {}: {}", interpreter, e))),
+    };
+
+    // The error output is read in another thread, otherwise the script would block once the pipe
+    // buffer is full.
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr_reader = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+
+    let status = loop {
+        if progress.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => thread::sleep(POLL_PERIOD),
+            Err(e) => return Some(Err(e.to_string())),
+        }
+    };
+
+    let stderr = stderr_reader.join().unwrap_or_default();
+    if !status.success() {
+        // Only the last lines are displayed in the dialog.
+        tracing::debug!("{} error output:\n{}", script.display(), stderr.trim_end());
+        let lines: Vec<_> = stderr.lines().collect();
+        let tail = lines[lines.len().saturating_sub(ERROR_MAX_LINES)..].join("\n");
+        return Some(Err(format!("Script failed ({})\n{}", status, tail)));
+    }
+    if !stderr.trim().is_empty() {
+        tracing::warn!("{} error output:\n{}", script.display(), stderr.trim_end());
+    }
+    Some(read_samples(output))
+}
+
+/// Loads the 1D float array saved by a script.
+fn read_samples(path: &Path) -> Result<Vec<f32>, String> {
+    let file =
+        fs::File::open(path).map_err(|e| format!("Failed to open the script output: {}", e))?;
+    let npy =
+        NpyFile::new(BufReader::new(file)).map_err(|e| format!("Invalid script output: {}", e))?;
+    if npy.shape().len() != 1 {
+        return Err(format!(
+            "The script output must be a 1D array, got shape {:?}",
+            npy.shape()
+        ));
+    }
+    let size = match npy.dtype() {
+        DType::Plain(dtype) if dtype.type_char() == TypeChar::Float => dtype.num_bytes(),
+        _ => None,
+    };
+    let samples = match size {
+        Some(4) => npy.into_vec::<f32>(),
+        Some(8) => npy
+            .into_vec::<f64>()
+            .map(|v| v.into_iter().map(|x| x as f32).collect()),
+        _ => {
+            return Err(format!(
+                "The script output must be a float32 or float64 array, got {}",
+                npy.dtype().descr()
+            ));
+        }
+    }
+    .map_err(|e| format!("Invalid script output: {}", e))?;
+    if samples.is_empty() {
+        return Err("The script output is empty".to_string());
+    }
+    Ok(samples)
+}
+
+/// Background execution of a script.
+type ScriptJob = ProcessingJob<Result<Vec<f32>, String>>;
+
+/// Result of the script dialog update.
+pub enum ScriptDialogResult {
+    /// The user is still configuring the script, or the script is running.
+    Pending,
+    /// The script has processed the samples.
+    Created(NewTrace),
+    /// Dialog was cancelled.
+    Cancelled,
+}
+
+/// Dialog for processing the trace of a viewer with a script.
+pub struct ScriptDialog {
+    id: Id,
+    /// Program running the script.
+    interpreter: String,
+    script: Option<PathBuf>,
+    /// Current Range selection of the viewer, if any.
+    selection: Option<Range<usize>>,
+    /// When true, only the selected samples are given to the script.
+    selection_only: bool,
+    file_dialog: FileDialog,
+    /// Error message of the last run.
+    error: Option<String>,
+    job: Option<ScriptJob>,
+}
+
+impl ScriptDialog {
+    /// Creates the dialog. The interpreter and the script of the previous run are selected again.
+    pub fn new(ctx: &egui::Context, id: Id, selection: Option<Range<usize>>) -> Self {
+        let (interpreter, script) = ctx
+            .data(|d| d.get_temp::<(String, PathBuf)>(Self::memory_id()))
+            .map(|(interpreter, script)| (interpreter, Some(script)))
+            .unwrap_or_else(|| (DEFAULT_INTERPRETER.to_string(), None));
+        Self {
+            id,
+            interpreter,
+            script,
+            selection_only: selection.is_some(),
+            selection,
+            file_dialog: FileDialog::new(),
+            error: None,
+            job: None,
+        }
+    }
+
+    /// Identifier of the last used interpreter and script in egui memory. Shared between all the
+    /// viewers.
+    fn memory_id() -> Id {
+        Id::new("script_dialog_memory")
+    }
+
+    /// Shows the dialog and returns the user decision. `trace` is the displayed trace of the
+    /// viewer, sampled at `sampling_rate` MS/s, and `label` its label.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        trace: &Arc<Trace>,
+        sampling_rate: f32,
+        label: &str,
+    ) -> ScriptDialogResult {
+        if let Some(job) = &self.job
+            && let Some(result) = job.try_take()
+        {
+            let result = result.and_then(|result| result);
+            self.job = None;
+            match result {
+                Ok(samples) => {
+                    let name = self
+                        .script
+                        .as_ref()
+                        .and_then(|s| s.file_name())
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    return ScriptDialogResult::Created(NewTrace {
+                        label: format!("{}({})", name, label),
+                        trace: samples,
+                        sampling_rate,
+                    });
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        self.file_dialog.update(ctx);
+        if let Some(path) = self.file_dialog.take_picked() {
+            self.script = Some(path);
+            self.error = None;
+        }
+        if *self.file_dialog.state() == DialogState::Open {
+            // The file dialog would be hidden behind the modal.
+            return ScriptDialogResult::Pending;
+        }
+
+        let mut result = ScriptDialogResult::Pending;
+        let response = Modal::new(self.id).show(ctx, |ui| {
+            ui.heading("Run script");
+            ui.add_space(5.0);
+            egui::Grid::new("script_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Interpreter:");
+                    ui.add(TextEdit::singleline(&mut self.interpreter).desired_width(300.0));
+                    ui.end_row();
+                    ui.label("Script:");
+                    ui.horizontal(|ui| {
+                        let text = self
+                            .script
+                            .as_ref()
+                            .map(|s| s.display().to_string())
+                            .unwrap_or_else(|| "None".to_string());
+                        ui.monospace(text);
+                        if ui.button("Browse…").clicked() {
+                            self.file_dialog.pick_file();
+                        }
+                    });
+                    ui.end_row();
+                    ui.label("Input:");
+                    ui.add_enabled(
+                        self.selection.is_some(),
+                        Checkbox::new(&mut self.selection_only, "Selection only"),
+                    );
+                    ui.end_row();
+                });
+            ui.label("The script is called with the input and output .npy file paths.");
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+            ui.add_space(5.0);
+            ui.horizontal(|ui| {
+                if let Some(job) = &self.job {
+                    ui.add(
+                        ProgressBar::new(job.progress())
+                            .desired_width(200.0)
+                            .text("Running…")
+                            .animate(true),
+                    );
+                    if ui.button("Cancel").clicked() {
+                        // Dropping the job kills the script.
+                        self.job = None;
+                    }
+                    return;
+                }
+                let valid = self.script.is_some() && !self.interpreter.trim().is_empty();
+                if ui.add_enabled(valid, Button::new("Run")).clicked() {
+                    self.run(ctx, trace, sampling_rate);
+                }
+                if ui.button("Cancel").clicked() {
+                    result = ScriptDialogResult::Cancelled;
+                }
+            });
+        });
+        if response.should_close() && self.job.is_none() {
+            ScriptDialogResult::Cancelled
+        } else {
+            result
+        }
+    }
+
+    /// Starts the script in background.
+    fn run(&mut self, ctx: &egui::Context, trace: &Arc<Trace>, sampling_rate: f32) {
+        let Some(script) = self.script.clone() else {
+            return;
+        };
+        let interpreter = self.interpreter.trim().to_string();
+        ctx.data_mut(|d| d.insert_temp(Self::memory_id(), (interpreter.clone(), script.clone())));
+        let range = match &self.selection {
+            Some(selection) if self.selection_only => selection.clone(),
+            _ => 0..trace.len(),
+        };
+        let trace = trace.clone();
+        self.error = None;
+        self.job = Some(ProcessingJob::spawn(ctx, move |progress| {
+            run_script(
+                &interpreter,
+                &script,
+                &trace[range],
+                sampling_rate,
+                progress,
+            )
+        }));
+    }
+}
@@ -0,0 +1,115 @@
+//! Settings remembered between launches, in the storage of eframe: the directory of the last
+//! opened files, the recently opened files with their load settings, the last color scale, the
+//! recently used sampling rates and the camera bookmarks of the traces. The window geometry is
+//! restored by eframe itself.
+
+use crate::{
+    camera::{BOOKMARK_SLOTS, Bookmark},
+    input::{FileSettings, TraceArgs},
+    loaders::STDIN_PATH,
+    tiling::ColorScale,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{self, PathBuf};
+
+/// Maximum number of recent sampling rates kept.
+const MAX_SAMPLING_RATES: usize = 5;
+
+/// Maximum number of recent files kept.
+const MAX_RECENT_FILES: usize = 10;
+
+/// Maximum number of traces whose bookmarks are kept.
+const MAX_BOOKMARKED_TRACES: usize = 50;
+
+/// File opened recently, with the load settings it was opened with.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RecentFile {
+    /// Absolute path of the file.
+    pub path: String,
+    pub settings: FileSettings,
+}
+
+/// Camera bookmarks of a trace.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TraceBookmarks {
+    /// Absolute path of the file of the trace, followed by the frame index for multi-trace
+    /// files.
+    pub trace: String,
+    pub slots: [Option<Bookmark>; BOOKMARK_SLOTS],
+}
+
+/// Settings of the previous launches.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Directory of the last files opened with the file manager.
+    pub last_directory: Option<PathBuf>,
+    /// Recently opened files, most recent first.
+    pub recent_files: Vec<RecentFile>,
+    /// Color scale of the first viewer when the application was last closed.
+    pub color_scale: Option<ColorScale>,
+    /// Recently used sampling rates in MS/s, most recent first.
+    pub sampling_rates: Vec<f32>,
+    /// Camera bookmarks of the traces, most recently viewed first.
+    pub bookmarks: Vec<TraceBookmarks>,
+}
+
+impl Settings {
+    const KEY: &str = "settings";
+
+    /// Reads the settings saved in `storage`, or returns the default ones.
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        storage
+            .and_then(|storage| eframe::get_value(storage, Self::KEY))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, Self::KEY, self);
+    }
+
+    /// Moves `rate` to the front of the recent sampling rates.
+    pub fn add_sampling_rate(&mut self, rate: f32) {
+        self.sampling_rates.retain(|&r| r != rate);
+        self.sampling_rates.insert(0, rate);
+        self.sampling_rates.truncate(MAX_SAMPLING_RATES);
+    }
+
+    /// Moves `paths` to the front of the recent files, with their load settings in `args`.
+    pub fn add_recent_files(&mut self, paths: &[String], args: &TraceArgs) {
+        for (i, path) in paths.iter().enumerate().rev() {
+            // The standard input cannot be opened again.
+            if path == STDIN_PATH {
+                continue;
+            }
+            let path = path::absolute(path)
+                .map_or_else(|_| path.clone(), |path| path.to_string_lossy().to_string());
+            self.recent_files.retain(|file| file.path != path);
+            self.recent_files.insert(
+                0,
+                RecentFile {
+                    path,
+                    settings: FileSettings::new(&args.file(i)),
+                },
+            );
+        }
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
+
+    /// Returns the bookmarks of `trace`, if any.
+    pub fn bookmarks(&self, trace: &str) -> Option<&[Option<Bookmark>; BOOKMARK_SLOTS]> {
+        self.bookmarks
+            .iter()
+            .find(|b| b.trace == trace)
+            .map(|b| &b.slots)
+    }
+
+    /// Moves the bookmarks of `trace` to the front, or forgets them when all the slots are empty.
+    pub fn set_bookmarks(&mut self, trace: String, slots: [Option<Bookmark>; BOOKMARK_SLOTS]) {
+        self.bookmarks.retain(|b| b.trace != trace);
+        if slots.iter().any(Option::is_some) {
+            self.bookmarks.insert(0, TraceBookmarks { trace, slots });
+            self.bookmarks.truncate(MAX_BOOKMARKED_TRACES);
+        }
+    }
+}
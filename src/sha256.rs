@@ -0,0 +1,40 @@
+//! SHA-256 hash of source files, identifying the exact capture an export refers to.
+
+use crate::processing::Progress;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
+/// Size of the blocks read from the hashed files.
+const READ_SIZE: usize = 1 << 20;
+
+/// Calculates the SHA-256 hash of the file at `path`, as a lowercase hexadecimal string. The read
+/// fraction is reported to `progress`, and `None` is returned if the calculation is cancelled.
+pub fn hash_file(path: &str, progress: &Progress) -> Option<io::Result<String>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Some(Err(e)),
+    };
+    let size = file.metadata().map_or(0, |m| m.len()).max(1);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; READ_SIZE];
+    let mut read = 0u64;
+    loop {
+        if progress.is_cancelled() {
+            return None;
+        }
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buffer[..n]);
+                read += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Some(Err(e)),
+        }
+        progress.set(read as f32 / size as f32);
+    }
+    Some(Ok(format!("{:x}", hasher.finalize())))
+}
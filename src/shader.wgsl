@@ -6,11 +6,21 @@ struct Params {
     w: u32,
     h: u32,
     scale_y: f32,
-    offset: f32
+    offset: f32,
+    // 0 for f32 samples, 1 for i8 and 2 for i16 codes, packed in little-endian order.
+    encoding: u32,
+    code_scale: f32,
+    code_offset: f32,
+    // Position in the input buffer of the first sample of the chunk, in samples.
+    first: u32,
+    // Number of samples the input buffer can hold. The samples wrap around its end.
+    capacity: u32,
+    // Position in the output buffer of the first pixel of the job.
+    output_offset: u32
 };
 
 @group(0) @binding(0)
-var<storage, read> input: array<f32>;
+var<storage, read> input: array<u32>;
 
 @group(0) @binding(1)
 var<storage, read_write> output: array<u32>;
@@ -18,6 +28,24 @@ var<storage, read_write> output: array<u32>;
 @group(0) @binding(2)
 var<uniform> params: Params;
 
+// Returns the trace sample at index i, converting the codes of quantized traces.
+fn sample(index: u32) -> f32 {
+    let i = (params.first + index) % params.capacity;
+    switch params.encoding {
+        case 1u: {
+            let code = extractBits(bitcast<i32>(input[i / 4u]), (i % 4u) * 8u, 8u);
+            return f32(code) * params.code_scale + params.code_offset;
+        }
+        case 2u: {
+            let code = extractBits(bitcast<i32>(input[i / 2u]), (i % 2u) * 16u, 16u);
+            return f32(code) * params.code_scale + params.code_offset;
+        }
+        default: {
+            return bitcast<f32>(input[i]);
+        }
+    }
+}
+
 @compute @workgroup_size(64)
 fn render(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let index = global_id.x;
@@ -42,8 +70,8 @@ fn render(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // ordinate range of the segment.
     //
     // for (var i = i_start; i < i_end; i++) {
-    //     let p0 = input[i];
-    //     let p1 = input[i + 1];
+    //     let p0 = sample(i);
+    //     let p1 = sample(i + 1);
     //     if (((y >= p0) && (y <= p1)) || ((y >= p1) && (y <= p0))) {
     //         density += 1;
     //     }
@@ -52,11 +80,11 @@ fn render(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // Some calculation can be kept for the next loop, leading to the following faster
     // implementation below.
 
-    var p0 = (input[i_start] + params.offset) * params.scale_y;
+    var p0 = (sample(i_start) + params.offset) * params.scale_y;
     var ca0 = (y <= p0);
     var cb0 = (y >= p0);
     for (var i = i_start; i < i_end; i+=1) {
-        let p1 = (input[i + 1] + params.offset) * params.scale_y;
+        let p1 = (sample(i + 1) + params.offset) * params.scale_y;
         let ca1 = (y <= p1);
         let cb1 = (y >= p1);
         // Cast from bool to u32 is slightly faster than doing conditional incrementation.
@@ -70,8 +98,8 @@ fn render(@builtin(global_invocation_id) global_id: vec3<u32>) {
     // Unfortunately it does not appear to be any faster than the other implementations.
     //
     // for (var i = i_start; i < i_end; i++) {
-    //     var p0 = input[i];
-    //     let p1 = input[i + 1];
+    //     var p0 = sample(i);
+    //     let p1 = sample(i + 1);
     //     let k = vec4(y, p0, y, p1);
     //     let l = vec4(p1, y, p0, y);
     //     let m = step(k, l);
@@ -79,5 +107,5 @@ fn render(@builtin(global_invocation_id) global_id: vec3<u32>) {
     //     density += u32(inside);
     // }
 
-    output[index] = density;
+    output[params.output_offset + index] = density;
 }
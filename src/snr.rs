@@ -0,0 +1,100 @@
+//! Signal-to-noise ratio of a labeled trace set.
+//!
+//! The traces of a campaign are grouped by the value of a variable processed by the target, such
+//! as a byte of the plaintext. The SNR of a time sample is the variance of the group means divided
+//! by the mean variance within the groups: it is high where the samples depend on the variable,
+//! which locates the points of interest of a side-channel attack.
+
+use crate::{loaders::TrsFile, overlay::load_overlay};
+use std::collections::HashMap;
+
+/// Prefix of the label sources reading a byte of the TRS trace data.
+const DATA_PREFIX: &str = "data:";
+
+/// Loads the label of each of `count` frames.
+///
+/// `source` is either the path of a 1D array with one label per frame, in one of the trace
+/// formats, or `data:N` to use the byte at offset `N` of the data of each trace of the TRS
+/// `file`. Array values are rounded to integers.
+pub fn load_labels(source: &str, count: usize, file: Option<&TrsFile>) -> Result<Vec<i64>, String> {
+    let labels: Vec<i64> = if let Some(offset) = source.strip_prefix(DATA_PREFIX) {
+        let offset: usize = offset
+            .parse()
+            .map_err(|_| format!("Invalid data byte offset '{}'", offset))?;
+        let file = file.ok_or("Labels from trace data require a TRS file")?;
+        if offset >= file.header.data_len {
+            return Err(format!(
+                "Data byte {} out of the {} data bytes of the traces",
+                offset, file.header.data_len
+            ));
+        }
+        (0..count)
+            .map(|i| {
+                file.read_data(i)
+                    .map(|data| data[offset] as i64)
+                    .map_err(|e| format!("Failed to read the data of trace {}: {}", i, e))
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        load_overlay(source)?
+            .iter()
+            .map(|&x| x.round() as i64)
+            .collect()
+    };
+    if labels.len() < count {
+        return Err(format!(
+            "{} labels for {} traces: each trace needs a label",
+            labels.len(),
+            count
+        ));
+    }
+    Ok(labels)
+}
+
+/// Calculates the SNR of each sample of `traces`, grouped by `labels`.
+///
+/// Traces of different lengths are truncated to the shortest one. The noise is the total variance
+/// minus the variance of the group means, so only the group sums have to be kept in memory.
+/// Samples without noise have a NaN SNR.
+pub fn snr(traces: &[&[f32]], labels: &[i64]) -> Vec<f32> {
+    let len = traces.iter().map(|t| t.len()).min().unwrap_or(0);
+    let mut groups: HashMap<i64, usize> = HashMap::new();
+    let mut sums: Vec<Vec<f64>> = Vec::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut total = vec![0.0f64; len];
+    let mut total2 = vec![0.0f64; len];
+    for (trace, label) in traces.iter().zip(labels) {
+        let group = *groups.entry(*label).or_insert_with(|| {
+            sums.push(vec![0.0; len]);
+            counts.push(0);
+            sums.len() - 1
+        });
+        counts[group] += 1;
+        for (i, &x) in trace[..len].iter().enumerate() {
+            let x = x as f64;
+            sums[group][i] += x;
+            total[i] += x;
+            total2[i] += x * x;
+        }
+    }
+
+    let n = traces.len().min(labels.len()) as f64;
+    (0..len)
+        .map(|i| {
+            let mean = total[i] / n;
+            let variance = total2[i] / n - mean * mean;
+            let signal = sums
+                .iter()
+                .zip(&counts)
+                .map(|(sum, &count)| count as f64 * (sum[i] / count as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            let noise = variance - signal;
+            if noise > 0.0 {
+                (signal / noise) as f32
+            } else {
+                f32::NAN
+            }
+        })
+        .collect()
+}
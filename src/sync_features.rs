@@ -5,22 +5,44 @@ pub struct SyncFeatures {
     pub shift_y: bool,
     pub scale_x: bool,
     pub scale_y: bool,
+    /// Active tool of the viewers.
+    pub tool: bool,
+    /// Range and Count selections.
+    pub selection: bool,
+    /// Labeled regions.
+    pub markers: bool,
+    /// Applies the camera changes to the other viewers instead of copying the camera, so that
+    /// they keep their own shifts and scales. This is a mode of the camera options, which is not
+    /// changed by [`SyncFeatures::set_all`].
+    pub relative: bool,
 }
 
 impl SyncFeatures {
-    /// Create a `SyncOptions` with all options enabled by default.
+    /// Create a `SyncOptions` with the camera options enabled by default. The tools and markers
+    /// of each viewer are independent.
     pub fn new() -> Self {
         Self {
             shift_x: true,
             shift_y: true,
             scale_x: true,
             scale_y: true,
+            tool: false,
+            selection: false,
+            markers: false,
+            relative: false,
         }
     }
 
-    /// Returns `true` if a least one option is enabled.
+    /// Returns `true` if a least one option is enabled. The relative mode alone is not an
+    /// option.
     pub fn any(&self) -> bool {
-        self.shift_x || self.shift_y || self.scale_x || self.scale_y
+        self.shift_x
+            || self.shift_y
+            || self.scale_x
+            || self.scale_y
+            || self.tool
+            || self.selection
+            || self.markers
     }
 
     /// Sets all options to `true` or `false`.
@@ -29,6 +51,9 @@ impl SyncFeatures {
         self.shift_y = value;
         self.scale_x = value;
         self.scale_y = value;
+        self.tool = value;
+        self.selection = value;
+        self.markers = value;
     }
 }
 
@@ -41,6 +66,10 @@ impl std::ops::Not for SyncFeatures {
             shift_y: !self.shift_y,
             scale_x: !self.scale_x,
             scale_y: !self.scale_y,
+            tool: !self.tool,
+            selection: !self.selection,
+            markers: !self.markers,
+            relative: !self.relative,
         }
     }
 }
@@ -54,6 +83,10 @@ impl std::ops::BitAnd for SyncFeatures {
             shift_y: self.shift_y && rhs.shift_y,
             scale_x: self.scale_x && rhs.scale_x,
             scale_y: self.scale_y && rhs.scale_y,
+            tool: self.tool && rhs.tool,
+            selection: self.selection && rhs.selection,
+            markers: self.markers && rhs.markers,
+            relative: self.relative && rhs.relative,
         }
     }
 }
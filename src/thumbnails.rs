@@ -0,0 +1,84 @@
+//! Batch rendering of thumbnails, for triaging many capture files without opening them.
+//!
+//! `turboplot thumbnails <FILES>... --out <DIR>` renders the first selected trace of each file as
+//! a small density image with the CPU renderer, after the processing steps given on the command
+//! line, and writes it as `<DIR>/<file path>.png`, the path being relative to the deepest
+//! directory containing all the files.
+
+use crate::{batch::load_trace, export::write_png, input::ThumbnailArgs, render::render_trace};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Renders the thumbnails of the files of `thumbnails.patterns`, whose wildcards and directories
+/// have been expanded with the arguments. Returns the process exit code: non-zero if a file could
+/// not be rendered.
+pub fn run(thumbnails: &ThumbnailArgs) -> i32 {
+    let paths = &thumbnails.patterns;
+    if paths.is_empty() {
+        eprintln!("No file matches the given patterns.");
+        return 1;
+    }
+    let mut failed = 0;
+    for (path, output) in paths.iter().zip(output_paths(&thumbnails.out, paths)) {
+        let created = output.parent().map_or(Ok(()), fs::create_dir_all);
+        if let Err(e) = created {
+            eprintln!("Failed to create {}: {}", output.display(), e);
+            failed += 1;
+            continue;
+        }
+        match render(thumbnails, path, &output) {
+            Ok(()) => println!("{} -> {}", path, output.display()),
+            Err(e) => {
+                eprintln!("Failed to render {}: {}", path, e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "Rendered {} of {} files.",
+        paths.len() - failed,
+        paths.len()
+    );
+    i32::from(failed > 0)
+}
+
+/// Renders the thumbnail of the file at `path` to `output`.
+fn render(thumbnails: &ThumbnailArgs, path: &str, output: &Path) -> Result<(), String> {
+    let trace = load_trace(&thumbnails.trace, path)?;
+    let image = render_trace(
+        &trace,
+        thumbnails.width,
+        thumbnails.height,
+        thumbnails.display.color_scale(),
+    )
+    .ok_or("the trace is too short")?;
+    write_png(output, &image).map_err(|e| e.to_string())
+}
+
+/// Returns the image paths of the thumbnails of `paths` in the `out` directory: the paths of the
+/// files relative to the deepest directory containing them all, followed by `.png`, so files with
+/// the same name in different directories have different thumbnails.
+fn output_paths(out: &Path, paths: &[String]) -> Vec<PathBuf> {
+    let absolute: Vec<PathBuf> = paths
+        .iter()
+        .map(|path| std::path::absolute(path).unwrap_or_else(|_| PathBuf::from(path)))
+        .collect();
+    let mut common = absolute
+        .first()
+        .and_then(|path| path.parent())
+        .unwrap_or(Path::new(""));
+    while !absolute.iter().all(|path| path.starts_with(common)) {
+        common = common.parent().unwrap_or(Path::new(""));
+    }
+    absolute
+        .iter()
+        .map(|path| {
+            let relative = path.strip_prefix(common).unwrap_or(path);
+            let mut name = relative.as_os_str().to_owned();
+            name.push(".png");
+            out.join(name)
+        })
+        .collect()
+}
@@ -0,0 +1,311 @@
+//! HTTP server of rendered tiles, so a web frontend or a Jupyter widget can browse a huge trace
+//! rendered on a remote machine, like a slippy map.
+//!
+//! `turboplot serve <FILE>` loads the first selected trace of the file, after the processing
+//! steps given on the command line, and answers:
+//!
+//! - `GET /info`: the metadata of the trace as JSON, for instance
+//!   `{"label":"capture.npy","samples":100000000,"sampling_rate":500,"tile_width":256,
+//!   "tile_height":256,"max_zoom":19}`.
+//! - `GET /tiles/{z}/{x}.png`: the density image of tile `x` at zoom level `z`. At level `z`, the
+//!   trace is split into `2^z` tiles of the same number of samples, from `0` at the first sample
+//!   to `2^z - 1` at the last one. Level 0 is the whole trace in a single tile, and at level
+//!   `max_zoom` a tile column has at most one sample.
+//!
+//! All the tiles share the vertical fit of the whole trace, so they can be put side by side. The
+//! last requested tiles are kept in a cache of `--tile-cache` tiles. At most 16 clients are served
+//! at the same time.
+
+use crate::{
+    batch::load_trace,
+    export::{ImageSettings, encode_png},
+    input::ServeArgs,
+    render::fit_settings,
+    renderer::{CpuRenderer, RenderJob, Renderer},
+    tiling::{TileSize, density_image},
+};
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+/// Time after which a client which does not send its request or read the response is
+/// disconnected.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of the request line and headers, in bytes.
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+
+/// Maximum number of clients served at the same time. The other clients are answered
+/// `503 Service Unavailable`.
+const MAX_CLIENTS: usize = 16;
+
+/// Delay before accepting clients again after a failure, such as too many open files.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Trace served by the tile server, with the settings shared by its tiles.
+struct TileSource {
+    label: String,
+    trace: Vec<f32>,
+    sampling_rate: f32,
+    tile_width: u32,
+    /// Rendering settings of a tile at zoom level 0.
+    settings: ImageSettings,
+    max_zoom: u32,
+    cache: Mutex<TileCache>,
+}
+
+/// Encoded tiles, by zoom level and index, kept so they are not rendered again.
+struct TileCache {
+    tiles: HashMap<(u32, u64), Arc<[u8]>>,
+    /// Keys of `tiles`, from the oldest to the most recent.
+    order: VecDeque<(u32, u64)>,
+    /// Maximum number of tiles kept.
+    size: usize,
+}
+
+impl TileCache {
+    fn new(size: usize) -> Self {
+        Self {
+            tiles: HashMap::new(),
+            order: VecDeque::new(),
+            size,
+        }
+    }
+
+    fn get(&self, key: (u32, u64)) -> Option<Arc<[u8]>> {
+        self.tiles.get(&key).cloned()
+    }
+
+    /// Adds a tile, removing the oldest ones beyond the maximum size.
+    fn insert(&mut self, key: (u32, u64), png: Arc<[u8]>) {
+        if self.size == 0 || self.tiles.insert(key, png).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.size {
+            if let Some(oldest) = self.order.pop_front() {
+                self.tiles.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl TileSource {
+    /// Returns the metadata of the trace, answered to `/info`.
+    fn info(&self) -> Value {
+        json!({
+            "label": self.label,
+            "samples": self.trace.len(),
+            "sampling_rate": self.sampling_rate,
+            "tile_width": self.tile_width,
+            "tile_height": self.settings.height,
+            "max_zoom": self.max_zoom,
+        })
+    }
+
+    /// Returns tile `x` at zoom level `z` as a PNG image, from the cache or rendered. Returns
+    /// `None` if there is no such tile.
+    fn tile(&self, z: u32, x: u64) -> Option<Arc<[u8]>> {
+        if z > self.max_zoom || x >= 1 << z {
+            return None;
+        }
+        if let Some(png) = self.cache.lock().unwrap().get((z, x)) {
+            return Some(png);
+        }
+        let png: Arc<[u8]> = self.render(z, x)?.into();
+        self.cache.lock().unwrap().insert((z, x), png.clone());
+        Some(png)
+    }
+
+    /// Renders tile `x` at zoom level `z`, which must exist, as a PNG image.
+    fn render(&self, z: u32, x: u64) -> Option<Vec<u8>> {
+        let tiles = (1u64 << z) as f64;
+        let len = self.trace.len() as f64;
+        let start = (x as f64 * len / tiles) as usize;
+        let end = ((x + 1) as f64 * len / tiles) as usize;
+        // Include the first sample of the next tile so the last segment is drawn.
+        let chunk = &self.trace[start..(end + 1).min(self.trace.len())];
+        if chunk.len() < 2 {
+            return None;
+        }
+        let samples = (end - start) as u32;
+        let ImageSettings {
+            height,
+            offset,
+            scale_y,
+            color_scale,
+            ..
+        } = self.settings;
+        let data = CpuRenderer::new().render(RenderJob {
+            chunk_samples: samples,
+            trace: chunk.into(),
+            origin: None,
+            w: self.tile_width,
+            h: height,
+            offset,
+            scale_y,
+        });
+        let image = density_image(
+            &data,
+            TileSize::new(self.tile_width, height),
+            samples as f32 / self.tile_width as f32,
+            color_scale,
+        );
+        encode_png(&image).ok()
+    }
+}
+
+/// Serves the tiles of the first selected trace of `args.path` until the process is stopped.
+/// Returns the process exit code, non-zero if the trace cannot be loaded or the address cannot
+/// be listened on.
+pub fn run(args: &ServeArgs) -> i32 {
+    let trace = match load_trace(&args.trace, &args.path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", args.path, e);
+            return 1;
+        }
+    };
+    if trace.len() < 2 || args.tile_width == 0 || args.tile_height == 0 {
+        eprintln!("Error: the trace or the tiles are too small");
+        return 1;
+    }
+    let settings = fit_settings(
+        &trace,
+        args.tile_width,
+        args.tile_height,
+        args.display.color_scale(),
+    );
+    // Smallest level where a tile column has at most one sample.
+    let max_zoom = settings.samples_per_column.max(1.0).log2().ceil() as u32;
+    let source = Arc::new(TileSource {
+        label: args.path.clone(),
+        trace,
+        sampling_rate: args.trace.sampling_rate,
+        tile_width: args.tile_width,
+        settings,
+        max_zoom,
+        cache: Mutex::new(TileCache::new(args.tile_cache)),
+    });
+    let listener = match TcpListener::bind(&args.address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", args.address, e);
+            return 1;
+        }
+    };
+    println!(
+        "Serving {} on http://{}/ ({} samples, zoom levels 0 to {})",
+        args.path,
+        args.address,
+        source.trace.len(),
+        max_zoom
+    );
+    let clients = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Failed to accept a client: {}", e);
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        if clients.fetch_add(1, Ordering::AcqRel) >= MAX_CLIENTS {
+            clients.fetch_sub(1, Ordering::AcqRel);
+            tracing::warn!("Client refused: {} clients already served", MAX_CLIENTS);
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            let _ = respond(stream, Err("503 Service Unavailable"));
+            continue;
+        }
+        let (source, clients) = (source.clone(), clients.clone());
+        thread::spawn(move || {
+            serve(stream, &source);
+            clients.fetch_sub(1, Ordering::AcqRel);
+        });
+    }
+    0
+}
+
+/// Answers a single request of a client, then closes the connection.
+fn serve(stream: TcpStream, source: &TileSource) {
+    let timeouts = stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)));
+    let Ok(reader) = timeouts.and_then(|()| stream.try_clone()) else {
+        return;
+    };
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_SIZE));
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // The headers are not used.
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next().unwrap_or(""));
+    // The query string is ignored.
+    let path = target.split('?').next().unwrap();
+    let response = match (method, path) {
+        // The size limit has been reached before the end of the headers.
+        _ if reader.get_ref().limit() == 0 => Err("431 Request Header Fields Too Large"),
+        (Some("GET"), "/info") => Ok((
+            "application/json",
+            source.info().to_string().into_bytes().into(),
+        )),
+        (Some("GET"), path) => match parse_tile(path) {
+            Some((z, x)) => source
+                .tile(z, x)
+                .map(|png| ("image/png", png))
+                .ok_or("404 Not Found"),
+            None => Err("404 Not Found"),
+        },
+        _ => Err("405 Method Not Allowed"),
+    };
+    tracing::debug!(
+        "{} -> {}",
+        request_line.trim(),
+        response.as_ref().err().unwrap_or(&"200 OK")
+    );
+    let _ = respond(stream, response);
+}
+
+/// Parses the path of a tile, `/tiles/{z}/{x}.png`.
+fn parse_tile(path: &str) -> Option<(u32, u64)> {
+    let (z, x) = path.strip_prefix("/tiles/")?.split_once('/')?;
+    Some((z.parse().ok()?, x.strip_suffix(".png")?.parse().ok()?))
+}
+
+/// Writes the response: the content and its type, or an error status. Browsers of other origins
+/// are allowed to read it.
+fn respond(
+    mut stream: TcpStream,
+    response: Result<(&str, Arc<[u8]>), &str>,
+) -> std::io::Result<()> {
+    let (status, content_type, body) = match response {
+        Ok((content_type, body)) => ("200 OK", content_type, body),
+        Err(status) => (status, "text/plain", status.as_bytes().into()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
@@ -1,54 +1,181 @@
 use crate::{
-    renderer::Renderer,
-    util::{Fixed, FixedVec2},
+    pyramid::{Pyramid, PyramidTile},
+    renderer::{ChunkOrigin, PendingBatch, RenderJob, Renderer},
+    trace::{ChunkSamples, Trace},
+    util::{Fixed, FixedVec2, panic_message},
 };
 use egui::{Color32, ColorImage, epaint::Hsva, lerp};
-use std::sync::{Arc, Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    thread,
+};
+use web_time::Instant;
+
+/// Traces of all the viewers, indexed by viewer identifier.
+///
+/// Shared between the viewers and the tile renderers. A viewer can replace its trace at runtime
+/// (when it is filtered for instance), in which case it must also increment the generation of the
+/// tiles it requests.
+pub type SharedTraces = Arc<RwLock<Vec<Arc<Trace>>>>;
 
 /// A library of tiles and their current rendering status and result.
 ///
 /// This structure is shared between the viewer, which asks for tiles and use them, and a tile
 /// rendered which receives and fulfill rendering requests.
+///
+/// Rendered tiles which are not needed anymore, after zooming for instance, can be kept in a cache
+/// of limited size, so they don't have to be rendered again when coming back to the same view.
+#[derive(Default)]
 pub struct Tiling {
     pub tiles: Vec<Tile>,
+    /// Rendered tiles discarded from `tiles`, from the oldest to the most recent.
+    cache: Vec<Tile>,
+    /// Maximum number of tiles in the cache.
+    cache_size: usize,
+    /// Failures of the renderers not taken by the viewer yet.
+    failures: Vec<RenderFailure>,
+    /// Traces whose rendering has already failed, identified like in [`RenderFailure`]. Only the
+    /// first failure of each trace is reported.
+    failed: HashSet<Option<u32>>,
+}
+
+/// Failure of a renderer, reported to the user by the viewer.
+pub struct RenderFailure {
+    /// Identifier of the trace of the tile which could not be rendered, `None` if the failure is
+    /// not related to a trace.
+    pub id: Option<u32>,
+    pub message: String,
 }
 
 impl Tiling {
     pub fn new() -> Self {
-        Self { tiles: Vec::new() }
+        Self::default()
+    }
+
+    /// Sets the maximum number of discarded tiles kept in the cache. Zero disables the cache.
+    pub fn set_cache_size(&mut self, size: usize) {
+        self.cache_size = size;
+        self.trim_cache();
     }
 
     pub fn get(&mut self, properties: TileProperties, request: bool) -> Option<Tile> {
         if let Some(tile) = self.tiles.iter().find(|x| x.properties == properties) {
             return Some(tile.clone());
         }
-        if request {
-            let tile = Tile::new(properties);
-            self.tiles.push(tile.clone());
-            Some(tile)
-        } else {
-            None
+        if !request {
+            return None;
+        }
+        let tile = match self.cache.iter().position(|x| x.properties == properties) {
+            Some(i) => {
+                tracing::trace!(
+                    target: "tiling",
+                    "Tile {} of trace {} restored from the cache",
+                    properties.index,
+                    properties.id
+                );
+                self.cache.remove(i)
+            }
+            None => Tile::new(properties),
+        };
+        self.tiles.push(tile.clone());
+        Some(tile)
+    }
+
+    /// Retains only the tiles for which `keep` returns true. The other rendered tiles are moved
+    /// to the cache.
+    pub fn retain(&mut self, keep: impl Fn(&Tile) -> bool) {
+        let (kept, discarded) = self.tiles.drain(..).partition(keep);
+        self.tiles = kept;
+        if self.cache_size > 0 {
+            self.cache.extend(
+                discarded
+                    .into_iter()
+                    .filter(|t: &Tile| t.status == TileStatus::Rendered),
+            );
+            self.trim_cache();
         }
     }
 
+    /// Removes the tiles of the trace `id`, and forgets its failures, when its viewer is closed.
+    pub fn remove_trace(&mut self, id: u32) {
+        self.tiles.retain(|t| t.properties.id != id);
+        self.cache.retain(|t| t.properties.id != id);
+        self.failures.retain(|f| f.id != Some(id));
+        self.failed.remove(&Some(id));
+    }
+
+    /// Marks the tiles of the trace `id` covering samples from `start` to be rendered again, after
+    /// samples have been appended to the trace from `start`. The other tiles remain valid. The
+    /// invalidated tiles keep their previous data until they are rendered again, and their
+    /// properties are returned so their textures can be updated.
+    pub fn invalidate_from(&mut self, id: u32, start: usize) -> Vec<TileProperties> {
+        let affected = |p: &TileProperties| p.id == id && p.sample_range().end > start as i64;
+        self.cache.retain(|t| !affected(&t.properties));
+        self.tiles
+            .iter_mut()
+            .filter(|t| affected(&t.properties))
+            .map(|t| {
+                t.status = TileStatus::NotRendered;
+                t.properties
+            })
+            .collect()
+    }
+
+    /// Records a failure of a renderer, unless a failure has already been recorded for the same
+    /// trace.
+    pub fn report_failure(&mut self, id: Option<u32>, message: String) {
+        if self.failed.insert(id) {
+            tracing::error!(target: "tiling", "{}", message);
+            self.failures.push(RenderFailure { id, message });
+        }
+    }
+
+    /// Returns the failures recorded since the last call.
+    pub fn take_failures(&mut self) -> Vec<RenderFailure> {
+        std::mem::take(&mut self.failures)
+    }
+
+    /// Removes the oldest tiles of the cache beyond its maximum size.
+    fn trim_cache(&mut self) {
+        let excess = self.cache.len().saturating_sub(self.cache_size);
+        self.cache.drain(..excess);
+    }
+
     /// Returns true if there is at least one tile which is not rendered.
     pub fn has_pending(&self) -> bool {
         self.tiles.iter().any(|t| t.status != TileStatus::Rendered)
     }
 
-    /// Finds and returns a pending rendering, and tag it has being currently rendered.
-    /// If no pending job is available, `None` is returned.
-    pub fn take_job(&mut self) -> Option<TileProperties> {
-        if let Some(tile) = self
+    /// Finds and returns up to `count` pending renderings, and tag them has being currently
+    /// rendered. The tiles of the same trace as the first one come first, so they can be rendered
+    /// together. If no pending job is available, an empty vector is returned.
+    pub fn take_jobs(&mut self, count: usize) -> Vec<TileProperties> {
+        let Some(id) = self
             .tiles
-            .iter_mut()
+            .iter()
             .find(|t| t.status == TileStatus::NotRendered)
-        {
-            tile.status = TileStatus::Rendering;
-            Some(tile.properties)
-        } else {
-            None
-        }
+            .map(|t| t.properties.id)
+        else {
+            return Vec::new();
+        };
+        let mut pending: Vec<&mut Tile> = self
+            .tiles
+            .iter_mut()
+            .filter(|t| t.status == TileStatus::NotRendered)
+            .collect();
+        pending.sort_by_key(|t| t.properties.id != id);
+        pending
+            .into_iter()
+            .take(count)
+            .map(|tile| {
+                tile.status = TileStatus::Rendering;
+                tile.properties
+            })
+            .collect()
     }
 }
 
@@ -69,28 +196,47 @@ impl Tile {
     }
 
     pub fn generate_image(&self, color_scale: ColorScale) -> ColorImage {
-        let size = self.properties.size;
-        let mut image = ColorImage::filled([size.w as usize, size.h as usize], Color32::BLACK);
-        let sx = 1.0 / self.properties.scale.x.to_num::<f32>();
-        for x in 0..(size.w as i32) {
-            for y in 0..size.h as i32 {
-                let offset = x * size.h as i32 + y;
-                let density = self.data[offset as usize];
-                let a = if density == 0 {
-                    0.0
-                } else {
-                    ((density as f32) * sx).powf(color_scale.power) * color_scale.opacity
-                };
-                let color = if a > 0.0 {
-                    color_scale.gradient.apply(a.clamp(0.0, 1.0))
-                } else {
-                    Color32::BLACK
-                };
-                image.pixels[(y * size.w as i32 + x) as usize] = color;
-            }
+        density_image(
+            &self.data,
+            self.properties.size,
+            self.properties.scale.x.to_num::<f32>(),
+            color_scale,
+        )
+    }
+}
+
+/// Converts density data calculated by a [`Renderer`] into an image, applying the given color
+/// scale.
+///
+/// `samples_per_column` is the number of trace samples covered by each pixel column. It is used to
+/// normalize the density so the colors do not depend on the zoom level.
+pub fn density_image(
+    data: &[u32],
+    size: TileSize,
+    samples_per_column: f32,
+    color_scale: ColorScale,
+) -> ColorImage {
+    let background = color_scale.gradient.background();
+    let mut image = ColorImage::filled([size.w as usize, size.h as usize], background);
+    let sx = 1.0 / samples_per_column;
+    for x in 0..(size.w as i32) {
+        for y in 0..size.h as i32 {
+            let offset = x * size.h as i32 + y;
+            let density = data[offset as usize];
+            let a = if density == 0 {
+                0.0
+            } else {
+                ((density as f32) * sx).powf(color_scale.power) * color_scale.opacity
+            };
+            let color = if a > 0.0 {
+                color_scale.gradient.apply(a.clamp(0.0, 1.0))
+            } else {
+                background
+            };
+            image.pixels[(y * size.w as i32 + x) as usize] = color;
         }
-        image
     }
+    image
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -117,110 +263,396 @@ pub struct TileProperties {
     /// This value is added to the trace samples during GPU rendering.
     pub offset: Fixed,
     /// Index of the first sample in the trace for this tile.
-    pub index: i32,
+    pub index: i64,
     /// Width and Height of the tile.
     pub size: TileSize,
+    /// Revision of the viewer trace.
+    /// Incremented each time the viewer trace is replaced, so tiles rendered from previous samples
+    /// are never mistaken for up-to-date ones.
+    pub generation: u32,
+}
+
+impl TileProperties {
+    /// Returns the range of the samples drawn in the tile. It includes the first sample of the
+    /// next tile, so the segment between both tiles is drawn.
+    pub fn sample_range(&self) -> Range<i64> {
+        // Calculated in fixed point: single precision floats cannot address the samples of long
+        // traces.
+        let width = Fixed::from_num(self.size.w) * self.scale.x;
+        let start = (Fixed::from_num(self.index) * width)
+            .floor()
+            .saturating_to_num::<i64>();
+        let end = (Fixed::from_num(self.index + 1) * width)
+            .floor()
+            .saturating_to_num::<i64>();
+        start..end + 1
+    }
+
+    /// Returns the range of the pixel columns covered by the tile, counted from the first sample
+    /// of the trace at the scale of the tile.
+    pub fn columns(&self) -> Range<i64> {
+        let width = self.size.w as i64;
+        self.index * width..(self.index + 1) * width
+    }
 }
 
 pub struct TilingRenderer {
     renderer: Box<dyn Renderer>,
     shared_tiling: Arc<(Mutex<Tiling>, Condvar)>,
-    traces: Arc<Vec<Arc<Vec<f32>>>>,
+    traces: SharedTraces,
+    /// Context of the viewers, repainted when tiles are rendered so they display them.
+    ctx: egui::Context,
 }
 
+/// Tiles taken by a [`TilingRenderer`], with the traces of their viewers.
+struct TileBatch {
+    tiles: Vec<TileProperties>,
+    traces: Vec<Arc<Trace>>,
+    /// When the rendering of the tiles started.
+    start: Instant,
+}
+
+/// [`TileBatch`] whose rendering has been started by the renderer, or has failed to start.
+type StartedBatch<'a> = (TileBatch, thread::Result<PendingBatch<'a>>);
+
 impl TilingRenderer {
     pub fn new(
         shared_tiling: Arc<(Mutex<Tiling>, Condvar)>,
-        traces: Arc<Vec<Arc<Vec<f32>>>>,
+        traces: SharedTraces,
         renderer: Box<dyn Renderer>,
+        ctx: &egui::Context,
     ) -> Self {
         Self {
             renderer,
             shared_tiling,
             traces,
+            ctx: ctx.clone(),
         }
     }
 
     pub fn render_loop(&mut self) {
+        let mut previous = None;
         loop {
-            self.render_next_tile();
-            {
-                let (tiling, condvar) = &*self.shared_tiling;
-                let guard = tiling.lock().unwrap();
-                let _guard = condvar.wait_while(guard, |t| !t.has_pending()).unwrap();
+            let next = self.start_next_tiles(self.renderer.batch_size());
+            if let Some(batch) = previous.take() {
+                self.finish_tiles(batch);
+            }
+            match next {
+                // The tiles submitted to the GPU are saved once the next ones are submitted, so
+                // they are prepared while the GPU renders.
+                Some(batch) if matches!(batch.1, Ok(PendingBatch::Submitted(_))) => {
+                    previous = Some(batch)
+                }
+                Some(batch) => self.finish_tiles(batch),
+                None => {
+                    let (tiling, condvar) = &*self.shared_tiling;
+                    let guard = tiling.lock().unwrap();
+                    let _guard = condvar.wait_while(guard, |t| !t.has_pending()).unwrap();
+                }
             }
         }
     }
 
-    fn render_next_tile(&mut self) {
-        let Some(properties) = self.shared_tiling.0.lock().unwrap().take_job() else {
-            return;
+    /// Renders up to `count` pending tiles, for rendering without a dedicated thread.
+    pub fn render_pending_tiles(&mut self, count: usize) {
+        let mut rendered = 0;
+        while rendered < count {
+            let size = (count - rendered).min(self.renderer.batch_size());
+            let Some(batch) = self.start_next_tiles(size) else {
+                break;
+            };
+            rendered += batch.0.tiles.len();
+            self.finish_tiles(batch);
+        }
+    }
+
+    /// Starts rendering up to `count` pending tiles together. Returns `None` if no tile is
+    /// pending.
+    fn start_next_tiles(&self, count: usize) -> Option<StartedBatch<'_>> {
+        let tiles = self.shared_tiling.0.lock().unwrap().take_jobs(count);
+        if tiles.is_empty() {
+            return None;
+        }
+        let traces: Vec<Arc<Trace>> = {
+            let traces = self.traces.read().unwrap();
+            tiles
+                .iter()
+                .map(|properties| traces[properties.id as usize].clone())
+                .collect()
+        };
+        let batch = TileBatch {
+            tiles,
+            traces,
+            start: Instant::now(),
+        };
+        // A failing renderer must not stop the rendering of the other tiles, nor leave the
+        // tiling locked.
+        let pending = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.start_tiles(&batch.tiles, &batch.traces)
+        }));
+        Some((batch, pending))
+    }
+
+    /// Waits for the tiles of a batch to be rendered and saves them, then requests a repaint of
+    /// the viewers.
+    fn finish_tiles(&self, (batch, pending): StartedBatch) {
+        let TileBatch {
+            tiles,
+            traces,
+            start,
+        } = batch;
+        // If the tiles fail together, they are rendered again one by one so only the failing ones
+        // are left blank.
+        let densities = pending.and_then(|pending| {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                Self::weight_tiles(&tiles, &traces, pending.wait())
+            }))
+        });
+        let results: Vec<Result<Vec<u32>, String>> = match densities {
+            Ok(data) => data.into_iter().map(Ok).collect(),
+            Err(payload) if tiles.len() == 1 => vec![Err(panic_message(&*payload))],
+            Err(_) => tiles
+                .iter()
+                .zip(&traces)
+                .map(|(properties, trace)| {
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        let (tiles, traces) = (&[*properties], std::slice::from_ref(trace));
+                        Self::weight_tiles(tiles, traces, self.start_tiles(tiles, traces).wait())
+                            .remove(0)
+                    }))
+                    .map_err(|payload| panic_message(&*payload))
+                })
+                .collect(),
         };
-        let data = self.render_tile(
-            properties.id,
-            properties.index,
-            properties.offset,
-            properties.scale,
-            properties.size,
+        tracing::debug!(
+            target: "tiling",
+            "{} tiles of trace {} rendered in {:.3} ms",
+            tiles.len(),
+            tiles[0].id,
+            start.elapsed().as_secs_f64() * 1e3
         );
-        // Save the result
+        // Save the results
         let (tiling, _) = &*self.shared_tiling;
         let mut tiling = tiling.lock().unwrap();
-        if let Some(tile) = tiling.tiles.iter_mut().find(|x| x.properties == properties) {
-            tile.data = data;
-            tile.status = TileStatus::Rendered;
-        } else {
-            // Tile not found, it probably has been deleted during rendering. Save as new tile
-            // anyway.
-            tiling.tiles.push(Tile {
-                status: TileStatus::Rendered,
-                properties,
-                data,
+        for (properties, result) in tiles.iter().copied().zip(results) {
+            let data = result.unwrap_or_else(|message| {
+                tiling.report_failure(
+                    Some(properties.id),
+                    format!("Failed to render the trace: {}", message),
+                );
+                vec![0; properties.size.area() as usize]
             });
+            if let Some(tile) = tiling.tiles.iter_mut().find(|x| x.properties == properties) {
+                // A tile invalidated during its rendering has been rendered from outdated
+                // samples, and remains to be rendered.
+                if tile.status == TileStatus::Rendering {
+                    tile.data = data;
+                    tile.status = TileStatus::Rendered;
+                }
+            } else {
+                // Tile not found, it probably has been deleted during rendering. Save as new tile
+                // anyway.
+                tiling.tiles.push(Tile {
+                    status: TileStatus::Rendered,
+                    properties,
+                    data,
+                });
+            }
         }
+        self.ctx.request_repaint();
+    }
+
+    /// Starts rendering the tiles of the given properties, from the given traces of their
+    /// viewers. The samples are read by the renderer before it returns.
+    fn start_tiles(&self, tiles: &[TileProperties], traces: &[Arc<Trace>]) -> PendingBatch<'_> {
+        let renderings: Vec<TileRendering> = tiles
+            .iter()
+            .zip(traces)
+            .map(|(properties, trace)| TileRendering::new(properties, trace))
+            .collect();
+        let samples: Vec<Option<ChunkSamples>> =
+            renderings.iter().map(TileRendering::read).collect();
+        let jobs = tiles
+            .iter()
+            .zip(&renderings)
+            .zip(&samples)
+            .filter_map(|((properties, rendering), samples)| {
+                rendering.job(properties, samples.as_ref())
+            })
+            .collect();
+        self.renderer.start_batch(jobs)
+    }
+
+    /// Returns the densities of the tiles started by [`TilingRenderer::start_tiles`], from the
+    /// densities rendered by their jobs.
+    fn weight_tiles(
+        tiles: &[TileProperties],
+        traces: &[Arc<Trace>],
+        densities: Vec<Vec<u32>>,
+    ) -> Vec<Vec<u32>> {
+        let mut densities = densities.into_iter();
+        tiles
+            .iter()
+            .zip(traces)
+            .map(
+                |(properties, trace)| match TileRendering::new(properties, trace) {
+                    TileRendering::Blank => vec![0; properties.size.area() as usize],
+                    TileRendering::Samples { .. } => densities.next().unwrap(),
+                    TileRendering::Pyramid(tile) => {
+                        let mut data = densities.next().unwrap();
+                        tile.weight(&mut data);
+                        data
+                    }
+                },
+            )
+            .collect()
     }
+}
+
+/// How a tile is rendered.
+enum TileRendering<'a> {
+    /// The tile covers less than two samples of the trace, and no segment is drawn.
+    Blank,
+    /// The samples of the trace covered by the tile are rendered.
+    Samples {
+        trace: &'a Trace,
+        range: Range<usize>,
+    },
+    /// Zoomed-out tiles are rendered from the pyramid of the trace.
+    Pyramid(PyramidTile<'a>),
+}
+
+impl<'a> TileRendering<'a> {
+    fn new(properties: &TileProperties, trace: &'a Trace) -> Self {
+        let TileProperties {
+            offset,
+            scale,
+            size,
+            ..
+        } = *properties;
+        let trace_len = trace.len() as i64;
+        let samples = properties.sample_range();
 
-    /// Renders the tile starting a sample `index` for the given scales `scale_x` and `scale_y`.
-    fn render_tile(
-        &mut self,
-        id: u32,
-        index: i32,
-        offset: Fixed,
-        scale: FixedVec2,
-        size: TileSize,
-    ) -> Vec<u32> {
-        let trace: &Vec<f32> = &self.traces[id as usize];
-        let trace_len = trace.len() as i32;
-        let i_start = (index as f32 * size.w as f32 * scale.x.to_num::<f32>()).floor() as i32;
-        let i_end = ((index + 1) as f32 * size.w as f32 * scale.x.to_num::<f32>()).floor() as i32;
-
-        if (i_start >= trace_len) || (i_start < 0) {
-            return vec![0; size.area() as usize];
+        if (samples.start >= trace_len) || (samples.start < 0) {
+            return Self::Blank;
         }
 
-        let trace_chunk = &trace[i_start as usize..(i_end + 1).min(trace_len) as usize];
+        let range = samples.start as usize..samples.end.min(trace_len) as usize;
 
         // We need at least 2 points to have one segment.
-        if trace_chunk.len() < 2 {
-            return vec![0; size.area() as usize];
+        if range.len() < 2 {
+            return Self::Blank;
         }
 
-        self.renderer.render(
-            (size.w as f32 * scale.x.to_num::<f32>()) as u32,
-            trace_chunk,
-            size.w,
-            size.h,
-            offset.to_num::<f32>(),
-            scale.y.to_num::<f32>(),
-        )
+        let samples_per_column = scale.x.to_num::<f64>();
+        let level = if Pyramid::covers(samples_per_column) {
+            trace
+                .pyramid()
+                .and_then(|pyramid| pyramid.level(samples_per_column))
+        } else {
+            None
+        };
+        if let Some(level) = level {
+            return Self::Pyramid(level.tile(
+                trace.serial(),
+                range,
+                samples_per_column,
+                (
+                    size.w,
+                    size.h,
+                    offset.to_num::<f32>(),
+                    scale.y.to_num::<f32>(),
+                ),
+            ));
+        }
+
+        Self::Samples { trace, range }
+    }
+
+    /// Reads the samples rendered by the tile, if any.
+    fn read(&self) -> Option<ChunkSamples<'a>> {
+        match self {
+            Self::Samples { trace, range } => Some(trace.read_chunk(range.clone())),
+            _ => None,
+        }
+    }
+
+    /// Returns the job rendering the tile, with the samples returned by
+    /// [`TileRendering::read`], `None` if it is blank.
+    fn job<'b>(
+        &'b self,
+        properties: &TileProperties,
+        samples: Option<&'b ChunkSamples>,
+    ) -> Option<RenderJob<'b>> {
+        let TileProperties {
+            offset,
+            scale,
+            size,
+            ..
+        } = *properties;
+        match self {
+            Self::Blank => None,
+            Self::Samples { trace, range } => Some(RenderJob {
+                chunk_samples: (size.w as f32 * scale.x.to_num::<f32>()) as u32,
+                trace: samples?.chunk(),
+                origin: Some({
+                    let (serial, first) = trace.origin();
+                    ChunkOrigin {
+                        source: (serial, 0),
+                        start: first + range.start,
+                    }
+                }),
+                w: size.w,
+                h: size.h,
+                offset: offset.to_num::<f32>(),
+                scale_y: scale.y.to_num::<f32>(),
+            }),
+            Self::Pyramid(tile) => Some(tile.job()),
+        }
+    }
+}
+
+/// Kinds of [`Gradient`], as selected from the command line or the configuration file.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GradientKind {
+    SingleColor,
+    BiColor,
+    Rainbow,
+}
+
+impl GradientKind {
+    /// Returns the gradient of this kind with its default colors.
+    pub fn gradient(self) -> Gradient {
+        match self {
+            GradientKind::SingleColor => Gradient::SingleColor {
+                min: 0.1,
+                end: Color32::WHITE,
+            },
+            GradientKind::BiColor => Gradient::BiColor {
+                start: Color32::BLUE,
+                end: Color32::GREEN,
+            },
+            GradientKind::Rainbow => Gradient::Rainbow,
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Gradient {
-    SingleColor { min: f32, end: Color32 },
-    BiColor { start: Color32, end: Color32 },
+    SingleColor {
+        min: f32,
+        end: Color32,
+    },
+    BiColor {
+        start: Color32,
+        end: Color32,
+    },
     Rainbow,
+    /// Single color over a transparent background, for traces painted over other traces.
+    Tint {
+        color: Color32,
+    },
 }
 
 impl Gradient {
@@ -233,6 +665,15 @@ impl Gradient {
             }
             Gradient::BiColor { start, end } => start.lerp_to_gamma(*end, x),
             Gradient::Rainbow => Hsva::new(lerp(4.0 / 6.0..=0.0, x), 1.0, 1.0, 1.0).into(),
+            Gradient::Tint { color } => color.gamma_multiply(x),
+        }
+    }
+
+    /// Color of the pixels without density.
+    pub fn background(&self) -> Color32 {
+        match self {
+            Gradient::Tint { .. } => Color32::TRANSPARENT,
+            _ => Color32::BLACK,
         }
     }
 
@@ -241,17 +682,28 @@ impl Gradient {
             Gradient::SingleColor { .. } => "Single color",
             Gradient::BiColor { .. } => "Gradient",
             Gradient::Rainbow => "Rainbow",
+            Gradient::Tint { .. } => "Tint",
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ColorScale {
     pub power: f32,
     pub opacity: f32,
     pub gradient: Gradient,
 }
 
+impl Default for ColorScale {
+    fn default() -> Self {
+        Self {
+            power: 1.0,
+            opacity: 10.0,
+            gradient: Gradient::Rainbow,
+        }
+    }
+}
+
 /// Defines the size of a tile.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct TileSize {
@@ -264,6 +716,14 @@ impl TileSize {
         Self { w, h }
     }
 
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
     /// Returns width multiplied by height.
     /// Panics in case of overflow.
     pub fn area(&self) -> u32 {
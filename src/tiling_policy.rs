@@ -0,0 +1,143 @@
+//! Width of the tiles rendered for the viewers.
+//!
+//! Narrow tiles raise the number of renderer calls needed to fill the view, and the overhead of
+//! each call slows the overall rendering down. Wide tiles need more samples and pixels per call,
+//! which may not fit in the memory of the renderer. The [`TilingPolicy`] chooses the width of the
+//! tiles of a view between both, as configured from the command line.
+
+use crate::{
+    renderer::{RENDERER_MAX_PIXELS, RENDERER_MAX_TRACE_SIZE},
+    util::Fixed,
+};
+use serde::{Deserialize, Serialize};
+
+/// Default width of the tiles, and width of the tiles of the offline renderings.
+/// The current value seems to be a good compromise between the renderer calls overhead and the
+/// minimum zoom level, limited by the samples of a tile fitting in the renderer memory.
+pub const TILE_WIDTH: u32 = 64;
+
+/// Default maximum width of the tiles in [`TilingMode::Adaptive`] mode.
+pub const MAX_TILE_WIDTH: u32 = 512;
+
+/// Tiles are twice as wide each time the number of samples per pixel column is multiplied by
+/// this factor, in [`TilingMode::Adaptive`] mode.
+const WIDTH_DOUBLING_ZOOM: f64 = 8.0;
+
+/// How the width of the tiles is chosen, as selected from the command line or the configuration
+/// file.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TilingMode {
+    /// All the tiles have the same width.
+    Fixed,
+    /// The tiles are wider when zoomed out, and narrower around the focus of the user.
+    Adaptive,
+}
+
+/// Chooses the width of the rendered tiles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TilingPolicy {
+    pub mode: TilingMode,
+    /// Width of the tiles in [`TilingMode::Fixed`] mode, and minimum width in
+    /// [`TilingMode::Adaptive`] mode, used around the focus.
+    pub width: u32,
+    /// Maximum width of the tiles in [`TilingMode::Adaptive`] mode.
+    pub max_width: u32,
+}
+
+/// Tile of a view, as chosen by a [`TilingPolicy`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileSlot {
+    /// Index of the tile among the tiles of the same width.
+    pub index: i64,
+    pub width: u32,
+}
+
+impl Default for TilingPolicy {
+    fn default() -> Self {
+        Self {
+            mode: TilingMode::Adaptive,
+            width: TILE_WIDTH,
+            max_width: MAX_TILE_WIDTH,
+        }
+    }
+}
+
+impl TilingPolicy {
+    /// Returns the width of the tiles of `height` pixels rendering `samples_per_column` samples
+    /// per pixel column, away from the focus.
+    ///
+    /// The width is reduced until the samples and the pixels of a tile fit in the renderer
+    /// buffers, below the minimum width if needed.
+    pub fn tile_width(&self, samples_per_column: Fixed, height: u32) -> u32 {
+        let mut width = match self.mode {
+            TilingMode::Fixed => self.width,
+            TilingMode::Adaptive => {
+                let zoom = samples_per_column.to_num::<f64>().max(1.0);
+                let doublings = (zoom.log(WIDTH_DOUBLING_ZOOM).floor() as u32).min(31);
+                self.width
+                    .saturating_mul(1 << doublings)
+                    .min(self.max_width.max(self.width))
+            }
+        };
+        let fits = |width: u32| {
+            Fixed::from_num(width) * samples_per_column < Fixed::from_num(RENDERER_MAX_TRACE_SIZE)
+                && width as usize * height as usize <= RENDERER_MAX_PIXELS
+        };
+        while width > 1 && !fits(width) {
+            width /= 2;
+        }
+        width.max(1)
+    }
+
+    /// Returns the tiles covering the pixel columns from `left` to `right`, counted from the
+    /// first sample of the trace. The tiles containing `focus` and their neighbours are split in
+    /// tiles of the minimum width in [`TilingMode::Adaptive`] mode, so they are rendered first.
+    ///
+    /// Tiles are sorted by distance from `focus`, or from the middle of the columns if there is
+    /// no focus, so the most interesting ones are rendered first.
+    pub fn tiles(
+        &self,
+        (left, right): (Fixed, Fixed),
+        focus: Option<Fixed>,
+        samples_per_column: Fixed,
+        height: u32,
+    ) -> Vec<TileSlot> {
+        let width = self.tile_width(samples_per_column, height);
+        let tile_width = Fixed::from_num(width);
+        let start = (left / tile_width).floor().saturating_to_num::<i64>();
+        let end = (right / tile_width).ceil().saturating_to_num::<i64>();
+        let center = focus.unwrap_or((left + right) / 2);
+        let focused = (center / tile_width).floor().saturating_to_num::<i64>();
+        let split = self.mode == TilingMode::Adaptive
+            && focus.is_some()
+            && width > self.width
+            && width.is_multiple_of(self.width);
+
+        let mut tiles = Vec::new();
+        for index in start..end {
+            if split && index.abs_diff(focused) <= 1 {
+                let n = (width / self.width) as i64;
+                let fine = Fixed::from_num(self.width);
+                tiles.extend(
+                    (index * n..(index + 1) * n)
+                        .filter(|&i| {
+                            Fixed::from_num(i + 1) * fine > left
+                                && Fixed::from_num(i) * fine < right
+                        })
+                        .map(|index| TileSlot {
+                            index,
+                            width: self.width,
+                        }),
+                );
+            } else {
+                tiles.push(TileSlot { index, width });
+            }
+        }
+        tiles.sort_by_key(|tile| {
+            let width = Fixed::from_num(tile.width);
+            (Fixed::from_num(tile.index) * width + width / 2 - center).abs()
+        });
+        tiles
+    }
+}
@@ -0,0 +1,851 @@
+//! Samples of a trace, held in memory or mapped from the file they are stored in.
+//!
+//! Float32 NumPy arrays can be mapped instead of being copied at loading: the pages of the file
+//! are only read when the tiles covering them are rendered, so multi-gigabyte traces open at
+//! once and do not need to fit in memory.
+//!
+//! Traces too large for the memory, in raw files and non-float32 NumPy arrays, are paged instead:
+//! their samples are read from the file by pages when needed, and only the recently used pages
+//! are kept in memory.
+//!
+//! The samples of 8 and 16-bit digitizers take at most 65536 evenly spaced values. Long traces of
+//! such samples are stored as integer codes with a scale and an offset, taking two or four times
+//! less memory than floats. The renderers read the codes directly; the other users of the samples
+//! convert them to floats once, when first needed.
+//!
+//! Streamed samples are appended to a [`TraceStream`] by blocks, which its traces share, so the
+//! samples received are not copied again as the trace grows.
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::loaders::{SampleType, decode_raw};
+use crate::pyramid::{PYRAMID_MIN_SAMPLES, Pyramid};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::Mutex,
+};
+use std::{
+    collections::VecDeque,
+    io,
+    ops::{Deref, Range},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use web_time::Instant;
+
+/// Traces with fewer samples are kept as floats by [`Trace::quantize`], as they take little
+/// memory anyway.
+const QUANTIZE_MIN_SAMPLES: usize = 1 << 20;
+
+/// Number of samples at the start of the trace from which the spacing of the values is guessed.
+const QUANTIZE_PROBE_SAMPLES: usize = 1 << 20;
+
+/// Traces with at least this number of samples are paged by the loaders supporting it, instead of
+/// being loaded in memory.
+pub const PAGED_MIN_SAMPLES: usize = 1 << 28;
+
+/// Number of samples of the pages of paged traces.
+#[cfg(not(target_arch = "wasm32"))]
+const PAGE_SAMPLES: usize = 1 << 20;
+
+/// Number of pages of a paged trace kept in memory, 128 MB of samples. Reads covering more than
+/// half of them are not cached, so rendering zoomed-out tiles does not evict the pages of the
+/// zoomed-in ones.
+#[cfg(not(target_arch = "wasm32"))]
+const CACHED_PAGES: usize = 32;
+
+/// Number of samples of the blocks of streamed traces.
+const STREAM_BLOCK_SAMPLES: usize = 1 << 16;
+
+/// Samples of a trace. Dereferences to the slice of its samples.
+pub struct Trace {
+    samples: Samples,
+    /// Unique number of the trace, see [`Trace::serial`].
+    serial: u64,
+    /// Min/max pyramid of the long traces, built when first needed.
+    pyramid: OnceLock<Option<Pyramid>>,
+}
+
+enum Samples {
+    Memory(Vec<f32>),
+    /// Samples equal to `code * scale + offset`, with the samples converted to floats once needed
+    /// as a slice.
+    Quantized {
+        codes: Codes,
+        scale: f64,
+        offset: f64,
+        floats: OnceLock<Vec<f32>>,
+    },
+    /// Byte range of the samples in a mapped file.
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped {
+        map: Arc<memmap2::Mmap>,
+        range: Range<usize>,
+    },
+    #[cfg(not(target_arch = "wasm32"))]
+    Paged(Box<PagedSamples>),
+    Stream(Box<StreamSamples>),
+}
+
+/// Samples of a [`TraceStream`], shared with the stream.
+struct StreamSamples {
+    /// Serial number of the stream, see [`Trace::origin`].
+    serial: u64,
+    /// Index in the stream of the first sample.
+    first: usize,
+    /// Blocks of [`STREAM_BLOCK_SAMPLES`] samples, the last one possibly shorter.
+    blocks: Vec<Arc<[f32]>>,
+    len: usize,
+    /// All the samples, copied when needed as a slice.
+    floats: OnceLock<Vec<f32>>,
+}
+
+impl StreamSamples {
+    /// Returns the samples in `range`, borrowed from their block if they are all in the same one.
+    fn chunk(&self, range: Range<usize>) -> ChunkSamples<'_> {
+        if range.is_empty() {
+            return ChunkSamples::Stored(TraceChunk::F32(&[]));
+        }
+        let block = range.start / STREAM_BLOCK_SAMPLES;
+        let start = block * STREAM_BLOCK_SAMPLES;
+        if range.end <= start + STREAM_BLOCK_SAMPLES {
+            return ChunkSamples::Stored(TraceChunk::F32(
+                &self.blocks[block][range.start - start..range.end - start],
+            ));
+        }
+        let mut samples = Vec::with_capacity(range.len());
+        for (i, block) in self.blocks.iter().enumerate() {
+            let start = i * STREAM_BLOCK_SAMPLES;
+            let (from, to) = (range.start.max(start), range.end.min(start + block.len()));
+            if from < to {
+                samples.extend_from_slice(&block[from - start..to - start]);
+            }
+        }
+        ChunkSamples::Read(samples)
+    }
+}
+
+/// Samples read from a file by pages, the recently used pages being kept in memory.
+#[cfg(not(target_arch = "wasm32"))]
+struct PagedSamples {
+    file: Arc<Mutex<File>>,
+    /// Position of the first sample in the file, in bytes.
+    offset: u64,
+    len: usize,
+    dtype: SampleType,
+    /// Cached pages, by index.
+    pages: Mutex<HashMap<usize, Arc<[f32]>>>,
+    /// Indices of the cached pages, from the least to the most recently used.
+    order: Mutex<VecDeque<usize>>,
+    /// All the samples, read when needed as a slice.
+    floats: OnceLock<Vec<f32>>,
+    /// First failure to read the file since the last call to [`Trace::take_read_error`].
+    error: Mutex<Option<String>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PagedSamples {
+    /// Reads the samples in `range` from the file.
+    fn read(&self, range: Range<usize>) -> io::Result<Vec<f32>> {
+        let size = self.dtype.size();
+        let mut data = vec![0; range.len() * size];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(self.offset + (range.start * size) as u64))?;
+        file.read_exact(&mut data)?;
+        Ok(decode_raw(&data, self.dtype).0)
+    }
+
+    /// Returns the page at `index`, reading it if it is not cached.
+    fn page(&self, index: usize) -> io::Result<Arc<[f32]>> {
+        if let Some(page) = self.pages.lock().unwrap().get(&index) {
+            let mut order = self.order.lock().unwrap();
+            order.retain(|&i| i != index);
+            order.push_back(index);
+            return Ok(page.clone());
+        }
+        let start = index * PAGE_SAMPLES;
+        let page: Arc<[f32]> = self
+            .read(start..(start + PAGE_SAMPLES).min(self.len))?
+            .into();
+        let mut pages = self.pages.lock().unwrap();
+        let mut order = self.order.lock().unwrap();
+        if pages.insert(index, page.clone()).is_none() {
+            order.push_back(index);
+        }
+        while order.len() > CACHED_PAGES {
+            let evicted = order.pop_front().unwrap();
+            pages.remove(&evicted);
+        }
+        Ok(page)
+    }
+
+    /// Returns the samples in `range`, read through the cached pages unless the range is too
+    /// large.
+    fn samples(&self, range: Range<usize>) -> io::Result<Vec<f32>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pages = range.start / PAGE_SAMPLES..(range.end - 1) / PAGE_SAMPLES + 1;
+        if pages.len() > CACHED_PAGES / 2 {
+            return self.read(range);
+        }
+        let mut samples = Vec::with_capacity(range.len());
+        for index in pages {
+            let page = self.page(index)?;
+            let start = index * PAGE_SAMPLES;
+            let from = range.start.max(start) - start;
+            let to = range.end.min(start + page.len()) - start;
+            samples.extend_from_slice(&page[from..to]);
+        }
+        Ok(samples)
+    }
+
+    /// Returns the samples in `range`, or NaN samples if the file cannot be read anymore, for
+    /// the users of the samples which cannot fail. The error is kept to be reported.
+    fn samples_or_nan(&self, range: Range<usize>) -> Vec<f32> {
+        self.samples(range.clone()).unwrap_or_else(|e| {
+            self.error
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| format!("Failed to read the samples of the trace: {}", e));
+            vec![f32::NAN; range.len()]
+        })
+    }
+}
+
+/// A file mapped in memory, from which traces are taken without copying their samples.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct MappedFile(Arc<memmap2::Mmap>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MappedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the samples are only read, and any bit pattern is a valid f32, so a file
+        // modified by another process while it is mapped only changes the displayed samples. A
+        // file truncated while it is mapped raises SIGBUS when the samples beyond its new end are
+        // read: the files which may change, the watched ones, are not mapped.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self(Arc::new(map)))
+    }
+
+    /// Size of the file, in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the trace of the `len` little-endian f32 samples starting at byte `offset`. Fails
+    /// if they are not aligned or lie beyond the end of the file.
+    pub fn trace(&self, offset: usize, len: usize) -> io::Result<Trace> {
+        let end = offset
+            .checked_add(len.saturating_mul(size_of::<f32>()))
+            .filter(|&end| end <= self.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated file"))?;
+        if !cfg!(target_endian = "little") || !offset.is_multiple_of(align_of::<f32>()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "samples cannot be mapped",
+            ));
+        }
+        Ok(Trace::new(Samples::Mapped {
+            map: self.0.clone(),
+            range: offset..end,
+        }))
+    }
+}
+
+/// A file whose samples are read by pages, shared by its traces.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct PagedFile {
+    file: Arc<Mutex<File>>,
+    len: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PagedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            len,
+        })
+    }
+
+    /// Size of the file, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the trace of the `len` little-endian samples of type `dtype` starting at byte
+    /// `offset`, which are read when needed. Fails if they lie beyond the end of the file.
+    pub fn trace(&self, offset: u64, len: usize, dtype: SampleType) -> io::Result<Trace> {
+        if offset + (len * dtype.size()) as u64 > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated file",
+            ));
+        }
+        Ok(Trace::new(Samples::Paged(Box::new(PagedSamples {
+            file: self.file.clone(),
+            offset,
+            len,
+            dtype,
+            pages: Mutex::default(),
+            order: Mutex::default(),
+            floats: OnceLock::new(),
+            error: Mutex::default(),
+        }))))
+    }
+}
+
+/// Integer codes of quantized samples.
+enum Codes {
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+}
+
+impl Codes {
+    fn len(&self) -> usize {
+        match self {
+            Self::I8(codes) => codes.len(),
+            Self::I16(codes) => codes.len(),
+        }
+    }
+}
+
+impl Trace {
+    fn new(samples: Samples) -> Self {
+        Self {
+            samples,
+            serial: next_serial(),
+            pyramid: OnceLock::new(),
+        }
+    }
+
+    /// Returns a number unique to the trace, as its samples never change, identifying them for
+    /// the GPU renderer which keeps them between tiles.
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    /// Returns the number identifying the samples of the trace for the GPU renderer, with the
+    /// index of the first sample of the trace in them. The traces of a [`TraceStream`] share the
+    /// serial number of the stream, whose samples never change once received, so the samples
+    /// kept by the renderer remain valid as the trace grows. The other traces have their own
+    /// [`Trace::serial`].
+    pub fn origin(&self) -> (u64, usize) {
+        match &self.samples {
+            Samples::Stream(stream) => (stream.serial, stream.first),
+            _ => (self.serial, 0),
+        }
+    }
+
+    /// Stores `samples` as 8 or 16-bit codes if they take at most 65536 evenly spaced values, as
+    /// the samples of 8 or 16-bit digitizers do. The codes of the digitizer are recovered, the
+    /// samples being restored within a thousandth of their spacing. Other samples, and short
+    /// traces, are kept as floats.
+    pub fn quantize(samples: Vec<f32>) -> Self {
+        if samples.len() < QUANTIZE_MIN_SAMPLES {
+            return samples.into();
+        }
+        match quantize(&samples) {
+            Some((codes, scale, offset)) => Self::new(Samples::Quantized {
+                codes,
+                scale,
+                offset,
+                floats: OnceLock::new(),
+            }),
+            None => samples.into(),
+        }
+    }
+
+    /// Returns true if the samples are mapped from a file instead of being held in memory.
+    pub fn is_mapped(&self) -> bool {
+        match self.samples {
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Mapped { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the samples are read from their file by pages.
+    pub fn is_paged(&self) -> bool {
+        match self.samples {
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the number of bits of the codes of quantized traces, `None` for float samples.
+    pub fn quantization_bits(&self) -> Option<u32> {
+        match &self.samples {
+            Samples::Quantized {
+                codes: Codes::I8(_),
+                ..
+            } => Some(8),
+            Samples::Quantized {
+                codes: Codes::I16(_),
+                ..
+            } => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Number of samples. Unlike the length of the dereferenced slice, does not convert quantized
+    /// samples to floats.
+    pub fn len(&self) -> usize {
+        match &self.samples {
+            Samples::Quantized { codes, .. } => codes.len(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(paged) => paged.len,
+            Samples::Stream(stream) => stream.len,
+            _ => self.deref().len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the samples in `range` as they are stored, for rendering them without converting
+    /// quantized samples to floats. The samples of paged traces are read from their file, and are
+    /// NaN if it cannot be read anymore (see [`Trace::take_read_error`]).
+    pub fn read_chunk(&self, range: Range<usize>) -> ChunkSamples<'_> {
+        match &self.samples {
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(paged) => ChunkSamples::Read(paged.samples_or_nan(range)),
+            Samples::Stream(stream) => stream.chunk(range),
+            _ => ChunkSamples::Stored(self.chunk(range)),
+        }
+    }
+
+    /// Returns the lowest and highest samples in `range`, used for autoscaling. The extrema of
+    /// the paged traces are estimated from evenly spaced pages for the ranges covering many
+    /// pages, so the whole file is not read. Fails if the file of a paged trace cannot be read
+    /// anymore. Panics if the range is empty.
+    pub fn min_max(&self, range: Range<usize>) -> io::Result<[f32; 2]> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Samples::Paged(paged) = &self.samples {
+            let pages = range.start / PAGE_SAMPLES..(range.end - 1) / PAGE_SAMPLES + 1;
+            let step = pages.len().div_ceil(CACHED_PAGES / 2);
+            let mut extrema = Vec::new();
+            for index in pages.step_by(step) {
+                let start = (index * PAGE_SAMPLES).max(range.start);
+                let end = ((index + 1) * PAGE_SAMPLES).min(range.end);
+                extrema.push(TraceChunk::F32(&paged.samples(start..end)?).min_max());
+            }
+            return Ok(extrema
+                .into_iter()
+                .reduce(|[min_a, max_a], [min_b, max_b]| [min_a.min(min_b), max_a.max(max_b)])
+                .expect("Empty trace"));
+        }
+        if let Samples::Stream(stream) = &self.samples {
+            return Ok((range.start..range.end)
+                .step_by(STREAM_BLOCK_SAMPLES)
+                .map(|start| {
+                    let end = (start + STREAM_BLOCK_SAMPLES).min(range.end);
+                    stream.chunk(start..end).chunk().min_max()
+                })
+                .reduce(|[min_a, max_a], [min_b, max_b]| [min_a.min(min_b), max_a.max(max_b)])
+                .expect("Empty trace"));
+        }
+        Ok(self.chunk(range).min_max())
+    }
+
+    /// Returns the first failure to read the samples of a paged trace since the previous call, the
+    /// unreadable samples having been replaced by NaN.
+    pub fn take_read_error(&self) -> Option<String> {
+        match &self.samples {
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(paged) => paged.error.lock().unwrap().take(),
+            _ => None,
+        }
+    }
+
+    /// Returns the samples in `range` as they are stored. The samples of paged traces are read
+    /// entirely by [`Deref`].
+    fn chunk(&self, range: Range<usize>) -> TraceChunk<'_> {
+        match &self.samples {
+            Samples::Quantized {
+                codes,
+                scale,
+                offset,
+                ..
+            } => {
+                let (scale, offset) = (*scale as f32, *offset as f32);
+                match codes {
+                    Codes::I8(codes) => TraceChunk::I8 {
+                        codes: &codes[range],
+                        scale,
+                        offset,
+                    },
+                    Codes::I16(codes) => TraceChunk::I16 {
+                        codes: &codes[range],
+                        scale,
+                        offset,
+                    },
+                }
+            }
+            _ => TraceChunk::F32(&self.deref()[range]),
+        }
+    }
+
+    /// Returns the sample at `index`, without converting quantized samples to floats.
+    pub fn sample(&self, index: usize) -> f32 {
+        match &self.samples {
+            Samples::Quantized {
+                codes,
+                scale,
+                offset,
+                ..
+            } => match codes {
+                Codes::I8(codes) => dequantize(codes[index] as f64, *scale, *offset),
+                Codes::I16(codes) => dequantize(codes[index] as f64, *scale, *offset),
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(paged) => paged
+                .samples_or_nan(index..index + 1)
+                .first()
+                .copied()
+                .unwrap_or(f32::NAN),
+            Samples::Stream(stream) => {
+                stream.blocks[index / STREAM_BLOCK_SAMPLES][index % STREAM_BLOCK_SAMPLES]
+            }
+            _ => self.deref()[index],
+        }
+    }
+
+    /// Returns the min/max pyramid of the trace, building it on the first call, or `None` if the
+    /// trace is shorter than [`PYRAMID_MIN_SAMPLES`].
+    pub fn pyramid(&self) -> Option<&Pyramid> {
+        self.pyramid
+            .get_or_init(|| {
+                (self.len() >= PYRAMID_MIN_SAMPLES).then(|| {
+                    let start = Instant::now();
+                    let pyramid = Pyramid::build(self);
+                    tracing::debug!(
+                        target: "loader",
+                        "Min/max pyramid of {} samples built in {:.3} s",
+                        self.len(),
+                        start.elapsed().as_secs_f64()
+                    );
+                    pyramid
+                })
+            })
+            .as_ref()
+    }
+
+    /// Returns the samples in a vector, copying them if they are mapped or quantized.
+    pub fn into_vec(self) -> Vec<f32> {
+        match self.samples {
+            Samples::Memory(samples) => samples,
+            Samples::Quantized { floats, .. } if floats.get().is_some() => {
+                floats.into_inner().unwrap()
+            }
+            _ => self.to_vec(),
+        }
+    }
+}
+
+impl Deref for Trace {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        match &self.samples {
+            Samples::Memory(samples) => samples,
+            Samples::Quantized {
+                codes,
+                scale,
+                offset,
+                floats,
+            } => floats.get_or_init(|| {
+                tracing::debug!(
+                    target: "loader",
+                    "Converting {} quantized samples to floats",
+                    codes.len()
+                );
+                match codes {
+                    Codes::I8(codes) => codes
+                        .iter()
+                        .map(|&code| dequantize(code as f64, *scale, *offset))
+                        .collect(),
+                    Codes::I16(codes) => codes
+                        .iter()
+                        .map(|&code| dequantize(code as f64, *scale, *offset))
+                        .collect(),
+                }
+            }),
+            // The mapping starts on a page boundary and the range is aligned, as checked by
+            // `MappedFile::trace`.
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Mapped { map, range } => bytemuck::cast_slice(&map[range.clone()]),
+            #[cfg(not(target_arch = "wasm32"))]
+            Samples::Paged(paged) => paged.floats.get_or_init(|| {
+                tracing::warn!(
+                    target: "loader",
+                    "Reading the {} samples of a paged trace in memory",
+                    paged.len
+                );
+                paged.samples_or_nan(0..paged.len)
+            }),
+            Samples::Stream(stream) => stream.floats.get_or_init(|| stream.blocks.concat()),
+        }
+    }
+}
+
+impl From<Vec<f32>> for Trace {
+    fn from(samples: Vec<f32>) -> Self {
+        Self::new(Samples::Memory(samples))
+    }
+}
+
+impl FromIterator<f32> for Trace {
+    fn from_iter<I: IntoIterator<Item = f32>>(iter: I) -> Self {
+        Self::new(Samples::Memory(iter.into_iter().collect()))
+    }
+}
+
+/// Samples received by blocks from a live source, shown as a trace growing at each update. Only
+/// the last samples are kept, up to a maximum length.
+pub struct TraceStream {
+    /// Serial number of the samples, see [`Trace::origin`].
+    serial: u64,
+    /// Index in the stream of the first kept sample.
+    first: usize,
+    /// Full blocks of [`STREAM_BLOCK_SAMPLES`] samples, shared with the traces.
+    blocks: VecDeque<Arc<[f32]>>,
+    /// Samples received after the full blocks.
+    tail: Vec<f32>,
+    /// Number of samples kept.
+    max_len: usize,
+}
+
+impl TraceStream {
+    /// Creates an empty stream keeping at least the last `max_len` samples. The oldest samples
+    /// are dropped by blocks, so the traces may hold up to [`STREAM_BLOCK_SAMPLES`] more.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            serial: next_serial(),
+            first: 0,
+            blocks: VecDeque::new(),
+            tail: Vec::with_capacity(STREAM_BLOCK_SAMPLES),
+            max_len,
+        }
+    }
+
+    /// Number of samples kept.
+    pub fn len(&self) -> usize {
+        self.blocks.len() * STREAM_BLOCK_SAMPLES + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends `samples`, dropping the oldest blocks beyond the maximum length. Returns the number
+    /// of samples dropped from the start of the trace.
+    pub fn append(&mut self, samples: &[f32]) -> usize {
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let n = (STREAM_BLOCK_SAMPLES - self.tail.len()).min(samples.len());
+            self.tail.extend_from_slice(&samples[..n]);
+            samples = &samples[n..];
+            if self.tail.len() == STREAM_BLOCK_SAMPLES {
+                self.blocks.push_back(self.tail.as_slice().into());
+                self.tail.clear();
+            }
+        }
+        let mut dropped = 0;
+        while self.len() >= self.max_len + STREAM_BLOCK_SAMPLES {
+            self.blocks.pop_front();
+            dropped += STREAM_BLOCK_SAMPLES;
+        }
+        self.first += dropped;
+        dropped
+    }
+
+    /// Returns the samples kept, as a trace sharing the full blocks with the stream.
+    pub fn trace(&self) -> Trace {
+        let mut blocks: Vec<Arc<[f32]>> = self.blocks.iter().cloned().collect();
+        if !self.tail.is_empty() {
+            blocks.push(self.tail.as_slice().into());
+        }
+        Trace::new(Samples::Stream(Box::new(StreamSamples {
+            serial: self.serial,
+            first: self.first,
+            blocks,
+            len: self.len(),
+            floats: OnceLock::new(),
+        })))
+    }
+}
+
+/// Samples of a range of a trace returned by [`Trace::read_chunk`], borrowed from the trace or
+/// read from its file.
+pub enum ChunkSamples<'a> {
+    Stored(TraceChunk<'a>),
+    Read(Vec<f32>),
+}
+
+impl ChunkSamples<'_> {
+    pub fn chunk(&self) -> TraceChunk<'_> {
+        match self {
+            Self::Stored(chunk) => *chunk,
+            Self::Read(samples) => TraceChunk::F32(samples),
+        }
+    }
+}
+
+/// Samples of a range of a trace, as they are stored. Read by the renderers, which convert the
+/// codes of quantized traces on the fly.
+#[derive(Copy, Clone)]
+pub enum TraceChunk<'a> {
+    F32(&'a [f32]),
+    /// Samples equal to `code * scale + offset`.
+    I8 {
+        codes: &'a [i8],
+        scale: f32,
+        offset: f32,
+    },
+    /// Samples equal to `code * scale + offset`.
+    I16 {
+        codes: &'a [i16],
+        scale: f32,
+        offset: f32,
+    },
+}
+
+impl TraceChunk<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::F32(samples) => samples.len(),
+            Self::I8 { codes, .. } => codes.len(),
+            Self::I16 { codes, .. } => codes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the lowest and highest samples, used for autoscaling. Panics if the chunk is
+    /// empty.
+    pub fn min_max(&self) -> [f32; 2] {
+        fn extrema<T: Copy + Ord>(codes: &[T]) -> [T; 2] {
+            let min = codes.iter().copied().min().expect("Empty trace");
+            let max = codes.iter().copied().max().expect("Empty trace");
+            [min, max]
+        }
+        match self {
+            Self::F32(samples) => [
+                samples
+                    .iter()
+                    .cloned()
+                    .min_by(f32::total_cmp)
+                    .expect("Trace has NaN sample"),
+                samples
+                    .iter()
+                    .cloned()
+                    .max_by(f32::total_cmp)
+                    .expect("Trace has NaN sample"),
+            ],
+            // The scale of quantized samples is positive.
+            Self::I8 {
+                codes,
+                scale,
+                offset,
+            } => extrema(codes).map(|code| code as f32 * scale + offset),
+            Self::I16 {
+                codes,
+                scale,
+                offset,
+            } => extrema(codes).map(|code| code as f32 * scale + offset),
+        }
+    }
+}
+
+impl<'a> From<&'a [f32]> for TraceChunk<'a> {
+    fn from(samples: &'a [f32]) -> Self {
+        Self::F32(samples)
+    }
+}
+
+/// Returns a new number identifying samples, see [`Trace::serial`].
+fn next_serial() -> u64 {
+    static SERIAL: AtomicU64 = AtomicU64::new(0);
+    SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+fn dequantize(code: f64, scale: f64, offset: f64) -> f32 {
+    (code * scale + offset) as f32
+}
+
+/// Returns the 8 or 16-bit codes of `samples`, with their scale and offset, if the samples take
+/// at most 65536 evenly spaced values.
+fn quantize(samples: &[f32]) -> Option<(Codes, f64, f64)> {
+    let mut min = f32::INFINITY;
+    let mut max = f32::NEG_INFINITY;
+    for &sample in samples {
+        if !sample.is_finite() {
+            return None;
+        }
+        min = min.min(sample);
+        max = max.max(sample);
+    }
+    let (min, max) = (min as f64, max as f64);
+    // The spacing of the values is the smallest difference between the values at the start of
+    // the trace. If some values are missing there, the spacing found is too large, and the
+    // samples are not quantized.
+    let mut probe: Vec<f32> = samples[..samples.len().min(QUANTIZE_PROBE_SAMPLES)].to_vec();
+    probe.sort_unstable_by(f32::total_cmp);
+    probe.dedup();
+    let step = probe
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]) as f64)
+        .min_by(f64::total_cmp)
+        .unwrap_or(1.0);
+    let levels = ((max - min) / step).round();
+    if levels > u16::MAX as f64 {
+        return None;
+    }
+    // The spacing is refined with the whole range of the values.
+    let step = if levels > 0.0 {
+        (max - min) / levels
+    } else {
+        1.0
+    };
+    let bias = if levels <= u8::MAX as f64 {
+        128.0
+    } else {
+        32768.0
+    };
+    let offset = min + bias * step;
+    let code = |sample: f32| {
+        let code = ((sample as f64 - min) / step).round() - bias;
+        let error = (dequantize(code, step, offset) as f64 - sample as f64).abs();
+        (error <= step * 1e-3).then_some(code)
+    };
+    let codes = if bias == 128.0 {
+        let codes: Option<Vec<i8>> = samples.iter().map(|&s| Some(code(s)? as i8)).collect();
+        Codes::I8(codes?)
+    } else {
+        let codes: Option<Vec<i16>> = samples.iter().map(|&s| Some(code(s)? as i16)).collect();
+        Codes::I16(codes?)
+    };
+    Some((codes, step, offset))
+}
@@ -0,0 +1,155 @@
+//! Inspection of the data bytes of TRS traces.
+//!
+//! TRS trace sets store, with each trace, the data bytes of the captured operation, such as its
+//! plaintext, ciphertext or key. The panel shows these bytes for the displayed trace, and allows
+//! navigating between the traces of the file, so what is seen on a trace can be related to the
+//! inputs it has been captured with.
+
+use crate::loaders::TrsFile;
+use egui::{Color32, DragValue, Id, RichText, ScrollArea, Ui};
+use std::sync::Arc;
+
+/// Number of bytes per line of the hexdump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Formats `bytes` as lines of hexadecimal values, prefixed by their offset.
+fn hexdump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(i, line)| {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{:04x}  {}", i * BYTES_PER_LINE, hex.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Panel showing the title and data bytes of a trace of a TRS file.
+pub struct TraceDataPanel {
+    id: Id,
+    file: Arc<TrsFile>,
+    /// Index of the displayed trace in the file.
+    index: usize,
+    title: String,
+    data: Vec<u8>,
+    /// Error of the last read.
+    error: Option<String>,
+    pub open: bool,
+}
+
+impl TraceDataPanel {
+    /// Creates the panel for the trace at `index` in `file`. The panel is initially closed.
+    pub fn new(id: Id, file: Arc<TrsFile>, index: usize) -> Self {
+        let mut panel = Self {
+            id,
+            file,
+            index,
+            title: String::new(),
+            data: Vec::new(),
+            error: None,
+            open: false,
+        };
+        panel.read(index);
+        panel
+    }
+
+    /// Index of the displayed trace in the file.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Shows the data of the trace at `index`, when the trace has been replaced by another trace
+    /// of the file.
+    pub fn select(&mut self, index: usize) {
+        self.read(index);
+    }
+
+    /// Label of the displayed trace.
+    pub fn label(&self) -> String {
+        format!("{} [frame {}]", self.file.path.display(), self.index)
+    }
+
+    /// Reads the trace at `index` and returns its samples.
+    fn read(&mut self, index: usize) -> Option<Vec<f32>> {
+        match self.file.read(index) {
+            Ok(record) => {
+                self.index = index;
+                self.title = record.title;
+                self.data = record.data;
+                self.error = None;
+                Some(record.samples)
+            }
+            Err(e) => {
+                self.error = Some(format!("Failed to read trace {}: {}", index, e));
+                None
+            }
+        }
+    }
+
+    /// Shows the panel window if it is open. Returns the samples of the trace the user navigated
+    /// to, if any.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<Vec<f32>> {
+        let mut open = self.open;
+        let mut samples = None;
+        egui::Window::new("Trace data")
+            .id(self.id)
+            .open(&mut open)
+            .default_width(420.0)
+            .show(ctx, |ui| samples = self.ui(ui));
+        self.open = open;
+        samples
+    }
+
+    fn ui(&mut self, ui: &mut Ui) -> Option<Vec<f32>> {
+        let header = &self.file.header;
+        let count = header.traces;
+        let mut index = self.index;
+        ui.horizontal(|ui| {
+            ui.label("Trace:");
+            if ui.add_enabled(index > 0, egui::Button::new("◀")).clicked() {
+                index -= 1;
+            }
+            ui.add(DragValue::new(&mut index).range(0..=count.saturating_sub(1)));
+            if ui
+                .add_enabled(index + 1 < count, egui::Button::new("▶"))
+                .clicked()
+            {
+                index += 1;
+            }
+            ui.label(format!("of {}", count));
+        });
+        if !header.global_title.is_empty() {
+            ui.label(format!("Trace set: {}", header.global_title));
+        }
+        if !self.title.is_empty() {
+            ui.label(format!("Title: {}", self.title));
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        ui.separator();
+
+        ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+            if self.data.is_empty() {
+                ui.label("No data bytes in this trace set.");
+            } else if header.parameters.is_empty() {
+                ui.label(format!("Data ({} bytes):", self.data.len()));
+                ui.label(RichText::new(hexdump(&self.data)).monospace());
+            } else {
+                for parameter in &header.parameters {
+                    let end = (parameter.offset + parameter.len).min(self.data.len());
+                    let bytes = &self.data[parameter.offset.min(end)..end];
+                    ui.label(format!("{} ({} bytes):", parameter.name, bytes.len()));
+                    ui.label(RichText::new(hexdump(bytes)).monospace());
+                }
+            }
+        });
+
+        if index != self.index {
+            self.read(index)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Trace transforms which are not digital filters in the strict sense.
+
+use biquad::{Biquad, Coefficients, DirectForm1, ToHertz, Type};
+use serde::{Deserialize, Serialize};
+use std::{f64::consts::PI, ops::Range};
+
+/// Quality factors of the two biquad stages of a 4th order Butterworth low-pass filter.
+const BUTTERWORTH_4_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+
+/// Anti-aliasing filter cutoff frequency, relative to the output sampling rate.
+const ANTI_ALIAS_CUTOFF: f32 = 0.45;
+
+/// Number of input samples taken on each side of an interpolated sample when upsampling.
+const INTERPOLATION_HALF_TAPS: isize = 8;
+
+/// Number of taps on each side of the FIR Hilbert transformer used for envelope extraction.
+const HILBERT_HALF_TAPS: isize = 31;
+
+/// Smooths a trace with a centered moving average (boxcar) over `window` samples.
+///
+/// Near the trace edges, the average is calculated on the available samples only, so the trace
+/// length is preserved and no time shift is introduced.
+pub fn moving_average(trace: &[f32], window: usize) -> Vec<f32> {
+    if window <= 1 || trace.is_empty() {
+        return trace.to_vec();
+    }
+    let before = (window - 1) / 2;
+    let after = window - 1 - before;
+    // Running sum accumulated in f64 to limit precision loss on long traces.
+    let mut sum: f64 = trace[..after.min(trace.len())]
+        .iter()
+        .map(|&x| x as f64)
+        .sum();
+    let mut result = Vec::with_capacity(trace.len());
+    for i in 0..trace.len() {
+        if let Some(&x) = trace.get(i + after) {
+            sum += x as f64;
+        }
+        if i > before {
+            sum -= trace[i - before - 1] as f64;
+        }
+        let start = i.saturating_sub(before);
+        let end = (i + after + 1).min(trace.len());
+        result.push((sum / (end - start) as f64) as f32);
+    }
+    result
+}
+
+/// Calculates the variance of the samples in a centered sliding window of `window` samples.
+///
+/// Activity bursts which are hidden in the density plot of a noisy trace stand out in its local
+/// variance. Near the trace edges, the variance is calculated on the available samples only, so
+/// the trace length is preserved.
+pub fn sliding_variance(trace: &[f32], window: usize) -> Vec<f32> {
+    if trace.is_empty() {
+        return Vec::new();
+    }
+    let window = window.max(1);
+    let before = (window - 1) / 2;
+    let after = window - 1 - before;
+    // Samples are centered on the trace mean to limit the cancellation in E[x²] - E[x]².
+    let mean = trace.iter().map(|&x| x as f64).sum::<f64>() / trace.len() as f64;
+    let value = |i: usize| trace[i] as f64 - mean;
+    let (mut sum, mut sum2) = (0.0, 0.0);
+    for i in 0..after.min(trace.len()) {
+        sum += value(i);
+        sum2 += value(i) * value(i);
+    }
+    let mut result = Vec::with_capacity(trace.len());
+    for i in 0..trace.len() {
+        if i + after < trace.len() {
+            let x = value(i + after);
+            sum += x;
+            sum2 += x * x;
+        }
+        if i > before {
+            let x = value(i - before - 1);
+            sum -= x;
+            sum2 -= x * x;
+        }
+        let n = ((i + after + 1).min(trace.len()) - i.saturating_sub(before)) as f64;
+        let m = sum / n;
+        result.push((sum2 / n - m * m).max(0.0) as f32);
+    }
+    result
+}
+
+/// Smooths a trace with a Savitzky–Golay filter: each sample is replaced by the value of a
+/// polynomial of degree `order` fitted by least squares on the `window` surrounding samples.
+///
+/// Peaks are better preserved than with a moving average of the same length. `window` must be odd
+/// and greater than `order`. Near the trace edges, the polynomial fitted on the first (or last)
+/// window is evaluated at the sample position, so the trace length is preserved.
+pub fn savitzky_golay(trace: &[f32], window: usize, order: usize) -> Vec<f32> {
+    assert!(
+        window % 2 == 1 && window > order,
+        "Invalid Savitzky–Golay parameters"
+    );
+    if window <= 1 || trace.len() < window {
+        return trace.to_vec();
+    }
+    let half = window / 2;
+    let convolve = |start: usize, kernel: &[f64]| -> f32 {
+        trace[start..start + window]
+            .iter()
+            .zip(kernel)
+            .map(|(&x, w)| x as f64 * w)
+            .sum::<f64>() as f32
+    };
+    let center = savitzky_golay_kernel(half, order, 0);
+    let mut result = Vec::with_capacity(trace.len());
+    for i in 0..half {
+        let kernel = savitzky_golay_kernel(half, order, i as isize - half as isize);
+        result.push(convolve(0, &kernel));
+    }
+    for i in half..trace.len() - half {
+        result.push(convolve(i - half, &center));
+    }
+    let last_start = trace.len() - window;
+    for i in 1..=half {
+        let kernel = savitzky_golay_kernel(half, order, i as isize);
+        result.push(convolve(last_start, &kernel));
+    }
+    result
+}
+
+/// Calculates the Savitzky–Golay weights giving the value at position `t` (relative to the window
+/// center) of the polynomial of degree `order` fitted on `2 * half + 1` samples.
+///
+/// The weights are `A.(AᵀA)⁻¹.e` where `A` is the Vandermonde matrix of the window positions and
+/// `e` the powers of `t`.
+fn savitzky_golay_kernel(half: usize, order: usize, t: isize) -> Vec<f64> {
+    let n = order + 1;
+    let positions: Vec<f64> = (-(half as isize)..=half as isize)
+        .map(|i| i as f64)
+        .collect();
+    // Normal equations, augmented with the right-hand side e.
+    let mut m: Vec<Vec<f64>> = (0..n)
+        .map(|j| {
+            let mut row: Vec<f64> = (0..n)
+                .map(|k| positions.iter().map(|x| x.powi((j + k) as i32)).sum())
+                .collect();
+            row.push((t as f64).powi(j as i32));
+            row
+        })
+        .collect();
+    // Gauss-Jordan elimination with partial pivoting.
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))
+            .unwrap();
+        m.swap(col, pivot);
+        let pivot_row = m[col].clone();
+        for (i, row) in m.iter_mut().enumerate() {
+            if i != col {
+                let f = row[col] / pivot_row[col];
+                for (x, p) in row.iter_mut().zip(&pivot_row).skip(col) {
+                    *x -= f * p;
+                }
+            }
+        }
+    }
+    let b: Vec<f64> = (0..n).map(|j| m[j][n] / m[j][j]).collect();
+    positions
+        .iter()
+        .map(|x| {
+            b.iter()
+                .enumerate()
+                .map(|(k, bk)| bk * x.powi(k as i32))
+                .sum()
+        })
+        .collect()
+}
+
+/// Applies a 4th order Butterworth low-pass filter in place. `cutoff` is relative to the sampling
+/// rate and must be below 0.5.
+fn low_pass_4(trace: &mut [f32], cutoff: f32) {
+    for q in BUTTERWORTH_4_Q {
+        let coeffs =
+            Coefficients::<f32>::from_params(Type::LowPass, 1.hz(), cutoff.hz(), q).unwrap();
+        let mut biquad = DirectForm1::<f32>::new(coeffs);
+        for x in trace.iter_mut() {
+            *x = biquad.run(*x);
+        }
+    }
+}
+
+/// Resamples a trace at a lower rate. `ratio` is the output sampling rate divided by the input
+/// sampling rate, and must be in `]0, 1]`. For instance, a ratio of `0.25` decimates the trace by
+/// a factor 4.
+///
+/// A 4th order Butterworth low-pass filter is applied first to prevent aliasing. Non-integer
+/// decimation factors are supported using linear interpolation.
+pub fn decimate(trace: &[f32], ratio: f64) -> Vec<f32> {
+    assert!(
+        ratio > 0.0 && ratio <= 1.0,
+        "Invalid decimation ratio {}",
+        ratio
+    );
+    if ratio == 1.0 || trace.len() < 2 {
+        return trace.to_vec();
+    }
+    let mut filtered = trace.to_vec();
+    low_pass_4(&mut filtered, ANTI_ALIAS_CUTOFF * ratio as f32);
+    let len = ((trace.len() - 1) as f64 * ratio).floor() as usize + 1;
+    (0..len)
+        .map(|i| {
+            let t = i as f64 / ratio;
+            let j = (t.floor() as usize).min(trace.len() - 1);
+            let frac = (t - j as f64) as f32;
+            if frac > 0.0 && j + 1 < filtered.len() {
+                filtered[j] + (filtered[j + 1] - filtered[j]) * frac
+            } else {
+                filtered[j]
+            }
+        })
+        .collect()
+}
+
+/// Upsamples a trace by an integer `factor` using band-limited interpolation.
+///
+/// This is a polyphase implementation of a windowed-sinc interpolation filter: one kernel is
+/// calculated for each of the `factor` output phases, and each output sample is the dot product of
+/// a kernel with the surrounding input samples. Original samples are preserved, and the trace is
+/// extended at its edges by repeating the first and last samples.
+pub fn upsample(trace: &[f32], factor: usize) -> Vec<f32> {
+    if factor <= 1 || trace.len() < 2 {
+        return trace.to_vec();
+    }
+    let taps = -(INTERPOLATION_HALF_TAPS - 1)..=INTERPOLATION_HALF_TAPS;
+    let kernels: Vec<Vec<f32>> = (0..factor)
+        .map(|phase| {
+            let frac = phase as f64 / factor as f64;
+            let kernel: Vec<f64> = taps
+                .clone()
+                .map(|j| windowed_sinc(j as f64 - frac, INTERPOLATION_HALF_TAPS as f64))
+                .collect();
+            // Normalize so the DC gain is exactly 1.
+            let sum: f64 = kernel.iter().sum();
+            kernel.iter().map(|w| (w / sum) as f32).collect()
+        })
+        .collect();
+
+    let last = trace.len() as isize - 1;
+    let mut result = Vec::with_capacity(last as usize * factor + 1);
+    for i in 0..trace.len() {
+        result.push(trace[i]);
+        if i as isize == last {
+            break;
+        }
+        for kernel in &kernels[1..] {
+            let value = taps
+                .clone()
+                .zip(kernel)
+                .map(|(j, w)| trace[(i as isize + j).clamp(0, last) as usize] * w)
+                .sum();
+            result.push(value);
+        }
+    }
+    result
+}
+
+/// Shifts a trace by a possibly fractional number of `samples`, using the band-limited
+/// interpolation of [`upsample`]: output sample `i` is the input interpolated at `i + samples`.
+/// The trace is extended at its edges by repeating the first and last samples.
+pub fn shift(trace: &[f32], samples: f64) -> Vec<f32> {
+    if trace.is_empty() {
+        return Vec::new();
+    }
+    let whole = samples.floor();
+    let frac = samples - whole;
+    let whole = whole as isize;
+    let taps = -(INTERPOLATION_HALF_TAPS - 1)..=INTERPOLATION_HALF_TAPS;
+    let kernel: Vec<f64> = taps
+        .clone()
+        .map(|j| windowed_sinc(j as f64 - frac, INTERPOLATION_HALF_TAPS as f64))
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|w| (w / sum) as f32).collect();
+
+    let last = trace.len() as isize - 1;
+    (0..trace.len() as isize)
+        .map(|i| {
+            taps.clone()
+                .zip(&kernel)
+                .map(|(j, w)| trace[(i + whole + j).clamp(0, last) as usize] * w)
+                .sum()
+        })
+        .collect()
+}
+
+/// Sinc function weighted by a Blackman window spanning `[-half_width, half_width]`.
+fn windowed_sinc(x: f64, half_width: f64) -> f64 {
+    if x.abs() >= half_width {
+        return 0.0;
+    }
+    let sinc = if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    };
+    sinc * blackman(x / half_width)
+}
+
+/// Blackman window, centered on 0 and spanning `[-1, 1]`.
+fn blackman(u: f64) -> f64 {
+    0.42 + 0.5 * (PI * u).cos() + 0.08 * (2.0 * PI * u).cos()
+}
+
+/// Extracts the envelope of a modulated trace using a FIR Hilbert transformer: the envelope is the
+/// magnitude of the analytic signal `x + j.H(x)`.
+///
+/// The transformer is centered, so the envelope is not delayed. Its bandwidth is limited at very
+/// low frequencies (relative to the sampling rate), so carriers should be well above
+/// `fs / HILBERT_HALF_TAPS`.
+pub fn envelope_hilbert(trace: &[f32]) -> Vec<f32> {
+    // Ideal Hilbert transformer impulse response is 2/(pi.n) for odd n, 0 for even n.
+    let kernel: Vec<(isize, f32)> = (1..=HILBERT_HALF_TAPS)
+        .step_by(2)
+        .map(|n| {
+            let window = blackman(n as f64 / (HILBERT_HALF_TAPS + 1) as f64);
+            (n, (2.0 / (PI * n as f64) * window) as f32)
+        })
+        .collect();
+    let last = trace.len() as isize - 1;
+    let at = |i: isize| trace[i.clamp(0, last) as usize];
+    (0..trace.len() as isize)
+        .map(|i| {
+            // The kernel is antisymmetric: h[-n] = -h[n].
+            let h: f32 = kernel
+                .iter()
+                .map(|&(n, w)| w * (at(i - n) - at(i + n)))
+                .sum();
+            let x = at(i);
+            (x * x + h * h).sqrt()
+        })
+        .collect()
+}
+
+/// Extracts the envelope of a modulated trace by rectification followed by a low-pass filter.
+/// `cutoff` is relative to the sampling rate and must be below 0.5.
+///
+/// The result is scaled by pi/2 so a sine carrier envelope matches its amplitude.
+pub fn envelope_rectify(trace: &[f32], cutoff: f32) -> Vec<f32> {
+    let mut result: Vec<f32> = trace
+        .iter()
+        .map(|x| x.abs() * std::f32::consts::FRAC_PI_2)
+        .collect();
+    low_pass_4(&mut result, cutoff);
+    result
+}
+
+/// Normalization methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    /// Subtract the mean and divide by the standard deviation.
+    ZScore,
+    /// Map the minimum to 0 and the maximum to 1.
+    MinMax,
+}
+
+impl Normalization {
+    pub fn name(&self) -> &str {
+        match self {
+            Normalization::ZScore => "Z-score",
+            Normalization::MinMax => "Min-max",
+        }
+    }
+}
+
+/// Normalizes a trace. The statistics are calculated on the samples in `window` (or on the whole
+/// trace if `None`) and the normalization is applied to the whole trace, so traces from different
+/// probes or gains can be compared on the same scale.
+pub fn normalize(trace: &[f32], method: Normalization, window: Option<Range<usize>>) -> Vec<f32> {
+    let reference = match window {
+        Some(window) => &trace[window.start.min(trace.len())..window.end.min(trace.len())],
+        None => trace,
+    };
+    if reference.is_empty() {
+        return trace.to_vec();
+    }
+    let (offset, scale) = match method {
+        Normalization::ZScore => {
+            let n = reference.len() as f64;
+            let mean = reference.iter().map(|&x| x as f64).sum::<f64>() / n;
+            let variance = reference
+                .iter()
+                .map(|&x| (x as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            (mean, variance.sqrt())
+        }
+        Normalization::MinMax => {
+            let min = reference.iter().copied().fold(f32::INFINITY, f32::min) as f64;
+            let max = reference.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+            (min, max - min)
+        }
+    };
+    // A constant reference would lead to a division by zero; only remove the offset then.
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+    trace
+        .iter()
+        .map(|&x| ((x as f64 - offset) / scale) as f32)
+        .collect()
+}
+
+/// Calculates the sample-wise mean and standard deviation of several traces.
+///
+/// Traces of different lengths are truncated to the shortest one. Returns empty traces if `traces`
+/// is empty.
+pub fn mean_std(traces: &[&[f32]]) -> (Vec<f32>, Vec<f32>) {
+    let len = traces.iter().map(|t| t.len()).min().unwrap_or(0);
+    let mut sum = vec![0.0f64; len];
+    let mut sum2 = vec![0.0f64; len];
+    for trace in traces {
+        for ((s, s2), &x) in sum.iter_mut().zip(sum2.iter_mut()).zip(trace.iter()) {
+            let x = x as f64;
+            *s += x;
+            *s2 += x * x;
+        }
+    }
+    let n = traces.len() as f64;
+    sum.iter()
+        .zip(sum2.iter())
+        .map(|(&s, &s2)| {
+            let mean = s / n;
+            let variance = (s2 / n - mean * mean).max(0.0);
+            (mean as f32, variance.sqrt() as f32)
+        })
+        .unzip()
+}
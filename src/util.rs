@@ -1,9 +1,15 @@
 use egui::{Color32, ColorImage, TextureHandle, TextureOptions, TextureWrapMode};
-use fixed::{FixedI64, types::extra::U24};
-use std::ops::{Add, Mul};
+use fixed::{FixedI128, types::extra::U40};
+use std::{
+    any::Any,
+    ops::{Add, Mul},
+};
 
 /// Fixed floating point number used by the viewer.
-pub type Fixed = FixedI64<U24>;
+///
+/// The 88 integer bits address the samples of multi-terasample traces, and the 40 fractional bits
+/// keep the camera precise when zoomed in far below one sample per pixel.
+pub type Fixed = FixedI128<U40>;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
 pub struct FixedVec2 {
@@ -53,32 +59,13 @@ pub fn generate_checkboard(ctx: &egui::Context, size: usize) -> TextureHandle {
     )
 }
 
-pub fn format_number_unit(n: usize) -> String {
-    if n < 1000 {
-        n.to_string()
-    } else if n < 1000000 {
-        format!("{:.1} k", (n as f32) / 1000.0)
-    } else if n < 1000000000 {
-        format!("{:.1} M", (n as f32) / 1e6)
+/// Returns the message of a panic caught with [`std::panic::catch_unwind`].
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
     } else {
-        format!("{:.1} G", (n as f32) / 1e9)
-    }
-}
-
-pub fn format_f64_unit(x: f64) -> String {
-    if x < 1e-6 {
-        format!("{:.3} n", x * 1e9)
-    } else if x < Fixed::from_num(1e-3) {
-        format!("{:.3} µ", x * 1e6)
-    } else if x < Fixed::from_num(1) {
-        format!("{:.3} m", x * 1e3)
-    } else if x < Fixed::from_num(1000) {
-        format!("{:.3} ", x)
-    } else if x < Fixed::from_num(1e6) {
-        format!("{:.3} k", x / 1e3)
-    } else if x < Fixed::from_num(1e9) {
-        format!("{:.3} M", x / 1e6)
-    } else {
-        format!("{:.3} G", x / 1e9)
+        "unknown error".to_string()
     }
 }
@@ -1,46 +1,134 @@
 use crate::{
-    camera::Camera,
+    automation::Command,
+    camera::{BOOKMARK_SLOTS, Bookmark, Camera},
+    clock::ClockGrid,
+    colorizer::{TileColorizer, TileTexture},
+    config::KeyBindings,
+    digital::{DigitalChannel, LogicChannel},
+    events::{Event, paint_events, paint_trigger},
+    export::{ExportDialog, ImageSettings, render_image, samples_to_csv, write_npy, write_png},
+    filter_designer::{FilterDesigner, FilterDesignerResult},
+    filter_preview::{
+        FilterPreview, PREVIEW_MARGIN, PREVIEW_MAX_SAMPLES, PreviewCalculation, PreviewKey,
+        PreviewSamples,
+    },
+    filtering::FilterSpec,
+    format::NumberFormat,
+    frame_browser::FrameBrowser,
+    input::FileSettings,
+    layout::Layout,
+    live::{Live, LiveUpdate},
+    loaders::{FrameFile, STDIN_PATH, TrsFile},
+    overlay::Overlays,
+    playback::Playback,
+    processing::{NewTrace, ProcessingJob, ProcessingStep, apply_steps, steps_rate_ratio},
+    processing_menu::{ProcessingAction, ProcessingMenu},
+    rate_estimation::{RateEstimate, RateEstimator},
+    regions::{ExportInfo, Region, Regions, TraceSource},
     renderer::RENDERER_MAX_TRACE_SIZE,
+    script::{ScriptDialog, ScriptDialogResult},
     sync_features::SyncFeatures,
-    tiling::{ColorScale, Gradient, TileProperties, TileSize, TileStatus, Tiling},
-    util::{Fixed, format_f64_unit, format_number_unit, generate_checkboard},
+    tiling::{
+        ColorScale, Gradient, GradientKind, SharedTraces, TileProperties, TileSize, TileStatus,
+        Tiling,
+    },
+    tiling_policy::{TILE_WIDTH, TilingPolicy},
+    trace::{Trace, TraceChunk},
+    trace_data::TraceDataPanel,
+    util::{Fixed, generate_checkboard},
 };
 use egui::{
     Align, Align2, Color32, DragValue, FontFamily, Key, Painter, PointerButton, Popup,
-    PopupCloseBehavior, Rect, Sense, Shape, Stroke, TextFormat, TextureHandle, TextureOptions, Ui,
-    pos2, text::LayoutJob, vec2,
+    PopupCloseBehavior, ProgressBar, Rect, Sense, Shape, Stroke, TextFormat, TextureHandle,
+    TextureId, TextureOptions, Ui, pos2, text::LayoutJob, vec2,
 };
 use std::{
-    collections::HashMap,
-    ops::Add,
+    collections::{HashMap, HashSet},
+    io,
+    ops::Range,
     path::Path,
     sync::{Arc, Condvar, Mutex},
+    time::Duration,
 };
 
-/// Defines the width of the tiles rendered by the GPU.
-/// A smaller value will raise the number of required tiles to fill the screen, the number of GPU
-/// calls will increase and therefore the overall rendering might be slower due to this overhead.
-/// A higher value can lead to insufficient GPU memory to store a trace slice for rendering a tile,
-/// and therefore the minimum zoom level may be very limited.
-/// The current value seems to be a good compromise.
-const TILE_WIDTH: u32 = 64;
-
 /// Minimum zoom level that can be rendered by the GPU.
 const MIN_SCALE_X: usize = (RENDERER_MAX_TRACE_SIZE - 1) / TILE_WIDTH as usize;
 
 /// Defines the zoom limit between antialiased lines display and density rendering.
 const LINES_RENDERING_SCALE_LIMIT: f32 = 5.0;
 
+/// Decay rate of the kinetic panning velocity, per second. The trace glides about
+/// `1 / PAN_DECAY` second after the drag is released.
+const PAN_DECAY: f32 = 3.0;
+
+/// Velocity of the kinetic panning below which the trace stops gliding, in points per second.
+const MIN_PAN_VELOCITY: f32 = 20.0;
+
+/// Keys of the bookmark slots: with Ctrl (Cmd on macOS) they store the camera in the slot,
+/// without they recall it.
+const BOOKMARK_KEYS: [Key; BOOKMARK_SLOTS] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+
 pub struct Viewer {
     /// Viewer identifier used to distinguish tiles in the shared tiling in case there are multiple
     /// viewers.
     id: u32,
     /// The trace being displayed.
-    trace: Arc<Vec<f32>>,
+    trace: Arc<Trace>,
+    /// The trace as it was loaded, before any runtime processing. Kept so the filter can be
+    /// changed or removed without reloading the file.
+    original_trace: Arc<Trace>,
+    /// Traces of all viewers, shared with the tiles renderers. Updated when the trace of this
+    /// viewer is replaced.
+    shared_traces: SharedTraces,
+    /// Revision of the displayed trace, incremented each time the trace is replaced.
+    generation: u32,
+    /// Sampling rate of the displayed trace divided by the sampling rate of the original trace.
+    /// Differs from 1 when the trace is resampled by the processing steps.
+    rate_ratio: f64,
+    /// Processing steps applied at runtime to the original trace, in order.
+    steps: Vec<ProcessingStep>,
+    /// Processing steps which produced the displayed trace. Differs from `steps` while the
+    /// processing is running, and restored if it is cancelled.
+    applied_steps: Vec<ProcessingStep>,
+    /// True while the load-time processing given on the command line is running. Its result
+    /// replaces the original trace, so the loaded samples are not kept in memory.
+    load_processing: bool,
+    /// Parameters of the processing menu.
+    processing_menu: ProcessingMenu,
+    /// Sampling rate estimation from a known tone.
+    rate_estimator: RateEstimator,
+    /// Clock cycles of the trace, for stepping the camera cycle by cycle.
+    clock: Option<ClockGrid>,
+    /// Scrolling of the camera through the trace, like a recording.
+    playback: Playback,
+    /// Filter configuration dialog, when opened.
+    filter_designer: Option<FilterDesigner>,
+    /// Index of the filter step edited by the filter designer, or `None` if the designer adds a
+    /// new filter at the end of the processing chain.
+    edited_step: Option<usize>,
+    /// Preview of the filter edited in the designer, on the visible samples.
+    filter_preview: FilterPreview,
+    /// Trace processing running in background, if any.
+    processing: Option<ProcessingJob>,
     /// Display label for this trace (e.g. "file.wfm" or "file.wfm [frame 3]").
     label: String,
     /// Current camera settings.
     camera: Camera,
+    /// Horizontal velocity of the trace gliding after a drag is released, in points per second.
+    /// Zero when the trace is not gliding.
+    pan_velocity: f32,
+    /// Cameras stored in the bookmark slots.
+    bookmarks: [Option<Bookmark>; BOOKMARK_SLOTS],
     /// Rendering tiles shared between the user interface and the GPU tiles renderer.
     shared_tiling: Arc<(Mutex<Tiling>, Condvar)>,
     /// Current tool for mouse left button
@@ -49,14 +137,38 @@ pub struct Viewer {
     tool_step: u8,
     /// Time selected by the tool.
     tool_times: Vec<Fixed>,
+    /// Set when the selection is mirrored from another viewer, so it does not follow the mouse
+    /// in this one. Cleared when the user clicks in the viewer.
+    tool_mirrored: bool,
+    /// Tool and selection when last compared or mirrored, to detect the changes done by the user.
+    synced_tool: ToolState,
+    /// Regions when last compared or mirrored, to detect the changes done by the user.
+    synced_regions: Vec<Region>,
     /// Defines how to calculate pixel colors depending on the density data calculated by the GPU.
     color_scale: ColorScale,
     /// Used to detect changes in color_scale so we can discard the texture cache.
     previous_color_scale: ColorScale,
+    /// Color scale replaced by a tint while the trace is overlaid with the other traces, restored
+    /// once they are not overlaid anymore.
+    overlay_color_scale: Option<ColorScale>,
+    /// Keyboard shortcuts.
+    keys: KeyBindings,
+    /// Formatting of the displayed numbers.
+    format: NumberFormat,
+    /// Chooses the width of the tiles covering the viewport.
+    tiling_policy: TilingPolicy,
+    /// Sample the user zoomed on, around which narrower tiles are rendered first.
+    focus: Option<Fixed>,
     /// Textures created from the tiles rendered by the GPU, after the color scale has been
     /// applied. This is kind of a cache to avoid creating the textures at each egui rendering.
     /// If the color scale changes, the texture cache is discarded.
-    textures: HashMap<TileProperties, TextureHandle>,
+    textures: HashMap<TileProperties, TileTexture>,
+    /// Colors the textures of the tiles on the GPU when the interface is painted with wgpu.
+    /// Otherwise they are colored by the CPU.
+    colorizer: Option<Arc<TileColorizer>>,
+    /// Textures of the tiles invalidated by samples appended to the trace. They are painted until
+    /// their tiles are rendered again.
+    outdated_textures: HashSet<TileProperties>,
     /// The texture used to draw the background checkboard.
     /// This texture is not loaded from a file but generated during initialization.
     texture_checkboard: TextureHandle,
@@ -65,8 +177,90 @@ pub struct Viewer {
     trace_min_max: [f32; 2],
     /// When true, the viewer will change scale and offset so the trace fits the screen.
     autoscale_request: bool,
+    /// When true, the viewer will change the vertical scale and offset so the visible samples fit
+    /// the screen.
+    autoscale_y_request: bool,
+    /// Set when the user asks for autoscaling all the traces vertically. The request is handled
+    /// by the [`crate::multi_viewer::MultiViewer`].
+    autoscale_all_request: bool,
     /// Trace sampling rate in MS/s
     sampling_rate: f32,
+    /// When set, the current Range selection is copied to the clipboard during the next update.
+    copy_request: Option<CopyFormat>,
+    /// Dialog for exporting the current Range selection to a file, when opened.
+    export_dialog: Option<ExportDialog>,
+    /// Set when the user asks for creating a new trace from an expression. The dialog is handled
+    /// by the [`crate::multi_viewer::MultiViewer`] since the expression can use all the traces.
+    expression_request: bool,
+    /// Dialog for processing the trace with an external script, when opened.
+    script_dialog: Option<ScriptDialog>,
+    /// Trace created by a script or a processing step, to be displayed by a new viewer.
+    new_trace: Option<NewTrace>,
+    /// Processing step applied in background to the displayed trace, whose result is displayed
+    /// by a new viewer.
+    new_view_job: Option<(ProcessingJob, ProcessingStep)>,
+    /// Horizontal offset of the trace in samples, kept when the camera shift is synchronized with
+    /// other viewers. Set by the alignment of repeated captures.
+    x_offset: Fixed,
+    /// Logic level conversion of the trace, displayed under the waveform when enabled.
+    digital: Option<DigitalChannel>,
+    /// Digital channels captured with the trace, displayed above its logic level conversion.
+    logic: Vec<LogicChannel>,
+    /// Analysis results painted over the trace.
+    overlays: Overlays,
+    /// Labeled regions of the trace.
+    regions: Regions,
+    /// File the trace has been loaded from, written with the exported regions.
+    source: Option<TraceSource>,
+    /// Load settings of the file of the trace, for loading it again. Not set for the traces
+    /// computed from the files, such as the frame statistics.
+    reload: Option<FileSettings>,
+    /// Set when the user asks for loading the trace again from its file. The file is loaded by
+    /// the [`crate::multi_viewer::MultiViewer`].
+    reload_request: bool,
+    /// Live source replacing the trace with the last received traces.
+    live: Option<Live>,
+    /// Events marked as vertical lines, shared by all the viewers.
+    events: Arc<Vec<Event>>,
+    show_events: bool,
+    /// Trigger sample recorded in the header of the file, in the original trace samples.
+    trigger: Option<f64>,
+    /// Data bytes of the trace, for traces loaded from a TRS file.
+    trace_data: Option<TraceDataPanel>,
+    /// Navigation between the frames of the file, for multi-frame files opened with `--browse`.
+    frame_browser: Option<FrameBrowser>,
+    /// Set when the user asks for the difference between this trace and another one. The dialog
+    /// is handled by the [`crate::multi_viewer::MultiViewer`].
+    difference_request: bool,
+    /// Set when the user closes the viewer from its toolbar. The viewer is removed by the
+    /// [`crate::multi_viewer::MultiViewer`].
+    close_request: bool,
+    /// Failures of the background processing, reported by the
+    /// [`crate::multi_viewer::MultiViewer`].
+    failures: Vec<String>,
+    /// Set when the user asks for aligning this trace to another one. The dialog is handled by
+    /// the [`crate::multi_viewer::MultiViewer`].
+    align_request: bool,
+    /// Set when the user asks for aligning all the other traces to the Range selection of this
+    /// trace. The dialog is handled by the [`crate::multi_viewer::MultiViewer`].
+    align_all_request: bool,
+    /// Set when the user asks for warping this trace onto another one. The dialog is handled by
+    /// the [`crate::multi_viewer::MultiViewer`].
+    dtw_request: bool,
+    /// Path of the multi-trace file the trace is a frame of.
+    frame_group: Option<String>,
+    /// Set when the user asks for the statistics of the frames of the file. They are calculated
+    /// by the [`crate::multi_viewer::MultiViewer`].
+    frame_statistics_request: bool,
+    /// Set when the user asks for comparing the averages of two groups of traces. The dialog is
+    /// handled by the [`crate::multi_viewer::MultiViewer`].
+    group_average_request: bool,
+    /// Set when the viewer is displayed in its own window instead of the main window. The
+    /// windows are handled by the [`crate::multi_viewer::MultiViewer`].
+    detached: bool,
+    /// Height of the viewer in the main window, relative to the other viewers: 1 by default,
+    /// changed with the splitters between the viewers.
+    height: f32,
 }
 
 impl Viewer {
@@ -76,44 +270,101 @@ impl Viewer {
         id: u32,
         ctx: &egui::Context,
         shared_tiling: Arc<(Mutex<Tiling>, Condvar)>,
+        shared_traces: SharedTraces,
         label: String,
-        trace: Arc<Vec<f32>>,
         sampling_rate: f32,
+        steps: Vec<ProcessingStep>,
     ) -> Self {
-        let trace_min_max = [
-            trace
-                .iter()
-                .cloned()
-                .min_by(f32::total_cmp)
-                .expect("Trace has NaN sample"),
-            trace
-                .iter()
-                .cloned()
-                .max_by(f32::total_cmp)
-                .expect("Trace has NaN sample"),
-        ];
-        let color_scale = ColorScale {
-            power: 1.0,
-            opacity: 10.0,
-            gradient: Gradient::Rainbow,
-        };
-        Self {
+        let trace = shared_traces.read().unwrap()[id as usize].clone();
+        let trace_min_max = trace.min_max(0..trace.len());
+        let color_scale = ColorScale::default();
+        let mut viewer = Self {
             id,
+            original_trace: trace.clone(),
             trace,
+            shared_traces,
+            generation: 0,
+            rate_ratio: 1.0,
+            steps: Vec::new(),
+            applied_steps: Vec::new(),
+            load_processing: false,
+            processing_menu: ProcessingMenu::new(),
+            rate_estimator: RateEstimator::new(),
+            clock: None,
+            playback: Playback::new(),
+            filter_designer: None,
+            edited_step: None,
+            filter_preview: FilterPreview::default(),
+            processing: None,
             label,
             camera: Camera::new(),
+            pan_velocity: 0.0,
+            bookmarks: [None; BOOKMARK_SLOTS],
             shared_tiling,
             tool: Tool::Move,
             tool_step: 0,
             tool_times: Vec::new(),
+            tool_mirrored: false,
+            synced_tool: ToolState {
+                tool: Tool::Move,
+                step: 0,
+                times: Vec::new(),
+            },
+            synced_regions: Vec::new(),
             color_scale,
             previous_color_scale: color_scale,
+            overlay_color_scale: None,
+            keys: KeyBindings::default(),
+            format: NumberFormat::default(),
+            tiling_policy: TilingPolicy::default(),
+            focus: None,
             textures: HashMap::default(),
+            colorizer: TileColorizer::get(ctx),
+            outdated_textures: HashSet::default(),
             texture_checkboard: generate_checkboard(ctx, 64),
-            trace_min_max,
+            trace_min_max: *trace_min_max.as_ref().unwrap_or(&[0.0; 2]),
             autoscale_request: true,
+            autoscale_y_request: false,
+            autoscale_all_request: false,
             sampling_rate,
+            copy_request: None,
+            export_dialog: None,
+            expression_request: false,
+            script_dialog: None,
+            new_trace: None,
+            new_view_job: None,
+            x_offset: Fixed::ZERO,
+            align_request: false,
+            align_all_request: false,
+            dtw_request: false,
+            frame_group: None,
+            frame_statistics_request: false,
+            group_average_request: false,
+            difference_request: false,
+            close_request: false,
+            failures: trace_min_max.err().map(read_failure).into_iter().collect(),
+            reload: None,
+            reload_request: false,
+            detached: false,
+            height: 1.0,
+            digital: None,
+            logic: Vec::new(),
+            overlays: Overlays::new(),
+            events: Arc::new(Vec::new()),
+            show_events: true,
+            trigger: None,
+            regions: Regions::new(),
+            source: None,
+            live: None,
+            trace_data: None,
+            frame_browser: None,
+        };
+        if !steps.is_empty() {
+            viewer.steps = steps;
+            viewer.process(ctx);
+            viewer.load_processing = true;
         }
+        viewer
     }
 
     pub fn get_camera(&self) -> &Camera {
@@ -126,10 +377,660 @@ impl Viewer {
         }
     }
 
+    /// Identifier of the trace of the viewer, in the traces shared with the renderers.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+    }
+
+    /// Returns the displayed trace.
+    pub fn trace(&self) -> Arc<Trace> {
+        self.trace.clone()
+    }
+
+    /// Returns true once if the user asked for creating a new trace from an expression.
+    pub fn take_expression_request(&mut self) -> bool {
+        std::mem::take(&mut self.expression_request)
+    }
+
+    /// Returns the trace created by a script or a processing step, if any. It is returned only
+    /// once.
+    pub fn take_new_trace(&mut self) -> Option<NewTrace> {
+        self.new_trace.take()
+    }
+
+    /// Returns true once if the user asked for the difference between this trace and another one.
+    pub fn take_difference_request(&mut self) -> bool {
+        std::mem::take(&mut self.difference_request)
+    }
+
+    /// Returns the active tool and its selection.
+    pub fn tool_state(&self) -> ToolState {
+        ToolState {
+            tool: self.tool,
+            step: self.tool_step,
+            times: self.tool_times.clone(),
+        }
+    }
+
+    /// Returns true if the tool or its selection changed since the last call, or since they were
+    /// mirrored from another viewer.
+    pub fn take_tool_change(&mut self) -> bool {
+        let state = self.tool_state();
+        let changed = state != self.synced_tool;
+        self.synced_tool = state;
+        changed
+    }
+
+    /// Mirrors the tool `state` of another viewer: the tool when `features.tool` is set, and the
+    /// selection when `features.selection` is set and the tools are the same. `offset` is added to
+    /// the selected times, so that aligned traces get the same selection.
+    pub fn mirror_tool(&mut self, state: &ToolState, features: SyncFeatures, offset: Fixed) {
+        if features.tool && self.tool != state.tool {
+            self.tool = state.tool;
+            self.tool_times.clear();
+            self.tool_step = 0;
+        }
+        if features.selection && self.tool == state.tool {
+            self.tool_step = state.step;
+            self.tool_times = state.times.iter().map(|&t| t + offset).collect();
+            self.tool_mirrored = true;
+        }
+        self.synced_tool = self.tool_state();
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions.items
+    }
+
+    /// Returns true if the regions changed since the last call, or since they were mirrored from
+    /// another viewer.
+    pub fn take_regions_change(&mut self) -> bool {
+        let changed = self.regions.items != self.synced_regions;
+        if changed {
+            self.synced_regions = self.regions.items.clone();
+        }
+        changed
+    }
+
+    /// Replaces the regions by the ones of another viewer, shifted by `offset` samples.
+    pub fn mirror_regions(&mut self, regions: &[Region], offset: f64) {
+        self.regions.items = regions
+            .iter()
+            .map(|region| Region {
+                start: region.start + offset,
+                end: region.end + offset,
+                label: region.label.clone(),
+            })
+            .collect();
+        self.synced_regions = self.regions.items.clone();
+    }
+
+    /// Returns true once if the user closed the viewer.
+    pub fn take_close_request(&mut self) -> bool {
+        std::mem::take(&mut self.close_request)
+    }
+
+    /// Returns the failures of the background processing since the previous call.
+    pub fn take_failures(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.failures)
+    }
+
+    /// Returns the color scale of the density rendering, the one restored after the overlay when
+    /// the trace is overlaid with the others.
+    pub fn color_scale(&self) -> ColorScale {
+        self.overlay_color_scale.unwrap_or(self.color_scale)
+    }
+
+    /// Sets the color scale of the density rendering.
+    pub fn set_color_scale(&mut self, color_scale: ColorScale) {
+        self.color_scale = color_scale;
+    }
+
+    /// Returns the tint of the trace when it is overlaid with the other traces.
+    pub fn overlay_color(&self) -> Option<Color32> {
+        match self.color_scale.gradient {
+            Gradient::Tint { color } if self.overlay_color_scale.is_some() => Some(color),
+            _ => None,
+        }
+    }
+
+    pub fn is_overlaid(&self) -> bool {
+        self.overlay_color_scale.is_some()
+    }
+
+    /// Tints the trace with `color` when it is overlaid with the other traces, or restores its
+    /// color scale when `color` is `None`.
+    pub fn set_overlay_color(&mut self, color: Option<Color32>) {
+        match color {
+            Some(color) => {
+                self.overlay_color_scale.get_or_insert(self.color_scale);
+                self.color_scale.gradient = Gradient::Tint { color };
+            }
+            None => {
+                if let Some(color_scale) = self.overlay_color_scale.take() {
+                    self.color_scale = color_scale;
+                }
+            }
+        }
+    }
+
+    /// Sets the keyboard shortcuts.
+    pub fn set_keys(&mut self, keys: KeyBindings) {
+        self.keys = keys;
+    }
+
+    /// Sets the formatting of the displayed numbers.
+    pub fn set_format(&mut self, format: NumberFormat) {
+        self.format = format;
+    }
+
+    /// Sets how the width of the tiles is chosen.
+    pub fn set_tiling_policy(&mut self, policy: TilingPolicy) {
+        self.tiling_policy = policy;
+    }
+
+    /// Revision of the displayed trace, incremented each time it is replaced.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Replaces the original trace and applies the processing steps to the new one. Used by
+    /// viewers displaying a trace derived from other viewers, when their sources change.
+    pub fn set_original_trace(&mut self, ctx: &egui::Context, trace: Arc<Trace>) {
+        self.original_trace = trace;
+        self.process(ctx);
+    }
+
+    /// Returns true once if the user asked for aligning this trace to another one.
+    pub fn take_align_request(&mut self) -> bool {
+        std::mem::take(&mut self.align_request)
+    }
+
+    /// Returns true once if the user asked for aligning all the other traces to the Range
+    /// selection of this trace.
+    pub fn take_align_all_request(&mut self) -> bool {
+        std::mem::take(&mut self.align_all_request)
+    }
+
+    /// Path of the multi-trace file the trace is a frame of, if any.
+    pub fn frame_group(&self) -> Option<&str> {
+        self.frame_group.as_deref()
+    }
+
+    /// Sets the file the trace has been loaded from, and its frame index in multi-trace files.
+    pub fn set_source(&mut self, path: String, frame: Option<usize>) {
+        self.source = Some(TraceSource { path, frame });
+    }
+
+    /// Allows loading the trace again from its file, with the load `settings` of the file.
+    pub fn set_reload(&mut self, settings: FileSettings) {
+        self.reload = Some(settings);
+    }
+
+    /// Returns the file of the trace, its frame index in multi-trace files and its load settings,
+    /// if the trace can be loaded again.
+    pub fn reload_source(&self) -> Option<(&str, Option<usize>, &FileSettings)> {
+        let source = self.source.as_ref().filter(|s| s.path != STDIN_PATH)?;
+        Some((&source.path, source.frame, self.reload.as_ref()?))
+    }
+
+    /// Returns true once if the user asked for loading the trace again from its file.
+    pub fn take_reload_request(&mut self) -> bool {
+        std::mem::take(&mut self.reload_request)
+    }
+
+    /// Loads the trace again in memory if its samples are mapped from its file, before the file
+    /// is watched: reading the samples of a mapped file truncated by another process crashes the
+    /// application.
+    pub fn unmap(&mut self) {
+        if self.original_trace.is_mapped() && self.reload_source().is_some() {
+            self.reload_request = true;
+        }
+    }
+
+    /// Identifies the trace in the saved bookmarks: the absolute path of its file, followed by
+    /// its frame index in multi-trace files. `None` if the trace has not been loaded from a file.
+    pub fn bookmark_key(&self) -> Option<String> {
+        let source = self.source.as_ref().filter(|s| s.path != STDIN_PATH)?;
+        let path = std::path::absolute(&source.path)
+            .map_or_else(|_| source.path.clone(), |p| p.to_string_lossy().to_string());
+        Some(match source.frame {
+            Some(frame) => format!("{} [frame {}]", path, frame),
+            None => path,
+        })
+    }
+
+    pub fn bookmarks(&self) -> &[Option<Bookmark>; BOOKMARK_SLOTS] {
+        &self.bookmarks
+    }
+
+    /// Restores bookmarks saved in the settings. Their scales are clamped to the zoom limits, so
+    /// edited or corrupted settings cannot recall a null scale.
+    pub fn set_bookmarks(&mut self, bookmarks: [Option<Bookmark>; BOOKMARK_SLOTS]) {
+        self.bookmarks = bookmarks.map(|bookmark| bookmark.map(|b| bookmark_camera(b).into()));
+    }
+
+    /// Displays the last traces received from `live`, as they arrive.
+    pub fn set_live(&mut self, live: Live) {
+        self.live = Some(live);
+    }
+
+    /// Marks the trace as a frame of the multi-trace file at `path`.
+    pub fn set_frame_group(&mut self, path: String) {
+        self.frame_group = Some(path);
+    }
+
+    /// Returns true once if the user asked for the statistics of the frames of the file.
+    pub fn take_frame_statistics_request(&mut self) -> bool {
+        std::mem::take(&mut self.frame_statistics_request)
+    }
+
+    /// Returns true once if the user asked for comparing the averages of two groups of traces.
+    pub fn take_group_average_request(&mut self) -> bool {
+        std::mem::take(&mut self.group_average_request)
+    }
+
+    /// Returns true once if the user asked for warping this trace onto another one.
+    pub fn take_dtw_request(&mut self) -> bool {
+        std::mem::take(&mut self.dtw_request)
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Moves the viewer to its own window, or back to the main window.
+    pub fn set_detached(&mut self, detached: bool) {
+        self.detached = detached;
+    }
+
+    /// Height of the viewer in the main window, relative to the other viewers.
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    pub fn set_height(&mut self, height: f32) {
+        self.height = height;
+    }
+
+    /// Horizontal offset of the trace, in samples.
+    pub fn x_offset(&self) -> Fixed {
+        self.x_offset
+    }
+
+    /// Changes the horizontal offset of the trace. The camera is moved by the offset difference,
+    /// so the trace moves relatively to the traces of the other viewers.
+    pub fn set_x_offset(&mut self, offset: Fixed) {
+        self.camera.shift.x += offset - self.x_offset;
+        self.x_offset = offset;
+    }
+
+    /// Paints `samples` over the trace, with its own vertical scale. The samples are spread over
+    /// the whole trace.
+    pub fn add_overlay(&mut self, label: String, samples: Arc<Vec<f32>>) {
+        self.overlays.add(label, samples, self.trace.len());
+    }
+
+    /// Marks `events` as vertical lines over the trace.
+    pub fn set_events(&mut self, events: Arc<Vec<Event>>) {
+        self.events = events;
+    }
+
+    /// Marks the trigger recorded by the oscilloscope at sample `sample` of the original trace.
+    pub fn set_trigger(&mut self, sample: Option<f64>) {
+        self.trigger = sample;
+    }
+
+    /// Sets the digital channels captured with the trace.
+    pub fn set_logic_channels(&mut self, channels: Vec<LogicChannel>) {
+        self.logic = channels;
+    }
+
+    /// Allows inspecting the data bytes of the trace, loaded as frame `index` of the TRS `file`.
+    pub fn set_trace_data(&mut self, file: Arc<TrsFile>, index: usize) {
+        self.trace_data = Some(TraceDataPanel::new(
+            egui::Id::new(("trace_data", self.id)),
+            file,
+            index,
+        ));
+    }
+
+    /// Allows navigating between the frames of `file` loaded from `path`, the trace being frame
+    /// `index`.
+    pub fn set_frame_browser(&mut self, path: String, file: Arc<dyn FrameFile>, index: usize) {
+        self.frame_browser = Some(FrameBrowser::new(path, file, index));
+    }
+
+    /// Appends a processing step and processes the trace again.
+    pub fn add_step(&mut self, ctx: &egui::Context, step: ProcessingStep) {
+        self.steps.push(step);
+        self.process(ctx);
+    }
+
+    /// Corrects the sampling rate so the displayed trace is sampled at `rate` MS/s. The
+    /// processing is applied again, as filter coefficients depend on the sampling rate.
+    fn set_trace_sampling_rate(&mut self, ctx: &egui::Context, rate: f32) {
+        self.sampling_rate = (rate as f64 / self.rate_ratio) as f32;
+        if !self.steps.is_empty() {
+            self.process(ctx);
+        }
+    }
+
+    /// Sampling rate of the loaded trace, in MS/s.
+    pub fn sampling_rate(&self) -> f32 {
+        self.sampling_rate
+    }
+
+    /// Replaces the sampling rate of the original trace, in MS/s, before a trace recorded at
+    /// another rate is given to [`Viewer::set_original_trace`].
+    pub fn set_sampling_rate(&mut self, sampling_rate: f32) {
+        self.sampling_rate = sampling_rate;
+    }
+
+    /// Sampling rate of the displayed trace, in MS/s.
+    pub fn trace_sampling_rate(&self) -> f32 {
+        (self.sampling_rate as f64 * self.rate_ratio) as f32
+    }
+
+    /// Replaces the trace of a strip chart by `trace`, made of the samples of the current trace,
+    /// without the `dropped` first ones, followed by the received samples from `start`. The
+    /// displayed samples keep their position on screen. Without processing, only the tiles
+    /// covering the received samples are rendered again, or all of them if samples have been
+    /// dropped. Processed traces are processed again entirely.
+    fn append_samples(&mut self, ctx: &egui::Context, trace: Trace, start: usize, dropped: usize) {
+        let trace = Arc::new(trace);
+        if dropped > 0 {
+            let shift = Fixed::from_num(dropped as f64 * self.rate_ratio);
+            self.camera.shift.x -= shift;
+            for t in self.tool_times.iter_mut() {
+                *t -= shift;
+            }
+        }
+        if !self.steps.is_empty() || self.processing.is_some() {
+            self.set_original_trace(ctx, trace);
+            return;
+        }
+        // Infinite and NaN samples are drawn out of the viewport, and ignored by the autoscale.
+        if let TraceChunk::F32(samples) = trace.read_chunk(start..trace.len()).chunk() {
+            for &sample in samples.iter().filter(|sample| sample.is_finite()) {
+                self.trace_min_max = [
+                    self.trace_min_max[0].min(sample),
+                    self.trace_min_max[1].max(sample),
+                ];
+            }
+        }
+        self.shared_traces.write().unwrap()[self.id as usize] = trace.clone();
+        self.original_trace = trace.clone();
+        self.trace = trace;
+        let invalidated = self
+            .shared_tiling
+            .0
+            .lock()
+            .unwrap()
+            .invalidate_from(self.id, if dropped > 0 { 0 } else { start });
+        self.outdated_textures.extend(invalidated);
+        self.shared_tiling.1.notify_one();
+    }
+
+    /// Replaces the displayed trace. Tiles rendered from the previous trace remain displayed as a
+    /// preview until the new ones are rendered.
+    ///
+    /// `rate_ratio` is the sampling rate of the new trace divided by the sampling rate of the
+    /// original trace. If the sampling rate changes, the camera and the tools are rescaled so the
+    /// same time interval remains displayed.
+    fn set_trace(&mut self, trace: Arc<Trace>, rate_ratio: f64) {
+        if rate_ratio != self.rate_ratio {
+            let k = Fixed::from_num(rate_ratio / self.rate_ratio);
+            self.camera.shift.x *= k;
+            self.camera.scale.x = (self.camera.scale.x * k)
+                .clamp(Fixed::from_num(0.01), Fixed::from_num(MIN_SCALE_X));
+            for t in self.tool_times.iter_mut() {
+                *t *= k;
+            }
+            self.x_offset *= k;
+            self.overlays.rescale(rate_ratio / self.rate_ratio);
+            self.regions.rescale(rate_ratio / self.rate_ratio);
+            if let Some(clock) = &mut self.clock {
+                clock.rescale(rate_ratio / self.rate_ratio);
+            }
+            self.rate_ratio = rate_ratio;
+        }
+        match trace.min_max(0..trace.len()) {
+            Ok(min_max) => self.trace_min_max = min_max,
+            Err(e) => self.failures.push(read_failure(e)),
+        }
+        self.shared_traces.write().unwrap()[self.id as usize] = trace.clone();
+        self.trace = trace;
+        self.generation += 1;
+    }
+
+    /// Opens the filter designer, editing the filter step at index `step`, or adding a new filter
+    /// if `None`.
+    fn open_filter_designer(&mut self, step: Option<usize>) {
+        let current = step.and_then(|i| match self.steps[i] {
+            ProcessingStep::Filter(spec) => Some(spec),
+            _ => None,
+        });
+        self.filter_designer = Some(FilterDesigner::new(
+            egui::Id::new(("filter_designer", self.id)),
+            current,
+            self.format,
+        ));
+        self.edited_step = step;
+    }
+
+    /// Sampling rate in MS/s of the trace given to the edited filter: the rate of the trace before
+    /// the edited step, or the rate of the processed trace for a new filter.
+    fn filter_sampling_rate(&self) -> f32 {
+        let position = self.edited_step.unwrap_or(self.steps.len());
+        (self.sampling_rate as f64 * steps_rate_ratio(&self.steps[..position])) as f32
+    }
+
+    /// Sets or removes the filter edited by the designer. An edited filter is replaced in place,
+    /// otherwise the filter is added after the other processing steps.
+    fn set_filter(&mut self, ctx: &egui::Context, filter: Option<FilterSpec>) {
+        match (self.edited_step, filter) {
+            (Some(i), Some(spec)) => self.steps[i] = ProcessingStep::Filter(spec),
+            (Some(i), None) => {
+                self.steps.remove(i);
+            }
+            (None, Some(spec)) => self.steps.push(ProcessingStep::Filter(spec)),
+            (None, None) => {}
+        }
+        self.process(ctx);
+    }
+
+    /// Starts processing the original trace in background with the current processing steps. The
+    /// processed trace replaces the current one once the processing is finished.
+    fn process(&mut self, ctx: &egui::Context) {
+        // Once the user edits the processing, the loaded samples are kept so the processing can
+        // be changed again.
+        self.load_processing = false;
+        if self.steps.is_empty() {
+            self.processing = None;
+            self.applied_steps.clear();
+            self.set_trace(self.original_trace.clone(), 1.0);
+            return;
+        }
+        let original = self.original_trace.clone();
+        let steps = self.steps.clone();
+        let sampling_rate = self.sampling_rate;
+        self.processing = Some(ProcessingJob::try_spawn(ctx, move |progress| {
+            apply_steps(&original, &steps, sampling_rate, progress)
+        }));
+    }
+
+    /// Aborts the running processing, keeping the displayed trace and its processing steps.
+    fn cancel_processing(&mut self) {
+        // Dropping the job cancels it.
+        self.processing = None;
+        self.steps = self.applied_steps.clone();
+        self.load_processing = false;
+    }
+
+    /// Called when the processing is finished, with the processed trace.
+    fn processing_finished(&mut self, trace: Vec<f32>) {
+        self.processing = None;
+        self.set_trace(Arc::new(trace.into()), steps_rate_ratio(&self.steps));
+        self.applied_steps = self.steps.clone();
+        if self.load_processing {
+            // The load-time processing is part of the trace as loaded: the processed trace
+            // becomes the original one, at the new sampling rate.
+            self.load_processing = false;
+            self.original_trace = self.trace.clone();
+            self.sampling_rate = self.trace_sampling_rate();
+            self.rate_ratio = 1.0;
+            self.steps.clear();
+            self.applied_steps.clear();
+            self.autoscale_request = true;
+        }
+    }
+
+    /// True while the viewer is processing its trace or waiting to autoscale. Automation commands
+    /// wait for the viewers to be ready.
+    pub fn is_busy(&self) -> bool {
+        self.processing.is_some() || self.autoscale_request
+    }
+
+    /// Fits the visible samples vertically at the next update.
+    pub fn request_autoscale_y(&mut self) {
+        self.autoscale_y_request = true;
+    }
+
+    /// Returns true once if the user asked for autoscaling all the traces vertically.
+    pub fn take_autoscale_all_request(&mut self) -> bool {
+        std::mem::take(&mut self.autoscale_all_request)
+    }
+
+    /// Fits the samples visible in `viewport` vertically.
+    fn autoscale_y(&mut self, ppp: f32, viewport: &Rect) {
+        let visible = self.visible_samples(ppp, viewport);
+        if visible.is_empty() {
+            return;
+        }
+        match self.trace.min_max(visible) {
+            Ok(min_max) => self.fit_y(min_max, viewport.height() * ppp),
+            Err(e) => self.failures.push(read_failure(e)),
+        }
+    }
+
+    /// Scales the camera vertically so the samples between `min` and `max` fill 75% of `height`
+    /// pixels. Constant samples are only centered, and infinite or NaN extrema are ignored.
+    fn fit_y(&mut self, [min, max]: [f32; 2], height: f32) {
+        if !min.is_finite() || !max.is_finite() || max < min {
+            return;
+        }
+        if max > min {
+            let span = max as f64 - min as f64;
+            self.camera.scale.y =
+                Fixed::saturating_from_num(height as f64 * 0.75 / span).max(Fixed::DELTA);
+        }
+        self.camera.shift.y = -Fixed::from_num(min.midpoint(max));
+    }
+
+    /// Executes an automation command targeting this viewer. `viewport` is the viewer region, as
+    /// given to [`Viewer::update`].
+    pub fn execute(
+        &mut self,
+        ctx: &egui::Context,
+        command: &Command,
+        viewport: Rect,
+    ) -> io::Result<()> {
+        let ppp = ctx.pixels_per_point();
+        // Automation times are in µs.
+        let sampling_rate = self.trace_sampling_rate() as f64;
+        let to_samples = |t: f64| Fixed::saturating_from_num(t * sampling_rate);
+        match command {
+            Command::Process(step) => {
+                if let ProcessingStep::Filter(spec) = step
+                    && spec.coefficients(self.trace_sampling_rate()).is_err()
+                {
+                    return Err(io::Error::other(format!(
+                        "filter frequency must be positive and below the Nyquist frequency ({} MHz)",
+                        self.trace_sampling_rate() / 2.0
+                    )));
+                }
+                self.steps.push(*step);
+                self.process(ctx);
+            }
+            Command::ResetProcessing => {
+                self.steps.clear();
+                self.process(ctx);
+            }
+            Command::Show { start, end } => {
+                let (start, end) = (to_samples(*start), to_samples(*end));
+                self.camera.shift.x = (start + end) / 2;
+                self.camera.scale.x = ((end - start).abs()
+                    / Fixed::from_num(viewport.width() * ppp))
+                .clamp(Fixed::from_num(0.01), Fixed::from_num(MIN_SCALE_X));
+            }
+            Command::Autoscale => self.autoscale_request = true,
+            Command::AutoscaleY => self.autoscale_y(ppp, &viewport),
+            Command::SelectRange { start, end } => {
+                self.tool = Tool::Range;
+                self.tool_step = 2;
+                self.tool_times = vec![to_samples(*start), to_samples(*end)];
+            }
+            Command::ClearRange => {
+                self.tool_times.clear();
+                self.tool_step = 0;
+            }
+            Command::ExportImage(path) => {
+                let range = self
+                    .selection()
+                    .unwrap_or_else(|| self.visible_samples(ppp, &viewport));
+                let image = render_image(&self.trace, range, self.image_settings(viewport, ppp))
+                    .ok_or_else(|| io::Error::other("not enough samples to render"))?;
+                if let Some(e) = self.trace.take_read_error() {
+                    return Err(io::Error::other(e));
+                }
+                write_png(path, &image)?;
+                tracing::info!("Exported {}", path.display());
+            }
+            Command::ExportNpy(path) => {
+                let range = self
+                    .selection()
+                    .unwrap_or_else(|| self.visible_samples(ppp, &viewport));
+                let samples: &[f32] = &self.trace;
+                if let Some(e) = self.trace.take_read_error() {
+                    return Err(io::Error::other(e));
+                }
+                write_npy(path, samples, range)?;
+                tracing::info!("Exported {}", path.display());
+            }
+            Command::Select(_)
+            | Command::SyncCamera { .. }
+            | Command::Load { .. }
+            | Command::AddMarker { .. }
+            | Command::Reply(_)
+            | Command::Quit => {}
+        }
+        Ok(())
+    }
+
     /// Toolbar widgets rendering.
-    pub fn ui_toolbar(&mut self, ui: &mut Ui, sync_options: Option<&mut SyncFeatures>) {
+    pub fn ui_toolbar(
+        &mut self,
+        ui: &mut Ui,
+        sync_options: Option<&mut SyncFeatures>,
+        layout: Option<&mut Layout>,
+    ) {
+        // The synchronization options are given when there are several viewers, one of which can
+        // be closed.
+        let closable = sync_options.is_some();
         ui.horizontal(|ui| {
-            ui.label(format!("Trace: {}S", format_number_unit(self.trace.len())));
+            ui.label(format!(
+                "Trace: {}",
+                self.format.count(self.trace.len(), "S")
+            ));
 
             ui.label("@");
             let drag = DragValue::new(&mut self.sampling_rate)
@@ -137,31 +1038,71 @@ impl Viewer {
                 .range(1.0..=1000e9)
                 .speed(25.0);
             ui.add(drag);
+            let response = ui
+                .small_button("⏱")
+                .on_hover_text("Estimate the sampling rate from a known tone");
+            let trace = self.trace.clone();
+            let sampling_rate = self.trace_sampling_rate();
+            let selection = self.selection();
+            let estimate = Popup::menu(&response)
+                .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                .show(|ui| {
+                    self.rate_estimator
+                        .ui(ui, &trace, sampling_rate, selection, &self.format)
+                })
+                .and_then(|r| r.inner);
+            match estimate {
+                Some(RateEstimate::SamplingRate(rate)) => {
+                    self.set_trace_sampling_rate(ui.ctx(), rate)
+                }
+                Some(RateEstimate::Clock { period, origin }) => {
+                    self.clock = Some(ClockGrid::new(period, origin as f64));
+                }
+                None => {}
+            }
+            if let Some(clock) = &mut self.clock {
+                let response = ui.button("Clock").on_hover_text(format!(
+                    "Clock cycles, stepped with the {} and {} keys",
+                    self.keys.previous_cycle.symbol_or_name(),
+                    self.keys.next_cycle.symbol_or_name()
+                ));
+                let keep = Popup::menu(&response)
+                    .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                    .show(|ui| clock.ui(ui, sampling_rate, &self.format))
+                    .is_none_or(|r| r.inner);
+                if !keep {
+                    self.clock = None;
+                }
+            }
+
+            let (icon, hover) = if self.playback.playing {
+                ("⏸", "Pause the playback")
+            } else {
+                ("▶", "Scroll through the trace, like a recording")
+            };
+            if ui.button(icon).on_hover_text(hover).clicked() {
+                self.playback.playing = !self.playback.playing;
+            }
+            let response = ui.small_button("⏩").on_hover_text("Speed of the playback");
+            Popup::menu(&response)
+                .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                .show(|ui| self.playback.ui(ui, sampling_rate, &self.format));
 
             egui::ComboBox::from_id_salt("display")
                 .selected_text(self.color_scale.gradient.name())
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(
-                        &mut self.color_scale.gradient,
-                        Gradient::SingleColor {
-                            min: 0.1,
-                            end: Color32::WHITE,
-                        },
-                        "Single color",
-                    );
-                    ui.selectable_value(
-                        &mut self.color_scale.gradient,
-                        Gradient::BiColor {
-                            start: Color32::BLUE,
-                            end: Color32::GREEN,
-                        },
-                        "Gradient",
-                    );
-                    ui.selectable_value(
-                        &mut self.color_scale.gradient,
-                        Gradient::Rainbow,
-                        "Rainbow",
-                    );
+                    for kind in [
+                        GradientKind::SingleColor,
+                        GradientKind::BiColor,
+                        GradientKind::Rainbow,
+                    ] {
+                        let gradient = kind.gradient();
+                        ui.selectable_value(
+                            &mut self.color_scale.gradient,
+                            gradient,
+                            gradient.name(),
+                        );
+                    }
                 });
 
             match &mut self.color_scale.gradient {
@@ -175,6 +1116,9 @@ impl Viewer {
                     ui.color_edit_button_srgba(end);
                 }
                 Gradient::Rainbow => {}
+                Gradient::Tint { color } => {
+                    ui.color_edit_button_srgba(color);
+                }
             };
 
             ui.label("Power:");
@@ -203,6 +1147,201 @@ impl Viewer {
                 self.tool_step = 0;
             }
 
+            let processing_text = if self.steps.is_empty() {
+                "Processing".to_string()
+            } else {
+                format!("Processing ({})", self.steps.len())
+            };
+            let response = ui.button(processing_text);
+            if response.clicked() {
+                self.processing_menu.reload_presets();
+            }
+            let steps_text = self
+                .steps
+                .iter()
+                .map(|step| step.name())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let response = if steps_text.is_empty() {
+                response
+            } else {
+                response.on_hover_text(steps_text)
+            };
+            let action = Popup::menu(&response)
+                .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                .show(|ui| {
+                    let sampling_rate =
+                        (self.sampling_rate as f64 * steps_rate_ratio(&self.steps)) as f32;
+                    let selection = self.selection();
+                    self.processing_menu.ui(
+                        ui,
+                        sampling_rate,
+                        &self.steps,
+                        selection,
+                        self.trace_min_max,
+                        self.frame_group.is_some(),
+                    )
+                })
+                .and_then(|r| r.inner);
+            match action {
+                Some(ProcessingAction::OpenFilterDesigner) => self.open_filter_designer(None),
+                Some(ProcessingAction::EditFilter(i)) => self.open_filter_designer(Some(i)),
+                Some(ProcessingAction::Add(step)) => {
+                    self.steps.push(step);
+                    self.process(ui.ctx());
+                }
+                Some(ProcessingAction::AddPreset(steps)) => {
+                    self.steps.extend(steps);
+                    self.process(ui.ctx());
+                }
+                Some(ProcessingAction::Remove(i)) => {
+                    self.steps.remove(i);
+                    self.process(ui.ctx());
+                }
+                Some(ProcessingAction::Swap(i, j)) => {
+                    self.steps.swap(i, j);
+                    self.process(ui.ctx());
+                }
+                Some(ProcessingAction::NewTraceFromExpression) => {
+                    self.expression_request = true;
+                }
+                Some(ProcessingAction::NewView(step)) => {
+                    let trace = self.trace.clone();
+                    let sampling_rate = self.trace_sampling_rate();
+                    let job = ProcessingJob::try_spawn(ui.ctx(), move |_| {
+                        step.apply(&trace, sampling_rate).map(Some)
+                    });
+                    self.new_view_job = Some((job, step));
+                }
+                Some(ProcessingAction::SetDigital(thresholds)) => {
+                    self.digital = thresholds.map(DigitalChannel::new);
+                }
+                Some(ProcessingAction::NewDifferenceTrace) => {
+                    self.difference_request = true;
+                }
+                Some(ProcessingAction::FrameStatistics) => {
+                    self.frame_statistics_request = true;
+                }
+                Some(ProcessingAction::GroupAverages) => {
+                    self.group_average_request = true;
+                }
+                Some(ProcessingAction::RunScript) => {
+                    self.script_dialog = Some(ScriptDialog::new(
+                        ui.ctx(),
+                        egui::Id::new(("script_dialog", self.id)),
+                        self.selection(),
+                    ));
+                }
+                Some(ProcessingAction::Reset) => {
+                    self.steps.clear();
+                    self.process(ui.ctx());
+                }
+                None => {}
+            }
+            if let Some(job) = &self.processing {
+                ui.add(
+                    ProgressBar::new(job.progress())
+                        .desired_width(100.0)
+                        .show_percentage()
+                        .animate(true),
+                );
+                if ui.button("Cancel").clicked() {
+                    self.cancel_processing();
+                } else {
+                    // Progress is not notified by the processing thread.
+                    ui.ctx().request_repaint_after(Duration::from_millis(100));
+                }
+            }
+
+            let overlays_text = if self.overlays.items.is_empty() {
+                "Overlay".to_string()
+            } else {
+                format!("Overlay ({})", self.overlays.items.len())
+            };
+            let response = ui
+                .button(overlays_text)
+                .on_hover_text("Analysis results painted over the trace");
+            Popup::menu(&response)
+                .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                .show(|ui| {
+                    if !self.events.is_empty() {
+                        ui.checkbox(
+                            &mut self.show_events,
+                            format!("Event markers ({})", self.events.len()),
+                        );
+                        ui.separator();
+                    }
+                    self.overlays.ui(ui)
+                });
+            self.overlays.update_dialog(ui.ctx(), self.trace.len());
+
+            let regions_text = if self.regions.items.is_empty() {
+                "Regions".to_string()
+            } else {
+                format!("Regions ({})", self.regions.items.len())
+            };
+            let response = ui
+                .button(regions_text)
+                .on_hover_text("Labeled regions of the trace, exported for analysis scripts");
+            let selection = self.selection();
+            Popup::menu(&response)
+                .close_behavior(PopupCloseBehavior::CloseOnClickOutside)
+                .show(|ui| self.regions.ui(ui, selection.clone()));
+            self.regions.update_dialog(
+                ui.ctx(),
+                ExportInfo {
+                    rate_ratio: self.rate_ratio,
+                    sampling_rate: self.sampling_rate,
+                    source: self.source.as_ref(),
+                    selection,
+                },
+            );
+
+            if let Some(live) = &mut self.live {
+                ui.toggle_value(&mut live.follow, "Follow")
+                    .on_hover_text("Keep the last received trace in view");
+                let text = live.status();
+                match live.error() {
+                    Some(error) => {
+                        ui.colored_label(Color32::RED, text).on_hover_text(error);
+                    }
+                    None => {
+                        ui.label(text).on_hover_text(live.source());
+                    }
+                }
+            }
+
+            if let Some(browser) = &mut self.frame_browser
+                && let Some(samples) = browser.ui(ui)
+            {
+                let index = browser.index();
+                self.label = browser.label();
+                if let Some(source) = &mut self.source {
+                    source.frame = Some(index);
+                }
+                if let Some(panel) = &mut self.trace_data {
+                    panel.select(index);
+                }
+                self.set_original_trace(ui.ctx(), Arc::new(samples.into()));
+            }
+
+            if let Some(panel) = &mut self.trace_data {
+                ui.toggle_value(&mut panel.open, "Data")
+                    .on_hover_text("Data bytes of the trace, with navigation in the trace set");
+            }
+
+            if self.selection().is_some() {
+                let response = ui.button("Copy");
+                Popup::menu(&response).show(|ui| {
+                    if ui.button("Copy as values").clicked() {
+                        self.copy_request = Some(CopyFormat::Values);
+                    }
+                    if ui.button("Copy as image").clicked() {
+                        self.copy_request = Some(CopyFormat::Image);
+                    }
+                });
+            }
+
             if let Some(options) = sync_options {
                 let response = ui.button("Sync");
                 Popup::menu(&response)
@@ -220,9 +1359,79 @@ impl Viewer {
                         ui.checkbox(&mut options.shift_y, "Shift Y");
                         ui.checkbox(&mut options.scale_x, "Scale X");
                         ui.checkbox(&mut options.scale_y, "Scale Y");
+                        ui.checkbox(&mut options.relative, "Relative")
+                            .on_hover_text(
+                                "Pan and zoom the traces together, each one keeping its own shifts \
+                                and scales",
+                            );
+                        if ui
+                            .button("Autoscale all")
+                            .on_hover_text("Fit each trace vertically to its visible samples")
+                            .clicked()
+                        {
+                            self.autoscale_all_request = true;
+                            ui.close();
+                        }
+                        ui.separator();
+                        ui.checkbox(&mut options.tool, "Tool")
+                            .on_hover_text("Use the same tool in all the traces");
+                        ui.checkbox(&mut options.selection, "Selections")
+                            .on_hover_text("Mirror the Range and Count selections");
+                        ui.checkbox(&mut options.markers, "Regions")
+                            .on_hover_text("Mirror the labeled regions");
+                        ui.separator();
+                        if ui.button("Align to reference…").clicked() {
+                            self.align_request = true;
+                            ui.close();
+                        }
+                        if ui
+                            .add_enabled(
+                                self.selection().is_some(),
+                                egui::Button::new("Align all to this pattern…"),
+                            )
+                            .on_hover_text(
+                                "Align the other traces to the Range selection of this trace",
+                            )
+                            .on_disabled_hover_text("Select the pattern with the Range tool")
+                            .clicked()
+                        {
+                            self.align_all_request = true;
+                            ui.close();
+                        }
+                        if ui
+                            .button("Elastic alignment (DTW)…")
+                            .on_hover_text(
+                                "Warp this trace onto another one, for captures with jitter or \
+                                random delays",
+                            )
+                            .clicked()
+                        {
+                            self.dtw_request = true;
+                            ui.close();
+                        }
+                        if self.x_offset != 0 {
+                            ui.horizontal(|ui| {
+                                ui.label(format!(
+                                    "Offset: {} samples",
+                                    self.format.integer(self.x_offset.round().to_num())
+                                ));
+                                if ui.button("Reset").clicked() {
+                                    self.set_x_offset(Fixed::ZERO);
+                                }
+                            });
+                        }
                     });
             }
 
+            if let Some(layout) = layout {
+                let response = ui.button("Layout");
+                Popup::menu(&response).show(|ui| {
+                    for option in Layout::ALL {
+                        ui.radio_value(layout, option, option.name());
+                    }
+                });
+            }
+
             // Extract short name from label for toolbar; full label shown on hover.
             // Label may be a plain path or "path [frame N]" for multi-frame files.
             let filename = Path::new(&self.label)
@@ -231,11 +1440,52 @@ impl Viewer {
                 .to_string_lossy()
                 .to_string();
             ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
+                if closable
+                    && ui
+                        .small_button("✖")
+                        .on_hover_text("Close this trace")
+                        .clicked()
+                {
+                    self.close_request = true;
+                }
+                // In the browser, the files are kept in memory and do not change.
+                if cfg!(not(target_arch = "wasm32"))
+                    && self.reload_source().is_some()
+                    && ui
+                        .small_button("⟳")
+                        .on_hover_text("Load the trace again from its file")
+                        .clicked()
+                {
+                    self.reload_request = true;
+                }
+                // In the browser, there is a single window.
+                if !ui.ctx().embed_viewports() {
+                    let hover = if self.detached {
+                        "Return to the main window"
+                    } else {
+                        "Show in its own window"
+                    };
+                    if ui.small_button("⧉").on_hover_text(hover).clicked() {
+                        self.detached = !self.detached;
+                    }
+                }
                 ui.label(filename).on_hover_text(self.label.clone());
             });
         });
     }
 
+    /// Returns the displayed time interval in µs, as given to [`Command::Show`].
+    pub fn shown_interval(&self, ppp: f32, viewport: Rect) -> (f64, f64) {
+        let sampling_rate = self.trace_sampling_rate() as f64;
+        let time = |x| {
+            self.camera
+                .screen_to_world_x(&viewport, ppp, x)
+                .to_num::<f64>()
+                / sampling_rate
+        };
+        (time(0.0), time(viewport.width()))
+    }
+
     /// Update viewer from mouse interaction.
     /// This is done before any painting.
     ///
@@ -247,18 +1497,45 @@ impl Viewer {
         ui: &mut Ui,
         viewport: Rect,
     ) -> ViewerUpdateStatus {
-        let (stable_dt, mut left_pressed, key_left, key_right, scroll_delta, pos, modifiers) = ctx
-            .input(|i| {
-                (
-                    i.stable_dt,
-                    i.pointer.button_pressed(PointerButton::Primary),
-                    i.key_down(Key::ArrowLeft),
-                    i.key_down(Key::ArrowRight),
-                    i.smooth_scroll_delta[1],
-                    i.pointer.latest_pos(),
-                    i.modifiers,
-                )
-            });
+        let (
+            stable_dt,
+            mut left_pressed,
+            key_left,
+            key_right,
+            cycle_step,
+            scroll_delta,
+            pos,
+            modifiers,
+        ) = ctx.input(|i| {
+            (
+                i.stable_dt,
+                i.pointer.button_pressed(PointerButton::Primary),
+                i.key_down(self.keys.pan_left),
+                i.key_down(self.keys.pan_right),
+                i.key_pressed(self.keys.next_cycle) as i32
+                    - i.key_pressed(self.keys.previous_cycle) as i32,
+                i.smooth_scroll_delta[1],
+                i.pointer.latest_pos(),
+                i.modifiers,
+            )
+        });
+
+        if let Some(live) = &mut self.live
+            && let Some(update) = live.update()
+        {
+            // Scopes report their sampling rate, which changes with the timebase.
+            if let Some(rate) = live.sampling_rate() {
+                self.sampling_rate = rate;
+            }
+            match update {
+                LiveUpdate::Trace(trace) => self.set_original_trace(ctx, Arc::new(trace.into())),
+                LiveUpdate::Samples {
+                    trace,
+                    start,
+                    dropped,
+                } => self.append_samples(ctx, trace, start, dropped),
+            }
+        }
 
         let response = ui.allocate_rect(viewport, Sense::drag());
 
@@ -290,6 +1567,26 @@ impl Viewer {
 
                 self.camera.shift.x = s1 * k + self.camera.shift.x - s2 * k;
                 self.camera.scale.x = s2;
+                self.focus = Some(self.camera.shift.x + s2 * k);
+            }
+        }
+
+        // Bookmarks of the hovered viewer, unless a text field has the keyboard.
+        let bookmark_key = ctx.input(|i| {
+            let slot = BOOKMARK_KEYS.iter().position(|&key| i.key_pressed(key))?;
+            Some((slot, i.modifiers.command))
+        });
+        let mut recalled = false;
+        if let Some((slot, store)) = bookmark_key
+            && hovered
+            && !ctx.wants_keyboard_input()
+        {
+            if store {
+                self.bookmarks[slot] = Some(self.camera.into());
+            } else if let Some(bookmark) = self.bookmarks[slot] {
+                self.camera = bookmark_camera(bookmark);
+                self.pan_velocity = 0.0;
+                recalled = true;
             }
         }
 
@@ -302,11 +1599,18 @@ impl Viewer {
             ctx.request_repaint();
         }
 
+        // Kinetic panning: the trace keeps gliding after a drag along the X axis is released,
+        // slowing down exponentially, until the user clicks, zooms or pans with the keys.
+        let pressed = ctx.input(|i| i.pointer.any_pressed()) && hovered;
+        if pressed || zooming || key_left || key_right {
+            self.pan_velocity = 0.0;
+        }
+
         let mut dragging_y = false;
         let mut dragging_x = false;
-        if response.dragged_by(PointerButton::Secondary)
-            || (response.dragged_by(PointerButton::Primary) && self.tool == Tool::Move)
-        {
+        let panning = response.dragged_by(PointerButton::Secondary)
+            || (response.dragged_by(PointerButton::Primary) && self.tool == Tool::Move);
+        if panning {
             if ui.input(|i| i.modifiers.alt) {
                 if response.drag_delta()[1] != 0.0 {
                     self.camera.shift.y -=
@@ -319,12 +1623,73 @@ impl Viewer {
                 dragging_x = true;
             }
         }
+        let panning_stopped = response.drag_stopped_by(PointerButton::Secondary)
+            || (response.drag_stopped_by(PointerButton::Primary) && self.tool == Tool::Move);
+        if panning_stopped && !modifiers.alt {
+            let velocity = ctx.input(|i| i.pointer.velocity().x);
+            if velocity.abs() >= MIN_PAN_VELOCITY {
+                self.pan_velocity = velocity;
+            }
+        }
+        if self.pan_velocity != 0.0 && !panning {
+            // Reported as a drag so the other viewers follow when the shift is synchronized.
+            self.camera.shift.x -=
+                Fixed::from_num(self.pan_velocity * stable_dt * ppp) * self.camera.scale.x;
+            self.pan_velocity *= (-PAN_DECAY * stable_dt).exp();
+            if self.pan_velocity.abs() < MIN_PAN_VELOCITY {
+                self.pan_velocity = 0.0;
+            }
+            dragging_x = true;
+            ctx.request_repaint();
+        }
+        if let Some(clock) = &self.clock
+            && cycle_step != 0
+        {
+            // Reported as a drag so the other viewers follow when the shift is synchronized.
+            self.camera.shift.x += clock.step_shift(cycle_step);
+            dragging_x = true;
+        }
+        // A recalled bookmark is reported as a drag as well.
+        dragging_x |= recalled;
+        if self.playback.playing && !panning {
+            // The playback stops once the end of the trace reaches the right of the viewport.
+            let half_width = self.camera.scale.x * Fixed::from_num(viewport.width() * ppp / 2.0);
+            let end = Fixed::from_num(self.trace.len()) - half_width;
+            let step = Fixed::saturating_from_num(
+                self.playback.samples_per_second(self.trace_sampling_rate()) * stable_dt as f64,
+            );
+            let shift = self.camera.shift.x.saturating_add(step);
+            if shift >= end {
+                // Never moves backwards when the end is already visible.
+                self.camera.shift.x = end.max(self.camera.shift.x);
+                self.playback.playing = false;
+            } else {
+                self.camera.shift.x = shift;
+            }
+            dragging_x = true;
+            ctx.request_repaint();
+        }
+        if let Some(live) = &mut self.live {
+            if dragging_x || key_left || key_right {
+                // The user moves away from the end of the trace.
+                live.follow = false;
+            } else if live.follow {
+                // The end of the trace is kept at the right of the viewport.
+                self.camera.shift.x = Fixed::from_num(self.trace.len())
+                    - self.camera.scale.x * Fixed::from_num(viewport.width() * ppp / 2.0);
+            }
+        }
 
         let world_x =
             self.camera
                 .screen_to_world_x(&viewport, ppp, pos.map(|p| p.x).unwrap_or(0.0));
 
-        // Tool management
+        // Tool management. A selection mirrored from another viewer does not follow the mouse,
+        // until the user clicks in this viewer.
+        if left_pressed {
+            self.tool_mirrored = false;
+        }
+        let follow = !self.tool_mirrored;
         match self.tool {
             Tool::Move => {}
             Tool::Range => match self.tool_step {
@@ -335,7 +1700,9 @@ impl Viewer {
                     }
                 }
                 1 => {
-                    self.tool_times[1] = world_x;
+                    if follow {
+                        self.tool_times[1] = world_x;
+                    }
                     if left_pressed {
                         self.tool_step = 2;
                     }
@@ -356,14 +1723,18 @@ impl Viewer {
                     }
                 }
                 1 => {
-                    self.tool_times[1] = world_x;
+                    if follow {
+                        self.tool_times[1] = world_x;
+                    }
                     if left_pressed {
                         self.tool_times.push(world_x);
                         self.tool_step = 2;
                     }
                 }
                 2 => {
-                    self.tool_times[2] = world_x;
+                    if follow {
+                        self.tool_times[2] = world_x;
+                    }
                     if left_pressed {
                         self.tool_step = 3;
                     }
@@ -378,24 +1749,92 @@ impl Viewer {
             },
         }
 
+        let autoscaled = self.autoscale_request;
         if self.autoscale_request {
             self.autoscale_request = false;
             let trace_len = Fixed::from_num(self.trace.len());
             self.camera.scale.x = (trace_len / Fixed::from_num(viewport.width() * ppp))
                 .min(Fixed::from_num(MIN_SCALE_X));
             self.camera.shift.x = trace_len / 2;
-            self.camera.scale.y = Fixed::from_num(
-                ((viewport.height() * ppp) * 0.75)
-                    / (self.trace_min_max[1] - self.trace_min_max[0]),
-            );
-            self.camera.shift.y =
-                -Fixed::from_num(self.trace_min_max[0].midpoint(self.trace_min_max[1]));
+            self.fit_y(self.trace_min_max, viewport.height() * ppp);
+        }
+        if self.autoscale_y_request {
+            self.autoscale_y_request = false;
+            self.autoscale_y(ppp, &viewport);
+        }
+
+        let filter_sampling_rate = self.filter_sampling_rate();
+        if let Some(designer) = &mut self.filter_designer {
+            match designer.update(ctx, filter_sampling_rate) {
+                FilterDesignerResult::Pending => {}
+                FilterDesignerResult::Apply(filter) => {
+                    self.filter_designer = None;
+                    self.set_filter(ctx, Some(filter));
+                }
+                FilterDesignerResult::Remove => {
+                    self.filter_designer = None;
+                    self.set_filter(ctx, None);
+                }
+                FilterDesignerResult::Cancelled => self.filter_designer = None,
+            }
+        }
+
+        match self.processing.as_ref().and_then(|job| job.try_take()) {
+            Some(Ok(trace)) => self.processing_finished(trace),
+            Some(Err(e)) => {
+                self.cancel_processing();
+                self.failures.push(format!("Processing failed: {}", e));
+            }
+            None => {}
+        }
+
+        // The samples of paged traces replaced by NaN when their file could not be read.
+        for trace in [&self.original_trace, &self.trace] {
+            if let Some(e) = trace.take_read_error() {
+                self.failures.push(e);
+            }
+        }
+
+        if let Some(digital) = &mut self.digital {
+            digital.update(ctx, &self.trace, self.generation);
+        }
+
+        if let Some((job, step)) = &self.new_view_job
+            && let Some(result) = job.try_take()
+        {
+            match result {
+                Ok(trace) => {
+                    self.new_trace = Some(NewTrace {
+                        label: format!("{} [{}]", self.label, step.name()),
+                        trace,
+                        sampling_rate: (self.trace_sampling_rate() as f64 * step.rate_ratio())
+                            as f32,
+                    })
+                }
+                Err(e) => self.failures.push(format!("Processing failed: {}", e)),
+            }
+            self.new_view_job = None;
+        }
+
+        if let Some(format) = self.copy_request.take()
+            && let Some(range) = self.selection()
+        {
+            match format {
+                CopyFormat::Values => ctx.copy_text(samples_to_csv(&self.trace, range)),
+                CopyFormat::Image => {
+                    let settings = self.image_settings(viewport, ppp);
+                    if let Some(image) = render_image(&self.trace, range, settings) {
+                        ctx.copy_image(image);
+                    }
+                }
+            }
         }
 
         ViewerUpdateStatus {
             zooming,
             dragging_x,
             dragging_y,
+            autoscaled,
         }
     }
 
@@ -403,12 +1842,13 @@ impl Viewer {
         &mut self,
         ctx: &egui::Context,
         sync: Option<&mut SyncFeatures>,
+        layout: Option<&mut Layout>,
         viewport: Rect,
     ) {
         egui::Window::new(format!("toolbar{}", self.id))
             .title_bar(false)
             .resizable(false)
-            .anchor(Align2::LEFT_TOP, vec2(0.0, viewport.top()))
+            .anchor(Align2::LEFT_TOP, viewport.left_top().to_vec2())
             .frame(
                 egui::Frame::default()
                     .fill(Color32::from_rgba_unmultiplied(30, 30, 30, 200))
@@ -419,16 +1859,19 @@ impl Viewer {
             .min_width(viewport.width() - 32.0)
             .show(ctx, |ui| {
                 ui.set_width(viewport.width() - 32.0);
-                self.ui_toolbar(ui, sync)
+                self.ui_toolbar(ui, sync, layout)
             });
     }
 
+    /// Paints the trace in `viewport`. Without `background`, the trace is painted over the one
+    /// already painted there.
     pub fn paint_waveform(
         &mut self,
         ctx: &egui::Context,
         ui: &mut Ui,
         viewport: Rect,
         allow_tile_requests: bool,
+        background: bool,
     ) {
         let painter = ui.painter().with_clip_rect(viewport);
 
@@ -445,12 +1888,23 @@ impl Viewer {
 
         match mode {
             RenderMode::Density => {
-                // If color scale changes all textures become invalid. We destroy the textures
-                // cache. The GPU rendering of the tiles remains valid, we just need to recreate
-                // the images from the density data.
+                // If color scale changes all textures become invalid. The GPU rendering of the
+                // tiles remains valid: the textures colored by the GPU are colored again from
+                // their densities, and the images of the other ones are recreated from the
+                // density data.
                 if (self.color_scale != self.previous_color_scale) && (mode == RenderMode::Density)
                 {
-                    self.textures.clear();
+                    self.textures
+                        .retain(|_, texture| matches!(texture, TileTexture::Native(_)));
+                    if let Some(colorizer) = &self.colorizer {
+                        let textures = self.textures.values().filter_map(|texture| match texture {
+                            TileTexture::Native(texture) => Some(texture),
+                            TileTexture::Image(_) => None,
+                        });
+                        colorizer.recolor(textures, self.color_scale);
+                    }
+                    self.outdated_textures
+                        .retain(|k| self.textures.contains_key(k));
                     self.previous_color_scale = self.color_scale;
                 }
                 // New tiles are requested when moving the camera has finished. While we are zooming or
@@ -461,7 +1915,7 @@ impl Viewer {
                     let required = self.compute_viewport_tiles(viewport * ppp);
 
                     let mut complete = true;
-                    for tile in required {
+                    for &tile in &required {
                         complete &= self
                             .shared_tiling
                             .0
@@ -476,17 +1930,30 @@ impl Viewer {
                     if complete {
                         // All the tiles required to render the trace perfectly with current camera
                         // settings have been rendered by the GPU. We can therefore discard all other
-                        // previous tiles which were used for the preview.
+                        // previous tiles which were used for the preview, and the tiles of the
+                        // same settings covered by required tiles of another width.
                         let mut tiling = self.shared_tiling.0.lock().unwrap();
-                        tiling.tiles.retain(|t| {
+                        let replaced = |p: &TileProperties| {
+                            let columns = p.columns();
+                            required.iter().any(|r| {
+                                r.size != p.size
+                                    && r.columns().start < columns.end
+                                    && columns.start < r.columns().end
+                            })
+                        };
+                        tiling.retain(|t| {
                             ((t.properties.scale == self.camera.scale)
-                                && (t.properties.offset == self.camera.shift.y))
+                                && (t.properties.offset == self.camera.shift.y)
+                                && (t.properties.generation == self.generation)
+                                && !replaced(&t.properties))
                                 // Don't remove tiles from other viewers!
                                 || (t.properties.id != self.id)
                         });
                         // We also discard textures that are not used anymore.
                         self.textures
                             .retain(|k, _| tiling.tiles.iter().any(|t| t.properties == *k));
+                        self.outdated_textures
+                            .retain(|k| self.textures.contains_key(k));
                     } else {
                         // Some tiles have not been rendered yet, and maybe have been added to the pool.
                         // Wake-up the rendering thread if it was sleeping.
@@ -495,49 +1962,281 @@ impl Viewer {
                 }
 
                 // Draw a background checkboard to show zones that are not rendered yet.
-                self.paint_checkboard(&viewport, &painter);
+                if background {
+                    self.paint_checkboard(&viewport, &painter);
+                }
 
+                // The tile renderers request a repaint when they have rendered tiles.
                 self.paint_tiles(ctx, ppp, &painter, viewport);
-
-                if self.shared_tiling.0.lock().unwrap().has_pending() {
-                    // TODO: it would be better to request repaint only when the GPU renderer has finished
-                    // rendering a tile. This would reduce CPU usage but requires extra thread
-                    // synchronization mechanisms.
-                    ctx.request_repaint();
-                }
             }
             RenderMode::Lines => {
-                self.paint_black_background(&painter, viewport);
+                if background {
+                    self.paint_black_background(&painter, viewport);
+                }
                 self.paint_waveform_as_lines(ppp, &painter, &viewport);
             }
         }
 
+        let preview = self
+            .filter_designer
+            .as_ref()
+            .and_then(|designer| designer.preview(self.filter_sampling_rate()));
+        if let Some(spec) = preview {
+            self.paint_filter_preview(spec, ppp, &painter, &viewport);
+        } else {
+            self.filter_preview.clear();
+        }
+
+        self.regions.paint(&self.camera, ppp, &painter, &viewport);
+        self.overlays.paint(&self.camera, ppp, &painter, &viewport);
+
+        if let Some(clock) = &self.clock {
+            clock.paint(&self.camera, ppp, &painter, &viewport);
+        }
+
+        if self.show_events {
+            paint_events(
+                &self.events,
+                self.rate_ratio,
+                self.x_offset,
+                &self.camera,
+                ppp,
+                &painter,
+                &viewport,
+            );
+        }
+        if let Some(trigger) = self.trigger {
+            paint_trigger(
+                trigger,
+                self.rate_ratio,
+                self.x_offset,
+                &self.camera,
+                ppp,
+                &painter,
+                &viewport,
+            );
+        }
+
+        let lane = self.digital.as_ref().and_then(|d| d.lane());
+        if let Some(lane) = lane {
+            lane.paint(&self.camera, ppp, &painter, &viewport, 0, None);
+        }
+        // The first channel is at the top, as in logic analyzers.
+        let rows = self.logic.len() + lane.is_some() as usize;
+        for (i, channel) in self.logic.iter().enumerate() {
+            channel.lane.paint(
+                &self.camera,
+                ppp,
+                &painter,
+                &viewport,
+                rows - 1 - i,
+                Some(&channel.name),
+            );
+        }
+
         self.paint_tool(ppp, &painter, &viewport);
+
+        if let Some(range) = self.selection() {
+            self.paint_export_button(ctx, ppp, viewport, range);
+        }
+        if let Some(dialog) = &mut self.export_dialog
+            && !dialog.show(ctx, &self.trace)
+        {
+            self.export_dialog = None;
+        }
+        let sampling_rate = self.trace_sampling_rate();
+        if let Some(dialog) = &mut self.script_dialog {
+            match dialog.show(ctx, &self.trace, sampling_rate, &self.label) {
+                ScriptDialogResult::Pending => {}
+                ScriptDialogResult::Created(new_trace) => {
+                    self.script_dialog = None;
+                    self.new_trace = Some(new_trace);
+                    // The multi-viewer adds the new viewer during the next update.
+                    ctx.request_repaint();
+                }
+                ScriptDialogResult::Cancelled => self.script_dialog = None,
+            }
+        }
+        if let Some(panel) = &mut self.trace_data
+            && let Some(samples) = panel.show(ctx)
+        {
+            self.label = panel.label();
+            if let Some(source) = &mut self.source {
+                source.frame = Some(panel.index());
+            }
+            if let Some(browser) = &mut self.frame_browser {
+                browser.set_index(panel.index());
+            }
+            self.set_original_trace(ctx, Arc::new(samples.into()));
+        }
     }
 
-    /// Paint the waveform as lines using egui painter. This is more suited for high zoom values
-    /// and benefits from lines antialiasing.
-    fn paint_waveform_as_lines(&self, ppp: f32, painter: &Painter, viewport: &Rect) {
+    /// Shows a small button under the Range selection label, opening the export dialog with the
+    /// selection bounds.
+    fn paint_export_button(
+        &mut self,
+        ctx: &egui::Context,
+        ppp: f32,
+        viewport: Rect,
+        range: Range<usize>,
+    ) {
+        let x = self
+            .camera
+            .world_to_screen_x(&viewport, ppp, self.tool_times[0])
+            .midpoint(
+                self.camera
+                    .world_to_screen_x(&viewport, ppp, self.tool_times[1]),
+            );
+        if !(viewport.min.x..=viewport.max.x).contains(&x) {
+            return;
+        }
+        let clicked = egui::Area::new(egui::Id::new(("export_button", self.id)))
+            .fixed_pos(pos2(x, viewport.min.y + 104.0))
+            .pivot(Align2::CENTER_TOP)
+            .show(ctx, |ui| ui.small_button("Export").clicked())
+            .inner;
+        if clicked {
+            self.export_dialog = Some(ExportDialog::new(
+                egui::Id::new(("export_dialog", self.id)),
+                &self.label,
+                range,
+                self.image_settings(viewport, ppp),
+            ));
+        }
+    }
+
+    /// Settings for rendering images which look like the current display.
+    fn image_settings(&self, viewport: Rect, ppp: f32) -> ImageSettings {
+        ImageSettings {
+            samples_per_column: self.camera.scale.x.to_num(),
+            height: (viewport.height() * ppp) as u32,
+            offset: self.camera.shift.y.to_num(),
+            scale_y: self.camera.scale.y.to_num(),
+            color_scale: self.color_scale,
+        }
+    }
+
+    /// Paints the preview of the filter edited in the designer, calculated on the visible samples
+    /// only.
+    fn paint_filter_preview(
+        &mut self,
+        spec: FilterSpec,
+        ppp: f32,
+        painter: &Painter,
+        viewport: &Rect,
+    ) {
+        let visible = self.visible_samples(ppp, viewport);
+        let key = PreviewKey {
+            spec,
+            visible: visible.clone(),
+            generation: self.generation,
+            edited_step: self.edited_step,
+        };
+        let mut preview = std::mem::take(&mut self.filter_preview);
+        preview.update(painter.ctx(), key, || {
+            self.prepare_filter_preview(spec, visible)
+        });
+        self.filter_preview = preview;
+        if self.filter_preview.is_available() {
+            self.filter_preview
+                .paint(&self.camera, ppp, painter, viewport);
+        } else {
+            painter.text(
+                viewport.center_top() + vec2(0.0, 30.0),
+                Align2::CENTER_TOP,
+                "Zoom in to preview the filter",
+                egui::FontId::proportional(14.0),
+                Color32::YELLOW,
+            );
+        }
+    }
+
+    /// Returns the calculation of the preview, to run in background: the `visible` samples of the
+    /// displayed trace are filtered, plus a margin before them so the transient response of the
+    /// filter is hidden.
+    ///
+    /// A new filter is added at the end of the processing chain, so it processes the displayed
+    /// trace. An edited filter processes the original trace transformed by the steps before it:
+    /// these steps are applied to the visible part of the original trace only. Returns `None` if
+    /// there are too many samples to process.
+    fn prepare_filter_preview(
+        &self,
+        spec: FilterSpec,
+        visible: Range<usize>,
+    ) -> Option<PreviewCalculation> {
+        let Some(i) = self.edited_step else {
+            let start = visible.start.saturating_sub(PREVIEW_MARGIN);
+            if visible.end - start > PREVIEW_MAX_SAMPLES {
+                return None;
+            }
+            let trace = self.trace.clone();
+            let sampling_rate = self.trace_sampling_rate();
+            return Some(Box::new(move |_| {
+                Ok(Some(PreviewSamples {
+                    samples: spec
+                        .apply(&trace[start..visible.end], sampling_rate)
+                        .map_err(|e| spec.error_message(e, sampling_rate))?,
+                    start: start as f64,
+                    step: 1.0,
+                }))
+            }));
+        };
+        let prefix = self.applied_steps.get(..i)?.to_vec();
+        let input_ratio = steps_rate_ratio(&prefix);
+        let start =
+            ((visible.start as f64 / self.rate_ratio) as usize).saturating_sub(PREVIEW_MARGIN);
+        let end =
+            ((visible.end as f64 / self.rate_ratio).ceil() as usize).min(self.original_trace.len());
+        if end.saturating_sub(start) as f64 * input_ratio.max(1.0) > PREVIEW_MAX_SAMPLES as f64 {
+            return None;
+        }
+        let original = self.original_trace.clone();
+        let (sampling_rate, rate_ratio) = (self.sampling_rate, self.rate_ratio);
+        Some(Box::new(move |progress| {
+            let Some(input) = apply_steps(&original[start..end], &prefix, sampling_rate, progress)?
+            else {
+                return Ok(None);
+            };
+            let input_rate = (sampling_rate as f64 * input_ratio) as f32;
+            Ok(Some(PreviewSamples {
+                samples: spec
+                    .apply(&input, input_rate)
+                    .map_err(|e| spec.error_message(e, input_rate))?,
+                start: start as f64 * rate_ratio,
+                step: rate_ratio / input_ratio,
+            }))
+        }))
+    }
+
+    /// Returns the range of displayed trace samples visible in the viewport.
+    fn visible_samples(&self, ppp: f32, viewport: &Rect) -> Range<usize> {
         let t0 = self
             .camera
             .screen_to_world_x(viewport, ppp, 0.0)
             .floor()
-            .to_num::<isize>()
-            .clamp(0, self.trace.len() as isize) as usize;
+            .saturating_to_num::<i64>()
+            .clamp(0, self.trace.len() as i64) as usize;
         let t1 = self
             .camera
             .screen_to_world_x(viewport, ppp, viewport.max.x)
             .ceil()
-            .to_num::<isize>()
-            .add(1)
-            .clamp(0, self.trace.len() as isize) as usize;
-        let points = (t0..t1)
+            .saturating_to_num::<i64>()
+            .saturating_add(1)
+            .clamp(0, self.trace.len() as i64) as usize;
+        t0..t1
+    }
+
+    /// Paint the waveform as lines using egui painter. This is more suited for high zoom values
+    /// and benefits from lines antialiasing.
+    fn paint_waveform_as_lines(&self, ppp: f32, painter: &Painter, viewport: &Rect) {
+        let points = self
+            .visible_samples(ppp, viewport)
             .map(|t| {
                 let x = self
                     .camera
                     .world_to_screen_x(viewport, ppp, Fixed::from_num(t));
                 let y = viewport.center().y
-                    - (self.trace[t] + self.camera.shift.y.to_num::<f32>())
+                    - (self.trace.sample(t) + self.camera.shift.y.to_num::<f32>())
                         * self.camera.scale.y.to_num::<f32>()
                         / ppp;
                 pos2(x, y)
@@ -547,6 +2246,7 @@ impl Viewer {
             Gradient::SingleColor { min: _, end } => end,
             Gradient::BiColor { start, end: _ } => start,
             Gradient::Rainbow => Color32::RED,
+            Gradient::Tint { color } => color,
         };
         painter.line(points, Stroke::new(1.0, color));
     }
@@ -577,17 +2277,30 @@ impl Viewer {
                 continue;
             };
             if tile.status != TileStatus::Rendered {
+                // The texture of an invalidated tile is painted until the tile is rendered again.
+                if let Some(tex) = self.textures.get(&p).map(TileTexture::id) {
+                    self.paint_tile(painter, ppp, rect, tile.properties, tex);
+                }
                 continue;
             }
+            if self.outdated_textures.remove(&p) {
+                self.textures.remove(&p);
+            }
             let tex = self
                 .textures
                 .entry(p)
                 .or_insert_with(|| {
-                    let image = tile.generate_image(self.color_scale);
-                    ctx.load_texture("tile", image, TextureOptions::NEAREST)
+                    let colored = self
+                        .colorizer
+                        .as_ref()
+                        .and_then(|colorizer| colorizer.texture(&tile, self.color_scale));
+                    colored.unwrap_or_else(|| {
+                        let image = tile.generate_image(self.color_scale);
+                        TileTexture::Image(ctx.load_texture("tile", image, TextureOptions::NEAREST))
+                    })
                 })
-                .clone();
-            self.paint_tile(painter, ppp, rect, tile.properties, &tex);
+                .id();
+            self.paint_tile(painter, ppp, rect, tile.properties, tex);
         }
     }
 
@@ -601,10 +2314,10 @@ impl Viewer {
         ppp: f32,
         viewport: Rect,
         properties: TileProperties,
-        tex: &TextureHandle,
+        tex: TextureId,
     ) {
         let world_tile_width =
-            Fixed::from_num(TILE_WIDTH) * properties.scale.x / self.camera.scale.x;
+            Fixed::from_num(properties.size.width()) * properties.scale.x / self.camera.scale.x;
         let shift_x = self.camera.shift.x / self.camera.scale.x;
 
         let mul_y = (self.camera.scale.y / properties.scale.y).to_num::<f32>();
@@ -622,7 +2335,7 @@ impl Viewer {
                 y1,
             ),
         };
-        painter.image(tex.into(), rect, Self::UV, Color32::WHITE);
+        painter.image(tex, rect, Self::UV, Color32::WHITE);
     }
 
     /// Draw a black rectangle on all the surface of the given painter.
@@ -765,7 +2478,6 @@ impl Viewer {
         let font_id = egui::FontId::new(12.0, FontFamily::Proportional);
         let (t0, t1) = (t0.min(t1), t0.max(t1)); // No negative range
         let dt = t1 - t0;
-        let duration = dt.to_num::<f64>() / (self.sampling_rate * 1e6) as f64;
         let x0 = self.camera.world_to_screen_x(viewport, ppp, t0);
         let x1 = self.camera.world_to_screen_x(viewport, ppp, t1);
 
@@ -777,7 +2489,9 @@ impl Viewer {
             ..Default::default()
         };
         job.append(
-            &format!("{}s\n{} samples", format_f64_unit(duration), dt.ceil()),
+            &self
+                .format
+                .duration(dt.to_num(), self.trace_sampling_rate()),
             0.0,
             TextFormat {
                 font_id: font_id.clone(),
@@ -829,27 +2543,44 @@ impl Viewer {
         ));
     }
 
+    /// Returns the range of samples selected with the Range tool, or `None` if there is no
+    /// complete selection. The range is clamped to the trace bounds.
+    pub fn selection(&self) -> Option<Range<usize>> {
+        if self.tool != Tool::Range || self.tool_step != 2 {
+            return None;
+        }
+        let (t0, t1) = (self.tool_times[0], self.tool_times[1]);
+        let len = self.trace.len() as i64;
+        let start = t0.min(t1).floor().saturating_to_num::<i64>().clamp(0, len) as usize;
+        let end = t0.max(t1).ceil().saturating_to_num::<i64>().clamp(0, len) as usize;
+        (end > start).then_some(start..end)
+    }
+
     /// Calculates the set of tiles required to render the trace at full resolution in the viewport
-    /// with current camera settings.
+    /// with current camera settings. Their width is chosen by the tiling policy.
     ///
-    /// Tiles are sorted by distance from the screen center, so the center will be rendered first
-    /// and the edges last.
+    /// Tiles are sorted by distance from the focus, or from the screen center, so the area the
+    /// user is looking at will be rendered first and the edges last.
     fn compute_viewport_tiles(&self, viewport: Rect) -> Vec<TileProperties> {
         let width_half = Fixed::from_num(viewport.width() / 2.0);
-        let tile_width = Fixed::from_num(TILE_WIDTH);
         let dx = self.camera.shift.x / self.camera.scale.x;
-        let start = ((-width_half + dx) / tile_width).floor().to_num::<i32>();
-        let end = ((width_half + dx) / tile_width).ceil().to_num::<i32>();
-        let mut tile_indexes: Vec<_> = (start..end).collect();
-        tile_indexes.sort_by_key(|&a| (a - (start + end) / 2).abs());
-        tile_indexes
-            .iter()
-            .map(|&index| TileProperties {
+        let focus = self.focus.map(|x| x / self.camera.scale.x);
+        let height = viewport.height() as u32;
+        self.tiling_policy
+            .tiles(
+                (dx - width_half, dx + width_half),
+                focus,
+                self.camera.scale.x,
+                height,
+            )
+            .into_iter()
+            .map(|tile| TileProperties {
                 id: self.id,
                 scale: self.camera.scale,
-                index,
+                index: tile.index,
                 offset: self.camera.shift.y,
-                size: TileSize::new(TILE_WIDTH, viewport.height() as u32),
+                size: TileSize::new(tile.width, height),
+                generation: self.generation,
             })
             .collect()
     }
@@ -861,6 +2592,24 @@ pub struct ViewerUpdateStatus {
     pub zooming: bool,
     pub dragging_x: bool,
     pub dragging_y: bool,
+    /// The trace has been fitted in the viewer, with the Auto button or an automation command.
+    pub autoscaled: bool,
+}
+
+impl ViewerUpdateStatus {
+    /// Returns true if the camera is being changed by the user.
+    pub fn is_moving(&self) -> bool {
+        self.zooming || self.dragging_x || self.dragging_y
+    }
+}
+
+/// Active tool of a viewer and its selection, mirrored to the other viewers.
+#[derive(Clone, PartialEq)]
+pub struct ToolState {
+    tool: Tool,
+    step: u8,
+    /// Selected times, in samples of the displayed trace.
+    times: Vec<Fixed>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -883,8 +2632,33 @@ impl Tool {
     }
 }
 
+/// Clipboard content which can be generated from a selection.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CopyFormat {
+    /// CSV text of the selected samples.
+    Values,
+    /// Selection rendered as an image.
+    Image,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum RenderMode {
     Density,
     Lines,
 }
+
+/// Message reported when the samples of a paged trace cannot be read anymore.
+fn read_failure(e: io::Error) -> String {
+    format!("Failed to read the samples of the trace: {}", e)
+}
+
+/// Camera recalled from `bookmark`, with its scales clamped to the zoom limits of the viewer.
+fn bookmark_camera(bookmark: Bookmark) -> Camera {
+    let mut camera = Camera::from(bookmark);
+    camera.scale.x = camera
+        .scale
+        .x
+        .clamp(Fixed::from_num(0.01), Fixed::from_num(MIN_SCALE_X));
+    camera.scale.y = camera.scale.y.max(Fixed::from_num(0.001));
+    camera
+}
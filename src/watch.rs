@@ -0,0 +1,316 @@
+//! Watch-folder mode: the trace files written in a directory by an acquisition rig are opened as
+//! they appear, so the viewer serves as a live-updating review screen.
+//!
+//! The directory is polled in background. Files present when watching starts are ignored, and a
+//! new file is loaded once its size has stopped changing between two polls, as it is usually
+//! written progressively.
+//!
+//! The opened files can also be loaded again, on demand or when they change on disk, replacing the
+//! traces of their viewers.
+
+use crate::{
+    input::{FileSettings, TraceArgs},
+    loaders::{file_sampling_rate, guess_format, load_file},
+    processing::ProcessingStep,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Time between two listings of the watched directory.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Trace loaded from a new file.
+pub struct WatchedTrace {
+    pub label: String,
+    /// Index of the frame in multi-trace files.
+    pub frame: Option<usize>,
+    pub samples: Vec<f32>,
+}
+
+/// New file of the watched directory, with its traces selected by `--frames`, or the reason why
+/// it could not be loaded.
+pub struct WatchedFile {
+    pub path: String,
+    pub traces: Result<Vec<WatchedTrace>, String>,
+    /// Sampling rate of the traces in MS/s: the one recorded in the file, or the one of the
+    /// options.
+    pub sampling_rate: f32,
+}
+
+/// Files loaded in background as they appear in a directory.
+pub struct FolderWatch {
+    dir: String,
+    receiver: Receiver<WatchedFile>,
+    /// When set, each new file replaces the trace of the last viewer instead of adding viewers.
+    pub replace: bool,
+    /// Load-time processing steps, applied to the traces of the viewers added for the new files.
+    pub steps: Vec<ProcessingStep>,
+}
+
+impl FolderWatch {
+    /// Starts watching `dir`, loading the new files with the options of `args`. Loaded files wake
+    /// up the user interface.
+    pub fn start(
+        ctx: &egui::Context,
+        dir: &str,
+        args: &TraceArgs,
+        replace: bool,
+    ) -> io::Result<Self> {
+        let steps = args.processing_steps().map_err(io::Error::other)?;
+        let (sender, receiver) = mpsc::channel();
+        let mut known: HashSet<PathBuf> = list(Path::new(dir), args)?.into_keys().collect();
+        let ctx = ctx.clone();
+        let thread_dir = PathBuf::from(dir);
+        let thread_args = args.clone();
+        thread::spawn(move || {
+            // Size and modification time of the new files at the previous poll.
+            let mut pending = HashMap::new();
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let Ok(files) = list(&thread_dir, &thread_args) else {
+                    continue;
+                };
+                pending.retain(|path, _| files.contains_key(path));
+                for (path, state) in files {
+                    if known.contains(&path) {
+                        continue;
+                    }
+                    if pending.insert(path.clone(), state) != Some(state) || state.0 == 0 {
+                        continue;
+                    }
+                    pending.remove(&path);
+                    known.insert(path.clone());
+                    let path = path.to_string_lossy().to_string();
+                    tracing::info!(target: "loader", "New file {}", path);
+                    let traces = load(&thread_args, &path);
+                    let sampling_rate = thread_args
+                        .format
+                        .or_else(|| guess_format(&path))
+                        .and_then(|format| file_sampling_rate(&path, format))
+                        .unwrap_or(thread_args.sampling_rate);
+                    let file = WatchedFile {
+                        path,
+                        traces,
+                        sampling_rate,
+                    };
+                    // Fails when the viewer has been closed.
+                    if sender.send(file).is_err() {
+                        return;
+                    }
+                    ctx.request_repaint();
+                }
+            }
+        });
+        Ok(Self {
+            dir: dir.to_string(),
+            receiver,
+            replace,
+            steps,
+        })
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    /// Returns the next loaded file not received yet, in order of appearance.
+    pub fn try_receive(&self) -> Option<WatchedFile> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Lists the trace files of `dir`, with their size and modification time. Without `--format`,
+/// only the files with a recognized extension are listed.
+fn list(dir: &Path, args: &TraceArgs) -> io::Result<HashMap<PathBuf, (u64, Option<SystemTime>)>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        if metadata.is_file()
+            && (args.format.is_some() || guess_format(&path.to_string_lossy()).is_some())
+        {
+            files.insert(path, (metadata.len(), metadata.modified().ok()));
+        }
+    }
+    Ok(files)
+}
+
+/// Loads the frames of the file at `path` selected by `--frames`, labeled like the files given
+/// on the command line.
+fn load(args: &TraceArgs, path: &str) -> Result<Vec<WatchedTrace>, String> {
+    let frames = load_file(
+        path,
+        args.format,
+        args.npy_layout,
+        args.skip_lines,
+        args.column,
+        args.dataset.as_deref(),
+        args.dtype,
+    )?;
+    let count = frames.len();
+    let selection = args.frame_selection()?;
+    let traces: Vec<_> = frames
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selection.as_ref().is_none_or(|sel| sel.contains(*i)))
+        .map(|(i, samples)| WatchedTrace {
+            label: if count > 1 {
+                format!("{} [frame {}]", path, i)
+            } else {
+                path.to_string()
+            },
+            frame: (count > 1).then_some(i),
+            samples,
+        })
+        .collect();
+    if traces.is_empty() {
+        return Err("no trace selected in the file".into());
+    }
+    Ok(traces)
+}
+
+/// Frames of an opened file to load again, with the identifiers of the viewers displaying them.
+pub struct ReloadRequest {
+    pub path: String,
+    pub settings: FileSettings,
+    /// Viewers to update, with the index of their frame in multi-trace files.
+    pub targets: Vec<(u32, Option<usize>)>,
+}
+
+/// Trace loaded again for the viewer `id`, or the reason why it could not be loaded.
+pub struct ReloadedTrace {
+    pub id: u32,
+    pub path: String,
+    pub samples: Result<Vec<f32>, String>,
+}
+
+/// Loads opened files again in background.
+pub struct Reloader {
+    sender: Sender<ReloadedTrace>,
+    receiver: Receiver<ReloadedTrace>,
+}
+
+impl Default for Reloader {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self { sender, receiver }
+    }
+}
+
+impl Reloader {
+    /// Loads the file of `request` in background. The loaded traces wake up the user interface.
+    pub fn reload(&self, ctx: &egui::Context, request: ReloadRequest) {
+        let sender = self.sender.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            tracing::info!(target: "loader", "Reloading {}", request.path);
+            let settings = &request.settings;
+            let frames = load_file(
+                &request.path,
+                settings.format,
+                settings.npy_layout,
+                settings.skip_lines,
+                settings.column,
+                settings.dataset.as_deref(),
+                settings.dtype,
+            );
+            for (id, frame) in request.targets {
+                let samples = match &frames {
+                    Ok(frames) => frames
+                        .get(frame.unwrap_or(0))
+                        .cloned()
+                        .ok_or_else(|| format!("no frame {} in the file", frame.unwrap_or(0))),
+                    Err(e) => Err(e.clone()),
+                };
+                let path = request.path.clone();
+                if sender.send(ReloadedTrace { id, path, samples }).is_err() {
+                    return;
+                }
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Returns the next loaded trace not received yet.
+    pub fn try_receive(&self) -> Option<ReloadedTrace> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Opened files polled in background, reported once they have changed on disk.
+pub struct FileWatch {
+    /// Paths of the polled files. The polling stops when the watch is dropped.
+    paths: Arc<Mutex<HashSet<String>>>,
+    receiver: Receiver<String>,
+}
+
+impl FileWatch {
+    /// Starts polling the files given with [`FileWatch::set_paths`]. Changed files wake up the
+    /// user interface.
+    pub fn start(ctx: &egui::Context) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let paths: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+        let thread_paths = Arc::downgrade(&paths);
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            // Size and modification time of the files when last loaded, and at the previous poll
+            // for the changing ones.
+            let mut known = HashMap::new();
+            let mut pending = HashMap::new();
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let Some(paths) = thread_paths.upgrade() else {
+                    return;
+                };
+                let paths = paths.lock().unwrap().clone();
+                known.retain(|path, _| paths.contains(path));
+                pending.retain(|path, _| paths.contains(path));
+                for path in paths {
+                    let Ok(metadata) = fs::metadata(&path) else {
+                        continue;
+                    };
+                    let state = (metadata.len(), metadata.modified().ok());
+                    let Some(&previous) = known.get(&path) else {
+                        known.insert(path, state);
+                        continue;
+                    };
+                    if previous == state
+                        || pending.insert(path.clone(), state) != Some(state)
+                        || state.0 == 0
+                    {
+                        continue;
+                    }
+                    pending.remove(&path);
+                    known.insert(path.clone(), state);
+                    tracing::info!(target: "loader", "{} changed", path);
+                    if sender.send(path).is_err() {
+                        return;
+                    }
+                    ctx.request_repaint();
+                }
+            }
+        });
+        Self { paths, receiver }
+    }
+
+    /// Replaces the polled files by `paths`. The new files are reported once they change after
+    /// their first poll.
+    pub fn set_paths(&self, paths: HashSet<String>) {
+        *self.paths.lock().unwrap() = paths;
+    }
+
+    /// Returns the path of the next changed file not received yet.
+    pub fn try_receive(&self) -> Option<String> {
+        self.receiver.try_recv().ok()
+    }
+}
@@ -0,0 +1,192 @@
+//! Wavelet-threshold denoising.
+//!
+//! The trace is decomposed with a discrete wavelet transform, the detail coefficients are
+//! thresholded to remove the noise, and the trace is reconstructed. Unlike linear filters, this
+//! preserves sharp transients while removing wideband or impulsive noise.
+
+use serde::{Deserialize, Serialize};
+
+/// Orthogonal wavelets available for denoising.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Wavelet {
+    Haar,
+    /// Daubechies wavelet with 2 vanishing moments (4 taps).
+    Db2,
+    /// Daubechies wavelet with 4 vanishing moments (8 taps).
+    Db4,
+}
+
+impl Wavelet {
+    pub fn name(&self) -> &str {
+        match self {
+            Wavelet::Haar => "Haar",
+            Wavelet::Db2 => "Daubechies 2",
+            Wavelet::Db4 => "Daubechies 4",
+        }
+    }
+
+    /// Decomposition low-pass filter coefficients.
+    fn low_pass(&self) -> &'static [f64] {
+        match self {
+            Wavelet::Haar => &[
+                std::f64::consts::FRAC_1_SQRT_2,
+                std::f64::consts::FRAC_1_SQRT_2,
+            ],
+            Wavelet::Db2 => &[
+                0.482_962_913_144_534_1,
+                0.836_516_303_737_807_9,
+                0.224_143_868_042_013_4,
+                -0.129_409_522_551_260_4,
+            ],
+            Wavelet::Db4 => &[
+                0.230_377_813_308_896_4,
+                0.714_846_570_552_915_4,
+                0.630_880_767_929_858_7,
+                -0.027_983_769_416_859_9,
+                -0.187_034_811_719_093_1,
+                0.030_841_381_835_560_7,
+                0.032_883_011_666_885_2,
+                -0.010_597_401_785_069_0,
+            ],
+        }
+    }
+}
+
+/// How the detail coefficients are thresholded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThresholdRule {
+    /// Universal threshold `σ.√(2.ln(N))` (VisuShrink), hard thresholding.
+    UniversalHard,
+    /// Universal threshold `σ.√(2.ln(N))` (VisuShrink), soft thresholding.
+    UniversalSoft,
+    /// Level-dependent threshold `σ²/σx` (BayesShrink), soft thresholding.
+    Bayes,
+}
+
+impl ThresholdRule {
+    pub fn name(&self) -> &str {
+        match self {
+            ThresholdRule::UniversalHard => "Universal (hard)",
+            ThresholdRule::UniversalSoft => "Universal (soft)",
+            ThresholdRule::Bayes => "BayesShrink",
+        }
+    }
+}
+
+/// Denoises a trace by thresholding its wavelet coefficients over `level` decomposition levels.
+///
+/// The noise level `σ` is estimated from the median absolute deviation of the finest detail
+/// coefficients. The trace is extended by mirroring at its edges to limit border effects, and the
+/// level is reduced if the trace is too short.
+pub fn denoise(trace: &[f32], wavelet: Wavelet, level: usize, rule: ThresholdRule) -> Vec<f32> {
+    let h = wavelet.low_pass();
+    let mut level = level;
+    while level > 0 && (trace.len() >> level) < h.len() {
+        level -= 1;
+    }
+    if level == 0 {
+        return trace.to_vec();
+    }
+
+    // Mirror extension, with a total length multiple of 2^level.
+    let block = 1 << level;
+    let ext = (h.len() << level).min(trace.len() - 1);
+    let len = (trace.len() + 2 * ext).div_ceil(block) * block;
+    let mut x: Vec<f64> = (0..len)
+        .map(|i| trace[mirror(i as isize - ext as isize, trace.len())] as f64)
+        .collect();
+
+    let mut details = Vec::with_capacity(level);
+    for _ in 0..level {
+        let (a, d) = dwt_step(&x, h);
+        details.push(d);
+        x = a;
+    }
+
+    let sigma = median_abs(&details[0]) / 0.6745;
+    let universal = sigma * (2.0 * (len as f64).ln()).sqrt();
+    for d in details.iter_mut() {
+        match rule {
+            ThresholdRule::UniversalHard => d.iter_mut().for_each(|c| {
+                if c.abs() < universal {
+                    *c = 0.0
+                }
+            }),
+            ThresholdRule::UniversalSoft => d.iter_mut().for_each(|c| *c = soft(*c, universal)),
+            ThresholdRule::Bayes => {
+                let variance = d.iter().map(|c| c * c).sum::<f64>() / d.len() as f64;
+                let signal_std = (variance - sigma * sigma).max(0.0).sqrt();
+                let threshold = if signal_std > 0.0 {
+                    sigma * sigma / signal_std
+                } else {
+                    d.iter().fold(0.0f64, |m, c| m.max(c.abs()))
+                };
+                d.iter_mut().for_each(|c| *c = soft(*c, threshold));
+            }
+        }
+    }
+
+    for d in details.iter().rev() {
+        x = idwt_step(&x, d, h);
+    }
+    x[ext..ext + trace.len()]
+        .iter()
+        .map(|&v| v as f32)
+        .collect()
+}
+
+/// Index of the sample at position `i` of a trace of length `len` extended by mirroring.
+fn mirror(i: isize, len: usize) -> usize {
+    let period = 2 * (len as isize - 1);
+    if period == 0 {
+        return 0;
+    }
+    let i = i.rem_euclid(period);
+    (if i < len as isize { i } else { period - i }) as usize
+}
+
+/// High-pass filter coefficient `j` from the low-pass filter (quadrature mirror filter).
+fn high_pass(h: &[f64], j: usize) -> f64 {
+    let c = h[h.len() - 1 - j];
+    if j.is_multiple_of(2) { c } else { -c }
+}
+
+/// One level of the periodized discrete wavelet transform. `x` length must be even.
+/// Returns the approximation and detail coefficients.
+fn dwt_step(x: &[f64], h: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let n = x.len();
+    (0..n / 2)
+        .map(|k| {
+            (0..h.len()).fold((0.0, 0.0), |(a, d), j| {
+                let v = x[(2 * k + j) % n];
+                (a + h[j] * v, d + high_pass(h, j) * v)
+            })
+        })
+        .unzip()
+}
+
+/// Inverse of [`dwt_step`].
+fn idwt_step(a: &[f64], d: &[f64], h: &[f64]) -> Vec<f64> {
+    let n = a.len() * 2;
+    let mut x = vec![0.0; n];
+    for k in 0..a.len() {
+        for j in 0..h.len() {
+            x[(2 * k + j) % n] += h[j] * a[k] + high_pass(h, j) * d[k];
+        }
+    }
+    x
+}
+
+/// Soft thresholding: shrinks `x` towards zero by `threshold`.
+fn soft(x: f64, threshold: f64) -> f64 {
+    x.signum() * (x.abs() - threshold).max(0.0)
+}
+
+/// Median of the absolute values.
+fn median_abs(values: &[f64]) -> f64 {
+    let mut abs: Vec<f64> = values.iter().map(|x| x.abs()).collect();
+    let mid = abs.len() / 2;
+    *abs.select_nth_unstable_by(mid, f64::total_cmp).1
+}
@@ -0,0 +1,41 @@
+//! Entry point of the viewer in a web browser, built with `trunk` from `index.html`.
+//!
+//! There is no command line nor configuration file in the browser: the viewer starts with the
+//! file manager and the default options, and the tiles are rendered by the CPU.
+
+use crate::{app::TurboPlotApp, colorizer::TileColorizer, input::Cli};
+use clap::Parser;
+use eframe::{wasm_bindgen::JsCast, web_sys};
+
+/// Identifier of the canvas of `index.html` the viewer is painted on.
+const CANVAS_ID: &str = "turboplot";
+
+/// Starts the viewer in the canvas of the page.
+pub fn start() {
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(CANVAS_ID))
+        .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+        .expect("The page has no canvas for the viewer");
+    let args = Cli::parse_from(["turboplot"]).view;
+    wasm_bindgen_futures::spawn_local(async move {
+        let result = eframe::WebRunner::new()
+            .start(
+                canvas,
+                eframe::WebOptions::default(),
+                Box::new(|cc| {
+                    TileColorizer::install(&cc.egui_ctx, cc.wgpu_render_state.as_ref());
+                    Ok(Box::new(TurboPlotApp::new(
+                        &cc.egui_ctx,
+                        args,
+                        Vec::new(),
+                        cc.storage,
+                    )))
+                }),
+            )
+            .await;
+        if let Err(e) = result {
+            web_sys::console::error_1(&e);
+        }
+    });
+}